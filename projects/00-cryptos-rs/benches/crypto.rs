@@ -0,0 +1,212 @@
+use std::str::FromStr;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+#[cfg(feature = "parallel")]
+use cryptos_rs::block::{self, Block};
+use cryptos_rs::keys::PublicKey;
+use cryptos_rs::ripemd160::{ripemd160, Ripemd160};
+use cryptos_rs::ru256::RU256;
+use cryptos_rs::secp256k1::{PrecomputeTable, WindowTable, SECP256K1};
+use cryptos_rs::sha256::{hash256, hash256_many, sha256, sha256_4way, sha256_reference, Sha256};
+use cryptos_rs::signature::{sign_ecdsa, sign_schnorr, verify_ecdsa, verify_schnorr};
+#[cfg(feature = "parallel")]
+use cryptos_rs::transaction::Tx;
+use digest::Digest;
+
+// Baselines for the performance chapters. Scalar multiplication and signing
+// take milliseconds each, so those groups run with a small sample size.
+
+fn scalar() -> RU256 {
+    RU256::from_str("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721").unwrap()
+}
+
+fn bench_ru256(c: &mut Criterion) {
+    let p = SECP256K1::p();
+    let a = scalar();
+    let b = SECP256K1::g().x;
+
+    c.bench_function("ru256 mul_mod", |bench| {
+        bench.iter(|| black_box(&a).mul_mod(black_box(&b), &p))
+    });
+    c.bench_function("ru256 exp_mod", |bench| {
+        bench.iter(|| black_box(&a).exp_mod(black_box(&b), &p))
+    });
+
+    let p_minus_2 = RU256 { v: p.v - 2 };
+    let mut group = c.benchmark_group("ru256 inversion");
+    group.bench_function("fermat", |bench| {
+        bench.iter(|| black_box(&a).exp_mod(&p_minus_2, &p))
+    });
+    group.bench_function("extended euclid", |bench| {
+        bench.iter(|| black_box(&a).inv_mod(&p))
+    });
+    group.bench_function("safegcd", |bench| {
+        bench.iter(|| black_box(&a).inv_mod_safegcd(&p))
+    });
+    group.finish();
+}
+
+fn bench_points(c: &mut Criterion) {
+    let g = SECP256K1::g();
+    let g2 = SECP256K1::double_point(&g);
+
+    c.bench_function("point add", |bench| {
+        bench.iter(|| SECP256K1::add_points(black_box(&g), black_box(&g2)))
+    });
+    c.bench_function("point double", |bench| {
+        bench.iter(|| SECP256K1::double_point(black_box(&g)))
+    });
+}
+
+fn bench_scalar_multiplication(c: &mut Criterion) {
+    let g = SECP256K1::g();
+    let k = scalar();
+    let doublings = PrecomputeTable::generate(&g);
+    let windows = WindowTable::generate(&g);
+
+    let mut group = c.benchmark_group("scalar multiplication");
+    group.sample_size(10);
+    group.bench_function("double and add", |bench| {
+        bench.iter(|| SECP256K1::scalar_multiplication(black_box(&k), &g, None))
+    });
+    group.bench_function("precomputed doublings", |bench| {
+        bench.iter(|| SECP256K1::scalar_multiplication(black_box(&k), &g, Some(&doublings)))
+    });
+    group.bench_function("fixed-base window", |bench| {
+        bench.iter(|| SECP256K1::scalar_multiplication_fixed_base(black_box(&k), &windows))
+    });
+    group.bench_function("ladder", |bench| {
+        bench.iter(|| SECP256K1::scalar_multiplication_ct(black_box(&k), &g))
+    });
+    group.finish();
+}
+
+fn bench_hashes(c: &mut Criterion) {
+    let message = vec![0x42u8; 1024];
+
+    c.bench_function("sha256 1KiB", |bench| {
+        bench.iter(|| sha256(black_box(message.clone())))
+    });
+    c.bench_function("sha256 reference 1KiB", |bench| {
+        bench.iter(|| sha256_reference(black_box(message.clone())))
+    });
+    c.bench_function("sha256 4way 4x1KiB", |bench| {
+        bench.iter(|| sha256_4way(black_box([&message, &message, &message, &message])))
+    });
+    // roughly a block's worth of 250 byte transactions
+    let txs = vec![vec![0x42u8; 250]; 2000];
+    let txs: Vec<&[u8]> = txs.iter().map(|tx| tx.as_slice()).collect();
+    c.bench_function("hash256_many 2000 txs", |bench| {
+        bench.iter(|| hash256_many(black_box(&txs)))
+    });
+    c.bench_function("hash256 1KiB", |bench| {
+        bench.iter(|| hash256(black_box(message.clone())))
+    });
+    c.bench_function("ripemd160 1KiB", |bench| {
+        bench.iter(|| ripemd160(black_box(&message)))
+    });
+}
+
+/// The same generic code over our hashes and the RustCrypto ones
+fn bench_digest(c: &mut Criterion) {
+    fn digest<D: Digest>(message: &[u8]) -> Vec<u8> {
+        D::digest(message).to_vec()
+    }
+    let message = vec![0x42u8; 1024];
+
+    let mut group = c.benchmark_group("digest 1KiB");
+    group.bench_function("sha256", |bench| {
+        bench.iter(|| digest::<Sha256>(black_box(&message)))
+    });
+    group.bench_function("sha2::Sha256", |bench| {
+        bench.iter(|| digest::<sha2::Sha256>(black_box(&message)))
+    });
+    group.bench_function("ripemd160", |bench| {
+        bench.iter(|| digest::<Ripemd160>(black_box(&message)))
+    });
+    group.finish();
+}
+
+/// Serial against rayon, with `--features bench,parallel`
+#[cfg(feature = "parallel")]
+fn bench_parallel(c: &mut Criterion) {
+    let txids: Vec<[u8; 32]> = (0..4000u32)
+        .map(|i| cryptos_rs::hashes::sha256d(&i.to_le_bytes()))
+        .collect();
+    let txs = vec![Tx::default(); 4000];
+
+    let mut group = c.benchmark_group("block 4000 txs");
+    group.bench_function("merkle_root", |bench| {
+        bench.iter(|| block::merkle_root(black_box(&txids)))
+    });
+    group.bench_function("par_merkle_root", |bench| {
+        bench.iter(|| block::par_merkle_root(black_box(&txids)))
+    });
+    group.bench_function("txids", |bench| {
+        bench.iter(|| block::txids(black_box(&txs)))
+    });
+    group.bench_function("par_txids", |bench| {
+        bench.iter(|| block::par_txids(black_box(&txs)))
+    });
+    group.finish();
+
+    // the genesis header with a target needing about 2^16 hashes
+    let genesis = hex::decode("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c").unwrap();
+    let mut header = Block::decode_header(&mut genesis.as_slice());
+    header.bits = vec![0xff, 0xff, 0x00, 0x1f];
+    let mut group = c.benchmark_group("mine");
+    group.sample_size(10);
+    group.bench_function("mine", |bench| bench.iter(|| header.clone().mine()));
+    group.bench_function("par_mine", |bench| bench.iter(|| header.clone().par_mine()));
+    group.finish();
+}
+
+#[cfg(not(feature = "parallel"))]
+fn bench_parallel(_c: &mut Criterion) {}
+
+fn bench_ecdsa(c: &mut Criterion) {
+    let secret_key = scalar();
+    let public_key = PublicKey::from_sk(&secret_key);
+    let message = b"benchmark message";
+    let sig = sign_ecdsa(&secret_key, message);
+
+    let mut group = c.benchmark_group("ecdsa");
+    group.sample_size(10);
+    group.bench_function("sign", |bench| {
+        bench.iter(|| sign_ecdsa(black_box(&secret_key), message))
+    });
+    group.bench_function("verify", |bench| {
+        bench.iter(|| verify_ecdsa(&public_key, message, black_box(&sig)))
+    });
+    group.finish();
+}
+
+fn bench_schnorr(c: &mut Criterion) {
+    let secret_key = scalar();
+    let public_key = PublicKey::from_sk(&secret_key);
+    let message = b"benchmark message";
+    let sig = sign_schnorr(&secret_key, message);
+
+    let mut group = c.benchmark_group("schnorr");
+    group.sample_size(10);
+    group.bench_function("sign", |bench| {
+        bench.iter(|| sign_schnorr(black_box(&secret_key), message))
+    });
+    group.bench_function("verify", |bench| {
+        bench.iter(|| verify_schnorr(&public_key, message, black_box(&sig)))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_ru256,
+    bench_points,
+    bench_scalar_multiplication,
+    bench_hashes,
+    bench_digest,
+    bench_parallel,
+    bench_ecdsa,
+    bench_schnorr
+);
+criterion_main!(benches);
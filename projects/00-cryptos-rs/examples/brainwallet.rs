@@ -0,0 +1,40 @@
+use std::fs;
+
+use cryptos_rs::brainwallet::crack;
+use cryptos_rs::index::Index;
+use cryptos_rs::keys::wif_encode;
+
+// Try every line of a wordlist as a brainwallet passphrase against an address
+// index built with the index module, and report the ones that were used:
+//
+//     cargo run --release --example brainwallet <index dir> <wordlist> [main|test]
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: brainwallet <index dir> <wordlist> [main|test]");
+        std::process::exit(2);
+    }
+    let net = args.get(2).map_or("main", String::as_str);
+    let index = Index::open(&args[0]).expect("can't open the index");
+    let wordlist = fs::read_to_string(&args[1]).expect("can't read the wordlist");
+    let passphrases: Vec<&str> = wordlist.lines().filter(|line| !line.is_empty()).collect();
+
+    let hits = crack(&index, &passphrases, net).expect("index lookup failed");
+    for hit in &hits {
+        let balance: u64 = hit.unspent.iter().map(|utxo| utxo.amount.to_sat()).sum();
+        println!(
+            "{:?} -> {} ({} txs, {} sat unspent) {}",
+            hit.passphrase,
+            hit.address,
+            hit.history.len(),
+            balance,
+            wif_encode(&hit.secret_key, net, hit.compressed)
+        );
+    }
+    println!(
+        "{} of {} passphrases were used",
+        hits.len(),
+        passphrases.len()
+    );
+}
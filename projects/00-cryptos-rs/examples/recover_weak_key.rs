@@ -0,0 +1,35 @@
+use std::time::Instant;
+
+use cryptos_rs::keys::{wif_encode, PublicKey};
+use cryptos_rs::weak_rng::recover_seed;
+
+// Brute force the secret key behind a public key made by a generator with a
+// guessable seed, see the weak_rng module. Seeds are tried from `from` up to
+// but not including `to`, for a key made from a timestamp that's the range of
+// seconds it could have been made in:
+//
+//     cargo run --release --example recover_weak_key <sec pubkey hex> <from> <to>
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 3 {
+        eprintln!("usage: recover_weak_key <sec pubkey hex> <from seed> <to seed>");
+        std::process::exit(2);
+    }
+    let public_key = hex::decode(&args[0])
+        .ok()
+        .and_then(|bytes| PublicKey::try_from_bytes(&bytes))
+        .expect("public key is a hex SEC encoded point");
+    let from: u64 = args[1].parse().expect("from is a number");
+    let to: u64 = args[2].parse().expect("to is a number");
+
+    let start = Instant::now();
+    match recover_seed(&public_key, from..to) {
+        Some((seed, key)) => {
+            println!("seed {}", seed);
+            println!("secret key {}", wif_encode(&key, "main", true));
+        }
+        None => println!("no seed in {}..{} makes this key", from, to),
+    }
+    println!("searched in {:.1?}", start.elapsed());
+}
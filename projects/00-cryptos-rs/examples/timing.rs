@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use primitive_types::U256;
+use rand::seq::index;
+use rand::thread_rng;
+
+use cryptos_rs::ru256::RU256;
+use cryptos_rs::secp256k1::SECP256K1;
+
+// The side channel lesson, measured: how long a scalar multiplication takes
+// depending on how many bits of the secret scalar are set. Double and add
+// does an extra point addition per set bit, so its time climbs with the
+// hamming weight and gives the weight of the key away; the ladder does the
+// same work for every scalar. Run with
+//
+//     cargo run --release --example timing [samples] [output]
+//
+// and plot the output, e.g. in gnuplot with
+// `plot "timing.dat" using 1:2 title "double and add", "" using 1:3 title "ladder"`.
+
+const WEIGHTS: [usize; 8] = [16, 48, 80, 112, 144, 176, 208, 240];
+
+/// A random scalar below n with exactly `weight` bits set
+fn scalar_with_weight(weight: usize) -> RU256 {
+    let n = SECP256K1::n();
+    loop {
+        let mut v = U256::zero();
+        for bit in index::sample(&mut thread_rng(), 256, weight) {
+            v |= U256::one() << bit;
+        }
+        if v < n.v {
+            return RU256 { v };
+        }
+    }
+}
+
+/// Milliseconds `f` takes, the median of `samples` runs each on a fresh scalar
+fn median_ms(samples: usize, weight: usize, f: impl Fn(&RU256)) -> f64 {
+    let mut times: Vec<f64> = (0..samples)
+        .map(|_| {
+            let scalar = scalar_with_weight(weight);
+            let start = Instant::now();
+            f(&scalar);
+            start.elapsed().as_secs_f64() * 1000.0
+        })
+        .collect();
+    times.sort_by(f64::total_cmp);
+    times[samples / 2]
+}
+
+/// Least squares slope of y over x
+fn slope(points: &[(f64, f64)]) -> f64 {
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    covariance / variance
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let samples: usize = args
+        .next()
+        .map_or(5, |arg| arg.parse().expect("samples is a number"));
+    let output = args.next().unwrap_or_else(|| "timing.dat".to_string());
+    let g = SECP256K1::g();
+
+    let mut file = File::create(&output)?;
+    writeln!(file, "# weight double_and_add_ms ladder_ms")?;
+    let (mut naive, mut ladder) = (vec![], vec![]);
+    for weight in WEIGHTS {
+        let naive_ms = median_ms(samples, weight, |k| {
+            std::hint::black_box(SECP256K1::scalar_multiplication(k, &g, None));
+        });
+        let ladder_ms = median_ms(samples, weight, |k| {
+            std::hint::black_box(SECP256K1::scalar_multiplication_ct(k, &g));
+        });
+        println!(
+            "weight {:3}: double and add {:8.3} ms, ladder {:8.3} ms",
+            weight, naive_ms, ladder_ms
+        );
+        writeln!(file, "{} {:.4} {:.4}", weight, naive_ms, ladder_ms)?;
+        naive.push((weight as f64, naive_ms));
+        ladder.push((weight as f64, ladder_ms));
+    }
+
+    println!("cost of a set bit, from a linear fit:");
+    println!("  double and add {:.4} ms", slope(&naive));
+    println!("  ladder         {:.4} ms", slope(&ladder));
+    println!("data written to {}", output);
+    Ok(())
+}
@@ -1,56 +1,87 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, BufRead};
-use std::path::Path;
 
+use cryptos_rs::secp256k1::PrecomputeTable;
 use hex;
-use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use secp256k1::{All, PublicKey, Secp256k1, SecretKey};
 
-fn main() {
-    // Initialize secp256k1 context
-    let secp = Secp256k1::new();
+/// Recompute the expected point 2^index * G using secp256k1
+fn expected_point(secp: &Secp256k1<All>, index: usize) -> PublicKey {
+    let mut scalar_bytes = [0u8; 32];
+    scalar_bytes[31 - index / 8] = 1 << (index % 8);
+    let secret_key = SecretKey::from_slice(&scalar_bytes).unwrap();
+    PublicKey::from_secret_key(secp, &secret_key)
+}
 
-    // Path to the precomputed points file
-    let path = "/Users/kody/Documents/github/fedi_stuff/ecash-course/projects/00-cryptos-rs/precomputed_points.txt";
+/// The legacy `index:compressed point hex` text format
+fn verify_text(secp: &Secp256k1<All>, file: File) {
+    let reader = io::BufReader::new(file);
 
-    // Open the file
-    if let Ok(file) = File::open(path) {
-        let reader = io::BufReader::new(file);
+    for (index, line) in reader.lines().enumerate() {
+        if let Ok(line) = line {
+            // Split the line into index and point
+            let parts: Vec<&str> = line.split(':').collect();
+            if parts.len() != 2 {
+                eprintln!("Invalid line format at line {}", index + 1);
+                continue;
+            }
 
-        for (index, line) in reader.lines().enumerate() {
-            if let Ok(line) = line {
-                // Split the line into index and point
-                let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() != 2 {
-                    eprintln!("Invalid line format at line {}", index + 1);
-                    continue;
-                }
+            let index_str = parts[0];
+            let point_str = parts[1];
 
-                let index_str = parts[0];
-                let point_str = parts[1];
-
-                if let Ok(index) = index_str.parse::<usize>() {
-                    match PublicKey::from_slice(&hex::decode(point_str).expect("Invalid hex")) {
-                        Ok(public_key) => {
-                            // Recompute the expected point using secp256k1
-                            let a = (index % 256) as u64;
-                            let b = (index / 256) as u32;
-                            let scalar = a * 256u64.pow(b);
-                            let mut scalar_bytes = [0u8; 32];
-                            scalar_bytes[24..].copy_from_slice(&scalar.to_be_bytes());
-                            let secret_key = SecretKey::from_slice(&scalar_bytes).unwrap();
-                            let expected_point = PublicKey::from_secret_key(&secp, &secret_key);
-
-                            if public_key == expected_point {
-                                println!("Point {} is valid", index);
-                            } else {
-                                println!("Point {} is invalid", index);
-                            }
+            if let Ok(index) = index_str.parse::<usize>() {
+                match PublicKey::from_slice(&hex::decode(point_str).expect("Invalid hex")) {
+                    Ok(public_key) => {
+                        if public_key == expected_point(secp, index) {
+                            println!("Point {} is valid", index);
+                        } else {
+                            println!("Point {} is invalid", index);
                         }
-                        Err(_) => println!("Point {} is invalid", index),
                     }
+                    Err(_) => println!("Point {} is invalid", index),
                 }
             }
         }
+    }
+}
+
+/// The checksummed binary format, whose header and checksum are checked
+/// before any point is
+fn verify_binary(secp: &Secp256k1<All>, bytes: &[u8]) {
+    let table = match PrecomputeTable::from_bytes(bytes) {
+        Ok(table) => table,
+        Err(error) => {
+            eprintln!("Invalid binary table: {}", error);
+            return;
+        }
+    };
+    for (index, point) in table.points().iter().enumerate() {
+        match PublicKey::from_slice(&point.to_compressed_bytes()) {
+            Ok(public_key) if public_key == expected_point(secp, index) => {
+                println!("Point {} is valid", index)
+            }
+            _ => println!("Point {} is invalid", index),
+        }
+    }
+}
+
+fn main() {
+    // Initialize secp256k1 context
+    let secp = Secp256k1::new();
+
+    // Path to the precomputed points file, defaults to the current directory.
+    // A .bin file is read as a binary table, anything else as text.
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "precomputed_points.txt".to_string());
+
+    if path.ends_with(".bin") {
+        match fs::read(&path) {
+            Ok(bytes) => verify_binary(&secp, &bytes),
+            Err(_) => eprintln!("Failed to open the file: {}", path),
+        }
+    } else if let Ok(file) = File::open(&path) {
+        verify_text(&secp, file);
     } else {
         eprintln!("Failed to open the file: {}", path);
     }
@@ -0,0 +1,10 @@
+#![no_main]
+
+use cryptos_rs::keys::{b58check_decode, b58check_encode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    if let Some(payload) = b58check_decode(s) {
+        b58check_encode(&payload);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use cryptos_rs::bech32::{decode_segwit_address, decode_unlimited, encode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    // a valid string reencodes to itself, lowercased
+    if let Some((hrp, data, variant)) = decode_unlimited(s) {
+        assert_eq!(encode(&hrp, &data, variant), s.to_ascii_lowercase());
+    }
+    decode_segwit_address("bc", s);
+});
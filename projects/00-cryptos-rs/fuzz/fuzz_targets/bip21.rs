@@ -0,0 +1,10 @@
+#![no_main]
+
+use cryptos_rs::bip21::PaymentUri;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    if let Ok(uri) = s.parse::<PaymentUri>() {
+        assert_eq!(uri.to_string().parse::<PaymentUri>(), Ok(uri));
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use cryptos_rs::block::Block;
+use cryptos_rs::encoding::{Encodable, TryDecodable};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // as for transactions, the encoding is stable after one pass
+    if let Ok(block) = Block::try_decode_all(data) {
+        let raw = block.encode();
+        assert_eq!(Block::try_decode_all(&raw).map(|block| block.encode()), Ok(raw));
+    }
+});
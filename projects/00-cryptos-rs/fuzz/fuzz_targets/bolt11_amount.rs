@@ -0,0 +1,8 @@
+#![no_main]
+
+use cryptos_rs::lnurl::invoice_amount_msat;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    invoice_amount_msat(s);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use cryptos_rs::encoding::Encodable;
+use cryptos_rs::signature::Signature;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // strict DER has one encoding per signature
+    if let Ok(sig) = Signature::from_der(data) {
+        assert_eq!(sig.encode(), data);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use cryptos_rs::keys::PublicKey;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(key) = PublicKey::try_from_bytes(data) {
+        assert_eq!(PublicKey::try_from_bytes(&key.sec(true, false)), Some(key));
+    }
+});
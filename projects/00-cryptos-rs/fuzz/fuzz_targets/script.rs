@@ -0,0 +1,15 @@
+#![no_main]
+
+use cryptos_rs::encoding::{Encodable, TryDecodable};
+use cryptos_rs::transaction::Script;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // any bytes are a script and keep their encoding, and the cmds of one
+    // that parses write back to a script with the same cmds
+    let script = Script::from_bytes(data);
+    assert_eq!(Script::try_decode_all(&script.encode()).unwrap().as_bytes(), data);
+    if let Ok(cmds) = script.cmds() {
+        assert_eq!(Script::new(cmds.clone()).cmds(), Ok(cmds));
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use cryptos_rs::encoding::{Encodable, TryDecodable};
+use cryptos_rs::transaction::Tx;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // varints get rewritten minimally, after that the encoding is stable
+    if let Ok(tx) = Tx::try_decode_all(data) {
+        let raw = tx.encode();
+        assert_eq!(Tx::try_decode_all(&raw).map(|tx| tx.encode()), Ok(raw));
+    }
+});
@@ -0,0 +1,283 @@
+use core::fmt;
+use core::iter::Sum;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::str::FromStr;
+
+// Amounts of bitcoin and fee rates as their own types rather than bare u64s,
+// so a fee rate can't be passed where an amount is expected and sats can't be
+// mixed up with BTC. Amounts are whole satoshis, `+` and `-` panic on overflow
+// like the integer ops do in debug builds, use the checked versions on
+// untrusted values.
+
+/// Satoshis in one bitcoin
+pub const SAT_PER_BTC: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const ONE_BTC: Amount = Amount(SAT_PER_BTC);
+    /// All the bitcoin there will ever be
+    pub const MAX_MONEY: Amount = Amount(21_000_000 * SAT_PER_BTC);
+
+    pub const fn from_sat(sat: u64) -> Self {
+        Amount(sat)
+    }
+
+    pub const fn to_sat(self) -> u64 {
+        self.0
+    }
+
+    /// In whole bitcoin, for display only since it's a float
+    pub fn to_btc(self) -> f64 {
+        self.0 as f64 / SAT_PER_BTC as f64
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_mul(rhs).map(Amount)
+    }
+
+    /// Sum amounts, `None` on overflow
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Amount>) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        self.checked_add(rhs).expect("amount overflow")
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, rhs: Amount) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Amount {
+        self.checked_sub(rhs).expect("amount underflow")
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, rhs: Amount) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.copied().sum()
+    }
+}
+
+/// In BTC with all 8 decimals, e.g. `0.00010000 BTC`
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:08} BTC",
+            self.0 / SAT_PER_BTC,
+            self.0 % SAT_PER_BTC
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseAmountError {
+    Empty,
+    InvalidCharacter(char),
+    /// More than 8 decimals, i.e. a fraction of a satoshi
+    TooPrecise,
+    TooBig,
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Empty => write!(f, "empty amount"),
+            ParseAmountError::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+            ParseAmountError::TooPrecise => write!(f, "more than 8 decimals"),
+            ParseAmountError::TooBig => write!(f, "amount too big"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseAmountError {}
+
+/// Parses a decimal BTC amount like `0.0001` or `21 BTC`
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_suffix("BTC").unwrap_or(s).trim_end();
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(ParseAmountError::Empty);
+        }
+        if let Some(c) = whole
+            .chars()
+            .chain(fraction.chars())
+            .find(|c| !c.is_ascii_digit())
+        {
+            return Err(ParseAmountError::InvalidCharacter(c));
+        }
+        if fraction.len() > 8 {
+            return Err(ParseAmountError::TooPrecise);
+        }
+
+        let mut sat: u64 = 0;
+        for digit in whole.bytes().chain(fraction.bytes()) {
+            sat = sat
+                .checked_mul(10)
+                .and_then(|sat| sat.checked_add((digit - b'0') as u64))
+                .ok_or(ParseAmountError::TooBig)?;
+        }
+        sat = sat
+            .checked_mul(10u64.pow(8 - fraction.len() as u32))
+            .ok_or(ParseAmountError::TooBig)?;
+        Ok(Amount(sat))
+    }
+}
+
+/// A fee rate, quoted in sat/vB but kept in sat per 1000 vbytes like Bitcoin
+/// Core so fractional rates such as 0.1 sat/vB are exact
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    pub const fn from_sat_per_vb(sat_per_vb: u64) -> Self {
+        FeeRate(sat_per_vb * 1000)
+    }
+
+    pub const fn from_sat_per_kvb(sat_per_kvb: u64) -> Self {
+        FeeRate(sat_per_kvb)
+    }
+
+    pub const fn to_sat_per_kvb(self) -> u64 {
+        self.0
+    }
+
+    /// The fee for a transaction of `vsize` vbytes, rounded down
+    pub fn fee(self, vsize: usize) -> Amount {
+        Amount(vsize as u64 * self.0 / 1000)
+    }
+
+    /// The rate a transaction of `vsize` vbytes paying `fee` pays
+    pub fn from_fee(fee: Amount, vsize: usize) -> Self {
+        assert!(vsize > 0, "transaction can't be empty");
+        FeeRate(fee.0 * 1000 / vsize as u64)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0 / 1000)?;
+        let fraction = self.0 % 1000;
+        if fraction != 0 {
+            let digits = alloc::format!("{:03}", fraction);
+            write!(f, ".{}", digits.trim_end_matches('0'))?;
+        }
+        write!(f, " sat/vB")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let cases = [
+            ("1", 100_000_000, "1.00000000 BTC"),
+            ("0.0001", 10_000, "0.00010000 BTC"),
+            (".5", 50_000_000, "0.50000000 BTC"),
+            (
+                "21000000 BTC",
+                21_000_000 * SAT_PER_BTC,
+                "21000000.00000000 BTC",
+            ),
+            ("0.00000001", 1, "0.00000001 BTC"),
+        ];
+        for (s, sat, formatted) in cases {
+            let amount: Amount = s.parse().unwrap();
+            assert_eq!(amount.to_sat(), sat);
+            assert_eq!(amount.to_string(), formatted);
+            assert_eq!(formatted.parse::<Amount>(), Ok(amount));
+        }
+
+        assert_eq!("".parse::<Amount>(), Err(ParseAmountError::Empty));
+        assert_eq!(
+            "-1".parse::<Amount>(),
+            Err(ParseAmountError::InvalidCharacter('-'))
+        );
+        assert_eq!(
+            "1.000000001".parse::<Amount>(),
+            Err(ParseAmountError::TooPrecise)
+        );
+        assert_eq!(
+            "999999999999".parse::<Amount>(),
+            Err(ParseAmountError::TooBig)
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = Amount::from_sat(u64::MAX);
+        assert_eq!(max.checked_add(Amount::from_sat(1)), None);
+        assert_eq!(Amount::ZERO.checked_sub(Amount::from_sat(1)), None);
+        assert_eq!(
+            Amount::checked_sum([Amount::ONE_BTC, Amount::from_sat(5)]),
+            Some(Amount::from_sat(100_000_005))
+        );
+        assert_eq!(Amount::checked_sum([max, max]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "amount underflow")]
+    fn test_sub_panics_on_underflow() {
+        let _ = Amount::from_sat(1) - Amount::from_sat(2);
+    }
+
+    #[test]
+    fn test_fee_rate() {
+        let rate = FeeRate::from_sat_per_vb(3);
+        assert_eq!(rate.fee(182), Amount::from_sat(546));
+        assert_eq!(FeeRate::from_fee(Amount::from_sat(546), 182), rate);
+        assert_eq!(rate.to_string(), "3 sat/vB");
+        assert_eq!(FeeRate::from_sat_per_kvb(100).to_string(), "0.1 sat/vB");
+        assert_eq!(
+            FeeRate::from_sat_per_kvb(100).fee(141),
+            Amount::from_sat(14)
+        );
+    }
+}
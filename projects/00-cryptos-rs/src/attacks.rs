@@ -0,0 +1,377 @@
+use crate::curve::{Curve, Generator};
+use crate::ru256::RU256;
+use crate::secp256k1::{Point, SECP256K1};
+use crate::sha256::hash256;
+use crate::signature::Signature;
+
+// Breaking ECDSA through its nonces, to show why RFC 6979 matters. A nonce
+// used twice gives the secret key away with a bit of algebra, and nonces that
+// are merely biased (here: too small) still leak it once enough signatures are
+// collected, by solving the hidden number problem with a lattice. The lattice
+// attack runs on a toy curve, the same math scales to secp256k1 with more
+// signatures and a proper big integer LLL. Last, points that aren't on the
+// curve at all: ECDH that doesn't check the peer's point can be made to
+// compute on a weaker curve of the attacker's choosing (or, for x-only
+// ladders, on the quadratic twist) and leaks the key piece by piece.
+
+/// If two signatures share a nonce they share r, and subtracting
+/// s1 = (z1 + r d) / k from s2 = (z2 + r d) / k gives k = (z1 - z2) / (s1 - s2),
+/// after which d = (s1 k - z1) / r
+pub fn recover_private_key_from_nonce_reuse(
+    sig1: &Signature,
+    sig2: &Signature,
+    msg1: &[u8],
+    msg2: &[u8],
+) -> Option<RU256> {
+    let n = &SECP256K1::n();
+    if sig1.r != sig2.r || sig1.s == sig2.s || sig1.r.is_zero() {
+        return None;
+    }
+    // the same message hashing as sign_ecdsa
+    let z1 = RU256::from_bytes(&hash256(msg1.to_vec())) % n.clone();
+    let z2 = RU256::from_bytes(&hash256(msg2.to_vec())) % n.clone();
+
+    let k = z1.sub_mod(&z2, n).div_mod(&sig1.s.sub_mod(&sig2.s, n), n);
+    Some(sig1.s.mul_mod(&k, n).sub_mod(&z1, n).div_mod(&sig1.r, n))
+}
+
+/// y^2 = x^3 + 7 over F_65647, a secp256k1 look-alike whose group has prime
+/// order 65173
+pub fn toy_generator() -> Generator {
+    Generator::new(
+        Curve::toy(65647, 0, 7),
+        Point {
+            x: RU256::from_u64(1),
+            y: RU256::from_u64(31426),
+        },
+        RU256::from_u64(65173),
+    )
+}
+
+/// An ECDSA signature on a toy curve, along with the message hash it signs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToySignature {
+    pub z: u64,
+    pub r: u64,
+    pub s: u64,
+}
+
+fn mul_mod(a: u64, b: u64, n: u64) -> u64 {
+    (a as u128 * b as u128 % n as u128) as u64
+}
+
+fn pow_mod(base: u64, mut exp: u64, n: u64) -> u64 {
+    let (mut result, mut base) = (1, base % n);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, n);
+        }
+        base = mul_mod(base, base, n);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Inverse mod a prime, a^(n - 2)
+fn inv_mod(a: u64, n: u64) -> u64 {
+    pow_mod(a, n - 2, n)
+}
+
+/// ECDSA over a toy generator with message hash `z` and nonce `k`
+pub fn toy_sign(generator: &Generator, secret_key: u64, z: u64, k: u64) -> ToySignature {
+    let n = generator.n.v.low_u64();
+    let r = generator.mul(&RU256::from_u64(k)).x.v.low_u64() % n;
+    let s = mul_mod((z + mul_mod(r, secret_key, n)) % n, inv_mod(k, n), n);
+    assert!(r != 0 && s != 0, "degenerate signature, pick another nonce");
+    ToySignature { z: z % n, r, s }
+}
+
+/// Recover the secret key from signatures whose nonces are all below
+/// 2^nonce_bits, on a curve of prime order `n`.
+///
+/// Every signature gives k = a d + b mod n with a = r / s and b = z / s. The
+/// lattice below contains the vector of nonces, which is much shorter than a
+/// typical lattice vector when they are biased. LLL finds it, and d with it.
+pub fn recover_key_from_biased_nonces(
+    signatures: &[ToySignature],
+    n: u64,
+    nonce_bits: u32,
+) -> Option<u64> {
+    let m = signatures.len();
+    let bound = 1i128 << nonce_bits;
+    let n_big = n as i128;
+
+    // rows n^2 e_i, (n a_1 .. n a_m, B, 0) and (n b_1 .. n b_m, 0, B n), all
+    // scaled by n to stay integral. d * (second to last) + (last) minus
+    // multiples of the first rows is (n k_1 .. n k_m, d B, B n).
+    let mut basis = vec![vec![0i128; m + 2]; m + 2];
+    for (i, sig) in signatures.iter().enumerate() {
+        let s_inv = inv_mod(sig.s, n);
+        basis[i][i] = n_big * n_big;
+        basis[m][i] = n_big * mul_mod(sig.r, s_inv, n) as i128;
+        basis[m + 1][i] = n_big * mul_mod(sig.z, s_inv, n) as i128;
+    }
+    basis[m][m] = bound;
+    basis[m + 1][m + 1] = bound * n_big;
+
+    lll(&mut basis);
+
+    basis
+        .iter()
+        .filter(|row| row[m + 1].abs() == bound * n_big)
+        .map(|row| (row[m + 1].signum() * row[m] / bound).rem_euclid(n_big) as u64)
+        .find(|&d| {
+            // the right key makes every nonce small
+            signatures.iter().all(|sig| {
+                let k = mul_mod((sig.z + mul_mod(sig.r, d, n)) % n, inv_mod(sig.s, n), n);
+                (k as i128) < bound
+            })
+        })
+}
+
+/// ECDH as naive code writes it: multiply whatever point the peer sent. The
+/// addition formulas never use the curve's b, so a point from another curve
+/// y^2 = x^3 + ax + b' is silently multiplied on *that* curve.
+pub fn toy_ecdh_naive(curve: &Curve, secret_key: u64, their_point: &Point) -> Point {
+    curve.scalar_multiplication(&RU256::from_u64(secret_key), their_point)
+}
+
+/// ECDH that checks the peer's point first, which defeats invalid curve attacks
+pub fn toy_ecdh(curve: &Curve, secret_key: u64, their_point: &Point) -> Option<Point> {
+    curve
+        .contains(their_point)
+        .then(|| toy_ecdh_naive(curve, secret_key, their_point))
+}
+
+fn is_infinity(point: &Point) -> bool {
+    point.x.is_zero() && point.y.is_zero()
+}
+
+/// Some point on a toy curve, p must be 3 mod 4 for the square root
+fn find_point(curve: &Curve) -> Point {
+    let p = curve.p.v.low_u64();
+    let (a, b) = (curve.a.v.low_u64(), curve.b.v.low_u64());
+    (1..p)
+        .find_map(|x| {
+            let rhs = (mul_mod(mul_mod(x, x, p), x, p) + mul_mod(a, x, p) + b) % p;
+            let y = pow_mod(rhs, (p + 1) / 4, p);
+            (rhs != 0 && mul_mod(y, y, p) == rhs).then(|| Point {
+                x: RU256::from_u64(x),
+                y: RU256::from_u64(y),
+            })
+        })
+        .unwrap()
+}
+
+fn prime_factors(mut n: u64) -> Vec<u64> {
+    let mut factors = vec![];
+    let mut f = 2;
+    while f * f <= n {
+        if n.is_multiple_of(f) {
+            factors.push(f);
+            while n.is_multiple_of(f) {
+                n /= f;
+            }
+        }
+        f += 1;
+    }
+    if n > 1 {
+        factors.push(n);
+    }
+    factors
+}
+
+/// Recover a naive ECDH victim's secret key. The attacker sends points of
+/// small prime order q from curves with other b values, each answer sk * Q is
+/// one of only q points, so brute force gives sk mod q. Enough of those and
+/// the Chinese remainder theorem gives sk. `oracle` stands in for anything
+/// the victim reveals about the shared point, e.g. a MAC keyed with it.
+pub fn invalid_curve_attack(curve: &Curve, n: u64, oracle: impl Fn(&Point) -> Point) -> u64 {
+    const MAX_SUBGROUP: u64 = 1000;
+    let (mut residue, mut modulus) = (0u128, 1u128);
+    let mut b = 1;
+    while modulus < n as u128 {
+        b += 1;
+        if RU256::from_u64(b) == curve.b {
+            continue;
+        }
+        let invalid_curve = Curve::new(curve.p.clone(), curve.a.clone(), RU256::from_u64(b));
+        let order = invalid_curve.order();
+        let point = find_point(&invalid_curve);
+        for q in prime_factors(order) {
+            if q > MAX_SUBGROUP || modulus % q as u128 == 0 {
+                continue;
+            }
+            // a point of order q, if the cofactor multiple isn't the identity
+            let small = invalid_curve.scalar_multiplication(&RU256::from_u64(order / q), &point);
+            if is_infinity(&small) {
+                continue;
+            }
+
+            let shared = oracle(&small);
+            let mut guess = Point {
+                x: RU256::zero(),
+                y: RU256::zero(),
+            };
+            let t = (0..q)
+                .find(|_| {
+                    let found = guess == shared;
+                    guess = invalid_curve.add_points(&guess, &small);
+                    found
+                })
+                .unwrap() as u128;
+
+            // combine sk = residue mod modulus with sk = t mod q
+            let q = q as u128;
+            let step =
+                (t + q - residue % q) % q * inv_mod((modulus % q) as u64, q as u64) as u128 % q;
+            residue += modulus * step;
+            modulus *= q;
+        }
+    }
+    // the key is below n, which is at most the product of the moduli
+    residue as u64
+}
+
+/// Gram-Schmidt orthogonalization, returning the orthogonal vectors and the
+/// projection coefficients
+fn gram_schmidt(basis: &[Vec<i128>]) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+    let dim = basis.len();
+    let mut ortho: Vec<Vec<f64>> = vec![];
+    let mut mu = vec![vec![0.0; dim]; dim];
+    for (i, row) in basis.iter().enumerate() {
+        let row: Vec<f64> = row.iter().map(|&x| x as f64).collect();
+        let mut v = row.clone();
+        for j in 0..i {
+            mu[i][j] = dot(&row, &ortho[j]) / dot(&ortho[j], &ortho[j]);
+            for (v, o) in v.iter_mut().zip(&ortho[j]) {
+                *v -= mu[i][j] * o;
+            }
+        }
+        ortho.push(v);
+    }
+    (ortho, mu)
+}
+
+/// Textbook LLL with delta = 0.99, floating point Gram-Schmidt is plenty for
+/// toy sized lattices
+fn lll(basis: &mut [Vec<i128>]) {
+    const DELTA: f64 = 0.99;
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>();
+    let mut k = 1;
+    while k < basis.len() {
+        let (ortho, mut mu) = gram_schmidt(basis);
+        // size reduction
+        for j in (0..k).rev() {
+            let q = mu[k][j].round();
+            if q != 0.0 {
+                let row = basis[j].clone();
+                for (x, y) in basis[k].iter_mut().zip(&row) {
+                    *x -= q as i128 * y;
+                }
+                mu[k][j] -= q;
+                let mu_j = mu[j].clone();
+                for (mu_k, mu_j) in mu[k][..j].iter_mut().zip(&mu_j) {
+                    *mu_k -= q * mu_j;
+                }
+            }
+        }
+        // Lovász condition
+        if norm(&ortho[k]) >= (DELTA - mu[k][k - 1].powi(2)) * norm(&ortho[k - 1]) {
+            k += 1;
+        } else {
+            basis.swap(k, k - 1);
+            k = (k - 1).max(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::PublicKey;
+    use crate::signature::sign_ecdsa_with_nonce;
+
+    #[test]
+    fn test_nonce_reuse() {
+        // a small nonce keeps signing cheap, it's reused either way
+        let secret_key = RU256::from_u64(0xdeadbeef);
+        let k = RU256::from_u64(2);
+        let sig1 = sign_ecdsa_with_nonce(&secret_key, b"first", &k).sig;
+        let sig2 = sign_ecdsa_with_nonce(&secret_key, b"second", &k).sig;
+        assert_eq!(
+            recover_private_key_from_nonce_reuse(&sig1, &sig2, b"first", b"second"),
+            Some(secret_key.clone())
+        );
+
+        let sig3 = sign_ecdsa_with_nonce(&secret_key, b"third", &RU256::from_u64(3)).sig;
+        assert_eq!(
+            recover_private_key_from_nonce_reuse(&sig1, &sig3, b"first", b"third"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_toy_curve_has_prime_order() {
+        let generator = toy_generator();
+        assert_eq!(generator.curve.order(), 65173);
+        assert_eq!(generator.curve.point_order(&generator.G), 65173);
+    }
+
+    #[test]
+    fn test_invalid_curve_attack() {
+        let generator = toy_generator();
+        let secret_key = 31337;
+        let recovered = invalid_curve_attack(&generator.curve, 65173, |point| {
+            toy_ecdh_naive(&generator.curve, secret_key, point)
+        });
+        assert_eq!(recovered, secret_key);
+
+        // the same points bounce off the hardened version
+        let invalid_curve = Curve::toy(65647, 0, 2);
+        let point = find_point(&invalid_curve);
+        assert_eq!(toy_ecdh(&generator.curve, secret_key, &point), None);
+        assert!(toy_ecdh(&generator.curve, secret_key, &generator.G).is_some());
+    }
+
+    #[test]
+    fn test_secp256k1_invalid_point() {
+        // (1, 0) is on y^2 = x^3 - 1 and has order 2, naive ECDH with it
+        // reveals whether the secret key is odd
+        let mut sec = vec![0x04];
+        sec.extend([0u8; 31]);
+        sec.push(1);
+        sec.extend([0u8; 32]);
+        let point = Point {
+            x: RU256::from_u64(1),
+            y: RU256::zero(),
+        };
+        let shared = SECP256K1::scalar_multiplication(&RU256::from_u64(0xdeadbeef), &point, None);
+        assert_eq!(shared, point);
+
+        assert_eq!(PublicKey::try_from_bytes(&sec), None);
+        let result = std::panic::catch_unwind(|| PublicKey::from_bytes(&sec));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_biased_nonces() {
+        let generator = toy_generator();
+        let n = 65173;
+        let secret_key = 31337;
+
+        // 8 of the 16 nonce bits are always zero
+        let nonces = [201, 17, 99, 250, 143, 62, 188, 5];
+        let signatures: Vec<ToySignature> = nonces
+            .iter()
+            .enumerate()
+            .map(|(i, &k)| toy_sign(&generator, secret_key, 1000 + 7919 * i as u64, k))
+            .collect();
+
+        assert_eq!(
+            recover_key_from_biased_nonces(&signatures, n, 8),
+            Some(secret_key)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// Bech32 (BIP173) and its fixed variant bech32m (BIP350), the encoding of
+// segwit addresses: a human readable part, a `1`, then the data in base32 and
+// a 6 character BCH checksum that catches up to 4 errors. Segwit v0 programs
+// use bech32, v1 (taproot) and later bech32m.
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Bech32,
+    Bech32m,
+}
+
+impl Variant {
+    /// What the checksum polymod of a valid string comes out to
+    fn constant(self) -> u32 {
+        match self {
+            Variant::Bech32 => 1,
+            Variant::Bech32m => 0x2bc830a3,
+        }
+    }
+}
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+/// The high bits of each character, a zero, then the low bits
+fn hrp_expand(hrp: &str) -> impl Iterator<Item = u8> + '_ {
+    hrp.bytes()
+        .map(|c| c >> 5)
+        .chain([0])
+        .chain(hrp.bytes().map(|c| c & 31))
+}
+
+/// Encode 5 bit `data` under a lowercase `hrp`
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    assert!(data.iter().all(|&d| d < 32), "data must be 5 bit values");
+    let checksum =
+        polymod(hrp_expand(hrp).chain(data.iter().copied()).chain([0; 6])) ^ variant.constant();
+    let mut out = String::from(hrp);
+    out.push('1');
+    for &d in data {
+        out.push(CHARSET[d as usize] as char);
+    }
+    for i in 0..6 {
+        out.push(CHARSET[((checksum >> (5 * (5 - i))) & 31) as usize] as char);
+    }
+    out
+}
+
+/// The lowercase hrp and 5 bit data of a bech32 or bech32m string, None if
+/// it's malformed, mixed case or the checksum doesn't match
+pub fn decode(s: &str) -> Option<(String, Vec<u8>, Variant)> {
+    if s.len() > 90 {
+        return None;
+    }
+    decode_unlimited(s)
+}
+
+/// Same as [`decode`] without the 90 character limit, which LNURLs and
+/// lightning invoices go past
+pub fn decode_unlimited(s: &str) -> Option<(String, Vec<u8>, Variant)> {
+    if s.bytes().any(|c| !(33..=126).contains(&c)) {
+        return None;
+    }
+    let lower = s.to_ascii_lowercase();
+    if s != lower && s != s.to_ascii_uppercase() {
+        return None;
+    }
+    let (hrp, data) = lower.rsplit_once('1')?;
+    if hrp.is_empty() || data.len() < 6 {
+        return None;
+    }
+    let data: Vec<u8> = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|&x| x == c).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    let variant = match polymod(hrp_expand(hrp).chain(data.iter().copied())) {
+        1 => Variant::Bech32,
+        0x2bc830a3 => Variant::Bech32m,
+        _ => return None,
+    };
+    Some((String::from(hrp), data[..data.len() - 6].to_vec(), variant))
+}
+
+/// Regroup bits, e.g. bytes into 5 bit values. Without `pad` leftover bits
+/// must be zero padding, else None.
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut out = Vec::new();
+    let max = (1 << to) - 1;
+    for &value in data {
+        if value as u32 >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+/// The segwit address of a witness program, `hrp` is `bc` or `tb`
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    assert!(version <= 16, "witness version {} is out of range", version);
+    let variant = match version {
+        0 => Variant::Bech32,
+        _ => Variant::Bech32m,
+    };
+    let mut data = alloc::vec![version];
+    data.extend(convert_bits(program, 8, 5, true).unwrap());
+    encode(hrp, &data, variant)
+}
+
+/// The witness version and program of a segwit address for `hrp`
+pub fn decode_segwit_address(hrp: &str, address: &str) -> Option<(u8, Vec<u8>)> {
+    let (decoded_hrp, data, variant) = decode(address)?;
+    let (&version, data) = data.split_first()?;
+    if decoded_hrp != hrp || version > 16 {
+        return None;
+    }
+    let program = convert_bits(data, 5, 8, false)?;
+    let expected = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    if variant != expected || !(2..=40).contains(&program.len()) {
+        return None;
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return None;
+    }
+    Some((version, program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segwit_addresses() {
+        // vectors from BIP173 and BIP350
+        let cases = [
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                0,
+                "751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                0,
+                "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                1,
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+        ];
+        for (address, version, program) in cases {
+            let hrp = &address[..2].to_ascii_lowercase();
+            let program = hex::decode(program).unwrap();
+            assert_eq!(
+                decode_segwit_address(hrp, address),
+                Some((version, program.clone()))
+            );
+            assert_eq!(
+                encode_segwit_address(hrp, version, &program),
+                address.to_ascii_lowercase()
+            );
+        }
+
+        // v0 with a bech32m checksum, v1 with bech32, mixed case, wrong hrp
+        for address in [
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh",
+            "bc1p38j9r5y49hruaue7wxjce0updqjuyyx0kh56v8s25huc6995vvpql3jow4",
+            "bc1qW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+        ] {
+            assert_eq!(decode_segwit_address("bc", address), None);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::Read;
+use std::process::ExitCode;
+
+use cryptos_rs::decode::decode;
+
+// Decode a raw transaction, block, header or script given as hex, or an
+// address, and print it as JSON:
+//
+//     btcdec [--net main|test] <hex or address>
+//
+// With no argument (or `-`) the input is read from stdin.
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut net = "main".to_string();
+    if args.first().map(String::as_str) == Some("--net") {
+        if args.len() < 2 {
+            eprintln!("usage: btcdec [--net main|test] <hex or address>");
+            return ExitCode::FAILURE;
+        }
+        net = args.remove(1);
+        args.remove(0);
+    }
+    if net != "main" && net != "test" {
+        eprintln!("{} is not a valid net type, should be main|test", net);
+        return ExitCode::FAILURE;
+    }
+
+    let input = match args.first().map(String::as_str) {
+        None | Some("-") => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input).unwrap();
+            input
+        }
+        Some(input) => input.to_string(),
+    };
+
+    // failed decode attempts panic, keep them quiet
+    std::panic::set_hook(Box::new(|_| {}));
+    match decode(&input, &net) {
+        Ok(json) => {
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::amount::{Amount, ParseAmountError, SAT_PER_BTC};
+
+// BIP21 payment URIs, `bitcoin:<address>?amount=0.001&label=...`, the format
+// behind most payment QR codes. Besides the standard parameters, unified QR
+// codes add `lightning=` with a BOLT11 invoice and ecash wallets `cashu=` with
+// a payment request, so one code can be paid on chain, over lightning or in
+// ecash. The address may be empty when only the alternatives are offered.
+// Unknown parameters are kept, except `req-` ones which the payer is required
+// to understand and so make the URI invalid.
+
+const SCHEME: &str = "bitcoin:";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaymentUri {
+    pub address: String,
+    pub amount: Option<Amount>,
+    /// Who is being paid, for the payer's address book
+    pub label: Option<String>,
+    /// What the payment is for
+    pub message: Option<String>,
+    /// A BOLT11 invoice for the same payment
+    pub lightning: Option<String>,
+    /// A Cashu payment request for the same payment
+    pub cashu: Option<String>,
+    /// Any other parameters, in order
+    pub extras: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UriError {
+    /// Doesn't start with `bitcoin:`
+    Scheme,
+    /// A `%` not followed by two hex digits, or escapes that aren't UTF-8
+    Encoding,
+    Amount(ParseAmountError),
+    /// The same parameter given twice
+    Duplicate(String),
+    /// A `req-` parameter we don't know
+    Required(String),
+}
+
+impl fmt::Display for UriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UriError::Scheme => write!(f, "not a bitcoin: URI"),
+            UriError::Encoding => write!(f, "invalid percent encoding"),
+            UriError::Amount(err) => write!(f, "invalid amount: {}", err),
+            UriError::Duplicate(key) => write!(f, "parameter {} given twice", key),
+            UriError::Required(key) => write!(f, "unsupported required parameter {}", key),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UriError {}
+
+impl PaymentUri {
+    /// A request for `amount` to `address`
+    pub fn new(address: &str, amount: Option<Amount>) -> Self {
+        PaymentUri {
+            address: address.to_string(),
+            amount,
+            ..Default::default()
+        }
+    }
+}
+
+fn percent_decode(s: &str) -> Result<String, UriError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&c, tail)) = rest.split_first() {
+        if c == b'%' {
+            let hex = tail.get(..2).ok_or(UriError::Encoding)?;
+            let hex = core::str::from_utf8(hex).map_err(|_| UriError::Encoding)?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| UriError::Encoding)?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(c);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| UriError::Encoding)
+}
+
+/// Escape everything but the characters RFC 3986 leaves unreserved
+fn percent_encode(s: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for c in s.bytes() {
+        if c.is_ascii_alphanumeric() || b"-._~".contains(&c) {
+            write!(f, "{}", c as char)?;
+        } else {
+            write!(f, "%{:02X}", c)?;
+        }
+    }
+    Ok(())
+}
+
+/// In BTC with trailing zeros dropped, e.g. `0.001`
+fn format_amount(amount: Amount) -> String {
+    let sat = amount.to_sat();
+    let fraction = alloc::format!("{:08}", sat % SAT_PER_BTC);
+    let fraction = fraction.trim_end_matches('0');
+    match fraction.is_empty() {
+        true => alloc::format!("{}", sat / SAT_PER_BTC),
+        false => alloc::format!("{}.{}", sat / SAT_PER_BTC, fraction),
+    }
+}
+
+impl FromStr for PaymentUri {
+    type Err = UriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let scheme = s.get(..SCHEME.len()).ok_or(UriError::Scheme)?;
+        if !scheme.eq_ignore_ascii_case(SCHEME) {
+            return Err(UriError::Scheme);
+        }
+        let (address, query) = s[SCHEME.len()..]
+            .split_once('?')
+            .unwrap_or((&s[SCHEME.len()..], ""));
+        let mut uri = PaymentUri::new(&percent_decode(address)?, None);
+
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let key = percent_decode(key)?;
+            let value = percent_decode(value)?;
+            let field = match key.to_ascii_lowercase().as_str() {
+                "amount" => {
+                    // plain decimal BTC, no unit or exponent
+                    if let Some(c) = value.chars().find(|c| !c.is_ascii_digit() && *c != '.') {
+                        return Err(UriError::Amount(ParseAmountError::InvalidCharacter(c)));
+                    }
+                    if uri.amount.is_some() {
+                        return Err(UriError::Duplicate(key));
+                    }
+                    uri.amount = Some(value.parse().map_err(UriError::Amount)?);
+                    continue;
+                }
+                "label" => &mut uri.label,
+                "message" => &mut uri.message,
+                "lightning" => &mut uri.lightning,
+                "cashu" => &mut uri.cashu,
+                lower if lower.starts_with("req-") => return Err(UriError::Required(key)),
+                _ => {
+                    uri.extras.push((key, value));
+                    continue;
+                }
+            };
+            if field.is_some() {
+                return Err(UriError::Duplicate(key));
+            }
+            *field = Some(value);
+        }
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // an address never needs escaping, but a parsed one may hold anything
+        f.write_str(SCHEME)?;
+        percent_encode(&self.address, f)?;
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(("amount", format_amount(amount)));
+        }
+        let optional = [
+            ("label", &self.label),
+            ("message", &self.message),
+            ("lightning", &self.lightning),
+            ("cashu", &self.cashu),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                params.push((key, value.clone()));
+            }
+        }
+        let extras = self
+            .extras
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.clone()));
+        for (i, (key, value)) in params.into_iter().chain(extras).enumerate() {
+            f.write_str(if i == 0 { "?" } else { "&" })?;
+            percent_encode(key, f)?;
+            f.write_str("=")?;
+            percent_encode(&value, f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bip21_examples() {
+        let uri: PaymentUri = "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=50&label=Luke-Jr&message=Donation%20for%20project%20xyz"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.address, "175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W");
+        assert_eq!(uri.amount, Some(Amount::from_sat(50 * SAT_PER_BTC)));
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+        assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+
+        let uri: PaymentUri = "BITCOIN:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?somethingyoudontunderstand=50&somethingelseyoudontget=999"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.extras.len(), 2);
+        assert_eq!(
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?req-somethingyoudontunderstand=50"
+                .parse::<PaymentUri>(),
+            Err(UriError::Required(
+                "req-somethingyoudontunderstand".to_string()
+            ))
+        );
+        assert_eq!(
+            "bitcoin:175tWpb8K1S7NmH4Zx6rewF9WQrcZv245W?amount=1e3".parse::<PaymentUri>(),
+            Err(UriError::Amount(ParseAmountError::InvalidCharacter('e')))
+        );
+        assert_eq!(
+            "bitcoin:1?label=a&label=b".parse::<PaymentUri>(),
+            Err(UriError::Duplicate("label".to_string()))
+        );
+        assert_eq!(
+            "bitcoin:1?label=%zz".parse::<PaymentUri>(),
+            Err(UriError::Encoding)
+        );
+        assert_eq!(
+            "lightning:lnbc1".parse::<PaymentUri>(),
+            Err(UriError::Scheme)
+        );
+    }
+
+    #[test]
+    fn test_unified_round_trip() {
+        let uri = PaymentUri {
+            lightning: Some("lnbc10u1pjexample".to_string()),
+            cashu: Some("creqApWF0ZGI0".to_string()),
+            label: Some("Kody's coffee & more".to_string()),
+            ..PaymentUri::new(
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                Some(Amount::from_sat(1_000)),
+            )
+        };
+        let s = uri.to_string();
+        assert_eq!(
+            s,
+            "bitcoin:bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4?amount=0.00001&label=Kody%27s%20coffee%20%26%20more&lightning=lnbc10u1pjexample&cashu=creqApWF0ZGI0"
+        );
+        assert_eq!(s.parse::<PaymentUri>(), Ok(uri));
+
+        // lightning only, no on chain address
+        let uri: PaymentUri = "bitcoin:?lightning=lnbc1".parse().unwrap();
+        assert_eq!(uri.address, "");
+        assert_eq!(uri.to_string(), "bitcoin:?lightning=lnbc1");
+
+        let uri: PaymentUri = "bitcoin:%3Fx".parse().unwrap();
+        assert_eq!(uri.to_string().parse::<PaymentUri>(), Ok(uri));
+    }
+}
@@ -0,0 +1,173 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use sha2::{Digest, Sha512};
+
+use crate::keys::{b58check_decode, b58check_encode, PublicKey};
+use crate::ru256::RU256;
+use crate::secp256k1::{Point, SECP256K1};
+
+// BIP32 public derivation, enough to derive watch-only keys from an account
+// xpub. Hardened children need the private key and aren't supported.
+
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// Child numbers from here on are hardened
+pub const HARDENED: u32 = 1 << 31;
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    const BLOCK_SIZE: usize = 128;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..64].copy_from_slice(&Sha512::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let ipad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    let opad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha512::new()
+        .chain_update(ipad)
+        .chain_update(data)
+        .finalize();
+    Sha512::new()
+        .chain_update(opad)
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Bip32ParseError;
+
+/// An xpub (or tpub on testnet)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedPublicKey {
+    pub net: String,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+impl ExtendedPublicKey {
+    /// First 4 bytes of the hash160 of the key, which children refer to
+    pub fn fingerprint(&self) -> [u8; 4] {
+        self.public_key.sec(true, true)[..4].try_into().unwrap()
+    }
+
+    /// Non-hardened child `index`, parent key + HMAC(chain code, key || index) * G
+    pub fn derive_child(&self, index: u32) -> Self {
+        assert!(
+            index < HARDENED,
+            "hardened derivation needs the private key"
+        );
+        let mut data = self.public_key.sec(true, false);
+        data.extend(index.to_be_bytes());
+        let i = hmac_sha512(&self.chain_code, &data);
+
+        let tweak = RU256::from_bytes(&i[..32]);
+        // happens with probability ~2^-127, BIP32 says to skip to the next index
+        assert!(
+            tweak < SECP256K1::n(),
+            "invalid child key, use the next index"
+        );
+        let point: Point = SECP256K1::public_key(&tweak) + self.public_key.0.clone();
+
+        ExtendedPublicKey {
+            net: self.net.clone(),
+            depth: self.depth + 1,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code: i[32..].try_into().unwrap(),
+            public_key: PublicKey::from_point(point),
+        }
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Self {
+        path.iter()
+            .fold(self.clone(), |key, &index| key.derive_child(index))
+    }
+}
+
+impl FromStr for ExtendedPublicKey {
+    type Err = Bip32ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = b58check_decode(s).ok_or(Bip32ParseError)?;
+        if bytes.len() != 78 {
+            return Err(Bip32ParseError);
+        }
+        let version: [u8; 4] = bytes[..4].try_into().unwrap();
+        let net = match version {
+            XPUB_VERSION => "main",
+            TPUB_VERSION => "test",
+            _ => return Err(Bip32ParseError),
+        };
+        let key = &bytes[45..78];
+        if key[0] != 0x02 && key[0] != 0x03 {
+            return Err(Bip32ParseError);
+        }
+        Ok(ExtendedPublicKey {
+            net: net.to_string(),
+            depth: bytes[4],
+            parent_fingerprint: bytes[5..9].try_into().unwrap(),
+            child_number: u32::from_be_bytes(bytes[9..13].try_into().unwrap()),
+            chain_code: bytes[13..45].try_into().unwrap(),
+            public_key: PublicKey::from_bytes(key),
+        })
+    }
+}
+
+impl fmt::Display for ExtendedPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = match self.net.as_str() {
+            "main" => XPUB_VERSION.to_vec(),
+            "test" => TPUB_VERSION.to_vec(),
+            net => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        bytes.push(self.depth);
+        bytes.extend(self.parent_fingerprint);
+        bytes.extend(self.child_number.to_be_bytes());
+        bytes.extend(self.chain_code);
+        bytes.extend(self.public_key.sec(true, false));
+        write!(f, "{}", b58check_encode(&bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_sha512_rfc4231() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha512(b"Jefe", b"what do ya want for nothing?")),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+    }
+
+    #[test]
+    fn xpub_parse_and_derive() {
+        // BIP84 account 0 of the "abandon ... about" mnemonic, as an xpub
+        let s = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let xpub = ExtendedPublicKey::from_str(s).unwrap();
+        assert_eq!(xpub.net, "main");
+        assert_eq!(xpub.depth, 3);
+        assert_eq!(xpub.child_number, HARDENED);
+        assert_eq!(xpub.to_string(), s);
+
+        let child = xpub.derive_path(&[0, 0]);
+        assert_eq!(child.depth, 5);
+        assert_eq!(
+            hex::encode(child.public_key.sec(true, false)),
+            "0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c"
+        );
+
+        assert_eq!(ExtendedPublicKey::from_str(&s[1..]), Err(Bip32ParseError));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rand::Rng;
+
+use crate::field::{Fn, Fp};
+use crate::keys::{gen_secret_key_with_rng, PublicKey};
+use crate::ru256::RU256;
+use crate::secp256k1::{point_add, point_mul, Point, SECP256K1};
+use crate::signature::{schnorr_challenge, Signature};
+
+// Blind Schnorr signatures, the construction behind Brands' credentials and
+// the first WabiSabi drafts, as a second way to blind sign next to the
+// Diffie-Hellman blinding of Chaumian ecash. The signer sends a nonce point
+// R = kG. The user shifts it to R' = R + alpha G + beta P with random alpha
+// and beta, which makes the challenge of its message e' = H(R' || m), and
+// sends the signer e = e' + beta. The signer answers s = k + e d without
+// learning anything about R' or m, and the user's s' = s + alpha makes
+// (R', s') an ordinary Schnorr signature on m under the signer's key, checked
+// by `verify_schnorr`.
+//
+// The catch is concurrency. A signer with many sessions open at once has
+// handed out many R's and answers challenges of the user's choosing, and
+// finding challenges whose answers combine into one signature more than it
+// issued is the ROS problem. Wagner's algorithm solves it in about 2^32 work
+// with a few hundred sessions, and Benhamouda et al. (2020) in polynomial
+// time once there are more than 256, so concurrent sessions forge. With a
+// single session at a time the scheme is secure, which is why `BlindSigner`
+// caps the sessions it keeps open. Schemes like Clause Blind Schnorr or the
+// Diffie-Hellman blinding don't have this problem.
+
+/// Open sessions a signer allows by default, one session at a time is the
+/// only setting safe from ROS attacks
+pub const MAX_PARALLEL_SESSIONS: usize = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlindError {
+    /// Opening another session would exceed the limit
+    TooManySessions,
+    /// The session was never opened or already answered
+    UnknownSession,
+}
+
+impl fmt::Display for BlindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlindError::TooManySessions => write!(f, "too many signing sessions open"),
+            BlindError::UnknownSession => write!(f, "no such signing session"),
+        }
+    }
+}
+
+impl std::error::Error for BlindError {}
+
+/// The signing side, which keeps the nonce of every open session
+#[derive(Debug)]
+pub struct BlindSigner {
+    secret_key: Fn,
+    public_key: PublicKey,
+    max_sessions: usize,
+    sessions: HashMap<u64, Fn>,
+    next_session: u64,
+}
+
+impl BlindSigner {
+    pub fn new(secret_key: &RU256) -> Self {
+        Self::with_max_sessions(secret_key, MAX_PARALLEL_SESSIONS)
+    }
+
+    /// A signer allowing `max_sessions` open sessions, more than one is
+    /// open to ROS attacks and only here to demonstrate them
+    pub fn with_max_sessions(secret_key: &RU256, max_sessions: usize) -> Self {
+        let secret_key = Fn::new(secret_key);
+        let public_key = PublicKey::from_point(point_mul(&secret_key, &SECP256K1::g()));
+        BlindSigner {
+            secret_key,
+            public_key,
+            max_sessions,
+            sessions: HashMap::new(),
+            next_session: 0,
+        }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn open_sessions(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Start a session, returns its id and the nonce point R for the user
+    pub fn commit<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<(u64, Point), BlindError> {
+        if self.sessions.len() >= self.max_sessions {
+            return Err(BlindError::TooManySessions);
+        }
+        let k = Fn::new(&gen_secret_key_with_rng(&SECP256K1::n(), rng));
+        sensitive!(?k, "blind schnorr nonce");
+        let nonce_point = point_mul(&k, &SECP256K1::g());
+        let id = self.next_session;
+        self.next_session += 1;
+        self.sessions.insert(id, k);
+        Ok((id, nonce_point))
+    }
+
+    /// Answer the blinded challenge of session `id`, closing it. A nonce
+    /// answers a single challenge, two answers give the secret key away.
+    pub fn sign(&mut self, id: u64, challenge: &RU256) -> Result<RU256, BlindError> {
+        let k = self
+            .sessions
+            .remove(&id)
+            .ok_or(BlindError::UnknownSession)?;
+        let s = k + Fn::new(challenge) * self.secret_key.clone();
+        Ok(s.as_ru256().clone())
+    }
+
+    /// Give up on session `id` without signing
+    pub fn abort(&mut self, id: u64) -> Result<(), BlindError> {
+        self.sessions
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(BlindError::UnknownSession)
+    }
+}
+
+/// The user's side of one session, holding the blinding factors
+#[derive(Debug, Clone)]
+pub struct BlindRequest {
+    signer_key: Point,
+    nonce_point: Point,
+    alpha: Fn,
+    /// e = e' + beta, what the signer answers
+    challenge: Fn,
+    blinded_nonce: Fp,
+}
+
+impl BlindRequest {
+    /// Blind the signer's `nonce_point` for `message`, returns the request
+    /// and the challenge to send to the signer
+    pub fn new<R: Rng + ?Sized>(
+        signer_key: &PublicKey,
+        nonce_point: &Point,
+        message: &[u8],
+        rng: &mut R,
+    ) -> (Self, RU256) {
+        let n = SECP256K1::n();
+        let alpha = Fn::new(&gen_secret_key_with_rng(&n, rng));
+        let beta = Fn::new(&gen_secret_key_with_rng(&n, rng));
+
+        // R' = R + alpha G + beta P
+        let shift = point_add(
+            &point_mul(&alpha, &SECP256K1::g()),
+            &point_mul(&beta, &signer_key.0),
+        );
+        let blinded = point_add(nonce_point, &shift);
+        let blinded_nonce = Fp::new(&blinded.x);
+        let challenge = schnorr_challenge(&blinded_nonce, message) + beta;
+        let request = BlindRequest {
+            signer_key: signer_key.0.clone(),
+            nonce_point: nonce_point.clone(),
+            alpha,
+            challenge: challenge.clone(),
+            blinded_nonce,
+        };
+        (request, challenge.as_ru256().clone())
+    }
+
+    /// The signature on the message, None if the signer's answer is wrong,
+    /// sG = R + eP
+    pub fn unblind(&self, s: &RU256) -> Option<Signature> {
+        let s = Fn::from_canonical(s)?;
+        let expected = point_add(
+            &self.nonce_point,
+            &point_mul(&self.challenge, &self.signer_key),
+        );
+        if point_mul(&s, &SECP256K1::g()) != expected {
+            return None;
+        }
+        Some(Signature {
+            r: self.blinded_nonce.as_ru256().clone(),
+            s: (s + self.alpha.clone()).as_ru256().clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::signature::verify_schnorr;
+
+    #[test]
+    fn test_blind_signature() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut signer = BlindSigner::new(&RU256::from_u64(0xb11d));
+        let message = b"one token";
+
+        let (id, nonce_point) = signer.commit(&mut rng).unwrap();
+        assert_eq!(signer.commit(&mut rng), Err(BlindError::TooManySessions));
+        let (request, challenge) =
+            BlindRequest::new(signer.public_key(), &nonce_point, message, &mut rng);
+        let s = signer.sign(id, &challenge).unwrap();
+        assert_eq!(signer.sign(id, &challenge), Err(BlindError::UnknownSession));
+        assert_eq!(signer.open_sessions(), 0);
+
+        // the signer never saw R' or the message, and neither is in what
+        // it signed
+        let sig = request.unblind(&s).unwrap();
+        assert_ne!(sig.r, nonce_point.x);
+        assert_ne!(sig.s, s);
+        assert!(verify_schnorr(signer.public_key(), message, &sig));
+
+        // a wrong answer is caught before unblinding
+        let wrong = Fn::new(&s) + Fn::one();
+        assert_eq!(request.unblind(wrong.as_ru256()), None);
+    }
+
+    #[test]
+    fn test_session_limit() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut signer = BlindSigner::with_max_sessions(&RU256::from_u64(3), 2);
+        let (first, _) = signer.commit(&mut rng).unwrap();
+        signer.commit(&mut rng).unwrap();
+        assert_eq!(signer.commit(&mut rng), Err(BlindError::TooManySessions));
+        signer.abort(first).unwrap();
+        assert_eq!(signer.abort(first), Err(BlindError::UnknownSession));
+        assert!(signer.commit(&mut rng).is_ok());
+    }
+}
@@ -1,9 +1,20 @@
-use std::io::{Cursor, Read};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use once_cell::sync::Lazy;
 use primitive_types::U256;
+use serde_json::{json, Value};
 
-use crate::sha256;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::amount::{Amount, SAT_PER_BTC};
+use crate::encoding::{
+    try_read_varint, try_take, try_take_array, DecodingError, Encodable, TryDecodable,
+};
+use crate::hashes::sha256d;
+use crate::transaction::{Cmd, FeeError, Prevouts, Script, Tx, TxIn, TxOut};
+use crate::{sha256, utils};
 
 static GENESIS_BLOCK_MAIN: Lazy<Vec<u8>> = Lazy::new(|| {
     hex::decode("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c").unwrap()
@@ -12,94 +23,127 @@ static GENESIS_BLOCK_MAIN: Lazy<Vec<u8>> = Lazy::new(|| {
 static GENESIS_BLOCK_TEST: Lazy<Vec<u8>> = Lazy::new(|| {
     hex::decode("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff001d1aa4ae18").unwrap()
 });
-fn decode_int(s: &mut Cursor<&Vec<u8>>, nbytes: usize) -> u32 {
-    let mut buf = vec![0; nbytes];
-    s.read_exact(&mut buf).unwrap();
-    u32::from_le_bytes(buf.try_into().unwrap())
-}
+
+/// The headline Satoshi put in the genesis coinbase
+pub const GENESIS_TIMESTAMP: &str =
+    "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks";
+
+const OP_CHECKSIG: u8 = 0xac;
+
+/// The key the genesis coinbase pays to, its 50 BTC can never be spent
+pub const GENESIS_PUBKEY: &str = "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f";
 
 fn encode_int(i: u32, nbytes: usize) -> Vec<u8> {
     i.to_le_bytes()[..nbytes].to_vec()
 }
 
+/// Headers the median time past is taken over
+const MEDIAN_TIME_SPAN: usize = 11;
+/// How far ahead of the node's clock a header's timestamp may be
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+// Difficulty adjustment parameters
+const RETARGET_INTERVAL: u32 = 2016;
+pub const TARGET_SPACING: u32 = 60 * 10;
+const TARGET_TIMESPAN: u32 = 60 * 60 * 24 * 14;
+
+/// Bits of the easiest allowed target, used by the genesis block
+const POW_LIMIT_BITS: [u8; 4] = [0xff, 0xff, 0x00, 0x1d];
+
 fn bits_to_target(bits: &[u8]) -> U256 {
     let exponent = bits[3] as usize;
     let coeff = U256::from_little_endian(&bits[..3]);
-    coeff * U256::from(256).pow(U256::from(exponent - 3))
+    if exponent <= 3 {
+        coeff >> (8 * (3 - exponent))
+    } else {
+        coeff << (8 * (exponent - 3))
+    }
 }
 
 fn target_to_bits(target: U256) -> Vec<u8> {
-    let mut b = vec![0u8; 32];
-    target.to_big_endian(&mut b);
-    while b[0] == 0 {
-        b.remove(0);
-    }
-    let (exponent, coeff) = if b[0] >= 128 {
-        (b.len() + 1, [0, b[0], b[1]])
+    let mut exponent = target.bits().div_ceil(8);
+    let mut coeff = if exponent <= 3 {
+        target.low_u32() << (8 * (3 - exponent))
     } else {
-        (b.len(), [b[0], b[1], b[2]])
+        (target >> (8 * (exponent - 3))).low_u32()
     };
-    let mut new_bits = coeff.to_vec();
-    new_bits.reverse();
+    // the coefficient is signed, if its top bit would be set we shift it down a
+    // byte and bump the exponent instead
+    if coeff & 0x00800000 != 0 {
+        coeff >>= 8;
+        exponent += 1;
+    }
+    let mut new_bits = coeff.to_le_bytes()[..3].to_vec();
     new_bits.push(exponent as u8);
     new_bits
 }
 
-fn calculate_new_bits(prev_bits: &[u8], dt: u32) -> Vec<u8> {
-    let two_weeks = 60 * 60 * 24 * 14;
-    let dt = dt.clamp(two_weeks / 4, two_weeks * 4);
-    println!("Clamped dt: {}", dt);
+/// Expected number of hashes needed to find a block with the given bits,
+/// 2^256 / (target + 1)
+pub fn work_from_bits(bits: &[u8]) -> U256 {
+    let target = bits_to_target(bits);
+    // 2^256 doesn't fit in a U256, but 2^256 / (target + 1) is equal to
+    // (2^256 - target - 1) / (target + 1) + 1
+    (!target / (target + U256::one())) + U256::one()
+}
 
+/// New bits after a retarget period, given the timestamps of the first and
+/// last block of the period
+fn calculate_new_bits(prev_bits: &[u8], first_timestamp: u32, last_timestamp: u32) -> Vec<u8> {
+    // block timestamps are not strictly increasing so the timespan can even be
+    // negative, clamp it to a factor of 4 in either direction
+    let timespan = TARGET_TIMESPAN as i64;
+    let dt = (last_timestamp as i64 - first_timestamp as i64).clamp(timespan / 4, timespan * 4);
     let prev_target = bits_to_target(prev_bits);
-    println!("Previous target: {:?}", prev_target);
-
-    let new_target = (prev_target * U256::from(dt)) / U256::from(two_weeks);
-    println!("New target before min: {:?}", new_target);
-
-    let max_target = U256::from(0xffff) * U256::from(256).pow(U256::from(0x1d - 3));
-    println!("Max target: {:?}", max_target);
-
-    let new_target = new_target.min(max_target);
-    println!("New target after min: {:?}", new_target);
+    let new_target = (prev_target * U256::from(dt)) / U256::from(timespan);
+    let new_target = new_target.min(bits_to_target(&POW_LIMIT_BITS));
+    debug!(dt, ?prev_target, ?new_target, "retarget");
 
     target_to_bits(new_target)
 }
 
 #[derive(Debug, Clone)]
-struct Block {
-    version: u32,
-    prev_block: Vec<u8>,
-    merkle_root: Vec<u8>,
-    timestamp: u32,
-    bits: Vec<u8>,
-    nonce: Vec<u8>,
+pub struct Block {
+    pub version: u32,
+    pub prev_block: Vec<u8>,
+    pub merkle_root: Vec<u8>,
+    pub timestamp: u32,
+    pub bits: Vec<u8>,
+    pub nonce: Vec<u8>,
+    /// Transactions of the block, empty when only the header was decoded
+    pub txs: Vec<Tx>,
 }
 
 impl Block {
-    fn decode(s: &mut Cursor<&Vec<u8>>) -> Block {
-        let version = decode_int(s, 4);
-        let mut prev_block = vec![0; 32];
-        s.read_exact(&mut prev_block).unwrap();
+    /// Decode the 80 byte header, leaving the transactions empty
+    pub fn decode_header(bytes: &mut &[u8]) -> Block {
+        Block::try_decode_header(bytes).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// `decode_header`, but a short header is an error
+    pub fn try_decode_header(bytes: &mut &[u8]) -> Result<Block, DecodingError> {
+        let version = u32::from_le_bytes(try_take_array(bytes)?);
+        let mut prev_block = try_take(bytes, 32)?.to_vec();
         prev_block.reverse();
-        let mut merkle_root = vec![0; 32];
-        s.read_exact(&mut merkle_root).unwrap();
+        let mut merkle_root = try_take(bytes, 32)?.to_vec();
         merkle_root.reverse();
-        let timestamp = decode_int(s, 4);
-        let mut bits = vec![0; 4];
-        s.read_exact(&mut bits).unwrap();
-        let mut nonce = vec![0; 4];
-        s.read_exact(&mut nonce).unwrap();
-        Block {
+        let timestamp = u32::from_le_bytes(try_take_array(bytes)?);
+        let bits = try_take(bytes, 4)?.to_vec();
+        let nonce = try_take(bytes, 4)?.to_vec();
+        Ok(Block {
             version,
             prev_block,
             merkle_root,
             timestamp,
             bits,
             nonce,
-        }
+            txs: vec![],
+        })
     }
 
-    fn encode(&self) -> Vec<u8> {
+    /// Encode the 80 byte header, which is what the block id and proof of
+    /// work commit to
+    pub fn encode_header(&self) -> Vec<u8> {
         let mut out = vec![];
         out.extend(encode_int(self.version, 4));
         let mut prev_block = self.prev_block.clone();
@@ -114,24 +158,22 @@ impl Block {
         out
     }
 
-    fn id(&self) -> String {
-        let mut result = sha256::hash256(self.encode());
+    pub fn id(&self) -> String {
+        let mut result = sha256::hash256(self.encode_header());
         result.reverse();
         hex::encode(result)
     }
 
-    fn target(&self) -> U256 {
+    pub fn target(&self) -> U256 {
         bits_to_target(&self.bits)
     }
 
-    fn difficulty(&self) -> U256 {
-        let genesis_block_target = U256::from(0xffff) * U256::from(256).pow(U256::from(0x1d - 3));
-        let target = self.target();
-        let difficulty = genesis_block_target / target;
-        difficulty
+    /// How many times harder the target is than the easiest allowed one
+    pub fn difficulty(&self) -> U256 {
+        bits_to_target(&POW_LIMIT_BITS) / self.target()
     }
 
-    fn validate(&self) -> bool {
+    pub fn validate(&self) -> bool {
         let header_vec = hex::decode(&self.id()).unwrap();
         let header: [u8; 32] = header_vec.try_into().unwrap();
         let header = U256::from_big_endian(&header);
@@ -143,14 +185,577 @@ impl Block {
 
         true
     }
+
+    /// The block as Bitcoin Core's `getblock <hash> 2` shows it, minus the
+    /// fields that depend on the chain like the height and confirmations
+    pub fn to_json(&self, net: &str) -> Value {
+        let mut bits = self.bits.clone();
+        bits.reverse();
+        let stripped_size = 80
+            + utils::encode_varint(self.txs.len() as u64).len()
+            + self
+                .txs
+                .iter()
+                .map(|tx| tx.encode_legacy().len())
+                .sum::<usize>();
+        let size = self.encode().len();
+        json!({
+            "hash": self.id(),
+            "size": size,
+            "strippedsize": stripped_size,
+            "weight": stripped_size * 3 + size,
+            "version": self.version,
+            "versionHex": format!("{:08x}", self.version),
+            "merkleroot": hex::encode(&self.merkle_root),
+            "tx": self.txs.iter().map(|tx| tx.to_json(net)).collect::<Vec<_>>(),
+            "time": self.timestamp,
+            "nonce": u32::from_le_bytes(self.nonce.clone().try_into().unwrap()),
+            "bits": hex::encode(bits),
+            "difficulty": to_f64(bits_to_target(&POW_LIMIT_BITS)) / to_f64(self.target()),
+            "previousblockhash": hex::encode(&self.prev_block),
+            "nTx": self.txs.len(),
+        })
+    }
+
+    /// The merkle root committing to `txs`, in the same byte order as the
+    /// `merkle_root` field
+    pub fn compute_merkle_root(&self) -> Vec<u8> {
+        let mut root = merkle_root(&txids(&self.txs)).to_vec();
+        root.reverse();
+        root
+    }
+
+    /// Search for the lowest nonce meeting the target and set it, returns
+    /// false if none of the 2^32 nonces does
+    pub fn mine(&mut self) -> bool {
+        let header = self.encode_header();
+        let target = self.target();
+        let nonce = (0..=u32::MAX).find(|&nonce| meets_target(&header, nonce, target));
+        self.set_nonce(nonce)
+    }
+
+    /// Same as [`Block::mine`] with the nonces split across threads, still
+    /// finding the lowest valid nonce
+    #[cfg(feature = "parallel")]
+    pub fn par_mine(&mut self) -> bool {
+        let header = self.encode_header();
+        let target = self.target();
+        let nonce = (0..=u32::MAX)
+            .into_par_iter()
+            .find_first(|&nonce| meets_target(&header, nonce, target));
+        self.set_nonce(nonce)
+    }
+
+    fn set_nonce(&mut self, nonce: Option<u32>) -> bool {
+        match nonce {
+            Some(nonce) => {
+                self.nonce = nonce.to_le_bytes().to_vec();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn to_f64(n: U256) -> f64 {
+    n.0.iter()
+        .rev()
+        .fold(0.0, |acc, &limb| acc * 2f64.powi(64) + limb as f64)
+}
+
+fn meets_target(header: &[u8], nonce: u32, target: U256) -> bool {
+    let mut header: [u8; 80] = header.try_into().unwrap();
+    header[76..].copy_from_slice(&nonce.to_le_bytes());
+    U256::from_little_endian(&sha256d(&header)) < target
+}
+
+/// Txids in internal byte order, the leaves of the merkle tree
+pub fn txids(txs: &[Tx]) -> Vec<[u8; 32]> {
+    let encoded: Vec<Vec<u8>> = txs.iter().map(Tx::encode_legacy).collect();
+    sha256::hash256_many(&encoded.iter().map(Vec::as_slice).collect::<Vec<_>>())
+}
+
+#[cfg(feature = "parallel")]
+pub fn par_txids(txs: &[Tx]) -> Vec<[u8; 32]> {
+    txs.par_iter()
+        .map(|tx| sha256d(&tx.encode_legacy()))
+        .collect()
+}
+
+/// The `i`th pair of a merkle tree level, an odd last node is paired with
+/// itself
+fn merkle_pair(level: &[[u8; 32]], i: usize) -> [u8; 64] {
+    let left = level[2 * i];
+    let right = level.get(2 * i + 1).unwrap_or(&left);
+    let mut pair = [0u8; 64];
+    pair[..32].copy_from_slice(&left);
+    pair[32..].copy_from_slice(right);
+    pair
+}
+
+/// Hash pairs of nodes level by level until a single root is left
+pub fn merkle_root(txids: &[[u8; 32]]) -> [u8; 32] {
+    assert!(!txids.is_empty(), "a block has at least a coinbase tx");
+    let mut level = txids.to_vec();
+    while level.len() > 1 {
+        let pairs: Vec<[u8; 64]> = (0..level.len().div_ceil(2))
+            .map(|i| merkle_pair(&level, i))
+            .collect();
+        level = sha256::hash256_many(&pairs.iter().map(|pair| pair.as_slice()).collect::<Vec<_>>());
+    }
+    level[0]
+}
+
+#[cfg(feature = "parallel")]
+pub fn par_merkle_root(txids: &[[u8; 32]]) -> [u8; 32] {
+    assert!(!txids.is_empty(), "a block has at least a coinbase tx");
+    let mut level = txids.to_vec();
+    while level.len() > 1 {
+        level = (0..level.len().div_ceil(2))
+            .into_par_iter()
+            .map(|i| sha256d(&merkle_pair(&level, i)))
+            .collect();
+    }
+    level[0]
+}
+
+/// A genesis block with the given nonce, built like Bitcoin Core's
+/// CreateGenesisBlock: one coinbase paying 50 BTC to `pubkey` with
+/// `psz_timestamp` in its scriptSig
+fn genesis_block(psz_timestamp: &str, pubkey: &[u8], time: u32, bits: &[u8], nonce: u32) -> Block {
+    let coinbase = Tx {
+        version: 1,
+        tx_ins: vec![TxIn {
+            prev_tx: vec![0; 32],
+            prev_index: 0xffffffff,
+            // the pushes are 486604799 (0x1d00ffff) and 4 as script numbers,
+            // whatever the bits of the network
+            script_sig: Script::new(vec![
+                Cmd::push(&[0xff, 0xff, 0x00, 0x1d]),
+                Cmd::push(&[0x04]),
+                Cmd::push(psz_timestamp.as_bytes()),
+            ]),
+            sequence: 0xffffffff,
+            ..Default::default()
+        }],
+        tx_outs: vec![TxOut {
+            amount: Amount::from_sat(50 * SAT_PER_BTC),
+            script_pubkey: Script::new(vec![Cmd::push(pubkey), Cmd::Op(OP_CHECKSIG)]),
+        }],
+        locktime: 0,
+        segwit: false,
+    };
+    let mut block = Block {
+        version: 1,
+        prev_block: vec![0; 32],
+        merkle_root: vec![],
+        timestamp: time,
+        bits: bits.to_vec(),
+        nonce: nonce.to_le_bytes().to_vec(),
+        txs: vec![coinbase],
+    };
+    block.merkle_root = block.compute_merkle_root();
+    block
+}
+
+/// Build and mine the genesis block of a custom network, `bits` in the same
+/// little endian form as `Block::bits`
+pub fn build_genesis(psz_timestamp: &str, pubkey: &[u8], time: u32, bits: &[u8]) -> Block {
+    let mut block = genesis_block(psz_timestamp, pubkey, time, bits, 0);
+    assert!(
+        block.mine(),
+        "no nonce meets the target, try another time or easier bits"
+    );
+    block
+}
+
+impl Block {
+    /// The full genesis block of `net`, rebuilt from its parameters
+    pub fn genesis(net: &str) -> Block {
+        let (time, nonce) = match net {
+            "main" => (1231006505, 2083236893),
+            "test" => (1296688602, 414098458),
+            _ => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        let pubkey = hex::decode(GENESIS_PUBKEY).unwrap();
+        genesis_block(GENESIS_TIMESTAMP, &pubkey, time, &POW_LIMIT_BITS, nonce)
+    }
+}
+
+// Monetary policy: every block may create a subsidy of new coins on top of the
+// fees of its transactions, paid out by its coinbase. The subsidy started at
+// 50 BTC and halves every 210,000 blocks (about four years), rounding down to
+// the satoshi, until it reaches zero after 33 halvings. That caps the supply
+// just under 21 million BTC.
+
+/// Blocks between halvings of the subsidy
+pub const HALVING_INTERVAL: u32 = 210_000;
+const INITIAL_SUBSIDY: u64 = 50 * SAT_PER_BTC;
+
+/// The new coins the block at `height` may create
+pub fn subsidy_at_height(height: u32) -> Amount {
+    let halvings = height / HALVING_INTERVAL;
+    // shifting a u64 by 64 or more bits isn't defined
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_sat(INITIAL_SUBSIDY >> halvings)
+}
+
+/// All the coins created by the blocks up to and including `height`
+pub fn supply_at_height(height: u32) -> Amount {
+    let end = height as u64 + 1;
+    let mut supply = 0;
+    let mut start = 0;
+    while start < end {
+        let era_end = (start + HALVING_INTERVAL as u64).min(end);
+        supply += (era_end - start) * subsidy_at_height(start as u32).to_sat();
+        start = era_end;
+    }
+    Amount::from_sat(supply)
+}
+
+/// Every coin there will ever be, 20,999,999.9769 BTC
+pub fn total_supply() -> Amount {
+    // the subsidy is 1 sat in the 33rd era and 0 after
+    supply_at_height(33 * HALVING_INTERVAL - 1)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockError {
+    /// The header hash doesn't meet its target
+    HighHash,
+    /// The merkle root doesn't commit to the transactions
+    BadMerkleRoot,
+    /// The first transaction isn't a coinbase
+    NoCoinbase,
+    /// A coinbase other than the first transaction
+    MultipleCoinbase,
+    /// The fee of the transaction at this index can't be worked out
+    Fee(usize, FeeError),
+    /// The coinbase pays out more than the subsidy and fees
+    BadCoinbaseAmount { value: Amount, max: Amount },
+}
+
+impl BlockError {
+    /// Bitcoin Core's reject reason for the failure
+    pub fn reason(&self) -> &'static str {
+        match self {
+            BlockError::HighHash => "high-hash",
+            BlockError::BadMerkleRoot => "bad-txnmrklroot",
+            BlockError::NoCoinbase => "bad-cb-missing",
+            BlockError::MultipleCoinbase => "bad-cb-multiple",
+            BlockError::Fee(_, FeeError::MissingPrevout { .. }) => "bad-txns-inputs-missingorspent",
+            BlockError::Fee(_, FeeError::Overflow) => "bad-txns-inputvalues-outofrange",
+            BlockError::Fee(_, FeeError::Negative { .. }) => "bad-txns-in-belowout",
+            BlockError::BadCoinbaseAmount { .. } => "bad-cb-amount",
+        }
+    }
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason())?;
+        match self {
+            BlockError::Fee(index, error) => write!(f, " (tx {}: {})", index, error),
+            BlockError::BadCoinbaseAmount { value, max } => {
+                write!(f, ", {} > {}", value.to_sat(), max.to_sat())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+impl Block {
+    /// Check the block as the `height`th of the chain: its proof of work,
+    /// that the merkle root commits to its transactions, that only the first
+    /// is a coinbase and that the coinbase claims no more than the subsidy and
+    /// fees. `prevouts` are the outputs its transactions spend from earlier
+    /// blocks, those of earlier transactions in the block are added as it goes.
+    pub fn check(&self, height: u32, prevouts: &Prevouts) -> Result<(), BlockError> {
+        if !self.validate() {
+            return Err(BlockError::HighHash);
+        }
+        if self.txs.is_empty() || self.compute_merkle_root() != self.merkle_root {
+            return Err(BlockError::BadMerkleRoot);
+        }
+        if !self.txs[0].is_coinbase() {
+            return Err(BlockError::NoCoinbase);
+        }
+
+        let mut prevouts = prevouts.clone();
+        let mut fees = Amount::ZERO;
+        for (index, tx) in self.txs.iter().enumerate() {
+            if index > 0 {
+                if tx.is_coinbase() {
+                    return Err(BlockError::MultipleCoinbase);
+                }
+                let fee = tx.fee(&prevouts).map_err(|e| BlockError::Fee(index, e))?;
+                fees = fees
+                    .checked_add(fee)
+                    .ok_or(BlockError::Fee(index, FeeError::Overflow))?;
+                // a spent output is gone for the rest of the block, which
+                // also catches a transaction spending one twice
+                for tx_in in &tx.tx_ins {
+                    let outpoint = (tx_in.prev_tx.clone(), tx_in.prev_index);
+                    if prevouts.remove(&outpoint).is_none() {
+                        let (prev_tx, prev_index) = outpoint;
+                        let missing = FeeError::MissingPrevout {
+                            prev_tx,
+                            prev_index,
+                        };
+                        return Err(BlockError::Fee(index, missing));
+                    }
+                }
+            }
+            let mut txid = hex::decode(tx.id()).unwrap();
+            txid.reverse();
+            for (vout, tx_out) in tx.tx_outs.iter().enumerate() {
+                prevouts.insert((txid.clone(), vout as u32), tx_out.clone());
+            }
+        }
+
+        let value = Amount::checked_sum(self.txs[0].tx_outs.iter().map(|tx_out| tx_out.amount))
+            .ok_or(BlockError::Fee(0, FeeError::Overflow))?;
+        let max = subsidy_at_height(height) + fees;
+        if value > max {
+            return Err(BlockError::BadCoinbaseAmount { value, max });
+        }
+        Ok(())
+    }
+}
+
+/// The header followed by the transactions
+impl Encodable for Block {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = self.encode_header();
+        out.extend(utils::encode_varint(self.txs.len() as u64));
+        for tx in &self.txs {
+            out.extend(tx.encode());
+        }
+        out
+    }
+}
+
+impl TryDecodable for Block {
+    fn try_decode(bytes: &mut &[u8]) -> Result<Self, DecodingError> {
+        let mut block = Block::try_decode_header(bytes)?;
+        let tx_count = try_read_varint(bytes)?;
+        block.txs = (0..tx_count)
+            .map(|_| Tx::try_decode(bytes))
+            .collect::<Result<_, _>>()?;
+        Ok(block)
+    }
+}
+
+/// The best chain of headers, starting at the genesis block, along with the
+/// cumulative work of each header
+#[derive(Debug, Clone)]
+pub struct Chain {
+    net: String,
+    headers: Vec<Block>,
+    chainwork: Vec<U256>,
+}
+
+impl Chain {
+    /// A chain containing only the genesis block of the network
+    pub fn new(net: &str) -> Self {
+        let genesis = match net {
+            "main" => GENESIS_BLOCK_MAIN.to_vec(),
+            "test" => GENESIS_BLOCK_TEST.to_vec(),
+            _ => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        let genesis = Block::decode_header(&mut genesis.as_slice());
+        let mut chain = Chain {
+            net: net.to_string(),
+            headers: vec![],
+            chainwork: vec![],
+        };
+        chain.append(genesis);
+        chain
+    }
+
+    /// A chain starting at a custom genesis block, following the difficulty
+    /// rules of `net`
+    pub fn with_genesis(net: &str, genesis: Block) -> Self {
+        assert!(
+            net == "main" || net == "test",
+            "{} is not a valid net type, should be main|test",
+            net
+        );
+        let mut chain = Chain {
+            net: net.to_string(),
+            headers: vec![],
+            chainwork: vec![],
+        };
+        chain.append(genesis);
+        chain
+    }
+
+    pub fn height(&self) -> u32 {
+        self.headers.len() as u32 - 1
+    }
+
+    pub fn tip(&self) -> &Block {
+        self.headers.last().unwrap()
+    }
+
+    pub fn header(&self, height: u32) -> Option<&Block> {
+        self.headers.get(height as usize)
+    }
+
+    /// Height of the header with the given id, if it's in the chain
+    pub fn height_of(&self, id: &str) -> Option<u32> {
+        self.headers
+            .iter()
+            .position(|header| header.id() == id)
+            .map(|height| height as u32)
+    }
+
+    /// Total work of the chain up to and including the tip
+    pub fn chainwork(&self) -> U256 {
+        *self.chainwork.last().unwrap()
+    }
+
+    /// The bits a block building on the tip with the given timestamp must have
+    pub fn next_bits(&self, timestamp: u32) -> Vec<u8> {
+        let tip = self.tip();
+        let next_height = self.height() + 1;
+
+        if !next_height.is_multiple_of(RETARGET_INTERVAL) {
+            if self.net == "test" {
+                // testnet allows a minimum difficulty block if no block was found
+                // for twice the target spacing
+                if timestamp > tip.timestamp + 2 * TARGET_SPACING {
+                    return POW_LIMIT_BITS.to_vec();
+                }
+                // otherwise the difficulty is that of the last block which wasn't
+                // mined under the minimum difficulty rule
+                let mut height = self.height();
+                while !height.is_multiple_of(RETARGET_INTERVAL)
+                    && self.headers[height as usize].bits == POW_LIMIT_BITS
+                {
+                    height -= 1;
+                }
+                return self.headers[height as usize].bits.clone();
+            }
+            return tip.bits.clone();
+        }
+
+        let first = &self.headers[(next_height - RETARGET_INTERVAL) as usize];
+        calculate_new_bits(&tip.bits, first.timestamp, tip.timestamp)
+    }
+
+    /// The median timestamp of the 11 headers ending at `height`, fewer
+    /// near genesis. Miners set timestamps as they like, within limits, but
+    /// the median moves forward only as the majority of them does.
+    pub fn median_time_past_at(&self, height: u32) -> u32 {
+        let end = height as usize + 1;
+        let start = end.saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times: Vec<u32> = self.headers[start..end]
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// The median time past of the tip, what the next block's timestamp has
+    /// to beat and its transactions' time locks are measured against (BIP113)
+    pub fn median_time_past(&self) -> u32 {
+        self.median_time_past_at(self.height())
+    }
+
+    /// Whether `tx`'s locktime lets it into the next block
+    pub fn is_final_tx(&self, tx: &Tx) -> bool {
+        tx.is_final(self.height() + 1, self.median_time_past())
+    }
+
+    /// Extend the chain by one header. Returns false, leaving the chain
+    /// untouched, if the header doesn't build on the tip, has the wrong bits,
+    /// doesn't meet its target, or its timestamp isn't past the median time
+    /// past or is more than two hours ahead of the clock.
+    pub fn push(&mut self, block: Block) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock is before 1970")
+            .as_secs() as u32;
+        self.push_at(block, now)
+    }
+
+    /// Like `push`, with `now` as the time on the node's clock
+    pub fn push_at(&mut self, block: Block, now: u32) -> bool {
+        if hex::encode(&block.prev_block) != self.tip().id() {
+            return false;
+        }
+        if block.timestamp <= self.median_time_past()
+            || block.timestamp > now.saturating_add(MAX_FUTURE_BLOCK_TIME)
+        {
+            return false;
+        }
+        if block.bits != self.next_bits(block.timestamp) {
+            return false;
+        }
+        if !block.validate() {
+            return false;
+        }
+        self.append(block);
+        true
+    }
+
+    /// The chain as it was when `height` was the tip
+    pub fn fork_at(&self, height: u32) -> Chain {
+        assert!(height <= self.height(), "height {} is past the tip", height);
+        let len = height as usize + 1;
+        Chain {
+            net: self.net.clone(),
+            headers: self.headers[..len].to_vec(),
+            chainwork: self.chainwork[..len].to_vec(),
+        }
+    }
+
+    /// Offer a branch of headers building on any header of the chain, and
+    /// switch to it if it ends with more work than the tip. Returns whether the
+    /// tip changed. A branch with an invalid header is rejected as a whole, and
+    /// on equal work the current tip stays since it was seen first.
+    pub fn submit_branch(&mut self, headers: Vec<Block>) -> bool {
+        let Some(first) = headers.first() else {
+            return false;
+        };
+        let Some(fork_height) = self.height_of(&hex::encode(&first.prev_block)) else {
+            return false;
+        };
+        let mut branch = self.fork_at(fork_height);
+        for header in headers {
+            if !branch.push(header) {
+                return false;
+            }
+        }
+        if branch.chainwork() <= self.chainwork() {
+            return false;
+        }
+        *self = branch;
+        true
+    }
+
+    fn append(&mut self, block: Block) {
+        let work = work_from_bits(&block.bits);
+        let chainwork = match self.chainwork.last() {
+            Some(prev) => *prev + work,
+            None => work,
+        };
+        self.headers.push(block);
+        self.chainwork.push(chainwork);
+    }
 }
 
 #[test]
 fn test_block() {
     let raw = hex::decode("020000208ec39428b17323fa0ddec8e887b4a7c53b8c0a0a220cfd0000000000000000005b0750fce0a889502d40508d39576821155e9c9e3f5c3157f961db38fd8b25be1e77a759e93c0118a4ffd71d").unwrap();
     println!("Raw block data: {}", hex::encode(&raw));
-    let mut cursor = Cursor::new(&raw);
-    let block = Block::decode(&mut cursor);
+    let block = Block::decode_header(&mut raw.as_slice());
     println!("Decoded block: {:?}", block);
 
     assert_eq!(block.version, 0x20000002);
@@ -166,7 +771,7 @@ fn test_block() {
     assert_eq!(block.bits, hex::decode("e93c0118").unwrap());
     assert_eq!(block.nonce, hex::decode("a4ffd71d").unwrap());
 
-    let raw2 = block.encode();
+    let raw2 = block.encode_header();
     println!("Encoded block data: {}", hex::encode(&raw2));
     assert_eq!(raw, raw2);
 
@@ -197,26 +802,25 @@ fn test_block() {
 fn test_validate() {
     let raw = hex::decode("04000000fbedbbf0cfdaf278c094f187f2eb987c86a199da22bbb20400000000000000007b7697b29129648fa08b4bcd13c9d5e60abb973a1efac9c8d573c71c807c56c3d6213557faa80518c3737ec1").unwrap();
     println!("Raw block data for validation: {}", hex::encode(&raw));
-    let mut cursor = Cursor::new(&raw);
-    let block = Block::decode(&mut cursor);
+    let block = Block::decode_header(&mut raw.as_slice());
     println!("Decoded block for validation: {:?}", block);
     assert!(block.validate());
 
     let raw = hex::decode("04000000fbedbbf0cfdaf278c094f187f2eb987c86a199da22bbb20400000000000000007b7697b29129648fa08b4bcd13c9d5e60abb973a1efac9c8d573c71c807c56c3d6213557faa80518c3737ec0").unwrap();
     println!("Raw block data for invalidation: {}", hex::encode(&raw));
-    let mut cursor = Cursor::new(&raw);
-    let block = Block::decode(&mut cursor);
+    let block = Block::decode_header(&mut raw.as_slice());
     println!("Decoded block for invalidation: {:?}", block);
     assert!(!block.validate());
 }
 
 #[test]
 fn test_calculate_bits() {
-    let dt = 302400;
+    let first_timestamp = 1500000000;
+    let last_timestamp = first_timestamp + 302400;
     let prev_bits = hex::decode("54d80118").unwrap();
 
     println!("Previous bits: {:?}", prev_bits);
-    let next_bits = calculate_new_bits(&prev_bits, dt);
+    let next_bits = calculate_new_bits(&prev_bits, first_timestamp, last_timestamp);
     println!("Next bits: {:?}", next_bits);
     assert_eq!(next_bits, hex::decode("00157617").unwrap());
 
@@ -236,8 +840,7 @@ fn test_genesis_block() {
     let block_bytes = GENESIS_BLOCK_MAIN.to_vec();
     println!("Genesis block bytes: {}", hex::encode(&block_bytes));
     assert_eq!(block_bytes.len(), 80);
-    let mut cursor = Cursor::new(&block_bytes);
-    let block = Block::decode(&mut cursor);
+    let block = Block::decode_header(&mut block_bytes.as_slice());
     let block_clone = block.clone();
 
     println!("Decoded genesis block: {:?}", block);
@@ -275,3 +878,471 @@ fn test_genesis_block() {
     println!("Genesis block validation: {}", validation);
     assert!(validation);
 }
+
+#[test]
+fn test_merkle_root() {
+    // block 100000
+    let txids: Vec<[u8; 32]> = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ]
+    .iter()
+    .map(|txid| {
+        let mut txid: [u8; 32] = hex::decode(txid).unwrap().try_into().unwrap();
+        txid.reverse();
+        txid
+    })
+    .collect();
+    let mut root = merkle_root(&txids);
+    root.reverse();
+    assert_eq!(
+        hex::encode(root),
+        "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766"
+    );
+    // a single tx is its own root, an odd one out is paired with itself
+    assert_eq!(merkle_root(&txids[..1]), txids[0]);
+    assert_eq!(
+        merkle_root(&txids[..3]),
+        merkle_root(&[txids[0], txids[1], txids[2], txids[2]])
+    );
+
+    #[cfg(feature = "parallel")]
+    for count in 1..=txids.len() {
+        assert_eq!(
+            par_merkle_root(&txids[..count]),
+            merkle_root(&txids[..count])
+        );
+    }
+}
+
+#[test]
+fn test_to_json() {
+    let block = Block::decode_header(&mut GENESIS_BLOCK_MAIN.as_slice());
+    let json = block.to_json("main");
+    assert_eq!(
+        json["hash"],
+        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+    );
+    assert_eq!(json["bits"], "1d00ffff");
+    assert_eq!(json["difficulty"], 1.0);
+    assert_eq!(json["nonce"], 2083236893);
+    assert_eq!(json["versionHex"], "00000001");
+}
+
+#[test]
+fn test_genesis_construction() {
+    for (net, header) in [("main", &GENESIS_BLOCK_MAIN), ("test", &GENESIS_BLOCK_TEST)] {
+        let block = Block::genesis(net);
+        assert_eq!(block.encode_header(), header.as_slice());
+        assert!(block.validate());
+    }
+    let genesis = Block::genesis("main");
+    assert_eq!(
+        genesis.txs[0].id(),
+        "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+    );
+    // the full block, header, tx count and coinbase
+    assert_eq!(genesis.encode().len(), 285);
+
+    // a regtest-like network, where half of all nonces work
+    let pubkey = hex::decode(GENESIS_PUBKEY).unwrap();
+    let block = build_genesis(
+        "cryptos course",
+        &pubkey,
+        1700000000,
+        &[0xff, 0xff, 0x7f, 0x20],
+    );
+    assert!(block.validate());
+    assert_eq!(block.merkle_root, block.compute_merkle_root());
+    assert_eq!(block.prev_block, vec![0; 32]);
+}
+
+#[test]
+fn test_mine() {
+    let mut block = Block::decode_header(&mut GENESIS_BLOCK_MAIN.as_slice());
+    // regtest's minimum difficulty, every other hash is below the target
+    block.bits = vec![0xff, 0xff, 0x7f, 0x20];
+    assert!(block.mine());
+    assert!(block.validate());
+
+    #[cfg(feature = "parallel")]
+    {
+        let mut parallel = block.clone();
+        parallel.nonce = vec![0xff; 4];
+        assert!(parallel.par_mine());
+        assert_eq!(parallel.nonce, block.nonce);
+    }
+}
+
+#[test]
+fn test_work_from_bits() {
+    // chainwork of the genesis block as reported by bitcoind
+    assert_eq!(work_from_bits(&POW_LIMIT_BITS), U256::from(0x100010001u64));
+    assert_eq!(Chain::new("main").chainwork(), U256::from(0x100010001u64));
+
+    // halving the target doubles the work
+    let bits = target_to_bits(bits_to_target(&POW_LIMIT_BITS) / 2);
+    assert_eq!(work_from_bits(&bits), U256::from(0x200020002u64));
+}
+
+#[test]
+fn test_small_targets() {
+    for exponent in 1..=3u8 {
+        let bits = [0x12, 0x34, 0x56, exponent];
+        let target = bits_to_target(&bits);
+        assert_eq!(
+            target,
+            U256::from(0x563412) >> (8 * (3 - exponent as usize))
+        );
+    }
+    assert_eq!(
+        target_to_bits(U256::from(0x12)),
+        vec![0x00, 0x00, 0x12, 0x01]
+    );
+}
+
+#[test]
+fn test_chain_push() {
+    let mut chain = Chain::new("main");
+    let raw = hex::decode("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299").unwrap();
+    let block1 = Block::decode_header(&mut raw.as_slice());
+
+    assert!(chain.push(block1.clone()));
+    assert_eq!(chain.height(), 1);
+    assert_eq!(
+        chain.tip().id(),
+        "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+    );
+    assert_eq!(chain.chainwork(), U256::from(0x200020002u64));
+
+    // block 1 no longer builds on the tip
+    assert!(!chain.push(block1));
+    assert_eq!(chain.height(), 1);
+}
+
+#[cfg(test)]
+fn extend_chain(chain: &mut Chain, count: u32, spacing: u32, bits: &[u8]) {
+    for _ in 0..count {
+        let mut block = chain.tip().clone();
+        block.timestamp += spacing;
+        block.bits = bits.to_vec();
+        chain.append(block);
+    }
+}
+
+#[test]
+fn test_retarget() {
+    // blocks found twice as fast as expected, the target should halve
+    let mut chain = Chain::new("main");
+    extend_chain(
+        &mut chain,
+        RETARGET_INTERVAL - 1,
+        TARGET_SPACING / 2,
+        &POW_LIMIT_BITS,
+    );
+    assert_eq!(chain.height(), RETARGET_INTERVAL - 1);
+    assert_eq!(
+        chain.chainwork(),
+        work_from_bits(&POW_LIMIT_BITS) * U256::from(RETARGET_INTERVAL)
+    );
+
+    let first_timestamp = chain.header(0).unwrap().timestamp;
+    let last_timestamp = chain.tip().timestamp;
+    let next_bits = chain.next_bits(last_timestamp + TARGET_SPACING);
+    assert_eq!(
+        next_bits,
+        calculate_new_bits(&POW_LIMIT_BITS, first_timestamp, last_timestamp)
+    );
+    assert_eq!(next_bits, hex::decode("3fef7f1c").unwrap());
+
+    // mid period the bits don't change
+    extend_chain(&mut chain, 1, TARGET_SPACING, &next_bits);
+    assert_eq!(chain.next_bits(chain.tip().timestamp + 1), next_bits);
+
+    // slow blocks can never push the target past the limit
+    let mut chain = Chain::new("main");
+    extend_chain(
+        &mut chain,
+        RETARGET_INTERVAL - 1,
+        TARGET_SPACING * 2,
+        &POW_LIMIT_BITS,
+    );
+    assert_eq!(
+        chain.next_bits(chain.tip().timestamp),
+        POW_LIMIT_BITS.to_vec()
+    );
+}
+
+#[test]
+fn test_testnet_min_difficulty() {
+    let bits = hex::decode("54d80118").unwrap();
+    let mut chain = Chain::new("test");
+    extend_chain(&mut chain, 1, TARGET_SPACING, &bits);
+    let timestamp = chain.tip().timestamp;
+
+    // more than 20 minutes without a block allows a minimum difficulty block
+    assert_eq!(
+        chain.next_bits(timestamp + 2 * TARGET_SPACING + 1),
+        POW_LIMIT_BITS
+    );
+    assert_eq!(chain.next_bits(timestamp + TARGET_SPACING), bits);
+
+    // after a minimum difficulty block the last real difficulty applies again
+    extend_chain(&mut chain, 1, 3 * TARGET_SPACING, &POW_LIMIT_BITS);
+    assert_eq!(
+        chain.next_bits(chain.tip().timestamp + TARGET_SPACING),
+        bits
+    );
+
+    // mainnet has no such rule
+    let mut chain = Chain::new("main");
+    extend_chain(&mut chain, 1, TARGET_SPACING, &bits);
+    assert_eq!(
+        chain.next_bits(chain.tip().timestamp + 3 * TARGET_SPACING),
+        bits
+    );
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn prop_block_roundtrip(block in crate::strategies::block()) {
+        let header = block.encode_header();
+        let decoded = Block::decode_header(&mut header.as_slice());
+        proptest::prop_assert_eq!(decoded.encode_header(), header);
+
+        let raw = block.encode();
+        let decoded = Block::try_decode_all(&raw).unwrap();
+        proptest::prop_assert_eq!(decoded.txs.len(), block.txs.len());
+        proptest::prop_assert_eq!(decoded.encode(), raw);
+    }
+}
+
+#[test]
+fn test_subsidy() {
+    let btc = |btc: u64| Amount::from_sat(btc * SAT_PER_BTC);
+    assert_eq!(subsidy_at_height(0), btc(50));
+    assert_eq!(subsidy_at_height(209_999), btc(50));
+    assert_eq!(subsidy_at_height(210_000), btc(25));
+    assert_eq!(subsidy_at_height(840_000), Amount::from_sat(312_500_000));
+    assert_eq!(
+        subsidy_at_height(32 * HALVING_INTERVAL),
+        Amount::from_sat(1)
+    );
+    assert_eq!(subsidy_at_height(33 * HALVING_INTERVAL), Amount::ZERO);
+    assert_eq!(subsidy_at_height(64 * HALVING_INTERVAL), Amount::ZERO);
+
+    assert_eq!(supply_at_height(0), btc(50));
+    assert_eq!(supply_at_height(209_999), btc(10_500_000));
+    assert_eq!(supply_at_height(210_000), btc(10_500_025));
+    assert_eq!(total_supply(), Amount::from_sat(2_099_999_997_690_000));
+    assert_eq!(supply_at_height(u32::MAX), total_supply());
+}
+
+#[test]
+fn test_check_coinbase_amount() {
+    assert_eq!(Block::genesis("main").check(0, &Prevouts::new()), Ok(()));
+
+    // a block at the fourth halving with one transaction paying 1000 sat
+    let mut prevouts = Prevouts::new();
+    prevouts.insert(
+        (vec![0x11; 32], 0),
+        TxOut {
+            amount: Amount::from_sat(10_000),
+            script_pubkey: Script::p2pkh(&[0xaa; 20]),
+        },
+    );
+    let spend = Tx {
+        version: 1,
+        tx_ins: vec![TxIn {
+            prev_tx: vec![0x11; 32],
+            prev_index: 0,
+            sequence: 0xffffffff,
+            ..Default::default()
+        }],
+        tx_outs: vec![TxOut {
+            amount: Amount::from_sat(9_000),
+            script_pubkey: Script::p2pkh(&[0xbb; 20]),
+        }],
+        locktime: 0,
+        segwit: false,
+    };
+    let block = |coinbase_value: u64, txs: Vec<Tx>| {
+        let mut block = genesis_block(
+            "height 840000",
+            &[0x02; 33],
+            1713571767,
+            &[0xff, 0xff, 0x7f, 0x20],
+            0,
+        );
+        block.txs[0].tx_outs[0].amount = Amount::from_sat(coinbase_value);
+        block.txs.extend(txs);
+        block.merkle_root = block.compute_merkle_root();
+        assert!(block.mine());
+        block
+    };
+    let full = 312_500_000 + 1_000;
+    assert_eq!(
+        block(full, vec![spend.clone()]).check(840_000, &prevouts),
+        Ok(())
+    );
+    // claiming less is allowed, those coins are gone for good
+    assert_eq!(
+        block(full - 1, vec![spend.clone()]).check(840_000, &prevouts),
+        Ok(())
+    );
+
+    let error = block(full + 1, vec![spend.clone()])
+        .check(840_000, &prevouts)
+        .unwrap_err();
+    assert_eq!(
+        error,
+        BlockError::BadCoinbaseAmount {
+            value: Amount::from_sat(full + 1),
+            max: Amount::from_sat(full),
+        }
+    );
+    assert_eq!(error.reason(), "bad-cb-amount");
+    // the same coinbase was fine before the halving
+    assert_eq!(
+        block(full + 1, vec![spend.clone()]).check(839_999, &prevouts),
+        Ok(())
+    );
+
+    // a transaction spending an output created earlier in the block
+    let mut spend_txid = hex::decode(spend.id()).unwrap();
+    spend_txid.reverse();
+    let chained = Tx {
+        tx_ins: vec![TxIn {
+            prev_tx: spend_txid,
+            prev_index: 0,
+            sequence: 0xffffffff,
+            ..Default::default()
+        }],
+        tx_outs: vec![TxOut {
+            amount: Amount::from_sat(8_500),
+            script_pubkey: Script::p2pkh(&[0xcc; 20]),
+        }],
+        ..spend.clone()
+    };
+    let both = vec![spend.clone(), chained.clone()];
+    assert_eq!(block(full + 500, both).check(840_000, &prevouts), Ok(()));
+    let out_of_order = vec![chained, spend.clone()];
+    assert_eq!(
+        block(full, out_of_order)
+            .check(840_000, &prevouts)
+            .unwrap_err()
+            .reason(),
+        "bad-txns-inputs-missingorspent"
+    );
+
+    // two transactions spending the same output, the second finds it spent
+    // and neither fee reaches the coinbase
+    let double_spend = Tx {
+        tx_outs: vec![TxOut {
+            amount: Amount::from_sat(8_000),
+            script_pubkey: Script::p2pkh(&[0xdd; 20]),
+        }],
+        ..spend.clone()
+    };
+    assert_eq!(
+        block(full + 2_000, vec![spend.clone(), double_spend]).check(840_000, &prevouts),
+        Err(BlockError::Fee(
+            2,
+            FeeError::MissingPrevout {
+                prev_tx: vec![0x11; 32],
+                prev_index: 0,
+            }
+        ))
+    );
+    // or one transaction spending it twice
+    let mut twice = spend.clone();
+    twice.tx_ins.push(twice.tx_ins[0].clone());
+    assert_eq!(
+        block(full + 10_000, vec![twice])
+            .check(840_000, &prevouts)
+            .unwrap_err()
+            .reason(),
+        "bad-txns-inputs-missingorspent"
+    );
+
+    let mut bad_root = block(full, vec![]);
+    bad_root.merkle_root = vec![0; 32];
+    bad_root.mine();
+    assert_eq!(
+        bad_root.check(840_000, &prevouts),
+        Err(BlockError::BadMerkleRoot)
+    );
+}
+
+#[test]
+fn test_median_time_past() {
+    let mut chain = Chain::new("main");
+    let genesis_time = chain.tip().timestamp;
+    assert_eq!(chain.median_time_past(), genesis_time);
+    extend_chain(&mut chain, 20, TARGET_SPACING, &POW_LIMIT_BITS);
+    // the 6th newest of the last 11
+    assert_eq!(chain.median_time_past(), genesis_time + 15 * TARGET_SPACING);
+    assert_eq!(chain.median_time_past_at(2), genesis_time + TARGET_SPACING);
+
+    // one timestamp far in the future barely moves it
+    let mut block = chain.tip().clone();
+    block.timestamp += 100 * TARGET_SPACING;
+    chain.append(block);
+    assert_eq!(chain.median_time_past(), genesis_time + 16 * TARGET_SPACING);
+}
+
+#[test]
+fn test_timestamp_rules() {
+    let pubkey = hex::decode(GENESIS_PUBKEY).unwrap();
+    let easy_bits = [0xff, 0xff, 0x7f, 0x20];
+    let start = 1700000000;
+    let genesis = build_genesis("timestamps", &pubkey, start, &easy_bits);
+    let mut chain = Chain::with_genesis("main", genesis);
+    let next = |chain: &Chain, timestamp: u32| {
+        let mut block = Block {
+            version: 1,
+            prev_block: hex::decode(chain.tip().id()).unwrap(),
+            merkle_root: vec![0; 32],
+            timestamp,
+            bits: easy_bits.to_vec(),
+            nonce: vec![0; 4],
+            txs: vec![],
+        };
+        assert!(block.mine());
+        block
+    };
+    for i in 1..=4 {
+        assert!(chain.push(next(&chain, start + i * TARGET_SPACING)));
+    }
+    // the median of the five timestamps is the third
+    let mtp = chain.median_time_past();
+    assert_eq!(mtp, start + 2 * TARGET_SPACING);
+    assert!(!chain.push(next(&chain, mtp)));
+    // but a block may be older than its parent
+    assert!(chain.push(next(&chain, mtp + 1)));
+
+    let now = start + 10 * TARGET_SPACING;
+    assert!(!chain.push_at(next(&chain, now + MAX_FUTURE_BLOCK_TIME + 1), now));
+    assert!(chain.push_at(next(&chain, now + MAX_FUTURE_BLOCK_TIME), now));
+    assert_eq!(chain.height(), 6);
+
+    // a transaction locked until just past the median time past
+    let mtp = chain.median_time_past();
+    let mut tx = Tx {
+        version: 1,
+        tx_ins: vec![TxIn {
+            prev_tx: vec![0x11; 32],
+            sequence: 0xfffffffe,
+            ..Default::default()
+        }],
+        tx_outs: vec![],
+        locktime: mtp,
+        segwit: false,
+    };
+    assert!(!chain.is_final_tx(&tx));
+    tx.locktime = mtp - 1;
+    assert!(chain.is_final_tx(&tx));
+}
@@ -0,0 +1,488 @@
+use crate::block::{txids, Block};
+use crate::encoding::{take, Decodable, Encodable};
+use crate::hashes::sha256d;
+use crate::murmur3::murmur3;
+use crate::transaction::{Cmd, Script, Tx};
+use crate::utils;
+
+// BIP37 bloom filters, how SPV wallets used to ask full nodes for only their
+// own transactions: the wallet sends a `filterload` with its keys, scripts
+// and outpoints hashed into a bit field, and the node answers each block with
+// a `merkleblock`, the header plus a partial merkle tree proving which of the
+// block's transactions matched. False positives were meant to hide the
+// wallet's real elements among random ones, but the node sees every filter
+// and can test any script against it, and filters loaded again with a new
+// tweak can be intersected until only the wallet's elements match. BIP158
+// turned this around: the node commits to one filter per block that the
+// client downloads and matches locally, so nothing about the wallet is sent.
+
+/// Filters larger than this many bytes are rejected by nodes
+const MAX_FILTER_SIZE: usize = 36_000;
+const MAX_HASH_FUNCS: u32 = 50;
+/// Multiplier spacing out the seeds of the hash functions
+const SEED_STEP: u32 = 0xfba4c795;
+
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// What the node adds to the filter when an output matches, so the wallet
+/// also hears about the transaction spending it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomUpdate {
+    None = 0,
+    All = 1,
+    /// Only for pay-to-pubkey and bare multisig outputs, whose spends don't
+    /// repeat the key
+    P2PubkeyOnly = 2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    pub bits: Vec<u8>,
+    pub hash_funcs: u32,
+    pub tweak: u32,
+    pub update: BloomUpdate,
+}
+
+impl BloomFilter {
+    /// An empty filter of `size` bytes
+    pub fn new(size: usize, hash_funcs: u32, tweak: u32, update: BloomUpdate) -> Self {
+        assert!(
+            (1..=MAX_FILTER_SIZE).contains(&size),
+            "filter size {} is out of range",
+            size
+        );
+        assert!(hash_funcs <= MAX_HASH_FUNCS, "too many hash functions");
+        BloomFilter {
+            bits: vec![0; size],
+            hash_funcs,
+            tweak,
+            update,
+        }
+    }
+
+    /// A filter sized like Bitcoin Core does for `elements` items and a false
+    /// positive rate of `fp_rate`
+    pub fn for_elements(elements: usize, fp_rate: f64, tweak: u32, update: BloomUpdate) -> Self {
+        let ln2 = core::f64::consts::LN_2;
+        let bits = (-1.0 / (ln2 * ln2) * elements as f64 * fp_rate.ln()) as usize;
+        let size = bits.min(MAX_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((size * 8) as f64 / elements as f64 * ln2) as u32;
+        BloomFilter::new(size.max(1), hash_funcs.min(MAX_HASH_FUNCS), tweak, update)
+    }
+
+    fn bit_indices<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        (0..self.hash_funcs).map(move |i| {
+            let seed = i.wrapping_mul(SEED_STEP).wrapping_add(self.tweak);
+            murmur3(seed, data) as usize % (self.bits.len() * 8)
+        })
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        let indices: Vec<usize> = self.bit_indices(data).collect();
+        for i in indices {
+            self.bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    /// Whether `data` may have been inserted, always true if it was
+    pub fn contains(&self, data: &[u8]) -> bool {
+        self.bit_indices(data)
+            .all(|i| self.bits[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    /// Payload of the `filterload` message
+    pub fn filterload(&self) -> Vec<u8> {
+        let mut out = utils::encode_varint(self.bits.len() as u64);
+        out.extend(&self.bits);
+        out.extend(self.hash_funcs.to_le_bytes());
+        out.extend(self.tweak.to_le_bytes());
+        out.push(self.update as u8);
+        out
+    }
+
+    /// Whether a node with this filter would send `tx`, adding outpoints of
+    /// matched outputs as the update flag says. The txid, any push in an
+    /// output script, a spent outpoint or any push in a scriptSig matches.
+    pub fn is_relevant(&mut self, tx: &Tx) -> bool {
+        let txid = sha256d(&tx.encode_legacy());
+        let mut found = self.contains(&txid);
+        for (vout, tx_out) in tx.tx_outs.iter().enumerate() {
+            let script = &tx_out.script_pubkey;
+            if !pushes(script).any(|data| self.contains(&data)) {
+                continue;
+            }
+            found = true;
+            let update = match self.update {
+                BloomUpdate::None => false,
+                BloomUpdate::All => true,
+                BloomUpdate::P2PubkeyOnly => is_pubkey_output(script),
+            };
+            if update {
+                let mut outpoint = txid.to_vec();
+                outpoint.extend((vout as u32).to_le_bytes());
+                self.insert(&outpoint);
+            }
+        }
+        if found || tx.is_coinbase() {
+            return found;
+        }
+        tx.tx_ins.iter().any(|tx_in| {
+            let mut outpoint = tx_in.prev_tx.clone();
+            outpoint.extend(tx_in.prev_index.to_le_bytes());
+            self.contains(&outpoint) || pushes(&tx_in.script_sig).any(|data| self.contains(&data))
+        })
+    }
+}
+
+/// The data pushed, up to a push running past the end
+fn pushes(script: &Script) -> impl Iterator<Item = Vec<u8>> + '_ {
+    script
+        .instructions()
+        .map_while(Result::ok)
+        .filter_map(|cmd| match cmd {
+            Cmd::Push(data) => Some(data),
+            Cmd::Op(_) => None,
+        })
+}
+
+/// Pay-to-pubkey or bare multisig
+fn is_pubkey_output(script: &Script) -> bool {
+    match script.template().unwrap_or_default().as_slice() {
+        [Cmd::Push(key), Cmd::Op(OP_CHECKSIG)] => key.len() == 33 || key.len() == 65,
+        [.., Cmd::Op(OP_CHECKMULTISIG)] => true,
+        _ => false,
+    }
+}
+
+/// Candidates every filter matches. A node that was sent several filters for
+/// the same wallet, say after each reconnect with a fresh tweak, keeps only
+/// what they all match: the false positives of independent filters rarely
+/// overlap, the wallet's own elements always do.
+pub fn intersect_matches<'a>(filters: &[BloomFilter], candidates: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+    candidates
+        .iter()
+        .filter(|candidate| filters.iter().all(|filter| filter.contains(candidate)))
+        .map(Vec::as_slice)
+        .collect()
+}
+
+/// At most this many transactions fit in a block, bounding `total` of a
+/// `merkleblock` from an untrusted peer
+const MAX_BLOCK_TXS: u32 = 4_000_000 / 240;
+
+/// A block header with a partial merkle tree proving which transactions
+/// matched a filter. `hashes` and `flags` are the depth first walk of the
+/// tree: a set flag means the node has a match below it and is descended into,
+/// a clear flag (or a leaf) means its hash is the next of `hashes`.
+#[derive(Debug, Clone)]
+pub struct MerkleBlock {
+    pub header: Block,
+    pub total: u32,
+    pub hashes: Vec<[u8; 32]>,
+    pub flags: Vec<bool>,
+}
+
+/// Nodes at `height` above the leaves of a tree with `total` leaves
+fn tree_width(total: u32, height: u32) -> u32 {
+    (total + (1 << height) - 1) >> height
+}
+
+fn tree_height(total: u32) -> u32 {
+    let mut height = 0;
+    while tree_width(total, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut pair = left.to_vec();
+    pair.extend(right);
+    sha256d(&pair)
+}
+
+fn node_hash(txids: &[[u8; 32]], height: u32, pos: u32) -> [u8; 32] {
+    if height == 0 {
+        return txids[pos as usize];
+    }
+    let left = node_hash(txids, height - 1, pos * 2);
+    let right = match pos * 2 + 1 < tree_width(txids.len() as u32, height - 1) {
+        true => node_hash(txids, height - 1, pos * 2 + 1),
+        false => left,
+    };
+    hash_pair(&left, &right)
+}
+
+impl MerkleBlock {
+    /// The merkleblock for `block` proving the transactions flagged in
+    /// `matches`
+    pub fn new(block: &Block, matches: &[bool]) -> Self {
+        assert_eq!(block.txs.len(), matches.len(), "a match flag per tx");
+        let txids = txids(&block.txs);
+        let mut header = block.clone();
+        header.txs = vec![];
+        let mut merkle_block = MerkleBlock {
+            header,
+            total: txids.len() as u32,
+            hashes: vec![],
+            flags: vec![],
+        };
+        merkle_block.build(&txids, matches, tree_height(txids.len() as u32), 0);
+        merkle_block
+    }
+
+    /// The merkleblock a node with `filter` sends for `block`, updating the
+    /// filter along the way so a spend later in the block matches too
+    pub fn from_filter(block: &Block, filter: &mut BloomFilter) -> Self {
+        let matches: Vec<bool> = block.txs.iter().map(|tx| filter.is_relevant(tx)).collect();
+        MerkleBlock::new(block, &matches)
+    }
+
+    fn build(&mut self, txids: &[[u8; 32]], matches: &[bool], height: u32, pos: u32) {
+        let start = (pos << height) as usize;
+        let end = (((pos + 1) << height) as usize).min(txids.len());
+        let parent_of_match = matches[start..end].contains(&true);
+        self.flags.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            self.hashes.push(node_hash(txids, height, pos));
+            return;
+        }
+        self.build(txids, matches, height - 1, pos * 2);
+        if pos * 2 + 1 < tree_width(self.total, height - 1) {
+            self.build(txids, matches, height - 1, pos * 2 + 1);
+        }
+    }
+
+    /// Walk the tree, collecting matched txids, and return the root hash. None
+    /// if the flags or hashes run out, or both children of a node are the
+    /// same hash (CVE-2012-2459, a duplicated subtree faking a match).
+    fn extract(
+        &self,
+        height: u32,
+        pos: u32,
+        used: &mut (usize, usize),
+        matched: &mut Vec<[u8; 32]>,
+    ) -> Option<[u8; 32]> {
+        let flag = *self.flags.get(used.0)?;
+        used.0 += 1;
+        if height == 0 || !flag {
+            let hash = *self.hashes.get(used.1)?;
+            used.1 += 1;
+            if height == 0 && flag {
+                matched.push(hash);
+            }
+            return Some(hash);
+        }
+        let left = self.extract(height - 1, pos * 2, used, matched)?;
+        if pos * 2 + 1 >= tree_width(self.total, height - 1) {
+            return Some(hash_pair(&left, &left));
+        }
+        let right = self.extract(height - 1, pos * 2 + 1, used, matched)?;
+        (right != left).then(|| hash_pair(&left, &right))
+    }
+
+    /// The matched txids in internal byte order, None unless the tree is well
+    /// formed, uses up every hash and flag, and hashes to the header's merkle
+    /// root
+    pub fn matched_txids(&self) -> Option<Vec<[u8; 32]>> {
+        if self.total == 0 || self.total > MAX_BLOCK_TXS || self.hashes.len() > self.total as usize
+        {
+            return None;
+        }
+        let mut used = (0, 0);
+        let mut matched = vec![];
+        let mut root = self.extract(tree_height(self.total), 0, &mut used, &mut matched)?;
+        // only the padding of the last flag byte may be left over
+        if used.0.div_ceil(8) != self.flags.len().div_ceil(8) || used.1 != self.hashes.len() {
+            return None;
+        }
+        root.reverse();
+        (root.as_slice() == self.header.merkle_root).then_some(matched)
+    }
+}
+
+impl Encodable for MerkleBlock {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = self.header.encode_header();
+        out.extend(self.total.to_le_bytes());
+        out.extend(utils::encode_varint(self.hashes.len() as u64));
+        for hash in &self.hashes {
+            out.extend(hash);
+        }
+        let mut flag_bytes = vec![0u8; self.flags.len().div_ceil(8)];
+        for (i, &flag) in self.flags.iter().enumerate() {
+            flag_bytes[i / 8] |= (flag as u8) << (i % 8);
+        }
+        out.extend(utils::encode_varint(flag_bytes.len() as u64));
+        out.extend(flag_bytes);
+        out
+    }
+}
+
+impl Decodable for MerkleBlock {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        let header = Block::decode_header(bytes);
+        let total = utils::read_u32(bytes).unwrap();
+        let hash_count = utils::read_varint(bytes).unwrap() as usize;
+        let hashes = (0..hash_count)
+            .map(|_| take(bytes, 32).try_into().unwrap())
+            .collect();
+        let flag_count = utils::read_varint(bytes).unwrap() as usize;
+        let flags = take(bytes, flag_count)
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| (byte >> i) & 1 == 1))
+            .collect();
+        MerkleBlock {
+            header,
+            total,
+            hashes,
+            flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction::TxBuilder;
+
+    #[test]
+    fn test_filterload() {
+        // from Programming Bitcoin, chapter 12
+        let mut filter = BloomFilter::new(10, 5, 99, BloomUpdate::None);
+        filter.insert(b"Hello World");
+        filter.insert(b"Goodbye!");
+        assert_eq!(
+            hex::encode(filter.filterload()),
+            "0a4000600a080000010940050000006300000000"
+        );
+
+        // from Bitcoin Core's bloom_tests
+        for (tweak, expected) in [
+            (0, "03614e9b050000000000000001"),
+            (2147483649, "03ce4299050000000100008001"),
+        ] {
+            let mut filter = BloomFilter::for_elements(3, 0.01, tweak, BloomUpdate::All);
+            for data in [
+                "99108ad8ed9bb6274d3980bab5a85c048f0950c8",
+                "b5a2c786d9ef4658287ced5914b37a1b4aa32eee",
+                "b9300670b4c5366e95b2699e8b18bc75e5f729c5",
+            ] {
+                filter.insert(&hex::decode(data).unwrap());
+                assert!(filter.contains(&hex::decode(data).unwrap()));
+            }
+            assert!(
+                !filter.contains(&hex::decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap())
+            );
+            assert_eq!(hex::encode(filter.filterload()), expected);
+        }
+    }
+
+    /// A block of `count` (at least 2) transactions paying to distinct key
+    /// hashes, the last one spending the first one's output
+    fn test_block(count: u8) -> Block {
+        let mut txs: Vec<Tx> = (0..count - 1)
+            .map(|i| {
+                TxBuilder::new("main")
+                    .add_input(vec![i; 32], 0)
+                    .add_output(Amount::from_sat(1_000), Script::p2pkh(&[i; 20]))
+                    .build()
+            })
+            .collect();
+        let spend = TxBuilder::new("main")
+            .add_input(sha256d(&txs[0].encode_legacy()).to_vec(), 0)
+            .add_output(Amount::from_sat(900), Script::p2pkh(&[0xee; 20]))
+            .build();
+        txs.push(spend);
+        let mut block = Block {
+            version: 1,
+            prev_block: vec![0; 32],
+            merkle_root: vec![],
+            timestamp: 0,
+            bits: vec![0xff, 0xff, 0x00, 0x1d],
+            nonce: vec![0; 4],
+            txs,
+        };
+        block.merkle_root = block.compute_merkle_root();
+        block
+    }
+
+    #[test]
+    fn test_merkleblock() {
+        for count in [2, 3, 7, 12] {
+            let block = test_block(count);
+            let txids = txids(&block.txs);
+            for pattern in [0u32, 1, 0b101, 0xfff, 1 << (count - 1)] {
+                let matches: Vec<bool> = (0..count).map(|i| pattern >> i & 1 == 1).collect();
+                let merkle_block = MerkleBlock::new(&block, &matches);
+                let decoded = MerkleBlock::decode_all(&merkle_block.encode());
+                let expected: Vec<[u8; 32]> = txids
+                    .iter()
+                    .zip(&matches)
+                    .filter(|(_, &matched)| matched)
+                    .map(|(txid, _)| *txid)
+                    .collect();
+                assert_eq!(decoded.matched_txids(), Some(expected));
+            }
+        }
+
+        let block = test_block(7);
+        let mut merkle_block =
+            MerkleBlock::new(&block, &[false, true, false, false, false, false, false]);
+        merkle_block.hashes[0][0] ^= 1;
+        assert_eq!(merkle_block.matched_txids(), None);
+        let mut merkle_block = MerkleBlock::new(&block, &[true; 7]);
+        merkle_block.hashes.pop();
+        assert_eq!(merkle_block.matched_txids(), None);
+    }
+
+    #[test]
+    fn test_filter_follows_spends() {
+        let block = test_block(6);
+        let txids = txids(&block.txs);
+        let mut filter = BloomFilter::for_elements(10, 0.0001, 7, BloomUpdate::All);
+        filter.insert(&[0; 20]);
+        let merkle_block = MerkleBlock::from_filter(&block, &mut filter);
+        // the payment to the key hash and the spend of it, found through the
+        // outpoint the filter picked up
+        assert_eq!(merkle_block.matched_txids(), Some(vec![txids[0], txids[5]]));
+
+        let mut filter = BloomFilter::for_elements(10, 0.0001, 7, BloomUpdate::None);
+        filter.insert(&[0; 20]);
+        let merkle_block = MerkleBlock::from_filter(&block, &mut filter);
+        assert_eq!(merkle_block.matched_txids(), Some(vec![txids[0]]));
+    }
+
+    #[test]
+    fn test_intersecting_filters() {
+        // a wallet with 10 key hashes hoping a 5% false positive rate hides
+        // them among 2000 others
+        let wallet: Vec<Vec<u8>> = (0..10u32)
+            .map(|i| sha256d(&i.to_le_bytes())[..20].to_vec())
+            .collect();
+        let mut candidates: Vec<Vec<u8>> = (10..2000u32)
+            .map(|i| sha256d(&i.to_le_bytes())[..20].to_vec())
+            .collect();
+        candidates.extend(wallet.clone());
+        let filters: Vec<BloomFilter> = (0..3)
+            .map(|tweak| {
+                let mut filter =
+                    BloomFilter::for_elements(wallet.len(), 0.05, tweak, BloomUpdate::None);
+                for element in &wallet {
+                    filter.insert(element);
+                }
+                filter
+            })
+            .collect();
+
+        let one = intersect_matches(&filters[..1], &candidates);
+        assert!(one.len() > wallet.len() + 20);
+        let all = intersect_matches(&filters, &candidates);
+        assert!(all.len() <= wallet.len() + 1);
+        assert!(wallet
+            .iter()
+            .all(|element| all.contains(&element.as_slice())));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+
+use rayon::prelude::*;
+
+use crate::index::Utxo;
+use crate::keys::PublicKey;
+use crate::ru256::RU256;
+use crate::secp256k1::SECP256K1;
+use crate::sha256::sha256;
+use crate::transaction::Script;
+use crate::wallet::ChainBackend;
+
+// Why brainwallets fail: a key that is the sha256 of a passphrase is only as
+// hard to guess as the passphrase, and people pick passphrases from the same
+// dictionaries, quotes and leaked password lists attackers have. Cracking is
+// deriving the key and both P2PKH addresses (compressed and uncompressed
+// public key, old brainwallet tools used the latter) for every candidate, and
+// looking them up in an address index. Derivation runs in parallel, each
+// candidate is one scalar multiplication, and the lookups go to any
+// `ChainBackend` like the block index, or to a plain set of addresses.
+
+/// The key a brainwallet makes from `passphrase`, None in the unlikely case
+/// the hash isn't a valid secret key
+pub fn brainwallet_key(passphrase: &str) -> Option<RU256> {
+    let key = RU256::from_bytes(&sha256(passphrase.as_bytes().to_vec()));
+    (key >= RU256::from_u64(1) && key < SECP256K1::n()).then_some(key)
+}
+
+/// A passphrase whose address has been used
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub passphrase: String,
+    pub secret_key: RU256,
+    pub address: String,
+    pub compressed: bool,
+    /// Transactions paying to or spending from the address
+    pub history: Vec<String>,
+    /// What's left to take
+    pub unspent: Vec<Utxo>,
+}
+
+/// A passphrase with its key pair
+struct Candidate<'a> {
+    passphrase: &'a str,
+    secret_key: RU256,
+    public_key: PublicKey,
+}
+
+fn derive<'a>(passphrases: &[&'a str]) -> Vec<Candidate<'a>> {
+    let g = SECP256K1::g();
+    passphrases
+        .par_iter()
+        .filter_map(|passphrase| {
+            let secret_key = brainwallet_key(passphrase)?;
+            // the public key isn't secret here, but the ladder is the fastest
+            // multiplication the crate has
+            let public_key =
+                PublicKey::from_point(SECP256K1::scalar_multiplication_ct(&secret_key, &g));
+            Some(Candidate {
+                passphrase,
+                secret_key,
+                public_key,
+            })
+        })
+        .collect()
+}
+
+/// Look up the addresses of every passphrase in `backend`, returns the ones
+/// that were ever used, in order of passphrase
+pub fn crack<B: ChainBackend>(
+    backend: &B,
+    passphrases: &[&str],
+    net: &str,
+) -> Result<Vec<Hit>, B::Error> {
+    let mut hits = vec![];
+    for candidate in derive(passphrases) {
+        for compressed in [true, false] {
+            let script_pubkey = Script::p2pkh(&candidate.public_key.sec(compressed, true));
+            let history = backend.history(&script_pubkey)?;
+            if history.is_empty() {
+                continue;
+            }
+            hits.push(Hit {
+                passphrase: candidate.passphrase.to_string(),
+                secret_key: candidate.secret_key.clone(),
+                address: candidate.public_key.address(net, compressed),
+                compressed,
+                history,
+                unspent: backend.unspent(&script_pubkey)?,
+            });
+        }
+    }
+    Ok(hits)
+}
+
+/// The passphrases whose address is in `addresses`, with the address, for a
+/// list of funded addresses rather than an index
+pub fn crack_addresses(
+    addresses: &HashSet<String>,
+    passphrases: &[&str],
+    net: &str,
+) -> Vec<(String, String)> {
+    derive(passphrases)
+        .into_iter()
+        .flat_map(|candidate| {
+            [true, false].map(|compressed| {
+                (
+                    candidate.passphrase.to_string(),
+                    candidate.public_key.address(net, compressed),
+                )
+            })
+        })
+        .filter(|(_, address)| addresses.contains(address))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::block::Block;
+    use crate::index::Index;
+    use crate::transaction::TxBuilder;
+
+    #[test]
+    fn test_crack_addresses() {
+        // the best known brainwallet, emptied within minutes of every deposit
+        let addresses = HashSet::from(["1JwSSubhmg6iPtRjtyqhUYYH7bZg3Lfy1T".to_string()]);
+        let hits = crack_addresses(
+            &addresses,
+            &["Tr0ub4dor&3", "correct horse battery staple"],
+            "main",
+        );
+        assert_eq!(
+            hits,
+            [(
+                "correct horse battery staple".to_string(),
+                "1JwSSubhmg6iPtRjtyqhUYYH7bZg3Lfy1T".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_crack_index() {
+        let secret_key = brainwallet_key("password").unwrap();
+        let public_key = PublicKey::from_point(SECP256K1::scalar_multiplication_ct(
+            &secret_key,
+            &SECP256K1::g(),
+        ));
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0; 32], 0xffffffff)
+            .add_output(
+                Amount::from_sat(50_000),
+                Script::p2pkh(&public_key.sec(true, true)),
+            )
+            .build();
+        let mut block = Block::genesis("main");
+        block.txs = vec![tx.clone()];
+        let index = Index::temporary().unwrap();
+        index.index_block(&block, 1).unwrap();
+
+        let hits = crack(&index, &["hunter2", "password", "letmein"], "main").unwrap();
+        assert_eq!(hits.len(), 1);
+        let hit = &hits[0];
+        assert_eq!(
+            (hit.passphrase.as_str(), &hit.secret_key, hit.compressed),
+            ("password", &secret_key, true)
+        );
+        assert_eq!(hit.address, public_key.address("main", true));
+        assert_eq!(hit.history, [tx.id()]);
+        assert_eq!(hit.unspent[0].amount, Amount::from_sat(50_000));
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use serde_json::{json, Value};
+
+use crate::encoding::Encodable;
+use crate::transaction::Tx;
+
+// Pushing a signed transaction to the network. Each backend hands the raw tx to
+// some node's mempool; when the node rejects it, its reject reason is mapped to
+// a BroadcastError so the caller learns *why* (an input was already spent, the
+// fee is too low, ...) instead of getting an opaque RPC error. Only transport
+// failures are worth retrying on another backend, a rejection would just be
+// repeated by every other node.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BroadcastError {
+    /// An input doesn't exist or was already spent
+    MissingInputs,
+    /// Below the node's minimum relay or mempool fee
+    FeeTooLow(String),
+    /// Valid by consensus but rejected by standardness policy, e.g. dust
+    /// outputs or an unknown script type
+    NonStandard(String),
+    /// Any other mempool rejection, with the node's reason
+    Rejected(String),
+    /// The backend couldn't be reached or returned garbage
+    Transport(String),
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastError::MissingInputs => write!(
+                f,
+                "an input is missing or already spent, check the previous tx and output index"
+            ),
+            BroadcastError::FeeTooLow(reason) => {
+                write!(f, "fee too low ({}), increase the fee rate", reason)
+            }
+            BroadcastError::NonStandard(reason) => write!(
+                f,
+                "non-standard transaction ({}), nodes won't relay it",
+                reason
+            ),
+            BroadcastError::Rejected(reason) => write!(f, "rejected by mempool: {}", reason),
+            BroadcastError::Transport(reason) => write!(f, "broadcast failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
+/// Map a Bitcoin Core reject reason (as returned by `sendrawtransaction` and
+/// passed on by Esplora and Electrum servers) to a typed error
+pub fn parse_reject_reason(reason: &str) -> BroadcastError {
+    let reason = reason.trim().to_string();
+    if reason.contains("missingorspent")
+        || reason.contains("missing-inputs")
+        || reason.contains("Missing inputs")
+        || reason.contains("bad-txns-inputs-spent")
+    {
+        BroadcastError::MissingInputs
+    } else if reason.contains("min relay fee not met")
+        || reason.contains("mempool min fee not met")
+        || reason.contains("insufficient fee")
+        || reason.contains("fee too low")
+    {
+        BroadcastError::FeeTooLow(reason)
+    } else if reason.contains("non-standard")
+        || reason.contains("dust")
+        || reason.contains("scriptpubkey")
+        || reason.contains("scriptsig-")
+        || reason.contains("tx-size")
+        || reason.contains("multi-op-return")
+        || reason.contains("non-mandatory-script-verify-flag")
+    {
+        BroadcastError::NonStandard(reason)
+    } else {
+        BroadcastError::Rejected(reason)
+    }
+}
+
+/// Something that can submit a raw transaction, returning its txid
+pub trait Broadcaster {
+    fn broadcast_hex(&self, tx_hex: &str) -> Result<String, BroadcastError>;
+}
+
+fn transport<E: fmt::Display>(err: E) -> BroadcastError {
+    BroadcastError::Transport(err.to_string())
+}
+
+/// A JSON-RPC response, shared by Core and Electrum
+fn parse_rpc_response(response: &Value) -> Result<String, BroadcastError> {
+    if let Some(error) = response.get("error").filter(|error| !error.is_null()) {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .ok_or_else(|| transport(error))?;
+        return Err(parse_reject_reason(message));
+    }
+    response
+        .get("result")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| transport(response))
+}
+
+/// Bitcoin Core's JSON-RPC interface, e.g. `http://127.0.0.1:8332`
+pub struct CoreRpc {
+    pub url: String,
+    pub user: String,
+    pub password: String,
+}
+
+impl Broadcaster for CoreRpc {
+    fn broadcast_hex(&self, tx_hex: &str) -> Result<String, BroadcastError> {
+        let request = json!({
+            "jsonrpc": "1.0",
+            "id": "cryptos",
+            "method": "sendrawtransaction",
+            "params": [tx_hex],
+        });
+        // Core answers rejections with a 500 status, the body still has the
+        // error so don't bail out on the status
+        let body = reqwest::blocking::Client::new()
+            .post(&self.url)
+            .basic_auth(&self.user, Some(&self.password))
+            .body(request.to_string())
+            .send()
+            .and_then(|response| response.text())
+            .map_err(transport)?;
+        parse_rpc_response(&serde_json::from_str(&body).map_err(transport)?)
+    }
+}
+
+/// The Esplora REST API run by blockstream.info and mempool.space
+pub struct Esplora {
+    pub base_url: String,
+}
+
+impl Esplora {
+    pub fn blockstream(net: &str) -> Self {
+        let base_url = match net {
+            "main" => "https://blockstream.info/api",
+            "test" => "https://blockstream.info/testnet/api",
+            _ => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        Esplora {
+            base_url: base_url.to_string(),
+        }
+    }
+}
+
+/// Esplora forwards Core's error, e.g.
+/// `sendrawtransaction RPC error: {"code":-25,"message":"bad-txns-inputs-missingorspent"}`
+fn parse_esplora_error(body: &str) -> BroadcastError {
+    let message = body
+        .find('{')
+        .and_then(|start| serde_json::from_str::<Value>(&body[start..]).ok())
+        .and_then(|error| error.get("message")?.as_str().map(str::to_string));
+    parse_reject_reason(message.as_deref().unwrap_or(body))
+}
+
+impl Broadcaster for Esplora {
+    fn broadcast_hex(&self, tx_hex: &str) -> Result<String, BroadcastError> {
+        let response = reqwest::blocking::Client::new()
+            .post(format!("{}/tx", self.base_url))
+            .body(tx_hex.to_string())
+            .send()
+            .map_err(transport)?;
+        let status = response.status();
+        let body = response.text().map_err(transport)?;
+        if status.is_success() {
+            Ok(body.trim().to_string())
+        } else if status.is_client_error() {
+            Err(parse_esplora_error(&body))
+        } else {
+            Err(BroadcastError::Transport(format!("{}: {}", status, body)))
+        }
+    }
+}
+
+/// An Electrum server over plain TCP, e.g. `127.0.0.1:50001`
+pub struct Electrum {
+    pub addr: String,
+}
+
+impl Broadcaster for Electrum {
+    fn broadcast_hex(&self, tx_hex: &str) -> Result<String, BroadcastError> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "blockchain.transaction.broadcast",
+            "params": [tx_hex],
+        });
+        let mut stream = TcpStream::connect(&self.addr).map_err(transport)?;
+        writeln!(stream, "{}", request).map_err(transport)?;
+        // requests and responses are newline delimited
+        let mut line = String::new();
+        BufReader::new(stream)
+            .read_line(&mut line)
+            .map_err(transport)?;
+        parse_rpc_response(&serde_json::from_str(&line).map_err(transport)?)
+    }
+}
+
+/// Broadcast through each backend in turn, moving on to the next one only if
+/// a backend couldn't be reached. Returns the txid, or the last error.
+pub fn broadcast(tx: &Tx, backends: &[&dyn Broadcaster]) -> Result<String, BroadcastError> {
+    assert!(!backends.is_empty(), "need at least one backend");
+    let tx_hex = hex::encode(tx.encode());
+    let mut last_error = None;
+    for backend in backends {
+        match backend.broadcast_hex(&tx_hex) {
+            Err(err @ BroadcastError::Transport(_)) => {
+                debug!(%err, "backend unavailable, trying the next one");
+                last_error = Some(err);
+            }
+            result => return result,
+        }
+    }
+    Err(last_error.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    struct MockBackend {
+        result: Result<String, BroadcastError>,
+        calls: Cell<u32>,
+    }
+
+    impl MockBackend {
+        fn new(result: Result<String, BroadcastError>) -> Self {
+            MockBackend {
+                result,
+                calls: Cell::new(0),
+            }
+        }
+    }
+
+    impl Broadcaster for MockBackend {
+        fn broadcast_hex(&self, _tx_hex: &str) -> Result<String, BroadcastError> {
+            self.calls.set(self.calls.get() + 1);
+            self.result.clone()
+        }
+    }
+
+    #[test]
+    fn test_parse_reject_reason() {
+        assert_eq!(
+            parse_reject_reason("bad-txns-inputs-missingorspent"),
+            BroadcastError::MissingInputs
+        );
+        assert_eq!(
+            parse_reject_reason("min relay fee not met, 100 < 141"),
+            BroadcastError::FeeTooLow("min relay fee not met, 100 < 141".to_string())
+        );
+        assert_eq!(
+            parse_reject_reason("dust"),
+            BroadcastError::NonStandard("dust".to_string())
+        );
+        assert_eq!(
+            parse_reject_reason("txn-already-in-mempool"),
+            BroadcastError::Rejected("txn-already-in-mempool".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_backend_errors() {
+        let response = json!({
+            "result": null,
+            "error": {"code": -26, "message": "mempool min fee not met, 110 < 220"},
+            "id": "cryptos",
+        });
+        assert!(matches!(
+            parse_rpc_response(&response),
+            Err(BroadcastError::FeeTooLow(_))
+        ));
+        let response = json!({"jsonrpc": "2.0", "result": "ab".repeat(32), "id": 0});
+        assert_eq!(parse_rpc_response(&response), Ok("ab".repeat(32)));
+
+        assert_eq!(
+            parse_esplora_error(
+                r#"sendrawtransaction RPC error: {"code":-25,"message":"bad-txns-inputs-missingorspent"}"#
+            ),
+            BroadcastError::MissingInputs
+        );
+    }
+
+    #[test]
+    fn test_broadcast_falls_back_on_transport_errors() {
+        let tx = Tx::default();
+        let down = MockBackend::new(Err(BroadcastError::Transport("refused".to_string())));
+        let rejecting = MockBackend::new(Err(BroadcastError::MissingInputs));
+        let accepting = MockBackend::new(Ok("txid".to_string()));
+
+        assert_eq!(broadcast(&tx, &[&down, &accepting]), Ok("txid".to_string()));
+        // a rejection is final, the same tx would be rejected everywhere
+        assert_eq!(
+            broadcast(&tx, &[&rejecting, &accepting]),
+            Err(BroadcastError::MissingInputs)
+        );
+        assert_eq!(accepting.calls.get(), 1);
+        assert_eq!(
+            broadcast(&tx, &[&down]),
+            Err(BroadcastError::Transport("refused".to_string()))
+        );
+    }
+}
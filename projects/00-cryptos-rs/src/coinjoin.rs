@@ -0,0 +1,418 @@
+use std::fmt;
+
+use crate::amount::Amount;
+use crate::transaction::{Prevouts, Script, Tx, TxIn, TxOut};
+
+// An equal-output CoinJoin round, the way a coordinator runs one. Each
+// participant registers coins and a change script, then, as if from a fresh
+// network identity, one output script for the round's denomination. Once
+// every input has an output the coordinator builds the transaction, inputs
+// and outputs sorted so their order gives nothing away, and each participant
+// checks its outputs are in it before signing its inputs. On chain the equal
+// outputs can't be told apart, any of them could belong to any input.
+// Unlinkability towards the coordinator needs output registration to be
+// authorized by a blind signature obtained during input registration, so the
+// coordinator can't tell which participant registered which output. The
+// round here leaves that out (blind_schnorr has a scheme that would do),
+// output registration is open and only capped at one output per input.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoinjoinError {
+    /// The request doesn't belong in the round's current phase
+    WrongPhase,
+    /// The coin is worth less than the denomination plus its fee share
+    TooSmall,
+    /// The coin or script is already registered
+    Duplicate,
+    /// More outputs than registered inputs
+    TooManyOutputs,
+    /// Fewer than two participants, or outputs still missing
+    NotReady,
+    /// The signature doesn't unlock the coin
+    InvalidSignature,
+}
+
+impl fmt::Display for CoinjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinjoinError::WrongPhase => write!(f, "not accepted in this phase of the round"),
+            CoinjoinError::TooSmall => write!(f, "coin too small for the denomination"),
+            CoinjoinError::Duplicate => write!(f, "already registered"),
+            CoinjoinError::TooManyOutputs => write!(f, "more outputs than inputs"),
+            CoinjoinError::NotReady => write!(f, "round not ready for the next phase"),
+            CoinjoinError::InvalidSignature => write!(f, "invalid input signature"),
+        }
+    }
+}
+
+impl std::error::Error for CoinjoinError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    InputRegistration,
+    OutputRegistration,
+    Signing,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+struct Registration {
+    prev_tx: Vec<u8>,
+    prev_index: u32,
+    spent: TxOut,
+    change: Script,
+}
+
+#[derive(Debug)]
+pub struct Coordinator {
+    pub denomination: Amount,
+    /// What each input pays towards the transaction fee
+    pub fee_per_input: Amount,
+    phase: Phase,
+    inputs: Vec<Registration>,
+    outputs: Vec<Script>,
+    tx: Option<Tx>,
+}
+
+impl Coordinator {
+    pub fn new(denomination: Amount, fee_per_input: Amount) -> Self {
+        Coordinator {
+            denomination,
+            fee_per_input,
+            phase: Phase::InputRegistration,
+            inputs: vec![],
+            outputs: vec![],
+            tx: None,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Register a coin, `spent` being the output it is, and where its change
+    /// goes
+    pub fn register_input(
+        &mut self,
+        prev_tx: Vec<u8>,
+        prev_index: u32,
+        spent: TxOut,
+        change: Script,
+    ) -> Result<(), CoinjoinError> {
+        if self.phase != Phase::InputRegistration {
+            return Err(CoinjoinError::WrongPhase);
+        }
+        if spent.amount < self.denomination + self.fee_per_input {
+            return Err(CoinjoinError::TooSmall);
+        }
+        if self
+            .inputs
+            .iter()
+            .any(|input| input.prev_tx == prev_tx && input.prev_index == prev_index)
+        {
+            return Err(CoinjoinError::Duplicate);
+        }
+        self.inputs.push(Registration {
+            prev_tx,
+            prev_index,
+            spent,
+            change,
+        });
+        Ok(())
+    }
+
+    /// Close input registration, a round needs at least two inputs
+    pub fn start_output_registration(&mut self) -> Result<(), CoinjoinError> {
+        if self.phase != Phase::InputRegistration {
+            return Err(CoinjoinError::WrongPhase);
+        }
+        if self.inputs.len() < 2 {
+            return Err(CoinjoinError::NotReady);
+        }
+        self.phase = Phase::OutputRegistration;
+        Ok(())
+    }
+
+    /// Register a script to receive one denomination
+    pub fn register_output(&mut self, script_pubkey: Script) -> Result<(), CoinjoinError> {
+        if self.phase != Phase::OutputRegistration {
+            return Err(CoinjoinError::WrongPhase);
+        }
+        // reusing a script would link the outputs paying to it
+        if self.outputs.contains(&script_pubkey)
+            || self.inputs.iter().any(|input| {
+                input.change == script_pubkey || input.spent.script_pubkey == script_pubkey
+            })
+        {
+            return Err(CoinjoinError::Duplicate);
+        }
+        if self.outputs.len() == self.inputs.len() {
+            return Err(CoinjoinError::TooManyOutputs);
+        }
+        self.outputs.push(script_pubkey);
+        Ok(())
+    }
+
+    /// Build the unsigned transaction once every input has its output
+    pub fn start_signing(&mut self) -> Result<Tx, CoinjoinError> {
+        if self.phase != Phase::OutputRegistration {
+            return Err(CoinjoinError::WrongPhase);
+        }
+        if self.outputs.len() != self.inputs.len() {
+            return Err(CoinjoinError::NotReady);
+        }
+        let mut tx_ins: Vec<TxIn> = self
+            .inputs
+            .iter()
+            .map(|input| TxIn {
+                prev_tx: input.prev_tx.clone(),
+                prev_index: input.prev_index,
+                sequence: 0xffffffff,
+                ..Default::default()
+            })
+            .collect();
+        let mut tx_outs: Vec<TxOut> = self
+            .outputs
+            .iter()
+            .map(|script_pubkey| TxOut {
+                amount: self.denomination,
+                script_pubkey: script_pubkey.clone(),
+            })
+            .collect();
+        for input in &self.inputs {
+            let change = input.spent.amount - self.denomination - self.fee_per_input;
+            if change > Amount::ZERO {
+                tx_outs.push(TxOut {
+                    amount: change,
+                    script_pubkey: input.change.clone(),
+                });
+            }
+        }
+        // BIP69 order, so the order says nothing about who registered what
+        tx_ins.sort_by(|a, b| {
+            let key = |tx_in: &TxIn| {
+                let mut txid = tx_in.prev_tx.clone();
+                txid.reverse();
+                (txid, tx_in.prev_index)
+            };
+            key(a).cmp(&key(b))
+        });
+        tx_outs.sort_by(|a, b| {
+            (a.amount, a.script_pubkey.to_bytes()).cmp(&(b.amount, b.script_pubkey.to_bytes()))
+        });
+        let tx = Tx {
+            version: 1,
+            tx_ins,
+            tx_outs,
+            locktime: 0,
+            segwit: false,
+        };
+        self.tx = Some(tx.clone());
+        self.phase = Phase::Signing;
+        Ok(tx)
+    }
+
+    /// The outputs the registered inputs spend
+    pub fn prevouts(&self) -> Prevouts {
+        self.inputs
+            .iter()
+            .map(|input| {
+                (
+                    (input.prev_tx.clone(), input.prev_index),
+                    input.spent.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// Add the scriptSig of input `index`, returns the finished transaction
+    /// once every input is signed
+    pub fn add_signature(
+        &mut self,
+        index: usize,
+        script_sig: Script,
+    ) -> Result<Option<Tx>, CoinjoinError> {
+        if self.phase != Phase::Signing {
+            return Err(CoinjoinError::WrongPhase);
+        }
+        let prevouts = self.prevouts();
+        let tx = self.tx.as_mut().expect("built when signing started");
+        let tx_in = tx
+            .tx_ins
+            .get(index)
+            .ok_or(CoinjoinError::InvalidSignature)?;
+        let script_pubkey = &prevouts[&(tx_in.prev_tx.clone(), tx_in.prev_index)].script_pubkey;
+        let script = script_sig.clone() + script_pubkey.clone();
+        if !script.evaluate(&tx.sig_message(index, script_pubkey)) {
+            return Err(CoinjoinError::InvalidSignature);
+        }
+        tx.tx_ins[index].script_sig = script_sig;
+        if tx.tx_ins.iter().any(|tx_in| tx_in.script_sig.is_empty()) {
+            return Ok(None);
+        }
+        self.phase = Phase::Done;
+        Ok(Some(tx.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::Encodable;
+    use crate::hashes::hash160;
+    use crate::ru256::RU256;
+    use crate::signer::{Signer, SoftwareSigner};
+    use crate::transaction::{Cmd, TxBuilder};
+
+    struct Participant {
+        signer: SoftwareSigner,
+        coin: Tx,
+    }
+
+    impl Participant {
+        fn new(secret: u64, sat: u64) -> Self {
+            let mut signer = SoftwareSigner::new(vec![
+                RU256::from_u64(secret),
+                RU256::from_u64(secret + 1),
+                RU256::from_u64(secret + 2),
+            ]);
+            let coin = TxBuilder::new("main")
+                .add_input(vec![secret as u8; 32], 0)
+                .add_output(Amount::from_sat(sat), script(&mut signer, 0))
+                .build();
+            Participant { signer, coin }
+        }
+
+        fn outpoint(&self) -> Vec<u8> {
+            let mut txid = hex::decode(self.coin.id()).unwrap();
+            txid.reverse();
+            txid
+        }
+    }
+
+    fn script(signer: &mut SoftwareSigner, key: u32) -> Script {
+        Script::p2pkh(&hash160(&signer.get_pubkey(key).unwrap().sec(true, false)))
+    }
+
+    fn script_sig(signer: &mut SoftwareSigner, tx: &Tx, index: usize, spent: &Script) -> Script {
+        let sig = signer.sign_tx_input(0, tx, index, spent).unwrap();
+        let public_key = signer.get_pubkey(0).unwrap();
+        Script::new(vec![Cmd::Push(sig), Cmd::Push(public_key.sec(true, false))])
+    }
+
+    #[test]
+    fn test_round() {
+        let denomination = Amount::from_sat(10_000);
+        let fee = Amount::from_sat(500);
+        let mut coordinator = Coordinator::new(denomination, fee);
+        let mut participants = [
+            Participant::new(101, 25_000),
+            Participant::new(202, 10_500),
+            Participant::new(303, 12_000),
+        ];
+
+        let small = Participant::new(404, 10_499);
+        assert_eq!(
+            coordinator.register_input(
+                small.outpoint(),
+                0,
+                small.coin.tx_outs[0].clone(),
+                Script::default()
+            ),
+            Err(CoinjoinError::TooSmall)
+        );
+        for p in participants.iter_mut() {
+            let change = script(&mut p.signer, 1);
+            coordinator
+                .register_input(p.outpoint(), 0, p.coin.tx_outs[0].clone(), change.clone())
+                .unwrap();
+        }
+        let p = &participants[0];
+        assert_eq!(
+            coordinator.register_input(
+                p.outpoint(),
+                0,
+                p.coin.tx_outs[0].clone(),
+                Script::default()
+            ),
+            Err(CoinjoinError::Duplicate)
+        );
+        assert_eq!(
+            coordinator.register_output(Script::default()),
+            Err(CoinjoinError::WrongPhase)
+        );
+        coordinator.start_output_registration().unwrap();
+
+        let outputs: Vec<Script> = participants
+            .iter_mut()
+            .map(|p| script(&mut p.signer, 2))
+            .collect();
+        for output in &outputs {
+            coordinator.register_output(output.clone()).unwrap();
+        }
+        assert_eq!(
+            coordinator.register_output(outputs[0].clone()),
+            Err(CoinjoinError::Duplicate)
+        );
+        assert_eq!(
+            coordinator.register_output(Script::p2pkh(&[0xee; 20])),
+            Err(CoinjoinError::TooManyOutputs)
+        );
+
+        let tx = coordinator.start_signing().unwrap();
+        // three equal outputs and change for the two coins worth more
+        assert_eq!(tx.tx_ins.len(), 3);
+        assert_eq!(tx.tx_outs.len(), 5);
+        let equal = tx.tx_outs.iter().filter(|out| out.amount == denomination);
+        assert_eq!(equal.count(), 3);
+        let total_in: Amount = participants.iter().map(|p| p.coin.tx_outs[0].amount).sum();
+        let total_out: Amount = tx.tx_outs.iter().map(|out| out.amount).sum();
+        assert_eq!(total_in - total_out, Amount::from_sat(1_500));
+
+        // a signature by the wrong key is turned away
+        let spent = participants[0].coin.tx_outs[0].script_pubkey.clone();
+        let index = tx
+            .tx_ins
+            .iter()
+            .position(|tx_in| tx_in.prev_tx == participants[0].outpoint())
+            .unwrap();
+        let wrong = script_sig(&mut participants[1].signer, &tx, index, &spent);
+        assert_eq!(
+            coordinator.add_signature(index, wrong).err(),
+            Some(CoinjoinError::InvalidSignature)
+        );
+
+        let mut signed = None;
+        for (p, output) in participants.iter_mut().zip(&outputs) {
+            // check our output made it in before signing
+            assert!(tx
+                .tx_outs
+                .iter()
+                .any(|out| &out.script_pubkey == output && out.amount == denomination));
+            let outpoint = p.outpoint();
+            let index = tx
+                .tx_ins
+                .iter()
+                .position(|tx_in| tx_in.prev_tx == outpoint)
+                .unwrap();
+            let spent = p.coin.tx_outs[0].script_pubkey.clone();
+            let sig = script_sig(&mut p.signer, &tx, index, &spent);
+            signed = coordinator.add_signature(index, sig).unwrap();
+        }
+        let signed = signed.unwrap();
+        assert_eq!(coordinator.phase(), Phase::Done);
+        let encode = |tx: &Tx| {
+            tx.tx_outs
+                .iter()
+                .map(|out| out.encode())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(encode(&signed), encode(&tx));
+        let prevouts = coordinator.prevouts();
+        for (i, tx_in) in signed.tx_ins.iter().enumerate() {
+            let spent = &prevouts[&(tx_in.prev_tx.clone(), tx_in.prev_index)].script_pubkey;
+            let script = tx_in.script_sig.clone() + spent.clone();
+            assert!(script.evaluate(&signed.sig_message(i, spent)));
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::block::Block;
+use crate::encoding::{take, Decodable, Encodable};
+use crate::hashes::sha256d;
+use crate::mempool::Mempool;
+use crate::sha256::sha256;
+use crate::transaction::Tx;
+use crate::utils;
+
+// BIP152 compact blocks. A peer that already has most of a block's
+// transactions in its mempool doesn't need them sent again: the `cmpctblock`
+// message carries the header, the coinbase, and a 6 byte short id per other
+// transaction, SipHash of its wtxid keyed by the header and a nonce so that
+// nobody can grind transactions colliding for every peer. The receiver matches
+// the ids against its mempool, asks for whatever it's missing with
+// `getblocktxn`, gets it back in a `blocktxn` and rebuilds the block. If two
+// transactions share a short id, or the rebuilt block doesn't hash to its
+// merkle root, it falls back to fetching the full block. The crate has no P2P
+// connection to send these over, so they're just encoded and decoded.
+
+/// Short ids are SipHash-2-4 truncated to 48 bits
+const SHORT_ID_SIZE: usize = 6;
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+/// SipHash-2-4 with the 128 bit key split into two little endian halves
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    };
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    // the last block holds the remaining bytes and the length in its top byte
+    let mut last = [0u8; 8];
+    let tail = chunks.remainder();
+    last[..tail.len()].copy_from_slice(tail);
+    compress(u64::from_le_bytes(last) | (data.len() as u64) << 56);
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Why a message can't be encoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactBlockError {
+    /// An index not above the one before it, indexes are sent as
+    /// differences so they have to go up
+    UnsortedIndex(u32),
+}
+
+impl fmt::Display for CompactBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactBlockError::UnsortedIndex(index) => {
+                write!(f, "index {} is not above the previous one", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompactBlockError {}
+
+/// `index` as the difference to `next`, the previous index plus one
+fn encode_index(index: u32, next: &mut u64) -> Result<Vec<u8>, CompactBlockError> {
+    let diff = (index as u64)
+        .checked_sub(*next)
+        .ok_or(CompactBlockError::UnsortedIndex(index))?;
+    *next = index as u64 + 1;
+    Ok(utils::encode_varint(diff))
+}
+
+/// Block hash in internal byte order, what the messages refer to blocks by
+fn block_hash(header: &Block) -> [u8; 32] {
+    sha256d(&header.encode_header())
+}
+
+/// A transaction sent in full along with the short ids, the coinbase at
+/// least since the receiver can't have it
+#[derive(Debug, Clone)]
+pub struct PrefilledTx {
+    /// Position in the block
+    pub index: u32,
+    pub tx: Tx,
+}
+
+/// A `cmpctblock` message
+#[derive(Debug, Clone)]
+pub struct CompactBlock {
+    /// The block with its transactions left out
+    pub header: Block,
+    /// Picked by the sender, keys the short ids along with the header
+    pub nonce: u64,
+    /// Short ids of the transactions not prefilled, in block order
+    pub short_ids: Vec<u64>,
+    pub prefilled: Vec<PrefilledTx>,
+}
+
+impl CompactBlock {
+    /// The compact form of `block`, prefilling only the coinbase
+    pub fn new(block: &Block, nonce: u64) -> Self {
+        assert!(!block.txs.is_empty(), "a block has a coinbase");
+        let mut header = block.clone();
+        header.txs = vec![];
+        let mut compact = CompactBlock {
+            header,
+            nonce,
+            short_ids: vec![],
+            prefilled: vec![PrefilledTx {
+                index: 0,
+                tx: block.txs[0].clone(),
+            }],
+        };
+        let keys = compact.keys();
+        compact.short_ids = block.txs[1..].iter().map(|tx| short_id(keys, tx)).collect();
+        compact
+    }
+
+    /// The SipHash key, the first 16 bytes of sha256(header || nonce)
+    fn keys(&self) -> (u64, u64) {
+        let mut data = self.header.encode_header();
+        data.extend(self.nonce.to_le_bytes());
+        let hash = sha256(data);
+        (
+            u64::from_le_bytes(hash[0..8].try_into().unwrap()),
+            u64::from_le_bytes(hash[8..16].try_into().unwrap()),
+        )
+    }
+
+    /// The short id of `tx` in this block
+    pub fn short_id(&self, tx: &Tx) -> u64 {
+        short_id(self.keys(), tx)
+    }
+
+    /// Fill in the transactions the mempool has. None if the message is
+    /// malformed or two of its short ids are the same, then the full block
+    /// has to be asked for. Mempool transactions sharing a short id are left
+    /// missing, to be fetched with the rest.
+    pub fn reconstruct(&self, mempool: &Mempool) -> Option<PartialBlock> {
+        let total = self.short_ids.len() + self.prefilled.len();
+        let mut txs: Vec<Option<Tx>> = vec![None; total];
+        for prefilled in &self.prefilled {
+            let slot = txs.get_mut(prefilled.index as usize)?;
+            if slot.is_some() {
+                return None;
+            }
+            *slot = Some(prefilled.tx.clone());
+        }
+        // the short ids fill the positions not prefilled, in order
+        let mut positions = HashMap::new();
+        let empty = (0..total).filter(|&index| txs[index].is_none());
+        for (index, &short_id) in empty.zip(&self.short_ids) {
+            if positions.insert(short_id, index).is_some() {
+                return None;
+            }
+        }
+
+        let keys = self.keys();
+        let mut collisions = vec![];
+        for tx in mempool.iter() {
+            if let Some(&index) = positions.get(&short_id(keys, tx)) {
+                match txs[index] {
+                    Some(_) => collisions.push(index),
+                    None => txs[index] = Some(tx.clone()),
+                }
+            }
+        }
+        for index in collisions {
+            txs[index] = None;
+        }
+        Some(PartialBlock {
+            header: self.header.clone(),
+            txs,
+        })
+    }
+}
+
+fn short_id((k0, k1): (u64, u64), tx: &Tx) -> u64 {
+    siphash24(k0, k1, &sha256d(&tx.encode())) & ((1 << (8 * SHORT_ID_SIZE)) - 1)
+}
+
+impl CompactBlock {
+    /// The `cmpctblock` message. Prefilled transactions go in block order
+    /// whatever their order here, two at the same index are an error.
+    pub fn encode(&self) -> Result<Vec<u8>, CompactBlockError> {
+        let mut out = self.header.encode_header();
+        out.extend(self.nonce.to_le_bytes());
+        out.extend(utils::encode_varint(self.short_ids.len() as u64));
+        for short_id in &self.short_ids {
+            out.extend(&short_id.to_le_bytes()[..SHORT_ID_SIZE]);
+        }
+        out.extend(utils::encode_varint(self.prefilled.len() as u64));
+        let mut prefilled: Vec<&PrefilledTx> = self.prefilled.iter().collect();
+        prefilled.sort_by_key(|prefilled| prefilled.index);
+        // indexes are sent as the difference to the previous one, minus one
+        let mut next = 0;
+        for prefilled in prefilled {
+            out.extend(encode_index(prefilled.index, &mut next)?);
+            out.extend(prefilled.tx.encode());
+        }
+        Ok(out)
+    }
+}
+
+impl Decodable for CompactBlock {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        let header = Block::decode_header(bytes);
+        let nonce = utils::read_u64(bytes).unwrap();
+        let short_id_count = utils::read_varint(bytes).unwrap();
+        let short_ids = (0..short_id_count)
+            .map(|_| {
+                let mut short_id = [0u8; 8];
+                short_id[..SHORT_ID_SIZE].copy_from_slice(take(bytes, SHORT_ID_SIZE));
+                u64::from_le_bytes(short_id)
+            })
+            .collect();
+        let prefilled_count = utils::read_varint(bytes).unwrap();
+        let mut next = 0;
+        let prefilled = (0..prefilled_count)
+            .map(|_| {
+                let index = next + utils::read_varint(bytes).unwrap() as u32;
+                next = index + 1;
+                PrefilledTx {
+                    index,
+                    tx: Tx::decode(bytes),
+                }
+            })
+            .collect();
+        CompactBlock {
+            header,
+            nonce,
+            short_ids,
+            prefilled,
+        }
+    }
+}
+
+/// A block being rebuilt from a compact block, None for the transactions
+/// still missing
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    pub header: Block,
+    pub txs: Vec<Option<Tx>>,
+}
+
+impl PartialBlock {
+    /// Positions of the missing transactions
+    pub fn missing(&self) -> Vec<u32> {
+        (0..self.txs.len() as u32)
+            .filter(|&index| self.txs[index as usize].is_none())
+            .collect()
+    }
+
+    /// The `getblocktxn` asking for the missing transactions
+    pub fn request(&self) -> GetBlockTxn {
+        GetBlockTxn {
+            block_hash: block_hash(&self.header),
+            indexes: self.missing(),
+        }
+    }
+
+    /// The full block, with `txs` from the `blocktxn` answering `request`
+    /// filling the gaps. None if they don't fill them exactly or the block
+    /// doesn't hash to its merkle root, which happens when a short id matched
+    /// the wrong mempool transaction.
+    pub fn fill(&self, txs: &[Tx]) -> Option<Block> {
+        if txs.len() != self.missing().len() {
+            return None;
+        }
+        let mut txs = txs.iter();
+        let mut block = self.header.clone();
+        block.txs = self
+            .txs
+            .iter()
+            .map(|tx| tx.clone().unwrap_or_else(|| txs.next().unwrap().clone()))
+            .collect();
+        (block.compute_merkle_root() == block.merkle_root).then_some(block)
+    }
+}
+
+/// A `getblocktxn` message, asking for transactions of a block by position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetBlockTxn {
+    pub block_hash: [u8; 32],
+    /// Ascending positions in the block
+    pub indexes: Vec<u32>,
+}
+
+impl GetBlockTxn {
+    /// The `blocktxn` a node having `block` answers with, None if it's a
+    /// different block or an index is out of range
+    pub fn answer(&self, block: &Block) -> Option<BlockTxn> {
+        if block_hash(block) != self.block_hash {
+            return None;
+        }
+        let txs = self
+            .indexes
+            .iter()
+            .map(|&index| block.txs.get(index as usize).cloned())
+            .collect::<Option<_>>()?;
+        Some(BlockTxn {
+            block_hash: self.block_hash,
+            txs,
+        })
+    }
+}
+
+impl GetBlockTxn {
+    /// The `getblocktxn` message. The answer comes back in the order of
+    /// `indexes`, so unsorted ones are an error rather than sorted here.
+    pub fn encode(&self) -> Result<Vec<u8>, CompactBlockError> {
+        let mut out = self.block_hash.to_vec();
+        out.extend(utils::encode_varint(self.indexes.len() as u64));
+        let mut next = 0;
+        for &index in &self.indexes {
+            out.extend(encode_index(index, &mut next)?);
+        }
+        Ok(out)
+    }
+}
+
+impl Decodable for GetBlockTxn {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        let block_hash = take(bytes, 32).try_into().unwrap();
+        let count = utils::read_varint(bytes).unwrap();
+        let mut next = 0;
+        let indexes = (0..count)
+            .map(|_| {
+                let index = next + utils::read_varint(bytes).unwrap() as u32;
+                next = index + 1;
+                index
+            })
+            .collect();
+        GetBlockTxn {
+            block_hash,
+            indexes,
+        }
+    }
+}
+
+/// A `blocktxn` message, the transactions a `getblocktxn` asked for
+#[derive(Debug, Clone)]
+pub struct BlockTxn {
+    pub block_hash: [u8; 32],
+    pub txs: Vec<Tx>,
+}
+
+impl Encodable for BlockTxn {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = self.block_hash.to_vec();
+        out.extend(utils::encode_varint(self.txs.len() as u64));
+        for tx in &self.txs {
+            out.extend(tx.encode());
+        }
+        out
+    }
+}
+
+impl Decodable for BlockTxn {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        let block_hash = take(bytes, 32).try_into().unwrap();
+        let count = utils::read_varint(bytes).unwrap();
+        let txs = (0..count).map(|_| Tx::decode(bytes)).collect();
+        BlockTxn { block_hash, txs }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::{Amount, SAT_PER_BTC};
+    use crate::transaction::{Cmd, Script, TxBuilder, TxIn, TxOut};
+
+    #[test]
+    fn test_siphash() {
+        // the reference vectors, key 00..0f and messages 00, 00 01, ...
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(k0, k1, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(k0, k1, &message[..8]), 0x93f5f5799a932462);
+        assert_eq!(siphash24(k0, k1, &message), 0xa129ca6149be45e5);
+    }
+
+    fn test_block(tx_count: u32) -> Block {
+        let coinbase = Tx {
+            version: 1,
+            tx_ins: vec![TxIn {
+                prev_tx: vec![0; 32],
+                prev_index: 0xffffffff,
+                script_sig: Script::new(vec![Cmd::push(&[1, 2, 3])]),
+                sequence: 0xffffffff,
+                ..Default::default()
+            }],
+            tx_outs: vec![TxOut {
+                amount: Amount::from_sat(50 * SAT_PER_BTC),
+                script_pubkey: Script::p2pkh(&[0; 20]),
+            }],
+            ..Default::default()
+        };
+        let mut txs = vec![coinbase];
+        for i in 1..tx_count {
+            let mut tx = TxBuilder::new("main")
+                .add_input(sha256d(&i.to_le_bytes()).to_vec(), 0)
+                .add_output(Amount::from_sat(10_000), Script::p2pkh(&[i as u8; 20]))
+                .build();
+            // about the size of a signature and public key
+            tx.tx_ins[0].script_sig =
+                Script::new(vec![Cmd::push(&[0x30; 72]), Cmd::push(&[0x02; 33])]);
+            txs.push(tx);
+        }
+        let mut block = Block {
+            version: 0x20000000,
+            prev_block: vec![0; 32],
+            merkle_root: vec![],
+            timestamp: 1700000000,
+            bits: vec![0xff, 0xff, 0x7f, 0x20],
+            nonce: vec![0; 4],
+            txs,
+        };
+        block.merkle_root = block.compute_merkle_root();
+        block
+    }
+
+    #[test]
+    fn test_relay() {
+        let block = test_block(500);
+        let missing = [7, 250, 499];
+        let mut mempool = Mempool::new();
+        for (index, tx) in block.txs.iter().enumerate().skip(1) {
+            if !missing.contains(&(index as u32)) {
+                mempool.insert(tx.clone()).unwrap();
+            }
+        }
+        // an unrelated transaction doesn't get in the way
+        mempool
+            .insert(
+                TxBuilder::new("main")
+                    .add_input(vec![0xee; 32], 0)
+                    .add_output(Amount::from_sat(1), Script::p2pkh(&[0xee; 20]))
+                    .build(),
+            )
+            .unwrap();
+
+        let cmpctblock = CompactBlock::new(&block, 0x0123456789abcdef)
+            .encode()
+            .unwrap();
+        let partial = CompactBlock::decode_all(&cmpctblock)
+            .reconstruct(&mempool)
+            .unwrap();
+        assert_eq!(partial.missing(), missing);
+
+        let getblocktxn = partial.request().encode().unwrap();
+        let request = GetBlockTxn::decode_all(&getblocktxn);
+        assert_eq!(request, partial.request());
+        let blocktxn = request.answer(&block).unwrap().encode();
+        let response = BlockTxn::decode_all(&blocktxn);
+        let rebuilt = partial.fill(&response.txs).unwrap();
+        assert_eq!(rebuilt.encode(), block.encode());
+
+        // 6 bytes per transaction the peer has, instead of the whole thing
+        let relayed = cmpctblock.len() + getblocktxn.len() + blocktxn.len();
+        let full = block.encode().len();
+        assert!(relayed * 10 < full, "{} vs {} bytes", relayed, full);
+
+        // the wrong transactions don't hash to the merkle root
+        assert!(partial.fill(&block.txs[1..4]).is_none());
+        assert!(partial.fill(&response.txs[1..]).is_none());
+    }
+
+    #[test]
+    fn test_short_id_collisions() {
+        let block = test_block(4);
+        let mut compact = CompactBlock::new(&block, 7);
+        assert_eq!(compact.short_ids.len(), 3);
+        assert_eq!(compact.short_id(&block.txs[2]), compact.short_ids[1]);
+        assert!(compact.short_ids.iter().all(|&id| id >> 48 == 0));
+        // another nonce keys different ids
+        assert_ne!(CompactBlock::new(&block, 8).short_ids, compact.short_ids);
+
+        // prefilled transactions are sent in block order
+        let mut unsorted = compact.clone();
+        unsorted.short_ids.truncate(1);
+        unsorted.prefilled.extend([
+            PrefilledTx {
+                index: 3,
+                tx: block.txs[3].clone(),
+            },
+            PrefilledTx {
+                index: 2,
+                tx: block.txs[2].clone(),
+            },
+        ]);
+        let decoded = CompactBlock::decode_all(&unsorted.encode().unwrap());
+        let indexes: Vec<u32> = decoded.prefilled.iter().map(|p| p.index).collect();
+        assert_eq!(indexes, [0, 2, 3]);
+        unsorted.prefilled[2].index = 3;
+        assert_eq!(
+            unsorted.encode().err(),
+            Some(CompactBlockError::UnsortedIndex(3))
+        );
+        let request = GetBlockTxn {
+            block_hash: [0; 32],
+            indexes: vec![5, 2],
+        };
+        assert_eq!(request.encode(), Err(CompactBlockError::UnsortedIndex(2)));
+
+        compact.short_ids[2] = compact.short_ids[0];
+        assert!(compact.reconstruct(&Mempool::new()).is_none());
+        compact.short_ids.pop();
+        compact.prefilled[0].index = 3;
+        assert!(compact.reconstruct(&Mempool::new()).is_none());
+    }
+}
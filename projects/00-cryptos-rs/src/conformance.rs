@@ -0,0 +1,184 @@
+use std::sync::OnceLock;
+
+use ::bitcoin::consensus::encode;
+use ::bitcoin::hashes::Hash;
+use ::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use ::bitcoin::{Address, Network, PubkeyHash, ScriptBuf, WPubkeyHash};
+use proptest::prelude::*;
+use secp256k1::{ecdsa, Message, PublicKey as LibPublicKey, Secp256k1, SecretKey};
+
+use crate::amount::Amount;
+use crate::encoding::Encodable;
+use crate::keys::{pkb_hash_to_address, PublicKey};
+use crate::ru256::RU256;
+use crate::secp256k1::{Point, WindowTable, SECP256K1};
+use crate::sha256::hash256;
+use crate::signature::{sign_ecdsa, verify_ecdsa, Signature};
+use crate::strategies;
+use crate::transaction::Script;
+
+// Differential tests against libsecp256k1 (through the secp256k1 crate) and
+// rust-bitcoin over random inputs. Signing and verifying with our own point
+// math takes seconds, run them with
+// `cargo test --release --features conformance conformance`.
+
+fn secret_key() -> impl Strategy<Value = SecretKey> {
+    any::<[u8; 32]>().prop_filter_map("not a valid secret key", |bytes| {
+        SecretKey::from_slice(&bytes).ok()
+    })
+}
+
+fn to_point(public_key: &LibPublicKey) -> Point {
+    let uncompressed = public_key.serialize_uncompressed();
+    Point {
+        x: RU256::from_bytes(&uncompressed[1..33]),
+        y: RU256::from_bytes(&uncompressed[33..65]),
+    }
+}
+
+fn to_scalar(secret_key: &SecretKey) -> RU256 {
+    RU256::from_bytes(&secret_key.secret_bytes())
+}
+
+fn window_table() -> &'static WindowTable {
+    static TABLE: OnceLock<WindowTable> = OnceLock::new();
+    TABLE.get_or_init(|| WindowTable::generate(&SECP256K1::g()))
+}
+
+proptest! {
+    #[test]
+    fn point_addition_matches_libsecp(a in secret_key(), b in secret_key()) {
+        let secp = Secp256k1::new();
+        let p = LibPublicKey::from_secret_key(&secp, &a);
+        let q = LibPublicKey::from_secret_key(&secp, &b);
+        prop_assume!(p != q && p != q.negate(&secp));
+
+        prop_assert_eq!(
+            SECP256K1::add_points(&to_point(&p), &to_point(&q)),
+            to_point(&p.combine(&q).unwrap())
+        );
+        prop_assert_eq!(
+            SECP256K1::double_point(&to_point(&p)),
+            to_point(&p.combine(&p).unwrap())
+        );
+    }
+
+    #[test]
+    fn p2pkh_address_matches_rust_bitcoin(pkb_hash: [u8; 20], testnet: bool) {
+        let (net, network) = if testnet {
+            ("test", Network::Testnet)
+        } else {
+            ("main", Network::Bitcoin)
+        };
+        let expected = Address::p2pkh(PubkeyHash::from_byte_array(pkb_hash), network);
+        prop_assert_eq!(pkb_hash_to_address(&pkb_hash, net), expected.to_string());
+    }
+
+    #[test]
+    fn tx_serialization_matches_rust_bitcoin(tx in strategies::tx()) {
+        // rust-bitcoin rejects a segwit marker without any witness data
+        prop_assume!(!tx.segwit || tx.tx_ins.iter().any(|tx_in| !tx_in.witness.is_empty()));
+
+        let raw = tx.encode();
+        let parsed: ::bitcoin::Transaction = encode::deserialize(&raw).unwrap();
+        prop_assert_eq!(encode::serialize(&parsed), raw);
+        prop_assert_eq!(tx.id(), parsed.compute_txid().to_string());
+    }
+
+    #[test]
+    fn legacy_sighash_matches_rust_bitcoin(
+        tx in strategies::tx(),
+        input: prop::sample::Index,
+        script_pubkey in strategies::script(),
+    ) {
+        // the witnesses aren't signed, and rust-bitcoin rejects a segwit
+        // marker without any
+        let parsed: ::bitcoin::Transaction = encode::deserialize(&tx.encode_legacy()).unwrap();
+        let input = input.index(tx.tx_ins.len());
+        let expected = SighashCache::new(&parsed)
+            .legacy_signature_hash(
+                input,
+                &ScriptBuf::from_bytes(script_pubkey.to_bytes()),
+                EcdsaSighashType::All.to_u32(),
+            )
+            .unwrap();
+        prop_assert_eq!(
+            hash256(tx.sig_message(input, &script_pubkey)),
+            expected.to_byte_array().to_vec()
+        );
+    }
+
+    #[test]
+    fn segwit_sighash_matches_rust_bitcoin(
+        tx in strategies::tx(),
+        input: prop::sample::Index,
+        program: [u8; 20],
+        sat in 0..=21_000_000 * 100_000_000u64,
+    ) {
+        let parsed: ::bitcoin::Transaction = encode::deserialize(&tx.encode_legacy()).unwrap();
+        let input = input.index(tx.tx_ins.len());
+        let expected = SighashCache::new(&parsed)
+            .p2wpkh_signature_hash(
+                input,
+                &ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(program)),
+                ::bitcoin::Amount::from_sat(sat),
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        // a p2wpkh output is signed with the p2pkh script of its key hash
+        let message = tx.segwit_sig_message(input, &Script::p2pkh(&program), Amount::from_sat(sat));
+        prop_assert_eq!(hash256(message), expected.to_byte_array().to_vec());
+    }
+}
+
+proptest! {
+    // every case is a few scalar multiplications with our point math
+    #![proptest_config(ProptestConfig::with_cases(4))]
+
+    #[test]
+    fn fixed_base_multiplication_matches_libsecp(secret_key in secret_key()) {
+        let secp = Secp256k1::new();
+        prop_assert_eq!(
+            SECP256K1::scalar_multiplication_fixed_base(&to_scalar(&secret_key), window_table()),
+            to_point(&LibPublicKey::from_secret_key(&secp, &secret_key))
+        );
+    }
+
+    #[test]
+    fn ecdsa_signatures_verify_with_libsecp(secret_key in secret_key(), message: Vec<u8>) {
+        let secp = Secp256k1::new();
+        let sig = sign_ecdsa(&to_scalar(&secret_key), &message);
+
+        let mut compact = [0u8; 64];
+        sig.r.to_bytes(&mut compact[..32]);
+        sig.s.to_bytes(&mut compact[32..]);
+        // libsecp only accepts low s signatures
+        let mut lib_sig = ecdsa::Signature::from_compact(&compact).unwrap();
+        lib_sig.normalize_s();
+
+        let digest: [u8; 32] = hash256(message).try_into().unwrap();
+        let public_key = LibPublicKey::from_secret_key(&secp, &secret_key);
+        prop_assert!(secp
+            .verify_ecdsa(&Message::from_digest(digest), &lib_sig, &public_key)
+            .is_ok());
+    }
+
+    #[test]
+    fn libsecp_signatures_verify(secret_key in secret_key(), message: Vec<u8>) {
+        let secp = Secp256k1::new();
+        let digest: [u8; 32] = hash256(message.clone()).try_into().unwrap();
+        let compact = secp
+            .sign_ecdsa(&Message::from_digest(digest), &secret_key)
+            .serialize_compact();
+        let sig = Signature {
+            r: RU256::from_bytes(&compact[..32]),
+            s: RU256::from_bytes(&compact[32..]),
+        };
+
+        let public_key = PublicKey::from_point(to_point(&LibPublicKey::from_secret_key(
+            &secp,
+            &secret_key,
+        )));
+        prop_assert!(verify_ecdsa(&public_key, &message, &sig));
+    }
+}
@@ -0,0 +1,165 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+use primitive_types::{U256, U512};
+
+#[cfg(feature = "std")]
+use crate::bitcoin::BITCOIN;
+use crate::curve::{Curve, Generator};
+use crate::field::{Fn, Fp};
+use crate::ru256::RU256;
+use crate::secp256k1::{Point, SECP256K1};
+
+// Self checks for the hand-written secp256k1 constants. p, n and G are hex
+// strings typed in from SEC2 and repeated for the generic curve code and the
+// `BITCOIN` coin, and one wrong digit gives a curve that mostly works: points
+// still add, keys still derive, only nobody else agrees on them. Instead of
+// comparing the strings against more strings, the checks test what SEC2 says
+// about them: p has its special form, p and n are prime, G is on the curve,
+// n * G is the point at infinity (so n is G's order), (n - 1) * G is -G, and
+// the Hasse bound leaves no room for a cofactor other than 1. `check` runs
+// them all in a fraction of a second, cheap enough for startup.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyError {
+    /// p isn't 2^256 - 2^32 - 977
+    FieldPrime,
+    /// p or n failed the primality test
+    NotPrime,
+    /// G doesn't satisfy y^2 = x^3 + 7
+    GeneratorNotOnCurve,
+    /// n * G isn't the point at infinity
+    GeneratorOrder,
+    /// (n - 1) * G isn't -G
+    GeneratorNegation,
+    /// The curve could have more than n points
+    Cofactor,
+    /// Another copy of the parameters differs from `SECP256K1`
+    Mismatch(&'static str),
+}
+
+impl fmt::Display for ConsistencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsistencyError::FieldPrime => write!(f, "p is not 2^256 - 2^32 - 977"),
+            ConsistencyError::NotPrime => write!(f, "p or n is not prime"),
+            ConsistencyError::GeneratorNotOnCurve => write!(f, "G is not on the curve"),
+            ConsistencyError::GeneratorOrder => write!(f, "n * G is not the point at infinity"),
+            ConsistencyError::GeneratorNegation => write!(f, "(n - 1) * G is not -G"),
+            ConsistencyError::Cofactor => write!(f, "the cofactor is not 1"),
+            ConsistencyError::Mismatch(what) => {
+                write!(f, "{} disagrees with the secp256k1 constants", what)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConsistencyError {}
+
+/// 2^256 - 2^32 - 977, the form SEC2 gives p in
+fn sec2_field_prime() -> RU256 {
+    RU256 {
+        v: U256::MAX - U256::from((1u64 << 32) + 977) + 1,
+    }
+}
+
+fn is_infinity(point: &Point) -> bool {
+    point.x.is_zero() && point.y.is_zero()
+}
+
+/// With n the order of G and prime, the number of points is a multiple h * n
+/// within the Hasse bound |#E - (p + 1)| <= 2 sqrt(p). Squaring keeps it in
+/// integers: n itself must be in the bound, and 2n must be past it.
+fn cofactor_is_one(p: &RU256, n: &RU256) -> bool {
+    let (p, n) = (U512::from(p.v), U512::from(n.v));
+    let four_p = p * 4;
+    let trace = if p + 1 >= n { p + 1 - n } else { n - p - 1 };
+    let two_n = n * 2;
+    trace * trace <= four_p && two_n > p + 1 && (two_n - p - 1) * (two_n - p - 1) > four_p
+}
+
+/// Run every check, the first failure is returned
+pub fn check() -> Result<(), ConsistencyError> {
+    let (p, n, g) = (SECP256K1::p(), SECP256K1::n(), SECP256K1::g());
+    if p != sec2_field_prime() {
+        return Err(ConsistencyError::FieldPrime);
+    }
+    if !p.is_probable_prime() || !n.is_probable_prime() {
+        return Err(ConsistencyError::NotPrime);
+    }
+    if !g.is_on_curve() {
+        return Err(ConsistencyError::GeneratorNotOnCurve);
+    }
+    // the ladder takes any 256 bit scalar, n and n - 1 included
+    if !is_infinity(&SECP256K1::scalar_multiplication_ct(&n, &g)) {
+        return Err(ConsistencyError::GeneratorOrder);
+    }
+    let n_minus_one = n.sub_mod(&RU256::one(), &n);
+    if SECP256K1::scalar_multiplication_ct(&n_minus_one, &g) != -g.clone() {
+        return Err(ConsistencyError::GeneratorNegation);
+    }
+    if !cofactor_is_one(&p, &n) {
+        return Err(ConsistencyError::Cofactor);
+    }
+
+    if Fp::modulus() != p || Fn::modulus() != n {
+        return Err(ConsistencyError::Mismatch("field"));
+    }
+    let generator = Generator::secp256k1();
+    let curve = Curve::new(p.clone(), RU256::zero(), RU256::from_u64(7));
+    if generator.curve != curve || generator.G != g || generator.n != n {
+        return Err(ConsistencyError::Mismatch("curve"));
+    }
+    #[cfg(feature = "std")]
+    if BITCOIN.gen.curve != curve || BITCOIN.gen.G != g || BITCOIN.gen.n != n {
+        return Err(ConsistencyError::Mismatch("bitcoin"));
+    }
+    Ok(())
+}
+
+/// Panic if the constants are wrong, checking only on the first call. Meant
+/// for the start of a binary, before any key is derived.
+#[cfg(feature = "std")]
+pub fn assert_consistent() {
+    static RESULT: OnceLock<Result<(), ConsistencyError>> = OnceLock::new();
+    if let Err(error) = RESULT.get_or_init(check) {
+        panic!("secp256k1 constants are inconsistent: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constants_are_consistent() {
+        assert_eq!(check(), Ok(()));
+        assert_consistent();
+    }
+
+    #[test]
+    fn test_checks_catch_typos() {
+        // one digit off in n: still odd, but not the order of G
+        let n = SECP256K1::n();
+        let typo = RU256 {
+            v: n.v - U256::from(0x10),
+        };
+        assert!(!is_infinity(&SECP256K1::scalar_multiplication_ct(
+            &typo,
+            &SECP256K1::g()
+        )));
+
+        // orders of subgroups, which the curve would have to be 2 or 256 times
+        // bigger than n for
+        let p = SECP256K1::p();
+        assert!(!cofactor_is_one(&p, &RU256 { v: (p.v >> 1) + 1 }));
+        assert!(!cofactor_is_one(&p, &RU256 { v: p.v >> 8 }));
+
+        // the generator moved off the curve
+        let mut g = SECP256K1::g();
+        g.y = g.y.add_mod(&RU256::one(), &p);
+        assert!(!g.is_on_curve());
+    }
+}
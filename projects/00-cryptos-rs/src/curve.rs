@@ -0,0 +1,502 @@
+use alloc::vec::Vec;
+use core::ops::{Add, Mul, Neg};
+use core::str::FromStr;
+
+use crate::ru256::RU256;
+use crate::secp256k1::{Point, SECP256K1};
+
+// Short Weierstrass curves y^2 = x^3 + ax + b over a prime field, with the
+// same affine point math as secp256k1.rs but for any a. secp256k1.rs stays the
+// fast path for Bitcoin, this module is for comparing curves and for the
+// small-curve exercises. The arithmetic works on `Coordinates`, where the
+// point at infinity is a variant of its own rather than a special pair, and
+// `CurvePoint` ties coordinates to the curve they were checked against, so
+// points of two different curves can't be added by accident. The methods
+// taking `Point` keep the convention of secp256k1.rs, (0, 0) stands in for
+// infinity, which is why b must be non-zero, and they check nothing: the
+// invalid curve attacks need exactly that.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    pub p: RU256,
+    pub a: RU256,
+    pub b: RU256,
+}
+
+impl Curve {
+    pub fn new(p: RU256, a: RU256, b: RU256) -> Self {
+        assert!(p > RU256::from_u64(3), "field prime must be larger than 3");
+        assert!(
+            !(b.v % p.v).is_zero(),
+            "b must be non-zero, (0, 0) is the point at infinity"
+        );
+        // 4a^3 + 27b^2 != 0, otherwise the curve is singular
+        let discriminant = RU256::from_u64(4)
+            .mul_mod(&a.exp_mod(&RU256::from_u64(3), &p), &p)
+            .add_mod(&RU256::from_u64(27).mul_mod(&b.mul_mod(&b, &p), &p), &p);
+        assert!(!discriminant.is_zero(), "curve is singular");
+        Curve { p, a, b }
+    }
+
+    /// A classroom-sized curve, e.g. `Curve::toy(223, 0, 7)` from Programming
+    /// Bitcoin
+    pub fn toy(p: u64, a: u64, b: u64) -> Self {
+        Self::new(RU256::from_u64(p), RU256::from_u64(a), RU256::from_u64(b))
+    }
+
+    pub fn secp256k1() -> Self {
+        Self::new(SECP256K1::p(), RU256::zero(), RU256::from_u64(7))
+    }
+
+    /// NIST P-256, also known as secp256r1
+    pub fn p256() -> Self {
+        let p = RU256::from_str("FFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFF")
+            .unwrap();
+        let a = RU256::zero().sub_mod(&RU256::from_u64(3), &p);
+        let b = RU256::from_str("5AC635D8AA3A93E7B3EBBD55769886BC651D06B0CC53B0F63BCE3C3E27D2604B")
+            .unwrap();
+        Self::new(p, a, b)
+    }
+
+    fn is_zero_point(point: &Point) -> bool {
+        Coordinates::from(point) == Coordinates::Infinity
+    }
+
+    /// Whether the point satisfies the curve equation
+    pub fn contains(&self, point: &Point) -> bool {
+        self.contains_coordinates(&point.into())
+    }
+
+    fn contains_coordinates(&self, coordinates: &Coordinates) -> bool {
+        let Coordinates::Affine { x, y } = coordinates else {
+            return true;
+        };
+        let p = &self.p;
+        let lhs = y.mul_mod(y, p);
+        let rhs = x
+            .exp_mod(&RU256::from_u64(3), p)
+            .add_mod(&self.a.mul_mod(x, p), p)
+            .add_mod(&self.b, p);
+        lhs == rhs
+    }
+
+    /// The point (x, y), None if it isn't on this curve
+    pub fn point(&self, x: RU256, y: RU256) -> Option<CurvePoint<'_>> {
+        let coordinates = Coordinates::Affine { x, y };
+        self.contains_coordinates(&coordinates)
+            .then_some(CurvePoint {
+                curve: self,
+                coordinates,
+            })
+    }
+
+    pub fn infinity(&self) -> CurvePoint<'_> {
+        CurvePoint {
+            curve: self,
+            coordinates: Coordinates::Infinity,
+        }
+    }
+
+    /// A point in the (0, 0) convention, None if it isn't on this curve
+    pub fn from_point(&self, point: &Point) -> Option<CurvePoint<'_>> {
+        match Coordinates::from(point) {
+            Coordinates::Infinity => Some(self.infinity()),
+            Coordinates::Affine { x, y } => self.point(x, y),
+        }
+    }
+
+    /// Add any two points, including equal and opposite ones
+    pub fn add_points(&self, p1: &Point, p2: &Point) -> Point {
+        self.add(&p1.into(), &p2.into()).into()
+    }
+
+    pub fn double_point(&self, point: &Point) -> Point {
+        self.double(&point.into()).into()
+    }
+
+    /// Double and add
+    pub fn scalar_multiplication(&self, scalar: &RU256, point: &Point) -> Point {
+        self.multiply(scalar, &point.into()).into()
+    }
+
+    fn add(&self, c1: &Coordinates, c2: &Coordinates) -> Coordinates {
+        let (x1, y1, x2, y2) = match (c1, c2) {
+            (Coordinates::Infinity, _) => return c2.clone(),
+            (_, Coordinates::Infinity) => return c1.clone(),
+            (Coordinates::Affine { x: x1, y: y1 }, Coordinates::Affine { x: x2, y: y2 }) => {
+                (x1, y1, x2, y2)
+            }
+        };
+        if x1 == x2 {
+            // either the same point or P + (-P) = O
+            return if y1 == y2 {
+                self.double(c1)
+            } else {
+                Coordinates::Infinity
+            };
+        }
+
+        let p = &self.p;
+        let lambda = y2.sub_mod(y1, p).div_mod(&x2.sub_mod(x1, p), p);
+        self.line_intersection(x1, y1, x2, &lambda)
+    }
+
+    fn double(&self, coordinates: &Coordinates) -> Coordinates {
+        let Coordinates::Affine { x, y } = coordinates else {
+            return Coordinates::Infinity;
+        };
+        // the tangent at a point with y = 0 is vertical
+        if y.is_zero() {
+            return Coordinates::Infinity;
+        }
+
+        // lambda = (3x^2 + a) / 2y
+        let p = &self.p;
+        let lambda = RU256::from_u64(3)
+            .mul_mod(&x.mul_mod(x, p), p)
+            .add_mod(&self.a, p)
+            .div_mod(&RU256::from_u64(2).mul_mod(y, p), p);
+        self.line_intersection(x, y, x, &lambda)
+    }
+
+    /// Reflection of the third point on the line through (x1, y1) and a
+    /// point with x coordinate x2
+    fn line_intersection(&self, x1: &RU256, y1: &RU256, x2: &RU256, lambda: &RU256) -> Coordinates {
+        let p = &self.p;
+        let x = lambda.mul_mod(lambda, p).sub_mod(x1, p).sub_mod(x2, p);
+        let y = lambda.mul_mod(&x1.sub_mod(&x, p), p).sub_mod(y1, p);
+        Coordinates::Affine { x, y }
+    }
+
+    fn multiply(&self, scalar: &RU256, coordinates: &Coordinates) -> Coordinates {
+        let mut result = Coordinates::Infinity;
+        for i in (0..scalar.v.bits()).rev() {
+            result = self.double(&result);
+            if scalar.v.bit(i) {
+                result = self.add(&result, coordinates);
+            }
+        }
+        result
+    }
+
+    fn negate(&self, coordinates: &Coordinates) -> Coordinates {
+        match coordinates {
+            Coordinates::Infinity => Coordinates::Infinity,
+            Coordinates::Affine { x, y } => Coordinates::Affine {
+                x: x.clone(),
+                y: RU256::zero().sub_mod(y, &self.p),
+            },
+        }
+    }
+
+    /// Every affine point of a toy curve, by trying all x and y
+    pub fn points(&self) -> Vec<Point> {
+        let p = self.toy_prime();
+        let mut points = Vec::new();
+        for x in 0..p {
+            for y in 0..p {
+                let point = Point {
+                    x: RU256::from_u64(x),
+                    y: RU256::from_u64(y),
+                };
+                if self.contains(&point) && !Self::is_zero_point(&point) {
+                    points.push(point);
+                }
+            }
+        }
+        points
+    }
+
+    /// Number of points on a toy curve including the point at infinity,
+    /// counting the square roots of x^3 + ax + b with Euler's criterion
+    pub fn order(&self) -> u64 {
+        let p = self.toy_prime();
+        let a = self.a.v.low_u64() % p;
+        let b = self.b.v.low_u64() % p;
+        let mut order = 1;
+        for x in 0..p {
+            let rhs = (x * x % p * x % p + a * x % p + b) % p;
+            order += match pow_mod(rhs, (p - 1) / 2, p) {
+                0 => 1,
+                1 => 2,
+                _ => 0,
+            };
+        }
+        order
+    }
+
+    /// Smallest n > 0 with n * point = O, by repeated addition
+    pub fn point_order(&self, point: &Point) -> u64 {
+        assert!(self.contains(point), "point is not on the curve");
+        let mut multiple = point.clone();
+        let mut n = 1;
+        while !Self::is_zero_point(&multiple) {
+            multiple = self.add_points(&multiple, point);
+            n += 1;
+        }
+        n
+    }
+
+    fn toy_prime(&self) -> u64 {
+        assert!(
+            self.p.v.bits() <= 32,
+            "point counting is only feasible for toy curves"
+        );
+        self.p.v.low_u64()
+    }
+}
+
+/// Where a point is, the point at infinity having no coordinates at all
+#[derive(Debug, Clone, PartialEq)]
+pub enum Coordinates {
+    Infinity,
+    Affine { x: RU256, y: RU256 },
+}
+
+impl From<&Point> for Coordinates {
+    fn from(point: &Point) -> Self {
+        if point.x.is_zero() && point.y.is_zero() {
+            Coordinates::Infinity
+        } else {
+            Coordinates::Affine {
+                x: point.x.clone(),
+                y: point.y.clone(),
+            }
+        }
+    }
+}
+
+impl From<Coordinates> for Point {
+    fn from(coordinates: Coordinates) -> Self {
+        match coordinates {
+            Coordinates::Infinity => Point {
+                x: RU256::zero(),
+                y: RU256::zero(),
+            },
+            Coordinates::Affine { x, y } => Point { x, y },
+        }
+    }
+}
+
+/// A point known to be on `curve`, made by `Curve::point`, `Curve::infinity`
+/// or arithmetic on other such points. Adding points of different curves
+/// panics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurvePoint<'a> {
+    curve: &'a Curve,
+    coordinates: Coordinates,
+}
+
+impl<'a> CurvePoint<'a> {
+    pub fn curve(&self) -> &'a Curve {
+        self.curve
+    }
+
+    pub fn coordinates(&self) -> &Coordinates {
+        &self.coordinates
+    }
+
+    pub fn is_infinity(&self) -> bool {
+        self.coordinates == Coordinates::Infinity
+    }
+
+    /// The point in the (0, 0) convention of secp256k1.rs
+    pub fn to_point(&self) -> Point {
+        self.coordinates.clone().into()
+    }
+
+    fn with(&self, coordinates: Coordinates) -> Self {
+        CurvePoint {
+            curve: self.curve,
+            coordinates,
+        }
+    }
+}
+
+impl<'a> Add for &CurvePoint<'a> {
+    type Output = CurvePoint<'a>;
+
+    fn add(self, other: &CurvePoint<'a>) -> CurvePoint<'a> {
+        assert!(
+            core::ptr::eq(self.curve, other.curve) || self.curve == other.curve,
+            "points are on different curves"
+        );
+        self.with(self.curve.add(&self.coordinates, &other.coordinates))
+    }
+}
+
+impl<'a> Add for CurvePoint<'a> {
+    type Output = CurvePoint<'a>;
+
+    fn add(self, other: CurvePoint<'a>) -> CurvePoint<'a> {
+        &self + &other
+    }
+}
+
+impl<'a> Mul<&RU256> for &CurvePoint<'a> {
+    type Output = CurvePoint<'a>;
+
+    fn mul(self, scalar: &RU256) -> CurvePoint<'a> {
+        self.with(self.curve.multiply(scalar, &self.coordinates))
+    }
+}
+
+impl<'a> Neg for &CurvePoint<'a> {
+    type Output = CurvePoint<'a>;
+
+    fn neg(self) -> CurvePoint<'a> {
+        self.with(self.curve.negate(&self.coordinates))
+    }
+}
+
+fn pow_mod(base: u64, mut exp: u64, modulus: u64) -> u64 {
+    let mut result = 1;
+    let mut base = base % modulus;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exp >>= 1;
+    }
+    result
+}
+
+/// A base point and its order on a curve
+#[allow(non_snake_case)]
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub curve: Curve,
+    pub G: Point,
+    pub n: RU256,
+}
+
+impl Generator {
+    #[allow(non_snake_case)]
+    pub fn new(curve: Curve, G: Point, n: RU256) -> Self {
+        assert!(curve.contains(&G), "generator is not on the curve");
+        Generator { curve, G, n }
+    }
+
+    pub fn secp256k1() -> Self {
+        Self::new(Curve::secp256k1(), SECP256K1::g(), SECP256K1::n())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn p256() -> Self {
+        let G = Point::from_hex_coordinates(
+            "6B17D1F2E12C4247F8BCE6E563A440F277037D812DEB33A0F4A13945D898C296",
+            "4FE342E2FE1A7F9B8EE7EB4A7C0F9E162BCE33576B315ECECBB6406837BF51F5",
+        );
+        let n = RU256::from_str("FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551")
+            .unwrap();
+        Self::new(Curve::p256(), G, n)
+    }
+
+    /// Multiply the generator by a scalar
+    pub fn mul(&self, scalar: &RU256) -> Point {
+        self.curve.scalar_multiplication(scalar, &self.G)
+    }
+
+    /// The generator as a point of its curve
+    pub fn base(&self) -> CurvePoint<'_> {
+        self.curve.from_point(&self.G).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toy_curve_group_order() {
+        // Programming Bitcoin chapter 3
+        let curve = Curve::toy(223, 0, 7);
+        let g = Point {
+            x: RU256::from_u64(47),
+            y: RU256::from_u64(71),
+        };
+        assert_eq!(curve.order(), 252);
+        assert_eq!(curve.points().len() + 1, 252);
+        assert_eq!(curve.point_order(&g), 21);
+        assert_eq!(
+            curve.scalar_multiplication(&RU256::from_u64(5), &g),
+            Point {
+                x: RU256::from_u64(126),
+                y: RU256::from_u64(96),
+            }
+        );
+        assert_eq!(
+            curve.scalar_multiplication(&RU256::from_u64(21), &g),
+            curve.infinity().to_point()
+        );
+    }
+
+    #[test]
+    fn secp256k1_matches_native_point_math() {
+        let generator = Generator::secp256k1();
+        let g2 = SECP256K1::double_point(&SECP256K1::g());
+        assert_eq!(generator.curve.double_point(&generator.G), g2);
+        assert_eq!(
+            generator.curve.add_points(&g2, &generator.G),
+            SECP256K1::add_points(&g2, &SECP256K1::g())
+        );
+    }
+
+    #[test]
+    fn p256_point_doubling() {
+        let generator = Generator::p256();
+        let g2 = generator.mul(&RU256::from_u64(2));
+        assert!(generator.curve.contains(&g2));
+        assert_eq!(
+            g2,
+            Point::from_hex_coordinates(
+                "7CF27B188D034F7E8A52380304B51AC3C08969E277F21B35A60B48FC47669978",
+                "07775510DB8ED040293D9AC69F7430DBBA7DADE63CE982299E04B79D227873D1",
+            )
+        );
+    }
+
+    #[test]
+    fn identity_laws() {
+        let curve = Curve::toy(223, 0, 7);
+        let g = curve
+            .point(RU256::from_u64(47), RU256::from_u64(71))
+            .unwrap();
+        let infinity = curve.infinity();
+
+        assert_eq!(&g + &infinity, g);
+        assert_eq!(&infinity + &g, g);
+        assert_eq!(&infinity + &infinity, infinity);
+        assert_eq!(-&infinity, infinity);
+        assert!((&g + &-&g).is_infinity());
+        assert!((&g * &RU256::zero()).is_infinity());
+        assert!((&g * &RU256::from_u64(21)).is_infinity());
+        assert_eq!(&g * &RU256::from_u64(22), g);
+        // the sum is on the curve again
+        let sum = &g + &g;
+        assert!(curve.contains(&sum.to_point()));
+        assert_eq!(curve.from_point(&sum.to_point()), Some(sum));
+
+        // (0, 0) isn't a point here, only the stand-in for infinity
+        assert_eq!(curve.point(RU256::zero(), RU256::zero()), None);
+        assert_eq!(curve.point(RU256::from_u64(47), RU256::from_u64(72)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "points are on different curves")]
+    fn mixing_curves_is_rejected() {
+        let (curve, other) = (Curve::toy(223, 0, 7), Curve::toy(223, 0, 5));
+        let g = curve
+            .point(RU256::from_u64(47), RU256::from_u64(71))
+            .unwrap();
+        let _ = &g + &other.infinity();
+    }
+
+    #[test]
+    #[should_panic(expected = "curve is singular")]
+    fn singular_curve_is_rejected() {
+        // y^2 = x^3 - 3x + 2 has a double root at x = 1
+        Curve::toy(97, 94, 2);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::block::Block;
+use crate::encoding::TryDecodable;
+use crate::keys::b58check_decode;
+use crate::policy::ScriptType;
+use crate::transaction::{Cmd, Script, Tx};
+
+// Backend of the `btcdec` tool: take whatever a lab hands out (an address, or
+// the hex of a transaction, block, header or script), work out what it is and
+// show it as JSON, in the shape of the matching Bitcoin Core RPC plus a
+// `decoded` field naming what was found. Each candidate type is tried in
+// turn and a decoding error just means "not this one".
+
+const OP_HASH160: u8 = 0xa9;
+const OP_EQUAL: u8 = 0x87;
+
+/// PSBT magic bytes, `psbt` and 0xff
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// Recognized, but there is no decoder for it yet
+    Unsupported(&'static str),
+    /// Neither an address nor hex of anything known
+    Unrecognized,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Unsupported(kind) => write!(f, "can't decode {}s yet", kind),
+            DecodeError::Unrecognized => write!(f, "not an address or hex of a known type"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn script_json(script: &Script, net: &str) -> Value {
+    let mut json = json!({
+        "asm": script.asm(),
+        "hex": hex::encode(script.to_bytes()),
+        "type": ScriptType::of(script).name(),
+    });
+    if let Some(address) = script.address(net) {
+        json["address"] = json!(address);
+    }
+    json
+}
+
+/// A base58check P2PKH or P2SH address
+fn decode_address(address: &str) -> Option<Value> {
+    let payload = b58check_decode(address)?;
+    let (&version, hash) = payload.split_first()?;
+    if hash.len() != 20 {
+        return None;
+    }
+    let (net, script_pubkey) = match version {
+        0x00 | 0x6f => (
+            if version == 0x00 { "main" } else { "test" },
+            Script::p2pkh(hash),
+        ),
+        0x05 | 0xc4 => (
+            if version == 0x05 { "main" } else { "test" },
+            Script::new(vec![
+                Cmd::Op(OP_HASH160),
+                Cmd::push(hash),
+                Cmd::Op(OP_EQUAL),
+            ]),
+        ),
+        _ => return None,
+    };
+    Some(json!({
+        "decoded": "address",
+        "address": address,
+        "net": net,
+        "scriptPubKey": script_json(&script_pubkey, net),
+    }))
+}
+
+/// Work out what `input` is and decode it, addresses are shown for `net`
+pub fn decode(input: &str, net: &str) -> Result<Value, DecodeError> {
+    let input = input.trim();
+    if input.starts_with("cashuA") || input.starts_with("cashuB") {
+        return Err(DecodeError::Unsupported("Cashu token"));
+    }
+    // base64 PSBTs start with the encoded magic
+    if input.starts_with("cHNidP8") {
+        return Err(DecodeError::Unsupported("PSBT"));
+    }
+    let Ok(bytes) = hex::decode(input) else {
+        return decode_address(input).ok_or(DecodeError::Unrecognized);
+    };
+    if bytes.starts_with(PSBT_MAGIC) {
+        return Err(DecodeError::Unsupported("PSBT"));
+    }
+
+    if let Ok(tx) = Tx::try_decode_all(&bytes) {
+        let mut json = tx.to_json(net);
+        json["decoded"] = json!("transaction");
+        return Ok(json);
+    }
+    if bytes.len() == 80 {
+        let block = Block::decode_header(&mut bytes.as_slice());
+        let mut json = block.to_json(net);
+        for field in ["size", "strippedsize", "weight", "tx", "nTx"] {
+            json.as_object_mut().unwrap().remove(field);
+        }
+        json["decoded"] = json!("block header");
+        return Ok(json);
+    }
+    if bytes.len() > 80 {
+        if let Ok(block) = Block::try_decode_all(&bytes) {
+            let mut json = block.to_json(net);
+            json["decoded"] = json!("block");
+            return Ok(json);
+        }
+    }
+    // anything is a script, only take one that parses
+    let script = Script::from_bytes(&bytes);
+    match script.cmds() {
+        Ok(_) => {
+            let mut json = script_json(&script, net);
+            json["decoded"] = json!("script");
+            Ok(json)
+        }
+        Err(_) => Err(DecodeError::Unrecognized),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::encoding::Encodable;
+    use crate::transaction::TxBuilder;
+
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+
+    #[test]
+    fn detects_each_type() {
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0x22; 20]))
+            .build();
+        let json = decode(&hex::encode(tx.encode()), "main").unwrap();
+        assert_eq!(json["decoded"], "transaction");
+        assert_eq!(json["txid"], tx.id());
+
+        let json = decode(GENESIS_HEADER, "main").unwrap();
+        assert_eq!(json["decoded"], "block header");
+        assert_eq!(
+            json["hash"],
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+
+        let json = decode("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac", "main").unwrap();
+        assert_eq!(json["decoded"], "script");
+        assert_eq!(json["type"], "pubkeyhash");
+        assert_eq!(json["address"], "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+
+        let json = decode("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "test").unwrap();
+        assert_eq!(json["decoded"], "address");
+        assert_eq!(json["net"], "main");
+        assert_eq!(
+            json["scriptPubKey"]["hex"],
+            "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_input() {
+        assert_eq!(
+            decode("70736274ff0100", "main"),
+            Err(DecodeError::Unsupported("PSBT"))
+        );
+        assert_eq!(
+            decode("cashuAeyJ0b2tlbiI6W119", "main"),
+            Err(DecodeError::Unsupported("Cashu token"))
+        );
+        assert_eq!(
+            decode("not hex or base58", "main"),
+            Err(DecodeError::Unrecognized)
+        );
+        // a push running past the end
+        assert_eq!(decode("4c", "main"), Err(DecodeError::Unrecognized));
+    }
+}
@@ -0,0 +1,449 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bip32::{ExtendedPublicKey, HARDENED};
+use crate::hashes::{hash160, tagged};
+use crate::keys::{PublicKey, XOnlyPublicKey};
+use crate::ru256::RU256;
+use crate::transaction::{Cmd, Script};
+
+// Output script descriptors (BIP380 and friends) for single key wallets:
+// wpkh(KEY), sh(wpkh(KEY)) and tr(KEY) where KEY is a hex public key or an
+// xpub with an unhardened derivation path, optionally ending in /* for a
+// range of keys, and optionally prefixed with its [fingerprint/path] origin.
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_HASH160: u8 = 0xa9;
+const OP_EQUAL: u8 = 0x87;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(symbols: impl Iterator<Item = u64>) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let mut chk = 1;
+    for value in symbols {
+        let top = chk >> 35;
+        chk = ((chk & 0x7ffffffff) << 5) ^ value;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+/// The 8 character checksum of a descriptor, None if it has characters that
+/// can't appear in descriptors
+pub fn checksum(desc: &str) -> Option<String> {
+    // each character is a 5 bit symbol plus a group number, the group numbers
+    // are packed three to a symbol
+    let mut symbols = vec![];
+    let mut groups = vec![];
+    for c in desc.chars() {
+        let value = INPUT_CHARSET.find(c)? as u64;
+        symbols.push(value & 31);
+        groups.push(value >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups[..] {
+        [a] => symbols.push(a),
+        [a, b] => symbols.push(a * 3 + b),
+        _ => {}
+    }
+
+    let chk = polymod(symbols.into_iter().chain([0; 8])) ^ 1;
+    Some(
+        (0..8)
+            .map(|i| CHECKSUM_CHARSET[((chk >> (5 * (7 - i))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DescriptorError {
+    InvalidChecksum,
+    InvalidKey(String),
+    InvalidPath(String),
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    Single(PublicKey),
+    /// A 32 byte key as tr() writes it, the point with the even y
+    XOnly(PublicKey),
+    Extended {
+        xpub: ExtendedPublicKey,
+        path: Vec<u32>,
+        ranged: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptorKey {
+    /// `fingerprint/path` of the key's origin, kept as written
+    pub origin: Option<String>,
+    pub source: KeySource,
+}
+
+impl DescriptorKey {
+    pub fn is_ranged(&self) -> bool {
+        matches!(self.source, KeySource::Extended { ranged: true, .. })
+    }
+
+    /// The public key at `index`, which only matters for ranged keys
+    pub fn derive(&self, index: u32) -> PublicKey {
+        self.derive_range(index, 1).remove(0)
+    }
+
+    /// The public keys at `start..start + count`, deriving the fixed part of
+    /// the path only once
+    pub fn derive_range(&self, start: u32, count: u32) -> Vec<PublicKey> {
+        match &self.source {
+            KeySource::Single(public_key) | KeySource::XOnly(public_key) => {
+                vec![public_key.clone(); count as usize]
+            }
+            KeySource::Extended { xpub, path, ranged } => {
+                let key = xpub.derive_path(path);
+                (start..start + count)
+                    .map(|index| match ranged {
+                        true => key.derive_child(index).public_key,
+                        false => key.public_key.clone(),
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for DescriptorKey {
+    type Err = DescriptorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest
+                    .split_once(']')
+                    .ok_or_else(|| DescriptorError::InvalidKey(s.to_string()))?;
+                (Some(origin.to_string()), key)
+            }
+            None => (None, s),
+        };
+
+        let source = if key.starts_with("xpub") || key.starts_with("tpub") {
+            let mut parts = key.split('/');
+            let xpub = ExtendedPublicKey::from_str(parts.next().unwrap())
+                .map_err(|_| DescriptorError::InvalidKey(key.to_string()))?;
+            let mut path = vec![];
+            let mut ranged = false;
+            for part in parts {
+                if ranged {
+                    // the wildcard has to be the last step
+                    return Err(DescriptorError::InvalidPath(key.to_string()));
+                }
+                if part == "*" {
+                    ranged = true;
+                    continue;
+                }
+                match part.parse::<u32>() {
+                    Ok(index) if index < HARDENED => path.push(index),
+                    // hardened steps (1' or 1h) need the private key
+                    _ => return Err(DescriptorError::InvalidPath(key.to_string())),
+                }
+            }
+            KeySource::Extended { xpub, path, ranged }
+        } else {
+            let invalid = || DescriptorError::InvalidKey(key.to_string());
+            let bytes = hex::decode(key).map_err(|_| invalid())?;
+            match bytes.len() {
+                33 if matches!(bytes[0], 0x02 | 0x03) => {
+                    KeySource::Single(PublicKey::try_from_bytes(&bytes).ok_or_else(invalid)?)
+                }
+                // x-only keys for tr()
+                32 => {
+                    let sec = [&[0x02], &bytes[..]].concat();
+                    KeySource::XOnly(PublicKey::try_from_bytes(&sec).ok_or_else(invalid)?)
+                }
+                _ => return Err(invalid()),
+            }
+        };
+
+        Ok(DescriptorKey { origin, source })
+    }
+}
+
+impl fmt::Display for DescriptorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(origin) = &self.origin {
+            write!(f, "[{}]", origin)?;
+        }
+        match &self.source {
+            KeySource::Single(public_key) => {
+                write!(f, "{}", hex::encode(public_key.sec(true, false)))
+            }
+            KeySource::XOnly(public_key) => {
+                write!(f, "{}", hex::encode(&public_key.sec(true, false)[1..]))
+            }
+            KeySource::Extended { xpub, path, ranged } => {
+                write!(f, "{}", xpub)?;
+                for index in path {
+                    write!(f, "/{}", index)?;
+                }
+                if *ranged {
+                    write!(f, "/*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// BIP86 output key of a key path only taproot output, the internal key (with
+/// even y) tweaked by the hash of its x coordinate
+fn taproot_output_key(internal_key: &PublicKey) -> Vec<u8> {
+    let (internal_key, _) = XOnlyPublicKey::from_pubkey(internal_key);
+    let tweak = RU256::from_bytes(&tagged("TapTweak", &internal_key.serialize()));
+    let (output_key, _) = internal_key
+        .tweak_add(&tweak)
+        .expect("taproot tweak is out of range");
+    output_key.serialize().to_vec()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Descriptor {
+    Wpkh(DescriptorKey),
+    ShWpkh(DescriptorKey),
+    Tr(DescriptorKey),
+}
+
+impl Descriptor {
+    fn key(&self) -> &DescriptorKey {
+        match self {
+            Descriptor::Wpkh(key) | Descriptor::ShWpkh(key) | Descriptor::Tr(key) => key,
+        }
+    }
+
+    pub fn is_ranged(&self) -> bool {
+        self.key().is_ranged()
+    }
+
+    /// The locking script for the key at `index`
+    pub fn script_pubkey(&self, index: u32) -> Script {
+        self.script_for_key(&self.key().derive(index))
+    }
+
+    /// The locking scripts for the keys at `start..start + count`
+    pub fn script_pubkeys(&self, start: u32, count: u32) -> Vec<Script> {
+        self.key()
+            .derive_range(start, count)
+            .iter()
+            .map(|public_key| self.script_for_key(public_key))
+            .collect()
+    }
+
+    fn script_for_key(&self, public_key: &PublicKey) -> Script {
+        match self {
+            Descriptor::Wpkh(_) => {
+                Script::new(vec![Cmd::Op(OP_0), Cmd::Push(public_key.sec(true, true))])
+            }
+            Descriptor::ShWpkh(_) => {
+                // the redeem script is the wpkh script, serialized as
+                // OP_0 <push 20 bytes> <key hash>
+                let mut redeem_script = vec![OP_0, 20];
+                redeem_script.extend(public_key.sec(true, true));
+                Script::new(vec![
+                    Cmd::Op(OP_HASH160),
+                    Cmd::push(&hash160(&redeem_script)),
+                    Cmd::Op(OP_EQUAL),
+                ])
+            }
+            Descriptor::Tr(_) => Script::new(vec![
+                Cmd::Op(OP_1),
+                Cmd::Push(taproot_output_key(public_key)),
+            ]),
+        }
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = DescriptorError;
+
+    /// Parse a descriptor, verifying the `#checksum` suffix if there is one
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc = match s.split_once('#') {
+            Some((desc, expected)) => {
+                if checksum(desc).as_deref() != Some(expected) {
+                    return Err(DescriptorError::InvalidChecksum);
+                }
+                desc
+            }
+            None => {
+                checksum(s).ok_or(DescriptorError::InvalidChecksum)?;
+                s
+            }
+        };
+
+        if let Some(inner) = desc
+            .strip_prefix("sh(wpkh(")
+            .and_then(|s| s.strip_suffix("))"))
+        {
+            Ok(Descriptor::ShWpkh(inner.parse()?))
+        } else if let Some(inner) = desc.strip_prefix("wpkh(").and_then(|s| s.strip_suffix(')')) {
+            Ok(Descriptor::Wpkh(inner.parse()?))
+        } else if let Some(inner) = desc.strip_prefix("tr(").and_then(|s| s.strip_suffix(')')) {
+            if inner.contains(',') {
+                return Err(DescriptorError::Unsupported(
+                    "tr() with script paths".to_string(),
+                ));
+            }
+            Ok(Descriptor::Tr(inner.parse()?))
+        } else {
+            Err(DescriptorError::Unsupported(desc.to_string()))
+        }
+    }
+}
+
+/// The descriptor with its checksum, like Bitcoin Core's getdescriptorinfo
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let desc = match self {
+            Descriptor::Wpkh(key) => format!("wpkh({})", key),
+            Descriptor::ShWpkh(key) => format!("sh(wpkh({}))", key),
+            Descriptor::Tr(key) => format!("tr({})", key),
+        };
+        write!(f, "{}#{}", desc, checksum(&desc).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c";
+
+    #[test]
+    fn descriptor_checksums() {
+        // BIP380 test vector
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(checksum(&format!("wpkh({})", PUBKEY)).unwrap(), "3chvf9zl");
+        assert_eq!(checksum("wpkh(\u{e9})"), None);
+
+        let desc = format!("wpkh({})#3chvf9zl", PUBKEY);
+        assert!(Descriptor::from_str(&desc).is_ok());
+        let corrupted = format!("wpkh({})#3chvf9zm", PUBKEY);
+        assert_eq!(
+            Descriptor::from_str(&corrupted),
+            Err(DescriptorError::InvalidChecksum)
+        );
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let tests = [
+            "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)#wc3n3van",
+            "sh(wpkh(xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*))#zkmlplxt",
+            "tr(xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)#8e7pq23w",
+        ];
+        for s in tests {
+            let desc = Descriptor::from_str(s).unwrap();
+            assert!(desc.is_ranged());
+            assert_eq!(desc.to_string(), s);
+        }
+
+        // hardened steps need the private key
+        assert!(matches!(
+            Descriptor::from_str("wpkh(xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0'/*)"),
+            Err(DescriptorError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            Descriptor::from_str(
+                "pkh(0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c)"
+            ),
+            Err(DescriptorError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn single_key_scripts() {
+        let wpkh = Descriptor::from_str(&format!("wpkh({})", PUBKEY)).unwrap();
+        assert!(!wpkh.is_ranged());
+        assert_eq!(
+            wpkh.script_pubkey(0).cmds().unwrap(),
+            vec![
+                Cmd::Op(OP_0),
+                Cmd::Push(hex::decode("c0cebcd6c3d3ca8c75dc5ec62ebe55330ef910e2").unwrap())
+            ]
+        );
+
+        let sh_wpkh = Descriptor::from_str(&format!("sh(wpkh({}))", PUBKEY)).unwrap();
+        assert_eq!(
+            sh_wpkh.script_pubkey(0).cmds().unwrap()[1],
+            Cmd::Push(hex::decode("a6b5888fddc8fa193dd353d10e5cd5a8eeab064e").unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_keys() {
+        // no point has x = 0, and a key has to be 33 or 32 bytes
+        let tests = [
+            format!("02{}", "00".repeat(32)),
+            "00".repeat(32),
+            "0430d54f".to_string(),
+            "zz".repeat(33),
+        ];
+        for key in tests {
+            assert!(matches!(
+                Descriptor::from_str(&format!("wpkh({})", key)),
+                Err(DescriptorError::InvalidKey(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn x_only_taproot_key() {
+        // the internal key of the derivation below, written x-only
+        let inner = "tr(cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115)";
+        let desc = Descriptor::from_str(inner).unwrap();
+        assert_eq!(
+            desc.to_string(),
+            format!("{}#{}", inner, checksum(inner).unwrap())
+        );
+        assert_eq!(
+            desc.script_pubkey(0).cmds().unwrap()[1],
+            Cmd::Push(
+                hex::decode("a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c")
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn ranged_taproot_derivation() {
+        // BIP86 m/86'/0'/0'/0/0 of the "abandon ... about" mnemonic, internal key
+        // cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115
+        let desc = Descriptor::from_str("tr(xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)").unwrap();
+        assert_eq!(
+            desc.script_pubkey(0).cmds().unwrap(),
+            vec![
+                Cmd::Op(OP_1),
+                Cmd::Push(
+                    hex::decode("a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c")
+                        .unwrap()
+                )
+            ]
+        );
+    }
+}
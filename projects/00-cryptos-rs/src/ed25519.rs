@@ -0,0 +1,420 @@
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use primitive_types::{U256, U512};
+use sha2::{Digest, Sha512};
+
+use crate::encoding::{take, Decodable, Encodable};
+use crate::ru256::RU256;
+
+// Ed25519 signatures (RFC 8032) and X25519 key exchange (RFC 7748) from
+// scratch, for comparing Schnorr over secp256k1 with the Edwards curve
+// -x^2 + y^2 = 1 + d x^2 y^2 over 2^255 - 19. Everything on the wire is little
+// endian, unlike the rest of the crate.
+
+/// Field prime 2^255 - 19
+fn p() -> RU256 {
+    RU256 {
+        v: (U256::one() << 255) - 19,
+    }
+}
+
+/// Order of the base point, 2^252 + 27742317777372353535851937790883648493
+pub fn l() -> RU256 {
+    RU256::from_str("1000000000000000000000000000000014def9dea2f79cd65812631a5cf5d3ed").unwrap()
+}
+
+/// Curve constant d = -121665 / 121666
+fn d() -> RU256 {
+    RU256::from_str("52036cee2b6ffe738cc740797779e89800700a4d4141d8ab75eb4dca135978a3").unwrap()
+}
+
+/// 2^((p - 1) / 4), a square root of -1
+fn sqrt_m1() -> RU256 {
+    RU256::from_str("2b8324804fc1df0b2b4d00993dfbd7a72f431806ad2fe478c4ee1b274a0ea0b0").unwrap()
+}
+
+fn from_le_bytes(bytes: &[u8]) -> RU256 {
+    RU256 {
+        v: U256::from_little_endian(bytes),
+    }
+}
+
+fn to_le_bytes(n: &RU256) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    n.v.to_little_endian(&mut out);
+    out
+}
+
+/// SHA-512 of the concatenated parts as an integer mod l
+fn hash_to_scalar(parts: &[&[u8]]) -> RU256 {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let wide = U512::from_little_endian(&hasher.finalize()) % U512::from(l().v);
+    RU256 {
+        v: U256::try_from(wide).unwrap(),
+    }
+}
+
+/// A point in extended coordinates (X : Y : Z : T) with x = X/Z, y = Y/Z and
+/// xy = T/Z, which lets additions skip the field inversion
+#[derive(Debug, Clone)]
+pub struct EdwardsPoint {
+    x: RU256,
+    y: RU256,
+    z: RU256,
+    t: RU256,
+}
+
+impl EdwardsPoint {
+    pub fn identity() -> Self {
+        EdwardsPoint {
+            x: RU256::zero(),
+            y: RU256::one(),
+            z: RU256::one(),
+            t: RU256::zero(),
+        }
+    }
+
+    /// The base point, the point with y = 4/5 and even x
+    pub fn base() -> Self {
+        let p = p();
+        let y = RU256::from_u64(4).div_mod(&RU256::from_u64(5), &p);
+        Self::from_affine(Self::recover_x(&y, false).unwrap(), y)
+    }
+
+    fn from_affine(x: RU256, y: RU256) -> Self {
+        let t = x.mul_mod(&y, &p());
+        EdwardsPoint {
+            x,
+            y,
+            z: RU256::one(),
+            t,
+        }
+    }
+
+    /// Solve the curve equation for x, x^2 = (y^2 - 1) / (d y^2 + 1)
+    fn recover_x(y: &RU256, odd: bool) -> Option<RU256> {
+        let p = p();
+        let y2 = y.mul_mod(y, &p);
+        let x2 = y2
+            .sub_mod(&RU256::one(), &p)
+            .div_mod(&d().mul_mod(&y2, &p).add_mod(&RU256::one(), &p), &p);
+        if x2.is_zero() {
+            return if odd { None } else { Some(x2) };
+        }
+
+        // p = 5 mod 8, so x2^((p + 3) / 8) is a square root of x2 or of -x2
+        let mut x = x2.exp_mod(&RU256 { v: (p.v + 3) >> 3 }, &p);
+        if x.mul_mod(&x, &p) != x2 {
+            x = x.mul_mod(&sqrt_m1(), &p);
+        }
+        if x.mul_mod(&x, &p) != x2 {
+            return None;
+        }
+        if x.v.bit(0) != odd {
+            x = RU256::zero().sub_mod(&x, &p);
+        }
+        Some(x)
+    }
+
+    /// Decode the 32 byte encoding, None if it isn't a curve point
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        let odd = bytes[31] & 0x80 != 0;
+        let mut y_bytes = *bytes;
+        y_bytes[31] &= 0x7f;
+        let y = from_le_bytes(&y_bytes);
+        if y >= p() {
+            return None;
+        }
+        Some(Self::from_affine(Self::recover_x(&y, odd)?, y))
+    }
+
+    pub fn add_point(&self, other: &Self) -> Self {
+        let p = &p();
+        let a = self
+            .y
+            .sub_mod(&self.x, p)
+            .mul_mod(&other.y.sub_mod(&other.x, p), p);
+        let b = self
+            .y
+            .add_mod(&self.x, p)
+            .mul_mod(&other.y.add_mod(&other.x, p), p);
+        let c = self
+            .t
+            .mul_mod(&RU256::from_u64(2), p)
+            .mul_mod(&d(), p)
+            .mul_mod(&other.t, p);
+        let d = self.z.mul_mod(&RU256::from_u64(2), p).mul_mod(&other.z, p);
+        Self::combine(
+            b.sub_mod(&a, p),
+            d.sub_mod(&c, p),
+            d.add_mod(&c, p),
+            b.add_mod(&a, p),
+        )
+    }
+
+    pub fn double(&self) -> Self {
+        let p = &p();
+        let a = self.x.mul_mod(&self.x, p);
+        let b = self.y.mul_mod(&self.y, p);
+        let c = RU256::from_u64(2).mul_mod(&self.z.mul_mod(&self.z, p), p);
+        let h = a.add_mod(&b, p);
+        let xy = self.x.add_mod(&self.y, p);
+        let e = h.sub_mod(&xy.mul_mod(&xy, p), p);
+        let g = a.sub_mod(&b, p);
+        let f = c.add_mod(&g, p);
+        Self::combine(e, f, g, h)
+    }
+
+    /// The shared tail of the RFC 8032 addition and doubling formulas
+    fn combine(e: RU256, f: RU256, g: RU256, h: RU256) -> Self {
+        let p = &p();
+        EdwardsPoint {
+            x: e.mul_mod(&f, p),
+            y: g.mul_mod(&h, p),
+            z: f.mul_mod(&g, p),
+            t: e.mul_mod(&h, p),
+        }
+    }
+
+    /// Double and add
+    pub fn scalar_multiplication(&self, scalar: &RU256) -> Self {
+        let mut result = Self::identity();
+        for i in (0..scalar.v.bits()).rev() {
+            result = result.double();
+            if scalar.v.bit(i) {
+                result = result.add_point(self);
+            }
+        }
+        result
+    }
+}
+
+/// Points are equal when their affine coordinates are, whatever Z they carry
+impl PartialEq for EdwardsPoint {
+    fn eq(&self, other: &Self) -> bool {
+        let p = &p();
+        self.x.mul_mod(&other.z, p) == other.x.mul_mod(&self.z, p)
+            && self.y.mul_mod(&other.z, p) == other.y.mul_mod(&self.z, p)
+    }
+}
+
+/// y in little endian with the parity of x in the top bit
+impl Encodable for EdwardsPoint {
+    fn encode(&self) -> Vec<u8> {
+        let p = &p();
+        let z_inv = RU256::one().div_mod(&self.z, p);
+        let x = self.x.mul_mod(&z_inv, p);
+        let mut out = to_le_bytes(&self.y.mul_mod(&z_inv, p));
+        if x.v.bit(0) {
+            out[31] |= 0x80;
+        }
+        out.to_vec()
+    }
+}
+
+impl Decodable for EdwardsPoint {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        let bytes: &[u8; 32] = take(bytes, 32).try_into().unwrap();
+        Self::from_bytes(bytes).expect("not a curve point")
+    }
+}
+
+/// The secret scalar and the nonce prefix derived from a 32 byte secret key
+fn expand_secret_key(secret_key: &[u8; 32]) -> (RU256, Vec<u8>) {
+    let h = Sha512::digest(secret_key);
+    let mut scalar_bytes: [u8; 32] = h[..32].try_into().unwrap();
+    // clear the cofactor bits and fix the top bit
+    scalar_bytes[0] &= 248;
+    scalar_bytes[31] &= 127;
+    scalar_bytes[31] |= 64;
+    (from_le_bytes(&scalar_bytes), h[32..].to_vec())
+}
+
+pub fn public_key(secret_key: &[u8; 32]) -> [u8; 32] {
+    let (a, _) = expand_secret_key(secret_key);
+    EdwardsPoint::base()
+        .scalar_multiplication(&a)
+        .encode()
+        .try_into()
+        .unwrap()
+}
+
+/// Deterministic Ed25519 signature `R || S`
+pub fn sign(secret_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+    let (a, prefix) = expand_secret_key(secret_key);
+    let l = l();
+    let base = EdwardsPoint::base();
+    let public_key = base.scalar_multiplication(&a).encode();
+
+    // the nonce is a hash of the secret prefix and the message, so signing
+    // needs no randomness
+    let r = hash_to_scalar(&[&prefix, message]);
+    let big_r = base.scalar_multiplication(&r).encode();
+    let k = hash_to_scalar(&[&big_r, &public_key, message]);
+    let s = r.add_mod(&k.mul_mod(&a, &l), &l);
+
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&big_r);
+    sig[32..].copy_from_slice(&to_le_bytes(&s));
+    sig
+}
+
+/// Check `[S]B = R + [k]A`
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    let s = from_le_bytes(&signature[32..]);
+    if s >= l() {
+        return false;
+    }
+    let r_bytes: &[u8; 32] = signature[..32].try_into().unwrap();
+    let (Some(big_r), Some(a)) = (
+        EdwardsPoint::from_bytes(r_bytes),
+        EdwardsPoint::from_bytes(public_key),
+    ) else {
+        return false;
+    };
+
+    let k = hash_to_scalar(&[r_bytes, public_key, message]);
+    EdwardsPoint::base().scalar_multiplication(&s) == big_r.add_point(&a.scalar_multiplication(&k))
+}
+
+/// X25519 Diffie-Hellman on the Montgomery form of the curve, multiplying the
+/// u coordinate by the clamped scalar with the RFC 7748 ladder
+pub fn x25519(scalar: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+    let p = &p();
+    let mut k_bytes = *scalar;
+    k_bytes[0] &= 248;
+    k_bytes[31] &= 127;
+    k_bytes[31] |= 64;
+    let k = from_le_bytes(&k_bytes);
+    let mut u_bytes = *u;
+    u_bytes[31] &= 0x7f;
+    let x1 = from_le_bytes(&u_bytes);
+    let a24 = RU256::from_u64(121665);
+
+    let (mut x2, mut z2) = (RU256::one(), RU256::zero());
+    let (mut x3, mut z3) = (x1.clone(), RU256::one());
+    let mut swap = false;
+    for t in (0..255).rev() {
+        let bit = k.v.bit(t);
+        if swap != bit {
+            core::mem::swap(&mut x2, &mut x3);
+            core::mem::swap(&mut z2, &mut z3);
+        }
+        swap = bit;
+
+        let a = x2.add_mod(&z2, p);
+        let aa = a.mul_mod(&a, p);
+        let b = x2.sub_mod(&z2, p);
+        let bb = b.mul_mod(&b, p);
+        let e = aa.sub_mod(&bb, p);
+        let c = x3.add_mod(&z3, p);
+        let d = x3.sub_mod(&z3, p);
+        let da = d.mul_mod(&a, p);
+        let cb = c.mul_mod(&b, p);
+        let sum = da.add_mod(&cb, p);
+        let diff = da.sub_mod(&cb, p);
+        x3 = sum.mul_mod(&sum, p);
+        z3 = x1.mul_mod(&diff.mul_mod(&diff, p), p);
+        x2 = aa.mul_mod(&bb, p);
+        z2 = e.mul_mod(&aa.add_mod(&a24.mul_mod(&e, p), p), p);
+    }
+    if swap {
+        core::mem::swap(&mut x2, &mut x3);
+        core::mem::swap(&mut z2, &mut z3);
+    }
+
+    to_le_bytes(&x2.div_mod(&z2, p))
+}
+
+/// X25519 public key, the scalar times the base point u = 9
+pub fn x25519_base(scalar: &[u8; 32]) -> [u8; 32] {
+    let mut nine = [0u8; 32];
+    nine[0] = 9;
+    x25519(scalar, &nine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes32(s: &str) -> [u8; 32] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn ed25519_rfc8032_vectors() {
+        // RFC 8032 section 7.1, tests 1 and 2
+        let tests = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ];
+
+        for (secret_key, expected_public_key, message, expected_sig) in tests {
+            let secret_key = bytes32(secret_key);
+            let message = hex::decode(message).unwrap();
+            let public_key = public_key(&secret_key);
+            assert_eq!(hex::encode(public_key), expected_public_key);
+
+            let sig = sign(&secret_key, &message);
+            assert_eq!(hex::encode(sig), expected_sig);
+            assert!(verify(&public_key, &message, &sig));
+            assert!(!verify(&public_key, b"another message", &sig));
+        }
+    }
+
+    #[test]
+    fn edwards_point_encoding_roundtrip() {
+        let base = EdwardsPoint::base();
+        assert_eq!(
+            hex::encode(base.encode()),
+            "5866666666666666666666666666666666666666666666666666666666666666"
+        );
+        let p = base.double().add_point(&base);
+        assert_eq!(EdwardsPoint::decode_all(&p.encode()), p);
+        assert_eq!(p, base.scalar_multiplication(&RU256::from_u64(3)));
+        assert_eq!(base.scalar_multiplication(&l()), EdwardsPoint::identity());
+    }
+
+    #[test]
+    fn x25519_rfc7748_vectors() {
+        // RFC 7748 section 5.2
+        assert_eq!(
+            hex::encode(x25519(
+                &bytes32("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4"),
+                &bytes32("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c"),
+            )),
+            "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"
+        );
+
+        // section 6.1, Diffie-Hellman
+        let alice = bytes32("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = bytes32("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_public = x25519_base(&alice);
+        let bob_public = x25519_base(&bob);
+        assert_eq!(
+            hex::encode(alice_public),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+        assert_eq!(
+            hex::encode(bob_public),
+            "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+        );
+        let shared = "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742";
+        assert_eq!(hex::encode(x25519(&alice, &bob_public)), shared);
+        assert_eq!(hex::encode(x25519(&bob, &alice_public)), shared);
+    }
+}
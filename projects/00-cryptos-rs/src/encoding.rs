@@ -0,0 +1,223 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Uniform conversion traits for the crate's types. Binary encodings follow
+// the Bitcoin wire/SEC/DER formats of each type, hex is always the hex of the
+// binary encoding.
+
+pub trait Encodable {
+    fn encode(&self) -> Vec<u8>;
+}
+
+pub trait Decodable: Sized {
+    /// Decode a value from the front of `bytes`, advancing the slice past it.
+    /// Panics on malformed input.
+    fn decode(bytes: &mut &[u8]) -> Self;
+
+    /// Decode a value that spans all of `bytes`
+    fn decode_all(mut bytes: &[u8]) -> Self {
+        let value = Self::decode(&mut bytes);
+        assert!(bytes.is_empty(), "{} trailing bytes", bytes.len());
+        value
+    }
+}
+
+/// Why `TryDecodable` rejected its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodingError {
+    /// The input ended in the middle of a value
+    UnexpectedEnd,
+    /// Bytes left over after a value that should span the whole input
+    TrailingBytes(usize),
+    /// The bytes are there but don't form a valid value
+    Invalid(&'static str),
+}
+
+impl fmt::Display for DecodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodingError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodingError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            DecodingError::Invalid(what) => write!(f, "invalid {}", what),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodingError {}
+
+/// `Decodable` for untrusted input: malformed bytes are an error instead of
+/// a panic
+pub trait TryDecodable: Sized {
+    /// Decode a value from the front of `bytes`, advancing the slice past it
+    fn try_decode(bytes: &mut &[u8]) -> Result<Self, DecodingError>;
+
+    /// Decode a value that spans all of `bytes`
+    fn try_decode_all(mut bytes: &[u8]) -> Result<Self, DecodingError> {
+        let value = Self::try_decode(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(DecodingError::TrailingBytes(bytes.len()));
+        }
+        Ok(value)
+    }
+}
+
+/// Trusted input goes through `Decodable`, which panics on what `try_decode`
+/// would reject
+impl<T: TryDecodable> Decodable for T {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        T::try_decode(bytes).unwrap_or_else(|error| panic!("{}", error))
+    }
+}
+
+pub trait ToHex {
+    fn to_hex(&self) -> String;
+}
+
+impl<T: Encodable> ToHex for T {
+    fn to_hex(&self) -> String {
+        hex::encode(self.encode())
+    }
+}
+
+pub trait FromHex: Sized {
+    fn from_hex(s: &str) -> Self;
+}
+
+impl<T: Decodable> FromHex for T {
+    fn from_hex(s: &str) -> Self {
+        Self::decode_all(&hex::decode(s).unwrap())
+    }
+}
+
+/// Split the first `n` bytes off the front of `bytes`
+pub fn take<'a>(bytes: &mut &'a [u8], n: usize) -> &'a [u8] {
+    assert!(bytes.len() >= n, "expected {} more bytes", n);
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    head
+}
+
+/// `take`, but running out of bytes is an error
+pub fn try_take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodingError> {
+    if bytes.len() < n {
+        return Err(DecodingError::UnexpectedEnd);
+    }
+    Ok(take(bytes, n))
+}
+
+/// The next `N` bytes as an array, for fixed size fields
+pub fn try_take_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], DecodingError> {
+    Ok(try_take(bytes, N)?.try_into().unwrap())
+}
+
+/// A Bitcoin CompactSize varint
+pub fn try_read_varint(bytes: &mut &[u8]) -> Result<u64, DecodingError> {
+    Ok(match try_take_array::<1>(bytes)?[0] {
+        0xfd => u16::from_le_bytes(try_take_array(bytes)?) as u64,
+        0xfe => u32::from_le_bytes(try_take_array(bytes)?) as u64,
+        0xff => u64::from_le_bytes(try_take_array(bytes)?),
+        n => n as u64,
+    })
+}
+
+const BASE64_CHARSET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with `=` padding, how PSBTs are passed around as text
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARSET[(group >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The bytes of padded standard base64, None if it's malformed
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    for (n, chunk) in s.chunks(4).enumerate() {
+        let last = n == s.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut group = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = BASE64_CHARSET.iter().position(|&x| x == c)? as u32;
+            group = group << 6 | value;
+        }
+        group <<= 6 * padding;
+        let bytes = group.to_be_bytes();
+        // leftover bits next to the padding must be zero
+        if bytes[4 - padding..].iter().any(|&b| b != 0) {
+            return None;
+        }
+        out.extend(&bytes[1..4 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        // RFC 4648 section 10
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (plain, encoded) in vectors {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded), Some(plain.as_bytes().to_vec()));
+        }
+        assert_eq!(base64_decode("Zm9"), None);
+        assert_eq!(base64_decode("Zg==Zm8="), None);
+        assert_eq!(base64_decode("Zh=="), None);
+        assert_eq!(base64_decode("Zm9*"), None);
+    }
+
+    #[test]
+    fn test_try_read_varint() {
+        let vectors: [(&[u8], u64); 4] = [
+            (&[0xfc], 0xfc),
+            (&[0xfd, 0x34, 0x12], 0x1234),
+            (&[0xfe, 0x78, 0x56, 0x34, 0x12], 0x12345678),
+            (&[0xff, 1, 0, 0, 0, 0, 0, 0, 0x80], 0x8000000000000001),
+        ];
+        for (mut bytes, value) in vectors {
+            assert_eq!(try_read_varint(&mut bytes), Ok(value));
+            assert!(bytes.is_empty());
+        }
+        assert_eq!(
+            try_read_varint(&mut &[][..]),
+            Err(DecodingError::UnexpectedEnd)
+        );
+        assert_eq!(
+            try_read_varint(&mut &[0xfe, 1, 2][..]),
+            Err(DecodingError::UnexpectedEnd)
+        );
+    }
+}
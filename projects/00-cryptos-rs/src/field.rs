@@ -0,0 +1,181 @@
+use core::ops::{Add, Mul, Neg, Sub};
+
+use crate::ru256::RU256;
+use crate::secp256k1::SECP256K1;
+
+// Typed residues for secp256k1. Point coordinates live in the base field,
+// integers mod p, while secret keys, nonces and signature values are scalars,
+// integers mod the group order n. Both are plain RU256s elsewhere, which makes
+// it easy to do scalar math mod p or to use an x coordinate as a scalar without
+// reducing it mod n (p > n, so a few x coordinates are out of range). Here
+// the two can't be mixed: arithmetic only takes the same type, and the one way
+// from a coordinate to a scalar is `Fp::reduce_to_scalar`. Values are always
+// in canonical form, below the modulus, so comparing two of them compares the
+// residues and not just the integers that happen to represent them.
+
+macro_rules! residue {
+    ($name:ident, $modulus:expr) => {
+        impl $name {
+            pub fn modulus() -> RU256 {
+                $modulus
+            }
+
+            /// `v` reduced into range
+            pub fn new(v: &RU256) -> Self {
+                Self::from_reduced(v.clone() % Self::modulus())
+            }
+
+            /// `v` if it's already in range, for values read from the outside
+            /// that must not silently wrap
+            pub fn from_canonical(v: &RU256) -> Option<Self> {
+                (*v < Self::modulus()).then(|| Self::from_reduced(v.clone()))
+            }
+
+            /// Every value is built here, so a result of the arithmetic that
+            /// isn't below the modulus trips debug builds before it gets to a
+            /// comparison
+            fn from_reduced(v: RU256) -> Self {
+                debug_assert!(
+                    v < Self::modulus(),
+                    concat!(stringify!($name), " value is not reduced")
+                );
+                $name(v)
+            }
+
+            /// A big endian integer reduced into range, e.g. a hash
+            pub fn from_bytes(bytes: &[u8]) -> Self {
+                Self::new(&RU256::from_bytes(bytes))
+            }
+
+            pub fn to_bytes(&self) -> [u8; 32] {
+                let mut bytes = [0u8; 32];
+                self.0.to_bytes(&mut bytes);
+                bytes
+            }
+
+            pub fn as_ru256(&self) -> &RU256 {
+                &self.0
+            }
+
+            pub fn zero() -> Self {
+                Self::from_reduced(RU256::zero())
+            }
+
+            pub fn one() -> Self {
+                Self::from_reduced(RU256::one())
+            }
+
+            pub fn is_zero(&self) -> bool {
+                self.0.is_zero()
+            }
+
+            /// Multiplicative inverse, None for zero
+            pub fn inv(&self) -> Option<Self> {
+                let modulus = Self::modulus();
+                (!self.is_zero())
+                    .then(|| Self::from_reduced(RU256::one().div_mod(&self.0, &modulus)))
+            }
+        }
+
+        impl Add for $name {
+            type Output = $name;
+
+            fn add(self, rhs: $name) -> $name {
+                $name::from_reduced(self.0.add_mod(&rhs.0, &Self::modulus()))
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+
+            fn sub(self, rhs: $name) -> $name {
+                $name::from_reduced(self.0.sub_mod(&rhs.0, &Self::modulus()))
+            }
+        }
+
+        impl Mul for $name {
+            type Output = $name;
+
+            fn mul(self, rhs: $name) -> $name {
+                $name::from_reduced(self.0.mul_mod(&rhs.0, &Self::modulus()))
+            }
+        }
+
+        impl Neg for $name {
+            type Output = $name;
+
+            fn neg(self) -> $name {
+                $name::from_reduced(RU256::zero().sub_mod(&self.0, &Self::modulus()))
+            }
+        }
+    };
+}
+
+/// An element of the base field, integers mod p
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fp(RU256);
+
+/// A scalar, integers mod the group order n
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fn(RU256);
+
+residue!(Fp, SECP256K1::p());
+residue!(Fn, SECP256K1::n());
+
+impl Fp {
+    /// The scalar with the same integer value mod n, how ECDSA turns the x
+    /// coordinate of R into r
+    pub fn reduce_to_scalar(&self) -> Fn {
+        Fn::new(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let a = Fn::new(&RU256::from_u64(7));
+        let b = Fn::new(&RU256::from_u64(9));
+        assert_eq!(a.clone() + b.clone(), Fn::new(&RU256::from_u64(16)));
+        assert_eq!(a.clone() - b.clone() + b.clone(), a);
+        assert_eq!(-a.clone() + a.clone(), Fn::zero());
+        assert_eq!(a.clone() * a.inv().unwrap(), Fn::one());
+        assert_eq!(Fn::zero().inv(), None);
+
+        // p - 1 squared wraps around to 1
+        let minus_one = -Fp::one();
+        assert_eq!(minus_one.clone() * minus_one, Fp::one());
+    }
+
+    #[test]
+    fn test_reduce_to_scalar() {
+        // an x coordinate between n and p stays as is in the field and wraps
+        // as a scalar
+        let x = RU256::from_str_radix(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364143",
+            16,
+        )
+        .unwrap();
+        let x = Fp::from_canonical(&x).unwrap();
+        assert_eq!(x.reduce_to_scalar(), Fn::new(&RU256::from_u64(2)));
+        assert_eq!(Fn::from_canonical(x.as_ru256()), None);
+        assert_eq!(Fp::from_canonical(&SECP256K1::p()), None);
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        // p + 5 and 5 are the same residue and end up as the same value
+        let p = SECP256K1::p();
+        let big = RU256 { v: p.v + 5 };
+        assert_eq!(Fp::new(&big), Fp::new(&RU256::from_u64(5)));
+        assert_eq!(Fp::new(&big).as_ru256(), &RU256::from_u64(5));
+        assert_eq!(Fp::from_canonical(&big), None);
+        assert_eq!(
+            Fp::from_bytes(&[0xff; 32]),
+            Fp::new(&RU256::from_bytes(&[0xff; 32]))
+        );
+        assert!(*Fp::from_bytes(&[0xff; 32]).as_ru256() < p);
+    }
+}
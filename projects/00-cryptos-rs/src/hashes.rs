@@ -0,0 +1,125 @@
+use alloc::vec::Vec;
+
+use crate::ripemd160::ripemd160;
+use crate::sha256::sha256;
+
+// The hash combinations Bitcoin builds out of SHA-256 and RIPEMD-160, so every
+// module spells them the same way.
+
+/// RIPEMD-160 of SHA-256, used for P2PKH and P2WPKH key hashes and P2SH script
+/// hashes
+pub fn hash160(bytes: &[u8]) -> [u8; 20] {
+    ripemd160(&sha256(bytes.to_vec()))
+}
+
+/// Double SHA-256, used for txids, block ids and legacy signature hashes
+pub fn sha256d(bytes: &[u8]) -> [u8; 32] {
+    sha256(sha256(bytes.to_vec())).try_into().unwrap()
+}
+
+/// BIP340 tagged hash, sha256(sha256(tag) || sha256(tag) || msg), which keeps
+/// hashes from one context from being valid in another
+pub fn tagged(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256(tag.as_bytes().to_vec());
+    let mut data: Vec<u8> = tag_hash.clone();
+    data.extend(tag_hash);
+    data.extend(msg);
+    sha256(data).try_into().unwrap()
+}
+
+/// SHA-1, only for OP_SHA1. It's broken, collisions can be found in practice.
+pub fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    // a 1 bit, zeros to 56 bytes mod 64, then the length in bits
+    let mut data = bytes.to_vec();
+    data.push(0x80);
+    data.resize((bytes.len() + 9).next_multiple_of(64), 0);
+    let length = data.len();
+    data[length - 8..].copy_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in data.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &wi) in w.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(wi);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, word) in digest.chunks_exact_mut(4).zip(state) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash160() {
+        // the compressed generator point, whose hash is in countless test vectors
+        let g = hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+            .unwrap();
+        assert_eq!(
+            hex::encode(hash160(&g)),
+            "751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
+
+    #[test]
+    fn test_sha256d() {
+        assert_eq!(
+            hex::encode(sha256d(b"hello")),
+            "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50"
+        );
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            hex::encode(sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            hex::encode(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // 56 bytes, so the length goes in a second block
+        assert_eq!(
+            hex::encode(sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_tagged() {
+        assert_eq!(
+            hex::encode(tagged("TapTweak", b"")),
+            "8aa4229474ab0100b2d6f0687f031d1fc9d8eef92a042ad97d279bff456b15e4"
+        );
+    }
+}
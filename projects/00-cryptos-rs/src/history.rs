@@ -0,0 +1,292 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+// A wallet's event log for the accounting exercise: every payment in or out,
+// on chain or in ecash, with the fee it cost and who was on the other side.
+// Unlike the transaction history a chain scan recovers, it remembers what
+// the chain doesn't say, which mint a token came from or which invoice a melt
+// paid. Events are appended to a file as JSON lines so the log survives
+// restarts, and can be filtered and exported as CSV or JSON.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Bitcoin or ecash came in
+    Receive,
+    /// Bitcoin or ecash went out
+    Send,
+    /// Ecash issued against a paid lightning invoice
+    Mint,
+    /// Ecash redeemed to pay a lightning invoice
+    Melt,
+    /// Ecash exchanged for fresh ecash at the same mint
+    Swap,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Receive => "receive",
+            EventKind::Send => "send",
+            EventKind::Mint => "mint",
+            EventKind::Melt => "melt",
+            EventKind::Swap => "swap",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            EventKind::Receive,
+            EventKind::Send,
+            EventKind::Mint,
+            EventKind::Melt,
+            EventKind::Swap,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Unix seconds
+    pub time: u64,
+    pub kind: EventKind,
+    /// What was paid or received, without the fee
+    pub amount: u64,
+    /// `sat` on chain, whatever the keyset's unit is in ecash
+    pub unit: String,
+    pub fee: u64,
+    /// The address paid, the mint, or the invoice, if known
+    pub counterparty: Option<String>,
+    /// The txid, quote id or token the event is about
+    pub reference: String,
+}
+
+impl Event {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "time": self.time,
+            "kind": self.kind.as_str(),
+            "amount": self.amount,
+            "unit": self.unit,
+            "fee": self.fee,
+            "counterparty": self.counterparty,
+            "reference": self.reference,
+        })
+    }
+
+    pub fn from_json(json: &Value) -> Option<Self> {
+        let str_field = |key| json.get(key)?.as_str().map(str::to_string);
+        Some(Event {
+            time: json.get("time")?.as_u64()?,
+            kind: EventKind::from_name(json.get("kind")?.as_str()?)?,
+            amount: json.get("amount")?.as_u64()?,
+            unit: str_field("unit")?,
+            fee: json.get("fee")?.as_u64()?,
+            counterparty: str_field("counterparty"),
+            reference: str_field("reference")?,
+        })
+    }
+}
+
+/// Which events `EventLog::history` returns, everything by default
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    /// Only these kinds, any if empty
+    pub kinds: Vec<EventKind>,
+    pub unit: Option<String>,
+    /// From this time on, inclusive
+    pub since: Option<u64>,
+    /// Before this time
+    pub until: Option<u64>,
+    pub counterparty: Option<String>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+            && self.unit.as_ref().is_none_or(|unit| *unit == event.unit)
+            && self.since.is_none_or(|since| event.time >= since)
+            && self.until.is_none_or(|until| event.time < until)
+            && self
+                .counterparty
+                .as_ref()
+                .is_none_or(|counterparty| event.counterparty.as_ref() == Some(counterparty))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EventLog {
+    /// The file events are appended to, None for a log kept in memory
+    path: Option<PathBuf>,
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    /// A log kept in memory only
+    pub fn new() -> Self {
+        EventLog::default()
+    }
+
+    /// Load the log at `path`, empty if there's no file yet. Events recorded
+    /// later are appended to it.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut events = vec![];
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let event = serde_json::from_str(&line)
+                    .ok()
+                    .and_then(|json| Event::from_json(&json))
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, line))?;
+                events.push(event);
+            }
+        }
+        Ok(EventLog {
+            path: Some(path.to_path_buf()),
+            events,
+        })
+    }
+
+    /// Add an event, writing it out first if the log has a file
+    pub fn record(&mut self, event: Event) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", event.to_json())?;
+        }
+        self.events.push(event);
+        Ok(())
+    }
+
+    /// The events matching `filter`, oldest first
+    pub fn history(&self, filter: &HistoryFilter) -> Vec<&Event> {
+        let mut events: Vec<&Event> = self
+            .events
+            .iter()
+            .filter(|event| filter.matches(event))
+            .collect();
+        events.sort_by_key(|event| event.time);
+        events
+    }
+
+    /// Total fees paid in `unit`
+    pub fn fees(&self, unit: &str) -> u64 {
+        self.events
+            .iter()
+            .filter(|event| event.unit == unit)
+            .map(|event| event.fee)
+            .sum()
+    }
+}
+
+/// Quote a CSV field if it holds a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Events as CSV with a header row, for a spreadsheet
+pub fn to_csv(events: &[&Event]) -> String {
+    let mut csv = String::from("time,kind,amount,unit,fee,counterparty,reference\n");
+    for event in events {
+        let fields = [
+            event.time.to_string(),
+            event.kind.as_str().to_string(),
+            event.amount.to_string(),
+            csv_field(&event.unit),
+            event.fee.to_string(),
+            csv_field(event.counterparty.as_deref().unwrap_or("")),
+            csv_field(&event.reference),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Events as a JSON array
+pub fn to_json(events: &[&Event]) -> Value {
+    Value::Array(events.iter().map(|event| event.to_json()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time: u64, kind: EventKind, amount: u64, fee: u64, counterparty: &str) -> Event {
+        Event {
+            time,
+            kind,
+            amount,
+            unit: "sat".to_string(),
+            fee,
+            counterparty: Some(counterparty.to_string()),
+            reference: format!("ref{}", time),
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let path = std::env::temp_dir().join(format!("events-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut log = EventLog::open(&path).unwrap();
+        log.record(event(300, EventKind::Melt, 1000, 3, "lnbc10u1p"))
+            .unwrap();
+        log.record(event(100, EventKind::Receive, 50_000, 0, "bc1qsender"))
+            .unwrap();
+        log.record(event(
+            200,
+            EventKind::Mint,
+            20_000,
+            0,
+            "https://mint.example.com",
+        ))
+        .unwrap();
+        log.record(Event {
+            unit: "usd".to_string(),
+            ..event(400, EventKind::Swap, 500, 1, "https://mint.example.com")
+        })
+        .unwrap();
+
+        // the log survives a restart
+        let log = EventLog::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let all = log.history(&HistoryFilter::default());
+        assert_eq!(
+            all.iter().map(|event| event.time).collect::<Vec<_>>(),
+            [100, 200, 300, 400]
+        );
+        assert_eq!(log.fees("sat"), 3);
+
+        let ecash = HistoryFilter {
+            kinds: vec![EventKind::Mint, EventKind::Melt, EventKind::Swap],
+            unit: Some("sat".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(log.history(&ecash).len(), 2);
+        let mint = HistoryFilter {
+            counterparty: Some("https://mint.example.com".to_string()),
+            since: Some(200),
+            until: Some(400),
+            ..Default::default()
+        };
+        assert_eq!(log.history(&mint), [all[1]]);
+
+        assert_eq!(to_json(&all[..1]), json!([all[0].to_json()]));
+        assert_eq!(
+            to_csv(&all[..2]),
+            "time,kind,amount,unit,fee,counterparty,reference\n\
+             100,receive,50000,sat,0,bc1qsender,ref100\n\
+             200,mint,20000,sat,0,https://mint.example.com,ref200\n"
+        );
+        assert_eq!(csv_field("a \"quoted\", b"), "\"a \"\"quoted\"\", b\"");
+    }
+}
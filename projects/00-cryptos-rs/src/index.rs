@@ -0,0 +1,390 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::amount::Amount;
+use crate::block::Block;
+use crate::encoding::{Encodable, TryDecodable};
+use crate::keys::address_to_pkb_hash;
+use crate::sha256::{hash256, sha256};
+use crate::transaction::{Script, Tx};
+use crate::utils;
+
+// A block explorer style indexer: blocks are scanned (from bitcoind's blk*.dat
+// files or handed over directly, e.g. after fetching them over RPC) and every
+// output and spend is recorded in sled trees so we can answer balance and
+// history queries for an address without rescanning the chain.
+
+/// Network magic that prefixes every block in a blk*.dat file
+pub fn network_magic(net: &str) -> [u8; 4] {
+    match net {
+        "main" => [0xf9, 0xbe, 0xb4, 0xd9],
+        "test" => [0x0b, 0x11, 0x09, 0x07],
+        _ => panic!("{} is not a valid net type, should be main|test", net),
+    }
+}
+
+/// Read all blocks stored in a bitcoind blk*.dat file
+pub fn read_block_file<P: AsRef<Path>>(path: P, net: &str) -> io::Result<Vec<Block>> {
+    let mut file = File::open(path)?;
+    let magic = network_magic(net);
+    let mut blocks = vec![];
+    loop {
+        let mut block_magic = [0u8; 4];
+        match file.read_exact(&mut block_magic) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        // bitcoind preallocates block files, the unused tail is all zeros
+        if block_magic == [0u8; 4] {
+            break;
+        }
+        if block_magic != magic {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected network magic {}", hex::encode(block_magic)),
+            ));
+        }
+        let size = utils::read_u32(&mut file)? as usize;
+        let mut raw = vec![0; size];
+        file.read_exact(&mut raw)?;
+        let block = Block::try_decode_all(&raw)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+/// Outpoint key: the txid in internal byte order followed by the output index
+fn outpoint_key(txid: &[u8], vout: u32) -> Vec<u8> {
+    let mut key = txid.to_vec();
+    key.extend(vout.to_le_bytes());
+    key
+}
+
+/// Index keys are grouped per script by prefixing with the script hash
+fn script_key(script_pubkey: &Script) -> Vec<u8> {
+    sha256(script_pubkey.encode())
+}
+
+fn display_txid(txid: &[u8]) -> String {
+    let mut txid = txid.to_vec();
+    txid.reverse();
+    hex::encode(txid)
+}
+
+/// An unspent output found in the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utxo {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: Amount,
+}
+
+pub struct Index {
+    /// outpoint -> amount || encoded script_pubkey
+    outputs: sled::Tree,
+    /// outpoint -> txid of the transaction spending it
+    spends: sled::Tree,
+    /// script hash || outpoint -> amount
+    script_outputs: sled::Tree,
+    /// script hash || height || position in block -> txid
+    script_history: sled::Tree,
+}
+
+impl Index {
+    /// Open (or create) an index stored at the given path
+    pub fn open<P: AsRef<Path>>(path: P) -> sled::Result<Self> {
+        Self::from_db(sled::open(path)?)
+    }
+
+    /// An index that is thrown away when dropped, handy for tests and demos
+    pub fn temporary() -> sled::Result<Self> {
+        Self::from_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    fn from_db(db: sled::Db) -> sled::Result<Self> {
+        Ok(Index {
+            outputs: db.open_tree("outputs")?,
+            spends: db.open_tree("spends")?,
+            script_outputs: db.open_tree("script_outputs")?,
+            script_history: db.open_tree("script_history")?,
+        })
+    }
+
+    /// Index all transactions of a block. Blocks must be indexed in chain order
+    /// so the outputs being spent are already known.
+    pub fn index_block(&self, block: &Block, height: u32) -> sled::Result<()> {
+        for (position, tx) in block.txs.iter().enumerate() {
+            self.index_tx(tx, height, position as u32)?;
+        }
+        Ok(())
+    }
+
+    fn index_tx(&self, tx: &Tx, height: u32, position: u32) -> sled::Result<()> {
+        let txid = hash256(tx.encode_legacy());
+
+        let mut history_suffix = height.to_be_bytes().to_vec();
+        history_suffix.extend(position.to_be_bytes());
+
+        if !tx.is_coinbase() {
+            for tx_in in &tx.tx_ins {
+                let outpoint = outpoint_key(&tx_in.prev_tx, tx_in.prev_index);
+                self.spends.insert(&outpoint, txid.as_slice())?;
+                // credit the spend to the history of the script that was spent
+                if let Some(output) = self.outputs.get(&outpoint)? {
+                    let mut key = sha256(output[8..].to_vec());
+                    key.extend(&history_suffix);
+                    self.script_history.insert(key, txid.as_slice())?;
+                }
+            }
+        }
+
+        for (vout, tx_out) in tx.tx_outs.iter().enumerate() {
+            let outpoint = outpoint_key(&txid, vout as u32);
+            let amount = tx_out.amount.to_sat().to_le_bytes();
+
+            let mut output = amount.to_vec();
+            output.extend(tx_out.script_pubkey.encode());
+            self.outputs.insert(&outpoint, output)?;
+
+            let mut key = script_key(&tx_out.script_pubkey);
+            key.extend(&outpoint);
+            self.script_outputs.insert(key, &amount)?;
+
+            let mut key = script_key(&tx_out.script_pubkey);
+            key.extend(&history_suffix);
+            self.script_history.insert(key, txid.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    /// All unspent outputs locked to the script
+    pub fn unspent_for_script(&self, script_pubkey: &Script) -> sled::Result<Vec<Utxo>> {
+        let prefix = script_key(script_pubkey);
+        let mut utxos = vec![];
+        for entry in self.script_outputs.scan_prefix(&prefix) {
+            let (key, amount) = entry?;
+            let outpoint = &key[prefix.len()..];
+            if !self.spends.contains_key(outpoint)? {
+                utxos.push(Utxo {
+                    txid: display_txid(&outpoint[..32]),
+                    vout: u32::from_le_bytes(outpoint[32..].try_into().unwrap()),
+                    amount: Amount::from_sat(u64::from_le_bytes(
+                        amount.as_ref().try_into().unwrap(),
+                    )),
+                });
+            }
+        }
+        Ok(utxos)
+    }
+
+    /// Sum of all unspent outputs locked to the script
+    pub fn balance_for_script(&self, script_pubkey: &Script) -> sled::Result<Amount> {
+        Ok(self
+            .unspent_for_script(script_pubkey)?
+            .iter()
+            .map(|utxo| utxo.amount)
+            .sum())
+    }
+
+    /// Ids of all transactions paying to or spending from the script, in chain
+    /// order
+    pub fn history_for_script(&self, script_pubkey: &Script) -> sled::Result<Vec<String>> {
+        let mut txids: Vec<String> = vec![];
+        for entry in self.script_history.scan_prefix(script_key(script_pubkey)) {
+            let (_, txid) = entry?;
+            let txid = display_txid(&txid);
+            // a tx both spending from and paying to the script shows up once
+            if txids.last() != Some(&txid) {
+                txids.push(txid);
+            }
+        }
+        Ok(txids)
+    }
+
+    /// Balance of a P2PKH address
+    pub fn balance(&self, address: &str) -> sled::Result<Amount> {
+        self.balance_for_script(&Script::p2pkh(&address_to_pkb_hash(address)))
+    }
+
+    /// Transaction history of a P2PKH address
+    pub fn history(&self, address: &str) -> sled::Result<Vec<String>> {
+        self.history_for_script(&Script::p2pkh(&address_to_pkb_hash(address)))
+    }
+
+    /// Id of the transaction spending the given output, if it was spent
+    pub fn spent_by(&self, txid: &str, vout: u32) -> sled::Result<Option<String>> {
+        let mut txid = hex::decode(txid).unwrap();
+        txid.reverse();
+        Ok(self
+            .spends
+            .get(outpoint_key(&txid, vout))?
+            .map(|spender| display_txid(&spender)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::keys::pkb_hash_to_address;
+    use crate::transaction::{Cmd, TxIn, TxOut};
+
+    fn coinbase(height: u32, script_pubkey: Script, amount: Amount) -> Tx {
+        Tx {
+            version: 1,
+            tx_ins: vec![TxIn {
+                prev_tx: vec![0; 32],
+                prev_index: 0xffffffff,
+                script_sig: Script::new(vec![Cmd::push(&height.to_le_bytes())]),
+                sequence: 0xffffffff,
+                ..Default::default()
+            }],
+            tx_outs: vec![TxOut {
+                amount,
+                script_pubkey,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn block(prev_block: Vec<u8>, txs: Vec<Tx>) -> Block {
+        Block {
+            version: 1,
+            prev_block,
+            merkle_root: vec![0; 32],
+            timestamp: 1231006505,
+            bits: vec![0xff, 0xff, 0x00, 0x1d],
+            nonce: vec![0; 4],
+            txs,
+        }
+    }
+
+    /// Two blocks: alice mines 50 BTC, then pays 30 BTC to bob
+    fn test_chain() -> (Vec<Block>, String, String) {
+        let alice = Script::p2pkh(&[0xaa; 20]);
+        let bob = Script::p2pkh(&[0xbb; 20]);
+
+        let tx1 = coinbase(1, alice.clone(), Amount::from_sat(50_0000_0000));
+        let tx2 = Tx {
+            version: 1,
+            tx_ins: vec![TxIn {
+                prev_tx: hash256(tx1.encode_legacy()),
+                prev_index: 0,
+                sequence: 0xffffffff,
+                ..Default::default()
+            }],
+            tx_outs: vec![
+                TxOut {
+                    amount: Amount::from_sat(30_0000_0000),
+                    script_pubkey: bob,
+                },
+                TxOut {
+                    amount: Amount::from_sat(20_0000_0000),
+                    script_pubkey: alice,
+                },
+            ],
+            ..Default::default()
+        };
+        let tx1_id = tx1.id();
+        let tx2_id = tx2.id();
+
+        let block1 = block(vec![0; 32], vec![tx1]);
+        let prev_block = hex::decode(block1.id()).unwrap();
+        let block2 = block(
+            prev_block,
+            vec![coinbase(2, Script::p2pkh(&[0xcc; 20]), Amount::ZERO), tx2],
+        );
+
+        (vec![block1, block2], tx1_id, tx2_id)
+    }
+
+    #[test]
+    fn test_balance_and_history() {
+        let (blocks, tx1_id, tx2_id) = test_chain();
+        let index = Index::temporary().unwrap();
+        for (height, block) in blocks.iter().enumerate() {
+            index.index_block(block, height as u32 + 1).unwrap();
+        }
+
+        let alice = pkb_hash_to_address(&[0xaa; 20], "main");
+        let bob = pkb_hash_to_address(&[0xbb; 20], "main");
+
+        assert_eq!(
+            index.balance(&alice).unwrap(),
+            Amount::from_sat(20_0000_0000)
+        );
+        assert_eq!(index.balance(&bob).unwrap(), Amount::from_sat(30_0000_0000));
+        assert_eq!(
+            index.history(&alice).unwrap(),
+            vec![tx1_id.clone(), tx2_id.clone()]
+        );
+        assert_eq!(index.history(&bob).unwrap(), vec![tx2_id.clone()]);
+
+        assert_eq!(
+            index
+                .unspent_for_script(&Script::p2pkh(&[0xaa; 20]))
+                .unwrap(),
+            vec![Utxo {
+                txid: tx2_id.clone(),
+                vout: 1,
+                amount: Amount::from_sat(20_0000_0000),
+            }]
+        );
+
+        assert_eq!(index.spent_by(&tx1_id, 0).unwrap(), Some(tx2_id.clone()));
+        assert_eq!(index.spent_by(&tx2_id, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_block_file() {
+        let (blocks, _, _) = test_chain();
+
+        let path = std::env::temp_dir().join(format!("blk-test-{}.dat", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for block in &blocks {
+            let raw = block.encode();
+            file.write_all(&network_magic("main")).unwrap();
+            file.write_all(&(raw.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(&raw).unwrap();
+        }
+        // preallocated, unused space at the end of the file
+        file.write_all(&[0u8; 64]).unwrap();
+        drop(file);
+
+        let read = read_block_file(&path, "main").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.len(), blocks.len());
+        for (a, b) in read.iter().zip(blocks.iter()) {
+            assert_eq!(a.id(), b.id());
+            assert_eq!(a.txs.len(), b.txs.len());
+            for (tx_a, tx_b) in a.txs.iter().zip(b.txs.iter()) {
+                assert_eq!(tx_a.id(), tx_b.id());
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_truncated_block() {
+        let (blocks, _, _) = test_chain();
+        let raw = blocks[0].encode();
+
+        // a record whose size covers only part of the block
+        let path = std::env::temp_dir().join(format!("blk-truncated-{}.dat", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&network_magic("main")).unwrap();
+        file.write_all(&(raw.len() as u32 - 10).to_le_bytes())
+            .unwrap();
+        file.write_all(&raw[..raw.len() - 10]).unwrap();
+        drop(file);
+
+        let error = read_block_file(&path, "main").unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,1821 @@
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::amount::Amount;
+use crate::hashes::{hash160, sha1};
+use crate::keys::PublicKey;
+use crate::ripemd160::ripemd160;
+use crate::sha256::{hash256, sha256};
+use crate::signature::{verify_ecdsa, Signature};
+use crate::transaction::{
+    opcode_name, script_num, Cmd, Script, Tx, TxOut, LOCKTIME_THRESHOLD, SIGHASH_ALL,
+};
+
+// A stack machine running scripts the way Bitcoin Core's EvalScript does, for
+// the legacy (pre-segwit) rules. Data is kept as byte vectors and numbers are
+// CScriptNums: little endian with the sign in the top bit of the last byte, so
+// there's a negative zero (0x80) and numbers are at most 4 bytes going in to
+// arithmetic, though results can grow to 5 and only fail when used again.
+// Numbers have to be minimally encoded, which is policy in Core (consensus
+// accepts padded ones), and every number is also a boolean: false is any
+// encoding of zero, including negative zero.
+//
+// Segwit outputs (BIP141) are checked against the witness instead: version 0
+// programs are P2WPKH or P2WSH by their length and anything else is invalid,
+// version 1 is taproot, whose structure is checked but not its signatures,
+// and every other version is anyone can spend. That's what lets soft forks
+// define new versions: old nodes accept any spend of them. Nodes won't relay
+// such spends though (see policy), so nobody loses coins to an upgrade by
+// spending them early. Version 0 signatures commit to the BIP143 message,
+// which includes the amount spent, so checking them takes the spending
+// transaction and amount rather than a precomputed message.
+//
+// Runs can be traced, recording the stacks after every command to walk
+// through how a script evaluates.
+
+const OP_0: u8 = 0x00;
+const OP_1NEGATE: u8 = 0x4f;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_NOP: u8 = 0x61;
+const OP_IF: u8 = 0x63;
+const OP_NOTIF: u8 = 0x64;
+const OP_VERIF: u8 = 0x65;
+const OP_VERNOTIF: u8 = 0x66;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_VERIFY: u8 = 0x69;
+const OP_RETURN: u8 = 0x6a;
+const OP_TOALTSTACK: u8 = 0x6b;
+const OP_FROMALTSTACK: u8 = 0x6c;
+const OP_2DROP: u8 = 0x6d;
+const OP_2DUP: u8 = 0x6e;
+const OP_3DUP: u8 = 0x6f;
+const OP_2OVER: u8 = 0x70;
+const OP_2ROT: u8 = 0x71;
+const OP_2SWAP: u8 = 0x72;
+const OP_IFDUP: u8 = 0x73;
+const OP_DEPTH: u8 = 0x74;
+const OP_DROP: u8 = 0x75;
+const OP_DUP: u8 = 0x76;
+const OP_NIP: u8 = 0x77;
+const OP_OVER: u8 = 0x78;
+const OP_PICK: u8 = 0x79;
+const OP_ROLL: u8 = 0x7a;
+const OP_ROT: u8 = 0x7b;
+const OP_SWAP: u8 = 0x7c;
+const OP_TUCK: u8 = 0x7d;
+const OP_CAT: u8 = 0x7e;
+const OP_RIGHT: u8 = 0x81;
+const OP_SIZE: u8 = 0x82;
+const OP_INVERT: u8 = 0x83;
+const OP_XOR: u8 = 0x86;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_1ADD: u8 = 0x8b;
+const OP_1SUB: u8 = 0x8c;
+const OP_2MUL: u8 = 0x8d;
+const OP_2DIV: u8 = 0x8e;
+const OP_NEGATE: u8 = 0x8f;
+const OP_ABS: u8 = 0x90;
+const OP_NOT: u8 = 0x91;
+const OP_0NOTEQUAL: u8 = 0x92;
+const OP_ADD: u8 = 0x93;
+const OP_SUB: u8 = 0x94;
+const OP_MUL: u8 = 0x95;
+const OP_RSHIFT: u8 = 0x99;
+const OP_BOOLAND: u8 = 0x9a;
+const OP_BOOLOR: u8 = 0x9b;
+const OP_NUMEQUAL: u8 = 0x9c;
+const OP_NUMEQUALVERIFY: u8 = 0x9d;
+const OP_NUMNOTEQUAL: u8 = 0x9e;
+const OP_LESSTHAN: u8 = 0x9f;
+const OP_GREATERTHAN: u8 = 0xa0;
+const OP_LESSTHANOREQUAL: u8 = 0xa1;
+const OP_GREATERTHANOREQUAL: u8 = 0xa2;
+const OP_MIN: u8 = 0xa3;
+const OP_MAX: u8 = 0xa4;
+const OP_WITHIN: u8 = 0xa5;
+const OP_RIPEMD160: u8 = 0xa6;
+const OP_SHA1: u8 = 0xa7;
+const OP_SHA256: u8 = 0xa8;
+const OP_HASH160: u8 = 0xa9;
+const OP_HASH256: u8 = 0xaa;
+const OP_CODESEPARATOR: u8 = 0xab;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKSIGVERIFY: u8 = 0xad;
+const OP_CHECKMULTISIG: u8 = 0xae;
+const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+const OP_NOP1: u8 = 0xb0;
+const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+const OP_NOP4: u8 = 0xb3;
+const OP_NOP10: u8 = 0xb9;
+
+/// Set in a relative locktime, it has no meaning (BIP68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+/// Set in a relative locktime, it's in units of 512 seconds, otherwise blocks
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: i64 = 0xffff;
+
+/// Largest element that can be pushed on the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Most elements the stack and altstack can hold together
+pub const MAX_STACK_SIZE: usize = 1000;
+/// Most keys in a CHECKMULTISIG
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+/// Most non-push opcodes in one script, each CHECKMULTISIG key counting as one
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Largest script that can be run, in serialized bytes
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    /// Popped from an empty stack or altstack
+    InvalidStackOperation,
+    /// More than MAX_STACK_SIZE elements
+    StackSize,
+    /// A script over MAX_SCRIPT_SIZE bytes
+    ScriptSize,
+    /// More than MAX_OPS_PER_SCRIPT opcodes
+    OpCount,
+    /// A push bigger than MAX_SCRIPT_ELEMENT_SIZE
+    PushSize,
+    /// A number operand longer than 4 bytes (5 for locktimes)
+    NumOverflow,
+    /// A number with padding it doesn't need
+    MinimalData,
+    /// An ELSE or ENDIF without IF, or an IF without ENDIF
+    UnbalancedConditional,
+    /// The check of a VERIFY opcode failed
+    Verify(u8),
+    OpReturn,
+    /// Disabled since 2010, fails even in an unexecuted branch
+    DisabledOpcode(u8),
+    /// Not assigned or not implemented here
+    BadOpcode(u8),
+    PubkeyCount,
+    SigCount,
+    /// The extra element CHECKMULTISIG pops isn't empty
+    SigNullDummy,
+    NegativeLocktime,
+    UnsatisfiedLocktime,
+    /// The script finished with an empty stack or false on top
+    EvalFalse,
+    /// A segwit script finished with more than one element on the stack
+    CleanStack,
+    /// A script ends in the middle of a push
+    TruncatedPush,
+    /// A P2SH scriptSig with more than pushes in it
+    SigPushOnly,
+    /// A native segwit spend with a scriptSig
+    WitnessMalleated,
+    /// A nested segwit spend whose scriptSig isn't just the redeem script
+    WitnessMalleatedP2sh,
+    /// A witness on an input that isn't segwit
+    WitnessUnexpected,
+    WitnessProgramWitnessEmpty,
+    /// The witness doesn't fit the program, e.g. a script with the wrong hash
+    WitnessProgramMismatch,
+    /// A version 0 program that is neither 20 nor 32 bytes
+    WitnessProgramWrongLength,
+    SchnorrSigSize,
+    TaprootWrongControlSize,
+    /// A taproot spend that is well formed, but can't be checked without
+    /// BIP340 signatures and BIP341 commitments, which the crate lacks
+    TaprootUnsupported,
+    /// A segwit v0 signature check with no spending transaction and amount
+    /// in the checker to build its BIP143 message from
+    WitnessSpendMissing,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::InvalidStackOperation => write!(f, "operation on an empty stack"),
+            ScriptError::StackSize => write!(f, "stack size limit exceeded"),
+            ScriptError::ScriptSize => write!(f, "script is too big"),
+            ScriptError::OpCount => write!(f, "operation limit exceeded"),
+            ScriptError::PushSize => write!(f, "push size limit exceeded"),
+            ScriptError::NumOverflow => write!(f, "script number overflow"),
+            ScriptError::MinimalData => write!(f, "non-minimally encoded script number"),
+            ScriptError::UnbalancedConditional => write!(f, "unbalanced conditional"),
+            ScriptError::Verify(op) => write!(f, "{} failed", opcode_name(*op)),
+            ScriptError::OpReturn => write!(f, "OP_RETURN was encountered"),
+            ScriptError::DisabledOpcode(op) => write!(f, "disabled opcode {}", opcode_name(*op)),
+            ScriptError::BadOpcode(op) => write!(f, "bad opcode 0x{:02x}", op),
+            ScriptError::PubkeyCount => write!(f, "pubkey count out of range"),
+            ScriptError::SigCount => write!(f, "signature count out of range"),
+            ScriptError::SigNullDummy => write!(f, "CHECKMULTISIG dummy must be empty"),
+            ScriptError::NegativeLocktime => write!(f, "negative locktime"),
+            ScriptError::UnsatisfiedLocktime => write!(f, "locktime requirement not satisfied"),
+            ScriptError::EvalFalse => write!(f, "script evaluated to false"),
+            ScriptError::CleanStack => write!(f, "stack not clean after evaluation"),
+            ScriptError::TruncatedPush => write!(f, "push past the end of the script"),
+            ScriptError::SigPushOnly => write!(f, "only pushes allowed in the scriptSig"),
+            ScriptError::WitnessMalleated => write!(f, "witness requires empty scriptSig"),
+            ScriptError::WitnessMalleatedP2sh => {
+                write!(f, "witness requires only-redeemscript scriptSig")
+            }
+            ScriptError::WitnessUnexpected => write!(f, "witness provided for non-witness script"),
+            ScriptError::WitnessProgramWitnessEmpty => {
+                write!(f, "witness program was passed an empty witness")
+            }
+            ScriptError::WitnessProgramMismatch => write!(f, "witness program hash mismatch"),
+            ScriptError::WitnessProgramWrongLength => {
+                write!(f, "witness program has incorrect length")
+            }
+            ScriptError::SchnorrSigSize => write!(f, "invalid Schnorr signature size"),
+            ScriptError::TaprootWrongControlSize => write!(f, "invalid taproot control block size"),
+            ScriptError::TaprootUnsupported => write!(f, "taproot spends can't be checked yet"),
+            ScriptError::WitnessSpendMissing => {
+                write!(
+                    f,
+                    "segwit signatures need the spending transaction and amount"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Parse a CScriptNum of at most `max_size` bytes
+pub fn decode_num(data: &[u8], max_size: usize) -> Result<i64, ScriptError> {
+    if data.len() > max_size {
+        return Err(ScriptError::NumOverflow);
+    }
+    // the last byte may only be 0x00 or 0x80 to make room for the sign bit
+    // of the byte before it
+    if let Some((&last, rest)) = data.split_last() {
+        if last & 0x7f == 0 && rest.last().is_none_or(|byte| byte & 0x80 == 0) {
+            return Err(ScriptError::MinimalData);
+        }
+    }
+    Ok(script_num(data))
+}
+
+/// The minimal CScriptNum encoding of `value`, empty for zero
+pub fn encode_num(value: i64) -> Vec<u8> {
+    let mut abs = value.unsigned_abs();
+    let mut result = vec![];
+    while abs > 0 {
+        result.push(abs as u8);
+        abs >>= 8;
+    }
+    // a sign bit that doesn't fit in the top byte gets a byte of its own
+    match result.last_mut() {
+        Some(last) if *last & 0x80 != 0 => result.push(if value < 0 { 0x80 } else { 0x00 }),
+        Some(last) if value < 0 => *last |= 0x80,
+        _ => {}
+    }
+    result
+}
+
+/// Whether a stack element counts as true: anything but zero and negative zero
+pub fn cast_to_bool(data: &[u8]) -> bool {
+    match data.split_last() {
+        Some((&last, rest)) => rest.iter().any(|&byte| byte != 0) || last & 0x7f != 0,
+        None => false,
+    }
+}
+
+fn bool_element(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+/// What signature and locktime opcodes check against, from the transaction
+/// spending the script
+#[derive(Debug, Clone, Default)]
+pub struct Checker {
+    /// The input's SIGHASH_ALL message, from Tx::sig_message
+    pub sig_message: Vec<u8>,
+    /// The transaction's version, relative locktimes need 2 or more
+    pub version: u32,
+    pub locktime: u32,
+    /// The input's sequence
+    pub sequence: u32,
+    /// The spending transaction, the input's index and the amount it spends,
+    /// for segwit v0 signatures, whose message is built per script
+    pub spend: Option<(Tx, usize, Amount)>,
+    /// The script a segwit v0 script's signatures commit to, while it runs
+    script_code: Option<Script>,
+}
+
+impl Checker {
+    /// The checker for input `input` of `tx`, which spends `prevout`. Legacy
+    /// signatures are checked against the prevout's script, so not those of
+    /// P2SH redeem scripts.
+    pub fn for_input(tx: &Tx, input: usize, prevout: &TxOut) -> Self {
+        Checker {
+            sig_message: tx.sig_message(input, &prevout.script_pubkey),
+            version: tx.version,
+            locktime: tx.locktime,
+            sequence: tx.tx_ins[input].sequence,
+            spend: Some((tx.clone(), input, prevout.amount)),
+            script_code: None,
+        }
+    }
+
+    /// The message signatures are checked against, Tx::segwit_sig_message
+    /// when a segwit v0 script is running. Its script code starts after the
+    /// last OP_CODESEPARATOR run, `separated`. Legacy messages are given, so
+    /// they always cover the whole prevout script.
+    fn message(&self, separated: Option<&Script>) -> Result<Vec<u8>, ScriptError> {
+        let Some(script_code) = &self.script_code else {
+            return Ok(self.sig_message.clone());
+        };
+        let script_code = separated.unwrap_or(script_code);
+        let (tx, input, amount) = self
+            .spend
+            .as_ref()
+            .ok_or(ScriptError::WitnessSpendMissing)?;
+        Ok(tx.segwit_sig_message(*input, script_code, *amount))
+    }
+
+    fn check_sig(&self, message: &[u8], sig: &[u8], pubkey: &[u8]) -> bool {
+        let Some((&sighash_type, der)) = sig.split_last() else {
+            return false;
+        };
+        if sighash_type != SIGHASH_ALL {
+            return false;
+        }
+        let (Ok(sig), Some(pubkey)) = (Signature::from_der(der), PublicKey::try_from_bytes(pubkey))
+        else {
+            return false;
+        };
+        verify_ecdsa(&pubkey, message, &sig)
+    }
+
+    fn check_locktime(&self, locktime: i64) -> bool {
+        let tx_locktime = self.locktime as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        // heights can't be compared with times
+        (locktime < threshold) == (tx_locktime < threshold)
+            && locktime <= tx_locktime
+            // a final input would disable the transaction's locktime
+            && self.sequence != 0xffffffff
+    }
+
+    /// BIP112, whether the input's relative locktime is at least `sequence`
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.sequence as i64;
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let (sequence, tx_sequence_masked) = (sequence & mask, tx_sequence & mask);
+        // the input has to be relative locked, in the same units
+        self.version >= 2
+            && tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+            && (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG)
+                == (tx_sequence_masked < SEQUENCE_LOCKTIME_TYPE_FLAG)
+            && sequence <= tx_sequence_masked
+    }
+}
+
+/// The state of a running script
+#[derive(Debug, Clone, Default)]
+pub struct Interpreter {
+    pub stack: Vec<Vec<u8>>,
+    pub alt_stack: Vec<Vec<u8>>,
+    /// For each IF being executed, whether its current branch runs
+    exec: Vec<bool>,
+    /// Non-push opcodes seen in the current script, run or not
+    op_count: usize,
+    /// The current script after its last OP_CODESEPARATOR run
+    separated: Option<Script>,
+}
+
+impl Interpreter {
+    pub fn new(stack: Vec<Vec<u8>>) -> Self {
+        Interpreter {
+            stack,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the current branch runs, false inside any skipped branch
+    pub fn executing(&self) -> bool {
+        self.exec.iter().all(|&exec| exec)
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::InvalidStackOperation)
+    }
+
+    fn pop_num(&mut self) -> Result<i64, ScriptError> {
+        decode_num(&self.pop()?, 4)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, ScriptError> {
+        Ok(cast_to_bool(&self.pop()?))
+    }
+
+    /// The element `depth` from the top, 0 being the top
+    fn peek(&self, depth: usize) -> Result<&Vec<u8>, ScriptError> {
+        self.stack
+            .len()
+            .checked_sub(depth + 1)
+            .map(|i| &self.stack[i])
+            .ok_or(ScriptError::InvalidStackOperation)
+    }
+
+    /// Run every command of `script` in turn, an IF left open is an error
+    pub fn execute(&mut self, script: &Script, checker: &Checker) -> Result<(), ScriptError> {
+        self.run(script, checker, None)
+    }
+
+    fn run(
+        &mut self,
+        script: &Script,
+        checker: &Checker,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<(), ScriptError> {
+        if script.as_bytes().len() > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::ScriptSize);
+        }
+        self.op_count = 0;
+        self.separated = None;
+        let mut instructions = script.instructions();
+        while let Some(cmd) = instructions.next() {
+            // like Core, a truncated push is only an error once it's reached
+            let cmd = cmd.map_err(|_| ScriptError::TruncatedPush)?;
+            let executed =
+                self.executing() || matches!(cmd, Cmd::Op(OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF));
+            self.step(&cmd, checker)?;
+            if executed && cmd == Cmd::Op(OP_CODESEPARATOR) {
+                self.separated = Some(Script::from_bytes(instructions.as_bytes()));
+            }
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.steps.push(TraceStep {
+                    cmd: cmd.clone(),
+                    executed,
+                    stack: self.stack.clone(),
+                    alt_stack: self.alt_stack.clone(),
+                });
+            }
+        }
+        if !self.exec.is_empty() {
+            return Err(ScriptError::UnbalancedConditional);
+        }
+        Ok(())
+    }
+
+    /// Run one command
+    pub fn step(&mut self, cmd: &Cmd, checker: &Checker) -> Result<(), ScriptError> {
+        let executing = self.executing();
+        match cmd {
+            Cmd::Push(data) if data.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                return Err(ScriptError::PushSize)
+            }
+            Cmd::Push(data) => {
+                if executing {
+                    self.stack.push(data.clone());
+                }
+            }
+            Cmd::Op(op) => {
+                // pushes by opcode (OP_0, OP_1NEGATE to OP_16) are free
+                if *op > OP_16 {
+                    self.count_ops(1)?;
+                }
+                self.op(*op, executing, checker)?
+            }
+        }
+        if self.stack.len() + self.alt_stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+        Ok(())
+    }
+
+    fn count_ops(&mut self, n: usize) -> Result<(), ScriptError> {
+        self.op_count += n;
+        if self.op_count > MAX_OPS_PER_SCRIPT {
+            return Err(ScriptError::OpCount);
+        }
+        Ok(())
+    }
+
+    fn op(&mut self, op: u8, executing: bool, checker: &Checker) -> Result<(), ScriptError> {
+        if matches!(
+            op,
+            OP_CAT..=OP_RIGHT | OP_INVERT..=OP_XOR | OP_2MUL | OP_2DIV | OP_MUL..=OP_RSHIFT
+        ) {
+            return Err(ScriptError::DisabledOpcode(op));
+        }
+        if matches!(op, OP_VERIF | OP_VERNOTIF) {
+            return Err(ScriptError::BadOpcode(op));
+        }
+        // conditionals are tracked in skipped branches too
+        if !executing && !matches!(op, OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF) {
+            return Ok(());
+        }
+
+        match op {
+            OP_0 => self.stack.push(vec![]),
+            OP_1NEGATE => self.stack.push(encode_num(-1)),
+            OP_1..=OP_16 => self.stack.push(encode_num((op - OP_1 + 1) as i64)),
+            OP_NOP | OP_NOP1 | OP_NOP4..=OP_NOP10 => {}
+
+            OP_IF | OP_NOTIF => {
+                let mut branch = false;
+                if executing {
+                    branch = self.pop_bool()? == (op == OP_IF);
+                }
+                self.exec.push(branch);
+            }
+            OP_ELSE => {
+                let exec = self
+                    .exec
+                    .last_mut()
+                    .ok_or(ScriptError::UnbalancedConditional)?;
+                *exec = !*exec;
+            }
+            OP_ENDIF => {
+                self.exec.pop().ok_or(ScriptError::UnbalancedConditional)?;
+            }
+            OP_VERIFY => {
+                if !self.pop_bool()? {
+                    return Err(ScriptError::Verify(op));
+                }
+            }
+            OP_RETURN => return Err(ScriptError::OpReturn),
+
+            OP_TOALTSTACK => {
+                let top = self.pop()?;
+                self.alt_stack.push(top);
+            }
+            OP_FROMALTSTACK => {
+                let top = self
+                    .alt_stack
+                    .pop()
+                    .ok_or(ScriptError::InvalidStackOperation)?;
+                self.stack.push(top);
+            }
+            OP_2DROP => {
+                self.pop()?;
+                self.pop()?;
+            }
+            OP_2DUP => {
+                let (a, b) = (self.peek(1)?.clone(), self.peek(0)?.clone());
+                self.stack.extend([a, b]);
+            }
+            OP_3DUP => {
+                self.peek(2)?;
+                let top = self.stack[self.stack.len() - 3..].to_vec();
+                self.stack.extend(top);
+            }
+            OP_2OVER => {
+                let (a, b) = (self.peek(3)?.clone(), self.peek(2)?.clone());
+                self.stack.extend([a, b]);
+            }
+            OP_2ROT => {
+                self.peek(5)?;
+                let at = self.stack.len() - 6;
+                let pair: Vec<_> = self.stack.drain(at..at + 2).collect();
+                self.stack.extend(pair);
+            }
+            OP_2SWAP => {
+                self.peek(3)?;
+                let at = self.stack.len() - 4;
+                self.stack[at..].rotate_left(2);
+            }
+            OP_IFDUP => {
+                let top = self.peek(0)?.clone();
+                if cast_to_bool(&top) {
+                    self.stack.push(top);
+                }
+            }
+            OP_DEPTH => self.stack.push(encode_num(self.stack.len() as i64)),
+            OP_DROP => {
+                self.pop()?;
+            }
+            OP_DUP => self.stack.push(self.peek(0)?.clone()),
+            OP_NIP => {
+                let top = self.pop()?;
+                self.pop()?;
+                self.stack.push(top);
+            }
+            OP_OVER => self.stack.push(self.peek(1)?.clone()),
+            OP_PICK | OP_ROLL => {
+                let depth = self.pop_num()?;
+                let depth =
+                    usize::try_from(depth).map_err(|_| ScriptError::InvalidStackOperation)?;
+                let element = self.peek(depth)?.clone();
+                if op == OP_ROLL {
+                    self.stack.remove(self.stack.len() - 1 - depth);
+                }
+                self.stack.push(element);
+            }
+            OP_ROT => {
+                self.peek(2)?;
+                let third = self.stack.remove(self.stack.len() - 3);
+                self.stack.push(third);
+            }
+            OP_SWAP => {
+                self.peek(1)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 1, len - 2);
+            }
+            OP_TUCK => {
+                self.peek(1)?;
+                let top = self.peek(0)?.clone();
+                self.stack.insert(self.stack.len() - 2, top);
+            }
+            OP_SIZE => self.stack.push(encode_num(self.peek(0)?.len() as i64)),
+
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let equal = self.pop()? == self.pop()?;
+                if op == OP_EQUALVERIFY {
+                    if !equal {
+                        return Err(ScriptError::Verify(op));
+                    }
+                } else {
+                    self.stack.push(bool_element(equal));
+                }
+            }
+
+            OP_1ADD..=OP_0NOTEQUAL => {
+                let a = self.pop_num()?;
+                let result = match op {
+                    OP_1ADD => a + 1,
+                    OP_1SUB => a - 1,
+                    OP_NEGATE => -a,
+                    OP_ABS => a.abs(),
+                    OP_NOT => (a == 0) as i64,
+                    _ => (a != 0) as i64,
+                };
+                self.stack.push(encode_num(result));
+            }
+            OP_ADD | OP_SUB | OP_BOOLAND..=OP_MAX => {
+                let b = self.pop_num()?;
+                let a = self.pop_num()?;
+                let result = match op {
+                    OP_ADD => a + b,
+                    OP_SUB => a - b,
+                    OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                    OP_NUMNOTEQUAL => (a != b) as i64,
+                    OP_LESSTHAN => (a < b) as i64,
+                    OP_GREATERTHAN => (a > b) as i64,
+                    OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    OP_MIN => a.min(b),
+                    _ => a.max(b),
+                };
+                if op == OP_NUMEQUALVERIFY {
+                    if result == 0 {
+                        return Err(ScriptError::Verify(op));
+                    }
+                } else {
+                    self.stack.push(encode_num(result));
+                }
+            }
+            OP_WITHIN => {
+                let max = self.pop_num()?;
+                let min = self.pop_num()?;
+                let x = self.pop_num()?;
+                self.stack.push(bool_element(min <= x && x < max));
+            }
+
+            OP_RIPEMD160 => {
+                let top = self.pop()?;
+                self.stack.push(ripemd160(&top).to_vec());
+            }
+            OP_SHA1 => {
+                let top = self.pop()?;
+                self.stack.push(sha1(&top).to_vec());
+            }
+            OP_SHA256 => {
+                let top = self.pop()?;
+                self.stack.push(sha256(top));
+            }
+            OP_HASH160 => {
+                let top = self.pop()?;
+                self.stack.push(hash160(&top).to_vec());
+            }
+            OP_HASH256 => {
+                let top = self.pop()?;
+                self.stack.push(hash256(top));
+            }
+            // `run` keeps the script after it for segwit v0 signatures
+            OP_CODESEPARATOR => {}
+
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let pubkey = self.pop()?;
+                let sig = self.pop()?;
+                let valid =
+                    checker.check_sig(&checker.message(self.separated.as_ref())?, &sig, &pubkey);
+                if op == OP_CHECKSIGVERIFY {
+                    if !valid {
+                        return Err(ScriptError::Verify(op));
+                    }
+                } else {
+                    self.stack.push(bool_element(valid));
+                }
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let n = self.pop_num()?;
+                if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&n) {
+                    return Err(ScriptError::PubkeyCount);
+                }
+                self.count_ops(n as usize)?;
+                let pubkeys = (0..n).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                let m = self.pop_num()?;
+                if !(0..=n).contains(&m) {
+                    return Err(ScriptError::SigCount);
+                }
+                let sigs = (0..m).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                // an off by one in the original implementation pops one more
+                if !self.pop()?.is_empty() {
+                    return Err(ScriptError::SigNullDummy);
+                }
+                // the signatures go in the same order as their keys, so each
+                // key is tried once, walking both lists from the top
+                let message = checker.message(self.separated.as_ref())?;
+                let mut keys = pubkeys.iter();
+                let valid = sigs.iter().all(|sig| {
+                    keys.by_ref()
+                        .any(|pubkey| checker.check_sig(&message, sig, pubkey))
+                });
+                if op == OP_CHECKMULTISIGVERIFY {
+                    if !valid {
+                        return Err(ScriptError::Verify(op));
+                    }
+                } else {
+                    self.stack.push(bool_element(valid));
+                }
+            }
+            OP_CHECKLOCKTIMEVERIFY => {
+                // locktimes go up to 2^32 - 1, hence the 5 byte limit
+                let locktime = decode_num(self.peek(0)?, 5)?;
+                if locktime < 0 {
+                    return Err(ScriptError::NegativeLocktime);
+                }
+                if !checker.check_locktime(locktime) {
+                    return Err(ScriptError::UnsatisfiedLocktime);
+                }
+            }
+            OP_CHECKSEQUENCEVERIFY => {
+                let sequence = decode_num(self.peek(0)?, 5)?;
+                if sequence < 0 {
+                    return Err(ScriptError::NegativeLocktime);
+                }
+                // with the disable flag set it's a NOP, left for soft forks
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                    && !checker.check_sequence(sequence)
+                {
+                    return Err(ScriptError::UnsatisfiedLocktime);
+                }
+            }
+            _ => return Err(ScriptError::BadOpcode(op)),
+        }
+        Ok(())
+    }
+}
+
+/// A segwit output's version and program from its serialized script: a
+/// version opcode then a direct push of 2 to 40 bytes (BIP141). Like Core,
+/// a program pushed with OP_PUSHDATA1 or larger doesn't count.
+pub fn witness_program(script_pubkey: &[u8]) -> Option<(u8, &[u8])> {
+    match script_pubkey {
+        [version @ (OP_0 | OP_1..=OP_16), length, program @ ..]
+            if (2..=40).contains(length) && program.len() == *length as usize =>
+        {
+            let version = match *version {
+                OP_0 => 0,
+                op => op - OP_1 + 1,
+            };
+            Some((version, program))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a serialized script is `OP_HASH160 <20 bytes> OP_EQUAL` with a
+/// direct push, the only form BIP16 gives a meaning
+fn is_p2sh(script_pubkey: &[u8]) -> bool {
+    matches!(script_pubkey, [OP_HASH160, 20, hash @ .., OP_EQUAL] if hash.len() == 20)
+}
+
+/// Run `script_sig`, then `script_pubkey` on the stack it leaves, which has to
+/// end with true on top, then the redeem script of P2SH outputs and the
+/// witness of segwit ones
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    checker: &Checker,
+) -> Result<(), ScriptError> {
+    verify(script_sig, script_pubkey, witness, checker, None)
+}
+
+/// Verify like `verify_script`, recording every step
+pub fn trace_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    checker: &Checker,
+) -> Trace {
+    let mut trace = Trace::default();
+    trace.error = verify(
+        script_sig,
+        script_pubkey,
+        witness,
+        checker,
+        Some(&mut trace),
+    )
+    .err();
+    trace
+}
+
+fn check_true(stack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    match stack.last() {
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+fn verify(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    checker: &Checker,
+    mut trace: Option<&mut Trace>,
+) -> Result<(), ScriptError> {
+    let mut interpreter = Interpreter::default();
+    interpreter.run(script_sig, checker, trace.as_deref_mut())?;
+    let sig_stack = interpreter.stack.clone();
+    // nothing carries over but the stack
+    let mut interpreter = Interpreter::new(interpreter.stack);
+    interpreter.run(script_pubkey, checker, trace.as_deref_mut())?;
+    check_true(&interpreter.stack)?;
+
+    // the templates are on the scriptPubKey's own bytes, a program or hash
+    // pushed with OP_PUSHDATA1 or larger doesn't match them
+    if let Some((version, program)) = witness_program(script_pubkey.as_bytes()) {
+        // signatures don't cover the scriptSig, so it has to stay empty
+        if !script_sig.is_empty() {
+            return Err(ScriptError::WitnessMalleated);
+        }
+        return verify_witness(version, program, witness, checker, trace, false);
+    }
+
+    if is_p2sh(script_pubkey.as_bytes()) {
+        // the scriptSig ran, so it parses
+        if !script_sig.cmds().unwrap_or_default().iter().all(|cmd| {
+            matches!(
+                cmd,
+                Cmd::Push(_) | Cmd::Op(OP_0 | OP_1NEGATE | OP_1..=OP_16)
+            )
+        }) {
+            return Err(ScriptError::SigPushOnly);
+        }
+        // the scriptSig's last push hashed to the script hash, it's the
+        // redeem script, run on the rest of the scriptSig's stack
+        let mut stack = sig_stack;
+        let redeem_bytes = stack.pop().expect("OP_HASH160 had an element to hash");
+        let redeem_script = Script::from_bytes(&redeem_bytes);
+        let mut interpreter = Interpreter::new(stack);
+        interpreter.run(&redeem_script, checker, trace.as_deref_mut())?;
+        check_true(&interpreter.stack)?;
+
+        // on the redeem script's own bytes too, so a program pushed with
+        // OP_PUSHDATA1 doesn't count here either
+        if let Some((version, program)) = witness_program(&redeem_bytes) {
+            if *script_sig != Script::new(vec![Cmd::Push(redeem_bytes.clone())]) {
+                return Err(ScriptError::WitnessMalleatedP2sh);
+            }
+            return verify_witness(version, program, witness, checker, trace, true);
+        }
+    }
+
+    if !witness.is_empty() {
+        return Err(ScriptError::WitnessUnexpected);
+    }
+    Ok(())
+}
+
+/// Check the witness against a version `version` program, `nested` if it was
+/// the redeem script of a P2SH output
+fn verify_witness(
+    version: u8,
+    program: &[u8],
+    witness: &[Vec<u8>],
+    checker: &Checker,
+    trace: Option<&mut Trace>,
+    nested: bool,
+) -> Result<(), ScriptError> {
+    let (script, stack) = match (version, program.len()) {
+        // P2WPKH, a signature and key run through the P2PKH script
+        (0, 20) => {
+            if witness.len() != 2 {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            (Script::p2pkh(program), witness.to_vec())
+        }
+        // P2WSH, the witness script goes last and has to match the hash
+        (0, 32) => {
+            let (script, stack) = witness
+                .split_last()
+                .ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+            if sha256(script.clone()) != program {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            (Script::from_bytes(script), stack.to_vec())
+        }
+        (0, _) => return Err(ScriptError::WitnessProgramWrongLength),
+        // taproot, which can't be nested in P2SH
+        (1, 32) if !nested => return verify_taproot(witness),
+        // versions and lengths without a meaning yet are anyone can spend, so
+        // a soft fork can give them one without making valid spends invalid
+        _ => return Ok(()),
+    };
+
+    if stack.iter().any(|e| e.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(ScriptError::PushSize);
+    }
+    let checker = Checker {
+        script_code: Some(script.clone()),
+        ..checker.clone()
+    };
+    let mut interpreter = Interpreter::new(stack);
+    interpreter.run(&script, &checker, trace)?;
+    check_true(&interpreter.stack)?;
+    if interpreter.stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+    Ok(())
+}
+
+/// The structure of a taproot spend: a key path signature, or a script path
+/// script and control block, either optionally followed by an annex
+fn verify_taproot(witness: &[Vec<u8>]) -> Result<(), ScriptError> {
+    let mut witness = witness;
+    if witness.is_empty() {
+        return Err(ScriptError::WitnessProgramWitnessEmpty);
+    }
+    if let [rest @ .., annex] = witness {
+        if !rest.is_empty() && annex.first() == Some(&0x50) {
+            witness = rest;
+        }
+    }
+    match witness {
+        // a signature, with a sighash type byte unless it's SIGHASH_DEFAULT
+        [sig] if sig.len() != 64 && sig.len() != 65 => Err(ScriptError::SchnorrSigSize),
+        [_] => Err(ScriptError::TaprootUnsupported),
+        // the control block is the leaf version and internal key, then up to
+        // 128 hashes of the merkle path
+        [.., control]
+            if control.len() < 33
+                || !(control.len() - 33).is_multiple_of(32)
+                || control.len() > 33 + 32 * 128 =>
+        {
+            Err(ScriptError::TaprootWrongControlSize)
+        }
+        _ => Err(ScriptError::TaprootUnsupported),
+    }
+}
+
+/// The state after running one command
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    pub cmd: Cmd,
+    /// False for commands in a branch that isn't taken
+    pub executed: bool,
+    pub stack: Vec<Vec<u8>>,
+    pub alt_stack: Vec<Vec<u8>>,
+}
+
+/// A step by step record of a script run, to show how it evaluates
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    /// Why the script failed, the step it failed at isn't recorded
+    pub error: Option<ScriptError>,
+}
+
+/// A stack element in a few characters, long ones cut down to their ends
+fn short_element(data: &[u8]) -> String {
+    match data.len() {
+        0 => "[]".to_string(),
+        1..=8 => hex::encode(data),
+        n => format!(
+            "{}..{}",
+            hex::encode(&data[..4]),
+            hex::encode(&data[n - 2..])
+        ),
+    }
+}
+
+/// An opcode as `asm` shows it, small numbers as numbers
+fn op_asm(op: u8) -> String {
+    Script::new(vec![Cmd::Op(op)]).asm()
+}
+
+impl Trace {
+    /// A text table with a row per step, stacks grow to the right
+    pub fn to_table(&self) -> String {
+        let rows: Vec<[String; 3]> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let cmd = match &step.cmd {
+                    Cmd::Push(data) => format!("<{}>", short_element(data)),
+                    Cmd::Op(op) => op_asm(*op),
+                };
+                let stack = |stack: &[Vec<u8>]| {
+                    let elements: Vec<String> = stack.iter().map(|e| short_element(e)).collect();
+                    elements.join(" ")
+                };
+                [
+                    if step.executed {
+                        cmd
+                    } else {
+                        format!("({})", cmd)
+                    },
+                    stack(&step.stack),
+                    stack(&step.alt_stack),
+                ]
+            })
+            .collect();
+        let width = |column: usize, header: &str| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .chain([header.len()])
+                .max()
+                .unwrap()
+        };
+        let (cmd_width, stack_width) = (width(0, "command"), width(1, "stack"));
+
+        let mut table = format!(
+            "{:<cmd_width$}  {:<stack_width$}  altstack\n",
+            "command", "stack"
+        );
+        for [cmd, stack, alt_stack] in rows {
+            let line = format!(
+                "{:<cmd_width$}  {:<stack_width$}  {}",
+                cmd, stack, alt_stack
+            );
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        match &self.error {
+            Some(error) => table.push_str(&format!("failed: {}\n", error)),
+            None => table.push_str("success\n"),
+        }
+        table
+    }
+
+    /// The steps with every element in full hex, for the course website
+    pub fn to_json(&self) -> Value {
+        let hex_all = |stack: &[Vec<u8>]| stack.iter().map(hex::encode).collect::<Vec<_>>();
+        let steps: Vec<Value> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let cmd = match &step.cmd {
+                    Cmd::Push(data) => hex::encode(data),
+                    Cmd::Op(op) => op_asm(*op),
+                };
+                json!({
+                    "cmd": cmd,
+                    "executed": step.executed,
+                    "stack": hex_all(&step.stack),
+                    "altstack": hex_all(&step.alt_stack),
+                })
+            })
+            .collect();
+        json!({
+            "steps": steps,
+            "error": self.error.as_ref().map(ScriptError::to_string),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{Decodable, Encodable};
+    use crate::ru256::RU256;
+    use crate::signer::{Signer, SoftwareSigner};
+    use crate::transaction::TxBuilder;
+
+    fn run(cmds: Vec<Cmd>) -> Result<Vec<Vec<u8>>, ScriptError> {
+        run_with(cmds, &Checker::default())
+    }
+
+    fn run_with(cmds: Vec<Cmd>, checker: &Checker) -> Result<Vec<Vec<u8>>, ScriptError> {
+        let mut interpreter = Interpreter::default();
+        interpreter.execute(&Script::new(cmds), checker)?;
+        Ok(interpreter.stack)
+    }
+
+    fn num(n: i64) -> Cmd {
+        Cmd::Push(encode_num(n))
+    }
+
+    #[test]
+    fn test_script_num() {
+        let cases: [(i64, &[u8]); 11] = [
+            (0, &[]),
+            (1, &[0x01]),
+            (-1, &[0x81]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x00]),
+            (-128, &[0x80, 0x80]),
+            (255, &[0xff, 0x00]),
+            (256, &[0x00, 0x01]),
+            (-256, &[0x00, 0x81]),
+            (0x7fffffff, &[0xff, 0xff, 0xff, 0x7f]),
+            (-0x7fffffff, &[0xff, 0xff, 0xff, 0xff]),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(encode_num(value), bytes);
+            assert_eq!(decode_num(bytes, 4), Ok(value));
+        }
+
+        // negative zero and padded numbers aren't minimal
+        assert_eq!(decode_num(&[0x80], 4), Err(ScriptError::MinimalData));
+        assert_eq!(decode_num(&[0x00], 4), Err(ScriptError::MinimalData));
+        assert_eq!(decode_num(&[0x01, 0x00], 4), Err(ScriptError::MinimalData));
+        assert_eq!(decode_num(&[0x01, 0x80], 4), Err(ScriptError::MinimalData));
+        assert_eq!(
+            decode_num(&[0, 0, 0, 0x80, 0], 4),
+            Err(ScriptError::NumOverflow)
+        );
+        assert_eq!(decode_num(&[0, 0, 0, 0, 0x01], 5), Ok(1 << 32));
+
+        assert!(!cast_to_bool(&[]));
+        assert!(!cast_to_bool(&[0x80]));
+        assert!(!cast_to_bool(&[0x00, 0x00, 0x80]));
+        assert!(cast_to_bool(&[0x00, 0x01]));
+        assert!(cast_to_bool(&[0x80, 0x00]));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let op = Cmd::Op;
+        assert_eq!(
+            run(vec![num(2), num(3), op(OP_ADD), num(5), op(OP_NUMEQUAL)]),
+            Ok(vec![vec![1]])
+        );
+        assert_eq!(run(vec![num(3), num(5), op(OP_SUB)]), Ok(vec![vec![0x82]]));
+        assert_eq!(
+            run(vec![num(-7), op(OP_ABS), op(OP_NEGATE), op(OP_1ADD)]),
+            Ok(vec![encode_num(-6)])
+        );
+        assert_eq!(
+            run(vec![num(2), num(3), op(OP_LESSTHAN)]),
+            Ok(vec![vec![1]])
+        );
+        assert_eq!(
+            run(vec![num(3), num(1), num(3), op(OP_WITHIN)]),
+            Ok(vec![vec![]])
+        );
+        assert_eq!(
+            run(vec![num(4), num(-4), op(OP_MIN), num(0), op(OP_MAX)]),
+            Ok(vec![vec![]])
+        );
+        assert_eq!(
+            run(vec![num(1), num(2), op(OP_NUMEQUALVERIFY)]),
+            Err(ScriptError::Verify(OP_NUMEQUALVERIFY))
+        );
+
+        // results may overflow 4 bytes, but can't be used as operands
+        let max = || num(0x7fffffff);
+        assert_eq!(
+            run(vec![max(), max(), op(OP_ADD)]),
+            Ok(vec![vec![0xfe, 0xff, 0xff, 0xff, 0x00]])
+        );
+        assert_eq!(
+            run(vec![max(), max(), op(OP_ADD), op(OP_1SUB)]),
+            Err(ScriptError::NumOverflow)
+        );
+        assert_eq!(
+            run(vec![max(), max(), op(OP_ADD), op(OP_SIZE)]),
+            Ok(vec![vec![0xfe, 0xff, 0xff, 0xff, 0x00], vec![5]])
+        );
+
+        // negative zero is false, but not a valid number
+        assert_eq!(
+            run(vec![
+                Cmd::push(&[0x80]),
+                op(OP_IF),
+                num(1),
+                op(OP_ELSE),
+                num(2),
+                op(OP_ENDIF)
+            ]),
+            Ok(vec![vec![2]])
+        );
+        assert_eq!(
+            run(vec![Cmd::push(&[0x80]), op(OP_NOT)]),
+            Err(ScriptError::MinimalData)
+        );
+        assert_eq!(
+            run(vec![Cmd::push(&[0x02, 0x00]), num(1), op(OP_ADD)]),
+            Err(ScriptError::MinimalData)
+        );
+        // OP_EQUAL compares bytes, OP_NUMEQUAL numbers
+        assert_eq!(
+            run(vec![Cmd::push(&[]), op(OP_0), op(OP_EQUAL)]),
+            Ok(vec![vec![1]])
+        );
+
+        // disabled opcodes fail even in a branch that doesn't run
+        assert_eq!(
+            run(vec![
+                op(OP_0),
+                op(OP_IF),
+                num(2),
+                num(2),
+                op(OP_MUL),
+                op(OP_ENDIF)
+            ]),
+            Err(ScriptError::DisabledOpcode(OP_MUL))
+        );
+        assert_eq!(
+            run(vec![op(OP_1), op(OP_IF)]),
+            Err(ScriptError::UnbalancedConditional)
+        );
+        assert_eq!(
+            run(vec![op(OP_ADD)]),
+            Err(ScriptError::InvalidStackOperation)
+        );
+    }
+
+    #[test]
+    fn test_stack_ops() {
+        let op = Cmd::Op;
+        let run_on = |stack: &[i64], cmds: &[Cmd]| {
+            let mut script: Vec<Cmd> = stack.iter().map(|&n| num(n)).collect();
+            script.extend_from_slice(cmds);
+            run(script)
+        };
+        let elements = |stack: &[i64]| Ok(stack.iter().map(|&n| encode_num(n)).collect());
+        assert_eq!(
+            run_on(&[1, 2, 3], &[op(OP_3DUP)]),
+            elements(&[1, 2, 3, 1, 2, 3])
+        );
+        assert_eq!(
+            run_on(&[1, 2, 3, 4], &[op(OP_2OVER)]),
+            elements(&[1, 2, 3, 4, 1, 2])
+        );
+        assert_eq!(
+            run_on(&[1, 2, 3, 4, 5, 6], &[op(OP_2ROT)]),
+            elements(&[3, 4, 5, 6, 1, 2])
+        );
+        assert_eq!(
+            run_on(&[1, 2, 3, 4], &[op(OP_2SWAP)]),
+            elements(&[3, 4, 1, 2])
+        );
+        assert_eq!(
+            run_on(&[1, 2, 3], &[num(2), op(OP_PICK)]),
+            elements(&[1, 2, 3, 1])
+        );
+        assert_eq!(
+            run_on(&[1, 2, 3], &[num(2), op(OP_ROLL)]),
+            elements(&[2, 3, 1])
+        );
+        assert_eq!(
+            run_on(&[1, 2, 3], &[num(0), op(OP_ROLL)]),
+            elements(&[1, 2, 3])
+        );
+        for (stack, cmds) in [
+            (&[1, 2][..], vec![op(OP_3DUP)]),
+            (&[1, 2, 3, 4, 5], vec![op(OP_2ROT)]),
+            (&[1, 2, 3], vec![op(OP_2SWAP)]),
+            (&[1], vec![num(1), op(OP_PICK)]),
+            (&[1], vec![num(-1), op(OP_ROLL)]),
+        ] {
+            assert_eq!(
+                run_on(stack, &cmds),
+                Err(ScriptError::InvalidStackOperation)
+            );
+        }
+
+        assert_eq!(
+            run(vec![op(OP_0), op(OP_SHA1)]),
+            Ok(vec![sha1(b"").to_vec()])
+        );
+    }
+
+    #[test]
+    fn test_locktime() {
+        let op = Cmd::Op;
+        let checker = Checker {
+            locktime: 800_000,
+            sequence: 0xfffffffe,
+            ..Default::default()
+        };
+        let cltv = |locktime| vec![num(locktime), op(OP_CHECKLOCKTIMEVERIFY)];
+        assert!(run_with(cltv(800_000), &checker).is_ok());
+        assert_eq!(
+            run_with(cltv(800_001), &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            run_with(cltv(1_700_000_000), &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            run_with(cltv(-1), &checker),
+            Err(ScriptError::NegativeLocktime)
+        );
+        // locktimes can take 5 bytes
+        let checker = Checker {
+            locktime: u32::MAX,
+            ..checker
+        };
+        assert!(run_with(cltv(u32::MAX as i64), &checker).is_ok());
+        let checker = Checker {
+            sequence: 0xffffffff,
+            ..checker
+        };
+        assert_eq!(
+            run_with(cltv(800_000), &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    fn test_sequence() {
+        let op = Cmd::Op;
+        let checker = Checker {
+            version: 2,
+            sequence: 144,
+            ..Default::default()
+        };
+        let csv = |sequence| vec![num(sequence), op(OP_CHECKSEQUENCEVERIFY)];
+        assert!(run_with(csv(144), &checker).is_ok());
+        assert_eq!(
+            run_with(csv(145), &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        // 512 second units can't be compared with blocks
+        assert_eq!(
+            run_with(csv(1 << 22 | 1), &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        assert_eq!(
+            run_with(csv(-1), &checker),
+            Err(ScriptError::NegativeLocktime)
+        );
+        // with the disable flag the operand means nothing
+        assert!(run_with(csv(1 << 31 | 1000), &checker).is_ok());
+        // bits outside the type flag and the low 16 aren't compared
+        assert!(run_with(csv(1 << 16 | 144), &checker).is_ok());
+
+        // relative locktimes need version 2 and an input that sets one
+        let v1 = Checker {
+            version: 1,
+            ..checker.clone()
+        };
+        assert_eq!(
+            run_with(csv(144), &v1),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        let disabled = Checker {
+            sequence: 1 << 31 | 144,
+            ..checker
+        };
+        assert_eq!(
+            run_with(csv(144), &disabled),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let mut signer = SoftwareSigner::new(vec![RU256::from_u64(12345)]);
+        let pubkey = signer.get_pubkey(0).unwrap().sec(true, false);
+        let script_pubkey = Script::p2pkh(&hash160(&pubkey));
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xaa; 20]))
+            .build();
+        let sig = signer.sign_tx_input(0, &tx, 0, &script_pubkey).unwrap();
+        let checker = Checker {
+            sig_message: tx.sig_message(0, &script_pubkey),
+            ..Default::default()
+        };
+        let script_sig = Script::new(vec![Cmd::Push(sig.clone()), Cmd::Push(pubkey.clone())]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &[], &checker),
+            Ok(())
+        );
+
+        let wrong_key = Script::new(vec![Cmd::Push(sig), Cmd::Push(vec![0x02; 33])]);
+        assert_eq!(
+            verify_script(&wrong_key, &script_pubkey, &[], &checker),
+            Err(ScriptError::Verify(OP_EQUALVERIFY))
+        );
+        assert_eq!(
+            verify_script(&Script::default(), &script_pubkey, &[], &checker),
+            Err(ScriptError::InvalidStackOperation)
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let op = Cmd::Op;
+        let mut signer = SoftwareSigner::new(vec![RU256::from_u64(12345), RU256::from_u64(67890)]);
+        let pubkeys: Vec<Vec<u8>> = (0..2)
+            .map(|key| signer.get_pubkey(key).unwrap().sec(true, false))
+            .collect();
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xaa; 20]))
+            .build();
+        let mut sign = |script_pubkey: &Script| {
+            let sig = signer.sign_tx_input(1, &tx, 0, script_pubkey).unwrap();
+            let checker = Checker {
+                sig_message: tx.sig_message(0, script_pubkey),
+                ..Default::default()
+            };
+            (sig, checker)
+        };
+
+        let p2pkh = Script::p2pkh(&hash160(&pubkeys[1]));
+        let (sig, checker) = sign(&p2pkh);
+        let script_sig = Script::new(vec![Cmd::Push(sig.clone()), Cmd::Push(pubkeys[1].clone())]);
+        let trace = trace_script(&script_sig, &p2pkh, &[], &checker);
+        assert_eq!(trace.error, None);
+        assert_eq!(trace.steps.len(), 7);
+        assert_eq!(trace.steps[2].stack.len(), 3);
+        assert_eq!(trace.steps[6].stack, vec![vec![1]]);
+        let table = trace.to_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert!(lines[0].starts_with("command"));
+        assert!(lines[3].starts_with("OP_DUP"));
+        assert_eq!(lines[8], "success");
+        let json = trace.to_json();
+        assert_eq!(json["steps"][2]["cmd"], "OP_DUP");
+        assert_eq!(json["steps"][0]["stack"][0], hex::encode(&sig));
+        assert!(json["error"].is_null());
+
+        // 1 of 2 multisig, signed by the second key
+        let multisig = Script::new(vec![
+            op(OP_1),
+            Cmd::Push(pubkeys[0].clone()),
+            Cmd::Push(pubkeys[1].clone()),
+            num(2),
+            op(OP_CHECKMULTISIG),
+        ]);
+        let (sig, checker) = sign(&multisig);
+        let script_sig = Script::new(vec![op(OP_0), Cmd::Push(sig)]);
+        let trace = trace_script(&script_sig, &multisig, &[], &checker);
+        assert_eq!(trace.error, None);
+        assert_eq!(trace.steps.last().unwrap().stack, vec![vec![1]]);
+
+        // an HTLC, spendable with the preimage by the second key or after
+        // block 800000 by the first
+        let preimage = b"secret".to_vec();
+        let htlc = Script::new(vec![
+            op(OP_IF),
+            op(OP_SHA256),
+            Cmd::Push(sha256(preimage.clone())),
+            op(OP_EQUALVERIFY),
+            Cmd::Push(pubkeys[1].clone()),
+            op(OP_ELSE),
+            num(800_000),
+            op(OP_CHECKLOCKTIMEVERIFY),
+            op(OP_DROP),
+            Cmd::Push(pubkeys[0].clone()),
+            op(OP_ENDIF),
+            op(OP_CHECKSIG),
+        ]);
+        let (sig, checker) = sign(&htlc);
+        let script_sig = Script::new(vec![Cmd::Push(sig.clone()), Cmd::Push(preimage), op(OP_1)]);
+        let trace = trace_script(&script_sig, &htlc, &[], &checker);
+        assert_eq!(trace.error, None);
+        // the timeout branch is skipped
+        assert_eq!(trace.steps.iter().filter(|step| !step.executed).count(), 4);
+        assert!(trace.to_table().contains("(OP_CHECKLOCKTIMEVERIFY)"));
+
+        let script_sig = Script::new(vec![Cmd::Push(sig), op(OP_0)]);
+        let trace = trace_script(&script_sig, &htlc, &[], &checker);
+        assert_eq!(trace.error, Some(ScriptError::UnsatisfiedLocktime));
+        assert!(trace
+            .to_table()
+            .ends_with("failed: locktime requirement not satisfied\n"));
+        assert_eq!(
+            trace.to_json()["error"],
+            "locktime requirement not satisfied"
+        );
+    }
+
+    #[test]
+    fn test_trace_csv_htlc() {
+        let op = Cmd::Op;
+        let mut signer = SoftwareSigner::new(vec![RU256::from_u64(12345), RU256::from_u64(67890)]);
+        let pubkeys: Vec<Vec<u8>> = (0..2)
+            .map(|key| signer.get_pubkey(key).unwrap().sec(true, false))
+            .collect();
+        // spendable with the preimage by the second key, or 144 blocks after
+        // it confirms by the first
+        let preimage = b"secret".to_vec();
+        let htlc = Script::new(vec![
+            op(OP_IF),
+            op(OP_SHA256),
+            Cmd::Push(sha256(preimage.clone())),
+            op(OP_EQUALVERIFY),
+            Cmd::Push(pubkeys[1].clone()),
+            op(OP_ELSE),
+            num(144),
+            op(OP_CHECKSEQUENCEVERIFY),
+            op(OP_DROP),
+            Cmd::Push(pubkeys[0].clone()),
+            op(OP_ENDIF),
+            op(OP_CHECKSIG),
+        ]);
+        let prevout = TxOut {
+            amount: Amount::from_sat(2_000),
+            script_pubkey: htlc.clone(),
+        };
+        let mut spend = |key, version, sequence| {
+            let mut tx = TxBuilder::new("main")
+                .add_input(vec![0x11; 32], 0)
+                .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xaa; 20]))
+                .build();
+            tx.version = version;
+            tx.tx_ins[0].sequence = sequence;
+            let sig = signer.sign_tx_input(key, &tx, 0, &htlc).unwrap();
+            (sig, Checker::for_input(&tx, 0, &prevout))
+        };
+
+        let (sig, checker) = spend(1, 2, 0xffffffff);
+        let script_sig = Script::new(vec![Cmd::Push(sig), Cmd::Push(preimage), op(OP_1)]);
+        let trace = trace_script(&script_sig, &htlc, &[], &checker);
+        assert_eq!(trace.error, None);
+        // the timeout branch is skipped, CSV with it
+        assert_eq!(trace.steps.iter().filter(|step| !step.executed).count(), 4);
+        assert!(trace.to_table().contains("(OP_CHECKSEQUENCEVERIFY)"));
+
+        let (sig, checker) = spend(0, 2, 144);
+        let script_sig = Script::new(vec![Cmd::Push(sig), op(OP_0)]);
+        let trace = trace_script(&script_sig, &htlc, &[], &checker);
+        assert_eq!(trace.error, None);
+        assert_eq!(trace.steps.last().unwrap().stack, vec![vec![1]]);
+        let csv_step = trace
+            .steps
+            .iter()
+            .find(|step| step.cmd == op(OP_CHECKSEQUENCEVERIFY))
+            .unwrap();
+        assert!(csv_step.executed);
+        // CSV leaves its operand, for the OP_DROP
+        assert_eq!(csv_step.stack.last(), Some(&encode_num(144)));
+
+        for (version, sequence) in [(2, 143), (1, 144)] {
+            let (sig, checker) = spend(0, version, sequence);
+            let script_sig = Script::new(vec![Cmd::Push(sig), op(OP_0)]);
+            let trace = trace_script(&script_sig, &htlc, &[], &checker);
+            assert_eq!(trace.error, Some(ScriptError::UnsatisfiedLocktime));
+            assert!(trace
+                .to_table()
+                .ends_with("failed: locktime requirement not satisfied\n"));
+        }
+    }
+
+    #[test]
+    fn test_bip143() {
+        // the native P2WPKH example from BIP143, whose second input spends
+        // 6 BTC from a P2WPKH output
+        let tx = Tx::decode_all(&hex::decode("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap());
+        let program = hex::decode("1d0f172a0ecb48aee1be1f2687d2963ae33f71a1").unwrap();
+        let amount = Amount::from_sat(600_000_000);
+        let message = tx.segwit_sig_message(1, &Script::p2pkh(&program), amount);
+        assert_eq!(
+            hex::encode(hash256(message)),
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+        );
+
+        let p2wpkh = Script::new(vec![Cmd::Op(OP_0), Cmd::Push(program)]);
+        let witness = [
+            hex::decode("304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01").unwrap(),
+            hex::decode("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357").unwrap(),
+        ];
+        let verify = |spend| {
+            let checker = Checker {
+                spend,
+                ..Default::default()
+            };
+            verify_script(&Script::default(), &p2wpkh, &witness, &checker)
+        };
+        assert_eq!(verify(Some((tx.clone(), 1, amount))), Ok(()));
+        // the amount is signed, and the legacy message isn't what's checked
+        assert_eq!(
+            verify(Some((tx.clone(), 1, Amount::from_sat(600_000_001)))),
+            Err(ScriptError::EvalFalse)
+        );
+        assert_eq!(verify(None), Err(ScriptError::WitnessSpendMissing));
+    }
+
+    #[test]
+    fn test_codeseparator() {
+        let op = Cmd::Op;
+        let mut signer = SoftwareSigner::new(vec![RU256::from_u64(12345)]);
+        let pubkey = signer.get_pubkey(0).unwrap().sec(true, false);
+        let checksig = Script::new(vec![Cmd::Push(pubkey), op(OP_CHECKSIG)]);
+        let witness_script = Script::new(vec![op(OP_CODESEPARATOR)]) + checksig.clone();
+        let p2wsh = Script::new(vec![op(OP_0), Cmd::Push(sha256(witness_script.to_bytes()))]);
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xaa; 20]))
+            .build();
+        let amount = Amount::from_sat(2_000);
+        let checker = Checker {
+            spend: Some((tx.clone(), 0, amount)),
+            ..Default::default()
+        };
+        let mut verify = |script_code: &Script| {
+            let message = tx.segwit_sig_message(0, script_code, amount);
+            let mut sig = signer.sign_ecdsa(0, &message).unwrap().encode();
+            sig.push(SIGHASH_ALL);
+            let witness = [sig, witness_script.to_bytes()];
+            verify_script(&Script::default(), &p2wsh, &witness, &checker)
+        };
+        // the signature covers the script after the separator
+        assert_eq!(verify(&checksig), Ok(()));
+        assert_eq!(verify(&witness_script), Err(ScriptError::EvalFalse));
+    }
+
+    #[test]
+    fn test_witness_versions() {
+        let op = Cmd::Op;
+        let no_sig = Script::default();
+        let verify = |script_sig: &Script, script_pubkey: &Script, witness: &[Vec<u8>]| {
+            verify_script(script_sig, script_pubkey, witness, &Checker::default())
+        };
+
+        // P2WSH of a script adding the two witness elements
+        let script = Script::new(vec![op(OP_ADD), num(5), op(OP_NUMEQUAL)]).to_bytes();
+        let p2wsh = Script::new(vec![op(OP_0), Cmd::Push(sha256(script.clone()))]);
+        let witness = |a, b| vec![encode_num(a), encode_num(b), script.clone()];
+        assert_eq!(verify(&no_sig, &p2wsh, &witness(2, 3)), Ok(()));
+        assert_eq!(
+            verify(&no_sig, &p2wsh, &witness(2, 2)),
+            Err(ScriptError::EvalFalse)
+        );
+        let mut extra = witness(2, 3);
+        extra.insert(0, vec![1]);
+        assert_eq!(
+            verify(&no_sig, &p2wsh, &extra),
+            Err(ScriptError::CleanStack)
+        );
+        let mut other_script = witness(2, 3);
+        other_script[2].push(OP_VERIFY);
+        assert_eq!(
+            verify(&no_sig, &p2wsh, &other_script),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+        assert_eq!(
+            verify(&no_sig, &p2wsh, &[]),
+            Err(ScriptError::WitnessProgramWitnessEmpty)
+        );
+        let script_sig = Script::new(vec![op(OP_1)]);
+        assert_eq!(
+            verify(&script_sig, &p2wsh, &witness(2, 3)),
+            Err(ScriptError::WitnessMalleated)
+        );
+
+        // the same nested in P2SH, the scriptSig only pushes the redeem script
+        let p2sh = Script::new(vec![
+            op(OP_HASH160),
+            Cmd::push(&hash160(&p2wsh.to_bytes())),
+            op(OP_EQUAL),
+        ]);
+        let script_sig = Script::new(vec![Cmd::Push(p2wsh.to_bytes())]);
+        assert_eq!(verify(&script_sig, &p2sh, &witness(2, 3)), Ok(()));
+        let script_sig = Script::new(vec![op(OP_1), Cmd::Push(p2wsh.to_bytes())]);
+        assert_eq!(
+            verify(&script_sig, &p2sh, &witness(2, 3)),
+            Err(ScriptError::WitnessMalleatedP2sh)
+        );
+
+        // version 0 programs are 20 or 32 bytes
+        let p2wpkh = Script::new(vec![op(OP_0), Cmd::Push(vec![0xaa; 20])]);
+        assert_eq!(
+            verify(&no_sig, &p2wpkh, &[vec![1], vec![2], vec![3]]),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+        let wrong_length = Script::new(vec![op(OP_0), Cmd::Push(vec![0xaa; 21])]);
+        assert_eq!(
+            verify(&no_sig, &wrong_length, &[vec![1]]),
+            Err(ScriptError::WitnessProgramWrongLength)
+        );
+
+        // taproot's structure is checked, its signatures can't be
+        let p2tr = Script::new(vec![op(OP_1), Cmd::Push(vec![0xaa; 32])]);
+        assert_eq!(
+            verify(&no_sig, &p2tr, &[]),
+            Err(ScriptError::WitnessProgramWitnessEmpty)
+        );
+        assert_eq!(
+            verify(&no_sig, &p2tr, &[vec![0; 63]]),
+            Err(ScriptError::SchnorrSigSize)
+        );
+        assert_eq!(
+            verify(&no_sig, &p2tr, &[vec![0; 64], vec![0x50, 0x01]]),
+            Err(ScriptError::TaprootUnsupported)
+        );
+        assert_eq!(
+            verify(&no_sig, &p2tr, &[vec![OP_1], vec![0xc0; 34]]),
+            Err(ScriptError::TaprootWrongControlSize)
+        );
+
+        // unknown versions, and taproot in P2SH, are anyone can spend
+        let v2 = Script::new(vec![op(OP_1 + 1), Cmd::Push(vec![0xaa; 32])]);
+        assert_eq!(verify(&no_sig, &v2, &[]), Ok(()));
+        let v1_short = Script::new(vec![op(OP_1), Cmd::Push(vec![0xaa; 20])]);
+        assert_eq!(verify(&no_sig, &v1_short, &[vec![0]]), Ok(()));
+        let nested_p2tr = Script::new(vec![
+            op(OP_HASH160),
+            Cmd::push(&hash160(&p2tr.to_bytes())),
+            op(OP_EQUAL),
+        ]);
+        let script_sig = Script::new(vec![Cmd::Push(p2tr.to_bytes())]);
+        assert_eq!(verify(&script_sig, &nested_p2tr, &[]), Ok(()));
+
+        let legacy = Script::new(vec![op(OP_1)]);
+        assert_eq!(
+            verify(&no_sig, &legacy, &[vec![1]]),
+            Err(ScriptError::WitnessUnexpected)
+        );
+
+        // a redeem script pushing the program with OP_PUSHDATA1 isn't a
+        // witness program, it's a legacy script leaving the program on top
+        let mut pushdata = vec![OP_0, 0x4c, 20];
+        pushdata.extend([0xaa; 20]);
+        assert_eq!(witness_program(&pushdata), None);
+        let mut direct = vec![OP_0, 20];
+        direct.extend([0xaa; 20]);
+        assert_eq!(witness_program(&direct), Some((0, &[0xaa; 20][..])));
+        let nested = |redeem: &[u8]| {
+            let p2sh = Script::new(vec![
+                op(OP_HASH160),
+                Cmd::push(&hash160(redeem)),
+                op(OP_EQUAL),
+            ]);
+            let script_sig = Script::new(vec![Cmd::push(redeem)]);
+            verify(&script_sig, &p2sh, &[])
+        };
+        assert_eq!(nested(&pushdata), Ok(()));
+        assert_eq!(nested(&direct), Err(ScriptError::WitnessProgramMismatch));
+
+        // the same for a scriptPubKey, as it was serialized
+        let mut pushdata = vec![OP_1, 0x4c, 32];
+        pushdata.extend([0xaa; 32]);
+        let pushdata = Script::from_bytes(&pushdata);
+        assert_eq!(verify(&no_sig, &pushdata, &[]), Ok(()));
+        assert_eq!(
+            verify(&no_sig, &pushdata, &[vec![0; 64]]),
+            Err(ScriptError::WitnessUnexpected)
+        );
+        // and a script hash pushed with OP_PUSHDATA1 isn't P2SH, the
+        // scriptSig needn't be push only
+        let redeem = Script::new(vec![op(OP_1)]).to_bytes();
+        let mut p2sh = vec![OP_HASH160, 0x4c, 20];
+        p2sh.extend(hash160(&redeem));
+        p2sh.push(OP_EQUAL);
+        let script_sig = Script::new(vec![op(OP_NOP), Cmd::Push(redeem)]);
+        assert_eq!(verify(&script_sig, &Script::from_bytes(&p2sh), &[]), Ok(()));
+    }
+
+    #[test]
+    fn test_limits() {
+        let nops = |n| vec![Cmd::Op(OP_NOP); n];
+        assert!(run(nops(MAX_OPS_PER_SCRIPT)).is_ok());
+        assert_eq!(run(nops(MAX_OPS_PER_SCRIPT + 1)), Err(ScriptError::OpCount));
+
+        // skipped opcodes count too, pushes don't
+        let mut cmds = vec![num(0), Cmd::Op(OP_IF)];
+        cmds.extend(nops(MAX_OPS_PER_SCRIPT - 1));
+        cmds.extend([num(1), Cmd::Op(OP_ENDIF)]);
+        assert_eq!(run(cmds), Err(ScriptError::OpCount));
+
+        // every key of a CHECKMULTISIG counts as an opcode
+        let multisig = |nop_count| {
+            let mut cmds = nops(nop_count);
+            cmds.extend([num(0), num(0)]);
+            cmds.extend(vec![Cmd::push(&[0x02; 33]); 20]);
+            cmds.extend([num(20), Cmd::Op(OP_CHECKMULTISIG)]);
+            run(cmds)
+        };
+        assert!(multisig(MAX_OPS_PER_SCRIPT - 21).is_ok());
+        assert_eq!(multisig(MAX_OPS_PER_SCRIPT - 20), Err(ScriptError::OpCount));
+
+        // 500 byte pushes take 502 bytes each
+        let pushes = |n| run(vec![Cmd::push(&[1; 500]); n]);
+        assert!(pushes(MAX_SCRIPT_SIZE / 502).is_ok());
+        assert_eq!(
+            pushes(MAX_SCRIPT_SIZE / 502 + 1),
+            Err(ScriptError::ScriptSize)
+        );
+    }
+}
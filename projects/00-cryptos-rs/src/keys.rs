@@ -1,22 +1,34 @@
-use std::ops::{Mul, Rem};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use primitive_types::U256;
+#[cfg(feature = "rand")]
 use rand::Rng;
 use sha2::{Digest, Sha256};
 
-use crate::ripemd160::ripemd160;
+#[cfg(test)]
+use crate::encoding::FromHex;
+use crate::encoding::{Decodable, Encodable};
+use crate::field::Fn;
+use crate::hashes;
 use crate::ru256::RU256;
 use crate::secp256k1::{Point, SECP256K1};
 
 // Secret key generation
+#[cfg(feature = "rand")]
 pub fn gen_secret_key(n: &RU256) -> RU256 {
+    gen_secret_key_with_rng(n, &mut rand::thread_rng())
+}
+
+/// Like `gen_secret_key` with randomness from `rng`, e.g. a seeded `StdRng`
+/// to get the same keys on every run of an exercise
+#[cfg(feature = "rand")]
+pub fn gen_secret_key_with_rng<R: Rng + ?Sized>(n: &RU256, rng: &mut R) -> RU256 {
     loop {
-        let mut rng = rand::thread_rng();
         let mut key_bytes = [0u8; 32];
         rng.fill(&mut key_bytes);
-        println!("key_bytes: {:?}", key_bytes);
         let key = RU256::from_bytes(&key_bytes);
-        println!("key: {:?}", key);
+        sensitive!(?key, "sampled secret key");
         if key >= RU256::from_u64(1) && key < *n {
             return key;
         }
@@ -24,7 +36,7 @@ pub fn gen_secret_key(n: &RU256) -> RU256 {
 }
 
 // Public key - specific functions, esp encoding / decoding
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PublicKey(pub Point);
 
 impl PublicKey {
@@ -38,36 +50,188 @@ impl PublicKey {
     }
 
     pub fn from_bytes(b: &[u8]) -> PublicKey {
-        PublicKey::from_point(PublicKey::decode(b))
+        PublicKey::decode_all(b)
     }
 
-    pub fn decode(b: &[u8]) -> Point {
-        todo!()
+    /// Like `from_bytes`, but None instead of a panic for anything that isn't
+    /// a SEC encoded point on the curve, for keys from untrusted peers
+    pub fn try_from_bytes(b: &[u8]) -> Option<PublicKey> {
+        let point = match (b.len(), b.first()) {
+            (33, Some(&prefix @ (0x02 | 0x03))) => {
+                Point::lift_x(&RU256::from_bytes(&b[1..]), prefix == 0x03)?
+            }
+            (65, Some(0x04)) => Point {
+                x: RU256::from_bytes(&b[1..33]),
+                y: RU256::from_bytes(&b[33..]),
+            },
+            _ => return None,
+        };
+        point.is_on_curve().then_some(PublicKey(point))
     }
 
-    pub fn encode(&self, compressed: bool, hash160: bool) -> Vec<u8> {
-        todo!()
+    /// SEC encoding of the key, or its hash160 when `hash160` is set
+    pub fn sec(&self, compressed: bool, hash160: bool) -> Vec<u8> {
+        let sec = if compressed {
+            self.0.to_compressed_bytes()
+        } else {
+            self.0.encode()
+        };
+        if hash160 {
+            hashes::hash160(&sec).to_vec()
+        } else {
+            sec
+        }
     }
 
     pub fn address(&self, net: &str, compressed: bool) -> String {
-        let pkb_hash = self.encode(compressed, true);
-        let version = match net {
-            "main" => 0x00,
-            "test" => 0x6f,
-            _ => panic!("Unknown network"),
+        let pkb_hash = self.sec(compressed, true);
+        pkb_hash_to_address(&pkb_hash, net)
+    }
+}
+
+/// Compressed SEC encoding
+impl Encodable for PublicKey {
+    fn encode(&self) -> Vec<u8> {
+        self.sec(true, false)
+    }
+}
+
+impl Decodable for PublicKey {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        PublicKey(Point::decode(bytes))
+    }
+}
+
+/// A public key as only its x coordinate, the BIP340 form used by taproot,
+/// MuSig2 and Nostr. It stands for the point with that x and an even y, so a
+/// key with odd y has to be negated (along with its secret key) to be used as
+/// one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XOnlyPublicKey(pub RU256);
+
+impl XOnlyPublicKey {
+    /// The x coordinate of `public_key` and whether its y was odd
+    pub fn from_pubkey(public_key: &PublicKey) -> (Self, bool) {
+        let point = &public_key.0;
+        (XOnlyPublicKey(point.x.clone()), point.y.v.bit(0))
+    }
+
+    /// None unless the bytes are the x coordinate of a point on the curve
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        let key = XOnlyPublicKey(RU256::from_bytes(bytes));
+        key.lift_x().map(|_| key)
+    }
+
+    pub fn serialize(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.to_bytes(&mut bytes);
+        bytes
+    }
+
+    /// The full key, the point with even y
+    pub fn lift_x(&self) -> Option<PublicKey> {
+        Point::lift_x(&self.0, false).map(PublicKey)
+    }
+
+    /// P + tweak * G with P the even y point, as the x-only result and its
+    /// parity, what a taproot output key commits to. None if the tweak
+    /// isn't a scalar or the sum is the point at infinity.
+    pub fn tweak_add(&self, tweak: &RU256) -> Option<(Self, bool)> {
+        let tweak = Fn::from_canonical(tweak)?;
+        let point = self.lift_x()?.0;
+        if tweak.is_zero() {
+            return Some((self.clone(), false));
+        }
+        let tweak_point = SECP256K1::public_key(tweak.as_ru256());
+        // add_points needs two different points that aren't each other's
+        // negation
+        let tweaked = match (tweak_point.x == point.x, tweak_point == point) {
+            (false, _) => point + tweak_point,
+            (true, true) => SECP256K1::double_point(&point),
+            (true, false) => return None,
         };
-        let mut ver_pkb_hash = vec![version];
-        ver_pkb_hash.extend_from_slice(&pkb_hash);
-        let checksum = &Sha256::digest(&Sha256::digest(&ver_pkb_hash))[..4];
-        ver_pkb_hash.extend_from_slice(checksum);
-        b58encode(&ver_pkb_hash)
+        Some(XOnlyPublicKey::from_pubkey(&PublicKey(tweaked)))
+    }
+
+    /// Whether `output` with `parity` is this key tweaked by `tweak`, how a
+    /// taproot script path spend checks the output key against the internal
+    /// key
+    pub fn tweak_add_check(&self, output: &XOnlyPublicKey, parity: bool, tweak: &RU256) -> bool {
+        self.tweak_add(tweak) == Some((output.clone(), parity))
+    }
+}
+
+/// The secret key of the x-only key of `secret_key` tweaked by `tweak`: the
+/// key is negated first if its point has odd y, then the tweak is added mod
+/// n. None if the tweak isn't a scalar or the result is zero.
+pub fn tweak_add_secret_key(secret_key: &RU256, tweak: &RU256) -> Option<RU256> {
+    let tweak = Fn::from_canonical(tweak)?;
+    let (_, odd) = XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(secret_key));
+    let secret_key = Fn::new(secret_key);
+    let secret_key = if odd { -secret_key } else { secret_key };
+    let tweaked = secret_key + tweak;
+    (!tweaked.is_zero()).then(|| tweaked.as_ru256().clone())
+}
+
+/// Build the b58check P2PKH address for a public key hash
+pub fn pkb_hash_to_address(pkb_hash: &[u8], net: &str) -> String {
+    let version = match net {
+        "main" => 0x00,
+        "test" => 0x6f,
+        _ => panic!("Unknown network"),
+    };
+    let mut ver_pkb_hash = vec![version];
+    ver_pkb_hash.extend_from_slice(pkb_hash);
+    b58check_encode(&ver_pkb_hash)
+}
+
+/// Wallet import format of a secret key, the b58check of a version byte, the
+/// key and a 0x01 when its public key is used compressed
+pub fn wif_encode(secret_key: &RU256, net: &str, compressed: bool) -> String {
+    let version = match net {
+        "main" => 0x80,
+        "test" => 0xef,
+        _ => panic!("{} is not a valid net type, should be main|test", net),
+    };
+    let mut payload = vec![version; 33];
+    secret_key.to_bytes(&mut payload[1..]);
+    if compressed {
+        payload.push(0x01);
     }
+    b58check_encode(&payload)
+}
+
+/// The secret key, net and compression flag of a WIF string, None if it
+/// isn't one
+pub fn wif_decode(wif: &str) -> Option<(RU256, &'static str, bool)> {
+    let payload = b58check_decode(wif)?;
+    let net = match payload.first()? {
+        0x80 => "main",
+        0xef => "test",
+        _ => return None,
+    };
+    let compressed = match payload.len() {
+        33 => false,
+        34 if payload[33] == 0x01 => true,
+        _ => return None,
+    };
+    let secret_key = RU256::from_bytes(&payload[1..33]);
+    if secret_key.is_zero() || secret_key >= SECP256K1::n() {
+        return None;
+    }
+    Some((secret_key, net, compressed))
 }
 
 // Convenience functions
+#[cfg(feature = "rand")]
 pub fn gen_key_pair() -> (RU256, PublicKey) {
-    let sk = gen_secret_key(&SECP256K1::n().into());
-    let pk = PublicKey::from_sk(&sk.clone().into());
+    gen_key_pair_with_rng(&mut rand::thread_rng())
+}
+
+#[cfg(feature = "rand")]
+pub fn gen_key_pair_with_rng<R: Rng + ?Sized>(rng: &mut R) -> (RU256, PublicKey) {
+    let sk = gen_secret_key_with_rng(&SECP256K1::n(), rng);
+    let pk = PublicKey::from_sk(&sk);
     (sk, pk)
 }
 
@@ -75,48 +239,75 @@ pub fn gen_key_pair() -> (RU256, PublicKey) {
 const ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
 fn b58encode(b: &[u8]) -> String {
-    let mut n = U256::from_big_endian(b);
-    let mut chars = Vec::new();
-    while n > U256::from(0) {
-        let quotient = n / U256::from(58);
-        let remainder = n % U256::from(58);
-        chars.push(ALPHABET.chars().nth(remainder.low_u32() as usize).unwrap());
-        n = quotient;
+    // base58 digits of the big endian number, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in b {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
     }
+    // every leading zero byte becomes a leading '1'
     let num_leading_zeros = b.iter().take_while(|&&x| x == 0).count();
+    let alphabet = ALPHABET.as_bytes();
     let mut res = String::new();
     for _ in 0..num_leading_zeros {
-        res.push(ALPHABET.chars().nth(0).unwrap());
+        res.push('1');
     }
-    res.extend(chars.iter().rev());
+    res.extend(digits.iter().rev().map(|&d| alphabet[d as usize] as char));
     res
 }
 
-fn b58decode(res: &str) -> Vec<u8> {
-    let mut n = U256::from(0);
+/// None if the string has characters outside the base58 alphabet
+fn b58decode(res: &str) -> Option<Vec<u8>> {
+    // bytes of the number, least significant first
+    let mut bytes: Vec<u8> = Vec::new();
     for c in res.chars() {
-        n = n * U256::from(58) + U256::from(ALPHABET.find(c).unwrap() as u64);
-    }
-    let mut byte_vec = Vec::new();
-    n.to_big_endian(&mut byte_vec);
-    let mut new_byte_vec: Vec<u8> = Vec::new();
-    for &num in &byte_vec {
-        new_byte_vec.extend_from_slice(&num.to_be_bytes());
-    }
-    let num_leading_zeros = res
-        .chars()
-        .take_while(|&c| c == ALPHABET.chars().nth(0).unwrap())
-        .count();
+        let mut carry = ALPHABET.find(c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let num_leading_zeros = res.chars().take_while(|&c| c == '1').count();
     let mut res = vec![0u8; num_leading_zeros];
-    res.extend_from_slice(&byte_vec);
-    res
+    res.extend(bytes.iter().rev());
+    Some(res)
+}
+
+/// Base58 with a 4 byte double sha256 checksum appended
+pub fn b58check_encode(payload: &[u8]) -> String {
+    let mut bytes = payload.to_vec();
+    bytes.extend_from_slice(&Sha256::digest(Sha256::digest(payload))[..4]);
+    b58encode(&bytes)
+}
+
+/// The payload of a b58check string, None if it isn't base58 or the checksum
+/// doesn't match
+pub fn b58check_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = b58decode(s)?;
+    if bytes.len() < 4 {
+        return None;
+    }
+    let (payload, checksum) = bytes.split_at(bytes.len() - 4);
+    (Sha256::digest(Sha256::digest(payload))[..4] == *checksum).then(|| payload.to_vec())
 }
 
 pub fn address_to_pkb_hash(b58check_address: &str) -> Vec<u8> {
-    let byte_address = b58decode(b58check_address);
-    let checksum = &Sha256::digest(&Sha256::digest(&byte_address[..21]))[..4];
-    assert_eq!(&byte_address[21..], checksum);
-    byte_address[1..21].to_vec()
+    let payload = b58check_decode(b58check_address).expect("invalid b58check address");
+    assert_eq!(payload.len(), 21);
+    payload[1..].to_vec()
 }
 
 #[test]
@@ -165,13 +356,13 @@ fn test_btc_addresses() {
         (
             "main",
             true,
-            "000000000000000000000000000000000000000000000000000000000012345d",
+            "00000000000000000000000000000000000000000000000000012345deadbeef",
             "1F1Pn2y6pDb68E5nYJJeba4TLg2U7B6KF1",
         ),
         (
             "test",
             true,
-            "0000000000000000000000000000000000000000000000000000000000002020",
+            "00000000000000000000000000000000000000000000000000777c6b16216400",
             "mopVkxp8UhXqRYbCYJsbeE1h1fiF64jcoH",
         ),
         (
@@ -195,7 +386,7 @@ fn test_btc_addresses() {
         let sk = RU256::from_bytes(&hex::decode(secret_key).unwrap());
         let pk = PublicKey::from_sk(&sk);
         // get the hash160 by stripping version byte and checksum
-        let pkb_hash = pk.encode(*compressed, true);
+        let pkb_hash = pk.sec(*compressed, true);
         // now extract from the address, address_to_pkb_hash
         let pkb_hash2 = address_to_pkb_hash(address);
         assert_eq!(pkb_hash, pkb_hash2);
@@ -210,20 +401,93 @@ fn test_pk_sec() {
     // these examples are taken from Programming Bitcoin Chapter 4 exercises
     let tests = vec![
         (G.clone() * RU256::from_u64(5000), false, "04ffe558e388852f0120e46af2d1b370f85854a8eb0841811ece0e3e03d282d57c315dc72890a4f10a1481c031b03b351b0dc79901ca18a00cf009dbdb157a1d10"),
-        (G.clone() * RU256::from_u64(2018) * RU256::from_u64(5), false, "04027f3da1918455e03c46f659266a1bb5204e959db7364d2f473bdf8f0a13cc9dff87647fd023c13b4a4994f17691895806e1b40b57f4fd22581a4f46851f3b06"),
+        (G.clone() * RU256::from_u64(2018u64.pow(5)), false, "04027f3da1918455e03c46f659266a1bb5204e959db7364d2f473bdf8f0a13cc9dff87647fd023c13b4a4994f17691895806e1b40b57f4fd22581a4f46851f3b06"),
         (G.clone() * RU256::from_u64(0xdeadbeef12345), false, "04d90cd625ee87dd38656dd95cf79f65f60f7273b67d3096e68bd81e4f5342691f842efa762fd59961d0e99803c61edba8b3e3f7dc3a341836f97733aebf987121"),
-        (G.clone() * RU256::from_u64(5001), true, "0357a4f3688868a8a6d572991e484e664810ff14c05c0fa023275251151fe0e53d1"),
-        (G.clone() * RU256::from_u64(2019) * RU256::from_u64(5), true, "02933ec2d2b111b92737ec12f1c5d20f3233a0ad21cd8b36d0bca7a0cfa5cb8701"),
+        (G.clone() * RU256::from_u64(5001), true, "0357a4f368868a8a6d572991e484e664810ff14c05c0fa023275251151fe0e53d1"),
+        (G.clone() * RU256::from_u64(2019u64.pow(5)), true, "02933ec2d2b111b92737ec12f1c5d20f3233a0ad21cd8b36d0bca7a0cfa5cb8701"),
         (G * RU256::from_u64(0xdeadbeef54321), true, "0296be5b1292f6c856b3c5654e886fc13511462059089cdf9c479623bfcbe77690"),
     ];
 
     for (P, compressed, sec_gt) in tests.iter() {
         // encode
-        let sec = PublicKey::from_point(P.clone()).encode(*compressed, false);
+        let sec = PublicKey::from_point(P.clone()).sec(*compressed, false);
         assert_eq!(hex::encode(sec), *sec_gt);
         // decode
-        let P2 = PublicKey::decode(&hex::decode(sec_gt).unwrap());
+        let P2 = PublicKey::from_hex(sec_gt).0;
         assert_eq!(P.x, P2.x);
         assert_eq!(P.y, P2.y);
     }
 }
+
+#[test]
+fn test_wif() {
+    // Example taken from Chapter 4 of Mastering Bitcoin
+    let sk = RU256::from_str_radix(
+        "1E99423A4ED27608A15A2616A2B0E9E52CED330AC530EDCC32C8FFC6A526AEDD",
+        16,
+    )
+    .unwrap();
+    for (compressed, wif) in [
+        (false, "5J3mBbAH58CpQ3Y5RNJpUKPE62SQ5tfcvU2JpbnkeyhfsYB1Jcn"),
+        (true, "KxFC1jmwwCoACiCAWZ3eXa96mBM6tb3TYzGmf6YwgdGWZgawvrtJ"),
+    ] {
+        assert_eq!(wif_encode(&sk, "main", compressed), wif);
+        assert_eq!(wif_decode(wif), Some((sk.clone(), "main", compressed)));
+    }
+    let testnet = wif_encode(&sk, "test", true);
+    assert_eq!(wif_decode(&testnet), Some((sk, "test", true)));
+    // an address isn't a WIF
+    assert_eq!(wif_decode("1PMycacnJaSqwwJqjawXBErnLsZ7RkXUAs"), None);
+}
+
+#[test]
+fn test_x_only_keys() {
+    // G has even y, 6G odd y
+    let g = PublicKey::from_sk(&RU256::one());
+    let (x_only, odd) = XOnlyPublicKey::from_pubkey(&g);
+    assert!(!odd);
+    assert_eq!(x_only.lift_x(), Some(g));
+    let (x_only, odd) = XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&RU256::from_u64(6)));
+    assert!(odd);
+    assert_eq!(
+        XOnlyPublicKey::from_bytes(&x_only.serialize()),
+        Some(x_only.clone())
+    );
+    // -6G, the even y point with the same x
+    let minus_six = RU256::zero().sub_mod(&RU256::from_u64(6), &SECP256K1::n());
+    assert_eq!(x_only.lift_x(), Some(PublicKey::from_sk(&minus_six)));
+    // x = 5 isn't on the curve
+    let mut bytes = [0u8; 32];
+    bytes[31] = 5;
+    assert_eq!(XOnlyPublicKey::from_bytes(&bytes), None);
+
+    // tweaking the key and tweaking its secret key agree, for both parities
+    let tweak = RU256::from_u64(3);
+    for secret_key in [RU256::one(), RU256::from_u64(6)] {
+        let (internal, _) = XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&secret_key));
+        let (output, parity) = internal.tweak_add(&tweak).unwrap();
+        let tweaked = tweak_add_secret_key(&secret_key, &tweak).unwrap();
+        assert_eq!(
+            XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&tweaked)),
+            (output.clone(), parity)
+        );
+        assert!(internal.tweak_add_check(&output, parity, &tweak));
+        assert!(!internal.tweak_add_check(&output, !parity, &tweak));
+    }
+    assert_eq!(x_only.tweak_add(&SECP256K1::n()), None);
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn prop_b58_roundtrip(bytes in proptest::collection::vec(proptest::num::u8::ANY, 0..100)) {
+        proptest::prop_assert_eq!(b58decode(&b58encode(&bytes)), Some(bytes));
+    }
+
+    #[test]
+    fn prop_address_roundtrip(pkb_hash in proptest::array::uniform20(proptest::num::u8::ANY), testnet: bool) {
+        let net = if testnet { "test" } else { "main" };
+        let address = pkb_hash_to_address(&pkb_hash, net);
+        proptest::prop_assert_eq!(address_to_pkb_hash(&address), pkb_hash.to_vec());
+    }
+}
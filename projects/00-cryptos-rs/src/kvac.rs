@@ -0,0 +1,615 @@
+use rand::Rng;
+
+use crate::field::Fn;
+use crate::hashes::tagged;
+use crate::keys::gen_secret_key_with_rng;
+use crate::ru256::RU256;
+use crate::secp256k1::{point_add, point_mul, Point, SECP256K1};
+use crate::sigma::{Proof, Relation};
+use crate::transcript::Transcript;
+
+// Keyed-verification anonymous credentials (Chase, Meiklejohn and Zaverucha
+// 2014), the kind of credential newer ecash designs use instead of Chaumian
+// tokens. A Chaumian token is a blind signature on a serial number and says
+// nothing but "worth one denomination". A credential carries attributes, an
+// amount, an expiry, a keyset, and the holder proves statements about them
+// without showing them. Since the issuer is also the verifier (the mint
+// checks its own credentials), a MAC does instead of a signature.
+//
+// MAC_GGM: the issuer's key is x0, x1 .. xn, the MAC on attributes m1 .. mn
+// is a random point U with U' = (x0 + x1 m1 + .. + xn mn) U. The issuer
+// publishes Cx0 = x0 G + x0~ H and Xi = xi H, and proves every MAC it hands
+// out consistent with them, so it can't tag users with a key of their own.
+// To show a credential the holder randomizes (U, U'), commits to each
+// attribute Cmi = mi U + zi H and to U' with C_U' = U' + r G. The issuer
+// computes V = x0 U + sum xi Cmi - C_U' with its key, which for a valid MAC is
+// sum zi Xi - r G, and the holder proves it knows the zi and r making it so,
+// and the mi in the commitments. An amount attribute can additionally be
+// proven below 2^bits, one commitment and OR proof per bit.
+//
+// The issuance and presentation proofs are sigma proofs of linear relations
+// from the sigma module, the range proof's OR proofs are built here.
+// Experimental: attributes are only issued in the clear, there is no blind
+// issuance or re-issuance of changed amounts yet, and nothing has been
+// reviewed beyond the tests.
+
+const ISSUANCE_DOMAIN: &[u8] = b"ecash-course/kvac/issuance";
+const PRESENTATION_DOMAIN: &[u8] = b"ecash-course/kvac/presentation";
+const RANGE_DOMAIN: &[u8] = b"ecash-course/kvac/range";
+
+/// A second generator with no known discrete log relative to G, by hashing
+/// to an x coordinate until one is on the curve
+fn generator_h() -> Point {
+    (0u32..)
+        .find_map(|counter| {
+            let x = RU256::from_bytes(&tagged("ecash-course/kvac/H", &counter.to_be_bytes()));
+            Point::lift_x(&x, false)
+        })
+        .unwrap()
+}
+
+fn sub(a: &Point, b: &Point) -> Point {
+    point_add(a, &-b.clone())
+}
+
+fn infinity() -> Point {
+    Point {
+        x: RU256::zero(),
+        y: RU256::zero(),
+    }
+}
+
+/// Whether the points the other side sent can be computed with: on the curve,
+/// which rules out unreduced coordinates and infinity too. The addition
+/// formulas never use the curve's b, so multiplying a secret by a point off
+/// it computes on a weaker curve, and the result leaks the secret (see the
+/// invalid-curve attack in attacks).
+fn on_curve<'a>(points: impl IntoIterator<Item = &'a Point>) -> bool {
+    points.into_iter().all(Point::is_on_curve)
+}
+
+fn random_scalar<R: Rng + ?Sized>(rng: &mut R) -> Fn {
+    Fn::new(&gen_secret_key_with_rng(&SECP256K1::n(), rng))
+}
+
+/// The attribute value for an amount
+pub fn amount(value: u64) -> Fn {
+    Fn::new(&RU256::from_u64(value))
+}
+
+/// Proof that a commitment C = b U + z H has a bit b, as a proof of
+/// knowledge of z with C = z H or C - U = z H
+#[derive(Debug, Clone, PartialEq)]
+struct BitProof {
+    challenges: [Fn; 2],
+    responses: [Fn; 2],
+}
+
+fn bit_challenge(transcript: &mut Transcript, commitment: &Point, nonce_points: &[Point; 2]) -> Fn {
+    transcript.append_point(b"bit commitment", commitment);
+    transcript.append_point(b"nonce 0", &nonce_points[0]);
+    transcript.append_point(b"nonce 1", &nonce_points[1]);
+    transcript.challenge_scalar(b"bit challenge")
+}
+
+impl BitProof {
+    /// The statements C and C - U, one of which is z H
+    fn statements(commitment: &Point, u: &Point) -> [Point; 2] {
+        [commitment.clone(), sub(commitment, u)]
+    }
+
+    fn prove<R: Rng + ?Sized>(
+        transcript: &mut Transcript,
+        commitment: &Point,
+        u: &Point,
+        h: &Point,
+        bit: bool,
+        blinding: &Fn,
+        rng: &mut R,
+    ) -> Self {
+        let statements = Self::statements(commitment, u);
+        let (real, fake) = (bit as usize, !bit as usize);
+
+        // the branch that isn't true is simulated from a chosen challenge
+        let mut challenges = [Fn::zero(), Fn::zero()];
+        let mut responses = [Fn::zero(), Fn::zero()];
+        challenges[fake] = random_scalar(rng);
+        responses[fake] = random_scalar(rng);
+        let nonce = random_scalar(rng);
+        let mut nonce_points = [infinity(), infinity()];
+        nonce_points[real] = point_mul(&nonce, h);
+        nonce_points[fake] = point_add(
+            &point_mul(&responses[fake], h),
+            &point_mul(&challenges[fake], &statements[fake]),
+        );
+
+        let challenge = bit_challenge(transcript, commitment, &nonce_points);
+        challenges[real] = challenge - challenges[fake].clone();
+        responses[real] = nonce - challenges[real].clone() * blinding.clone();
+        BitProof {
+            challenges,
+            responses,
+        }
+    }
+
+    fn verify(
+        &self,
+        transcript: &mut Transcript,
+        commitment: &Point,
+        u: &Point,
+        h: &Point,
+    ) -> bool {
+        let statements = Self::statements(commitment, u);
+        let nonce_points = [0, 1].map(|i| {
+            point_add(
+                &point_mul(&self.responses[i], h),
+                &point_mul(&self.challenges[i], &statements[i]),
+            )
+        });
+        self.challenges[0].clone() + self.challenges[1].clone()
+            == bit_challenge(transcript, commitment, &nonce_points)
+    }
+}
+
+/// Which attribute is an amount and how many bits it may have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountRange {
+    pub attribute: usize,
+    pub bits: u32,
+}
+
+/// Proof that an attribute commitment holds a value below 2^bits: it is the
+/// sum of 2^j times commitments to bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeProof {
+    bit_commitments: Vec<Point>,
+    bit_proofs: Vec<BitProof>,
+}
+
+/// The transcript every bit proof of a range proof goes into, in order
+fn range_transcript(commitment: &Point, u: &Point, bits: u32) -> Transcript {
+    let mut transcript = Transcript::new(RANGE_DOMAIN);
+    transcript.append_point(b"commitment", commitment);
+    transcript.append_point(b"U", u);
+    transcript.append_u64(b"bits", bits as u64);
+    transcript
+}
+
+impl RangeProof {
+    /// None if `value` doesn't fit in `bits` bits
+    fn prove<R: Rng + ?Sized>(
+        value: &Fn,
+        blinding: &Fn,
+        commitment: &Point,
+        u: &Point,
+        h: &Point,
+        bits: u32,
+        rng: &mut R,
+    ) -> Option<Self> {
+        let value = value.as_ru256();
+        if bits == 0 || value.v.bits() > bits as usize {
+            return None;
+        }
+        // blindings weighted by 2^j add up to the attribute's blinding, the
+        // top one takes what's left
+        let mut blindings: Vec<Fn> = (1..bits).map(|_| random_scalar(rng)).collect();
+        let weighted = blindings
+            .iter()
+            .enumerate()
+            .fold(Fn::zero(), |sum, (j, z)| sum + power_of_two(j) * z.clone());
+        let top = power_of_two(bits as usize - 1).inv().unwrap();
+        blindings.push((blinding.clone() - weighted) * top);
+
+        let mut transcript = range_transcript(commitment, u, bits);
+        let mut bit_commitments = vec![];
+        let mut bit_proofs = vec![];
+        for (j, z) in blindings.iter().enumerate() {
+            let bit = value.v.bit(j);
+            let bit_commitment = match bit {
+                true => point_add(u, &point_mul(z, h)),
+                false => point_mul(z, h),
+            };
+            bit_proofs.push(BitProof::prove(
+                &mut transcript,
+                &bit_commitment,
+                u,
+                h,
+                bit,
+                z,
+                rng,
+            ));
+            bit_commitments.push(bit_commitment);
+        }
+        Some(RangeProof {
+            bit_commitments,
+            bit_proofs,
+        })
+    }
+
+    fn verify(&self, commitment: &Point, u: &Point, h: &Point, bits: u32) -> bool {
+        if self.bit_commitments.len() != bits as usize
+            || self.bit_proofs.len() != bits as usize
+            || !on_curve(&self.bit_commitments)
+        {
+            return false;
+        }
+        // sum 2^j Cj by Horner's rule from the top bit
+        let sum = self
+            .bit_commitments
+            .iter()
+            .rev()
+            .fold(infinity(), |sum, c| {
+                point_add(&SECP256K1::double_point(&sum), c)
+            });
+        let mut transcript = range_transcript(commitment, u, bits);
+        sum == *commitment
+            && self
+                .bit_commitments
+                .iter()
+                .zip(&self.bit_proofs)
+                .all(|(c, proof)| proof.verify(&mut transcript, c, u, h))
+    }
+}
+
+fn power_of_two(j: usize) -> Fn {
+    Fn::new(&RU256::from_u64(2).exp_mod(&RU256::from_u64(j as u64), &SECP256K1::n()))
+}
+
+/// What the issuer publishes
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssuerParams {
+    /// Cx0 = x0 G + x0~ H
+    pub cx0: Point,
+    /// Xi = xi H, one per attribute
+    pub x: Vec<Point>,
+}
+
+/// A MAC_GGM key for credentials with a fixed number of attributes
+#[derive(Debug, Clone)]
+pub struct Issuer {
+    x0: Fn,
+    x0_blinding: Fn,
+    x: Vec<Fn>,
+    params: IssuerParams,
+}
+
+/// A MAC the issuer hands out, with the proof that it's under the published
+/// key
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssuanceResponse {
+    pub u: Point,
+    pub u_prime: Point,
+    pub proof: Proof,
+}
+
+/// The relations an issued MAC satisfies, secrets x0, x0~, x1 .. xn
+fn issuance_relations(
+    params: &IssuerParams,
+    attributes: &[Fn],
+    u: &Point,
+    u_prime: &Point,
+) -> Vec<Relation> {
+    let (g, h) = (SECP256K1::g(), generator_h());
+    let mut relations = vec![Relation::new(
+        params.cx0.clone(),
+        vec![(0, g), (1, h.clone())],
+    )];
+    for (i, xi) in params.x.iter().enumerate() {
+        relations.push(Relation::new(xi.clone(), vec![(2 + i, h.clone())]));
+    }
+    let mut mac_terms = vec![(0, u.clone())];
+    for (i, m) in attributes.iter().enumerate() {
+        mac_terms.push((2 + i, point_mul(m, u)));
+    }
+    relations.push(Relation::new(u_prime.clone(), mac_terms));
+    relations
+}
+
+impl Issuer {
+    pub fn new<R: Rng + ?Sized>(attributes: usize, rng: &mut R) -> Self {
+        let h = generator_h();
+        let x0 = random_scalar(rng);
+        let x0_blinding = random_scalar(rng);
+        let x: Vec<Fn> = (0..attributes).map(|_| random_scalar(rng)).collect();
+        let params = IssuerParams {
+            cx0: point_add(
+                &point_mul(&x0, &SECP256K1::g()),
+                &point_mul(&x0_blinding, &h),
+            ),
+            x: x.iter().map(|xi| point_mul(xi, &h)).collect(),
+        };
+        Issuer {
+            x0,
+            x0_blinding,
+            x,
+            params,
+        }
+    }
+
+    pub fn params(&self) -> &IssuerParams {
+        &self.params
+    }
+
+    /// MAC the attributes, which the issuer sees in the clear
+    pub fn issue<R: Rng + ?Sized>(&self, attributes: &[Fn], rng: &mut R) -> IssuanceResponse {
+        assert_eq!(attributes.len(), self.x.len(), "wrong number of attributes");
+        let u = point_mul(&random_scalar(rng), &SECP256K1::g());
+        let exponent = attributes
+            .iter()
+            .zip(&self.x)
+            .fold(self.x0.clone(), |sum, (m, xi)| sum + m.clone() * xi.clone());
+        let u_prime = point_mul(&exponent, &u);
+
+        let relations = issuance_relations(&self.params, attributes, &u, &u_prime);
+        let mut secrets = vec![self.x0.clone(), self.x0_blinding.clone()];
+        secrets.extend(self.x.iter().cloned());
+        IssuanceResponse {
+            proof: Proof::prove(
+                &mut Transcript::new(ISSUANCE_DOMAIN),
+                &relations,
+                &secrets,
+                rng,
+            ),
+            u,
+            u_prime,
+        }
+    }
+
+    /// Check a presentation, and the amount range if one is asked for
+    pub fn verify(&self, presentation: &Presentation, range: Option<AmountRange>) -> bool {
+        let Presentation {
+            u,
+            attribute_commitments,
+            u_prime_commitment,
+            ..
+        } = presentation;
+        if attribute_commitments.len() != self.x.len()
+            || !on_curve(
+                [u, u_prime_commitment]
+                    .into_iter()
+                    .chain(attribute_commitments),
+            )
+        {
+            return false;
+        }
+        // V = x0 U + sum xi Cmi - C_U'
+        let v = attribute_commitments
+            .iter()
+            .zip(&self.x)
+            .fold(point_mul(&self.x0, u), |sum, (c, xi)| {
+                point_add(&sum, &point_mul(xi, c))
+            });
+        let v = sub(&v, u_prime_commitment);
+        let relations = presentation_relations(&self.params, u, attribute_commitments, v);
+        if !presentation
+            .proof
+            .verify(&mut Transcript::new(PRESENTATION_DOMAIN), &relations)
+        {
+            return false;
+        }
+
+        match (range, &presentation.range_proof) {
+            (None, _) => true,
+            (Some(range), Some(proof)) => attribute_commitments
+                .get(range.attribute)
+                .is_some_and(|c| proof.verify(c, u, &generator_h(), range.bits)),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// A credential held by a user
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credential {
+    pub attributes: Vec<Fn>,
+    u: Point,
+    u_prime: Point,
+}
+
+/// A credential shown without its attributes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Presentation {
+    pub u: Point,
+    /// Cmi = mi U + zi H
+    pub attribute_commitments: Vec<Point>,
+    /// C_U' = U' + r G
+    pub u_prime_commitment: Point,
+    proof: Proof,
+    range_proof: Option<RangeProof>,
+}
+
+/// The relations of a presentation, secrets m1 .. mn, z1 .. zn and -r
+fn presentation_relations(
+    params: &IssuerParams,
+    u: &Point,
+    attribute_commitments: &[Point],
+    v: Point,
+) -> Vec<Relation> {
+    let (h, n) = (generator_h(), params.x.len());
+    let mut relations: Vec<Relation> = attribute_commitments
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Relation::new(c.clone(), vec![(i, u.clone()), (n + i, h.clone())]))
+        .collect();
+    let mut v_terms: Vec<(usize, Point)> = params
+        .x
+        .iter()
+        .enumerate()
+        .map(|(i, xi)| (n + i, xi.clone()))
+        .collect();
+    v_terms.push((2 * n, SECP256K1::g()));
+    relations.push(Relation::new(v, v_terms));
+    relations
+}
+
+impl Credential {
+    /// Accept an issued MAC, None unless it's proven to be under `params`
+    pub fn receive(
+        params: &IssuerParams,
+        attributes: Vec<Fn>,
+        response: &IssuanceResponse,
+    ) -> Option<Self> {
+        if attributes.len() != params.x.len()
+            || !on_curve(
+                [&response.u, &response.u_prime, &params.cx0]
+                    .into_iter()
+                    .chain(&params.x),
+            )
+        {
+            return None;
+        }
+        let relations = issuance_relations(params, &attributes, &response.u, &response.u_prime);
+        response
+            .proof
+            .verify(&mut Transcript::new(ISSUANCE_DOMAIN), &relations)
+            .then(|| Credential {
+                attributes,
+                u: response.u.clone(),
+                u_prime: response.u_prime.clone(),
+            })
+    }
+
+    /// Show the credential, proving the amount attribute in `range` if
+    /// given. None if the amount doesn't fit.
+    pub fn present<R: Rng + ?Sized>(
+        &self,
+        params: &IssuerParams,
+        range: Option<AmountRange>,
+        rng: &mut R,
+    ) -> Option<Presentation> {
+        let h = generator_h();
+        // a fresh multiple of the MAC, so two shows can't be linked by U
+        let a = random_scalar(rng);
+        let (u, u_prime) = (point_mul(&a, &self.u), point_mul(&a, &self.u_prime));
+
+        let blindings: Vec<Fn> = self.attributes.iter().map(|_| random_scalar(rng)).collect();
+        let r = random_scalar(rng);
+        let attribute_commitments: Vec<Point> = self
+            .attributes
+            .iter()
+            .zip(&blindings)
+            .map(|(m, z)| point_add(&point_mul(m, &u), &point_mul(z, &h)))
+            .collect();
+        let u_prime_commitment = point_add(&u_prime, &point_mul(&r, &SECP256K1::g()));
+
+        let range_proof = match range {
+            Some(range) => Some(RangeProof::prove(
+                self.attributes.get(range.attribute)?,
+                &blindings[range.attribute],
+                &attribute_commitments[range.attribute],
+                &u,
+                &h,
+                range.bits,
+                rng,
+            )?),
+            None => None,
+        };
+
+        // V = sum zi Xi - r G, what the issuer computes with its key
+        let v = blindings
+            .iter()
+            .zip(&params.x)
+            .fold(point_mul(&-r.clone(), &SECP256K1::g()), |sum, (z, xi)| {
+                point_add(&sum, &point_mul(z, xi))
+            });
+        let relations = presentation_relations(params, &u, &attribute_commitments, v);
+        let mut secrets = self.attributes.clone();
+        secrets.extend(blindings);
+        secrets.push(-r);
+        Some(Presentation {
+            proof: Proof::prove(
+                &mut Transcript::new(PRESENTATION_DOMAIN),
+                &relations,
+                &secrets,
+                rng,
+            ),
+            u,
+            attribute_commitments,
+            u_prime_commitment,
+            range_proof,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_issue_and_present() {
+        let mut rng = StdRng::seed_from_u64(4196);
+        let issuer = Issuer::new(2, &mut rng);
+        let attributes = vec![amount(200), Fn::new(&RU256::from_u64(0xcafe))];
+        let response = issuer.issue(&attributes, &mut rng);
+        let credential = Credential::receive(issuer.params(), attributes, &response).unwrap();
+
+        let range = AmountRange {
+            attribute: 0,
+            bits: 8,
+        };
+        let presentation = credential
+            .present(issuer.params(), Some(range), &mut rng)
+            .unwrap();
+        assert_ne!(presentation.u, response.u);
+        assert!(issuer.verify(&presentation, Some(range)));
+        // the range proof is for 8 bits and nothing else
+        assert!(!issuer.verify(&presentation, Some(AmountRange { bits: 9, ..range })));
+
+        // a MAC the issuer didn't make
+        let mut forged = presentation.clone();
+        forged.u_prime_commitment = point_add(&forged.u_prime_commitment, &SECP256K1::g());
+        assert!(!issuer.verify(&forged, None));
+
+        // points off the curve never get multiplied by the issuer's key, or
+        // by the holder's secrets
+        let g = SECP256K1::g();
+        let off_curve = Point {
+            x: g.x.clone(),
+            y: g.y.add_mod(&RU256::one(), &SECP256K1::p()),
+        };
+        let mut forged = presentation.clone();
+        forged.u = off_curve.clone();
+        assert!(!issuer.verify(&forged, None));
+        let mut forged = presentation.clone();
+        forged.attribute_commitments[1] = off_curve.clone();
+        assert!(!issuer.verify(&forged, None));
+        let mut forged = response.clone();
+        forged.u = off_curve;
+        let attributes = credential.attributes.clone();
+        assert_eq!(
+            Credential::receive(issuer.params(), attributes, &forged),
+            None
+        );
+    }
+
+    #[test]
+    fn test_amount_out_of_range() {
+        let mut rng = StdRng::seed_from_u64(4197);
+        let issuer = Issuer::new(1, &mut rng);
+        let response = issuer.issue(&[amount(300)], &mut rng);
+        let credential =
+            Credential::receive(issuer.params(), vec![amount(300)], &response).unwrap();
+        let range = AmountRange {
+            attribute: 0,
+            bits: 8,
+        };
+        assert_eq!(
+            credential.present(issuer.params(), Some(range), &mut rng),
+            None
+        );
+
+        // a MAC under some other key doesn't pass for the issuer's
+        let other = Issuer::new(1, &mut rng);
+        assert_eq!(
+            Credential::receive(
+                issuer.params(),
+                vec![amount(300)],
+                &other.issue(&[amount(300)], &mut rng)
+            ),
+            None
+        );
+    }
+}
@@ -1,11 +1,108 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// The crypto core (ru256, secp256k1, sha256, ripemd160, keys and signature)
+// only needs `alloc`, everything touching files or the network is behind the
+// default `std` feature and key generation and signing behind `rand`.
+extern crate alloc;
+
+#[macro_use]
+mod log;
+
+pub mod amount;
+#[cfg(feature = "std")]
+pub mod attacks;
+pub mod bech32;
+pub mod bip21;
+pub mod bip32;
+#[cfg(feature = "std")]
 pub mod bitcoin;
+#[cfg(feature = "std")]
+pub mod blind_schnorr;
+#[cfg(feature = "std")]
 pub mod block;
+#[cfg(feature = "std")]
+pub mod bloom;
+#[cfg(feature = "std")]
+pub mod brainwallet;
+#[cfg(feature = "std")]
+pub mod broadcast;
+#[cfg(feature = "std")]
+pub mod coinjoin;
+#[cfg(feature = "std")]
+pub mod compact_block;
+#[cfg(all(test, feature = "conformance"))]
+mod conformance;
+pub mod consistency;
+pub mod curve;
+#[cfg(feature = "std")]
+pub mod decode;
+#[cfg(feature = "std")]
+pub mod descriptor;
+pub mod ed25519;
+pub mod encoding;
+pub mod field;
+pub mod hashes;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod index;
+#[cfg(feature = "std")]
+pub mod interpreter;
 pub mod keys;
+#[cfg(feature = "std")]
+pub mod kvac;
+#[cfg(feature = "std")]
+pub mod lightning;
+#[cfg(feature = "std")]
+pub mod lnurl;
+#[cfg(feature = "std")]
+pub mod mempool;
+#[cfg(feature = "std")]
+pub mod mint_commitment;
+#[cfg(feature = "std")]
+pub mod mint_info;
+pub mod murmur3;
+#[cfg(feature = "std")]
 pub mod network;
+pub mod paper;
+#[cfg(feature = "std")]
+pub mod payjoin;
+pub mod payment_request;
+#[cfg(feature = "std")]
+pub mod policy;
+#[cfg(feature = "std")]
+pub mod psbt;
+#[cfg(feature = "qr")]
+pub mod qr;
 pub mod ripemd160;
 pub mod ru256;
 pub mod secp256k1;
 pub mod sha256;
+pub mod shamir;
+#[cfg(feature = "std")]
+pub mod sigma;
 pub mod signature;
+#[cfg(feature = "std")]
+pub mod signer;
+#[cfg(feature = "std")]
+pub mod silent_payments;
+#[cfg(feature = "std")]
+pub mod simulator;
+pub mod slip39;
+#[cfg(test)]
+mod strategies;
+#[cfg(feature = "std")]
 pub mod transaction;
+pub mod transcript;
+#[cfg(feature = "std")]
 pub mod utils;
+#[cfg(feature = "std")]
+pub mod versionbits;
+#[cfg(feature = "std")]
+pub mod wallet;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod watcher;
+#[cfg(feature = "std")]
+pub mod weak_rng;
@@ -0,0 +1,1079 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::blocking::Client;
+use reqwest::{Certificate, Method};
+use serde_json::{json, Value};
+
+use crate::bech32::{convert_bits, decode_unlimited, encode, Variant};
+use crate::encoding::{base64_decode, base64_encode};
+use crate::lnurl::invoice_amount_msat;
+use crate::sha256::sha256;
+
+// What a mint needs from a lightning node: invoices to be paid when minting
+// ecash, and paying invoices when melting it. `LightningBackend` is that
+// interface, with a fake behind it for the classroom, where there's no node:
+// its invoices can be paid instantly or on demand, its payments can be made
+// to fail or hang, and everything is deterministic so exercises replay the
+// same. The fake's invoices are BOLT11 shaped, with the amount and payment
+// hash real wallets read, but carry a zero signature. For demos on regtest
+// with real payments, `LndRest` and `ClnRpc` talk to LND's REST API and Core
+// Lightning's JSON-RPC socket. Amounts are in millisatoshis, like the protocol.
+
+/// Regtest invoices, the fake's payments never leave the process
+const FAKE_HRP: &str = "lnbcrt";
+/// Timestamp of the fake's first invoice, each later one is a second after
+const FAKE_TIME: u64 = 1700000000;
+/// Seconds invoices are valid for
+const INVOICE_EXPIRY: u64 = 3600;
+
+// BOLT11 tagged field types
+const TAG_PAYMENT_HASH: u8 = 1;
+const TAG_EXPIRY: u8 = 6;
+const TAG_DESCRIPTION: u8 = 13;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightningError {
+    /// Not a BOLT11 invoice, or one without an amount
+    InvalidInvoice,
+    /// The routing fee would be more than allowed
+    FeeTooHigh { fee_msat: u64, max_fee_msat: u64 },
+    /// The invoice was already paid, or a payment of it is in flight
+    AlreadyPaid,
+    /// No invoice or payment with that payment hash
+    Unknown,
+    /// The node couldn't be reached or answered with an error
+    Transport(String),
+    /// The backend doesn't implement this yet
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for LightningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightningError::InvalidInvoice => write!(f, "invalid invoice"),
+            LightningError::FeeTooHigh {
+                fee_msat,
+                max_fee_msat,
+            } => write!(
+                f,
+                "fee of {} msat is over the maximum of {} msat",
+                fee_msat, max_fee_msat
+            ),
+            LightningError::AlreadyPaid => write!(f, "invoice already paid"),
+            LightningError::Unknown => write!(f, "unknown payment hash"),
+            LightningError::Transport(reason) => write!(f, "node error: {}", reason),
+            LightningError::Unsupported(what) => write!(f, "{} isn't supported yet", what),
+        }
+    }
+}
+
+impl std::error::Error for LightningError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    pub bolt11: String,
+    pub payment_hash: [u8; 32],
+    pub amount_msat: u64,
+    /// Unix time after which it can't be paid
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceStatus {
+    Unpaid,
+    Paid,
+    Expired,
+}
+
+/// A payment that went through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub payment_hash: [u8; 32],
+    /// Proof of payment, hashes to the payment hash
+    pub preimage: [u8; 32],
+    pub amount_msat: u64,
+    pub fee_msat: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentStatus {
+    /// Still in flight, it may yet succeed or fail
+    Pending,
+    Succeeded(Payment),
+    /// Nothing was paid, with the node's reason
+    Failed(String),
+}
+
+/// A lightning node as a mint uses it
+pub trait LightningBackend {
+    /// An invoice for `amount_msat`
+    fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+    ) -> Result<Invoice, LightningError>;
+
+    fn invoice_status(&self, payment_hash: &[u8; 32]) -> Result<InvoiceStatus, LightningError>;
+
+    /// Pay `bolt11`, spending at most `max_fee_msat` on routing. A payment
+    /// that doesn't settle right away comes back `Pending`.
+    fn pay_invoice(&self, bolt11: &str, max_fee_msat: u64)
+        -> Result<PaymentStatus, LightningError>;
+
+    fn payment_status(&self, payment_hash: &[u8; 32]) -> Result<PaymentStatus, LightningError>;
+}
+
+/// The tagged fields of a BOLT11 invoice, each type with its 5 bit groups
+fn tagged_fields(bolt11: &str) -> Option<Vec<(u8, Vec<u8>)>> {
+    let (hrp, data, _) = decode_unlimited(bolt11)?;
+    if !hrp.starts_with("ln") {
+        return None;
+    }
+    // a 35 bit timestamp, the tagged fields, then a 520 bit signature
+    let mut fields = data.get(7..data.len().checked_sub(104)?)?;
+    let mut tagged = vec![];
+    while fields.len() >= 3 {
+        let length = (fields[1] as usize) << 5 | fields[2] as usize;
+        tagged.push((fields[0], fields.get(3..3 + length)?.to_vec()));
+        fields = &fields[3 + length..];
+    }
+    Some(tagged)
+}
+
+/// The payment hash of a BOLT11 invoice, None if it isn't one or has none
+pub fn invoice_payment_hash(bolt11: &str) -> Option<[u8; 32]> {
+    // a payment hash of any other length is skipped, per BOLT11
+    let (_, hash) = tagged_fields(bolt11)?
+        .into_iter()
+        .find(|(tag, value)| *tag == TAG_PAYMENT_HASH && value.len() == 52)?;
+    convert_bits(&hash, 5, 8, false)?.try_into().ok()
+}
+
+/// The amount in a BOLT11 human readable part, with the largest multiplier
+/// that divides it
+fn encode_amount(amount_msat: u64) -> String {
+    let multipliers = [("m", 100_000_000), ("u", 100_000), ("n", 100)];
+    for (multiplier, msat) in multipliers {
+        if amount_msat.is_multiple_of(msat) {
+            return format!("{}{}", amount_msat / msat, multiplier);
+        }
+    }
+    format!("{}p", amount_msat * 10)
+}
+
+/// An integer field's value, big endian 5 bit groups without leading zeros
+fn int_groups(mut value: u64) -> Vec<u8> {
+    let mut groups = vec![];
+    while value > 0 {
+        groups.push(value as u8 & 31);
+        value >>= 5;
+    }
+    groups.reverse();
+    groups
+}
+
+/// Append a tagged field, its 5 bit type and 10 bit length then the value
+fn push_field(data: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    data.extend([tag, (value.len() >> 5) as u8, (value.len() & 31) as u8]);
+    data.extend(value);
+}
+
+/// A BOLT11 encoded invoice with a zero signature
+fn fake_bolt11(
+    amount_msat: u64,
+    timestamp: u64,
+    payment_hash: &[u8; 32],
+    description: &str,
+) -> String {
+    let hrp = format!("{}{}", FAKE_HRP, encode_amount(amount_msat));
+    let mut data: Vec<u8> = (0..7)
+        .rev()
+        .map(|i| (timestamp >> (5 * i)) as u8 & 31)
+        .collect();
+    push_field(
+        &mut data,
+        TAG_PAYMENT_HASH,
+        &convert_bits(payment_hash, 8, 5, true).unwrap(),
+    );
+    push_field(
+        &mut data,
+        TAG_DESCRIPTION,
+        &convert_bits(description.as_bytes(), 8, 5, true).unwrap(),
+    );
+    push_field(&mut data, TAG_EXPIRY, &int_groups(INVOICE_EXPIRY));
+    data.extend([0; 104]);
+    encode(&hrp, &data, Variant::Bech32)
+}
+
+/// How the fake's next payments go wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureMode {
+    /// They fail without paying anything
+    NoRoute,
+    /// They stay in flight until resolved with `resolve_payment`
+    Pending,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    /// Invoices created so far, which also picks the next preimage
+    created: u64,
+    invoices: HashMap<[u8; 32], (Invoice, [u8; 32], InvoiceStatus)>,
+    payments: HashMap<[u8; 32], (Payment, PaymentStatus)>,
+    balance_msat: u64,
+    failure: Option<FailureMode>,
+}
+
+impl FakeState {
+    /// A payment to one of our own invoices went through, so it's paid and
+    /// the amount comes back to us. Nothing if the invoice isn't ours or
+    /// was already settled.
+    fn settle_own(&mut self, payment_hash: &[u8; 32]) {
+        let Some((invoice, _, status)) = self.invoices.get_mut(payment_hash) else {
+            return;
+        };
+        if *status == InvoiceStatus::Unpaid {
+            *status = InvoiceStatus::Paid;
+            self.balance_msat += invoice.amount_msat;
+        }
+    }
+}
+
+/// A lightning node that exists only in memory
+#[derive(Debug)]
+pub struct FakeLightning {
+    /// Whether invoices count as paid as soon as they're created, else they
+    /// wait for `settle_invoice` or a payment from this same fake
+    pub auto_settle: bool,
+    /// Routing fee charged on every payment
+    pub fee_msat: u64,
+    state: RefCell<FakeState>,
+}
+
+impl FakeLightning {
+    /// A node with `balance_msat` to pay invoices with, whose invoices are
+    /// paid instantly and payments routed for free
+    pub fn new(balance_msat: u64) -> Self {
+        FakeLightning {
+            auto_settle: true,
+            fee_msat: 0,
+            state: RefCell::new(FakeState {
+                balance_msat,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn balance_msat(&self) -> u64 {
+        self.state.borrow().balance_msat
+    }
+
+    /// Make the following payments fail or hang, None to let them succeed
+    pub fn set_failure(&self, failure: Option<FailureMode>) {
+        self.state.borrow_mut().failure = failure;
+    }
+
+    /// Mark an unpaid invoice paid, as if someone outside paid it. False if
+    /// there's no such invoice or it's already paid.
+    pub fn settle_invoice(&self, payment_hash: &[u8; 32]) -> bool {
+        let mut state = self.state.borrow_mut();
+        let Some((invoice, _, status)) = state.invoices.get_mut(payment_hash) else {
+            return false;
+        };
+        if *status != InvoiceStatus::Unpaid {
+            return false;
+        }
+        *status = InvoiceStatus::Paid;
+        let amount_msat = invoice.amount_msat;
+        state.balance_msat += amount_msat;
+        true
+    }
+
+    /// Let a pending payment succeed or fail, false if it isn't pending
+    pub fn resolve_payment(&self, payment_hash: &[u8; 32], succeed: bool) -> bool {
+        let mut state = self.state.borrow_mut();
+        let Some((payment, status)) = state.payments.get_mut(payment_hash) else {
+            return false;
+        };
+        if *status != PaymentStatus::Pending {
+            return false;
+        }
+        let refund = payment.amount_msat + payment.fee_msat;
+        *status = match succeed {
+            true => PaymentStatus::Succeeded(payment.clone()),
+            false => PaymentStatus::Failed("payment timed out".to_string()),
+        };
+        match succeed {
+            true => state.settle_own(payment_hash),
+            false => state.balance_msat += refund,
+        }
+        true
+    }
+}
+
+impl LightningBackend for FakeLightning {
+    fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+    ) -> Result<Invoice, LightningError> {
+        let mut state = self.state.borrow_mut();
+        let mut seed = b"fake preimage".to_vec();
+        seed.extend(state.created.to_le_bytes());
+        let preimage: [u8; 32] = sha256(seed).try_into().unwrap();
+        let payment_hash: [u8; 32] = sha256(preimage.to_vec()).try_into().unwrap();
+        let timestamp = FAKE_TIME + state.created;
+        state.created += 1;
+
+        let invoice = Invoice {
+            bolt11: fake_bolt11(amount_msat, timestamp, &payment_hash, description),
+            payment_hash,
+            amount_msat,
+            expires_at: timestamp + INVOICE_EXPIRY,
+        };
+        let status = match self.auto_settle {
+            true => {
+                state.balance_msat += amount_msat;
+                InvoiceStatus::Paid
+            }
+            false => InvoiceStatus::Unpaid,
+        };
+        state
+            .invoices
+            .insert(payment_hash, (invoice.clone(), preimage, status));
+        Ok(invoice)
+    }
+
+    fn invoice_status(&self, payment_hash: &[u8; 32]) -> Result<InvoiceStatus, LightningError> {
+        let state = self.state.borrow();
+        let (_, _, status) = state
+            .invoices
+            .get(payment_hash)
+            .ok_or(LightningError::Unknown)?;
+        Ok(*status)
+    }
+
+    fn pay_invoice(
+        &self,
+        bolt11: &str,
+        max_fee_msat: u64,
+    ) -> Result<PaymentStatus, LightningError> {
+        let payment_hash = invoice_payment_hash(bolt11).ok_or(LightningError::InvalidInvoice)?;
+        let amount_msat = invoice_amount_msat(bolt11).ok_or(LightningError::InvalidInvoice)?;
+        if self.fee_msat > max_fee_msat {
+            return Err(LightningError::FeeTooHigh {
+                fee_msat: self.fee_msat,
+                max_fee_msat,
+            });
+        }
+        let mut state = self.state.borrow_mut();
+        let paid = match state.payments.get(&payment_hash) {
+            Some((_, PaymentStatus::Failed(_))) | None => false,
+            Some(_) => true,
+        };
+        let own = state.invoices.get(&payment_hash).cloned();
+        if paid
+            || own
+                .as_ref()
+                .is_some_and(|(_, _, status)| *status == InvoiceStatus::Paid)
+        {
+            return Err(LightningError::AlreadyPaid);
+        }
+
+        // the preimage of an invoice from elsewhere is a stand-in, it won't
+        // hash to the payment hash
+        let preimage = match &own {
+            Some((_, preimage, _)) => *preimage,
+            None => {
+                let mut seed = b"fake preimage".to_vec();
+                seed.extend(bolt11.as_bytes());
+                sha256(seed).try_into().unwrap()
+            }
+        };
+        let payment = Payment {
+            payment_hash,
+            preimage,
+            amount_msat,
+            fee_msat: self.fee_msat,
+        };
+        let status = if state.failure == Some(FailureMode::NoRoute) {
+            PaymentStatus::Failed("no route".to_string())
+        } else if state.balance_msat < amount_msat + self.fee_msat {
+            PaymentStatus::Failed("insufficient balance".to_string())
+        } else {
+            state.balance_msat -= amount_msat + self.fee_msat;
+            match state.failure {
+                Some(FailureMode::Pending) => PaymentStatus::Pending,
+                _ => {
+                    state.settle_own(&payment_hash);
+                    PaymentStatus::Succeeded(payment.clone())
+                }
+            }
+        };
+        state
+            .payments
+            .insert(payment_hash, (payment, status.clone()));
+        Ok(status)
+    }
+
+    fn payment_status(&self, payment_hash: &[u8; 32]) -> Result<PaymentStatus, LightningError> {
+        let state = self.state.borrow();
+        let (_, status) = state
+            .payments
+            .get(payment_hash)
+            .ok_or(LightningError::Unknown)?;
+        Ok(status.clone())
+    }
+}
+
+fn transport<E: fmt::Display>(err: E) -> LightningError {
+    LightningError::Transport(err.to_string())
+}
+
+/// A u64 field that may be a number, a string as LND sends 64 bit integers,
+/// or a string ending in `msat` as older Core Lightning does
+fn u64_field(json: &Value, key: &str) -> Option<u64> {
+    let value = json.get(key)?;
+    value.as_u64().or_else(|| {
+        let text = value.as_str()?;
+        text.strip_suffix("msat").unwrap_or(text).parse().ok()
+    })
+}
+
+fn str_field<'a>(json: &'a Value, key: &str) -> Option<&'a str> {
+    json.get(key)?.as_str()
+}
+
+fn hash_from_hex(hex_str: &str) -> Option<[u8; 32]> {
+    hex::decode(hex_str).ok()?.try_into().ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// gRPC's status code for a missing invoice or payment
+const LND_NOT_FOUND: u64 = 5;
+
+/// LND's REST API at `url`, e.g. `https://localhost:8080`
+#[derive(Debug, Clone)]
+pub struct LndRest {
+    pub url: String,
+    /// Hex encoded, the way the REST API takes it
+    macaroon: String,
+    client: Client,
+}
+
+impl LndRest {
+    /// A client authenticated with `macaroon`, the contents of LND's
+    /// admin.macaroon, that trusts `tls_cert`, the self-signed tls.cert
+    pub fn new(url: &str, macaroon: &[u8], tls_cert: &[u8]) -> Result<Self, LightningError> {
+        let cert = Certificate::from_pem(tls_cert).map_err(transport)?;
+        let client = Client::builder()
+            .add_root_certificate(cert)
+            .build()
+            .map_err(transport)?;
+        Ok(LndRest {
+            url: url.trim_end_matches('/').to_string(),
+            macaroon: hex::encode(macaroon),
+            client,
+        })
+    }
+
+    /// Call an endpoint, with a JSON body if there's one. Streaming endpoints
+    /// send an object per line, `{"result": ..}` or `{"error": ..}`, and only
+    /// the first is read.
+    fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, LightningError> {
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.url, path))
+            .header("Grpc-Metadata-macaroon", &self.macaroon);
+        if let Some(body) = body {
+            request = request.body(body.to_string());
+        }
+        let response = request.send().map_err(transport)?;
+        let mut line = String::new();
+        BufReader::new(response)
+            .read_line(&mut line)
+            .map_err(transport)?;
+        let json: Value = serde_json::from_str(&line).map_err(transport)?;
+        lnd_result(json)
+    }
+}
+
+/// The result in an LND response, or its error
+fn lnd_result(json: Value) -> Result<Value, LightningError> {
+    let error = json.get("error").unwrap_or(&json);
+    if let Some(message) = str_field(error, "message") {
+        return match u64_field(error, "code") {
+            Some(LND_NOT_FOUND) => Err(LightningError::Unknown),
+            _ => Err(LightningError::Transport(message.to_string())),
+        };
+    }
+    match json.get("result") {
+        Some(result) => Ok(result.clone()),
+        None => Ok(json),
+    }
+}
+
+/// The router's path for tracking a payment, which takes the hash as
+/// base64url where the v1 API takes hex
+fn track_path(payment_hash: &[u8; 32]) -> String {
+    let hash = base64_encode(payment_hash)
+        .replace('+', "-")
+        .replace('/', "_");
+    format!("/v2/router/track/{}", hash)
+}
+
+/// A payment as LND's router reports it
+fn lnd_payment(json: &Value) -> Option<PaymentStatus> {
+    let status = match str_field(json, "status")? {
+        "SUCCEEDED" => PaymentStatus::Succeeded(Payment {
+            payment_hash: hash_from_hex(str_field(json, "payment_hash")?)?,
+            preimage: hash_from_hex(str_field(json, "payment_preimage")?)?,
+            amount_msat: u64_field(json, "value_msat")?,
+            fee_msat: u64_field(json, "fee_msat")?,
+        }),
+        "FAILED" => PaymentStatus::Failed(str_field(json, "failure_reason")?.to_string()),
+        _ => PaymentStatus::Pending,
+    };
+    Some(status)
+}
+
+impl LightningBackend for LndRest {
+    fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+    ) -> Result<Invoice, LightningError> {
+        let body = json!({
+            "value_msat": amount_msat.to_string(),
+            "memo": description,
+            "expiry": INVOICE_EXPIRY.to_string(),
+        });
+        let json = self.call(Method::POST, "/v1/invoices", Some(body))?;
+        let invalid = || LightningError::Transport("invalid invoice response".to_string());
+        let payment_hash = str_field(&json, "r_hash")
+            .and_then(base64_decode)
+            .and_then(|hash| hash.try_into().ok())
+            .ok_or_else(invalid)?;
+        Ok(Invoice {
+            bolt11: str_field(&json, "payment_request")
+                .ok_or_else(invalid)?
+                .to_string(),
+            payment_hash,
+            amount_msat,
+            expires_at: now() + INVOICE_EXPIRY,
+        })
+    }
+
+    fn invoice_status(&self, payment_hash: &[u8; 32]) -> Result<InvoiceStatus, LightningError> {
+        let path = format!("/v1/invoice/{}", hex::encode(payment_hash));
+        let json = self.call(Method::GET, &path, None)?;
+        // LND cancels invoices once they expire
+        match str_field(&json, "state") {
+            Some("SETTLED") => Ok(InvoiceStatus::Paid),
+            Some("CANCELED") => Ok(InvoiceStatus::Expired),
+            _ => Ok(InvoiceStatus::Unpaid),
+        }
+    }
+
+    fn pay_invoice(
+        &self,
+        bolt11: &str,
+        max_fee_msat: u64,
+    ) -> Result<PaymentStatus, LightningError> {
+        let body = json!({
+            "payment_request": bolt11,
+            "fee_limit_msat": max_fee_msat.to_string(),
+            "timeout_seconds": 60,
+            "no_inflight_updates": true,
+        });
+        // without in-flight updates the stream's first line is the outcome
+        let json = self.call(Method::POST, "/v2/router/send", Some(body));
+        match json {
+            Ok(json) => lnd_payment(&json).ok_or_else(|| transport("invalid payment response")),
+            Err(LightningError::Transport(reason)) if reason.contains("already paid") => {
+                Err(LightningError::AlreadyPaid)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn payment_status(&self, payment_hash: &[u8; 32]) -> Result<PaymentStatus, LightningError> {
+        let json = self.call(Method::GET, &track_path(payment_hash), None)?;
+        lnd_payment(&json).ok_or_else(|| transport("invalid payment response"))
+    }
+}
+
+// Core Lightning's error codes from pay
+const CLN_PAY_IN_PROGRESS: u64 = 200;
+const CLN_PAY_RHASH_ALREADY_USED: u64 = 201;
+
+/// Core Lightning's JSON-RPC on the unix socket at `socket_path`, usually
+/// `~/.lightning/regtest/lightning-rpc`
+#[derive(Debug, Clone)]
+pub struct ClnRpc {
+    pub socket_path: PathBuf,
+}
+
+impl ClnRpc {
+    pub fn new(socket_path: &Path) -> Self {
+        ClnRpc {
+            socket_path: socket_path.to_path_buf(),
+        }
+    }
+
+    /// Call `method`, returning the whole response, with either a result or
+    /// an error in it
+    #[cfg(unix)]
+    fn call(&self, method: &str, params: Value) -> Result<Value, LightningError> {
+        let mut stream = UnixStream::connect(&self.socket_path).map_err(transport)?;
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        stream
+            .write_all(request.to_string().as_bytes())
+            .map_err(transport)?;
+        // the connection stays open, so read one JSON value rather than to the end
+        serde_json::Deserializer::from_reader(stream)
+            .into_iter()
+            .next()
+            .ok_or_else(|| transport("connection closed"))?
+            .map_err(transport)
+    }
+
+    #[cfg(not(unix))]
+    fn call(&self, _: &str, _: Value) -> Result<Value, LightningError> {
+        Err(LightningError::Unsupported(
+            "Core Lightning without unix sockets",
+        ))
+    }
+
+    /// Call `method` and take the result out of the response
+    fn result(&self, method: &str, params: Value) -> Result<Value, LightningError> {
+        let mut response = self.call(method, params)?;
+        if let Some(error) = response.get("error") {
+            let message = str_field(error, "message").unwrap_or("unknown error");
+            return Err(LightningError::Transport(message.to_string()));
+        }
+        response
+            .get_mut("result")
+            .map(Value::take)
+            .ok_or_else(|| transport("response without a result"))
+    }
+}
+
+/// A payment as Core Lightning's `pay` or `listpays` reports it
+fn cln_payment(json: &Value) -> Option<PaymentStatus> {
+    let status = match str_field(json, "status")? {
+        "complete" => {
+            let amount_msat = u64_field(json, "amount_msat")?;
+            // pay names the preimage payment_preimage, listpays just preimage
+            let preimage = str_field(json, "payment_preimage").or(str_field(json, "preimage"))?;
+            PaymentStatus::Succeeded(Payment {
+                payment_hash: hash_from_hex(str_field(json, "payment_hash")?)?,
+                preimage: hash_from_hex(preimage)?,
+                amount_msat,
+                fee_msat: u64_field(json, "amount_sent_msat")?.checked_sub(amount_msat)?,
+            })
+        }
+        "failed" => PaymentStatus::Failed("payment failed".to_string()),
+        _ => PaymentStatus::Pending,
+    };
+    Some(status)
+}
+
+impl LightningBackend for ClnRpc {
+    fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+    ) -> Result<Invoice, LightningError> {
+        // labels have to be unique
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let params = json!({
+            "amount_msat": amount_msat,
+            "label": format!("cryptos-{}", nanos),
+            "description": description,
+            "expiry": INVOICE_EXPIRY,
+        });
+        let json = self.result("invoice", params)?;
+        let invalid = || transport("invalid invoice response");
+        Ok(Invoice {
+            bolt11: str_field(&json, "bolt11").ok_or_else(invalid)?.to_string(),
+            payment_hash: str_field(&json, "payment_hash")
+                .and_then(hash_from_hex)
+                .ok_or_else(invalid)?,
+            amount_msat,
+            expires_at: u64_field(&json, "expires_at").ok_or_else(invalid)?,
+        })
+    }
+
+    fn invoice_status(&self, payment_hash: &[u8; 32]) -> Result<InvoiceStatus, LightningError> {
+        let params = json!({"payment_hash": hex::encode(payment_hash)});
+        let json = self.result("listinvoices", params)?;
+        let invoice = json.pointer("/invoices/0").ok_or(LightningError::Unknown)?;
+        match str_field(invoice, "status") {
+            Some("paid") => Ok(InvoiceStatus::Paid),
+            Some("expired") => Ok(InvoiceStatus::Expired),
+            _ => Ok(InvoiceStatus::Unpaid),
+        }
+    }
+
+    fn pay_invoice(
+        &self,
+        bolt11: &str,
+        max_fee_msat: u64,
+    ) -> Result<PaymentStatus, LightningError> {
+        let params = json!({"bolt11": bolt11, "maxfee": max_fee_msat});
+        let response = self.call("pay", params)?;
+        if let Some(error) = response.get("error") {
+            let message = str_field(error, "message").unwrap_or("payment failed");
+            return match u64_field(error, "code") {
+                Some(CLN_PAY_IN_PROGRESS) => Ok(PaymentStatus::Pending),
+                Some(CLN_PAY_RHASH_ALREADY_USED) => Err(LightningError::AlreadyPaid),
+                // pay can give up on a payment whose HTLCs are still out, so
+                // it only failed if listpays agrees
+                _ => {
+                    let payment_hash =
+                        invoice_payment_hash(bolt11).ok_or(LightningError::InvalidInvoice)?;
+                    match self.payment_status(&payment_hash) {
+                        Ok(PaymentStatus::Failed(_)) | Err(LightningError::Unknown) => {
+                            Ok(PaymentStatus::Failed(message.to_string()))
+                        }
+                        status => status,
+                    }
+                }
+            };
+        }
+        response
+            .get("result")
+            .and_then(cln_payment)
+            .ok_or_else(|| transport("invalid payment response"))
+    }
+
+    fn payment_status(&self, payment_hash: &[u8; 32]) -> Result<PaymentStatus, LightningError> {
+        let params = json!({"payment_hash": hex::encode(payment_hash)});
+        let json = self.result("listpays", params)?;
+        let pay = json.pointer("/pays/0").ok_or(LightningError::Unknown)?;
+        cln_payment(pay).ok_or_else(|| transport("invalid payment response"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the invoice from BOLT11's examples, paying 2500u with payment hash
+    // 0001020304050607080900010203040506070809000102030405060708090102
+    const INVOICE: &str = "lnbc2500u1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpuaztrnwngzn3kdzw5hydlzf03qdgm2hdq27cqv3agm2awhz5se903vruatfhq77w3ls4evs3ch9zw97j25emudupq63nyw24cg27h2rspfj9srp";
+
+    #[test]
+    fn test_invoice_payment_hash() {
+        assert_eq!(
+            hex::encode(invoice_payment_hash(INVOICE).unwrap()),
+            "0001020304050607080900010203040506070809000102030405060708090102"
+        );
+        assert_eq!(invoice_payment_hash("lnurl1dp68gurn8ghj7"), None);
+
+        assert_eq!(encode_amount(250_000_000), "2500u");
+        assert_eq!(encode_amount(1_000), "10n");
+        assert_eq!(encode_amount(1), "10p");
+    }
+
+    #[test]
+    fn test_fake_receive() {
+        let node = FakeLightning::new(0);
+        let invoice = node.create_invoice(21_000, "mint 21 sat").unwrap();
+        assert!(invoice.bolt11.starts_with("lnbcrt210n1"));
+        assert_eq!(invoice_amount_msat(&invoice.bolt11), Some(21_000));
+        assert_eq!(
+            invoice_payment_hash(&invoice.bolt11),
+            Some(invoice.payment_hash)
+        );
+        // the expiry wallets read is the one the invoice says
+        let (_, expiry) = tagged_fields(&invoice.bolt11)
+            .unwrap()
+            .into_iter()
+            .find(|(tag, _)| *tag == TAG_EXPIRY)
+            .unwrap();
+        assert_eq!(expiry.len(), 3);
+        let expiry = expiry.iter().fold(0, |n, &group| n << 5 | group as u64);
+        assert_eq!(expiry, INVOICE_EXPIRY);
+        assert_eq!(invoice.expires_at, FAKE_TIME + INVOICE_EXPIRY);
+        assert_eq!(
+            node.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Paid)
+        );
+        assert_eq!(node.balance_msat(), 21_000);
+
+        // the same calls give the same invoices
+        let again = FakeLightning::new(0).create_invoice(21_000, "mint 21 sat");
+        assert_eq!(again, Ok(invoice));
+
+        let node = FakeLightning {
+            auto_settle: false,
+            ..FakeLightning::new(0)
+        };
+        let invoice = node.create_invoice(1_000, "").unwrap();
+        assert_eq!(
+            node.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Unpaid)
+        );
+        assert!(node.settle_invoice(&invoice.payment_hash));
+        assert!(!node.settle_invoice(&invoice.payment_hash));
+        assert_eq!(node.balance_msat(), 1_000);
+        assert_eq!(node.invoice_status(&[0; 32]), Err(LightningError::Unknown));
+    }
+
+    #[test]
+    fn test_fake_pay() {
+        let node = FakeLightning {
+            fee_msat: 2_000,
+            ..FakeLightning::new(500_000_000)
+        };
+        assert_eq!(
+            node.pay_invoice(INVOICE, 1_000),
+            Err(LightningError::FeeTooHigh {
+                fee_msat: 2_000,
+                max_fee_msat: 1_000
+            })
+        );
+        assert_eq!(
+            node.pay_invoice("lnbc1invalid", 10_000),
+            Err(LightningError::InvalidInvoice)
+        );
+
+        node.set_failure(Some(FailureMode::NoRoute));
+        let status = node.pay_invoice(INVOICE, 10_000).unwrap();
+        assert_eq!(status, PaymentStatus::Failed("no route".to_string()));
+        assert_eq!(node.balance_msat(), 500_000_000);
+
+        // a failed payment can be retried
+        node.set_failure(Some(FailureMode::Pending));
+        assert_eq!(
+            node.pay_invoice(INVOICE, 10_000),
+            Ok(PaymentStatus::Pending)
+        );
+        assert_eq!(
+            node.pay_invoice(INVOICE, 10_000),
+            Err(LightningError::AlreadyPaid)
+        );
+        assert_eq!(node.balance_msat(), 250_000_000 - 2_000);
+        let payment_hash = invoice_payment_hash(INVOICE).unwrap();
+        assert!(node.resolve_payment(&payment_hash, true));
+        let PaymentStatus::Succeeded(payment) = node.payment_status(&payment_hash).unwrap() else {
+            panic!("payment should have succeeded");
+        };
+        assert_eq!(
+            (payment.amount_msat, payment.fee_msat),
+            (250_000_000, 2_000)
+        );
+
+        // paying one of our own invoices gets its real preimage
+        let node = FakeLightning {
+            auto_settle: false,
+            ..FakeLightning::new(10_000)
+        };
+        let invoice = node.create_invoice(5_000, "").unwrap();
+        let PaymentStatus::Succeeded(payment) = node.pay_invoice(&invoice.bolt11, 0).unwrap()
+        else {
+            panic!("payment should have succeeded");
+        };
+        assert_eq!(sha256(payment.preimage.to_vec()), invoice.payment_hash);
+        assert_eq!(
+            node.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Paid)
+        );
+        let invoice = node.create_invoice(20_000, "").unwrap();
+        assert_eq!(
+            node.pay_invoice(&invoice.bolt11, 0),
+            Ok(PaymentStatus::Failed("insufficient balance".to_string()))
+        );
+
+        // paying ourselves settles only once the payment goes through
+        node.set_failure(Some(FailureMode::Pending));
+        let invoice = node.create_invoice(3_000, "").unwrap();
+        assert_eq!(
+            node.pay_invoice(&invoice.bolt11, 0),
+            Ok(PaymentStatus::Pending)
+        );
+        assert_eq!(
+            node.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Unpaid)
+        );
+        assert_eq!(node.balance_msat(), 7_000);
+        assert!(node.resolve_payment(&invoice.payment_hash, false));
+        assert_eq!(
+            node.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Unpaid)
+        );
+        assert_eq!(node.balance_msat(), 10_000);
+
+        assert_eq!(
+            node.pay_invoice(&invoice.bolt11, 0),
+            Ok(PaymentStatus::Pending)
+        );
+        assert!(node.resolve_payment(&invoice.payment_hash, true));
+        assert_eq!(
+            node.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Paid)
+        );
+        assert_eq!(node.balance_msat(), 10_000);
+    }
+
+    #[test]
+    fn test_lnd_responses() {
+        let hash = "0001020304050607080900010203040506070809000102030405060708090102";
+        let sent = json!({"result": {
+            "payment_hash": hash,
+            "payment_preimage": "11".repeat(32),
+            "value_msat": "250000000",
+            "fee_msat": "1500",
+            "status": "SUCCEEDED",
+            "failure_reason": "FAILURE_REASON_NONE",
+        }});
+        let payment = lnd_result(sent).ok().and_then(|json| lnd_payment(&json));
+        assert_eq!(
+            payment,
+            Some(PaymentStatus::Succeeded(Payment {
+                payment_hash: hash_from_hex(hash).unwrap(),
+                preimage: [0x11; 32],
+                amount_msat: 250_000_000,
+                fee_msat: 1_500,
+            }))
+        );
+        let failed = json!({"status": "FAILED", "failure_reason": "FAILURE_REASON_NO_ROUTE"});
+        assert_eq!(
+            lnd_payment(&failed),
+            Some(PaymentStatus::Failed("FAILURE_REASON_NO_ROUTE".to_string()))
+        );
+        assert_eq!(
+            lnd_payment(&json!({"status": "IN_FLIGHT"})),
+            Some(PaymentStatus::Pending)
+        );
+
+        // hashes go in the track path url safe
+        assert_eq!(
+            track_path(&[0xfb, 0xff].repeat(16).try_into().unwrap()),
+            "/v2/router/track/-__7__v_-__7__v_-__7__v_-__7__v_-__7__v_-_8="
+        );
+
+        let missing = json!({"error": {"code": 5, "message": "payment isn't initiated"}});
+        assert_eq!(lnd_result(missing), Err(LightningError::Unknown));
+        let denied = json!({"code": 2, "message": "permission denied", "details": []});
+        assert_eq!(
+            lnd_result(denied),
+            Err(LightningError::Transport("permission denied".to_string()))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cln_rpc() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("lightning-rpc-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        // a node that answers each call with a canned response
+        let node = std::thread::spawn(move || {
+            let responses = [
+                json!({"result": {
+                    "payment_hash": "22".repeat(32),
+                    "bolt11": "lnbcrt10u1fake",
+                    "expires_at": 1700003600,
+                }}),
+                json!({"result": {"invoices": [{"status": "paid"}]}}),
+                json!({"error": {"code": 205, "message": "Ran out of routes to try"}}),
+                json!({"result": {"pays": [{"status": "failed"}]}}),
+                json!({"error": {"code": 200, "message": "Already in progress"}}),
+                json!({"error": {"code": 201, "message": "Already paid"}}),
+                // pay stopped retrying, but a part got through
+                json!({"error": {"code": 210, "message": "Stopped retrying"}}),
+                json!({"result": {"pays": [{
+                    "payment_hash": "22".repeat(32),
+                    "preimage": "33".repeat(32),
+                    "amount_msat": 1_000_000,
+                    "amount_sent_msat": 1_001_000,
+                    "status": "complete",
+                }]}}),
+                json!({"result": {"pays": []}}),
+            ];
+            let mut methods = vec![];
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request: Value = serde_json::Deserializer::from_reader(&stream)
+                    .into_iter()
+                    .next()
+                    .unwrap()
+                    .unwrap();
+                methods.push(request["method"].as_str().unwrap().to_string());
+                stream.write_all(response.to_string().as_bytes()).unwrap();
+            }
+            methods
+        });
+
+        let cln = ClnRpc::new(&path);
+        let invoice = cln.create_invoice(1_000_000, "mint 1000 sat").unwrap();
+        assert_eq!(invoice.payment_hash, [0x22; 32]);
+        assert_eq!(invoice.expires_at, 1700003600);
+        assert_eq!(
+            cln.invoice_status(&invoice.payment_hash),
+            Ok(InvoiceStatus::Paid)
+        );
+        assert_eq!(
+            cln.pay_invoice(INVOICE, 1_000),
+            Ok(PaymentStatus::Failed(
+                "Ran out of routes to try".to_string()
+            ))
+        );
+        assert_eq!(cln.pay_invoice(INVOICE, 1_000), Ok(PaymentStatus::Pending));
+        assert_eq!(
+            cln.pay_invoice(INVOICE, 1_000),
+            Err(LightningError::AlreadyPaid)
+        );
+        let PaymentStatus::Succeeded(payment) = cln.pay_invoice(INVOICE, 1_000).unwrap() else {
+            panic!("payment should have succeeded");
+        };
+        assert_eq!((payment.preimage, payment.fee_msat), ([0x33; 32], 1_000));
+        assert_eq!(
+            cln.payment_status(&[0x44; 32]),
+            Err(LightningError::Unknown)
+        );
+
+        let methods = node.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            methods,
+            [
+                "invoice",
+                "listinvoices",
+                "pay",
+                "listpays",
+                "pay",
+                "pay",
+                "pay",
+                "listpays",
+                "listpays"
+            ]
+        );
+    }
+}
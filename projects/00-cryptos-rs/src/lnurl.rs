@@ -0,0 +1,454 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::bech32::{convert_bits, decode_unlimited};
+
+// LNURL client: turning a lightning address (`user@domain`, LUD-16) or an
+// `lnurl1...` string (LUD-01) into a service URL, then running the pay
+// (LUD-06) or withdraw (LUD-03) flow against it. Paying asks the service's
+// callback for an invoice of the chosen amount, which is what an ecash wallet
+// needs to melt tokens to a lightning address. Services answer errors with
+// `{"status": "ERROR", "reason": ...}` which comes back as `Service`.
+// Amounts are in millisatoshis throughout, like the protocol.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LnurlError {
+    /// Not a lightning address, LNURL or URL
+    InvalidInput,
+    /// The service couldn't be reached or didn't return JSON
+    Transport(String),
+    /// The service answered with an error
+    Service(String),
+    /// The response is missing fields or isn't the expected kind
+    InvalidResponse(String),
+    AmountOutOfRange {
+        amount: u64,
+        min: u64,
+        max: u64,
+    },
+    /// The invoice from the callback isn't for the amount asked for
+    InvoiceMismatch,
+}
+
+impl fmt::Display for LnurlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LnurlError::InvalidInput => write!(f, "not a lightning address or LNURL"),
+            LnurlError::Transport(reason) => write!(f, "request failed: {}", reason),
+            LnurlError::Service(reason) => write!(f, "service error: {}", reason),
+            LnurlError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+            LnurlError::AmountOutOfRange { amount, min, max } => write!(
+                f,
+                "{} msat is outside the allowed {}..={} msat",
+                amount, min, max
+            ),
+            LnurlError::InvoiceMismatch => write!(f, "invoice doesn't match the request"),
+        }
+    }
+}
+
+impl std::error::Error for LnurlError {}
+
+/// A service offering to be paid
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayRequest {
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    /// JSON array of what's being paid for, its hash is committed to by the
+    /// invoice
+    pub metadata: String,
+    /// Longest comment the service accepts, 0 if none
+    pub comment_allowed: u64,
+}
+
+/// A service offering to pay an invoice of ours
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithdrawRequest {
+    pub callback: String,
+    /// Identifies this withdrawal to the service
+    pub k1: String,
+    pub default_description: String,
+    pub min_withdrawable: u64,
+    pub max_withdrawable: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lnurl {
+    Pay(PayRequest),
+    Withdraw(WithdrawRequest),
+}
+
+fn invalid(reason: &str) -> LnurlError {
+    LnurlError::InvalidResponse(reason.to_string())
+}
+
+fn transport<E: fmt::Display>(err: E) -> LnurlError {
+    LnurlError::Transport(err.to_string())
+}
+
+/// The service's error, if the response is one
+fn check_status(json: &Value) -> Result<(), LnurlError> {
+    match json.get("status").and_then(Value::as_str) {
+        Some(status) if status.eq_ignore_ascii_case("ERROR") => {
+            let reason = json.get("reason").and_then(Value::as_str).unwrap_or("");
+            Err(LnurlError::Service(reason.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn get_json(url: &str) -> Result<Value, LnurlError> {
+    let body = reqwest::blocking::get(url)
+        .and_then(|response| response.text())
+        .map_err(transport)?;
+    let json: Value = serde_json::from_str(&body).map_err(transport)?;
+    check_status(&json)?;
+    Ok(json)
+}
+
+/// `url` with a query parameter added
+fn with_param(url: &str, key: &str, value: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}={}", url, separator, key, value)
+}
+
+/// The URL a lightning address resolves to, None if it isn't one
+pub fn lightning_address_url(address: &str) -> Option<String> {
+    let (user, domain) = address.trim().split_once('@')?;
+    let user_ok = !user.is_empty()
+        && user
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || b"-_.+".contains(&c));
+    if !user_ok || !domain.contains('.') || domain.contains('/') {
+        return None;
+    }
+    // onion services don't have certificates
+    let scheme = if domain.ends_with(".onion") {
+        "http"
+    } else {
+        "https"
+    };
+    Some(format!(
+        "{}://{}/.well-known/lnurlp/{}",
+        scheme, domain, user
+    ))
+}
+
+/// The URL an `lnurl1...` string encodes, in either case and with or without
+/// a `lightning:` scheme
+pub fn decode_lnurl(lnurl: &str) -> Option<String> {
+    let lnurl = lnurl.trim();
+    let lnurl = match lnurl.get(..10) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("lightning:") => &lnurl[10..],
+        _ => lnurl,
+    };
+    let (hrp, data, _) = decode_unlimited(lnurl)?;
+    if hrp != "lnurl" {
+        return None;
+    }
+    String::from_utf8(convert_bits(&data, 5, 8, false)?).ok()
+}
+
+/// The URL to query for any of the inputs a user might paste: a lightning
+/// address, an LNURL, an `lnurlp://` or `lnurlw://` URL (LUD-17) or a plain
+/// https URL
+pub fn service_url(input: &str) -> Result<String, LnurlError> {
+    let input = input.trim();
+    if let Some(url) = lightning_address_url(input).or_else(|| decode_lnurl(input)) {
+        return Ok(url);
+    }
+    for scheme in ["lnurlp://", "lnurlw://"] {
+        if let Some(rest) = input.strip_prefix(scheme) {
+            let host = rest.split('/').next().unwrap_or_default();
+            let scheme = if host.ends_with(".onion") {
+                "http"
+            } else {
+                "https"
+            };
+            return Ok(format!("{}://{}", scheme, rest));
+        }
+    }
+    match input.starts_with("https://") {
+        true => Ok(input.to_string()),
+        false => Err(LnurlError::InvalidInput),
+    }
+}
+
+fn msat_field(json: &Value, key: &str) -> Result<u64, LnurlError> {
+    json.get(key)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid(&format!("missing {}", key)))
+}
+
+fn str_field(json: &Value, key: &str) -> Result<String, LnurlError> {
+    json.get(key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| invalid(&format!("missing {}", key)))
+}
+
+/// Parse the first response of a service
+pub fn parse_response(json: &Value) -> Result<Lnurl, LnurlError> {
+    check_status(json)?;
+    match json.get("tag").and_then(Value::as_str) {
+        Some("payRequest") => Ok(Lnurl::Pay(PayRequest {
+            callback: str_field(json, "callback")?,
+            min_sendable: msat_field(json, "minSendable")?,
+            max_sendable: msat_field(json, "maxSendable")?,
+            metadata: str_field(json, "metadata")?,
+            comment_allowed: json
+                .get("commentAllowed")
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+        })),
+        Some("withdrawRequest") => Ok(Lnurl::Withdraw(WithdrawRequest {
+            callback: str_field(json, "callback")?,
+            k1: str_field(json, "k1")?,
+            default_description: str_field(json, "defaultDescription").unwrap_or_default(),
+            min_withdrawable: msat_field(json, "minWithdrawable")?,
+            max_withdrawable: msat_field(json, "maxWithdrawable")?,
+        })),
+        Some(tag) => Err(invalid(&format!("unsupported tag {}", tag))),
+        None => Err(invalid("missing tag")),
+    }
+}
+
+/// Fetch what the service behind a lightning address or LNURL offers
+pub fn resolve(input: &str) -> Result<Lnurl, LnurlError> {
+    parse_response(&get_json(&service_url(input)?)?)
+}
+
+/// The amount a BOLT11 invoice asks for, from its human readable part, None
+/// if it doesn't set one
+pub fn invoice_amount_msat(invoice: &str) -> Option<u64> {
+    let (hrp, _, _) = decode_unlimited(invoice)?;
+    let amount = hrp
+        .strip_prefix("ln")?
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let (digits, multiplier) = match amount.chars().last()? {
+        c if c.is_ascii_digit() => (amount, None),
+        c => (&amount[..amount.len() - 1], Some(c)),
+    };
+    let value: u64 = digits.parse().ok()?;
+    // msat per unit of each multiplier, pico is a tenth of a msat
+    match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        Some('p') if value.is_multiple_of(10) => Some(value / 10),
+        _ => None,
+    }
+}
+
+impl PayRequest {
+    /// The callback URL asking for an invoice of `amount_msat`
+    pub fn callback_url(
+        &self,
+        amount_msat: u64,
+        comment: Option<&str>,
+    ) -> Result<String, LnurlError> {
+        if !(self.min_sendable..=self.max_sendable).contains(&amount_msat) {
+            return Err(LnurlError::AmountOutOfRange {
+                amount: amount_msat,
+                min: self.min_sendable,
+                max: self.max_sendable,
+            });
+        }
+        let mut url = with_param(&self.callback, "amount", &amount_msat.to_string());
+        if let Some(comment) = comment.filter(|comment| !comment.is_empty()) {
+            if comment.chars().count() as u64 > self.comment_allowed {
+                return Err(invalid("comment too long for the service"));
+            }
+            let encoded: String = comment
+                .bytes()
+                .map(
+                    |c| match c.is_ascii_alphanumeric() || b"-._~".contains(&c) {
+                        true => (c as char).to_string(),
+                        false => format!("%{:02X}", c),
+                    },
+                )
+                .collect();
+            url = with_param(&url, "comment", &encoded);
+        }
+        Ok(url)
+    }
+
+    /// The invoice in a callback response, checked to be for `amount_msat`
+    pub fn parse_invoice(json: &Value, amount_msat: u64) -> Result<String, LnurlError> {
+        check_status(json)?;
+        let invoice = str_field(json, "pr")?;
+        if invoice_amount_msat(&invoice) != Some(amount_msat) {
+            return Err(LnurlError::InvoiceMismatch);
+        }
+        Ok(invoice)
+    }
+
+    /// Ask the service for an invoice of `amount_msat`
+    pub fn request_invoice(
+        &self,
+        amount_msat: u64,
+        comment: Option<&str>,
+    ) -> Result<String, LnurlError> {
+        let json = get_json(&self.callback_url(amount_msat, comment)?)?;
+        PayRequest::parse_invoice(&json, amount_msat)
+    }
+}
+
+impl WithdrawRequest {
+    /// The callback URL handing the service an invoice to pay
+    pub fn callback_url(&self, invoice: &str) -> Result<String, LnurlError> {
+        let amount = invoice_amount_msat(invoice).ok_or(LnurlError::InvoiceMismatch)?;
+        if !(self.min_withdrawable..=self.max_withdrawable).contains(&amount) {
+            return Err(LnurlError::AmountOutOfRange {
+                amount,
+                min: self.min_withdrawable,
+                max: self.max_withdrawable,
+            });
+        }
+        Ok(with_param(
+            &with_param(&self.callback, "k1", &self.k1),
+            "pr",
+            invoice,
+        ))
+    }
+
+    /// Have the service pay `invoice`, Ok once it accepted it. The payment
+    /// itself happens asynchronously, watch the invoice to see it arrive.
+    pub fn withdraw(&self, invoice: &str) -> Result<(), LnurlError> {
+        get_json(&self.callback_url(invoice)?).map(|_| ())
+    }
+}
+
+/// Get an invoice paying `amount_msat` to a lightning address or LNURL-pay
+/// service, e.g. to melt ecash to it
+pub fn invoice_for(input: &str, amount_msat: u64) -> Result<String, LnurlError> {
+    match resolve(input)? {
+        Lnurl::Pay(pay) => pay.request_invoice(amount_msat, None),
+        Lnurl::Withdraw(_) => Err(invalid("expected a pay request, got a withdraw request")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // the invoice from BOLT11's examples, 2500u
+    const INVOICE: &str = "lnbc2500u1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpuaztrnwngzn3kdzw5hydlzf03qdgm2hdq27cqv3agm2awhz5se903vruatfhq77w3ls4evs3ch9zw97j25emudupq63nyw24cg27h2rspfj9srp";
+
+    #[test]
+    fn test_service_urls() {
+        assert_eq!(
+            service_url("satoshi@bitcoin.org").unwrap(),
+            "https://bitcoin.org/.well-known/lnurlp/satoshi"
+        );
+        assert_eq!(
+            lightning_address_url("alice@abc.onion").unwrap(),
+            "http://abc.onion/.well-known/lnurlp/alice"
+        );
+        assert_eq!(lightning_address_url("Alice@bitcoin.org"), None);
+        assert_eq!(lightning_address_url("alice@localhost"), None);
+
+        // the example from LUD-01
+        let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        assert_eq!(
+            service_url(&format!("lightning:{}", lnurl)).unwrap(),
+            "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df"
+        );
+        assert_eq!(
+            service_url("lnurlw://site.com/withdraw?k1=aa").unwrap(),
+            "https://site.com/withdraw?k1=aa"
+        );
+        assert_eq!(
+            service_url("http://site.com"),
+            Err(LnurlError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_invoice_amount() {
+        assert_eq!(invoice_amount_msat(INVOICE), Some(250_000_000));
+        assert_eq!(invoice_amount_msat("not an invoice"), None);
+    }
+
+    #[test]
+    fn test_pay_flow() {
+        let response = json!({
+            "tag": "payRequest",
+            "callback": "https://bitcoin.org/lnurlp/satoshi/callback",
+            "minSendable": 1000,
+            "maxSendable": 1_000_000_000,
+            "metadata": "[[\"text/plain\",\"pay satoshi\"]]",
+            "commentAllowed": 32,
+        });
+        let Lnurl::Pay(pay) = parse_response(&response).unwrap() else {
+            panic!("expected a pay request");
+        };
+        assert_eq!(
+            pay.callback_url(250_000_000, Some("for coffee")).unwrap(),
+            "https://bitcoin.org/lnurlp/satoshi/callback?amount=250000000&comment=for%20coffee"
+        );
+        assert_eq!(
+            pay.callback_url(1, None),
+            Err(LnurlError::AmountOutOfRange {
+                amount: 1,
+                min: 1000,
+                max: 1_000_000_000
+            })
+        );
+
+        let callback = json!({"pr": INVOICE, "routes": []});
+        assert_eq!(
+            PayRequest::parse_invoice(&callback, 250_000_000),
+            Ok(INVOICE.to_string())
+        );
+        assert_eq!(
+            PayRequest::parse_invoice(&callback, 1_000),
+            Err(LnurlError::InvoiceMismatch)
+        );
+        assert_eq!(
+            PayRequest::parse_invoice(&json!({"status": "ERROR", "reason": "no route"}), 1_000),
+            Err(LnurlError::Service("no route".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_withdraw_flow() {
+        let response = json!({
+            "tag": "withdrawRequest",
+            "callback": "https://site.com/withdraw/cb?id=7",
+            "k1": "ab".repeat(32),
+            "defaultDescription": "faucet",
+            "minWithdrawable": 1000,
+            "maxWithdrawable": 500_000_000,
+        });
+        let Lnurl::Withdraw(withdraw) = parse_response(&response).unwrap() else {
+            panic!("expected a withdraw request");
+        };
+        assert_eq!(withdraw.default_description, "faucet");
+        assert_eq!(
+            withdraw.callback_url(INVOICE).unwrap(),
+            format!(
+                "https://site.com/withdraw/cb?id=7&k1={}&pr={}",
+                "ab".repeat(32),
+                INVOICE
+            )
+        );
+        let small = WithdrawRequest {
+            max_withdrawable: 100_000,
+            ..withdraw
+        };
+        assert!(matches!(
+            small.callback_url(INVOICE),
+            Err(LnurlError::AmountOutOfRange { .. })
+        ));
+        assert!(matches!(
+            parse_response(&json!({"tag": "login"})),
+            Err(LnurlError::InvalidResponse(_))
+        ));
+    }
+}
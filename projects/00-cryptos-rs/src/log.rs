@@ -0,0 +1,18 @@
+// Debug events for the hot paths. They compile to nothing unless the `tracing`
+// feature is on, and anything derived from a secret key or nonce goes through
+// `sensitive!`, which additionally needs the `log-sensitive` feature so a
+// subscriber can't leak secrets by accident.
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($arg)*);
+    };
+}
+
+macro_rules! sensitive {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log-sensitive")]
+        ::tracing::trace!(sensitive = true, $($arg)*);
+    };
+}
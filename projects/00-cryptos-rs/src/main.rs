@@ -1,81 +1,169 @@
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
-use std::str::FromStr;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
 
-use secp256k1::constants::{GENERATOR_X, GENERATOR_Y};
+use cryptos_rs::secp256k1::{PrecomputeTable, WindowTable};
+use rayon::prelude::*;
 use secp256k1::{PublicKey, Secp256k1, SecretKey};
 
-fn main() {
-    // Initialize secp256k1 context
-    let secp = Secp256k1::new();
-    println!("Secp256k1 context initialized.");
-
-    // Hard code the generator point
-    let mut generator_bytes = [0u8; 65];
-    generator_bytes[0] = 0x04; // Uncompressed public key prefix
-    generator_bytes[1..33].copy_from_slice(&GENERATOR_X);
-    generator_bytes[33..65].copy_from_slice(&GENERATOR_Y);
-    let generator_point = PublicKey::from_slice(&generator_bytes).unwrap();
+/// Entries computed per rayon task
+const CHUNK_SIZE: usize = 16;
+
+/// 2^i * G, computed directly so every chunk can start independently
+fn chunk_start(secp: &Secp256k1<secp256k1::All>, i: usize) -> PublicKey {
+    let mut scalar_bytes = [0u8; 32];
+    scalar_bytes[31 - i / 8] = 1 << (i % 8);
+    PublicKey::from_secret_key(secp, &SecretKey::from_slice(&scalar_bytes).unwrap())
+}
+
+/// Entries [start, end) of the table, each one the double of the previous
+fn compute_chunk(secp: &Secp256k1<secp256k1::All>, start: usize, end: usize) -> Vec<PublicKey> {
+    let mut points = vec![chunk_start(secp, start)];
+    for _ in start + 1..end {
+        let last = points.last().unwrap();
+        points.push(last.combine(last).unwrap());
+    }
+    points
+}
+
+/// Read the entries already written to the file, stopping at the first line
+/// that is malformed, out of order or not the double of its predecessor (e.g.
+/// a partial write from an interrupted run). Returns the valid entries and the
+/// byte length of the file they cover.
+fn read_valid_entries(path: &Path) -> (Vec<PublicKey>, usize) {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let mut points: Vec<PublicKey> = vec![];
+    let mut valid_len = 0;
+
+    for line in contents.split_inclusive('\n') {
+        let Some((index, point_hex)) = line.trim_end_matches('\n').split_once(':') else {
+            break;
+        };
+        if !line.ends_with('\n') || index.parse::<usize>() != Ok(points.len()) {
+            break;
+        }
+        let Some(point) = hex::decode(point_hex)
+            .ok()
+            .and_then(|bytes| PublicKey::from_slice(&bytes).ok())
+        else {
+            break;
+        };
+        let expected = match points.last() {
+            Some(last) => last.combine(last).unwrap(),
+            None => chunk_start(&Secp256k1::new(), 0),
+        };
+        if point != expected {
+            break;
+        }
+        points.push(point);
+        valid_len += line.len();
+    }
+
+    (points, valid_len)
+}
+
+/// Digits 1..16 of one window, `j * 16^window * G`
+fn compute_window(secp: &Secp256k1<secp256k1::All>, window: usize) -> Vec<PublicKey> {
+    let window_base = chunk_start(secp, window * WindowTable::WINDOW_BITS);
+    let mut points = vec![window_base];
+    for _ in 1..WindowTable::DIGITS {
+        points.push(points.last().unwrap().combine(&window_base).unwrap());
+    }
+    points
+}
+
+/// The window table is cheap enough to compute in one go, so it is written
+/// in full and converted to the binary format right away
+fn generate_window_table(secp: &Secp256k1<secp256k1::All>, path: &Path) {
+    let windows: Vec<Vec<PublicKey>> = (0..WindowTable::WINDOWS)
+        .into_par_iter()
+        .map(|window| compute_window(secp, window))
+        .collect();
+
+    let mut file = fs::File::create(path).unwrap();
+    for (index, point) in windows.iter().flatten().enumerate() {
+        writeln!(file, "{}:{}", index, hex::encode(point.serialize())).unwrap();
+    }
+    file.flush().unwrap();
     println!(
-        "Generator point obtained: {:?}",
-        hex::encode(generator_point.serialize())
+        "All {} window points written to file successfully.",
+        WindowTable::SIZE
     );
 
-    let mut precomputed_points = vec![generator_point; 256 * 32];
-    println!("Initialized vector for precomputed points.");
+    let binary_path = path.with_extension("bin");
+    WindowTable::convert_text_file(path, &binary_path).unwrap();
+    println!("Binary table written to {}", binary_path.display());
+}
+
+fn main() {
+    // the tables are checked against the crate's own G, which had better be right
+    cryptos_rs::consistency::assert_consistent();
+    let secp = Secp256k1::new();
+
+    // `--window` generates the fixed-base window table used for signing
+    // instead of the table of doublings
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--window") {
+        let path = args
+            .get(1)
+            .cloned()
+            .unwrap_or_else(|| "precomputed_windows.txt".to_string());
+        generate_window_table(&secp, Path::new(&path));
+        return;
+    }
+
+    // Output file, defaults to the current directory. The text file doubles as
+    // a resumable work log, the binary table is written once it is complete.
+    let path = if args.is_empty() {
+        "precomputed_points.txt".to_string()
+    } else {
+        args.remove(0)
+    };
+    let path = Path::new(&path);
 
+    let (points, valid_len) = read_valid_entries(path);
+    println!("Resuming from index: {}", points.len());
+
+    // Drop anything after the last valid entry before appending
     let mut file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
-        .open("/Users/kody/Documents/github/fedi_stuff/ecash-course/projects/00-cryptos-rs/precomputed_points.txt")
+        .open(path)
         .unwrap();
+    file.set_len(valid_len as u64).unwrap();
 
-    // Determine the last precomputed point
-    let mut last_index = 0;
-    if let Ok(file) = File::open("/Users/kody/Documents/github/fedi_stuff/ecash-course/projects/00-cryptos-rs/precomputed_points.txt") {
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                if let Some((index_str, point_str)) = line.split_once(':') {
-                    if let Ok(index) = index_str.parse::<usize>() {
-                        last_index = index;
-                        let point_bytes = hex::decode(point_str).unwrap();
-                        precomputed_points[index] = PublicKey::from_slice(&point_bytes).unwrap();
-                    }
-                }
-            }
-        }
-    }
-    println!("Resuming from index: {}", last_index);
+    let chunk_starts: Vec<usize> = (points.len()..PrecomputeTable::SIZE)
+        .step_by(CHUNK_SIZE)
+        .collect();
 
-    for i in 0..256 * 32 {
-        if i <= last_index {
-            continue;
-        }
-        let mut current_point = generator_point.clone();
-        for _ in 0..i {
-            current_point = current_point.combine(&generator_point).unwrap();
+    // Compute one chunk per thread at a time and append them in order, so an
+    // interrupted run loses at most one round of work
+    for round in chunk_starts.chunks(rayon::current_num_threads()) {
+        let chunks: Vec<(usize, Vec<PublicKey>)> = round
+            .par_iter()
+            .map(|&start| {
+                let end = (start + CHUNK_SIZE).min(PrecomputeTable::SIZE);
+                (start, compute_chunk(&secp, start, end))
+            })
+            .collect();
+
+        for (start, chunk) in chunks {
+            for (offset, point) in chunk.iter().enumerate() {
+                writeln!(
+                    file,
+                    "{}:{}",
+                    start + offset,
+                    hex::encode(point.serialize())
+                )
+                .unwrap();
+            }
+            println!("Written points {}..{}", start, start + chunk.len());
         }
-        precomputed_points[i] = current_point;
-        println!(
-            "Precomputed point for index {}: {:?}",
-            i, precomputed_points[i]
-        );
-
-        // Write the precomputed point to file immediately
-        writeln!(
-            file,
-            "{}:{}",
-            i,
-            hex::encode(precomputed_points[i].serialize())
-        )
-        .unwrap();
-        println!(
-            "Written point {} to file: {}",
-            i,
-            hex::encode(precomputed_points[i].serialize())
-        );
+        file.flush().unwrap();
     }
     println!("All precomputed points written to file successfully.");
+
+    let binary_path = path.with_extension("bin");
+    PrecomputeTable::convert_text_file(path, &binary_path).unwrap();
+    println!("Binary table written to {}", binary_path.display());
 }
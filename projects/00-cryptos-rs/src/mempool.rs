@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::amount::{Amount, SAT_PER_BTC};
+use crate::block::Block;
+use crate::index::Index;
+use crate::sha256::hash256;
+use crate::transaction::{Cmd, Script, Tx, TxBuilder, TxIn, TxOut};
+
+// A toy mempool for the double-spend chapter: unconfirmed transactions along
+// with the outpoints they spend, so a new transaction spending a coin that a
+// pending one (or, through the index, a confirmed one) already spends can be
+// caught. Like Bitcoin Core without replace-by-fee, the first spend seen wins.
+// There's no script or fee validation, check those with `Policy` first.
+
+/// An input of a transaction spending an outpoint that another transaction
+/// already spends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Index of the conflicting input
+    pub input: usize,
+    /// Id of the transaction already spending the outpoint
+    pub txid: String,
+    /// Whether that transaction is in the chain rather than the mempool
+    pub confirmed: bool,
+}
+
+/// Outpoints as the previous txid in internal byte order and output index,
+/// like `TxIn::prev_tx` and `prev_index`
+type Outpoint = (Vec<u8>, u32);
+
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    txs: HashMap<String, Tx>,
+    /// outpoint -> id of the mempool transaction spending it
+    spends: HashMap<Outpoint, String>,
+}
+
+fn display_txid(txid: &[u8]) -> String {
+    let mut txid = txid.to_vec();
+    txid.reverse();
+    hex::encode(txid)
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Mempool::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    pub fn get(&self, txid: &str) -> Option<&Tx> {
+        self.txs.get(txid)
+    }
+
+    /// The transactions, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Tx> {
+        self.txs.values()
+    }
+
+    /// Inputs of `tx` spending an outpoint a mempool transaction spends
+    fn mempool_conflicts(&self, tx: &Tx) -> Vec<Conflict> {
+        if tx.is_coinbase() {
+            return vec![];
+        }
+        tx.tx_ins
+            .iter()
+            .enumerate()
+            .filter_map(|(input, tx_in)| {
+                let spender = self
+                    .spends
+                    .get(&(tx_in.prev_tx.clone(), tx_in.prev_index))?;
+                Some(Conflict {
+                    input,
+                    txid: spender.clone(),
+                    confirmed: false,
+                })
+            })
+            .collect()
+    }
+
+    /// Add a transaction unless it spends an outpoint a mempool transaction
+    /// already spends. Returns its id, or the conflicts it was rejected for.
+    pub fn insert(&mut self, tx: Tx) -> Result<String, Vec<Conflict>> {
+        let conflicts = self.mempool_conflicts(&tx);
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+        let txid = tx.id();
+        for tx_in in &tx.tx_ins {
+            self.spends
+                .insert((tx_in.prev_tx.clone(), tx_in.prev_index), txid.clone());
+        }
+        self.txs.insert(txid.clone(), tx);
+        Ok(txid)
+    }
+
+    /// Remove a transaction along with all mempool transactions spending its
+    /// outputs, which can't confirm without it
+    fn remove(&mut self, txid: &str) {
+        let Some(tx) = self.txs.remove(txid) else {
+            return;
+        };
+        for tx_in in &tx.tx_ins {
+            self.spends
+                .remove(&(tx_in.prev_tx.clone(), tx_in.prev_index));
+        }
+        let mut internal_txid = hex::decode(txid).unwrap();
+        internal_txid.reverse();
+        for vout in 0..tx.tx_outs.len() as u32 {
+            if let Some(child) = self.spends.get(&(internal_txid.clone(), vout)).cloned() {
+                self.remove(&child);
+            }
+        }
+    }
+
+    /// Drop the transactions a new block confirmed, and the ones conflicting
+    /// with them since those can never confirm now
+    pub fn remove_for_block(&mut self, block: &Block) {
+        for tx in &block.txs {
+            self.remove(&tx.id());
+            for conflict in self.mempool_conflicts(tx) {
+                self.remove(&conflict.txid);
+            }
+        }
+    }
+
+    /// Every input of `tx` spending an outpoint already spent by a mempool
+    /// transaction or by one in the chain `index` has seen
+    pub fn detect_conflicts(&self, tx: &Tx, index: &Index) -> sled::Result<Vec<Conflict>> {
+        let mut conflicts = self.mempool_conflicts(tx);
+        if tx.is_coinbase() {
+            return Ok(conflicts);
+        }
+        for (input, tx_in) in tx.tx_ins.iter().enumerate() {
+            if let Some(txid) = index.spent_by(&display_txid(&tx_in.prev_tx), tx_in.prev_index)? {
+                conflicts.push(Conflict {
+                    input,
+                    txid,
+                    confirmed: true,
+                });
+            }
+        }
+        conflicts.sort_by_key(|conflict| conflict.input);
+        Ok(conflicts)
+    }
+}
+
+/// Coins mined for an exercise, a quarter spent in the chain, a quarter in the
+/// mempool and the rest unspent
+const EXERCISE_COINS: u32 = 8;
+
+/// A chain and mempool to check transactions against, with the answers
+pub struct Exercise {
+    pub index: Index,
+    pub mempool: Mempool,
+    /// Transactions to check, each with the conflicts a correct detector finds
+    pub attempts: Vec<(Tx, Vec<Conflict>)>,
+}
+
+fn coinbase(height: u32, script_pubkey: Script) -> Tx {
+    Tx {
+        version: 1,
+        tx_ins: vec![TxIn {
+            prev_tx: vec![0; 32],
+            prev_index: 0xffffffff,
+            script_sig: Script::new(vec![Cmd::push(&height.to_le_bytes())]),
+            sequence: 0xffffffff,
+            ..Default::default()
+        }],
+        tx_outs: vec![TxOut {
+            amount: Amount::from_sat(50 * SAT_PER_BTC),
+            script_pubkey,
+        }],
+        ..Default::default()
+    }
+}
+
+/// Spend output 0 of each coin to a random key
+fn spend(rng: &mut StdRng, coins: &[&[u8]]) -> Tx {
+    let mut builder = TxBuilder::new("main");
+    for coin in coins {
+        builder = builder.add_input(coin.to_vec(), 0);
+    }
+    let amount = Amount::from_sat(rng.gen_range(1..50 * SAT_PER_BTC));
+    builder
+        .add_output(amount, Script::p2pkh(&rng.gen::<[u8; 20]>()))
+        .build()
+}
+
+/// Generate a double-spend exercise: a chain where some coins were spent, a
+/// mempool spending some more, and `attempts` transactions to check. Some are
+/// honest, some spend a coin again in the chain or the mempool, and some hide
+/// a double spend behind an honest input. The same seed gives the same
+/// exercise.
+pub fn double_spend_exercise(seed: u64, attempts: usize) -> sled::Result<Exercise> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let index = Index::temporary()?;
+    let mut mempool = Mempool::new();
+
+    let mut coins = vec![];
+    let mut prev_block = vec![0; 32];
+    for height in 0..=EXERCISE_COINS {
+        let mut txs = vec![coinbase(height, Script::p2pkh(&[height as u8; 20]))];
+        coins.push(hash256(txs[0].encode_legacy()));
+        // the last block spends the first quarter of the coins
+        if height == EXERCISE_COINS {
+            let spent = &coins[..EXERCISE_COINS as usize / 4];
+            txs.extend(spent.iter().map(|coin| spend(&mut rng, &[coin])));
+        }
+        let block = Block {
+            version: 1,
+            prev_block,
+            merkle_root: vec![0; 32],
+            timestamp: 1231006505 + height * 600,
+            bits: vec![0xff, 0xff, 0x00, 0x1d],
+            nonce: vec![0; 4],
+            txs,
+        };
+        index.index_block(&block, height)?;
+        prev_block = hex::decode(block.id()).unwrap();
+    }
+
+    let (confirmed, rest) = coins[..EXERCISE_COINS as usize].split_at(EXERCISE_COINS as usize / 4);
+    let (pending, unspent) = rest.split_at(EXERCISE_COINS as usize / 4);
+    let mut pending_spenders = vec![];
+    for coin in pending {
+        let txid = mempool.insert(spend(&mut rng, &[coin])).unwrap();
+        pending_spenders.push(txid);
+    }
+    let confirmed_spenders: Vec<String> = confirmed
+        .iter()
+        .map(|coin| index.spent_by(&display_txid(coin), 0).map(Option::unwrap))
+        .collect::<sled::Result<_>>()?;
+
+    let mut exercise_attempts = vec![];
+    for _ in 0..attempts {
+        let honest = unspent.choose(&mut rng).unwrap();
+        // 0: honest, 1: chain double spend, 2: mempool double spend, 3: a
+        // double spend behind an honest input
+        let kind = rng.gen_range(0..4);
+        if kind == 0 {
+            exercise_attempts.push((spend(&mut rng, &[honest]), vec![]));
+            continue;
+        }
+        let confirmed_spend = kind == 1 || (kind == 3 && rng.gen());
+        let (coins, spenders) = match confirmed_spend {
+            true => (confirmed, &confirmed_spenders),
+            false => (pending, &pending_spenders),
+        };
+        let which = rng.gen_range(0..coins.len());
+        let conflict = |input| Conflict {
+            input,
+            txid: spenders[which].clone(),
+            confirmed: confirmed_spend,
+        };
+        if kind == 3 && rng.gen() {
+            let tx = spend(&mut rng, &[honest, &coins[which]]);
+            exercise_attempts.push((tx, vec![conflict(1)]));
+        } else if kind == 3 {
+            let tx = spend(&mut rng, &[&coins[which], honest]);
+            exercise_attempts.push((tx, vec![conflict(0)]));
+        } else {
+            exercise_attempts.push((spend(&mut rng, &[&coins[which]]), vec![conflict(0)]));
+        }
+    }
+
+    Ok(Exercise {
+        index,
+        mempool,
+        attempts: exercise_attempts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_rejects_double_spends() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let coin = [0x11; 32];
+        let first = spend(&mut rng, &[&coin]);
+        let second = spend(&mut rng, &[&[0x22; 32], &coin]);
+
+        let mut mempool = Mempool::new();
+        let first_id = mempool.insert(first.clone()).unwrap();
+        assert_eq!(
+            mempool.insert(second.clone()),
+            Err(vec![Conflict {
+                input: 1,
+                txid: first_id.clone(),
+                confirmed: false,
+            }])
+        );
+        assert_eq!(mempool.len(), 1);
+
+        // a child of the first spend goes when a block confirms the double spend
+        let mut first_internal = hash256(first.encode_legacy());
+        let child = spend(&mut rng, &[&first_internal]);
+        let child_id = mempool.insert(child).unwrap();
+        first_internal.reverse();
+        assert_eq!(hex::encode(first_internal), first_id);
+        let block = Block {
+            version: 1,
+            prev_block: vec![0; 32],
+            merkle_root: vec![0; 32],
+            timestamp: 0,
+            bits: vec![0xff, 0xff, 0x00, 0x1d],
+            nonce: vec![0; 4],
+            txs: vec![second.clone()],
+        };
+        mempool.remove_for_block(&block);
+        assert!(mempool.get(&first_id).is_none());
+        assert!(mempool.get(&child_id).is_none());
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.insert(second.clone()), Ok(second.id()));
+    }
+
+    #[test]
+    fn test_exercise_answers() {
+        let exercise = double_spend_exercise(7, 40).unwrap();
+        assert_eq!(exercise.mempool.len(), EXERCISE_COINS as usize / 4);
+        let mut kinds = [0; 3];
+        for (tx, expected) in &exercise.attempts {
+            let conflicts = exercise
+                .mempool
+                .detect_conflicts(tx, &exercise.index)
+                .unwrap();
+            assert_eq!(&conflicts, expected);
+            match expected.first() {
+                None => kinds[0] += 1,
+                Some(conflict) if conflict.confirmed => kinds[1] += 1,
+                Some(_) => kinds[2] += 1,
+            }
+        }
+        assert!(kinds.iter().all(|&count| count > 0));
+
+        // deterministic for a seed
+        let again = double_spend_exercise(7, 40).unwrap();
+        let ids = |exercise: &Exercise| -> Vec<String> {
+            exercise.attempts.iter().map(|(tx, _)| tx.id()).collect()
+        };
+        assert_eq!(ids(&exercise), ids(&again));
+    }
+}
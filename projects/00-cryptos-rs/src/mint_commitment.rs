@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::block::Block;
+use crate::interpreter::{verify_script, Checker};
+use crate::keys::PublicKey;
+use crate::sha256::sha256;
+use crate::transaction::{Cmd, Prevouts, Script, Tx, TxBuilder};
+use crate::utils;
+
+// Holding a mint to what it said: every so often the mint hashes a snapshot
+// of its keysets and of the ecash it has outstanding, and puts the hash in an
+// OP_RETURN output. The chain then timestamps each snapshot, and the mint
+// can't later claim it had different keys or owed less than it did. The
+// commitment transaction spends one of the mint's P2PKH or P2WPKH coins, and
+// a verifier only counts commitments whose transaction has an input spending
+// a coin of the mint's key with a valid signature: anyone can post the tag,
+// and anyone can put the mint's public key in a scriptSig, but only the mint
+// can sign for it. Commitments are numbered, a gap in the numbers is a
+// snapshot the mint skipped or tried to hide. The snapshot itself is
+// published off chain, anyone holding it checks it against the hash.
+
+/// Tag in front of every commitment
+pub const COMMITMENT_TAG: &[u8; 4] = b"ecsh";
+const COMMITMENT_VERSION: u8 = 0;
+/// Tag, version, sequence number and the snapshot hash
+const COMMITMENT_SIZE: usize = 4 + 1 + 4 + 32;
+
+const OP_0: u8 = 0x00;
+
+/// A mint's keys for one unit, a public key per amount
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyset {
+    pub unit: String,
+    pub keys: BTreeMap<u64, PublicKey>,
+}
+
+impl Keyset {
+    /// The NUT-02 id: version 00 and the start of the hash of the compressed
+    /// keys, in order of amount
+    pub fn id(&self) -> [u8; 8] {
+        let keys: Vec<u8> = self
+            .keys
+            .values()
+            .flat_map(|key| key.sec(true, false))
+            .collect();
+        let mut id = [0; 8];
+        id[1..].copy_from_slice(&sha256(keys)[..7]);
+        id
+    }
+
+    /// The unit, then each amount and its compressed key in order of amount
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = utils::encode_varint(self.unit.len() as u64);
+        bytes.extend(self.unit.as_bytes());
+        bytes.extend(utils::encode_varint(self.keys.len() as u64));
+        for (amount, key) in &self.keys {
+            bytes.extend(amount.to_le_bytes());
+            bytes.extend(key.sec(true, false));
+        }
+        bytes
+    }
+}
+
+/// What a mint commits to: its keysets and the ecash outstanding per unit
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub keysets: Vec<Keyset>,
+    /// Ecash issued and not yet redeemed, by unit
+    pub liabilities: BTreeMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Hash of the whole keysets in order of their bytes, then the
+    /// liabilities in order of unit. Not of the keyset ids, which at 56 bits
+    /// are short enough for a mint to find two keysets sharing one.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut keysets: Vec<Vec<u8>> = self.keysets.iter().map(Keyset::to_bytes).collect();
+        keysets.sort();
+        let mut data = utils::encode_varint(keysets.len() as u64);
+        for keyset in keysets {
+            data.extend(keyset);
+        }
+        data.extend(utils::encode_varint(self.liabilities.len() as u64));
+        for (unit, amount) in &self.liabilities {
+            data.extend(utils::encode_varint(unit.len() as u64));
+            data.extend(unit.as_bytes());
+            data.extend(amount.to_le_bytes());
+        }
+        sha256(data).try_into().unwrap()
+    }
+}
+
+/// The OP_RETURN payload of a commitment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Commitment {
+    pub sequence: u32,
+    pub snapshot_hash: [u8; 32],
+}
+
+impl Commitment {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = COMMITMENT_TAG.to_vec();
+        bytes.push(COMMITMENT_VERSION);
+        bytes.extend(self.sequence.to_le_bytes());
+        bytes.extend(self.snapshot_hash);
+        bytes
+    }
+
+    /// None unless `data` is a commitment of a version this code knows
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != COMMITMENT_SIZE
+            || &data[..4] != COMMITMENT_TAG
+            || data[4] != COMMITMENT_VERSION
+        {
+            return None;
+        }
+        Some(Commitment {
+            sequence: u32::from_le_bytes(data[5..9].try_into().unwrap()),
+            snapshot_hash: data[9..].try_into().unwrap(),
+        })
+    }
+}
+
+/// The mint's side, when the next commitment is due and what it holds
+#[derive(Debug, Clone)]
+pub struct Committer {
+    /// Blocks between commitments
+    pub interval: u32,
+    next_sequence: u32,
+    last_height: Option<u32>,
+}
+
+impl Committer {
+    pub fn new(interval: u32) -> Self {
+        Committer {
+            interval,
+            next_sequence: 0,
+            last_height: None,
+        }
+    }
+
+    /// Whether a commitment should go into the block at `height`
+    pub fn due(&self, height: u32) -> bool {
+        self.last_height
+            .is_none_or(|last| height >= last + self.interval)
+    }
+
+    /// Add the commitment to `snapshot` to a transaction spending one of the
+    /// mint's coins, `height` is the block it's expected in
+    pub fn commit(&mut self, builder: TxBuilder, snapshot: &Snapshot, height: u32) -> TxBuilder {
+        let commitment = Commitment {
+            sequence: self.next_sequence,
+            snapshot_hash: snapshot.hash(),
+        };
+        self.next_sequence += 1;
+        self.last_height = Some(height);
+        builder.add_data_output(&commitment.to_bytes())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// No commitment with that sequence number was seen
+    Missing,
+    /// The commitment is for a different snapshot
+    Mismatch,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Missing => write!(f, "no such commitment on chain"),
+            VerifyError::Mismatch => write!(f, "snapshot doesn't match its commitment"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// A commitment found on chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Committed {
+    pub commitment: Commitment,
+    pub txid: String,
+    pub height: u32,
+}
+
+/// Collects a mint's commitments from blocks and checks snapshots against them
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    /// The P2PKH and P2WPKH scripts of the mint's key
+    mint_scripts: [Script; 2],
+    commitments: BTreeMap<u32, Committed>,
+}
+
+impl Verifier {
+    pub fn new(mint_key: &PublicKey) -> Self {
+        let hash = mint_key.sec(true, true);
+        Verifier {
+            mint_scripts: [
+                Script::p2pkh(&hash),
+                Script::new(vec![Cmd::Op(OP_0), Cmd::Push(hash)]),
+            ],
+            commitments: BTreeMap::new(),
+        }
+    }
+
+    /// Whether an input of `tx` spends a coin of the mint's key, with a
+    /// signature that checks out against the coin in `prevouts`
+    fn signed_by_mint(&self, tx: &Tx, prevouts: &Prevouts) -> bool {
+        tx.tx_ins.iter().enumerate().any(|(input, tx_in)| {
+            let Some(prevout) = prevouts.get(&(tx_in.prev_tx.clone(), tx_in.prev_index)) else {
+                return false;
+            };
+            self.mint_scripts.contains(&prevout.script_pubkey)
+                && verify_script(
+                    &tx_in.script_sig,
+                    &prevout.script_pubkey,
+                    &tx_in.witness,
+                    &Checker::for_input(tx, input, prevout),
+                )
+                .is_ok()
+        })
+    }
+
+    /// Record the mint's commitments in the block at `height`, whose spent
+    /// outputs are in `prevouts`, returns how many there were. A sequence
+    /// number already seen keeps its first commitment.
+    pub fn process_block(&mut self, block: &Block, height: u32, prevouts: &Prevouts) -> usize {
+        let mut found = 0;
+        let mint_txs: Vec<&Tx> = block
+            .txs
+            .iter()
+            .filter(|tx| self.signed_by_mint(tx, prevouts))
+            .collect();
+        for tx in mint_txs {
+            let commitments = tx.tx_outs.iter().filter_map(|tx_out| {
+                Commitment::from_bytes(&tx_out.script_pubkey.op_return_data()?)
+            });
+            for commitment in commitments {
+                found += 1;
+                self.commitments
+                    .entry(commitment.sequence)
+                    .or_insert(Committed {
+                        commitment,
+                        txid: tx.id(),
+                        height,
+                    });
+            }
+        }
+        found
+    }
+
+    /// The commitment numbered `sequence`
+    pub fn get(&self, sequence: u32) -> Option<&Committed> {
+        self.commitments.get(&sequence)
+    }
+
+    /// Check a snapshot the mint published as number `sequence`, returns the
+    /// height it was committed at
+    pub fn verify(&self, sequence: u32, snapshot: &Snapshot) -> Result<u32, VerifyError> {
+        let committed = self.get(sequence).ok_or(VerifyError::Missing)?;
+        match committed.commitment.snapshot_hash == snapshot.hash() {
+            true => Ok(committed.height),
+            false => Err(VerifyError::Mismatch),
+        }
+    }
+
+    /// Sequence numbers below the latest one that never showed up
+    pub fn gaps(&self) -> Vec<u32> {
+        let Some(&last) = self.commitments.keys().next_back() else {
+            return vec![];
+        };
+        (0..last)
+            .filter(|sequence| !self.commitments.contains_key(sequence))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::encoding::Encodable;
+    use crate::ru256::RU256;
+    use crate::signature::sign_ecdsa;
+    use crate::transaction::{TxOut, SIGHASH_ALL};
+
+    fn key(secret_key: u64) -> PublicKey {
+        PublicKey::from_sk(&RU256::from_u64(secret_key))
+    }
+
+    fn keyset(unit: &str) -> Keyset {
+        Keyset {
+            unit: unit.to_string(),
+            keys: (0..4).map(|i| (1 << i, key(i + 1))).collect(),
+        }
+    }
+
+    /// A commitment transaction signed for a coin of `secret_key`, a P2WPKH
+    /// one if `segwit`, and that coin
+    fn commitment_tx(
+        secret_key: u64,
+        segwit: bool,
+        committer: &mut Committer,
+        snapshot: &Snapshot,
+        height: u32,
+    ) -> (Tx, Prevouts) {
+        let public_key = key(secret_key);
+        let [p2pkh, p2wpkh] = Verifier::new(&public_key).mint_scripts;
+        let coin = TxOut {
+            amount: Amount::from_sat(60_000),
+            script_pubkey: if segwit { p2wpkh } else { p2pkh.clone() },
+        };
+        let outpoint = (vec![height as u8; 32], secret_key as u32);
+        let builder = TxBuilder::new("test")
+            .add_input(outpoint.0.clone(), outpoint.1)
+            .add_output(Amount::from_sat(50_000), p2pkh.clone());
+        let mut tx = committer.commit(builder, snapshot, height).build();
+
+        let message = match segwit {
+            true => tx.segwit_sig_message(0, &p2pkh, coin.amount),
+            false => tx.sig_message(0, &p2pkh),
+        };
+        let mut sig = sign_ecdsa(&RU256::from_u64(secret_key), &message).encode();
+        sig.push(SIGHASH_ALL);
+        let pushes = vec![sig, public_key.sec(true, false)];
+        if segwit {
+            tx.tx_ins[0].witness = pushes;
+            tx.segwit = true;
+        } else {
+            tx.tx_ins[0].script_sig = Script::new(pushes.into_iter().map(Cmd::Push).collect());
+        }
+        (tx, Prevouts::from([(outpoint, coin)]))
+    }
+
+    #[test]
+    fn test_keyset_id() {
+        let keyset = keyset("sat");
+        assert_eq!(keyset.id()[0], 0);
+        let mut other = keyset.clone();
+        other.keys.remove(&8);
+        assert_ne!(keyset.id(), other.id());
+
+        // the id doesn't cover the amounts, the snapshot does
+        let doubled = Keyset {
+            keys: keyset
+                .keys
+                .iter()
+                .map(|(a, k)| (a * 2, k.clone()))
+                .collect(),
+            ..keyset.clone()
+        };
+        assert_eq!(keyset.id(), doubled.id());
+        let snapshot = |keyset| Snapshot {
+            keysets: vec![keyset],
+            ..Default::default()
+        };
+        assert_ne!(snapshot(keyset).hash(), snapshot(doubled).hash());
+    }
+
+    #[test]
+    fn test_commitments() {
+        let mint_key = key(5);
+        let mut snapshot = Snapshot {
+            keysets: vec![keyset("sat")],
+            liabilities: BTreeMap::from([("sat".to_string(), 21_000)]),
+        };
+        let mut committer = Committer::new(6);
+        let mut verifier = Verifier::new(&mint_key);
+        let mut snapshots = vec![];
+
+        let mut block = Block::genesis("test");
+        for height in 1..=24 {
+            // anyone can post the tag and squat the mint's next sequence
+            // number, even with the mint's key in the scriptSig, spending
+            // their own coin or the mint's without its signature
+            let mut impostor = Committer::new(1);
+            impostor.next_sequence = committer.next_sequence;
+            let (mut forged, mut prevouts) =
+                commitment_tx(6, false, &mut impostor, &Snapshot::default(), height);
+            let mut cmds = forged.tx_ins[0].script_sig.cmds().unwrap();
+            cmds[1] = Cmd::Push(mint_key.sec(true, false));
+            forged.tx_ins[0].script_sig = Script::new(cmds);
+            let mut stolen = forged.clone();
+            stolen.tx_ins[0].prev_index = 5;
+            block.txs = vec![forged, stolen];
+
+            if committer.due(height) {
+                snapshots.push(snapshot.clone());
+                // the second commitment spends a P2WPKH coin
+                let segwit = snapshots.len() == 2;
+                let (tx, coin) = commitment_tx(5, segwit, &mut committer, &snapshot, height);
+                prevouts.extend(coin);
+                // the third commitment never makes it on chain
+                if snapshots.len() != 3 {
+                    block.txs.push(tx);
+                }
+            }
+            let found = verifier.process_block(&block, height, &prevouts);
+            assert_eq!(found, block.txs.len() - 2);
+            *snapshot.liabilities.get_mut("sat").unwrap() += 1_000;
+        }
+
+        assert_eq!(snapshots.len(), 4);
+        assert_eq!(verifier.verify(0, &snapshots[0]), Ok(1));
+        assert_eq!(verifier.verify(1, &snapshots[1]), Ok(7));
+        assert_eq!(verifier.verify(3, &snapshots[3]), Ok(19));
+        assert_eq!(verifier.verify(2, &snapshots[2]), Err(VerifyError::Missing));
+        assert_eq!(verifier.gaps(), [2]);
+
+        // claiming to have owed less than committed to
+        let mut understated = snapshots[1].clone();
+        understated.liabilities.insert("sat".to_string(), 0);
+        assert_eq!(verifier.verify(1, &understated), Err(VerifyError::Mismatch));
+
+        let commitment = verifier.get(0).unwrap().commitment;
+        assert_eq!(
+            Commitment::from_bytes(&commitment.to_bytes()),
+            Some(commitment)
+        );
+        assert_eq!(Commitment::from_bytes(b"ecsh"), None);
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+
+// The wallet side of a mint's `GET /v1/info` (NUT-06): who runs the mint, how
+// to reach them, a message of the day, and which NUTs it implements with
+// their settings. Wallets check it before relying on optional features, a
+// mint without NUT-11 won't honour P2PK locks and one without NUT-12 can't
+// prove its signatures with DLEQ proofs. NUTs 0 to 6 are mandatory, the rest
+// are advertised as `{"supported": true}` or, for per-method features like
+// websockets (NUT-17), a non-empty list of what's supported. Mint and melt
+// (NUT-04 and 05) can be switched off with `"disabled": true`.
+
+/// The mandatory NUTs every mint implements
+const MANDATORY_NUTS: u32 = 6;
+
+const NUT_MINT: u32 = 4;
+const NUT_MELT: u32 = 5;
+const NUT_P2PK: u32 = 11;
+const NUT_DLEQ: u32 = 12;
+const NUT_WEBSOCKETS: u32 = 17;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MintInfoError {
+    /// The mint couldn't be reached or didn't return JSON
+    Transport(String),
+    /// The response is missing fields or has the wrong types
+    InvalidResponse(String),
+}
+
+impl fmt::Display for MintInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MintInfoError::Transport(reason) => write!(f, "request failed: {}", reason),
+            MintInfoError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MintInfoError {}
+
+fn invalid(reason: &str) -> MintInfoError {
+    MintInfoError::InvalidResponse(reason.to_string())
+}
+
+/// A way to reach the mint's operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    /// `email`, `nostr`, ...
+    pub method: String,
+    pub info: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MintInfo {
+    pub name: Option<String>,
+    /// The mint's public key, compressed SEC hex
+    pub pubkey: Option<String>,
+    /// Implementation and version, e.g. `Nutshell/0.16.0`
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub description_long: Option<String>,
+    pub contact: Vec<Contact>,
+    /// Message of the day, to show users
+    pub motd: Option<String>,
+    pub icon_url: Option<String>,
+    /// URLs the mint is reachable at
+    pub urls: Vec<String>,
+    /// The mint's clock, unix seconds
+    pub time: Option<u64>,
+    /// Settings of each NUT the mint advertises
+    pub nuts: BTreeMap<u32, Value>,
+}
+
+fn str_field(json: &Value, key: &str) -> Result<Option<String>, MintInfoError> {
+    match json.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(invalid(&format!("{} isn't a string", key))),
+    }
+}
+
+impl MintInfo {
+    /// Parse an info response. Unknown fields are ignored.
+    pub fn from_json(json: &Value) -> Result<Self, MintInfoError> {
+        if !json.is_object() {
+            return Err(invalid("not an object"));
+        }
+        let pubkey = str_field(json, "pubkey")?;
+        if let Some(pubkey) = &pubkey {
+            let valid = hex::decode(pubkey)
+                .is_ok_and(|sec| sec.len() == 33 && (sec[0] == 0x02 || sec[0] == 0x03));
+            if !valid {
+                return Err(invalid("pubkey isn't a compressed public key"));
+            }
+        }
+        let contact = match json.get("contact") {
+            None | Some(Value::Null) => vec![],
+            Some(Value::Array(contacts)) => contacts
+                .iter()
+                .map(|contact| {
+                    Ok(Contact {
+                        method: str_field(contact, "method")?.ok_or_else(|| invalid("contact"))?,
+                        info: str_field(contact, "info")?.ok_or_else(|| invalid("contact"))?,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(invalid("contact isn't a list")),
+        };
+        let urls = match json.get("urls") {
+            None | Some(Value::Null) => vec![],
+            Some(urls) => urls
+                .as_array()
+                .and_then(|urls| {
+                    urls.iter()
+                        .map(|url| url.as_str().map(str::to_string))
+                        .collect()
+                })
+                .ok_or_else(|| invalid("urls isn't a list of strings"))?,
+        };
+        let time = match json.get("time") {
+            None | Some(Value::Null) => None,
+            Some(time) => Some(time.as_u64().ok_or_else(|| invalid("time"))?),
+        };
+        let nuts = match json.get("nuts") {
+            None | Some(Value::Null) => BTreeMap::new(),
+            Some(Value::Object(nuts)) => nuts
+                .iter()
+                .map(|(nut, settings)| {
+                    let nut = nut.parse().map_err(|_| invalid("nut numbers"))?;
+                    Ok((nut, settings.clone()))
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(invalid("nuts isn't an object")),
+        };
+        Ok(MintInfo {
+            name: str_field(json, "name")?,
+            pubkey,
+            version: str_field(json, "version")?,
+            description: str_field(json, "description")?,
+            description_long: str_field(json, "description_long")?,
+            contact,
+            motd: str_field(json, "motd")?,
+            icon_url: str_field(json, "icon_url")?,
+            urls,
+            time,
+            nuts,
+        })
+    }
+
+    /// The info response a mint serves, leaving out what isn't set
+    pub fn to_json(&self) -> Value {
+        let mut json = Map::new();
+        let strings = [
+            ("name", &self.name),
+            ("pubkey", &self.pubkey),
+            ("version", &self.version),
+            ("description", &self.description),
+            ("description_long", &self.description_long),
+            ("motd", &self.motd),
+            ("icon_url", &self.icon_url),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                json.insert(key.to_string(), json!(value));
+            }
+        }
+        if !self.contact.is_empty() {
+            let contact: Vec<Value> = self
+                .contact
+                .iter()
+                .map(|contact| json!({"method": contact.method, "info": contact.info}))
+                .collect();
+            json.insert("contact".to_string(), json!(contact));
+        }
+        if !self.urls.is_empty() {
+            json.insert("urls".to_string(), json!(self.urls));
+        }
+        if let Some(time) = self.time {
+            json.insert("time".to_string(), json!(time));
+        }
+        let nuts: Map<String, Value> = self
+            .nuts
+            .iter()
+            .map(|(nut, settings)| (nut.to_string(), settings.clone()))
+            .collect();
+        json.insert("nuts".to_string(), Value::Object(nuts));
+        Value::Object(json)
+    }
+
+    /// Fetch the info of the mint at `mint_url`
+    pub fn fetch(mint_url: &str) -> Result<Self, MintInfoError> {
+        let url = format!("{}/v1/info", mint_url.trim_end_matches('/'));
+        let transport = |err: reqwest::Error| MintInfoError::Transport(err.to_string());
+        let body = reqwest::blocking::get(url)
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(transport)?;
+        let json: Value =
+            serde_json::from_str(&body).map_err(|err| MintInfoError::Transport(err.to_string()))?;
+        MintInfo::from_json(&json)
+    }
+
+    /// Whether the mint implements NUT-`nut`
+    pub fn supports(&self, nut: u32) -> bool {
+        let settings = self.nuts.get(&nut);
+        match nut {
+            NUT_MINT | NUT_MELT => {
+                settings.and_then(|settings| settings.get("disabled")) != Some(&json!(true))
+            }
+            _ if nut <= MANDATORY_NUTS => true,
+            _ => match settings.and_then(|settings| settings.get("supported")) {
+                Some(Value::Bool(supported)) => *supported,
+                Some(Value::Array(methods)) => !methods.is_empty(),
+                _ => false,
+            },
+        }
+    }
+
+    /// Whether the mint honours ecash locked to a public key (NUT-11)
+    pub fn supports_p2pk(&self) -> bool {
+        self.supports(NUT_P2PK)
+    }
+
+    /// Whether the mint proves its signatures with DLEQ proofs (NUT-12)
+    pub fn supports_dleq(&self) -> bool {
+        self.supports(NUT_DLEQ)
+    }
+
+    /// Whether quote updates can be subscribed to over a websocket (NUT-17)
+    pub fn supports_websockets(&self) -> bool {
+        self.supports(NUT_WEBSOCKETS)
+    }
+}
+
+/// Mint infos by mint URL, fetched again once they're older than `ttl`
+#[derive(Debug, Clone)]
+pub struct MintInfoCache {
+    ttl: Duration,
+    entries: HashMap<String, (MintInfo, Instant)>,
+}
+
+impl MintInfoCache {
+    pub fn new(ttl: Duration) -> Self {
+        MintInfoCache {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The info of the mint at `mint_url`, from the cache if it's fresh
+    pub fn get(&mut self, mint_url: &str) -> Result<&MintInfo, MintInfoError> {
+        self.get_with(mint_url, MintInfo::fetch)
+    }
+
+    /// Like `get`, fetching with `fetch` on a miss
+    pub fn get_with<F>(&mut self, mint_url: &str, fetch: F) -> Result<&MintInfo, MintInfoError>
+    where
+        F: FnOnce(&str) -> Result<MintInfo, MintInfoError>,
+    {
+        let mint_url = mint_url.trim_end_matches('/');
+        let fresh = self
+            .entries
+            .get(mint_url)
+            .is_some_and(|(_, fetched)| fetched.elapsed() < self.ttl);
+        if !fresh {
+            let info = fetch(mint_url)?;
+            self.entries
+                .insert(mint_url.to_string(), (info, Instant::now()));
+        }
+        Ok(&self.entries[mint_url].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn info_json() -> Value {
+        // trimmed from NUT-06's example
+        json!({
+            "name": "Bob's Cashu mint",
+            "pubkey": "0283bf290884eed3a7ca2663fc0260de2e2064d6b355ea13f98dec004b7a7ead99",
+            "version": "Nutshell/0.15.0",
+            "description": "The short mint description",
+            "contact": [
+                {"method": "email", "info": "contact@me.com"},
+                {"method": "nostr", "info": "npub1337"}
+            ],
+            "motd": "Message to display to users.",
+            "time": 1725304480,
+            "nuts": {
+                "4": {
+                    "methods": [{"method": "bolt11", "unit": "sat", "min_amount": 0, "max_amount": 10000}],
+                    "disabled": false
+                },
+                "5": {"methods": [{"method": "bolt11", "unit": "sat"}], "disabled": true},
+                "7": {"supported": true},
+                "11": {"supported": true},
+                "12": {"supported": false},
+                "17": {"supported": [{"method": "bolt11", "unit": "sat", "commands": ["bolt11_mint_quote"]}]}
+            }
+        })
+    }
+
+    #[test]
+    fn test_mint_info() {
+        let info = MintInfo::from_json(&info_json()).unwrap();
+        assert_eq!(info.name.as_deref(), Some("Bob's Cashu mint"));
+        assert_eq!(info.contact[1].method, "nostr");
+        assert_eq!(info.time, Some(1725304480));
+        assert_eq!(MintInfo::from_json(&info.to_json()), Ok(info.clone()));
+
+        assert!(info.supports(0) && info.supports(NUT_MINT));
+        assert!(!info.supports(NUT_MELT));
+        assert!(info.supports(7));
+        assert!(info.supports_p2pk());
+        assert!(!info.supports_dleq());
+        assert!(info.supports_websockets());
+        assert!(!info.supports(14));
+
+        let mut bad = info_json();
+        bad["pubkey"] = json!("04");
+        assert!(MintInfo::from_json(&bad).is_err());
+        bad = info_json();
+        bad["nuts"] = json!({"four": {}});
+        assert!(MintInfo::from_json(&bad).is_err());
+        // an empty info only has the mandatory NUTs
+        let empty = MintInfo::from_json(&json!({})).unwrap();
+        assert!(empty.supports(NUT_MELT) && !empty.supports_p2pk());
+    }
+
+    #[test]
+    fn test_cache() {
+        let fetches = Cell::new(0);
+        let fetch = |_: &str| {
+            fetches.set(fetches.get() + 1);
+            MintInfo::from_json(&info_json())
+        };
+        let mut cache = MintInfoCache::new(Duration::from_secs(3600));
+        cache.get_with("https://mint.example.com/", fetch).unwrap();
+        let info = cache.get_with("https://mint.example.com", fetch).unwrap();
+        assert!(info.supports_p2pk());
+        assert_eq!(fetches.get(), 1);
+
+        // a stale entry is fetched again, a failed fetch is passed on
+        let mut cache = MintInfoCache::new(Duration::ZERO);
+        cache.get_with("https://mint.example.com", fetch).unwrap();
+        assert!(cache
+            .get_with("https://mint.example.com", |_| Err(invalid("down")))
+            .is_err());
+        assert_eq!(fetches.get(), 2);
+    }
+}
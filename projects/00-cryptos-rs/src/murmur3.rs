@@ -0,0 +1,67 @@
+// MurmurHash3, the x86 32 bit variant. Not a cryptographic hash, just a fast
+// well mixed one: BIP37 bloom filters use it with a different seed per hash
+// function, so anyone can find collisions but the filter only needs its bits
+// spread evenly.
+
+/// MurmurHash3 of `data` with `seed`
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let scramble = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut h = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        h ^= scramble(u32::from_le_bytes(chunk.try_into().unwrap()));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, &byte| (k << 8) | byte as u32);
+        h ^= scramble(k);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_murmur3() {
+        // Bitcoin Core's vectors, covering every tail length
+        let tests = [
+            (0x00000000, 0x00000000, ""),
+            (0xfba4c795, 0x6a396f08, ""),
+            (0xffffffff, 0x81f16f39, ""),
+            (0x00000000, 0x514e28b7, "00"),
+            (0xfba4c795, 0xea3f0b17, "00"),
+            (0x00000000, 0xfd6cf10d, "ff"),
+            (0x00000000, 0x16c6b7ab, "0011"),
+            (0x00000000, 0x8eb51c3d, "001122"),
+            (0x00000000, 0xb4471bf8, "00112233"),
+            (0x00000000, 0xe2301fa8, "0011223344"),
+            (0x00000000, 0xfc2e4a15, "001122334455"),
+            (0x00000000, 0xb074502c, "00112233445566"),
+            (0x00000000, 0x8034d2a0, "0011223344556677"),
+            (0x00000000, 0xb4698def, "001122334455667788"),
+        ];
+        for (seed, expected, data) in tests {
+            assert_eq!(
+                murmur3(seed, &hex::decode(data).unwrap()),
+                expected,
+                "{}",
+                data
+            );
+        }
+    }
+}
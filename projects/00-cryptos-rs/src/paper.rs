@@ -0,0 +1,181 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+use crate::keys::{wif_decode, wif_encode, PublicKey};
+#[cfg(feature = "qr")]
+use crate::qr::{EcLevel, QrCode};
+use crate::ru256::RU256;
+use crate::secp256k1::SECP256K1;
+use crate::shamir;
+use crate::shamir::Share;
+
+// Paper wallets for cold storage: a key printed as the address to pay to and
+// the WIF to sweep it with, each with a QR code when the `qr` feature is on.
+// Instead of one sheet that anyone finding it can spend from, the key can be
+// split into Shamir shares printed on separate cards and kept in different
+// places, any `threshold` of which bring it back. Every card repeats the
+// address, which is how a recovered key is checked: combining too few or the
+// wrong shares gives a different key, not an error.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperWallet {
+    pub address: String,
+    pub wif: String,
+}
+
+/// One card of a split paper wallet
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperShare {
+    /// The address of the whole key, to check a recovery against
+    pub address: String,
+    pub threshold: u8,
+    pub count: u8,
+    pub share: Share,
+}
+
+/// Append a QR code of `data`, nothing without the `qr` feature
+fn push_qr(out: &mut String, data: &str) {
+    #[cfg(feature = "qr")]
+    out.push_str(
+        &QrCode::encode(data.as_bytes(), EcLevel::Medium)
+            .expect("short enough for a QR code")
+            .to_terminal(),
+    );
+    #[cfg(not(feature = "qr"))]
+    let _ = (out, data);
+}
+
+impl PaperWallet {
+    /// The sheet for a key, with its compressed P2PKH address
+    pub fn new(secret_key: &RU256, net: &str) -> Self {
+        PaperWallet {
+            address: PublicKey::from_sk(secret_key).address(net, true),
+            wif: wif_encode(secret_key, net, true),
+        }
+    }
+
+    /// The secret key back from the WIF
+    pub fn secret_key(&self) -> RU256 {
+        wif_decode(&self.wif)
+            .expect("paper wallet holds a valid WIF")
+            .0
+    }
+
+    /// The printable sheet
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Address (share this to receive)\n{}\n", self.address);
+        push_qr(&mut out, &self.address);
+        out.push_str(&format!(
+            "\nPrivate key, WIF (keep secret, sweep to spend)\n{}\n",
+            self.wif
+        ));
+        push_qr(&mut out, &self.wif);
+        out
+    }
+
+    /// Split the key into `count` cards, any `threshold` of which recover it
+    #[cfg(feature = "rand")]
+    pub fn split(&self, threshold: u8, count: u8) -> Vec<PaperShare> {
+        self.split_with_rng(threshold, count, &mut rand::thread_rng())
+    }
+
+    #[cfg(feature = "rand")]
+    pub fn split_with_rng<R: Rng + ?Sized>(
+        &self,
+        threshold: u8,
+        count: u8,
+        rng: &mut R,
+    ) -> Vec<PaperShare> {
+        let mut secret = [0u8; 32];
+        self.secret_key().to_bytes(&mut secret);
+        shamir::split_with_rng(&secret, threshold, count, rng)
+            .into_iter()
+            .map(|share| PaperShare {
+                address: self.address.clone(),
+                threshold,
+                count,
+                share,
+            })
+            .collect()
+    }
+
+    /// The wallet behind `shares`, None unless they combine to the key of
+    /// the address printed on them
+    pub fn recover(shares: &[PaperShare]) -> Option<Self> {
+        let address = &shares.first()?.address;
+        if shares.iter().any(|share| share.address != *address) {
+            return None;
+        }
+        let parts: Vec<Share> = shares.iter().map(|share| share.share.clone()).collect();
+        let secret = shamir::combine(&parts)?;
+        if secret.len() != 32 {
+            return None;
+        }
+        let secret_key = RU256::from_bytes(&secret);
+        if secret_key.is_zero() || secret_key >= SECP256K1::n() {
+            return None;
+        }
+        // the address says which net the key is for
+        let net = match address.chars().next()? {
+            '1' => "main",
+            'm' | 'n' => "test",
+            _ => return None,
+        };
+        let wallet = PaperWallet::new(&secret_key, net);
+        (wallet.address == *address).then_some(wallet)
+    }
+}
+
+impl PaperShare {
+    /// The printable card, the share value in hex
+    pub fn to_text(&self) -> String {
+        let value = hex::encode(&self.share.value);
+        let mut out = format!(
+            "Share {} of {}, any {} recover the key of\n{}\n\n{:02x}{}\n",
+            self.share.index, self.count, self.threshold, self.address, self.share.index, value
+        );
+        push_qr(&mut out, &format!("{:02x}{}", self.share.index, value));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_paper_wallet() {
+        let secret_key = RU256::from_str_radix(
+            "1E99423A4ED27608A15A2616A2B0E9E52CED330AC530EDCC32C8FFC6A526AEDD",
+            16,
+        )
+        .unwrap();
+        let wallet = PaperWallet::new(&secret_key, "main");
+        assert_eq!(
+            wallet.wif,
+            "KxFC1jmwwCoACiCAWZ3eXa96mBM6tb3TYzGmf6YwgdGWZgawvrtJ"
+        );
+        assert_eq!(wallet.address, "1J7mdg5rbQyUHENYdx39WVWK7fsLpEoXZy");
+        assert_eq!(wallet.secret_key(), secret_key);
+        let sheet = wallet.to_text();
+        assert!(sheet.contains(&wallet.address) && sheet.contains(&wallet.wif));
+
+        let shares = wallet.split_with_rng(2, 3, &mut StdRng::seed_from_u64(5));
+        assert!(shares[0].to_text().contains("Share 1 of 3, any 2"));
+        assert_eq!(
+            PaperWallet::recover(&[shares[2].clone(), shares[0].clone()]),
+            Some(wallet.clone())
+        );
+        assert_eq!(PaperWallet::recover(&shares[1..2]), None);
+        let mut tampered = shares[1].clone();
+        tampered.share.value[0] ^= 1;
+        assert_eq!(PaperWallet::recover(&[shares[0].clone(), tampered]), None);
+    }
+}
@@ -0,0 +1,737 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+use serde_json::{json, Value};
+
+use crate::amount::{Amount, FeeRate};
+use crate::bip21::PaymentUri;
+use crate::broadcast::{BroadcastError, Broadcaster};
+use crate::encoding::Encodable;
+use crate::hashes::hash160;
+use crate::policy::ScriptType;
+use crate::psbt::Psbt;
+use crate::signer::{Signer, SignerError};
+use crate::transaction::{Cmd, Prevouts, Script, Tx, TxIn, TxOut};
+
+// PayJoin (BIP78), a payment where the receiver adds an input of its own.
+// Chain analysis assumes all inputs of a transaction belong to the payer and
+// that the output matching the amount is the payment; a payjoin breaks both,
+// the receiver's input makes the payment look larger than it was. The sender
+// POSTs a signed original PSBT to the `pj=` endpoint of the BIP21 URI, the
+// receiver answers with a proposal holding its signed input and the payment
+// output grown by that input, and the sender checks it took nothing it
+// shouldn't, signs its inputs again and broadcasts. The original is a valid
+// transaction by itself, so the receiver can broadcast it if the sender
+// walks away, and the sender broadcasts it whenever the payjoin fails.
+// Inputs here are P2PKH since that's what the signers sign.
+
+/// vbytes one P2PKH input adds, with a 72 byte signature and compressed key
+const P2PKH_INPUT_VSIZE: usize = 148;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayjoinError {
+    /// The receiver can't payjoin right now, e.g. it has no coins to add
+    Unavailable,
+    /// The receiver would need to pay too much fee
+    NotEnoughMoney,
+    VersionUnsupported,
+    /// The receiver refused the original, with its reason
+    OriginalPsbtRejected(String),
+    /// The proposal breaks a rule the sender checks, broadcast the original
+    InvalidProposal(String),
+    Signer(SignerError),
+    /// The endpoint couldn't be reached or answered garbage
+    Transport(String),
+}
+
+impl PayjoinError {
+    /// The BIP78 error code sent back to the sender
+    pub fn code(&self) -> &'static str {
+        match self {
+            PayjoinError::Unavailable => "unavailable",
+            PayjoinError::NotEnoughMoney => "not-enough-money",
+            PayjoinError::VersionUnsupported => "version-unsupported",
+            _ => "original-psbt-rejected",
+        }
+    }
+
+    /// An error from the JSON body of a failed request
+    fn from_response(body: &str) -> Self {
+        let Ok(error) = serde_json::from_str::<Value>(body) else {
+            return PayjoinError::Transport(body.to_string());
+        };
+        let message = error["message"].as_str().unwrap_or_default().to_string();
+        match error["errorCode"].as_str() {
+            Some("unavailable") => PayjoinError::Unavailable,
+            Some("not-enough-money") => PayjoinError::NotEnoughMoney,
+            Some("version-unsupported") => PayjoinError::VersionUnsupported,
+            Some("original-psbt-rejected") => PayjoinError::OriginalPsbtRejected(message),
+            _ => PayjoinError::Transport(body.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for PayjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayjoinError::Unavailable => write!(f, "receiver can't payjoin right now"),
+            PayjoinError::NotEnoughMoney => write!(f, "receiver can't cover the fee"),
+            PayjoinError::VersionUnsupported => write!(f, "unsupported payjoin version"),
+            PayjoinError::OriginalPsbtRejected(reason) => {
+                write!(f, "original PSBT rejected: {}", reason)
+            }
+            PayjoinError::InvalidProposal(reason) => write!(f, "invalid proposal: {}", reason),
+            PayjoinError::Signer(err) => write!(f, "signing failed: {}", err),
+            PayjoinError::Transport(reason) => write!(f, "payjoin request failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PayjoinError {}
+
+fn rejected(reason: &str) -> PayjoinError {
+    PayjoinError::OriginalPsbtRejected(reason.to_string())
+}
+
+fn invalid(reason: &str) -> PayjoinError {
+    PayjoinError::InvalidProposal(reason.to_string())
+}
+
+/// The sender's terms, sent as the query string
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayjoinParams {
+    /// The sender output the receiver may take the fee for its input from,
+    /// usually the change
+    pub additional_fee_output_index: Option<usize>,
+    pub max_additional_fee_contribution: Amount,
+    /// The proposal has to pay at least this much
+    pub min_fee_rate: FeeRate,
+}
+
+impl PayjoinParams {
+    pub fn to_query(&self) -> String {
+        let mut query = String::from("v=1");
+        if let Some(index) = self.additional_fee_output_index {
+            query.push_str(&format!(
+                "&additionalfeeoutputindex={}&maxadditionalfeecontribution={}",
+                index,
+                self.max_additional_fee_contribution.to_sat()
+            ));
+        }
+        let kvb = self.min_fee_rate.to_sat_per_kvb();
+        if kvb > 0 {
+            query.push_str(&format!("&minfeerate={}.{:03}", kvb / 1000, kvb % 1000));
+        }
+        query
+    }
+
+    pub fn from_query(query: &str) -> Result<Self, PayjoinError> {
+        let mut params = PayjoinParams {
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: Amount::ZERO,
+            min_fee_rate: FeeRate::ZERO,
+        };
+        let mut version = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let bad = || rejected(&format!("invalid parameter {}", key));
+            match key {
+                "v" => version = Some(value.to_string()),
+                "additionalfeeoutputindex" => {
+                    params.additional_fee_output_index = Some(value.parse().map_err(|_| bad())?)
+                }
+                "maxadditionalfeecontribution" => {
+                    params.max_additional_fee_contribution =
+                        Amount::from_sat(value.parse().map_err(|_| bad())?)
+                }
+                "minfeerate" => {
+                    let rate: f64 = value.parse().map_err(|_| bad())?;
+                    if !rate.is_finite() || rate < 0.0 {
+                        return Err(bad());
+                    }
+                    params.min_fee_rate = FeeRate::from_sat_per_kvb((rate * 1000.0) as u64);
+                }
+                // other parameters are optional to understand
+                _ => {}
+            }
+        }
+        if version.as_deref() != Some("1") {
+            return Err(PayjoinError::VersionUnsupported);
+        }
+        Ok(params)
+    }
+}
+
+/// The payjoin endpoint of a BIP21 URI, its `pj` parameter
+pub fn endpoint(uri: &PaymentUri) -> Option<&str> {
+    uri.extras
+        .iter()
+        .find(|(key, _)| key == "pj")
+        .map(|(_, value)| value.as_str())
+}
+
+/// Sign input `index` of the PSBT's transaction spending P2PKH `script_pubkey`
+/// and put the scriptSig in place
+fn sign_p2pkh<S: Signer>(
+    psbt: &mut Psbt,
+    index: usize,
+    script_pubkey: &Script,
+    signer: &mut S,
+    key: u32,
+) -> Result<(), PayjoinError> {
+    let public_key = signer.get_pubkey(key).map_err(PayjoinError::Signer)?;
+    let sig = signer
+        .sign_tx_input(key, &psbt.unsigned_tx, index, script_pubkey)
+        .map_err(PayjoinError::Signer)?;
+    psbt.inputs[index].final_script_sig = Some(Script::new(vec![
+        Cmd::Push(sig),
+        Cmd::Push(public_key.sec(true, false)),
+    ]));
+    Ok(())
+}
+
+/// The outputs the PSBT's inputs spend, None if one is missing
+fn spent_outputs(psbt: &Psbt) -> Option<Prevouts> {
+    let mut prevouts = Prevouts::new();
+    for (i, tx_in) in psbt.unsigned_tx.tx_ins.iter().enumerate() {
+        prevouts.insert(
+            (tx_in.prev_tx.clone(), tx_in.prev_index),
+            psbt.spent_output(i)?,
+        );
+    }
+    Some(prevouts)
+}
+
+/// A P2PKH coin to spend: the transaction it's in, its index and the signer
+/// key of its output
+#[derive(Debug, Clone)]
+pub struct Coin {
+    pub tx: Tx,
+    pub vout: u32,
+    pub key: u32,
+}
+
+pub struct PayjoinReceiver<S: Signer> {
+    /// The script the receiver asked to be paid to
+    pub script_pubkey: Script,
+    pub signer: S,
+    /// Coins to add, the first one is used and then dropped
+    pub utxos: Vec<Coin>,
+}
+
+impl<S: Signer> PayjoinReceiver<S> {
+    /// Check the original PSBT and answer with the proposal, both base64
+    pub fn process(&mut self, original: &str, query: &str) -> Result<String, PayjoinError> {
+        let params = PayjoinParams::from_query(query)?;
+        let original = Psbt::from_base64(original).map_err(|_| rejected("invalid PSBT"))?;
+        let prevouts = spent_outputs(&original).ok_or_else(|| rejected("missing UTXO"))?;
+        let tx = original
+            .extract_tx()
+            .ok_or_else(|| rejected("inputs not finalized"))?;
+        let fee = tx.fee(&prevouts).map_err(|_| rejected("negative fee"))?;
+        if prevouts
+            .values()
+            .any(|tx_out| tx_out.script_pubkey == self.script_pubkey)
+        {
+            return Err(rejected("spends the receiver's own coins"));
+        }
+        let payment = tx
+            .tx_outs
+            .iter()
+            .position(|tx_out| tx_out.script_pubkey == self.script_pubkey)
+            .ok_or_else(|| rejected("doesn't pay the receiver"))?;
+        let utxo = self
+            .utxos
+            .first()
+            .cloned()
+            .ok_or(PayjoinError::Unavailable)?;
+        let spent = utxo
+            .tx
+            .tx_outs
+            .get(utxo.vout as usize)
+            .cloned()
+            .ok_or(PayjoinError::Unavailable)?;
+
+        // our coin joins the inputs and its value the payment
+        let mut proposal = Psbt::from_unsigned_tx(tx.clone());
+        let mut txid = hex::decode(utxo.tx.id()).unwrap();
+        txid.reverse();
+        proposal.unsigned_tx.tx_ins.push(TxIn {
+            prev_tx: txid,
+            prev_index: utxo.vout,
+            sequence: tx.tx_ins[0].sequence,
+            net: tx.tx_ins[0].net.clone(),
+            ..Default::default()
+        });
+        proposal.inputs.push(Default::default());
+        let outputs = &mut proposal.unsigned_tx.tx_outs;
+        outputs[payment].amount += spent.amount;
+
+        // the bigger transaction has to keep the fee rate, the sender pays
+        // for our input up to its limit and we pay the rest
+        let fee_rate = FeeRate::from_fee(fee, tx.vsize()).max(params.min_fee_rate);
+        let mut additional = fee_rate.fee(P2PKH_INPUT_VSIZE);
+        if let Some(index) = params.additional_fee_output_index {
+            if index == payment || index >= outputs.len() {
+                return Err(rejected("invalid additionalfeeoutputindex"));
+            }
+            let from_sender = additional
+                .min(params.max_additional_fee_contribution)
+                .min(outputs[index].amount);
+            outputs[index].amount -= from_sender;
+            additional -= from_sender;
+        }
+        outputs[payment].amount = outputs[payment]
+            .amount
+            .checked_sub(additional)
+            .ok_or(PayjoinError::NotEnoughMoney)?;
+
+        let last = proposal.inputs.len() - 1;
+        proposal.inputs[last].non_witness_utxo = Some(utxo.tx.clone());
+        sign_p2pkh(
+            &mut proposal,
+            last,
+            &spent.script_pubkey,
+            &mut self.signer,
+            utxo.key,
+        )?;
+        self.utxos.remove(0);
+        Ok(proposal.to_base64())
+    }
+
+    /// Answer one request on `listener` with a proposal, or the error as
+    /// BIP78's JSON
+    pub fn serve_once(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let query = request_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|target| target.split_once('?'))
+            .map(|(_, query)| query.to_string())
+            .unwrap_or_default();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let (status, body) = match self.process(&String::from_utf8_lossy(&body), &query) {
+            Ok(proposal) => ("200 OK", proposal),
+            Err(err) => (
+                "400 Bad Request",
+                json!({"errorCode": err.code(), "message": err.to_string()}).to_string(),
+            ),
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+}
+
+pub struct PayjoinSender {
+    /// The signed original, broadcastable by itself
+    pub original: Psbt,
+    pub params: PayjoinParams,
+    /// The output paying the receiver
+    pub payee: Script,
+}
+
+impl PayjoinSender {
+    /// The original's transaction, to broadcast when the payjoin fails
+    pub fn original_tx(&self) -> Tx {
+        self.original
+            .extract_tx()
+            .expect("the original is finalized")
+    }
+
+    /// POST the original to `endpoint` and return the receiver's proposal
+    pub fn request(&self, endpoint: &str) -> Result<String, PayjoinError> {
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}{}", endpoint, separator, self.params.to_query());
+        let transport = |err: reqwest::Error| PayjoinError::Transport(err.to_string());
+        let response = reqwest::blocking::Client::new()
+            .post(url)
+            .header("Content-Type", "text/plain")
+            .body(self.original.to_base64())
+            .send()
+            .map_err(transport)?;
+        let status = response.status();
+        let body = response.text().map_err(transport)?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(PayjoinError::from_response(&body))
+        }
+    }
+
+    /// Check the receiver's proposal and sign our inputs in it, with `keys`
+    /// the signer key of each original input
+    pub fn process_proposal<S: Signer>(
+        &self,
+        proposal: &str,
+        signer: &mut S,
+        keys: &[u32],
+    ) -> Result<Tx, PayjoinError> {
+        let mut proposal = Psbt::from_base64(proposal).map_err(|_| invalid("not a PSBT"))?;
+        let original = &self.original.unsigned_tx;
+        let tx = &proposal.unsigned_tx;
+        if tx.version != original.version || tx.locktime != original.locktime {
+            return Err(invalid("version or locktime changed"));
+        }
+
+        // our inputs are all still there and unsigned, theirs are signed and
+        // of the same type as ours so the transaction doesn't show two wallets
+        let our_prevouts = spent_outputs(&self.original).expect("original has its UTXOs");
+        let our_type = ScriptType::of(&our_prevouts.values().next().unwrap().script_pubkey);
+        let mut our_inputs = vec![];
+        let mut prevouts = our_prevouts.clone();
+        for (i, tx_in) in tx.tx_ins.iter().enumerate() {
+            let outpoint = (tx_in.prev_tx.clone(), tx_in.prev_index);
+            if let Some(j) = original
+                .tx_ins
+                .iter()
+                .position(|ours| (ours.prev_tx.clone(), ours.prev_index) == outpoint)
+            {
+                if proposal.is_finalized(i) || tx_in.sequence != original.tx_ins[j].sequence {
+                    return Err(invalid("our input was changed"));
+                }
+                our_inputs.push((i, j));
+                continue;
+            }
+            let spent = proposal
+                .spent_output(i)
+                .ok_or_else(|| invalid("receiver input without UTXO"))?;
+            if !proposal.is_finalized(i) || ScriptType::of(&spent.script_pubkey) != our_type {
+                return Err(invalid("receiver input unsigned or of another type"));
+            }
+            prevouts.insert(outpoint, spent);
+        }
+        if our_inputs.len() != original.tx_ins.len() {
+            return Err(invalid("our inputs are missing"));
+        }
+
+        // the same outputs, only the payment and the fee output may change
+        if tx.tx_outs.len() != original.tx_outs.len() {
+            return Err(invalid("outputs added or removed"));
+        }
+        let mut contribution = Amount::ZERO;
+        for (i, (ours, theirs)) in original.tx_outs.iter().zip(&tx.tx_outs).enumerate() {
+            if ours.script_pubkey != theirs.script_pubkey {
+                return Err(invalid("outputs changed"));
+            }
+            if ours.script_pubkey == self.payee {
+                continue;
+            }
+            if theirs.amount < ours.amount && Some(i) == self.params.additional_fee_output_index {
+                contribution = ours.amount - theirs.amount;
+            } else if theirs.amount != ours.amount {
+                return Err(invalid("our output amounts changed"));
+            }
+        }
+        if contribution > self.params.max_additional_fee_contribution {
+            return Err(invalid("fee contribution too high"));
+        }
+
+        // what we take out of the fee output has to go to fees, not to them
+        let original_fee = self.original_tx().fee(&our_prevouts).unwrap();
+        let fee = tx.fee(&prevouts).map_err(|_| invalid("negative fee"))?;
+        if fee < original_fee + contribution {
+            return Err(invalid("contribution didn't go to fees"));
+        }
+
+        for (i, j) in our_inputs {
+            let script_pubkey = self.original.spent_output(j).unwrap().script_pubkey;
+            sign_p2pkh(&mut proposal, i, &script_pubkey, signer, keys[j])?;
+        }
+        let tx = proposal.extract_tx().unwrap();
+        if FeeRate::from_fee(fee, tx.vsize()) < self.params.min_fee_rate {
+            return Err(invalid("fee rate below minfeerate"));
+        }
+        Ok(tx)
+    }
+
+    /// The whole flow: request a payjoin from `endpoint`, sign it and
+    /// broadcast it, or broadcast the original if anything fails. Returns
+    /// the txid and whether it's the payjoin.
+    pub fn send<S: Signer, B: Broadcaster>(
+        &self,
+        endpoint: &str,
+        signer: &mut S,
+        keys: &[u32],
+        broadcaster: &B,
+    ) -> Result<(String, bool), BroadcastError> {
+        let payjoin = self
+            .request(endpoint)
+            .and_then(|proposal| self.process_proposal(&proposal, signer, keys));
+        match payjoin {
+            Ok(tx) => Ok((broadcaster.broadcast_hex(&hex::encode(tx.encode()))?, true)),
+            Err(_) => {
+                let tx = self.original_tx();
+                Ok((broadcaster.broadcast_hex(&hex::encode(tx.encode()))?, false))
+            }
+        }
+    }
+}
+
+/// The original PSBT for paying `amount` to `payee` from P2PKH coins, with
+/// `change` for the rest and every input signed
+pub fn original_psbt<S: Signer>(
+    coins: &[Coin],
+    payee: &Script,
+    amount: Amount,
+    change: TxOut,
+    signer: &mut S,
+) -> Result<Psbt, PayjoinError> {
+    let mut tx = Tx {
+        version: 2,
+        tx_outs: vec![
+            TxOut {
+                amount,
+                script_pubkey: payee.clone(),
+            },
+            change,
+        ],
+        ..Default::default()
+    };
+    for coin in coins {
+        let mut txid = hex::decode(coin.tx.id()).unwrap();
+        txid.reverse();
+        tx.tx_ins.push(TxIn {
+            prev_tx: txid,
+            prev_index: coin.vout,
+            sequence: 0xfffffffd,
+            ..Default::default()
+        });
+    }
+    let mut psbt = Psbt::from_unsigned_tx(tx);
+    for (i, coin) in coins.iter().enumerate() {
+        psbt.inputs[i].non_witness_utxo = Some(coin.tx.clone());
+        let script_pubkey = coin.tx.tx_outs[coin.vout as usize].script_pubkey.clone();
+        let public_key = signer.get_pubkey(coin.key).map_err(PayjoinError::Signer)?;
+        assert_eq!(
+            script_pubkey,
+            Script::p2pkh(&hash160(&public_key.sec(true, false))),
+            "coin {} isn't P2PKH to the signer's key",
+            i
+        );
+        sign_p2pkh(&mut psbt, i, &script_pubkey, signer, coin.key)?;
+    }
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::thread;
+
+    use super::*;
+    use crate::keys::PublicKey;
+    use crate::ru256::RU256;
+    use crate::signer::SoftwareSigner;
+    use crate::transaction::TxBuilder;
+
+    /// Records what it's asked to broadcast
+    #[derive(Default)]
+    struct MockBroadcaster {
+        sent: RefCell<Vec<Tx>>,
+    }
+
+    impl Broadcaster for MockBroadcaster {
+        fn broadcast_hex(&self, tx_hex: &str) -> Result<String, BroadcastError> {
+            let tx: Tx = crate::encoding::FromHex::from_hex(tx_hex);
+            self.sent.borrow_mut().push(tx.clone());
+            Ok(tx.id())
+        }
+    }
+
+    fn p2pkh(signer: &mut SoftwareSigner, key: u32) -> Script {
+        let public_key: PublicKey = signer.get_pubkey(key).unwrap();
+        Script::p2pkh(&hash160(&public_key.sec(true, false)))
+    }
+
+    fn funding(script_pubkey: Script, sat: u64, seed: u8) -> Tx {
+        TxBuilder::new("main")
+            .add_input(vec![seed; 32], 0)
+            .add_output(Amount::from_sat(sat), script_pubkey)
+            .build()
+    }
+
+    /// Every input's signature checks out against the output it spends
+    fn assert_signed(tx: &Tx, spent: &[&Script]) {
+        for (i, script_pubkey) in spent.iter().enumerate() {
+            let script = tx.tx_ins[i].script_sig.clone() + (*script_pubkey).clone();
+            assert!(script.evaluate(&tx.sig_message(i, script_pubkey)));
+        }
+    }
+
+    #[test]
+    fn test_payjoin() {
+        let mut sender_signer = SoftwareSigner::new(vec![RU256::from_u64(1001)]);
+        let mut receiver_signer = SoftwareSigner::new(vec![RU256::from_u64(2002)]);
+        let sender_script = p2pkh(&mut sender_signer, 0);
+        let receiver_script = p2pkh(&mut receiver_signer, 0);
+        let sender_coin = Coin {
+            tx: funding(sender_script.clone(), 50_000, 1),
+            vout: 0,
+            key: 0,
+        };
+        let receiver_coin = Coin {
+            tx: funding(receiver_script.clone(), 40_000, 2),
+            vout: 0,
+            key: 0,
+        };
+
+        // pay 30k with a 1000 sat fee, about 4 sat/vB
+        let change = TxOut {
+            amount: Amount::from_sat(19_000),
+            script_pubkey: sender_script.clone(),
+        };
+        let original = original_psbt(
+            std::slice::from_ref(&sender_coin),
+            &receiver_script,
+            Amount::from_sat(30_000),
+            change,
+            &mut sender_signer,
+        )
+        .unwrap();
+        let sender = PayjoinSender {
+            original,
+            params: PayjoinParams {
+                additional_fee_output_index: Some(1),
+                max_additional_fee_contribution: Amount::from_sat(1_000),
+                min_fee_rate: FeeRate::from_sat_per_vb(1),
+            },
+            payee: receiver_script.clone(),
+        };
+        assert_eq!(
+            PayjoinParams::from_query(&sender.params.to_query()),
+            Ok(sender.params.clone())
+        );
+        assert_eq!(
+            PayjoinParams::from_query("v=2"),
+            Err(PayjoinError::VersionUnsupported)
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/pj", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut receiver = PayjoinReceiver {
+                script_pubkey: receiver_script,
+                signer: receiver_signer,
+                utxos: vec![receiver_coin],
+            };
+            receiver.serve_once(&listener).unwrap();
+            // out of coins for the second request
+            receiver.serve_once(&listener).unwrap();
+        });
+
+        let broadcaster = MockBroadcaster::default();
+        let (txid, joined) = sender
+            .send(&endpoint, &mut sender_signer, &[0], &broadcaster)
+            .unwrap();
+        assert!(joined);
+        let tx = broadcaster.sent.borrow()[0].clone();
+        assert_eq!(tx.id(), txid);
+        assert_eq!(tx.tx_ins.len(), 2);
+        // the payment grew by the receiver's coin and the change paid the
+        // fee for its input
+        let fee_rate = FeeRate::from_fee(Amount::from_sat(1_000), sender.original_tx().vsize());
+        let additional = fee_rate.fee(P2PKH_INPUT_VSIZE);
+        assert_eq!(tx.tx_outs[0].amount, Amount::from_sat(70_000));
+        assert_eq!(tx.tx_outs[1].amount, Amount::from_sat(19_000) - additional);
+        let spent = [
+            &sender_coin.tx.tx_outs[0].script_pubkey,
+            &p2pkh(&mut SoftwareSigner::new(vec![RU256::from_u64(2002)]), 0),
+        ];
+        assert_signed(&tx, &spent);
+
+        // the receiver can't join again, so the original goes out
+        assert_eq!(sender.request(&endpoint), Err(PayjoinError::Unavailable));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_proposal_checks() {
+        let mut signer = SoftwareSigner::new(vec![RU256::from_u64(1001), RU256::from_u64(2002)]);
+        let sender_script = p2pkh(&mut signer, 0);
+        let receiver_script = p2pkh(&mut signer, 1);
+        let coin = Coin {
+            tx: funding(sender_script.clone(), 50_000, 1),
+            vout: 0,
+            key: 0,
+        };
+        let change = TxOut {
+            amount: Amount::from_sat(19_000),
+            script_pubkey: sender_script,
+        };
+        let original = original_psbt(
+            &[coin],
+            &receiver_script,
+            Amount::from_sat(30_000),
+            change,
+            &mut signer,
+        )
+        .unwrap();
+        let sender = PayjoinSender {
+            original: original.clone(),
+            params: PayjoinParams {
+                additional_fee_output_index: Some(1),
+                max_additional_fee_contribution: Amount::from_sat(100),
+                min_fee_rate: FeeRate::ZERO,
+            },
+            payee: receiver_script.clone(),
+        };
+
+        // a receiver that isn't paid by the original turns it down
+        let mut stranger = PayjoinReceiver {
+            script_pubkey: Script::p2pkh(&[0xaa; 20]),
+            signer: signer.clone(),
+            utxos: vec![],
+        };
+        assert!(matches!(
+            stranger.process(&original.to_base64(), "v=1"),
+            Err(PayjoinError::OriginalPsbtRejected(_))
+        ));
+
+        // skimming the change beyond the allowed contribution
+        let mut proposal = Psbt::from_unsigned_tx(sender.original_tx());
+        proposal.unsigned_tx.tx_outs[1].amount -= Amount::from_sat(500);
+        assert_eq!(
+            sender
+                .process_proposal(&proposal.to_base64(), &mut signer, &[0])
+                .err(),
+            Some(PayjoinError::InvalidProposal(
+                "fee contribution too high".to_string()
+            ))
+        );
+        // or redirecting it
+        let mut proposal = Psbt::from_unsigned_tx(sender.original_tx());
+        proposal.unsigned_tx.tx_outs[1].script_pubkey = Script::p2pkh(&[0xaa; 20]);
+        assert_eq!(
+            sender
+                .process_proposal(&proposal.to_base64(), &mut signer, &[0])
+                .err(),
+            Some(PayjoinError::InvalidProposal("outputs changed".to_string()))
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::encoding::{base64_decode, base64_encode};
+
+// Cashu payment requests (NUT-18), `creqA` followed by the base64url of a CBOR
+// map with single letter keys. The receiver says what it wants paid: an
+// amount in some unit, from which mints, and where to send the ecash, say a
+// nostr profile or an HTTP endpoint. Every field is optional, a request
+// without an amount leaves it to the payer and one without mints takes any.
+// Only the CBOR the requests use is supported: unsigned integers, byte and
+// text strings, arrays, maps and booleans, all of definite length. Unknown
+// keys are skipped so newer fields don't break older wallets.
+
+const PREFIX: &str = "creqA";
+
+/// Arrays and maps nested deeper than this are rejected, requests only go
+/// three levels deep (transport tags)
+const MAX_DEPTH: usize = 8;
+
+/// Where to send the ecash paying a request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transport {
+    /// `nostr` or `post`
+    pub kind: String,
+    /// An nprofile for nostr, a URL for post
+    pub target: String,
+    /// Extra parameters, each a key followed by values, e.g. the NIPs a nostr
+    /// receiver reads
+    pub tags: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaymentRequest {
+    /// Returned with the payment so the receiver can match them up
+    pub payment_id: Option<String>,
+    pub amount: Option<u64>,
+    /// `sat`, `usd`, ...
+    pub unit: Option<String>,
+    /// Whether the request can only be paid once
+    pub single_use: Option<bool>,
+    /// Mints whose ecash is accepted, any if empty
+    pub mints: Vec<String>,
+    pub description: Option<String>,
+    /// Where to send the payment, in order of preference. Without any the
+    /// payer has to hand the ecash over some other way.
+    pub transports: Vec<Transport>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestError {
+    /// Doesn't start with `creqA`
+    Prefix,
+    /// Not base64
+    Encoding,
+    /// Malformed or unsupported CBOR, or not a map
+    Cbor,
+    /// A field of the wrong type, or a transport without a type or target
+    Field(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Prefix => write!(f, "not a creqA payment request"),
+            RequestError::Encoding => write!(f, "invalid base64"),
+            RequestError::Cbor => write!(f, "invalid CBOR"),
+            RequestError::Field(key) => write!(f, "invalid field {}", key),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RequestError {}
+
+impl PaymentRequest {
+    /// A request for `amount` of `unit`, from any mint
+    pub fn new(amount: u64, unit: &str) -> Self {
+        PaymentRequest {
+            amount: Some(amount),
+            unit: Some(unit.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Whether ecash from `mint` can pay the request
+    pub fn accepts_mint(&self, mint: &str) -> bool {
+        let mint = mint.trim_end_matches('/');
+        self.mints.is_empty()
+            || self
+                .mints
+                .iter()
+                .any(|accepted| accepted.trim_end_matches('/') == mint)
+    }
+}
+
+/// The subset of CBOR values payment requests are made of
+#[derive(Debug, Clone, PartialEq)]
+enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Bool(bool),
+    Null,
+}
+
+/// The major type in the top 3 bits, the argument in the low 5 if it's under
+/// 24, otherwise in the 1, 2, 4 or 8 big endian bytes that follow
+fn encode_head(major: u8, arg: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xff => out.extend([major | 24, arg as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((arg as u16).to_be_bytes());
+        }
+        0x10000..=0xffffffff => {
+            out.push(major | 26);
+            out.extend((arg as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(arg.to_be_bytes());
+        }
+    }
+}
+
+fn split_off<'a>(bytes: &mut &'a [u8], n: u64) -> Option<&'a [u8]> {
+    let n = usize::try_from(n).ok()?;
+    if bytes.len() < n {
+        return None;
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Some(head)
+}
+
+impl Cbor {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Cbor::Uint(n) => encode_head(0, *n, out),
+            Cbor::Bytes(bytes) => {
+                encode_head(2, bytes.len() as u64, out);
+                out.extend(bytes);
+            }
+            Cbor::Text(text) => {
+                encode_head(3, text.len() as u64, out);
+                out.extend(text.as_bytes());
+            }
+            Cbor::Array(items) => {
+                encode_head(4, items.len() as u64, out);
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Cbor::Map(entries) => {
+                encode_head(5, entries.len() as u64, out);
+                for (key, value) in entries {
+                    key.encode(out);
+                    value.encode(out);
+                }
+            }
+            Cbor::Bool(b) => encode_head(7, 20 + *b as u64, out),
+            Cbor::Null => encode_head(7, 22, out),
+        }
+    }
+
+    /// Decode a value from the front of `bytes`, None if it's malformed or
+    /// uses something outside the subset
+    fn decode(bytes: &mut &[u8], depth: usize) -> Option<Cbor> {
+        let first = split_off(bytes, 1)?[0];
+        let (major, info) = (first >> 5, first & 0x1f);
+        let arg = match info {
+            0..=23 => info as u64,
+            24..=27 => split_off(bytes, 1 << (info - 24))?
+                .iter()
+                .fold(0, |arg, &b| arg << 8 | b as u64),
+            // indefinite lengths
+            _ => return None,
+        };
+        let value = match major {
+            0 => Cbor::Uint(arg),
+            2 => Cbor::Bytes(split_off(bytes, arg)?.to_vec()),
+            3 => Cbor::Text(String::from_utf8(split_off(bytes, arg)?.to_vec()).ok()?),
+            4 if depth < MAX_DEPTH => Cbor::Array(
+                (0..arg)
+                    .map(|_| Cbor::decode(bytes, depth + 1))
+                    .collect::<Option<_>>()?,
+            ),
+            5 if depth < MAX_DEPTH => Cbor::Map(
+                (0..arg)
+                    .map(|_| {
+                        Some((
+                            Cbor::decode(bytes, depth + 1)?,
+                            Cbor::decode(bytes, depth + 1)?,
+                        ))
+                    })
+                    .collect::<Option<_>>()?,
+            ),
+            // floats and other simple values aren't used
+            7 if info < 24 => match arg {
+                20 => Cbor::Bool(false),
+                21 => Cbor::Bool(true),
+                22 => Cbor::Null,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+fn text(value: &Cbor, key: &str) -> Result<String, RequestError> {
+    match value {
+        Cbor::Text(text) => Ok(text.clone()),
+        _ => Err(RequestError::Field(key.to_string())),
+    }
+}
+
+fn array<'a>(value: &'a Cbor, key: &str) -> Result<&'a [Cbor], RequestError> {
+    match value {
+        Cbor::Array(items) => Ok(items),
+        _ => Err(RequestError::Field(key.to_string())),
+    }
+}
+
+fn entries<'a>(value: &'a Cbor, key: &str) -> Result<&'a [(Cbor, Cbor)], RequestError> {
+    match value {
+        Cbor::Map(entries) => Ok(entries),
+        _ => Err(RequestError::Field(key.to_string())),
+    }
+}
+
+fn text_cbor(text: &str) -> Cbor {
+    Cbor::Text(text.to_string())
+}
+
+impl Transport {
+    fn to_cbor(&self) -> Cbor {
+        let mut entries = vec![
+            (text_cbor("t"), text_cbor(&self.kind)),
+            (text_cbor("a"), text_cbor(&self.target)),
+        ];
+        if !self.tags.is_empty() {
+            let tags = self
+                .tags
+                .iter()
+                .map(|tag| Cbor::Array(tag.iter().map(|s| text_cbor(s)).collect()))
+                .collect();
+            entries.push((text_cbor("g"), Cbor::Array(tags)));
+        }
+        Cbor::Map(entries)
+    }
+
+    fn from_cbor(value: &Cbor) -> Result<Self, RequestError> {
+        let mut transport = Transport::default();
+        for (key, value) in entries(value, "t")? {
+            match key {
+                Cbor::Text(key) if key == "t" => transport.kind = text(value, "t.t")?,
+                Cbor::Text(key) if key == "a" => transport.target = text(value, "t.a")?,
+                Cbor::Text(key) if key == "g" => {
+                    transport.tags = array(value, "t.g")?
+                        .iter()
+                        .map(|tag| array(tag, "t.g")?.iter().map(|s| text(s, "t.g")).collect())
+                        .collect::<Result<_, _>>()?;
+                }
+                _ => {}
+            }
+        }
+        if transport.kind.is_empty() || transport.target.is_empty() {
+            return Err(RequestError::Field("t".to_string()));
+        }
+        Ok(transport)
+    }
+}
+
+impl PaymentRequest {
+    fn to_cbor(&self) -> Cbor {
+        let mut entries = vec![];
+        if let Some(payment_id) = &self.payment_id {
+            entries.push((text_cbor("i"), text_cbor(payment_id)));
+        }
+        if let Some(amount) = self.amount {
+            entries.push((text_cbor("a"), Cbor::Uint(amount)));
+        }
+        if let Some(unit) = &self.unit {
+            entries.push((text_cbor("u"), text_cbor(unit)));
+        }
+        if let Some(single_use) = self.single_use {
+            entries.push((text_cbor("s"), Cbor::Bool(single_use)));
+        }
+        if !self.mints.is_empty() {
+            let mints = self.mints.iter().map(|mint| text_cbor(mint)).collect();
+            entries.push((text_cbor("m"), Cbor::Array(mints)));
+        }
+        if let Some(description) = &self.description {
+            entries.push((text_cbor("d"), text_cbor(description)));
+        }
+        if !self.transports.is_empty() {
+            let transports = self.transports.iter().map(Transport::to_cbor).collect();
+            entries.push((text_cbor("t"), Cbor::Array(transports)));
+        }
+        Cbor::Map(entries)
+    }
+
+    fn from_cbor(value: &Cbor) -> Result<Self, RequestError> {
+        let Cbor::Map(map) = value else {
+            return Err(RequestError::Cbor);
+        };
+        let mut request = PaymentRequest::default();
+        for (key, value) in map {
+            let Cbor::Text(key) = key else {
+                continue;
+            };
+            // null is the same as leaving a field out
+            if *value == Cbor::Null {
+                continue;
+            }
+            match key.as_str() {
+                "i" => request.payment_id = Some(text(value, key)?),
+                "a" => match value {
+                    Cbor::Uint(amount) => request.amount = Some(*amount),
+                    _ => return Err(RequestError::Field(key.clone())),
+                },
+                "u" => request.unit = Some(text(value, key)?),
+                "s" => match value {
+                    Cbor::Bool(single_use) => request.single_use = Some(*single_use),
+                    _ => return Err(RequestError::Field(key.clone())),
+                },
+                "m" => {
+                    request.mints = array(value, key)?
+                        .iter()
+                        .map(|mint| text(mint, key))
+                        .collect::<Result<_, _>>()?;
+                }
+                "d" => request.description = Some(text(value, key)?),
+                "t" => {
+                    request.transports = array(value, key)?
+                        .iter()
+                        .map(Transport::from_cbor)
+                        .collect::<Result<_, _>>()?;
+                }
+                _ => {}
+            }
+        }
+        Ok(request)
+    }
+}
+
+impl FromStr for PaymentRequest {
+    type Err = RequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.trim().strip_prefix(PREFIX).ok_or(RequestError::Prefix)?;
+        // base64url, with the padding optional
+        let mut base64: String = encoded
+            .chars()
+            .map(|c| match c {
+                '-' => '+',
+                '_' => '/',
+                c => c,
+            })
+            .collect();
+        while !base64.len().is_multiple_of(4) {
+            base64.push('=');
+        }
+        let cbor = base64_decode(&base64).ok_or(RequestError::Encoding)?;
+        let mut bytes = cbor.as_slice();
+        let value = Cbor::decode(&mut bytes, 0).ok_or(RequestError::Cbor)?;
+        if !bytes.is_empty() {
+            return Err(RequestError::Cbor);
+        }
+        PaymentRequest::from_cbor(&value)
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cbor = vec![];
+        self.to_cbor().encode(&mut cbor);
+        let base64url = base64_encode(&cbor).replace('+', "-").replace('/', "_");
+        write!(f, "{}{}", PREFIX, base64url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_encoding() {
+        // {"a": 100, "u": "sat"}
+        let request = PaymentRequest::new(100, "sat");
+        assert_eq!(request.to_string(), "creqAomFhGGRhdWNzYXQ=");
+        assert_eq!("creqAomFhGGRhdWNzYXQ".parse(), Ok(request));
+    }
+
+    #[test]
+    fn test_spec_vector() {
+        // the nostr example from NUT-18, whose keys are in a different order
+        // to ours so only the parsed request is compared
+        let encoded = "creqApWF0gaNhdGVub3N0cmFheKlucHJvZmlsZTFxeTI4d3VtbjhnaGo3dW45ZDNzaGp0bnl2OWtoMnVld2Q5aHN6OW1od2RlbjV0ZTB3ZmprY2N0ZTljdXJ4dmVuOWVlaHFjdHJ2NWhzenJ0aHdkZW41dGUwZGVoaHh0bnZkYWtxcWd5ZGFxeTdjdXJrNDM5eWtwdGt5c3Y3dWRoZGh1NjhzdWNtMjk1YWtxZWZkZWhrZjBkNDk1Y3d1bmw1YWeBgmFuYjE3YWloYjdhOTAxNzZhYQphdWNzYXRhbYF4Imh0dHBzOi8vbm9mZWVzLnRlc3RudXQuY2FzaHUuc3BhY2U=";
+        let request = PaymentRequest {
+            payment_id: Some("b7a90176".to_string()),
+            mints: vec!["https://nofees.testnut.cashu.space".to_string()],
+            transports: vec![Transport {
+                kind: "nostr".to_string(),
+                target: "nprofile1qy28wumn8ghj7un9d3shjtnyv9kh2uewd9hsz9mhwden5te0wfjkccte9curxven9eehqctrv5hszrthwden5te0dehhxtnvdakqqgydaqy7curk439ykptkysv7udhdhu68sucm295akqefdehkf0d495cwunl5".to_string(),
+                tags: vec![vec!["n".to_string(), "17".to_string()]],
+            }],
+            ..PaymentRequest::new(10, "sat")
+        };
+        assert_eq!(encoded.parse(), Ok(request.clone()));
+        assert_eq!(request.to_string().parse(), Ok(request));
+    }
+
+    #[test]
+    fn test_nostr_request_round_trip() {
+        // pay me 100 sats in ecash via nostr
+        let request = PaymentRequest {
+            payment_id: Some("b7a90176".to_string()),
+            single_use: Some(true),
+            mints: vec!["https://8333.space:3338".to_string()],
+            description: Some("coffee".to_string()),
+            transports: vec![Transport {
+                kind: "nostr".to_string(),
+                target: "nprofile1qqsgm6qfa3c8dtz2fvzhvfqeacmwm0e50pe3k5tfmvpjjmn0vj7m2tgpz3mhxue69uhhyetvv9ujuerpd46hxtnfduq3wamnwvaz7tmjv4kxz7fw8qenxvewwdcxzcm99uqs6amnwvaz7tmwdaejumr0ds4ljh7n".to_string(),
+                tags: vec![vec!["n".to_string(), "17".to_string()]],
+            }],
+            ..PaymentRequest::new(100, "sat")
+        };
+        let encoded = request.to_string();
+        assert!(encoded.starts_with("creqA"));
+        assert!(!encoded.contains(['+', '/']));
+        assert_eq!(encoded.parse(), Ok(request.clone()));
+
+        assert!(request.accepts_mint("https://8333.space:3338/"));
+        assert!(!request.accepts_mint("https://mint.example.com"));
+        assert!(PaymentRequest::default().accepts_mint("https://mint.example.com"));
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let parse = |s: &str| s.parse::<PaymentRequest>();
+        assert_eq!(parse("cashuAomFhGGQ"), Err(RequestError::Prefix));
+        assert_eq!(parse("creqA!!!!"), Err(RequestError::Encoding));
+        // a float amount, then an indefinite length map
+        let encode = |cbor: &[u8]| alloc::format!("creqA{}", base64_encode(cbor));
+        assert_eq!(
+            parse(&encode(&[0xa1, 0x61, b'a', 0xf9, 0x3c, 0x00])),
+            Err(RequestError::Cbor)
+        );
+        assert_eq!(parse(&encode(&[0xbf, 0xff])), Err(RequestError::Cbor));
+        // a text amount
+        assert_eq!(
+            parse(&encode(&[0xa1, 0x61, b'a', 0x61, b'1'])),
+            Err(RequestError::Field("a".to_string()))
+        );
+        // a transport without a target
+        assert_eq!(
+            parse(&encode(&[
+                0xa1, 0x61, b't', 0x81, 0xa1, 0x61, b't', 0x64, b'p', b'o', b's', b't'
+            ])),
+            Err(RequestError::Field("t".to_string()))
+        );
+        // deeply nested arrays, and trailing bytes
+        assert_eq!(parse(&encode(&[0x81; 64])), Err(RequestError::Cbor));
+        assert_eq!(parse(&encode(&[0xa0, 0x00])), Err(RequestError::Cbor));
+        // unknown keys are skipped
+        assert_eq!(
+            parse(&encode(&[0xa1, 0x61, b'z', 0x01])),
+            Ok(PaymentRequest::default())
+        );
+    }
+}
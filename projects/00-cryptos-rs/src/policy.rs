@@ -0,0 +1,460 @@
+use std::fmt;
+
+use crate::amount::{Amount, FeeRate};
+use crate::interpreter::witness_program;
+use crate::transaction::{Cmd, Script, Tx, TxOut, MAX_OP_RETURN_DATA};
+
+// Relay policy, like `testmempoolaccept`: on top of the consensus rules nodes
+// only relay and mine transactions that are "standard", so the ones that would
+// never propagate can be caught before broadcast. The checks and their reject
+// reasons follow Bitcoin Core's IsStandardTx and PreChecks, in the same order,
+// and like Core we stop at the first failure.
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_DUP: u8 = 0x76;
+const OP_HASH160: u8 = 0xa9;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_RETURN: u8 = 0x6a;
+
+/// Largest standard transaction, in weight units
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// Smaller transactions can be confused with 64-byte merkle tree nodes
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+
+/// The output types nodes relay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    /// A segwit version without rules yet, standard to pay to but not to
+    /// spend from
+    WitnessUnknown,
+    OpReturn,
+    NonStandard,
+}
+
+impl ScriptType {
+    pub fn of(script_pubkey: &Script) -> Self {
+        if script_pubkey.as_bytes().first() == Some(&OP_RETURN) {
+            return ScriptType::OpReturn;
+        }
+        match script_pubkey.template().unwrap_or_default().as_slice() {
+            [Cmd::Op(OP_DUP), Cmd::Op(OP_HASH160), Cmd::Push(hash), Cmd::Op(OP_EQUALVERIFY), Cmd::Op(OP_CHECKSIG)]
+                if hash.len() == 20 =>
+            {
+                ScriptType::P2pkh
+            }
+            [Cmd::Op(OP_HASH160), Cmd::Push(hash), Cmd::Op(OP_EQUAL)] if hash.len() == 20 => {
+                ScriptType::P2sh
+            }
+            [Cmd::Op(OP_0), Cmd::Push(program)] if program.len() == 20 => ScriptType::P2wpkh,
+            [Cmd::Op(OP_0), Cmd::Push(program)] if program.len() == 32 => ScriptType::P2wsh,
+            [Cmd::Op(OP_1), Cmd::Push(program)] if program.len() == 32 => ScriptType::P2tr,
+            _ if witness_program(script_pubkey.as_bytes())
+                .is_some_and(|(version, _)| version != 0) =>
+            {
+                ScriptType::WitnessUnknown
+            }
+            _ => ScriptType::NonStandard,
+        }
+    }
+
+    /// The name Bitcoin Core gives the type in its RPCs
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptType::P2pkh => "pubkeyhash",
+            ScriptType::P2sh => "scripthash",
+            ScriptType::P2wpkh => "witness_v0_keyhash",
+            ScriptType::P2wsh => "witness_v0_scripthash",
+            ScriptType::P2tr => "witness_v1_taproot",
+            ScriptType::WitnessUnknown => "witness_unknown",
+            ScriptType::OpReturn => "nulldata",
+            ScriptType::NonStandard => "nonstandard",
+        }
+    }
+
+    fn is_witness_program(&self) -> bool {
+        matches!(
+            self,
+            ScriptType::P2wpkh | ScriptType::P2wsh | ScriptType::P2tr | ScriptType::WitnessUnknown
+        )
+    }
+}
+
+/// Size of the script on the wire, without its own length prefix
+fn script_size(script: &Script) -> usize {
+    script.to_bytes().len()
+}
+
+impl TxOut {
+    /// What creating and later spending the output costs at `fee_rate`, the
+    /// dust threshold of Bitcoin Core's GetDustThreshold
+    pub fn dust_threshold(&self, fee_rate: FeeRate) -> Amount {
+        let script_type = ScriptType::of(&self.script_pubkey);
+        if script_type == ScriptType::OpReturn {
+            return Amount::ZERO;
+        }
+        // amount, script length and script
+        let output_size = 8 + 1 + script_size(&self.script_pubkey);
+        // outpoint, scriptSig length, sequence and a typical signature + key,
+        // which is discounted to a quarter when it goes in the witness
+        let input_size = match script_type.is_witness_program() {
+            true => 32 + 4 + 1 + 107 / 4 + 4,
+            false => 32 + 4 + 1 + 107 + 4,
+        };
+        fee_rate.fee(output_size + input_size)
+    }
+
+    /// Whether the output is worth less than it costs at `fee_rate`
+    pub fn is_dust(&self, fee_rate: FeeRate) -> bool {
+        self.amount < self.dust_threshold(fee_rate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyError {
+    /// Version outside 1..=3
+    Version(u32),
+    /// Weight above MAX_STANDARD_TX_WEIGHT
+    TxSize(usize),
+    /// Non-witness size below MIN_STANDARD_TX_NONWITNESS_SIZE
+    TxSizeSmall(usize),
+    /// Input whose scriptSig is above MAX_STANDARD_SCRIPTSIG_SIZE
+    ScriptSigSize(usize),
+    /// Output with a non-standard or oversized script
+    ScriptPubkey(usize),
+    /// Output worth less than it would cost to spend
+    Dust(usize),
+    MultiOpReturn,
+    /// Input without a scriptSig or witness
+    MissingSignature(usize),
+    /// Outputs are worth more than the inputs
+    InBelowOut {
+        input: Amount,
+        output: Amount,
+    },
+    FeeTooLow {
+        fee: Amount,
+        min_fee: Amount,
+    },
+    /// Input spending a witness version reserved for soft forks
+    UpgradableWitness(usize),
+}
+
+impl PolicyError {
+    /// Bitcoin Core's reject reason for the failure, as reported by
+    /// `testmempoolaccept`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PolicyError::Version(_) => "version",
+            PolicyError::TxSize(_) => "tx-size",
+            PolicyError::TxSizeSmall(_) => "tx-size-small",
+            PolicyError::ScriptSigSize(_) => "scriptsig-size",
+            PolicyError::ScriptPubkey(_) => "scriptpubkey",
+            PolicyError::Dust(_) => "dust",
+            PolicyError::MultiOpReturn => "multi-op-return",
+            PolicyError::MissingSignature(_) => "mandatory-script-verify-flag-failed",
+            PolicyError::InBelowOut { .. } => "bad-txns-in-belowout",
+            PolicyError::FeeTooLow { .. } => "min relay fee not met",
+            PolicyError::UpgradableWitness(_) => "non-mandatory-script-verify-flag",
+        }
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason())?;
+        match self {
+            PolicyError::Version(version) => write!(f, ", version {}", version),
+            PolicyError::TxSize(weight) => write!(f, ", weight {}", weight),
+            PolicyError::TxSizeSmall(size) => write!(f, ", {} bytes", size),
+            PolicyError::ScriptSigSize(index) | PolicyError::MissingSignature(index) => {
+                write!(f, " (input {})", index)
+            }
+            PolicyError::ScriptPubkey(index) | PolicyError::Dust(index) => {
+                write!(f, " (output {})", index)
+            }
+            PolicyError::MultiOpReturn => Ok(()),
+            // in sats, like Core
+            PolicyError::InBelowOut { input, output } => {
+                write!(f, ", {} < {}", input.to_sat(), output.to_sat())
+            }
+            PolicyError::FeeTooLow { fee, min_fee } => {
+                write!(f, ", {} < {}", fee.to_sat(), min_fee.to_sat())
+            }
+            PolicyError::UpgradableWitness(index) => write!(
+                f,
+                " (Witness version reserved for soft-fork upgrades, input {})",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Relay settings, the defaults match Bitcoin Core
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub min_relay_fee_rate: FeeRate,
+    /// Fee rate outputs are valued at to decide what is dust
+    pub dust_relay_fee_rate: FeeRate,
+    /// Largest OP_RETURN payload
+    pub max_data_carrier_size: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            min_relay_fee_rate: FeeRate::from_sat_per_vb(1),
+            dust_relay_fee_rate: FeeRate::from_sat_per_vb(3),
+            max_data_carrier_size: MAX_OP_RETURN_DATA,
+        }
+    }
+}
+
+impl Policy {
+    /// Smallest standard amount for an output, the fee of spending it at the
+    /// dust relay fee rate
+    pub fn dust_threshold(&self, tx_out: &TxOut) -> Amount {
+        tx_out.dust_threshold(self.dust_relay_fee_rate)
+    }
+
+    /// Check that `tx` would be accepted to the mempool, `spent` are the
+    /// outputs its inputs spend, in order
+    pub fn check(&self, tx: &Tx, spent: &[TxOut]) -> Result<(), PolicyError> {
+        assert_eq!(
+            tx.tx_ins.len(),
+            spent.len(),
+            "need the spent output of every input"
+        );
+
+        let size = tx.encode_legacy().len();
+        if size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+            return Err(PolicyError::TxSizeSmall(size));
+        }
+        if !(1..=3).contains(&tx.version) {
+            return Err(PolicyError::Version(tx.version));
+        }
+        let weight = tx.weight();
+        if weight > MAX_STANDARD_TX_WEIGHT {
+            return Err(PolicyError::TxSize(weight));
+        }
+        for (index, tx_in) in tx.tx_ins.iter().enumerate() {
+            if script_size(&tx_in.script_sig) > MAX_STANDARD_SCRIPTSIG_SIZE {
+                return Err(PolicyError::ScriptSigSize(index));
+            }
+        }
+
+        let mut data_outputs = 0;
+        for (index, tx_out) in tx.tx_outs.iter().enumerate() {
+            match ScriptType::of(&tx_out.script_pubkey) {
+                ScriptType::NonStandard => return Err(PolicyError::ScriptPubkey(index)),
+                ScriptType::OpReturn => {
+                    // OP_RETURN and an OP_PUSHDATA1 push of the payload
+                    if script_size(&tx_out.script_pubkey) > self.max_data_carrier_size + 3 {
+                        return Err(PolicyError::ScriptPubkey(index));
+                    }
+                    data_outputs += 1;
+                }
+                _ if tx_out.amount < self.dust_threshold(tx_out) => {
+                    return Err(PolicyError::Dust(index))
+                }
+                _ => {}
+            }
+        }
+        if data_outputs > 1 {
+            return Err(PolicyError::MultiOpReturn);
+        }
+
+        for (index, tx_in) in tx.tx_ins.iter().enumerate() {
+            if tx_in.script_sig.is_empty() && tx_in.witness.is_empty() {
+                return Err(PolicyError::MissingSignature(index));
+            }
+        }
+
+        let input: Amount = spent.iter().map(|tx_out| tx_out.amount).sum();
+        let output: Amount = tx.tx_outs.iter().map(|tx_out| tx_out.amount).sum();
+        if input < output {
+            return Err(PolicyError::InBelowOut { input, output });
+        }
+        let fee = input - output;
+        let min_fee = self.min_relay_fee_rate.fee(tx.vsize());
+        if fee < min_fee {
+            return Err(PolicyError::FeeTooLow { fee, min_fee });
+        }
+
+        // until a soft fork gives an unknown witness version its rules any
+        // spend is valid, relaying one would get a transaction that could
+        // be invalid under the new rules mined. Taproot only applies to bare
+        // outputs, nested in P2SH a version 1 program is as unknown as any.
+        for (index, (tx_in, spent)) in tx.tx_ins.iter().zip(spent).enumerate() {
+            let upgradable = match (
+                ScriptType::of(&spent.script_pubkey),
+                tx_in
+                    .script_sig
+                    .cmds()
+                    .ok()
+                    .and_then(|cmds| cmds.last().cloned()),
+            ) {
+                (ScriptType::P2sh, Some(Cmd::Push(redeem_script))) => {
+                    witness_program(&redeem_script).is_some_and(|(version, _)| version != 0)
+                }
+                (script_type, _) => script_type == ScriptType::WitnessUnknown,
+            };
+            if upgradable {
+                return Err(PolicyError::UpgradableWitness(index));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TxBuilder;
+
+    /// Spends a 10_000 sat P2PKH output with a dummy signature and key
+    fn signed(builder: TxBuilder) -> (Tx, Vec<TxOut>) {
+        let mut tx = builder.add_input(vec![0x11; 32], 0).build();
+        tx.tx_ins[0].script_sig =
+            Script::new(vec![Cmd::Push(vec![0x30; 72]), Cmd::Push(vec![0x02; 33])]);
+        let spent = TxOut {
+            amount: Amount::from_sat(10_000),
+            script_pubkey: Script::p2pkh(&[0xaa; 20]),
+        };
+        (tx, vec![spent])
+    }
+
+    #[test]
+    fn test_standard_tx() {
+        let (tx, spent) = signed(
+            TxBuilder::new("main")
+                .add_output(Amount::from_sat(9_000), Script::p2pkh(&[0xbb; 20]))
+                .add_data_output(b"hello"),
+        );
+        assert_eq!(Policy::default().check(&tx, &spent), Ok(()));
+    }
+
+    #[test]
+    fn test_dust_thresholds() {
+        let policy = Policy::default();
+        let dust = |script_pubkey| {
+            policy
+                .dust_threshold(&TxOut {
+                    amount: Amount::ZERO,
+                    script_pubkey,
+                })
+                .to_sat()
+        };
+        assert_eq!(dust(Script::p2pkh(&[0; 20])), 546);
+        assert_eq!(
+            dust(Script::new(vec![Cmd::Op(OP_0), Cmd::Push(vec![0; 20])])),
+            294
+        );
+        assert_eq!(
+            dust(Script::new(vec![Cmd::Op(OP_1), Cmd::Push(vec![0; 32])])),
+            330
+        );
+        assert_eq!(dust(Script::op_return(b"data")), 0);
+
+        let tx_out = TxOut {
+            amount: Amount::from_sat(546),
+            script_pubkey: Script::p2pkh(&[0; 20]),
+        };
+        assert!(!tx_out.is_dust(FeeRate::from_sat_per_vb(3)));
+        assert!(tx_out.is_dust(FeeRate::from_sat_per_vb(4)));
+    }
+
+    #[test]
+    fn test_rejections() {
+        let policy = Policy::default();
+        let bob = Script::p2pkh(&[0xbb; 20]);
+
+        let (tx, spent) =
+            signed(TxBuilder::new("main").add_output(Amount::from_sat(500), bob.clone()));
+        assert_eq!(policy.check(&tx, &spent), Err(PolicyError::Dust(0)));
+
+        let (tx, spent) =
+            signed(TxBuilder::new("main").add_output(Amount::from_sat(9_990), bob.clone()));
+        assert_eq!(
+            policy.check(&tx, &spent).unwrap_err().reason(),
+            "min relay fee not met"
+        );
+
+        let (mut tx, spent) = signed(
+            TxBuilder::new("main")
+                .add_output(Amount::from_sat(9_000), bob.clone())
+                .add_output(
+                    Amount::ZERO,
+                    Script::new(vec![
+                        Cmd::Op(OP_RETURN),
+                        Cmd::Push(vec![0; MAX_OP_RETURN_DATA + 1]),
+                    ]),
+                ),
+        );
+        assert_eq!(policy.check(&tx, &spent), Err(PolicyError::ScriptPubkey(1)));
+
+        tx.tx_outs.truncate(1);
+        tx.tx_ins[0].script_sig = Script::default();
+        assert_eq!(
+            policy.check(&tx, &spent),
+            Err(PolicyError::MissingSignature(0))
+        );
+    }
+
+    #[test]
+    fn test_unknown_witness_version() {
+        let policy = Policy::default();
+        let v2 = Script::new(vec![Cmd::Op(OP_1 + 1), Cmd::Push(vec![0xaa; 32])]);
+        assert_eq!(ScriptType::of(&v2).name(), "witness_unknown");
+        // a version 0 program of the wrong length is just non-standard
+        let wrong_length = Script::new(vec![Cmd::Op(OP_0), Cmd::Push(vec![0xaa; 21])]);
+        assert_eq!(ScriptType::of(&wrong_length), ScriptType::NonStandard);
+
+        // paying to a future version is fine
+        let (tx, mut spent) =
+            signed(TxBuilder::new("main").add_output(Amount::from_sat(9_000), v2.clone()));
+        assert_eq!(policy.check(&tx, &spent), Ok(()));
+
+        // spending from one isn't, directly or nested in P2SH
+        spent[0].script_pubkey = v2.clone();
+        let error = policy.check(&tx, &spent).unwrap_err();
+        assert_eq!(error, PolicyError::UpgradableWitness(0));
+        assert_eq!(error.reason(), "non-mandatory-script-verify-flag");
+
+        let (mut tx, mut spent) = signed(
+            TxBuilder::new("main").add_output(Amount::from_sat(9_000), Script::p2pkh(&[0xbb; 20])),
+        );
+        spent[0].script_pubkey = Script::new(vec![
+            Cmd::Op(OP_HASH160),
+            Cmd::Push(vec![0xcc; 20]),
+            Cmd::Op(OP_EQUAL),
+        ]);
+        tx.tx_ins[0].script_sig = Script::new(vec![Cmd::Push(v2.to_bytes())]);
+        assert_eq!(
+            policy.check(&tx, &spent),
+            Err(PolicyError::UpgradableWitness(0))
+        );
+
+        // taproot is only taproot bare
+        let v1 = Script::new(vec![Cmd::Op(OP_1), Cmd::Push(vec![0xaa; 32])]);
+        tx.tx_ins[0].script_sig = Script::new(vec![Cmd::Push(v1.to_bytes())]);
+        assert_eq!(
+            policy.check(&tx, &spent),
+            Err(PolicyError::UpgradableWitness(0))
+        );
+        spent[0].script_pubkey = v1;
+        tx.tx_ins[0].script_sig = Script::default();
+        tx.tx_ins[0].witness = vec![vec![0x01; 64]];
+        assert_eq!(policy.check(&tx, &spent), Ok(()));
+    }
+}
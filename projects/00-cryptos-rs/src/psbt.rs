@@ -0,0 +1,358 @@
+use std::fmt;
+
+use crate::encoding::{
+    base64_decode, base64_encode, try_read_varint, try_take, DecodingError, Encodable, TryDecodable,
+};
+use crate::transaction::{Script, Tx, TxOut};
+use crate::utils;
+
+// Partially signed transactions (BIP174), the format wallets hand unsigned or
+// half signed transactions around in. The unsigned transaction goes in the
+// global map, each input gets a map with the output it spends and, once
+// signed, its final scriptSig and witness, and each output a map of its own.
+// Only the fields needed to pass a transaction between two parties that sign
+// their own inputs are kept: no partial signatures, scripts or derivation
+// paths, and unknown fields are dropped on parsing.
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const IN_NON_WITNESS_UTXO: u8 = 0x00;
+const IN_WITNESS_UTXO: u8 = 0x01;
+const IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+/// Why a PSBT couldn't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum PsbtError {
+    /// Not valid base64
+    Base64,
+    /// Doesn't start with the `psbt` 0xff magic
+    Magic,
+    /// The global map has no unsigned transaction
+    MissingUnsignedTx,
+    /// The unsigned transaction has scriptSigs or witnesses
+    SignedTx,
+    /// A map or one of the values in it is malformed
+    Decoding(DecodingError),
+}
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PsbtError::Base64 => write!(f, "invalid base64"),
+            PsbtError::Magic => write!(f, "missing PSBT magic bytes"),
+            PsbtError::MissingUnsignedTx => write!(f, "no unsigned transaction"),
+            PsbtError::SignedTx => write!(f, "the unsigned transaction has signatures"),
+            PsbtError::Decoding(error) => write!(f, "malformed PSBT: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PsbtError {}
+
+impl From<DecodingError> for PsbtError {
+    fn from(error: DecodingError) -> Self {
+        PsbtError::Decoding(error)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PsbtInput {
+    /// The whole transaction the input spends from, for legacy inputs
+    pub non_witness_utxo: Option<Tx>,
+    /// Just the spent output, enough for segwit inputs
+    pub witness_utxo: Option<TxOut>,
+    pub final_script_sig: Option<Script>,
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Psbt {
+    /// The transaction with empty scriptSigs and witnesses
+    pub unsigned_tx: Tx,
+    pub inputs: Vec<PsbtInput>,
+}
+
+fn push_pair(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    out.extend(utils::encode_varint(key.len() as u64));
+    out.extend(key);
+    out.extend(utils::encode_varint(value.len() as u64));
+    out.extend(value);
+}
+
+/// Key value pairs of a PSBT map
+type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// The key value pairs of one map up to its 0x00 separator
+fn read_map(bytes: &mut &[u8]) -> Result<Map, DecodingError> {
+    let mut pairs = vec![];
+    loop {
+        let key = read_bytes(bytes)?;
+        if key.is_empty() {
+            return Ok(pairs);
+        }
+        pairs.push((key, read_bytes(bytes)?));
+    }
+}
+
+/// A varint length followed by that many bytes
+fn read_bytes(bytes: &mut &[u8]) -> Result<Vec<u8>, DecodingError> {
+    let len = try_read_varint(bytes)?;
+    let len = usize::try_from(len).map_err(|_| DecodingError::UnexpectedEnd)?;
+    Ok(try_take(bytes, len)?.to_vec())
+}
+
+impl Psbt {
+    /// A PSBT for `tx`, whose scriptSigs and witnesses are cleared
+    pub fn from_unsigned_tx(mut tx: Tx) -> Self {
+        for tx_in in tx.tx_ins.iter_mut() {
+            tx_in.script_sig = Script::default();
+            tx_in.witness = vec![];
+        }
+        tx.segwit = false;
+        Psbt {
+            inputs: vec![PsbtInput::default(); tx.tx_ins.len()],
+            unsigned_tx: tx,
+        }
+    }
+
+    /// The output input `index` spends, if the PSBT carries it
+    pub fn spent_output(&self, index: usize) -> Option<TxOut> {
+        let input = &self.inputs[index];
+        if let Some(tx_out) = &input.witness_utxo {
+            return Some(tx_out.clone());
+        }
+        let prev_tx = input.non_witness_utxo.as_ref()?;
+        let tx_in = &self.unsigned_tx.tx_ins[index];
+        // the txid is in display order, the outpoint in internal order
+        let mut txid = hex::decode(prev_tx.id()).unwrap();
+        txid.reverse();
+        if txid != tx_in.prev_tx {
+            return None;
+        }
+        prev_tx.tx_outs.get(tx_in.prev_index as usize).cloned()
+    }
+
+    pub fn is_finalized(&self, index: usize) -> bool {
+        let input = &self.inputs[index];
+        input.final_script_sig.is_some() || input.final_script_witness.is_some()
+    }
+
+    /// The signed transaction, None until every input is finalized
+    pub fn extract_tx(&self) -> Option<Tx> {
+        let mut tx = self.unsigned_tx.clone();
+        for (i, (tx_in, input)) in tx.tx_ins.iter_mut().zip(&self.inputs).enumerate() {
+            if !self.is_finalized(i) {
+                return None;
+            }
+            tx_in.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            tx_in.witness = input.final_script_witness.clone().unwrap_or_default();
+        }
+        tx.segwit = tx.tx_ins.iter().any(|tx_in| !tx_in.witness.is_empty());
+        Some(tx)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        push_pair(
+            &mut out,
+            &[GLOBAL_UNSIGNED_TX],
+            &self.unsigned_tx.encode_legacy(),
+        );
+        out.push(0x00);
+        for input in &self.inputs {
+            if let Some(tx) = &input.non_witness_utxo {
+                push_pair(&mut out, &[IN_NON_WITNESS_UTXO], &tx.encode());
+            }
+            if let Some(tx_out) = &input.witness_utxo {
+                push_pair(&mut out, &[IN_WITNESS_UTXO], &tx_out.encode());
+            }
+            if let Some(script_sig) = &input.final_script_sig {
+                push_pair(&mut out, &[IN_FINAL_SCRIPTSIG], &script_sig.to_bytes());
+            }
+            if let Some(witness) = &input.final_script_witness {
+                let mut value = utils::encode_varint(witness.len() as u64);
+                for item in witness {
+                    value.extend(utils::encode_varint(item.len() as u64));
+                    value.extend(item);
+                }
+                push_pair(&mut out, &[IN_FINAL_SCRIPTWITNESS], &value);
+            }
+            out.push(0x00);
+        }
+        // nothing is kept about outputs, their maps are empty
+        out.extend(vec![0x00; self.unsigned_tx.tx_outs.len()]);
+        out
+    }
+
+    /// Parse a PSBT, the input comes from whoever sent it
+    pub fn deserialize(bytes: &[u8]) -> Result<Psbt, PsbtError> {
+        let mut bytes = bytes.strip_prefix(MAGIC).ok_or(PsbtError::Magic)?;
+        let global = read_map(&mut bytes)?;
+        let (_, tx) = global
+            .iter()
+            .find(|(key, _)| key.as_slice() == [GLOBAL_UNSIGNED_TX])
+            .ok_or(PsbtError::MissingUnsignedTx)?;
+        let unsigned_tx = Tx::try_decode_all(tx)?;
+        if unsigned_tx
+            .tx_ins
+            .iter()
+            .any(|tx_in| !tx_in.script_sig.is_empty() || !tx_in.witness.is_empty())
+        {
+            return Err(PsbtError::SignedTx);
+        }
+
+        let mut inputs = vec![];
+        for _ in 0..unsigned_tx.tx_ins.len() {
+            let mut input = PsbtInput::default();
+            for (key, value) in read_map(&mut bytes)? {
+                match key.as_slice() {
+                    [IN_NON_WITNESS_UTXO] => {
+                        input.non_witness_utxo = Some(Tx::try_decode_all(&value)?)
+                    }
+                    [IN_WITNESS_UTXO] => input.witness_utxo = Some(TxOut::try_decode_all(&value)?),
+                    [IN_FINAL_SCRIPTSIG] => {
+                        input.final_script_sig = Some(Script::from_bytes(&value))
+                    }
+                    [IN_FINAL_SCRIPTWITNESS] => {
+                        let mut value = value.as_slice();
+                        let count = try_read_varint(&mut value)?;
+                        let witness = (0..count)
+                            .map(|_| read_bytes(&mut value))
+                            .collect::<Result<_, _>>()?;
+                        input.final_script_witness = Some(witness);
+                    }
+                    _ => {}
+                }
+            }
+            inputs.push(input);
+        }
+        for _ in 0..unsigned_tx.tx_outs.len() {
+            read_map(&mut bytes)?;
+        }
+        if !bytes.is_empty() {
+            return Err(DecodingError::TrailingBytes(bytes.len()).into());
+        }
+        Ok(Psbt {
+            unsigned_tx,
+            inputs,
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64_encode(&self.serialize())
+    }
+
+    pub fn from_base64(s: &str) -> Result<Psbt, PsbtError> {
+        Psbt::deserialize(&base64_decode(s.trim()).ok_or(PsbtError::Base64)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction::{Cmd, TxBuilder};
+
+    #[test]
+    fn test_roundtrip() {
+        let prev_tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(5_000), Script::p2pkh(&[0xaa; 20]))
+            .build();
+        let mut prev_txid = hex::decode(prev_tx.id()).unwrap();
+        prev_txid.reverse();
+        let tx = TxBuilder::new("main")
+            .add_input(prev_txid, 0)
+            .add_input(vec![0x22; 32], 1)
+            .add_output(Amount::from_sat(4_000), Script::p2pkh(&[0xbb; 20]))
+            .build();
+        let mut psbt = Psbt::from_unsigned_tx(tx);
+        assert!(psbt.extract_tx().is_none());
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx.clone());
+        psbt.inputs[0].final_script_sig = Some(Script::new(vec![
+            Cmd::push(&[0x30; 71]),
+            Cmd::push(&[0x02; 33]),
+        ]));
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            amount: Amount::from_sat(1_000),
+            script_pubkey: Script::p2pkh(&[0xcc; 20]),
+        });
+        psbt.inputs[1].final_script_witness = Some(vec![vec![0x30; 71], vec![0x03; 33]]);
+
+        let encoded = psbt.to_base64();
+        assert!(encoded.starts_with("cHNidP8"));
+        let decoded = Psbt::from_base64(&encoded).unwrap();
+        assert_eq!(decoded.serialize(), psbt.serialize());
+        assert_eq!(
+            decoded.spent_output(0).unwrap().encode(),
+            prev_tx.tx_outs[0].encode()
+        );
+        assert_eq!(
+            decoded.spent_output(1).unwrap().amount,
+            Amount::from_sat(1_000)
+        );
+        let signed = decoded.extract_tx().unwrap();
+        assert!(signed.segwit);
+        assert_eq!(signed.tx_ins[1].witness.len(), 2);
+
+        // a utxo from a different transaction than the input spends
+        psbt.inputs[0].non_witness_utxo = Some(psbt.unsigned_tx.clone());
+        assert!(psbt.spent_output(0).is_none());
+
+        let bytes = decoded.serialize();
+        assert_eq!(
+            Psbt::deserialize(&bytes[..bytes.len() - 1]).err(),
+            Some(PsbtError::Decoding(DecodingError::UnexpectedEnd))
+        );
+        assert_eq!(Psbt::deserialize(&bytes[1..]).err(), Some(PsbtError::Magic));
+        assert_eq!(
+            Psbt::from_base64("not base64").err(),
+            Some(PsbtError::Base64)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xaa; 20]))
+            .build();
+        let mut psbt = Psbt::from_unsigned_tx(tx);
+        psbt.inputs[0].final_script_witness = Some(vec![vec![0x30; 71]]);
+        let bytes = psbt.serialize();
+
+        // the witness claims a second item that isn't there
+        let at = bytes.windows(2).position(|w| w == [0x01, 0x47]).unwrap();
+        let mut bad = bytes.clone();
+        bad[at] = 0x02;
+        assert_eq!(
+            Psbt::deserialize(&bad).err(),
+            Some(PsbtError::Decoding(DecodingError::UnexpectedEnd))
+        );
+
+        // a key length of 2^64 - 1 in the global map
+        let mut bad = MAGIC.to_vec();
+        bad.extend([0xff; 9]);
+        assert_eq!(
+            Psbt::deserialize(&bad).err(),
+            Some(PsbtError::Decoding(DecodingError::UnexpectedEnd))
+        );
+
+        // only an empty global map
+        let mut bad = MAGIC.to_vec();
+        bad.push(0x00);
+        assert_eq!(
+            Psbt::deserialize(&bad).err(),
+            Some(PsbtError::MissingUnsignedTx)
+        );
+
+        let mut bad = bytes;
+        bad.push(0x00);
+        assert_eq!(
+            Psbt::deserialize(&bad).err(),
+            Some(PsbtError::Decoding(DecodingError::TrailingBytes(1)))
+        );
+    }
+}
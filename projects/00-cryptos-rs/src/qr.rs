@@ -0,0 +1,760 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+// QR codes for moving ecash tokens and lightning invoices between a phone and
+// a laptop, written from the spec (ISO/IEC 18004) like the rest of the crate's
+// primitives. Data is encoded as a single alphanumeric or byte segment in the
+// smallest version that fits, with Reed-Solomon error correction over GF(256)
+// and the mask with the lowest penalty score. Codes render as SVG or as
+// unicode half blocks for a terminal, and scanned strings are parsed back
+// into a Cashu token or a BOLT11 invoice.
+
+/// How much of the code can be damaged and still be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcLevel {
+    /// About 7%
+    Low,
+    /// About 15%
+    Medium,
+    /// About 25%
+    Quartile,
+    /// About 30%
+    High,
+}
+
+impl EcLevel {
+    fn ordinal(self) -> usize {
+        self as usize
+    }
+
+    /// The two bits identifying the level in the format information
+    fn format_bits(self) -> u32 {
+        match self {
+            EcLevel::Low => 1,
+            EcLevel::Medium => 0,
+            EcLevel::Quartile => 3,
+            EcLevel::High => 2,
+        }
+    }
+}
+
+// Error correction codewords per block and number of blocks, indexed by level
+// and version, version 0 doesn't exist
+#[rustfmt::skip]
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28],
+    [0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30, 30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+];
+#[rustfmt::skip]
+const NUM_ERROR_CORRECTION_BLOCKS: [[u8; 41]; 4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13, 14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29, 34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32, 35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81],
+];
+
+/// Characters of the alphanumeric mode, a character is stored as its index
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// Light modules around the code, the spec asks for 4
+const QUIET_ZONE: usize = 4;
+
+/// Modules available for data and error correction in a version, i.e. all
+/// but the function patterns and format and version information
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize, ec_level: EcLevel) -> usize {
+    let level = ec_level.ordinal();
+    num_raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[level][version] as usize
+            * NUM_ERROR_CORRECTION_BLOCKS[level][version] as usize
+}
+
+/// Multiply in GF(256) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1d);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+/// The Reed-Solomon generator polynomial of the given degree, without its
+/// leading 1 term, highest coefficients first
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+/// The error correction codewords of `data`
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, &y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_mul(y, factor);
+        }
+    }
+    result
+}
+
+/// A growable string of bits, most significant first
+#[derive(Default)]
+struct BitBuffer(Vec<bool>);
+
+impl BitBuffer {
+    fn append(&mut self, value: u32, len: usize) {
+        self.0.extend((0..len).rev().map(|i| (value >> i) & 1 == 1));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Alphanumeric,
+    Byte,
+}
+
+impl Mode {
+    fn indicator(self) -> u32 {
+        match self {
+            Mode::Alphanumeric => 0b0010,
+            Mode::Byte => 0b0100,
+        }
+    }
+
+    /// Width of the character count field, which grows with the version
+    fn count_bits(self, version: usize) -> usize {
+        let widths = match self {
+            Mode::Alphanumeric => [9, 11, 13],
+            Mode::Byte => [8, 16, 16],
+        };
+        widths[(version + 7) / 17]
+    }
+
+    fn data_bits(self, len: usize) -> usize {
+        match self {
+            Mode::Alphanumeric => len / 2 * 11 + len % 2 * 6,
+            Mode::Byte => len * 8,
+        }
+    }
+}
+
+/// A QR code, as a square of dark and light modules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that fits at the error correction
+    /// level, None if it's too long for even version 40. Uppercase text like
+    /// `LIGHTNING:LNBC...` packs tighter than mixed case.
+    pub fn encode(data: &[u8], ec_level: EcLevel) -> Option<QrCode> {
+        let mode = match data.iter().all(|c| ALPHANUMERIC.contains(c)) {
+            true => Mode::Alphanumeric,
+            false => Mode::Byte,
+        };
+        let version = (1..=40).find(|&version| {
+            data.len() < 1 << mode.count_bits(version)
+                && 4 + mode.count_bits(version) + mode.data_bits(data.len())
+                    <= num_data_codewords(version, ec_level) * 8
+        })?;
+
+        let mut bits = BitBuffer::default();
+        bits.append(mode.indicator(), 4);
+        bits.append(data.len() as u32, mode.count_bits(version));
+        match mode {
+            Mode::Alphanumeric => {
+                let index = |c: &u8| ALPHANUMERIC.iter().position(|a| a == c).unwrap() as u32;
+                for pair in data.chunks(2) {
+                    match pair {
+                        [a, b] => bits.append(index(a) * 45 + index(b), 11),
+                        [a] => bits.append(index(a), 6),
+                        _ => unreachable!(),
+                    }
+                }
+            }
+            Mode::Byte => data.iter().for_each(|&byte| bits.append(byte as u32, 8)),
+        }
+
+        // terminator, then pad to a whole byte and fill with the pad codewords
+        let capacity = num_data_codewords(version, ec_level) * 8;
+        bits.append(0, (capacity - bits.0.len()).min(4));
+        bits.append(0, (8 - bits.0.len() % 8) % 8);
+        for pad in [0xec, 0x11].into_iter().cycle() {
+            if bits.0.len() >= capacity {
+                break;
+            }
+            bits.append(pad, 8);
+        }
+        let codewords: Vec<u8> = bits
+            .0
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| (acc << 1) | bit as u8))
+            .collect();
+
+        let mut qr = QrCode {
+            size: version * 4 + 17,
+            modules: vec![false; (version * 4 + 17).pow(2)],
+            is_function: vec![false; (version * 4 + 17).pow(2)],
+        };
+        qr.draw_function_patterns(version, ec_level);
+        qr.draw_codewords(&add_ecc_and_interleave(&codewords, version, ec_level));
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format_bits(ec_level, mask);
+                let penalty = qr.penalty_score();
+                qr.apply_mask(mask);
+                penalty
+            })
+            .unwrap();
+        qr.apply_mask(mask);
+        qr.draw_format_bits(ec_level, mask);
+        Some(qr)
+    }
+
+    /// Modules per side, without the quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at column `x` and row `y` is dark, anything outside
+    /// the code is light
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize, ec_level: EcLevel) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // finder patterns in three corners, with their light separators
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                    if (0..size as i32).contains(&xx) && (0..size as i32).contains(&yy) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        // alignment patterns everywhere but on top of the finder patterns
+        let positions = alignment_pattern_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                if (i, j) == (0, 0) || (i, j) == (0, last) || (i, j) == (last, 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let (xx, yy) = ((x as i32 + dx) as usize, (y as i32 + dy) as usize);
+                        self.set_function(xx, yy, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+
+        // reserve the format area, the real bits go in once a mask is chosen
+        self.draw_format_bits(ec_level, 0);
+        self.draw_version_bits(version);
+    }
+
+    fn draw_format_bits(&mut self, ec_level: EcLevel, mask: u32) {
+        let bits = format_bits(ec_level, mask);
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+
+        // around the top left finder pattern, skipping the timing patterns
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        // split between the other two finder patterns
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version_bits(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+        let bits = version_bits(version);
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 == 1;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Place the codewords in the zigzag of two module wide columns, going up
+    /// and down from the right edge and skipping the function patterns
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            // the vertical timing pattern takes a whole column
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                for x in [right, right - 1] {
+                    let y = if upward {
+                        size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.is_function[y * size + x] && i < data.len() * 8 {
+                        self.modules[y * size + x] = (data[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// XOR a mask pattern over the data modules, so applying it twice undoes it
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    7 => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                    _ => panic!("{} is not a mask pattern", mask),
+                };
+                let index = y * self.size + x;
+                self.modules[index] ^= invert && !self.is_function[index];
+            }
+        }
+    }
+
+    /// How hard the code is to scan, lower is better: long runs and blocks of
+    /// one color, patterns that look like finder patterns and an unbalanced
+    /// share of dark modules all cost points
+    fn penalty_score(&self) -> usize {
+        let size = self.size;
+        let row = |y: usize| -> Vec<bool> { (0..size).map(|x| self.get(x, y)).collect() };
+        let column = |x: usize| -> Vec<bool> { (0..size).map(|y| self.get(x, y)).collect() };
+        let lines = (0..size).map(row).chain((0..size).map(column));
+
+        let finder_like = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+        let mut penalty = 0;
+        for line in lines {
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            for window in line.windows(finder_like.len()) {
+                if window == finder_like || window.iter().rev().eq(finder_like.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.get(x, y);
+                if self.get(x + 1, y) == color
+                    && self.get(x, y + 1) == color
+                    && self.get(x + 1, y + 1) == color
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // 10 points for every 5% the dark share is away from half
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        penalty + (dark * 20).abs_diff(total * 10) / total * 10
+    }
+
+    /// An SVG image of the code with a quiet zone, one unit per module
+    pub fn to_svg(&self) -> String {
+        let dimension = self.size + 2 * QUIET_ZONE;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.get(x, y) {
+                    if !path.is_empty() {
+                        path.push(' ');
+                    }
+                    write!(path, "M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE).unwrap();
+                }
+            }
+        }
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {0} {0}" stroke="none">"#,
+            dimension
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r##"<rect width="100%" height="100%" fill="#ffffff"/>"##
+        )
+        .unwrap();
+        writeln!(svg, r##"<path d="{}" fill="#000000"/>"##, path).unwrap();
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// The code drawn with unicode half blocks, two rows of modules per line.
+    /// Light modules are drawn, so it reads right in a terminal with light text
+    /// on a dark background.
+    pub fn to_terminal(&self) -> String {
+        let dimension = self.size + 2 * QUIET_ZONE;
+        // the quiet zone shifts the code, so wrap around to reach the light
+        // modules outside it
+        let light =
+            |x: usize, y: usize| !self.get(x.wrapping_sub(QUIET_ZONE), y.wrapping_sub(QUIET_ZONE));
+        let mut out = String::new();
+        for y in (0..dimension).step_by(2) {
+            for x in 0..dimension {
+                let bottom = y + 1 < dimension && light(x, y + 1);
+                out.push(match (light(x, y), bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// The 15 bit format information: level, mask and a BCH(15,5) checksum, XORed
+/// so it's never all zeros
+fn format_bits(ec_level: EcLevel, mask: u32) -> u32 {
+    let data = ec_level.format_bits() << 3 | mask;
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    (data << 10 | rem) ^ 0x5412
+}
+
+/// The 18 bit version information of versions 7 and up, the version and a
+/// BCH(18,6) checksum
+fn version_bits(version: usize) -> u32 {
+    let mut rem = version as u32;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+    }
+    (version as u32) << 12 | rem
+}
+
+/// Centers of the alignment patterns along either axis, spread evenly from
+/// column 6 to 7 modules before the edge
+fn alignment_pattern_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+    let size = version * 4 + 17;
+    let num_align = version / 7 + 2;
+    let step = (version * 8 + num_align * 3 + 5) / (num_align * 4 - 4) * 2;
+    let mut result: Vec<usize> = (0..num_align - 1).map(|i| size - 7 - i * step).collect();
+    result.push(6);
+    result.reverse();
+    result
+}
+
+/// Split the data codewords into blocks, append each block's error
+/// correction and interleave the blocks codeword by codeword
+fn add_ecc_and_interleave(data: &[u8], version: usize, ec_level: EcLevel) -> Vec<u8> {
+    let level = ec_level.ordinal();
+    let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[level][version] as usize;
+    let block_ecc_len = ECC_CODEWORDS_PER_BLOCK[level][version] as usize;
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    // the first blocks are a codeword shorter when it doesn't divide evenly
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = rs_divisor(block_ecc_len);
+    let mut blocks = vec![];
+    let mut data = data;
+    for i in 0..num_blocks {
+        let data_len = short_block_len - block_ecc_len + usize::from(i >= num_short_blocks);
+        let (block_data, rest) = data.split_at(data_len);
+        data = rest;
+        let mut block = block_data.to_vec();
+        // placeholder so all blocks line up, skipped when interleaving
+        if i < num_short_blocks {
+            block.push(0);
+        }
+        block.extend(rs_remainder(block_data, &divisor));
+        blocks.push(block);
+    }
+
+    let mut result = vec![];
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - block_ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+/// What a scanned QR code asked to pay or receive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// A serialized Cashu token, `cashuA...` or `cashuB...`
+    CashuToken(String),
+    /// A BOLT11 lightning invoice, in lowercase
+    Bolt11(String),
+}
+
+/// Make sense of a scanned string, with or without a `cashu:` or `lightning:`
+/// URI scheme
+pub fn parse_scanned(scanned: &str) -> Option<Payload> {
+    let scanned = scanned.trim();
+    let strip_scheme = |scheme: &str| {
+        let prefix = scanned.get(..scheme.len())?;
+        prefix
+            .eq_ignore_ascii_case(scheme)
+            .then(|| &scanned[scheme.len()..])
+    };
+    if let Some(token) = strip_scheme("cashu:").map(|rest| rest.trim_start_matches("//")) {
+        return is_cashu_token(token).then(|| Payload::CashuToken(token.to_string()));
+    }
+    let invoice = strip_scheme("lightning:").unwrap_or(scanned);
+    if is_cashu_token(invoice) {
+        return Some(Payload::CashuToken(invoice.to_string()));
+    }
+    // invoices are bech32 so case doesn't matter, but mixed case is invalid
+    let lowercase = invoice.to_ascii_lowercase();
+    let mixed_case = invoice != lowercase && invoice != invoice.to_ascii_uppercase();
+    (lowercase.starts_with("lnbc")
+        || lowercase.starts_with("lntb")
+        || lowercase.starts_with("lntbs"))
+    .then_some(())
+    .filter(|_| !mixed_case && lowercase.contains('1'))
+    .map(|_| Payload::Bolt11(lowercase))
+}
+
+fn is_cashu_token(token: &str) -> bool {
+    (token.starts_with("cashuA") || token.starts_with("cashuB")) && token.len() > 6
+}
+
+/// A QR code for the payload, with its URI scheme. Invoices are uppercased so
+/// they fit the denser alphanumeric mode, tokens are case sensitive and use
+/// the byte mode.
+pub fn payload_qr(payload: &Payload) -> QrCode {
+    let uri = match payload {
+        Payload::CashuToken(token) => alloc::format!("cashu:{}", token),
+        Payload::Bolt11(invoice) => alloc::format!("lightning:{}", invoice).to_ascii_uppercase(),
+    };
+    QrCode::encode(uri.as_bytes(), EcLevel::Low).expect("payload too long for a QR code")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reed_solomon() {
+        // "HELLO WORLD" as version 1-M, the worked example from thonky.com
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            rs_remainder(&data, &rs_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn test_format_and_version_bits() {
+        assert_eq!(format_bits(EcLevel::Low, 0), 0b111011111000100);
+        assert_eq!(format_bits(EcLevel::Medium, 0), 0b101010000010010);
+        assert_eq!(format_bits(EcLevel::High, 7), 0b000100000111011);
+        assert_eq!(version_bits(7), 0x07c94);
+        assert_eq!(version_bits(40), 0x28c69);
+        assert_eq!(alignment_pattern_positions(32), [6, 34, 60, 86, 112, 138]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        // byte mode capacities from the spec's tables
+        let cases = [
+            (1, EcLevel::Low, 17),
+            (1, EcLevel::High, 7),
+            (10, EcLevel::Medium, 213),
+            (40, EcLevel::Low, 2953),
+            (40, EcLevel::Quartile, 1663),
+            (40, EcLevel::High, 1273),
+        ];
+        for (version, ec_level, bytes) in cases {
+            let fits = QrCode::encode(&vec![b'a'; bytes], ec_level).unwrap();
+            assert_eq!(fits.size(), version * 4 + 17);
+            if version < 40 {
+                let next = QrCode::encode(&vec![b'a'; bytes + 1], ec_level).unwrap();
+                assert_eq!(next.size(), version * 4 + 21);
+            } else {
+                assert!(QrCode::encode(&vec![b'a'; bytes + 1], ec_level).is_none());
+            }
+        }
+        // the alphanumeric mode packs more in
+        let alphanumeric = QrCode::encode(&[b'A'; 25], EcLevel::Low).unwrap();
+        assert_eq!(alphanumeric.size(), 21);
+    }
+
+    #[test]
+    fn test_layout() {
+        let qr = QrCode::encode(b"HELLO WORLD", EcLevel::Quartile).unwrap();
+        assert_eq!(qr.size(), 21);
+        let rows: Vec<String> = (0..qr.size())
+            .map(|y| {
+                (0..qr.size())
+                    .map(|x| if qr.get(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+        // finder patterns with their separators, the timing pattern between
+        assert_eq!(&rows[0][..8], "#######.");
+        assert_eq!(&rows[0][13..], ".#######");
+        assert_eq!(&rows[2][..8], "#.###.#.");
+        assert_eq!(&rows[6][..8], "#######.");
+        assert_eq!(&rows[6][8..13], "#.#.#");
+        assert_eq!(&rows[20][..8], "#######.");
+        assert!(qr.get(8, qr.size() - 8));
+
+        // both copies of the format information agree and name the level
+        let first: u32 = [
+            (8, 0),
+            (8, 1),
+            (8, 2),
+            (8, 3),
+            (8, 4),
+            (8, 5),
+            (8, 7),
+            (8, 8),
+        ]
+        .into_iter()
+        .chain([(7, 8), (5, 8), (4, 8), (3, 8), (2, 8), (1, 8), (0, 8)])
+        .enumerate()
+        .map(|(i, (x, y))| (qr.get(x, y) as u32) << i)
+        .sum();
+        let second: u32 = (0..8)
+            .map(|i| (qr.size() - 1 - i, 8))
+            .chain((8..15).map(|i| (8, qr.size() - 15 + i)))
+            .enumerate()
+            .map(|(i, (x, y))| (qr.get(x, y) as u32) << i)
+            .sum();
+        assert_eq!(first, second);
+        let mask = ((first ^ 0x5412) >> 10) & 0b111;
+        assert_eq!(first, format_bits(EcLevel::Quartile, mask));
+
+        assert!(qr.to_svg().contains(r#"viewBox="0 0 29 29""#));
+        assert_eq!(qr.to_terminal().lines().count(), 15);
+    }
+
+    #[test]
+    fn test_parse_scanned() {
+        let invoice = "lnbc2500u1pvjluezpp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpuaztrnwngzn3kdzw5hydlzf03qdgm2hdq27cqv3agm2awhz5se903vruatfhq77w3ls4evs3ch9zw97j25emudupq63nyw24cg27h2rspfj9srp";
+        assert_eq!(
+            parse_scanned(&alloc::format!(
+                "LIGHTNING:{}",
+                invoice.to_ascii_uppercase()
+            )),
+            Some(Payload::Bolt11(invoice.to_string()))
+        );
+        assert_eq!(
+            parse_scanned(invoice),
+            Some(Payload::Bolt11(invoice.to_string()))
+        );
+        assert_eq!(
+            parse_scanned(" cashu:cashuBo2FteB \n"),
+            Some(Payload::CashuToken("cashuBo2FteB".to_string()))
+        );
+        assert_eq!(
+            parse_scanned("cashuAeyJ0b2tlbiI6W119"),
+            Some(Payload::CashuToken("cashuAeyJ0b2tlbiI6W119".to_string()))
+        );
+        assert_eq!(parse_scanned("cashu:lnbc1abc"), None);
+        assert_eq!(parse_scanned("LnBc2500u1abc"), None);
+        assert_eq!(
+            parse_scanned("bitcoin:1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"),
+            None
+        );
+
+        let qr = payload_qr(&Payload::Bolt11(invoice.to_string()));
+        let token_qr = payload_qr(&Payload::CashuToken(invoice.to_string()));
+        assert!(qr.size() < token_qr.size());
+    }
+}
@@ -16,6 +16,7 @@ const PADDING: [u8; 64] = [
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
 
+#[derive(Clone)]
 struct RMDContext {
     state: [u32; 5],
     count: u64,
@@ -38,6 +39,68 @@ pub fn ripemd160(input: &[u8]) -> [u8; 20] {
     rmd160_final(&mut ctx)
 }
 
+/// Incremental RIPEMD-160, for hashing data that arrives in pieces
+#[derive(Clone)]
+pub struct Ripemd160(RMDContext);
+
+impl Ripemd160 {
+    pub fn new() -> Self {
+        Ripemd160(RMDContext::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        rmd160_update(&mut self.0, data, data.len());
+    }
+
+    pub fn finalize(mut self) -> [u8; 20] {
+        rmd160_final(&mut self.0)
+    }
+}
+
+impl Default for Ripemd160 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drop-in for the RustCrypto `ripemd` crate in generic code
+#[cfg(feature = "digest")]
+mod digest_impl {
+    use digest::consts::{U20, U64};
+    use digest::core_api::BlockSizeUser;
+    use digest::{FixedOutput, HashMarker, Output, OutputSizeUser, Reset, Update};
+
+    use super::Ripemd160;
+
+    impl HashMarker for Ripemd160 {}
+
+    impl OutputSizeUser for Ripemd160 {
+        type OutputSize = U20;
+    }
+
+    impl BlockSizeUser for Ripemd160 {
+        type BlockSize = U64;
+    }
+
+    impl Update for Ripemd160 {
+        fn update(&mut self, data: &[u8]) {
+            Ripemd160::update(self, data);
+        }
+    }
+
+    impl FixedOutput for Ripemd160 {
+        fn finalize_into(self, out: &mut Output<Self>) {
+            out.copy_from_slice(&Ripemd160::finalize(self));
+        }
+    }
+
+    impl Reset for Ripemd160 {
+        fn reset(&mut self) {
+            *self = Ripemd160::new();
+        }
+    }
+}
+
 fn rmd160_update(ctx: &mut RMDContext, input: &[u8], input_len: usize) {
     let mut have = (ctx.count / 8 % 64) as usize;
     let need = 64 - have;
@@ -333,7 +396,7 @@ fn rmd160_transform(state: &mut [u32; 5], block: &[u8]) {
 
 #[cfg(test)]
 mod tests {
-    use super::ripemd160;
+    use super::*;
 
     #[test]
     fn test_ripemd160() {
@@ -360,4 +423,14 @@ mod tests {
             assert_eq!(expected, &result_hex);
         }
     }
+
+    #[test]
+    fn test_ripemd160_incremental() {
+        let message = "1234567890".repeat(20);
+        let mut hasher = Ripemd160::new();
+        for piece in message.as_bytes().chunks(7) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finalize(), ripemd160(message.as_bytes()));
+    }
 }
@@ -1,5 +1,7 @@
-use std::ops::{Add, Mul, Neg, Rem, Sub};
-use std::str::FromStr;
+use alloc::string::{String, ToString};
+use core::hint::black_box;
+use core::ops::{Add, BitAnd, BitOr, Mul, Neg, Not, Rem};
+use core::str::FromStr;
 
 use primitive_types::U256;
 
@@ -11,6 +13,116 @@ pub struct RU256 {
 #[derive(Debug, PartialEq, Eq)]
 pub struct RU256ParseError;
 
+/// A condition that may depend on a secret, held as 0 or 1 and combined with
+/// bit operations rather than branches, like `subtle::Choice`. Turn it into a
+/// `bool` only where the result is public.
+#[derive(Clone, Copy, Debug)]
+pub struct Choice(u8);
+
+impl Choice {
+    /// `bit` must be 0 or 1
+    pub fn from_bit(bit: u8) -> Self {
+        debug_assert!(bit <= 1, "a choice is 0 or 1");
+        // keep the optimizer from reasoning about the value and branching
+        Choice(black_box(bit))
+    }
+
+    pub fn unwrap_u8(self) -> u8 {
+        self.0
+    }
+
+    /// All ones for 1, all zeros for 0
+    fn mask(self) -> u64 {
+        0u64.wrapping_sub(self.0 as u64)
+    }
+}
+
+impl From<Choice> for bool {
+    fn from(choice: Choice) -> bool {
+        choice.0 == 1
+    }
+}
+
+impl BitAnd for Choice {
+    type Output = Choice;
+
+    fn bitand(self, rhs: Choice) -> Choice {
+        Choice(self.0 & rhs.0)
+    }
+}
+
+impl BitOr for Choice {
+    type Output = Choice;
+
+    fn bitor(self, rhs: Choice) -> Choice {
+        Choice(self.0 | rhs.0)
+    }
+}
+
+impl Not for Choice {
+    type Output = Choice;
+
+    fn not(self) -> Choice {
+        Choice(self.0 ^ 1)
+    }
+}
+
+/// Two's complement integer of 320 bits, room for the f and g of safegcd
+/// which stay within 2^257 either side of zero
+#[derive(Clone, Copy)]
+struct Signed([u64; 5]);
+
+impl Signed {
+    fn from_u256(v: &U256) -> Self {
+        let mut limbs = [0u64; 5];
+        limbs[..4].copy_from_slice(&v.0);
+        Signed(limbs)
+    }
+
+    fn add(&self, other: &Signed) -> Signed {
+        let mut limbs = [0u64; 5];
+        let mut carry = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 | c2;
+        }
+        Signed(limbs)
+    }
+
+    fn neg(&self) -> Signed {
+        Signed(self.0.map(|limb| !limb)).add(&Signed::from_u256(&U256::one()))
+    }
+
+    /// Halve, rounding towards minus infinity
+    fn shr1(&self) -> Signed {
+        let mut limbs = [0u64; 5];
+        for (limb, pair) in limbs.iter_mut().zip(self.0.windows(2)) {
+            *limb = (pair[0] >> 1) | (pair[1] << 63);
+        }
+        limbs[4] = ((self.0[4] as i64) >> 1) as u64;
+        Signed(limbs)
+    }
+
+    fn select(a: &Signed, b: &Signed, choice: Choice) -> Signed {
+        let mask = choice.mask();
+        let mut limbs = [0u64; 5];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = a.0[i] ^ (mask & (a.0[i] ^ b.0[i]));
+        }
+        Signed(limbs)
+    }
+
+    fn is_odd(&self) -> Choice {
+        Choice::from_bit((self.0[0] & 1) as u8)
+    }
+
+    fn is_negative(&self) -> Choice {
+        Choice::from_bit((self.0[4] >> 63) as u8)
+    }
+}
+
 impl FromStr for RU256 {
     type Err = RU256ParseError;
 
@@ -108,21 +220,56 @@ impl RU256 {
         Self { v: U256::one() }
     }
 
-    pub fn add_mod(&self, b: &RU256, p: &RU256) -> Self {
-        // Calculate x1 and x2 as the values of self and b modulo p
-        let x1 = self.v % p.v;
-        let x2 = b.v % p.v;
+    /// Whether self equals `other`, looking at every limb
+    pub fn ct_eq(&self, other: &RU256) -> Choice {
+        let diff = self
+            .v
+            .0
+            .iter()
+            .zip(other.v.0.iter())
+            .fold(0u64, |acc, (a, b)| acc | (a ^ b));
+        // the top bit of x | -x is set for any nonzero x
+        Choice::from_bit((((diff | diff.wrapping_neg()) >> 63) ^ 1) as u8)
+    }
 
-        // Attempt to add x1 and x2
-        let (mut x3, overflow) = x1.overflowing_add(x2);
+    /// Whether self is less than `other`, from the borrow of self - other
+    pub fn ct_lt(&self, other: &RU256) -> Choice {
+        Choice::from_bit(self.v.overflowing_sub(other.v).1 as u8)
+    }
 
-        // If there's an overflow or x3 is greater than or equal to p, adjust x3
-        if overflow || x3 >= p.v {
-            x3 = x3.overflowing_sub(p.v).0;
+    /// `b` if `choice` is set, else `a`, without branching
+    pub fn ct_select(a: &RU256, b: &RU256, choice: Choice) -> RU256 {
+        let mask = choice.mask();
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = a.v.0[i] ^ (mask & (a.v.0[i] ^ b.v.0[i]));
         }
+        RU256 { v: U256(limbs) }
+    }
 
-        // Return the new RU256 instance with the result value
-        Self { v: x3 }
+    /// Reduce a value below 2p to below p by subtracting p when it's at
+    /// least p. `carry` is a bit above the 256 that didn't fit.
+    pub fn ct_reduce(&self, p: &RU256, carry: Choice) -> RU256 {
+        let reduced = RU256 {
+            v: self.v.overflowing_sub(p.v).0,
+        };
+        RU256::ct_select(self, &reduced, carry | !self.ct_lt(p))
+    }
+
+    /// Sum of two values already below p
+    fn add_reduced(&self, b: &RU256, p: &RU256) -> RU256 {
+        let (sum, overflow) = self.v.overflowing_add(b.v);
+        RU256 { v: sum }.ct_reduce(p, Choice::from_bit(overflow as u8))
+    }
+
+    pub fn add_mod(&self, b: &RU256, p: &RU256) -> Self {
+        // Calculate x1 and x2 as the values of self and b modulo p
+        let x1 = Self { v: self.v % p.v };
+        let x2 = Self { v: b.v % p.v };
+
+        // Add them, taking p off again if the sum reached it, whether or not
+        // it overflowed 256 bits
+        x1.add_reduced(&x2, p)
     }
 
     /// Modular subtraction
@@ -145,11 +292,12 @@ impl RU256 {
         let mut result = Self::zero();
         let mut adder = Self { v: x2 };
 
-        for i in 0..x1.bits() {
-            if x1.bit(i) {
-                result = result.add_mod(&adder, &p);
-            }
-            adder = adder.add_mod(&adder, &p);
+        // double and add over every bit p could have, always adding and then
+        // keeping the sum or not, so the time doesn't depend on the bits of x1
+        for i in 0..p.v.bits() {
+            let sum = result.add_reduced(&adder, p);
+            result = Self::ct_select(&result, &sum, Choice::from_bit(x1.bit(i) as u8));
+            adder = adder.add_reduced(&adder, p);
         }
 
         result
@@ -170,19 +318,210 @@ impl RU256 {
         result
     }
 
-    /// Modular division
+    /// Modular division, inverting b by Fermat's little theorem, or with the
+    /// `safegcd` feature by constant-time divsteps for the odd moduli of the
+    /// secp256k1 field and scalars
     pub fn div_mod(&self, b: &RU256, p: &RU256) -> Self {
         assert!(p.v > U256::from(2));
+        #[cfg(feature = "safegcd")]
+        if p.v.bit(0) {
+            return self.mul_mod(&b.inv_mod_safegcd(p), p);
+        }
         let b_inv = b.exp_mod(&RU256 { v: p.v - 2 }, &p);
         self.mul_mod(&b_inv, &p)
     }
+
+    /// Inverse mod `m` by the extended Euclidean algorithm, None unless self
+    /// and m are coprime. Unlike Fermat's a^(m - 2) it works for any modulus.
+    /// The Bezout coefficients are kept mod m instead of as signed values, so
+    /// they never go negative or outgrow 256 bits.
+    pub fn inv_mod(&self, m: &RU256) -> Option<Self> {
+        assert!(!m.is_zero(), "modulus must be nonzero");
+        // invariant: t0 * self = r0 and t1 * self = r1 mod m
+        let (mut r0, mut r1) = (m.v, self.v % m.v);
+        let (mut t0, mut t1) = (Self::zero(), Self::one());
+        while !r1.is_zero() {
+            let (q, r) = r0.div_mod(r1);
+            let t = t0.sub_mod(&RU256 { v: q }.mul_mod(&t1, m), m);
+            (r0, r1) = (r1, r);
+            (t0, t1) = (t1, t);
+        }
+        (r0 == U256::one()).then_some(t0)
+    }
+
+    /// a - b for values already below p
+    fn sub_reduced(&self, b: &RU256, p: &RU256) -> RU256 {
+        let (diff, borrow) = self.v.overflowing_sub(b.v);
+        let wrapped = RU256 {
+            v: diff.overflowing_add(p.v).0,
+        };
+        RU256::ct_select(&RU256 { v: diff }, &wrapped, Choice::from_bit(borrow as u8))
+    }
+
+    /// self / 2 mod an odd p, for self below p: adding p first when self is
+    /// odd makes it even
+    fn half_reduced(&self, p: &RU256) -> RU256 {
+        let odd = Choice::from_bit(self.v.bit(0) as u8);
+        let (sum, carry) = self
+            .v
+            .overflowing_add(RU256::ct_select(&Self::zero(), p, odd).v);
+        let mut half = sum >> 1;
+        half.0[3] |= (carry as u64) << 63;
+        RU256 { v: half }
+    }
+
+    /// Inverse mod an odd `p` in constant time, by the divsteps of Bernstein
+    /// and Yang ("safegcd") that libsecp256k1 uses. Each step halves g, after
+    /// first subtracting f from it when it's odd and swapping the two when
+    /// delta says f is the larger. It runs a fixed 741 steps, enough for any
+    /// 256 bit input to bring g to 0, leaving f = +-1 when self is invertible.
+    /// Alongside f and g it tracks d and e mod p with f = d * self and
+    /// g = e * self, so the inverse is d or -d. Self must be invertible, zero
+    /// comes back as zero like with Fermat inversion.
+    pub fn inv_mod_safegcd(&self, p: &RU256) -> Self {
+        assert!(p.v.bit(0), "safegcd needs an odd modulus");
+        const STEPS: usize = (49 * 256 + 57) / 17;
+        let mut delta: i64 = 1;
+        let mut f = Signed::from_u256(&p.v);
+        let mut g = Signed::from_u256(&(self.v % p.v));
+        let mut d = Self::zero();
+        let mut e = Self::one();
+        for _ in 0..STEPS {
+            let odd = g.is_odd();
+            // delta > 0 exactly when -delta is negative
+            let swap = Choice::from_bit((delta.wrapping_neg() as u64 >> 63) as u8) & odd;
+
+            let g_plus_f = g.add(&f);
+            let g_minus_f = g.add(&f.neg());
+            let numerator = Signed::select(&Signed::select(&g, &g_plus_f, odd), &g_minus_f, swap);
+            f = Signed::select(&f, &g, swap);
+            g = numerator.shr1();
+
+            let e_plus_d = e.add_reduced(&d, p);
+            let e_minus_d = e.sub_reduced(&d, p);
+            let numerator = Self::ct_select(&Self::ct_select(&e, &e_plus_d, odd), &e_minus_d, swap);
+            d = Self::ct_select(&d, &e, swap);
+            e = numerator.half_reduced(p);
+
+            let mask = 0i64.wrapping_sub(swap.unwrap_u8() as i64);
+            delta = 1 + (delta ^ mask).wrapping_sub(mask);
+        }
+        let negated = Self::zero().sub_reduced(&d, p);
+        Self::ct_select(&d, &negated, f.is_negative())
+    }
+
+    /// Legendre symbol (self / p) for an odd prime `p` by Euler's criterion:
+    /// 1 if self is a nonzero square mod p, -1 if it isn't, 0 if p divides it
+    pub fn legendre(&self, p: &RU256) -> i8 {
+        assert!(p.v > U256::from(2) && p.v.bit(0), "p must be an odd prime");
+        let e = RU256 { v: (p.v - 1) >> 1 };
+        let r = self.exp_mod(&e, p);
+        if r.is_zero() {
+            0
+        } else if r == Self::one() {
+            1
+        } else {
+            -1
+        }
+    }
+
+    /// Jacobi symbol (self / n) for any odd `n`, the product of the Legendre
+    /// symbols over the prime factors of n. Needs no factoring, it's computed
+    /// with quadratic reciprocity like a gcd. A -1 proves self is not a
+    /// square mod n, a 1 doesn't prove it is.
+    pub fn jacobi(&self, n: &RU256) -> i8 {
+        assert!(n.v.bit(0), "n must be odd");
+        let mut a = self.v % n.v;
+        let mut n = n.v;
+        let mut t = 1;
+        while !a.is_zero() {
+            // (2 / n) is -1 exactly when n is 3 or 5 mod 8
+            let twos = a.trailing_zeros();
+            a >>= twos;
+            if twos % 2 == 1 && matches!(n.low_u32() & 7, 3 | 5) {
+                t = -t;
+            }
+            // reciprocity, flipping the sign when both are 3 mod 4
+            core::mem::swap(&mut a, &mut n);
+            if a.low_u32() & 3 == 3 && n.low_u32() & 3 == 3 {
+                t = -t;
+            }
+            a %= n;
+        }
+        if n == U256::one() {
+            t
+        } else {
+            0
+        }
+    }
+
+    /// Miller-Rabin with the first 20 primes as bases. No composite below
+    /// 3.3 * 10^24 passes, and for larger ones a random odd number passing
+    /// is vanishingly unlikely.
+    pub fn is_probable_prime(&self) -> bool {
+        const BASES: [u64; 20] = [
+            2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+        ];
+        let n = &self.v;
+        if *n < U256::from(2) {
+            return false;
+        }
+        for base in BASES {
+            if *n == U256::from(base) {
+                return true;
+            }
+            if (*n % base).is_zero() {
+                return false;
+            }
+        }
+
+        // n - 1 = d * 2^s with d odd
+        let n_minus_one = RU256 { v: n - 1 };
+        let s = n_minus_one.v.trailing_zeros();
+        let d = RU256 {
+            v: n_minus_one.v >> s,
+        };
+        'bases: for base in BASES {
+            let mut x = RU256::from_u64(base).exp_mod(&d, self);
+            if x == Self::one() || x == n_minus_one {
+                continue;
+            }
+            for _ in 1..s {
+                x = x.mul_mod(&x, self);
+                if x == n_minus_one {
+                    continue 'bases;
+                }
+            }
+            return false;
+        }
+        true
+    }
+
+    /// The smallest probable prime at or above self
+    pub fn next_prime(&self) -> Self {
+        if self.v <= U256::from(2) {
+            return RU256::from_u64(2);
+        }
+        let mut candidate = RU256 {
+            v: self.v | U256::one(),
+        };
+        while !candidate.is_probable_prime() {
+            candidate.v += U256::from(2);
+        }
+        candidate
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use crate::ru256::RU256;
+    use primitive_types::{U256, U512};
+    use proptest::prelude::*;
+
+    use crate::ru256::{Choice, RU256};
+    use crate::secp256k1::SECP256K1;
+    use crate::strategies;
 
     #[test]
     fn ru256_addition_case_1() {
@@ -285,6 +624,9 @@ mod tests {
         );
     }
 
+    // b shares the factor 3 with p, so the answer is whatever Fermat
+    // inversion makes of a non-invertible value
+    #[cfg(not(feature = "safegcd"))]
     #[test]
     fn ru256_division_case() {
         let a = RU256::from_str("0x1ce606").unwrap(); // a = 189389.unwrap();
@@ -298,4 +640,145 @@ mod tests {
             "0000000000000000000000000000000000000000000000000000000000061f57"
         );
     }
+
+    #[test]
+    fn ru256_inverse_case() {
+        let p = RU256::from_u64(99933);
+        // 99933 = 3 * 33311 isn't prime, Fermat inversion doesn't apply
+        assert_eq!(
+            RU256::from_u64(189389).inv_mod(&p),
+            Some(RU256::from_u64(21032))
+        );
+        assert_eq!(RU256::from_u64(2).inv_mod(&p), Some(RU256::from_u64(49967)));
+        assert_eq!(RU256::from_u64(289833894).inv_mod(&p), None);
+        assert_eq!(RU256::zero().inv_mod(&p), None);
+        assert_eq!(
+            RU256::from_u64(5).inv_mod(&RU256::one()),
+            Some(RU256::zero())
+        );
+    }
+
+    #[test]
+    fn ru256_safegcd_inverse_case() {
+        let p = RU256::from_u64(99991);
+        assert_eq!(
+            RU256::from_u64(189389).inv_mod_safegcd(&p),
+            RU256::from_u64(189389).inv_mod(&p).unwrap()
+        );
+        assert_eq!(RU256::one().inv_mod_safegcd(&p), RU256::one());
+        assert_eq!(RU256::zero().inv_mod_safegcd(&p), RU256::zero());
+        // the largest input, 2^256 - 1, against the secp256k1 field prime
+        let p = SECP256K1::p();
+        let max = RU256 { v: U256::MAX };
+        assert_eq!(max.inv_mod_safegcd(&p), max.inv_mod(&p).unwrap());
+    }
+
+    #[test]
+    fn ru256_constant_time_helpers() {
+        let a = RU256::from_u64(5);
+        let b = RU256::from_u64(9);
+        assert!(bool::from(a.ct_eq(&a.clone())));
+        assert!(!bool::from(a.ct_eq(&b)));
+        assert!(bool::from(a.ct_lt(&b)));
+        assert!(!bool::from(b.ct_lt(&a)));
+        assert_eq!(RU256::ct_select(&a, &b, Choice::from_bit(0)), a);
+        assert_eq!(RU256::ct_select(&a, &b, Choice::from_bit(1)), b);
+        assert_eq!((!Choice::from_bit(0) & Choice::from_bit(1)).unwrap_u8(), 1);
+
+        let p = RU256::from_u64(7);
+        assert_eq!(b.ct_reduce(&p, Choice::from_bit(0)), RU256::from_u64(2));
+        assert_eq!(a.ct_reduce(&p, Choice::from_bit(0)), a);
+        // 2^256 + 1 mod (2^256 - 1) is 2
+        let max = RU256 { v: U256::MAX };
+        assert_eq!(
+            RU256::one().ct_reduce(&max, Choice::from_bit(1)),
+            RU256::from_u64(2)
+        );
+    }
+
+    #[test]
+    fn ru256_residue_symbols() {
+        let p = RU256::from_u64(7);
+        // the squares mod 7 are 1, 2 and 4
+        let symbols: Vec<i8> = (0..7).map(|a| RU256::from_u64(a).legendre(&p)).collect();
+        assert_eq!(symbols, [0, 1, 1, -1, 1, -1, -1]);
+        for a in 0..7 {
+            assert_eq!(RU256::from_u64(a).jacobi(&p), symbols[a as usize]);
+        }
+
+        let jacobi = |a, n| RU256::from_u64(a).jacobi(&RU256::from_u64(n));
+        assert_eq!(jacobi(1001, 9907), -1);
+        assert_eq!(jacobi(19, 45), 1);
+        assert_eq!(jacobi(8, 21), -1);
+        assert_eq!(jacobi(5, 21), 1);
+        assert_eq!(jacobi(6, 21), 0);
+        // a 1 from a composite doesn't make it a square: 2 isn't one mod 15
+        assert_eq!(jacobi(2, 15), 1);
+    }
+
+    #[test]
+    fn ru256_primality() {
+        let primes = [2u64, 3, 101, 7919, 2_147_483_647, (1 << 61) - 1];
+        let composites = [0u64, 1, 4, 561, 1105, 3_215_031_751, 7919 * 7927];
+        for n in primes {
+            assert!(RU256::from_u64(n).is_probable_prime(), "{}", n);
+        }
+        for n in composites {
+            assert!(!RU256::from_u64(n).is_probable_prime(), "{}", n);
+        }
+        let secp256k1_p =
+            RU256::from_str("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f")
+                .unwrap();
+        assert!(secp256k1_p.is_probable_prime());
+        // the product of two Mersenne primes
+        let semiprime = ((U256::one() << 61) - 1) * ((U256::one() << 89) - 1);
+        assert!(!RU256 { v: semiprime }.is_probable_prime());
+
+        assert_eq!(RU256::from_u64(100).next_prime(), RU256::from_u64(101));
+        assert_eq!(RU256::from_u64(7919).next_prime(), RU256::from_u64(7919));
+        assert_eq!(RU256::from_u64(0).next_prime(), RU256::from_u64(2));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_add_then_sub_mod(
+            a in strategies::ru256(),
+            b in strategies::ru256(),
+            p in strategies::modulus(),
+        ) {
+            prop_assert_eq!(a.add_mod(&b, &p).sub_mod(&b, &p), RU256 { v: a.v % p.v });
+        }
+
+        #[test]
+        fn prop_mul_mod_matches_wide_multiplication(
+            a in strategies::ru256(),
+            b in strategies::ru256(),
+            p in strategies::modulus(),
+        ) {
+            let expected = a.v.full_mul(b.v) % U512::from(p.v);
+            prop_assert_eq!(a.mul_mod(&b, &p).v, U256::try_from(expected).unwrap());
+        }
+
+        #[test]
+        fn prop_inv_mod_matches_fermat(a in strategies::ru256()) {
+            for p in [SECP256K1::p(), SECP256K1::n()] {
+                let fermat = a.exp_mod(&RU256 { v: p.v - 2 }, &p);
+                match a.inv_mod(&p) {
+                    Some(inverse) => {
+                        prop_assert_eq!(a.mul_mod(&inverse, &p), RU256::one());
+                        prop_assert_eq!(inverse, fermat);
+                    }
+                    None => prop_assert!((a.v % p.v).is_zero()),
+                }
+            }
+        }
+
+        #[test]
+        fn prop_inv_mod_safegcd_matches_fermat(a in strategies::ru256()) {
+            for p in [SECP256K1::p(), SECP256K1::n()] {
+                let fermat = a.exp_mod(&RU256 { v: p.v - 2 }, &p);
+                prop_assert_eq!(a.inv_mod_safegcd(&p), fermat);
+            }
+        }
+    }
 }
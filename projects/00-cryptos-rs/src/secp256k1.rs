@@ -1,13 +1,26 @@
-use std::fs::File;
+#[cfg(feature = "std")]
+use alloc::format;
+#[cfg(feature = "std")]
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::{Add, Mul, Neg};
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
 use std::io::{self, BufRead};
-use std::ops::{Add, Mul, Neg};
+#[cfg(feature = "std")]
 use std::path::Path;
-use std::str::FromStr;
 
-use primitive_types::U256;
-use secp256k1::{PublicKey, Secp256k1, SecretKey};
+#[cfg(feature = "std")]
+use secp256k1::PublicKey;
 
-use crate::ru256::RU256;
+use crate::encoding::{take, Decodable, Encodable};
+use crate::field::Fn;
+use crate::ru256::{Choice, RU256};
+#[cfg(feature = "std")]
+use crate::sha256::sha256;
 
 /// Represents a point on an elliptic curve
 #[derive(PartialEq, Clone, Debug)]
@@ -25,15 +38,105 @@ impl Point {
         };
     }
 
-    /// Return the uncompressed version of a point
-    pub fn to_hex_string(&self) -> String {
-        return format!("04{}{}", self.x.to_string(), self.y.to_string());
-    }
-
     /// Determines if a point is the identity element
-    fn is_zero_point(&self) -> bool {
+    pub fn is_zero_point(&self) -> bool {
         self.x.is_zero() && self.y.is_zero()
     }
+
+    /// Compressed SEC encoding, `02 || x` for an even y and `03 || x` for an
+    /// odd one
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        let mut out = vec![if self.y.v.bit(0) { 0x03 } else { 0x02 }; 33];
+        self.x.to_bytes(&mut out[1..33]);
+        out
+    }
+
+    /// Decode a compressed SEC point, recovering y from the curve equation
+    pub fn from_compressed_bytes(bytes: &[u8]) -> Self {
+        assert_eq!(bytes.len(), 33, "compressed points are 33 bytes");
+        let odd = match bytes[0] {
+            0x02 => false,
+            0x03 => true,
+            prefix => panic!("unsupported point encoding {:#04x}", prefix),
+        };
+        let x = RU256::from_bytes(&bytes[1..33]);
+        assert!(x < SECP256K1::p(), "x coordinate is not a field element");
+        Self::lift_x(&x, odd).expect("x coordinate is not on the curve")
+    }
+
+    /// Whether both coordinates are field elements, below p. Points compare
+    /// as integers, so x and x + p would look like two different points.
+    pub fn is_canonical(&self) -> bool {
+        let p = SECP256K1::p();
+        self.x < p && self.y < p
+    }
+
+    /// Whether the coordinates satisfy y^2 = x^3 + 7. The point math never
+    /// looks at the 7, so off-curve points have to be rejected when decoding.
+    pub fn is_on_curve(&self) -> bool {
+        let p = SECP256K1::p();
+        if !self.is_canonical() {
+            return false;
+        }
+        let y2 = self.y.mul_mod(&self.y, &p);
+        let x3 = self.x.exp_mod(&RU256::from_u64(3), &p);
+        y2 == x3.add_mod(&RU256::from_u64(7), &p)
+    }
+
+    /// The point with the given x coordinate and y parity, if there is one
+    pub fn lift_x(x: &RU256, odd: bool) -> Option<Self> {
+        let p = SECP256K1::p();
+        if *x >= p {
+            return None;
+        }
+
+        // y^2 = x^3 + 7, and since p = 3 mod 4 a square root of a is
+        // a^((p + 1) / 4)
+        let y2 = x
+            .exp_mod(&RU256::from_u64(3), &p)
+            .add_mod(&RU256::from_u64(7), &p);
+        let exponent = RU256 { v: (p.v + 1) >> 2 };
+        let y = y2.exp_mod(&exponent, &p);
+        if y.mul_mod(&y, &p) != y2 {
+            return None;
+        }
+
+        let y = if y.v.bit(0) == odd {
+            y
+        } else {
+            RU256::zero().sub_mod(&y, &p)
+        };
+        Some(Point { x: x.clone(), y })
+    }
+}
+
+/// Uncompressed SEC encoding, `04 || x || y`
+impl Encodable for Point {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0x04; 65];
+        self.x.to_bytes(&mut out[1..33]);
+        self.y.to_bytes(&mut out[33..65]);
+        out
+    }
+}
+
+/// Either SEC encoding, told apart by the prefix byte
+impl Decodable for Point {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        match bytes.first() {
+            Some(0x02 | 0x03) => Point::from_compressed_bytes(take(bytes, 33)),
+            _ => {
+                let prefix = take(bytes, 1)[0];
+                assert_eq!(prefix, 0x04, "unsupported point encoding {:#04x}", prefix);
+                let point = Point {
+                    x: RU256::from_bytes(take(bytes, 32)),
+                    y: RU256::from_bytes(take(bytes, 32)),
+                };
+                assert!(point.is_on_curve(), "point is not on the curve");
+                point
+            }
+        }
+    }
 }
 
 impl Add<Point> for Point {
@@ -50,7 +153,7 @@ impl Mul<RU256> for Point {
     fn mul(self, scalar: RU256) -> Point {
         // Implement the scalar multiplication logic here
         // This is a placeholder; replace with actual implementation
-        SECP256K1::scalar_multiplication(&scalar, &self, false)
+        SECP256K1::scalar_multiplication(&scalar, &self, None)
     }
 }
 
@@ -58,9 +161,10 @@ impl Neg for Point {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
+        // -y in the field, p - y
         Point {
             x: self.x,
-            y: self.y.neg(),
+            y: RU256::zero().sub_mod(&self.y, &SECP256K1::p()),
         }
     }
 }
@@ -109,7 +213,12 @@ impl SECP256K1 {
 
         // we need to make sure the points are not the same,
         // if the same when calculating lambda, we will have
-        // a division by zero error
+        // a division by zero error. That takes canonical
+        // coordinates, otherwise equal points compare unequal
+        debug_assert!(
+            p1.is_canonical() && p2.is_canonical(),
+            "point coordinates are not reduced"
+        );
         assert!(p1 != p2);
 
         // if any of the point is the identity, we return the
@@ -143,12 +252,14 @@ impl SECP256K1 {
     }
 
     /// Double a curve point
-    fn double_point(p1: &Point) -> Point {
+    pub fn double_point(p1: &Point) -> Point {
         // only one point (x, y)
         // lambda = (3x^2 + a) / 2y
         // x3 = lambda^2 - x - x
         // y3 = lambda(xp - x) - y
 
+        debug_assert!(p1.is_canonical(), "point coordinates are not reduced");
+
         // doubling the identity point, returns the identity point
         // O + O = O
         if p1.is_zero_point() {
@@ -195,65 +306,32 @@ impl SECP256K1 {
         }
     }
 
-    fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-    where
-        P: AsRef<Path>,
-    {
-        let file = File::open(filename)?;
-        Ok(io::BufReader::new(file).lines())
-    }
-
-    fn load_precomputed_points(file_path: &str) -> Vec<Point> {
-        let mut points = Vec::new();
-        if let Ok(lines) = Self::read_lines(file_path) {
-            for line in lines {
-                if let Ok(point_str) = line {
-                    let parts: Vec<&str> = point_str.split(':').collect();
-                    if parts.len() == 2 {
-                        let compressed_pubkey = parts[1];
-                        let pubkey_bytes = hex::decode(compressed_pubkey).unwrap();
-                        let pubkey = PublicKey::from_slice(&pubkey_bytes).unwrap();
-                        let uncompressed = pubkey.serialize_uncompressed();
-                        let x = RU256::from_str(&hex::encode(&uncompressed[1..33])).unwrap();
-                        let y = RU256::from_str(&hex::encode(&uncompressed[33..65])).unwrap();
-                        points.push(Point { x, y });
-                    }
-                }
-            }
-        } else {
-            println!("Failed to read lines from file: {}", file_path);
-        }
-        println!("Loaded {} precomputed points", points.len());
-        points
-    }
-
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(precomputed = precomputed.is_some()))
+    )]
     pub fn scalar_multiplication(
         scalar: &RU256,
         curve_point: &Point,
-        use_precomputed: bool,
+        precomputed: Option<&PrecomputeTable>,
     ) -> Point {
+        sensitive!(?scalar, "scalar multiplication");
         let mut result = Self::zero_point();
 
-        if use_precomputed {
-            println!("Using precomputed points for scalar multiplication");
-            let precomputed_points = Self::load_precomputed_points(
-                "/Users/kody/Documents/github/fedi_stuff/ecash-course/projects/00-cryptos-rs/precomputed_points.txt",
+        if let Some(table) = precomputed {
+            assert!(
+                table.base() == curve_point,
+                "precompute table was built for a different base point"
             );
 
+            // the table holds 2^i * P, so we only need one addition per set bit
             for i in 0..scalar.v.bits() {
                 if scalar.v.bit(i) {
-                    let index = i as usize;
-                    if index < precomputed_points.len() {
-                        println!("Adding precomputed point for bit index: {}", index);
-                        result = Self::add_points(&result, &precomputed_points[index]);
-                    } else {
-                        println!("Index out of bounds for precomputed points: {}", index);
-                    }
+                    result = Self::add_points(&result, &table.points()[i]);
                 }
             }
         } else {
-            println!("Starting scalar multiplication without precomputed points");
-            let mut adder = curve_point.clone();
+            let adder = curve_point.clone();
 
             for i in (0..scalar.v.bits()).rev() {
                 result = Self::double_point(&result);
@@ -266,15 +344,498 @@ impl SECP256K1 {
         result
     }
 
+    /// Multiply the base point of a window table, one addition per non-zero
+    /// 4 bit digit of the scalar
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn scalar_multiplication_fixed_base(scalar: &RU256, table: &WindowTable) -> Point {
+        sensitive!(?scalar, "fixed-base scalar multiplication");
+        let mut result = Self::zero_point();
+        let digits = scalar.v.bits().div_ceil(WindowTable::WINDOW_BITS);
+        for window in 0..digits {
+            let digit = (scalar.v >> (window * WindowTable::WINDOW_BITS)).low_u32() & 0xf;
+            // for a scalar below n the partial sum is always a smaller multiple
+            // of the base than the entry, so the points never coincide
+            if digit != 0 {
+                result = Self::add_points(&result, table.entry(window, digit as usize));
+            }
+        }
+        result
+    }
+
+    /// Multiply with a Montgomery ladder, which doubles and adds once for
+    /// each of the 256 bits whatever the bit is, and swaps the running points
+    /// with masks instead of branching. The complete addition formulas have no
+    /// special cases for the point at infinity or equal points to branch on
+    /// either. The underlying `mul_mod` isn't constant time itself, so this
+    /// removes the big leak of double and add, time growing with the number of
+    /// set bits, but not every last one.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn scalar_multiplication_ct(scalar: &RU256, curve_point: &Point) -> Point {
+        sensitive!(?scalar, "constant-time scalar multiplication");
+        let p = Self::p();
+        let mut r0 = Projective::infinity();
+        let mut r1 = Projective::from_affine(curve_point);
+        for i in (0..256).rev() {
+            let bit = Choice::from_bit(scalar.v.bit(i) as u8);
+            Projective::ct_swap(&mut r0, &mut r1, bit);
+            r1 = r0.add(&r1, &p);
+            r0 = r0.double(&p);
+            Projective::ct_swap(&mut r0, &mut r1, bit);
+        }
+        r0.to_affine(&p)
+    }
+
     /// Derive the public key from a given private key
     pub fn public_key(private_key: &RU256) -> Point {
-        Self::scalar_multiplication(&private_key, &Self::g(), false)
+        Self::scalar_multiplication(private_key, &Self::g(), None)
+    }
+}
+
+/// Sum of any two points, for protocols whose equations can hit every case
+/// `add_points` leaves out: equal points are doubled, a point and its
+/// negation give the point at infinity, and infinity adds nothing
+pub fn point_add(p1: &Point, p2: &Point) -> Point {
+    if p1.is_zero_point() {
+        return p2.clone();
     }
+    if p2.is_zero_point() {
+        return p1.clone();
+    }
+    match (p1.x == p2.x, p1.y == p2.y) {
+        (true, true) => SECP256K1::double_point(p1),
+        (true, false) => SECP256K1::zero_point(),
+        _ => SECP256K1::add_points(p1, p2),
+    }
+}
+
+/// `scalar` times `point`, in constant time
+pub fn point_mul(scalar: &Fn, point: &Point) -> Point {
+    SECP256K1::scalar_multiplication_ct(scalar.as_ru256(), point)
+}
+
+/// A point as (X, Y, Z) standing for (X/Z, Y/Z), with the point at infinity
+/// as (0, 1, 0), for the complete formulas of Renes, Costello and Batina
+/// (2016) for curves with a = 0
+#[derive(Clone, Debug)]
+struct Projective {
+    x: RU256,
+    y: RU256,
+    z: RU256,
+}
+
+impl Projective {
+    fn infinity() -> Self {
+        Projective {
+            x: RU256::zero(),
+            y: RU256::one(),
+            z: RU256::zero(),
+        }
+    }
+
+    fn from_affine(point: &Point) -> Self {
+        if point.is_zero_point() {
+            return Projective::infinity();
+        }
+        Projective {
+            x: point.x.clone(),
+            y: point.y.clone(),
+            z: RU256::one(),
+        }
+    }
+
+    /// Back to affine coordinates, inverting Z in constant time. Infinity
+    /// has Z = 0, whose "inverse" 0 gives the zero point.
+    fn to_affine(&self, p: &RU256) -> Point {
+        let z_inv = self.z.inv_mod_safegcd(p);
+        Point {
+            x: self.x.mul_mod(&z_inv, p),
+            y: self.y.mul_mod(&z_inv, p),
+        }
+    }
+
+    /// Swap `a` and `b` if `choice` is set, without branching
+    fn ct_swap(a: &mut Projective, b: &mut Projective, choice: Choice) {
+        let swapped = Projective {
+            x: RU256::ct_select(&a.x, &b.x, choice),
+            y: RU256::ct_select(&a.y, &b.y, choice),
+            z: RU256::ct_select(&a.z, &b.z, choice),
+        };
+        b.x = RU256::ct_select(&b.x, &a.x, choice);
+        b.y = RU256::ct_select(&b.y, &a.y, choice);
+        b.z = RU256::ct_select(&b.z, &a.z, choice);
+        *a = swapped;
+    }
+
+    /// Algorithm 7 of the paper, any two points including equal ones and
+    /// infinity
+    fn add(&self, other: &Projective, p: &RU256) -> Projective {
+        let b3 = RU256::from_u64(21);
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&other.x, &other.y, &other.z);
+        let t0 = x1.mul_mod(x2, p);
+        let t1 = y1.mul_mod(y2, p);
+        let t2 = z1.mul_mod(z2, p);
+        let t3 = x1.add_mod(y1, p).mul_mod(&x2.add_mod(y2, p), p);
+        let t3 = t3.sub_mod(&t0.add_mod(&t1, p), p);
+        let t4 = y1.add_mod(z1, p).mul_mod(&y2.add_mod(z2, p), p);
+        let t4 = t4.sub_mod(&t1.add_mod(&t2, p), p);
+        let y3 = x1.add_mod(z1, p).mul_mod(&x2.add_mod(z2, p), p);
+        let y3 = y3.sub_mod(&t0.add_mod(&t2, p), p);
+        let t0 = t0.add_mod(&t0, p).add_mod(&t0, p);
+        let t2 = b3.mul_mod(&t2, p);
+        let z3 = t1.add_mod(&t2, p);
+        let t1 = t1.sub_mod(&t2, p);
+        let y3 = b3.mul_mod(&y3, p);
+        let x3 = t3.mul_mod(&t1, p).sub_mod(&t4.mul_mod(&y3, p), p);
+        let y3 = t1.mul_mod(&z3, p).add_mod(&y3.mul_mod(&t0, p), p);
+        let z3 = z3.mul_mod(&t4, p).add_mod(&t0.mul_mod(&t3, p), p);
+        Projective {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    /// Algorithm 9 of the paper
+    fn double(&self, p: &RU256) -> Projective {
+        let b3 = RU256::from_u64(21);
+        let (x, y, z) = (&self.x, &self.y, &self.z);
+        let t0 = y.mul_mod(y, p);
+        let z3 = t0.mul_mod(&RU256::from_u64(8), p);
+        let t1 = y.mul_mod(z, p);
+        let t2 = b3.mul_mod(&z.mul_mod(z, p), p);
+        let x3 = t2.mul_mod(&z3, p);
+        let y3 = t0.add_mod(&t2, p);
+        let z3 = t1.mul_mod(&z3, p);
+        let t2 = t2.add_mod(&t2, p).add_mod(&t2, p);
+        let t0 = t0.sub_mod(&t2, p);
+        let y3 = x3.add_mod(&t0.mul_mod(&y3, p), p);
+        let x3 = t0.mul_mod(&x.mul_mod(y, p), p);
+        Projective {
+            x: x3.add_mod(&x3, p),
+            y: y3,
+            z: z3,
+        }
+    }
+}
+
+/// Multiples of a fixed base point, `points[i] = 2^i * base`, so scalar
+/// multiplication of the base only needs point additions
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrecomputeTable {
+    points: Vec<Point>,
+}
+
+impl PrecomputeTable {
+    /// Number of entries, one per bit of a scalar
+    pub const SIZE: usize = 256;
+
+    /// Compute the table in memory by repeatedly doubling the base point
+    pub fn generate(base: &Point) -> Self {
+        let mut points = vec![base.clone()];
+        for i in 1..Self::SIZE {
+            points.push(SECP256K1::double_point(&points[i - 1]));
+        }
+        PrecomputeTable { points }
+    }
+
+    pub fn base(&self) -> &Point {
+        &self.points[0]
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+}
+
+#[cfg(feature = "std")]
+impl PrecomputeTable {
+    const MAGIC: [u8; 4] = *b"PCTB";
+
+    /// Serialize to the binary table format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_table(Self::MAGIC, &self.points)
+    }
+
+    /// Check the header and checksum of a binary table without decoding the
+    /// points
+    pub fn verify(bytes: &[u8]) -> io::Result<()> {
+        verify_table(Self::MAGIC, Self::SIZE, bytes)
+    }
+
+    /// Parse the binary format, e.g. a table embedded with `include_bytes!`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let points = decode_table(Self::MAGIC, Self::SIZE, bytes)?;
+        Ok(PrecomputeTable { points })
+    }
+
+    /// Parse the legacy `index:compressed point hex` line format
+    pub fn from_text(text: &[u8]) -> io::Result<Self> {
+        let points = table_from_text(Self::SIZE, text)?;
+        Ok(PrecomputeTable { points })
+    }
+
+    /// Serialize to the legacy `index:compressed point hex` line format
+    pub fn to_text(&self) -> Vec<u8> {
+        table_to_text(&self.points)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /// Convert a legacy text table file into the binary format
+    pub fn convert_text_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        text_path: P,
+        binary_path: Q,
+    ) -> io::Result<()> {
+        Self::from_text(&fs::read(text_path)?)?.save(binary_path)
+    }
+}
+
+/// Fixed-base table for 4 bit windows: window `w` holds `j * 16^w * base` for
+/// the digits `j` in 1..16 (digit 0 is the identity and not stored), so a
+/// scalar multiplication of the base is at most 64 additions and no doublings
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowTable {
+    points: Vec<Point>,
+}
+
+impl WindowTable {
+    pub const WINDOW_BITS: usize = 4;
+    /// Number of windows needed to cover a 256 bit scalar
+    pub const WINDOWS: usize = 256 / Self::WINDOW_BITS;
+    /// Stored multiples per window, the non-zero digits
+    pub const DIGITS: usize = (1 << Self::WINDOW_BITS) - 1;
+    pub const SIZE: usize = Self::WINDOWS * Self::DIGITS;
+
+    /// Compute the table in memory, every window starts at 16 times the
+    /// previous window's base
+    pub fn generate(base: &Point) -> Self {
+        let mut points = Vec::with_capacity(Self::SIZE);
+        let mut window_base = base.clone();
+        for window in 0..Self::WINDOWS {
+            points.push(window_base.clone());
+            // add_points needs distinct points, so 2 * base is a doubling
+            points.push(SECP256K1::double_point(&window_base));
+            for _ in 3..=Self::DIGITS {
+                let multiple = SECP256K1::add_points(points.last().unwrap(), &window_base);
+                points.push(multiple);
+            }
+            if window + 1 < Self::WINDOWS {
+                // 16 * base = 2 * (8 * base)
+                window_base = SECP256K1::double_point(&points[window * Self::DIGITS + 7]);
+            }
+        }
+        WindowTable { points }
+    }
+
+    pub fn base(&self) -> &Point {
+        &self.points[0]
+    }
+
+    /// `digit * 16^window * base`, for a non-zero digit
+    pub fn entry(&self, window: usize, digit: usize) -> &Point {
+        assert!(
+            digit > 0 && digit <= Self::DIGITS,
+            "invalid window digit {}",
+            digit
+        );
+        &self.points[window * Self::DIGITS + digit - 1]
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+}
+
+#[cfg(feature = "std")]
+impl WindowTable {
+    const MAGIC: [u8; 4] = *b"PCWT";
+
+    /// Serialize to the binary table format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_table(Self::MAGIC, &self.points)
+    }
+
+    /// Check the header and checksum of a binary table without decoding the
+    /// points
+    pub fn verify(bytes: &[u8]) -> io::Result<()> {
+        verify_table(Self::MAGIC, Self::SIZE, bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let points = decode_table(Self::MAGIC, Self::SIZE, bytes)?;
+        Ok(WindowTable { points })
+    }
+
+    /// Parse the `index:compressed point hex` line format, entries are ordered
+    /// by window and then digit
+    pub fn from_text(text: &[u8]) -> io::Result<Self> {
+        let points = table_from_text(Self::SIZE, text)?;
+        Ok(WindowTable { points })
+    }
+
+    pub fn to_text(&self) -> Vec<u8> {
+        table_to_text(&self.points)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /// Convert a text table file into the binary format
+    pub fn convert_text_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        text_path: P,
+        binary_path: Q,
+    ) -> io::Result<()> {
+        Self::from_text(&fs::read(text_path)?)?.save(binary_path)
+    }
+}
+
+#[cfg(feature = "std")]
+/// Version of the binary table format
+const TABLE_VERSION: u8 = 1;
+#[cfg(feature = "std")]
+/// Magic, version and point count
+const TABLE_HEADER_LEN: usize = 9;
+
+#[cfg(feature = "std")]
+/// Binary table format: magic, version, point count, the 33 byte compressed
+/// points and a sha256 checksum over everything before it
+fn encode_table(magic: [u8; 4], points: &[Point]) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.push(TABLE_VERSION);
+    out.extend((points.len() as u32).to_le_bytes());
+    for point in points {
+        out.extend(point.to_compressed_bytes());
+    }
+    let checksum = sha256(out.clone());
+    out.extend(checksum);
+    out
+}
+
+#[cfg(feature = "std")]
+fn verify_table(magic: [u8; 4], size: usize, bytes: &[u8]) -> io::Result<()> {
+    if bytes.len() < TABLE_HEADER_LEN + 32 {
+        return Err(invalid_data("precompute table is truncated".to_string()));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 32);
+    if sha256(body.to_vec()) != checksum {
+        return Err(invalid_data(
+            "precompute table checksum mismatch".to_string(),
+        ));
+    }
+    if body[..4] != magic {
+        return Err(invalid_data(format!(
+            "not a {} precompute table",
+            String::from_utf8_lossy(&magic)
+        )));
+    }
+    if body[4] != TABLE_VERSION {
+        return Err(invalid_data(format!("unsupported version {}", body[4])));
+    }
+    let count = u32::from_le_bytes(body[5..9].try_into().unwrap()) as usize;
+    if count != size || body.len() != TABLE_HEADER_LEN + 33 * count {
+        return Err(invalid_data(format!("expected {} points", size)));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+fn decode_table(magic: [u8; 4], size: usize, bytes: &[u8]) -> io::Result<Vec<Point>> {
+    verify_table(magic, size, bytes)?;
+    bytes[TABLE_HEADER_LEN..bytes.len() - 32]
+        .chunks(33)
+        .map(|chunk| {
+            decompress_point(chunk)
+                .ok_or_else(|| invalid_data(format!("invalid point: {}", hex::encode(chunk))))
+        })
+        .collect()
+}
+
+/// `index:compressed point hex` lines
+#[cfg(feature = "std")]
+fn table_from_text(size: usize, text: &[u8]) -> io::Result<Vec<Point>> {
+    let mut points = Vec::with_capacity(size);
+    for line in text.lines() {
+        let line = line?;
+        let (index, point_hex) = line
+            .split_once(':')
+            .ok_or_else(|| invalid_data(format!("invalid line: {}", line)))?;
+        if index.parse::<usize>() != Ok(points.len()) {
+            return Err(invalid_data(format!("unexpected index: {}", index)));
+        }
+        let point = hex::decode(point_hex)
+            .ok()
+            .and_then(|bytes| decompress_point(&bytes))
+            .ok_or_else(|| invalid_data(format!("invalid point: {}", point_hex)))?;
+        points.push(point);
+    }
+    if points.len() != size {
+        return Err(invalid_data(format!("expected {} points", size)));
+    }
+    Ok(points)
+}
+
+#[cfg(feature = "std")]
+fn table_to_text(points: &[Point]) -> Vec<u8> {
+    let mut out = String::new();
+    for (index, point) in points.iter().enumerate() {
+        out.push_str(&format!(
+            "{}:{}\n",
+            index,
+            hex::encode(point.to_compressed_bytes())
+        ));
+    }
+    out.into_bytes()
+}
+
+#[cfg(feature = "std")]
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// 33 byte SEC compressed encoding of a point
+#[cfg(feature = "std")]
+fn decompress_point(bytes: &[u8]) -> Option<Point> {
+    let uncompressed = PublicKey::from_slice(bytes).ok()?.serialize_uncompressed();
+    Some(Point {
+        x: RU256::from_bytes(&uncompressed[1..33]),
+        y: RU256::from_bytes(&uncompressed[33..65]),
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+    use secp256k1::{Secp256k1, SecretKey};
+
     use super::*;
+    use crate::encoding::ToHex;
+    use crate::strategies;
+
+    #[test]
+    fn secp256k1_point_add() {
+        let g = SECP256K1::g();
+        let infinity = SECP256K1::zero_point();
+        assert_eq!(point_add(&g, &-g.clone()), infinity);
+        assert_eq!(point_add(&g, &infinity), g);
+        assert_eq!(point_add(&infinity, &g), g);
+        assert_eq!(point_add(&infinity, &infinity), infinity);
+        let two_g = point_add(&g, &g);
+        assert_eq!(two_g, SECP256K1::double_point(&g));
+        assert_eq!(point_add(&two_g, &-g.clone()), g);
+        assert_eq!(point_mul(&Fn::new(&RU256::from_u64(2)), &g), two_g);
+    }
 
     #[test]
     fn secp256k1_add_points() {
@@ -288,7 +849,7 @@ mod tests {
         );
         let pt3 = SECP256K1::add_points(&pt1, &pt2);
 
-        assert_eq!(pt3.to_hex_string(), "04f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9388f7b0f632de8140fe337e62a37f3566500a99934c2231b6cb9fd7584b8e672");
+        assert_eq!(pt3.to_hex(), "04f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9388f7b0f632de8140fe337e62a37f3566500a99934c2231b6cb9fd7584b8e672");
     }
 
     #[test]
@@ -301,7 +862,28 @@ mod tests {
         let pt2 = SECP256K1::double_point(&pt1);
         let pt3 = SECP256K1::double_point(&pt2);
 
-        assert_eq!(pt3.to_hex_string(), "04e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd1351ed993ea0d455b75642e2098ea51448d967ae33bfbdfe40cfe97bdc47739922");
+        assert_eq!(pt3.to_hex(), "04e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd1351ed993ea0d455b75642e2098ea51448d967ae33bfbdfe40cfe97bdc47739922");
+    }
+
+    #[test]
+    fn non_canonical_point() {
+        // (p + 1, 1) is the residue (1, 1) written as a bigger integer
+        let p = SECP256K1::p();
+        let point = Point {
+            x: RU256 { v: p.v + 1 },
+            y: RU256::one(),
+        };
+        assert!(SECP256K1::g().is_canonical());
+        assert!(!point.is_canonical());
+        assert!(!point.is_on_curve());
+        assert_ne!(point.x, RU256::one());
+
+        // the point math would treat it as a point of its own
+        #[cfg(debug_assertions)]
+        {
+            let result = std::panic::catch_unwind(|| SECP256K1::double_point(&point));
+            assert!(result.is_err());
+        }
     }
 
     #[test]
@@ -315,13 +897,13 @@ mod tests {
             .to_big_endian(&mut scalar_bytes);
         let secret_key = SecretKey::from_slice(&scalar_bytes).unwrap();
         let secp_pubkey = PublicKey::from_secret_key(&secp, &secret_key);
-        println!("Generated public key: {}", pub_key.to_hex_string());
+        println!("Generated public key: {}", pub_key.to_hex());
         println!(
             "Expected public key: {}",
             hex::encode(secp_pubkey.serialize_uncompressed())
         );
         assert_eq!(
-            pub_key.to_hex_string(),
+            pub_key.to_hex(),
             hex::encode(secp_pubkey.serialize_uncompressed())
         );
     }
@@ -422,4 +1004,159 @@ mod tests {
             "B7C52588D95C3B9AA25B0403F1EEF75702E84BB7597AABE663B82F6F04EF2777"
         );
     }
+
+    #[test]
+    fn precompute_table_scalar_multiplication() {
+        let table = PrecomputeTable::generate(&SECP256K1::g());
+        assert_eq!(table.points().len(), PrecomputeTable::SIZE);
+
+        for k in [1u64, 2, 5, 0xdeadbeef12345] {
+            let k = RU256::from_u64(k);
+            assert_eq!(
+                SECP256K1::scalar_multiplication(&k, &SECP256K1::g(), Some(&table)),
+                SECP256K1::scalar_multiplication(&k, &SECP256K1::g(), None)
+            );
+        }
+    }
+
+    #[test]
+    fn precompute_table_save_load() {
+        let table = PrecomputeTable::generate(&SECP256K1::g());
+        assert_eq!(
+            PrecomputeTable::from_bytes(&table.to_bytes()).unwrap(),
+            table
+        );
+        assert_eq!(table.to_bytes().len(), 9 + 33 * PrecomputeTable::SIZE + 32);
+
+        let path = std::env::temp_dir().join(format!("precompute-{}.bin", std::process::id()));
+        table.save(&path).unwrap();
+        let loaded = PrecomputeTable::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, table);
+    }
+
+    #[test]
+    fn precompute_table_corruption() {
+        let table = PrecomputeTable::generate(&SECP256K1::g());
+        let bytes = table.to_bytes();
+        assert!(PrecomputeTable::verify(&bytes).is_ok());
+
+        // truncated tables and flipped bits are caught by the checksum
+        assert!(PrecomputeTable::verify(&bytes[..bytes.len() / 2]).is_err());
+        let mut corrupted = bytes.clone();
+        corrupted[100] ^= 0x01;
+        assert!(PrecomputeTable::from_bytes(&corrupted).is_err());
+    }
+
+    #[test]
+    fn precompute_table_convert_text() {
+        let table = PrecomputeTable::generate(&SECP256K1::g());
+        assert_eq!(PrecomputeTable::from_text(&table.to_text()).unwrap(), table);
+
+        let dir = std::env::temp_dir();
+        let text_path = dir.join(format!("precompute-{}.txt", std::process::id()));
+        let binary_path = dir.join(format!("precompute-converted-{}.bin", std::process::id()));
+        fs::write(&text_path, table.to_text()).unwrap();
+        PrecomputeTable::convert_text_file(&text_path, &binary_path).unwrap();
+        let converted = PrecomputeTable::load(&binary_path).unwrap();
+        fs::remove_file(&text_path).unwrap();
+        fs::remove_file(&binary_path).unwrap();
+        assert_eq!(converted, table);
+
+        let text = table.to_text();
+        assert!(PrecomputeTable::from_text(&text[..text.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn window_table_scalar_multiplication() {
+        let table = WindowTable::generate(&SECP256K1::g());
+        assert_eq!(table.points().len(), WindowTable::SIZE);
+        assert_eq!(
+            table.entry(1, 1),
+            &SECP256K1::public_key(&RU256::from_u64(16))
+        );
+
+        let n_minus_one =
+            RU256::from_str("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364140")
+                .unwrap();
+        for k in [
+            RU256::from_u64(0),
+            RU256::from_u64(1),
+            RU256::from_u64(0xff),
+            RU256::from_u64(0xdeadbeef12345),
+            n_minus_one,
+        ] {
+            assert_eq!(
+                SECP256K1::scalar_multiplication_fixed_base(&k, &table),
+                SECP256K1::scalar_multiplication(&k, &SECP256K1::g(), None)
+            );
+        }
+
+        let bytes = table.to_bytes();
+        assert_eq!(WindowTable::from_bytes(&bytes).unwrap(), table);
+        assert_eq!(WindowTable::from_text(&table.to_text()).unwrap(), table);
+        // the two table layouts can't be mixed up
+        assert!(PrecomputeTable::verify(&bytes).is_err());
+    }
+
+    #[test]
+    fn ladder_scalar_multiplication() {
+        let n_minus_one =
+            RU256::from_str("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364140")
+                .unwrap();
+        let point = SECP256K1::public_key(&RU256::from_u64(7));
+        for k in [
+            RU256::from_u64(0),
+            RU256::from_u64(1),
+            RU256::from_u64(2),
+            RU256::from_u64(0xdeadbeef12345),
+            RU256::from_str("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721")
+                .unwrap(),
+            n_minus_one,
+        ] {
+            assert_eq!(
+                SECP256K1::scalar_multiplication_ct(&k, &point),
+                SECP256K1::scalar_multiplication(&k, &point, None)
+            );
+        }
+    }
+
+    proptest! {
+        // every case costs a few scalar multiplications
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn prop_points_on_curve_and_addition_commutes(
+            p in strategies::point(),
+            q in strategies::point(),
+        ) {
+            let prime = SECP256K1::p();
+            for point in [&p, &q] {
+                let y2 = point.y.mul_mod(&point.y, &prime);
+                let x3 = point.x.mul_mod(&point.x, &prime).mul_mod(&point.x, &prime);
+                prop_assert_eq!(y2, x3.add_mod(&RU256::from_u64(7), &prime));
+            }
+            prop_assume!(p.x != q.x);
+            prop_assert_eq!(SECP256K1::add_points(&p, &q), SECP256K1::add_points(&q, &p));
+        }
+
+        #[test]
+        fn prop_scalar_multiplication_is_linear(a in 1u64..256, b in 1u64..256) {
+            prop_assume!(a != b);
+            let sum = SECP256K1::add_points(
+                &SECP256K1::public_key(&RU256::from_u64(a)),
+                &SECP256K1::public_key(&RU256::from_u64(b)),
+            );
+            prop_assert_eq!(sum, SECP256K1::public_key(&RU256::from_u64(a + b)));
+        }
+
+        #[test]
+        fn prop_compressed_sec_roundtrip(point in strategies::point()) {
+            let compressed = point.to_compressed_bytes();
+            let expected = PublicKey::from_slice(&point.encode()).unwrap().serialize();
+            prop_assert_eq!(&compressed[..], &expected[..]);
+            prop_assert_eq!(Point::from_compressed_bytes(&compressed), point.clone());
+            prop_assert_eq!(Point::decode_all(&compressed), point);
+        }
+    }
 }
@@ -1,3 +1,7 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
 const K: [u32; 64] = [
     0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
     0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
@@ -45,76 +49,481 @@ fn maj(x: u32, y: u32, z: u32) -> u32 {
     (x & y) ^ (x & z) ^ (y & z)
 }
 
-fn pad(mut b: Vec<u8>) -> Vec<u8> {
-    let l = (b.len() * 8) as u64;
-    b.push(0x80);
-    while (b.len() * 8) % 512 != 448 {
+/// The padding SHA-256 appends to a message of `length` bytes: 0x80, zeros up
+/// to 56 mod 64 and the length in bits
+pub fn padding(length: u64) -> Vec<u8> {
+    let mut b = vec![0x80];
+    while (length as usize + b.len()) % 64 != 56 {
         b.push(0x00);
     }
-    b.extend_from_slice(&l.to_be_bytes());
+    b.extend_from_slice(&(length * 8).to_be_bytes());
     b
 }
 
+/// The textbook compression function, kept as the reference the faster paths
+/// are tested against
+fn compress_reference(h: &mut [u32; 8], chunk: &[u8]) {
+    let mut w = [0u32; 64];
+    for t in 0..16 {
+        w[t] = u32::from_be_bytes([
+            chunk[4 * t],
+            chunk[4 * t + 1],
+            chunk[4 * t + 2],
+            chunk[4 * t + 3],
+        ]);
+    }
+    for t in 16..64 {
+        w[t] = sig1(w[t - 2])
+            .wrapping_add(w[t - 7])
+            .wrapping_add(sig0(w[t - 15]))
+            .wrapping_add(w[t - 16]);
+    }
+
+    let mut a = h[0];
+    let mut b = h[1];
+    let mut c = h[2];
+    let mut d = h[3];
+    let mut e = h[4];
+    let mut f = h[5];
+    let mut g = h[6];
+    let mut h7 = h[7];
+
+    for t in 0..64 {
+        let t1 = h7
+            .wrapping_add(capsig1(e))
+            .wrapping_add(ch(e, f, g))
+            .wrapping_add(K[t])
+            .wrapping_add(w[t]);
+        let t2 = capsig0(a).wrapping_add(maj(a, b, c));
+        h7 = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    h[0] = h[0].wrapping_add(a);
+    h[1] = h[1].wrapping_add(b);
+    h[2] = h[2].wrapping_add(c);
+    h[3] = h[3].wrapping_add(d);
+    h[4] = h[4].wrapping_add(e);
+    h[5] = h[5].wrapping_add(f);
+    h[6] = h[6].wrapping_add(g);
+    h[7] = h[7].wrapping_add(h7); // Update h[7] with h7
+}
+
+/// The same rounds with the working variables renamed instead of shifted and
+/// the message schedule computed on the fly in a rolling 16 word window
+fn compress_unrolled(h: &mut [u32; 8], chunk: &[u8]) {
+    let mut w = [0u32; 16];
+    for (w, word) in w.iter_mut().zip(chunk.chunks_exact(4)) {
+        *w = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h7] = *h;
+
+    // round t leaves the new `a` in `$h` and the new `e` in `$d`, so the next
+    // round is the same with every name moved one place to the right
+    macro_rules! round {
+        ($a:ident, $b:ident, $c:ident, $d:ident, $e:ident, $f:ident, $g:ident, $h:ident, $t:expr) => {
+            let t = $t;
+            if t >= 16 {
+                w[t & 15] = sig1(w[(t - 2) & 15])
+                    .wrapping_add(w[(t - 7) & 15])
+                    .wrapping_add(sig0(w[(t - 15) & 15]))
+                    .wrapping_add(w[t & 15]);
+            }
+            let t1 = $h
+                .wrapping_add(capsig1($e))
+                .wrapping_add(ch($e, $f, $g))
+                .wrapping_add(K[t])
+                .wrapping_add(w[t & 15]);
+            let t2 = capsig0($a).wrapping_add(maj($a, $b, $c));
+            $d = $d.wrapping_add(t1);
+            $h = t1.wrapping_add(t2);
+        };
+    }
+
+    for t in (0..64).step_by(8) {
+        round!(a, b, c, d, e, f, g, h7, t);
+        round!(h7, a, b, c, d, e, f, g, t + 1);
+        round!(g, h7, a, b, c, d, e, f, t + 2);
+        round!(f, g, h7, a, b, c, d, e, t + 3);
+        round!(e, f, g, h7, a, b, c, d, t + 4);
+        round!(d, e, f, g, h7, a, b, c, t + 5);
+        round!(c, d, e, f, g, h7, a, b, t + 6);
+        round!(b, c, d, e, f, g, h7, a, t + 7);
+    }
+
+    for (h, x) in h.iter_mut().zip([a, b, c, d, e, f, g, h7]) {
+        *h = h.wrapping_add(x);
+    }
+}
+
+// The SHA extensions (SHA-NI) run two rounds per instruction and compute the
+// message schedule four words at a time. They keep the state split across two
+// registers as ABEF and CDGH, hence the shuffles on the way in and out. A port
+// of the RustCrypto sha2 backend.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+mod shani {
+    use core::arch::x86_64::*;
+
+    use super::K;
+
+    pub fn available() -> bool {
+        std::is_x86_feature_detected!("sha")
+            && std::is_x86_feature_detected!("sse2")
+            && std::is_x86_feature_detected!("ssse3")
+            && std::is_x86_feature_detected!("sse4.1")
+    }
+
+    /// Four rounds over the message words `w` (already big endian)
+    macro_rules! rounds4 {
+        ($abef:ident, $cdgh:ident, $w:expr, $i:expr) => {
+            let k = _mm_loadu_si128(K.as_ptr().add(4 * $i) as *const __m128i);
+            let wk = _mm_add_epi32($w, k);
+            $cdgh = _mm_sha256rnds2_epu32($cdgh, $abef, wk);
+            $abef = _mm_sha256rnds2_epu32($abef, $cdgh, _mm_shuffle_epi32(wk, 0x0E));
+        };
+    }
+
+    /// Compute the next four schedule words into `$w4`, then run four rounds
+    macro_rules! schedule_rounds4 {
+        ($abef:ident, $cdgh:ident, $w0:ident, $w1:ident, $w2:ident, $w3:ident, $w4:ident, $i:expr) => {
+            let t = _mm_add_epi32(_mm_sha256msg1_epu32($w0, $w1), _mm_alignr_epi8($w3, $w2, 4));
+            $w4 = _mm_sha256msg2_epu32(t, $w3);
+            rounds4!($abef, $cdgh, $w4, $i);
+        };
+    }
+
+    /// # Safety
+    ///
+    /// The CPU must support the features checked by [`available`]
+    #[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+    pub unsafe fn compress_blocks(state: &mut [u32; 8], blocks: &[u8]) {
+        // byte swaps each 32 bit word
+        let mask = _mm_set_epi64x(0x0c0d_0e0f_0809_0a0b, 0x0405_0607_0001_0203);
+
+        let dcba = _mm_loadu_si128(state.as_ptr() as *const __m128i);
+        let hgfe = _mm_loadu_si128(state.as_ptr().add(4) as *const __m128i);
+        let cdab = _mm_shuffle_epi32(dcba, 0xb1);
+        let efgh = _mm_shuffle_epi32(hgfe, 0x1b);
+        let mut abef = _mm_alignr_epi8(cdab, efgh, 8);
+        let mut cdgh = _mm_blend_epi16(efgh, cdab, 0xf0);
+
+        for block in blocks.chunks_exact(64) {
+            let (abef_save, cdgh_save) = (abef, cdgh);
+            let load = |i: usize| {
+                let words = _mm_loadu_si128(block.as_ptr().add(16 * i) as *const __m128i);
+                _mm_shuffle_epi8(words, mask)
+            };
+            let (mut w0, mut w1, mut w2, mut w3) = (load(0), load(1), load(2), load(3));
+            let mut w4;
+
+            rounds4!(abef, cdgh, w0, 0);
+            rounds4!(abef, cdgh, w1, 1);
+            rounds4!(abef, cdgh, w2, 2);
+            rounds4!(abef, cdgh, w3, 3);
+            schedule_rounds4!(abef, cdgh, w0, w1, w2, w3, w4, 4);
+            schedule_rounds4!(abef, cdgh, w1, w2, w3, w4, w0, 5);
+            schedule_rounds4!(abef, cdgh, w2, w3, w4, w0, w1, 6);
+            schedule_rounds4!(abef, cdgh, w3, w4, w0, w1, w2, 7);
+            schedule_rounds4!(abef, cdgh, w4, w0, w1, w2, w3, 8);
+            schedule_rounds4!(abef, cdgh, w0, w1, w2, w3, w4, 9);
+            schedule_rounds4!(abef, cdgh, w1, w2, w3, w4, w0, 10);
+            schedule_rounds4!(abef, cdgh, w2, w3, w4, w0, w1, 11);
+            schedule_rounds4!(abef, cdgh, w3, w4, w0, w1, w2, 12);
+            schedule_rounds4!(abef, cdgh, w4, w0, w1, w2, w3, 13);
+            schedule_rounds4!(abef, cdgh, w0, w1, w2, w3, w4, 14);
+            schedule_rounds4!(abef, cdgh, w1, w2, w3, w4, w0, 15);
+
+            abef = _mm_add_epi32(abef, abef_save);
+            cdgh = _mm_add_epi32(cdgh, cdgh_save);
+        }
+
+        let feba = _mm_shuffle_epi32(abef, 0x1b);
+        let dchg = _mm_shuffle_epi32(cdgh, 0xb1);
+        let dcba = _mm_blend_epi16(feba, dchg, 0xf0);
+        let hgef = _mm_alignr_epi8(dchg, feba, 8);
+        _mm_storeu_si128(state.as_mut_ptr() as *mut __m128i, dcba);
+        _mm_storeu_si128(state.as_mut_ptr().add(4) as *mut __m128i, hgef);
+    }
+}
+
+/// Compress whole 64 byte blocks with the fastest path the CPU supports
+fn compress_blocks(h: &mut [u32; 8], blocks: &[u8]) {
+    debug_assert!(blocks.len().is_multiple_of(64));
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if shani::available() {
+        // SAFETY: the required CPU features were just detected
+        unsafe { shani::compress_blocks(h, blocks) };
+        return;
+    }
+    for chunk in blocks.chunks_exact(64) {
+        compress_unrolled(h, chunk);
+    }
+}
+
+/// Four 32 bit lanes, one per message of a 4-way hash
+type Lanes = [u32; 4];
+
+fn lanes(f: impl FnMut(usize) -> u32) -> Lanes {
+    core::array::from_fn(f)
+}
+
+/// Compress one block of each of four messages in lockstep. Every operation is
+/// applied lane-wise, which the compiler turns into SIMD instructions.
+fn compress_4way(h: &mut [[u32; 8]; 4], chunks: [&[u8]; 4]) {
+    let mut w = [[0u32; 4]; 64];
+    for (t, w) in w.iter_mut().take(16).enumerate() {
+        *w = lanes(|l| u32::from_be_bytes(chunks[l][4 * t..4 * t + 4].try_into().unwrap()));
+    }
+    for t in 16..64 {
+        w[t] = lanes(|l| {
+            sig1(w[t - 2][l])
+                .wrapping_add(w[t - 7][l])
+                .wrapping_add(sig0(w[t - 15][l]))
+                .wrapping_add(w[t - 16][l])
+        });
+    }
+
+    let mut v: [Lanes; 8] = core::array::from_fn(|i| lanes(|l| h[l][i]));
+    for (k, w) in K.iter().zip(w) {
+        let [a, b, c, d, e, f, g, h7] = v;
+        let t1 = lanes(|l| {
+            h7[l]
+                .wrapping_add(capsig1(e[l]))
+                .wrapping_add(ch(e[l], f[l], g[l]))
+                .wrapping_add(*k)
+                .wrapping_add(w[l])
+        });
+        let t2 = lanes(|l| capsig0(a[l]).wrapping_add(maj(a[l], b[l], c[l])));
+        v = [
+            lanes(|l| t1[l].wrapping_add(t2[l])),
+            a,
+            b,
+            c,
+            lanes(|l| d[l].wrapping_add(t1[l])),
+            e,
+            f,
+            g,
+        ];
+    }
+
+    for (l, h) in h.iter_mut().enumerate() {
+        for (h, v) in h.iter_mut().zip(v) {
+            *h = h.wrapping_add(v[l]);
+        }
+    }
+}
+
 pub fn sha256(mut b: Vec<u8>) -> Vec<u8> {
-    b = pad(b);
+    b.extend(padding(b.len() as u64));
     let mut h = H0;
+    compress_blocks(&mut h, &b);
+    h.iter().flat_map(|&x| x.to_be_bytes()).collect()
+}
 
+/// SHA-256 using only the reference compression function
+pub fn sha256_reference(mut b: Vec<u8>) -> Vec<u8> {
+    b.extend(padding(b.len() as u64));
+    let mut h = H0;
     for chunk in b.chunks(64) {
-        let mut w = [0u32; 64];
-        for t in 0..16 {
-            w[t] = u32::from_be_bytes([
-                chunk[4 * t],
-                chunk[4 * t + 1],
-                chunk[4 * t + 2],
-                chunk[4 * t + 3],
-            ]);
+        compress_reference(&mut h, chunk);
+    }
+    h.iter().flat_map(|&x| x.to_be_bytes()).collect()
+}
+
+/// Hash four messages at once, compressing the blocks they have in common
+/// side by side. Fastest when the messages have similar lengths, like the
+/// transactions of a block.
+pub fn sha256_4way(messages: [&[u8]; 4]) -> [[u8; 32]; 4] {
+    let padded = messages.map(|m| {
+        let mut b = m.to_vec();
+        b.extend(padding(m.len() as u64));
+        b
+    });
+    let common = padded.iter().map(|b| b.len()).min().unwrap() / 64;
+    let mut h = [H0; 4];
+    for i in 0..common {
+        let chunks = core::array::from_fn(|l| &padded[l][64 * i..64 * (i + 1)]);
+        compress_4way(&mut h, chunks);
+    }
+    // whatever is left of the longer messages goes one at a time
+    core::array::from_fn(|l| {
+        compress_blocks(&mut h[l], &padded[l][64 * common..]);
+        let mut digest = [0u8; 32];
+        for (bytes, word) in digest.chunks_mut(4).zip(h[l]) {
+            bytes.copy_from_slice(&word.to_be_bytes());
         }
-        for t in 16..64 {
-            w[t] = sig1(w[t - 2])
-                .wrapping_add(w[t - 7])
-                .wrapping_add(sig0(w[t - 15]))
-                .wrapping_add(w[t - 16]);
+        digest
+    })
+}
+
+/// Hash many messages, four at a time unless the CPU has SHA instructions,
+/// which beat the portable 4-way code hashing one message at a time
+pub fn sha256_many(messages: &[&[u8]]) -> Vec<[u8; 32]> {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if shani::available() {
+        return messages.iter().map(|m| Sha256::digest(m)).collect();
+    }
+    let mut chunks = messages.chunks_exact(4);
+    let mut digests = Vec::with_capacity(messages.len());
+    for group in &mut chunks {
+        digests.extend(sha256_4way([group[0], group[1], group[2], group[3]]));
+    }
+    digests.extend(chunks.remainder().iter().map(|m| Sha256::digest(m)));
+    digests
+}
+
+/// Double SHA-256 of many messages, e.g. the txids of a block
+pub fn hash256_many(messages: &[&[u8]]) -> Vec<[u8; 32]> {
+    let first = sha256_many(messages);
+    sha256_many(&first.iter().map(|d| d.as_slice()).collect::<Vec<_>>())
+}
+
+/// Incremental SHA-256, for hashing data that arrives in pieces
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// input not yet compressed, always less than a block
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256 {
+            state: H0,
+            buffer: Vec::with_capacity(64),
+            length: 0,
         }
+    }
 
-        let mut a = h[0];
-        let mut b = h[1];
-        let mut c = h[2];
-        let mut d = h[3];
-        let mut e = h[4];
-        let mut f = h[5];
-        let mut g = h[6];
-        let mut h7 = h[7];
-
-        for t in 0..64 {
-            let t1 = h7
-                .wrapping_add(capsig1(e))
-                .wrapping_add(ch(e, f, g))
-                .wrapping_add(K[t])
-                .wrapping_add(w[t]);
-            let t2 = capsig0(a).wrapping_add(maj(a, b, c));
-            h7 = g;
-            g = f;
-            f = e;
-            e = d.wrapping_add(t1);
-            d = c;
-            c = b;
-            b = a;
-            a = t1.wrapping_add(t2);
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.buffer.is_empty() {
+            let take = data.len().min(64 - self.buffer.len());
+            self.buffer.extend(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            compress_blocks(&mut self.state, &self.buffer);
+            self.buffer.clear();
+        }
+        let whole = data.len() - data.len() % 64;
+        compress_blocks(&mut self.state, &data[..whole]);
+        self.buffer.extend(&data[whole..]);
+    }
+
+    /// Hash a whole message in one go
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn finalize(mut self) -> [u8; 32] {
+        let mut tail = core::mem::take(&mut self.buffer);
+        tail.extend(padding(self.length));
+        compress_blocks(&mut self.state, &tail);
+        let mut digest = [0u8; 32];
+        for (bytes, word) in digest.chunks_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drop-in for the RustCrypto `sha2` crate in generic code
+#[cfg(feature = "digest")]
+mod digest_impl {
+    use digest::consts::{U32, U64};
+    use digest::core_api::BlockSizeUser;
+    use digest::{FixedOutput, HashMarker, Output, OutputSizeUser, Reset, Update};
+
+    use super::Sha256;
+
+    impl HashMarker for Sha256 {}
+
+    impl OutputSizeUser for Sha256 {
+        type OutputSize = U32;
+    }
+
+    impl BlockSizeUser for Sha256 {
+        type BlockSize = U64;
+    }
+
+    impl Update for Sha256 {
+        fn update(&mut self, data: &[u8]) {
+            Sha256::update(self, data);
         }
+    }
 
-        h[0] = h[0].wrapping_add(a);
-        h[1] = h[1].wrapping_add(b);
-        h[2] = h[2].wrapping_add(c);
-        h[3] = h[3].wrapping_add(d);
-        h[4] = h[4].wrapping_add(e);
-        h[5] = h[5].wrapping_add(f);
-        h[6] = h[6].wrapping_add(g);
-        h[7] = h[7].wrapping_add(h7); // Update h[7] with h7
+    impl FixedOutput for Sha256 {
+        fn finalize_into(self, out: &mut Output<Self>) {
+            out.copy_from_slice(&Sha256::finalize(self));
+        }
     }
 
+    impl Reset for Sha256 {
+        fn reset(&mut self) {
+            *self = Sha256::new();
+        }
+    }
+}
+
+/// Length extension: resume hashing from the digest of some unknown message
+/// of `length` bytes, giving sha256(message || padding(length) || suffix)
+/// without knowing the message. The digest is the entire internal state, which
+/// is why sha256(secret || data) is no MAC, use HMAC instead.
+pub fn sha256_from_state(state: &[u8], length: u64, suffix: &[u8]) -> Vec<u8> {
+    assert_eq!(state.len(), 32, "the state is a 32 byte digest");
+    let mut h = [0u32; 8];
+    for (h, word) in h.iter_mut().zip(state.chunks(4)) {
+        *h = u32::from_be_bytes(word.try_into().unwrap());
+    }
+
+    // the blocks hashed so far, message and padding
+    let processed = length + padding(length).len() as u64;
+    let mut b = suffix.to_vec();
+    b.extend(padding(processed + suffix.len() as u64));
+    compress_blocks(&mut h, &b);
     h.iter().flat_map(|&x| x.to_be_bytes()).collect()
 }
 
+/// Brute force two messages `prefix || counter` whose digests agree in the
+/// first `bits` bits. By the birthday bound this takes around 2^(bits / 2)
+/// hashes, which is why a 256 bit hash gives only 128 bits of collision
+/// resistance.
+pub fn find_partial_collision(prefix: &[u8], bits: u32) -> (Vec<u8>, Vec<u8>) {
+    assert!(bits <= 64, "truncate to at most 64 bits");
+    let truncate = |digest: &[u8]| {
+        let top = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        top.checked_shr(64 - bits).unwrap_or(0)
+    };
+
+    let mut seen = BTreeMap::new();
+    for counter in 0u64.. {
+        let mut message = prefix.to_vec();
+        message.extend(counter.to_be_bytes());
+        let truncated = truncate(&sha256(message.clone()));
+        if let Some(other) = seen.insert(truncated, message.clone()) {
+            return (other, message);
+        }
+    }
+    unreachable!()
+}
+
 // Double SHA-256 hash for transaction Ids
 pub fn hash256(input: Vec<u8>) -> Vec<u8> {
     sha256(sha256(input))
@@ -145,3 +554,96 @@ fn test_sha256() {
         assert_eq!(gt.as_slice(), yolo.as_slice());
     }
 }
+
+#[test]
+fn test_compress_paths_match_reference() {
+    // lengths around the block and padding boundaries
+    for length in [0, 1, 55, 56, 63, 64, 65, 119, 120, 128, 1000] {
+        let message: Vec<u8> = (0..length).map(|i| (i * 7 + 3) as u8).collect();
+        let expected = sha256_reference(message.clone());
+        assert_eq!(sha256(message.clone()), expected);
+
+        let mut padded = message.clone();
+        padded.extend(padding(length as u64));
+        let mut h = H0;
+        for chunk in padded.chunks(64) {
+            compress_unrolled(&mut h, chunk);
+        }
+        let unrolled: Vec<u8> = h.iter().flat_map(|x| x.to_be_bytes()).collect();
+        assert_eq!(unrolled, expected);
+    }
+}
+
+#[test]
+fn test_sha256_4way() {
+    let messages: Vec<Vec<u8>> = [0, 64, 200, 1000, 3, 70, 70]
+        .iter()
+        .map(|&length| vec![length as u8; length])
+        .collect();
+    let digests = sha256_4way([&messages[0], &messages[1], &messages[2], &messages[3]]);
+    for (digest, message) in digests.iter().zip(&messages) {
+        assert_eq!(digest.to_vec(), sha256(message.clone()));
+    }
+
+    let refs: Vec<&[u8]> = messages.iter().map(|m| m.as_slice()).collect();
+    for (digest, message) in hash256_many(&refs).iter().zip(&messages) {
+        assert_eq!(digest.to_vec(), hash256(message.clone()));
+    }
+}
+
+#[test]
+fn test_length_extension() {
+    let secret = b"super secret key".to_vec();
+    let data = b"amount=100".to_vec();
+    let mut message = secret.clone();
+    message.extend(&data);
+    let mac = sha256(message.clone());
+
+    // knowing only the mac and the message length
+    let suffix = b"&amount=1000000";
+    let forged = sha256_from_state(&mac, message.len() as u64, suffix);
+
+    message.extend(padding(message.len() as u64));
+    message.extend(suffix);
+    assert_eq!(forged, sha256(message));
+}
+
+#[test]
+fn test_partial_collision() {
+    let (a, b) = find_partial_collision(b"cryptos", 20);
+    assert_ne!(a, b);
+    assert_eq!(sha256(a)[..2], sha256(b.clone())[..2]);
+}
+
+#[test]
+fn test_sha256_incremental() {
+    let message =
+        b"a longer message to make sure that a larger number of blocks works okay too".repeat(3);
+    // pieces that both fill the buffer and skip past it
+    for piece_size in [1, 7, 64, 100] {
+        let mut hasher = Sha256::new();
+        for piece in message.chunks(piece_size) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finalize().to_vec(), sha256(message.clone()));
+    }
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn test_digest_trait() {
+    use digest::Digest;
+
+    fn hash<D: Digest>(data: &[u8]) -> Vec<u8> {
+        let mut hasher = D::new();
+        hasher.update(data);
+        hasher.finalize().to_vec()
+    }
+
+    let data = b"generic over digest::Digest";
+    assert_eq!(hash::<Sha256>(data), hash::<sha2::Sha256>(data));
+    assert_eq!(
+        hash::<crate::ripemd160::Ripemd160>(data).as_slice(),
+        crate::ripemd160::ripemd160(data)
+    );
+}
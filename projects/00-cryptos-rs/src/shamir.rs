@@ -0,0 +1,186 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+// Shamir's secret sharing, byte by byte over GF(256). Each byte of the secret
+// is the constant term of a random polynomial of degree threshold - 1, and
+// share i holds the polynomials evaluated at x = i. Any `threshold` shares pin
+// the polynomials down again by Lagrange interpolation, fewer leave every
+// secret equally likely. The field is the AES one, reduced by
+// x^8 + x^4 + x^3 + x + 1, the same as SLIP-39 uses. Nothing here can tell a
+// wrong or tampered share from a good one, combining them just gives a
+// different secret, so store something to check the result against.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    /// The x coordinate, never 0 which is where the secret sits
+    pub index: u8,
+    pub value: Vec<u8>,
+}
+
+/// Multiplication in GF(256), carry-less and reduced by 0x11b
+pub fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 == 1 {
+            product ^= a;
+        }
+        // a * x, folding x^8 back in
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+/// Inverse in GF(256), a^254 since a^255 = 1
+pub fn gf_inv(a: u8) -> u8 {
+    assert!(a != 0, "zero has no inverse");
+    let mut result = 1;
+    let mut power = a;
+    for bit in 0..8 {
+        if 254 >> bit & 1 == 1 {
+            result = gf_mul(result, power);
+        }
+        power = gf_mul(power, power);
+    }
+    result
+}
+
+/// The value at `x` of the polynomials through the points of `shares`, which
+/// must have distinct indices and values of the same length
+pub fn interpolate(shares: &[(u8, &[u8])], x: u8) -> Vec<u8> {
+    assert!(!shares.is_empty(), "need at least one point");
+    let len = shares[0].1.len();
+    assert!(
+        shares.iter().all(|(_, value)| value.len() == len),
+        "values must all have the same length"
+    );
+    if let Some((_, value)) = shares.iter().find(|(index, _)| *index == x) {
+        return value.to_vec();
+    }
+    let mut result = vec![0u8; len];
+    for (i, (xi, value)) in shares.iter().enumerate() {
+        // the Lagrange basis polynomial of xi at x, subtraction is xor
+        let mut basis = 1;
+        for (j, (xj, _)) in shares.iter().enumerate() {
+            if i != j {
+                assert!(xi != xj, "indices must be distinct");
+                basis = gf_mul(basis, gf_mul(x ^ xj, gf_inv(xi ^ xj)));
+            }
+        }
+        for (out, &byte) in result.iter_mut().zip(value.iter()) {
+            *out ^= gf_mul(basis, byte);
+        }
+    }
+    result
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which recover it
+#[cfg(feature = "rand")]
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Vec<Share> {
+    split_with_rng(secret, threshold, count, &mut rand::thread_rng())
+}
+
+/// Like `split` with the polynomials drawn from `rng`
+#[cfg(feature = "rand")]
+pub fn split_with_rng<R: Rng + ?Sized>(
+    secret: &[u8],
+    threshold: u8,
+    count: u8,
+    rng: &mut R,
+) -> Vec<Share> {
+    assert!(
+        threshold >= 1 && threshold <= count,
+        "threshold must be between 1 and the share count"
+    );
+    // per byte of the secret, the coefficients of x^1 to x^(threshold - 1)
+    let coefficients: Vec<Vec<u8>> = secret
+        .iter()
+        .map(|_| (1..threshold).map(|_| rng.gen()).collect())
+        .collect();
+    (1..=count)
+        .map(|index| {
+            let value = secret
+                .iter()
+                .zip(&coefficients)
+                .map(|(&constant, coefficients)| {
+                    // Horner's rule from the highest power down
+                    coefficients
+                        .iter()
+                        .rev()
+                        .chain([&constant])
+                        .fold(0, |acc, &c| gf_mul(acc, index) ^ c)
+                })
+                .collect();
+            Share { index, value }
+        })
+        .collect()
+}
+
+/// The secret behind `shares`, None if there are none, an index repeats, an
+/// index is 0 or the values differ in length. With fewer shares than the
+/// threshold this returns a wrong secret, not None.
+pub fn combine(shares: &[Share]) -> Option<Vec<u8>> {
+    let first = shares.first()?;
+    for (i, share) in shares.iter().enumerate() {
+        if share.index == 0
+            || share.value.len() != first.value.len()
+            || shares[..i].iter().any(|other| other.index == share.index)
+        {
+            return None;
+        }
+    }
+    let points: Vec<(u8, &[u8])> = shares
+        .iter()
+        .map(|share| (share.index, share.value.as_slice()))
+        .collect();
+    Some(interpolate(&points, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_field() {
+        // the example from FIPS 197, {57} * {83} = {c1}
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() {
+        let secret = b"correct horse battery staple".to_vec();
+        let mut rng = StdRng::seed_from_u64(1);
+        let shares = split_with_rng(&secret, 3, 5, &mut rng);
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|share| share.value != secret));
+
+        // every 3 of the 5 shares
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(combine(&subset), Some(secret.clone()));
+                }
+            }
+        }
+        assert_ne!(combine(&shares[..2]), Some(secret.clone()));
+        assert_eq!(combine(&shares), Some(secret.clone()));
+
+        assert_eq!(combine(&[]), None);
+        assert_eq!(combine(&[shares[0].clone(), shares[0].clone()]), None);
+
+        // a threshold of 1 hands everyone the secret
+        let shares = split_with_rng(&secret, 1, 2, &mut rng);
+        assert!(shares.iter().all(|share| share.value == secret));
+    }
+}
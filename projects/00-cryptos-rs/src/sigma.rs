@@ -0,0 +1,340 @@
+use std::slice;
+
+use rand::Rng;
+
+use crate::field::Fn;
+use crate::keys::gen_secret_key_with_rng;
+use crate::ru256::RU256;
+use crate::secp256k1::{point_add, point_mul, Point, SECP256K1};
+use crate::transcript::Transcript;
+
+// Sigma protocols for statements that are linear in secret scalars: the
+// prover knows s1 .. sk with P = sum si Bi for every relation in a set, where
+// the P and B are public points. Knowing a discrete log (P = s G), equality
+// of discrete logs (A = s G and C = s B, the DLEQ proof a mint attaches to a
+// blind signature) and knowing a representation (P = a G + b H, an opening
+// of a Pedersen commitment) are the common cases, and several relations
+// sharing secrets prove they're the same secret, which is all the credential
+// proofs need. The three moves, commit to random nonces, get a challenge,
+// respond with nonce - challenge * secret, are made non-interactive with
+// Fiat-Shamir: the relations and the nonce commitments go into a transcript
+// the caller started for its protocol, and the challenge comes out of it, so
+// a proof for one protocol or context never verifies in another.
+
+/// A public point that's a linear combination of secrets, lhs = sum
+/// secrets[i] * base over the terms (i, base)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+    pub lhs: Point,
+    pub terms: Vec<(usize, Point)>,
+}
+
+impl Relation {
+    pub fn new(lhs: Point, terms: Vec<(usize, Point)>) -> Self {
+        Relation { lhs, terms }
+    }
+
+    /// sum scalars[i] * base
+    fn combine(&self, scalars: &[Fn]) -> Point {
+        let infinity = Point {
+            x: RU256::zero(),
+            y: RU256::zero(),
+        };
+        self.terms.iter().fold(infinity, |sum, (i, base)| {
+            point_add(&sum, &point_mul(&scalars[*i], base))
+        })
+    }
+}
+
+/// A non-interactive proof of knowledge of the secrets of a set of relations
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proof {
+    pub challenge: Fn,
+    /// One per secret
+    pub responses: Vec<Fn>,
+}
+
+/// How many secrets the relations are over, one past the highest index
+fn witnesses(relations: &[Relation]) -> usize {
+    relations
+        .iter()
+        .flat_map(|r| &r.terms)
+        .map(|(i, _)| i + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The challenge for the relations and the prover's nonce commitments
+fn challenge(transcript: &mut Transcript, relations: &[Relation], commitments: &[Point]) -> Fn {
+    transcript.append_u64(b"secrets", witnesses(relations) as u64);
+    transcript.append_u64(b"relations", relations.len() as u64);
+    for relation in relations {
+        transcript.append_point(b"lhs", &relation.lhs);
+        for (i, base) in &relation.terms {
+            transcript.append_u64(b"secret", *i as u64);
+            transcript.append_point(b"base", base);
+        }
+    }
+    for commitment in commitments {
+        transcript.append_point(b"commitment", commitment);
+    }
+    transcript.challenge_scalar(b"challenge")
+}
+
+impl Proof {
+    /// Prove knowledge of `secrets` satisfying every relation, which the
+    /// caller is trusted to have gotten right. The verifier has to bring a
+    /// transcript in the same state.
+    pub fn prove<R: Rng + ?Sized>(
+        transcript: &mut Transcript,
+        relations: &[Relation],
+        secrets: &[Fn],
+        rng: &mut R,
+    ) -> Self {
+        assert_eq!(secrets.len(), witnesses(relations), "a secret per index");
+        let n = SECP256K1::n();
+        let nonces: Vec<Fn> = secrets
+            .iter()
+            .map(|_| Fn::new(&gen_secret_key_with_rng(&n, rng)))
+            .collect();
+        let commitments: Vec<Point> = relations.iter().map(|r| r.combine(&nonces)).collect();
+        let challenge = challenge(transcript, relations, &commitments);
+        let responses = nonces
+            .into_iter()
+            .zip(secrets)
+            .map(|(nonce, secret)| nonce - challenge.clone() * secret.clone())
+            .collect();
+        Proof {
+            challenge,
+            responses,
+        }
+    }
+
+    /// Rebuild each commitment as sum responses[i] * base + challenge * lhs
+    /// and check they hash to the challenge. There has to be a response for
+    /// each secret and no more.
+    pub fn verify(&self, transcript: &mut Transcript, relations: &[Relation]) -> bool {
+        if self.responses.len() != witnesses(relations) {
+            return false;
+        }
+        let commitments: Vec<Point> = relations
+            .iter()
+            .map(|r| {
+                point_add(
+                    &r.combine(&self.responses),
+                    &point_mul(&self.challenge, &r.lhs),
+                )
+            })
+            .collect();
+        challenge(transcript, relations, &commitments) == self.challenge
+    }
+}
+
+/// point = secret * base
+pub fn dlog_relations(base: &Point, point: &Point) -> Vec<Relation> {
+    vec![Relation::new(point.clone(), vec![(0, base.clone())])]
+}
+
+pub fn prove_dlog<R: Rng + ?Sized>(
+    transcript: &mut Transcript,
+    secret: &Fn,
+    base: &Point,
+    point: &Point,
+    rng: &mut R,
+) -> Proof {
+    Proof::prove(
+        transcript,
+        &dlog_relations(base, point),
+        slice::from_ref(secret),
+        rng,
+    )
+}
+
+pub fn verify_dlog(
+    transcript: &mut Transcript,
+    proof: &Proof,
+    base: &Point,
+    point: &Point,
+) -> bool {
+    proof.verify(transcript, &dlog_relations(base, point))
+}
+
+/// point1 = secret * base1 and point2 = secret * base2
+pub fn dleq_relations(
+    base1: &Point,
+    point1: &Point,
+    base2: &Point,
+    point2: &Point,
+) -> Vec<Relation> {
+    vec![
+        Relation::new(point1.clone(), vec![(0, base1.clone())]),
+        Relation::new(point2.clone(), vec![(0, base2.clone())]),
+    ]
+}
+
+pub fn prove_dleq<R: Rng + ?Sized>(
+    transcript: &mut Transcript,
+    secret: &Fn,
+    (base1, point1): (&Point, &Point),
+    (base2, point2): (&Point, &Point),
+    rng: &mut R,
+) -> Proof {
+    let relations = dleq_relations(base1, point1, base2, point2);
+    Proof::prove(transcript, &relations, slice::from_ref(secret), rng)
+}
+
+pub fn verify_dleq(
+    transcript: &mut Transcript,
+    proof: &Proof,
+    (base1, point1): (&Point, &Point),
+    (base2, point2): (&Point, &Point),
+) -> bool {
+    proof.verify(transcript, &dleq_relations(base1, point1, base2, point2))
+}
+
+/// point = sum secrets[i] * bases[i]
+pub fn representation_relations(bases: &[Point], point: &Point) -> Vec<Relation> {
+    let terms = bases.iter().cloned().enumerate().collect();
+    vec![Relation::new(point.clone(), terms)]
+}
+
+pub fn prove_representation<R: Rng + ?Sized>(
+    transcript: &mut Transcript,
+    secrets: &[Fn],
+    bases: &[Point],
+    point: &Point,
+    rng: &mut R,
+) -> Proof {
+    assert_eq!(secrets.len(), bases.len(), "a secret per base");
+    Proof::prove(
+        transcript,
+        &representation_relations(bases, point),
+        secrets,
+        rng,
+    )
+}
+
+pub fn verify_representation(
+    transcript: &mut Transcript,
+    proof: &Proof,
+    bases: &[Point],
+    point: &Point,
+) -> bool {
+    proof.verify(transcript, &representation_relations(bases, point))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn transcript() -> Transcript {
+        Transcript::new(b"ecash-course/sigma/test")
+    }
+
+    fn scalar(value: u64) -> Fn {
+        Fn::new(&RU256::from_u64(value))
+    }
+
+    #[test]
+    fn test_dlog_and_dleq() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let g = SECP256K1::g();
+        let secret = scalar(0x5ec7e7);
+        let public = point_mul(&secret, &g);
+        let proof = prove_dlog(&mut transcript(), &secret, &g, &public, &mut rng);
+        assert!(verify_dlog(&mut transcript(), &proof, &g, &public));
+        assert!(!verify_dlog(
+            &mut Transcript::new(b"another protocol"),
+            &proof,
+            &g,
+            &public
+        ));
+        assert!(!verify_dlog(&mut transcript(), &proof, &g, &g));
+        // bound to a session the verifier isn't in
+        let mut session = transcript();
+        session.append_u64(b"session", 1);
+        let proof = prove_dlog(&mut session, &secret, &g, &public, &mut rng);
+        assert!(!verify_dlog(&mut transcript(), &proof, &g, &public));
+
+        // a mint's blind signature C = k B with its key K = k G
+        let b = point_mul(&scalar(77), &g);
+        let c = point_mul(&secret, &b);
+        let proof = prove_dleq(
+            &mut transcript(),
+            &secret,
+            (&g, &public),
+            (&b, &c),
+            &mut rng,
+        );
+        assert!(verify_dleq(
+            &mut transcript(),
+            &proof,
+            (&g, &public),
+            (&b, &c)
+        ));
+        // signed with some other key
+        let wrong = point_mul(&scalar(0x5ec7e8), &b);
+        let proof = prove_dleq(
+            &mut transcript(),
+            &secret,
+            (&g, &public),
+            (&b, &wrong),
+            &mut rng,
+        );
+        assert!(!verify_dleq(
+            &mut transcript(),
+            &proof,
+            (&g, &public),
+            (&b, &wrong)
+        ));
+    }
+
+    #[test]
+    fn test_representation() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let g = SECP256K1::g();
+        let h = point_mul(&scalar(3), &g);
+        // a Pedersen commitment to 21 with blinding 1000
+        let secrets = [scalar(21), scalar(1000)];
+        let bases = [g.clone(), h];
+        let commitment = point_add(
+            &point_mul(&secrets[0], &bases[0]),
+            &point_mul(&secrets[1], &bases[1]),
+        );
+        let proof =
+            prove_representation(&mut transcript(), &secrets, &bases, &commitment, &mut rng);
+        assert!(verify_representation(
+            &mut transcript(),
+            &proof,
+            &bases,
+            &commitment
+        ));
+        assert!(!verify_representation(
+            &mut transcript(),
+            &proof,
+            &bases[..1],
+            &commitment
+        ));
+
+        let mut tampered = proof.clone();
+        tampered.responses.push(Fn::one());
+        assert!(!verify_representation(
+            &mut transcript(),
+            &tampered,
+            &bases,
+            &commitment
+        ));
+
+        let mut tampered = proof.clone();
+        tampered.responses[1] = tampered.responses[1].clone() + Fn::one();
+        assert!(!verify_representation(
+            &mut transcript(),
+            &tampered,
+            &bases,
+            &commitment
+        ));
+    }
+}
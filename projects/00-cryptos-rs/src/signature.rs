@@ -1,9 +1,17 @@
-use std::io::{Cursor, Read};
-use std::ops::Mul;
-
-use crate::bitcoin::BITCOIN;
-use crate::keys::{gen_secret_key, PublicKey};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Mul;
+
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+use crate::encoding::{take, Decodable, Encodable};
+use crate::field::{Fn, Fp};
+#[cfg(feature = "rand")]
+use crate::keys::gen_secret_key_with_rng;
+use crate::keys::PublicKey;
 use crate::ru256::RU256;
+use crate::secp256k1::{point_add, Point, SECP256K1};
 use crate::sha256::hash256;
 
 // ECDSA Signature
@@ -13,37 +21,163 @@ pub struct Signature {
     pub s: RU256,
 }
 
+/// Why `Signature::from_der` rejected an encoding
+#[derive(Debug, Clone, PartialEq)]
+pub enum DerError {
+    /// Shorter or longer than any valid signature
+    Length(usize),
+    /// Not a sequence of two integers
+    Tag,
+    /// A length byte disagrees with the actual length
+    LengthMismatch,
+    /// An integer without any bytes
+    ZeroLength,
+    /// An integer with the sign bit set
+    Negative,
+    /// An integer with a needless leading zero byte
+    NonMinimal,
+    /// An integer above 2^256
+    Overflow,
+}
+
+impl Signature {
+    /// Strict DER parsing as required by BIP66: exact lengths, no negative
+    /// integers and no padding. Every signature has exactly one encoding this
+    /// accepts, the one `encode` produces.
+    pub fn from_der(der: &[u8]) -> Result<Self, DerError> {
+        // 0x30 [total length] 0x02 [r length] [r] 0x02 [s length] [s]
+        if !(8..=72).contains(&der.len()) {
+            return Err(DerError::Length(der.len()));
+        }
+        if der[0] != 0x30 {
+            return Err(DerError::Tag);
+        }
+        if der[1] as usize != der.len() - 2 {
+            return Err(DerError::LengthMismatch);
+        }
+        let rlength = der[3] as usize;
+        if 5 + rlength >= der.len() {
+            return Err(DerError::LengthMismatch);
+        }
+        let slength = der[5 + rlength] as usize;
+        if rlength + slength + 6 != der.len() {
+            return Err(DerError::LengthMismatch);
+        }
+
+        fn integer(tag: u8, n: &[u8]) -> Result<RU256, DerError> {
+            if tag != 0x02 {
+                return Err(DerError::Tag);
+            }
+            match n {
+                [] => Err(DerError::ZeroLength),
+                [first, ..] if first & 0x80 != 0 => Err(DerError::Negative),
+                // a leading zero is only allowed to keep the sign bit clear
+                [0x00, second, ..] if second & 0x80 == 0 => Err(DerError::NonMinimal),
+                _ => {
+                    let n = n.strip_prefix(&[0x00]).unwrap_or(n);
+                    if n.len() > 32 {
+                        return Err(DerError::Overflow);
+                    }
+                    Ok(RU256::from_bytes(n))
+                }
+            }
+        }
+
+        let r = integer(der[2], &der[4..4 + rlength])?;
+        let s = integer(der[4 + rlength], &der[6 + rlength..])?;
+        Ok(Signature { r, s })
+    }
+}
+
 impl Signature {
-    pub fn decode(der: &[u8]) -> Self {
-        let mut s = Cursor::new(der);
-        let mut byte = [0u8; 1];
-        s.read_exact(&mut byte).unwrap();
-        assert_eq!(byte[0], 0x30);
-        s.read_exact(&mut byte).unwrap();
-        let length = byte[0];
-        assert_eq!(length as usize, der.len() - 2);
-        s.read_exact(&mut byte).unwrap();
-        assert_eq!(byte[0], 0x02);
-        s.read_exact(&mut byte).unwrap();
-        let rlength = byte[0];
-        let mut r = vec![0; rlength as usize];
-        s.read_exact(&mut r).unwrap();
-        let r = RU256::from_bytes(&r);
-        s.read_exact(&mut byte).unwrap();
-        assert_eq!(byte[0], 0x02);
-        s.read_exact(&mut byte).unwrap();
-        let slength = byte[0];
-        let mut s_vec = vec![0; slength as usize];
-        s.read_exact(&mut s_vec).unwrap();
-        let s = RU256::from_bytes(&s_vec);
-        assert_eq!(der.len(), 6 + rlength as usize + slength as usize);
-        Signature { r, s }
-    }
-
-    pub fn encode(&self) -> Vec<u8> {
+    /// `r || s` as 32-byte big endian integers, the fixed size form used by
+    /// BIP340 and most protocols outside of Bitcoin transactions
+    pub fn to_compact(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        self.r.to_bytes(&mut bytes[..32]);
+        self.s.to_bytes(&mut bytes[32..]);
+        bytes
+    }
+
+    pub fn from_compact(bytes: &[u8; 64]) -> Self {
+        Signature {
+            r: RU256::from_bytes(&bytes[..32]),
+            s: RU256::from_bytes(&bytes[32..]),
+        }
+    }
+}
+
+/// An ECDSA signature and its recovery id, which picks the signer's public key
+/// out of the up to four keys the signature is valid for
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoverableSignature {
+    pub sig: Signature,
+    pub recovery_id: u8,
+}
+
+impl RecoverableSignature {
+    /// `r || s || recovery id`
+    pub fn to_compact(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        bytes[..64].copy_from_slice(&self.sig.to_compact());
+        bytes[64] = self.recovery_id;
+        bytes
+    }
+
+    /// None if the recovery id is out of range
+    pub fn from_compact(bytes: &[u8; 65]) -> Option<Self> {
+        if bytes[64] > 3 {
+            return None;
+        }
+        Some(RecoverableSignature {
+            sig: Signature::from_compact(bytes[..64].try_into().unwrap()),
+            recovery_id: bytes[64],
+        })
+    }
+
+    /// The public key that signed `message`, Q = r^-1 (sR - zG)
+    pub fn recover(&self, message: &[u8]) -> Option<PublicKey> {
+        let n = &SECP256K1::n();
+        let Signature { r, s } = &self.sig;
+        if r.is_zero() || *r >= *n || s.is_zero() || *s >= *n {
+            return None;
+        }
+
+        // rebuild R from its x coordinate, which was r or r + n
+        let x = match self.recovery_id & 2 {
+            0 => r.clone(),
+            _ => RU256 {
+                v: r.v.checked_add(n.v)?,
+            },
+        };
+        #[allow(non_snake_case)]
+        let R = Point::lift_x(&x, self.recovery_id & 1 == 1)?;
+
+        let z = Fn::from_bytes(&hash256(message.to_vec()));
+        let r_inv = Fn::new(r).inv()?;
+        let u1 = -z * r_inv.clone();
+        let u2 = Fn::new(s) * r_inv;
+        // u1G and u2R can be equal or opposite for a crafted signature
+        let q = point_add(
+            &SECP256K1::g().mul(u1.as_ru256().clone()),
+            &R.mul(u2.as_ru256().clone()),
+        );
+        if q.x.is_zero() && q.y.is_zero() {
+            return None;
+        }
+        Some(PublicKey::from_point(q))
+    }
+}
+
+/// DER encoding
+impl Encodable for Signature {
+    fn encode(&self) -> Vec<u8> {
         fn dern(n: &RU256) -> Vec<u8> {
             let mut nb = vec![0u8; 32];
             n.to_bytes(&mut nb);
+            // minimal big endian, keeping a single byte for zero
+            let leading_zeros = nb.iter().take(31).take_while(|&&b| b == 0).count();
+            nb.drain(..leading_zeros);
             if nb[0] >= 0x80 {
                 nb.insert(0, 0x00);
             }
@@ -63,99 +197,191 @@ impl Signature {
     }
 }
 
+/// Strict DER decoding, panics on anything `from_der` rejects
+impl Decodable for Signature {
+    fn decode(bytes: &mut &[u8]) -> Self {
+        let length = 2 + bytes.get(1).copied().unwrap_or(0) as usize;
+        let der = take(bytes, length);
+        Signature::from_der(der).unwrap_or_else(|err| panic!("invalid DER signature: {:?}", err))
+    }
+}
+
+#[cfg(feature = "rand")]
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
 pub fn sign_ecdsa(secret_key: &RU256, message: &[u8]) -> Signature {
-    // Hash the message to sign
-    let z = RU256::from_bytes(&hash256(message.to_vec()));
+    sign_ecdsa_with_rng(secret_key, message, &mut rand::thread_rng())
+}
 
+/// Like `sign_ecdsa` with the nonce drawn from `rng`. A seeded rng makes the
+/// signatures of an exercise reproducible, never use one for real keys.
+#[cfg(feature = "rand")]
+pub fn sign_ecdsa_with_rng<R: Rng + ?Sized>(
+    secret_key: &RU256,
+    message: &[u8],
+    rng: &mut R,
+) -> Signature {
+    sign_ecdsa_recoverable_with_rng(secret_key, message, rng).sig
+}
+
+/// ECDSA signature that also records how to recover the public key from it
+#[cfg(feature = "rand")]
+pub fn sign_ecdsa_recoverable(secret_key: &RU256, message: &[u8]) -> RecoverableSignature {
+    sign_ecdsa_recoverable_with_rng(secret_key, message, &mut rand::thread_rng())
+}
+
+#[cfg(feature = "rand")]
+pub fn sign_ecdsa_recoverable_with_rng<R: Rng + ?Sized>(
+    secret_key: &RU256,
+    message: &[u8],
+    rng: &mut R,
+) -> RecoverableSignature {
     // Generate a random nonce
-    let k = gen_secret_key(&BITCOIN.gen.n);
+    let k = gen_secret_key_with_rng(&SECP256K1::n(), rng);
+    sensitive!(?k, "ecdsa nonce");
+    sign_ecdsa_with_nonce(secret_key, message, &k)
+}
+
+/// ECDSA with a caller chosen nonce `k`. Only for demonstrating attacks, a
+/// nonce that is reused or guessable gives away the secret key.
+pub fn sign_ecdsa_with_nonce(
+    secret_key: &RU256,
+    message: &[u8],
+    k: &RU256,
+) -> RecoverableSignature {
+    // Hash the message to sign
+    let z = Fn::from_bytes(&hash256(message.to_vec()));
 
     // Map the nonce scalar to a point on the SECP256k1 curve using the generator as
     // the base point
     #[allow(non_snake_case)]
-    let R = PublicKey::from_sk(&k);
+    let R = PublicKey::from_sk(k).0;
 
-    // r is the x component of the point
-    let r = R.0.x.clone();
+    // r is the x component of the point, a field element reduced to a scalar
+    let r = Fp::new(&R.x).reduce_to_scalar();
 
-    // Grab the group order
-    let n = &BITCOIN.gen.n;
+    // Compute s = (r * d + z) / k
+    let k_inv = Fn::new(k).inv().expect("nonce must be nonzero");
+    let s = (r.clone() * Fn::new(secret_key) + z) * k_inv;
+    let (r, s) = (r.as_ru256().clone(), s.as_ru256().clone());
 
-    // Compute s
-    let s = (r.clone().mul_mod(secret_key, n).add_mod(&z, n)).div_mod(&k, n);
+    // the parity of R's y, and whether its x was reduced, pick R out of the
+    // (up to four) points with x = r mod n
+    let recovery_id = R.y.v.bit(0) as u8 | (!R.x.ct_eq(&r)).unwrap_u8() << 1;
 
-    Signature { r, s }
+    debug!(?r, ?s, recovery_id, "ecdsa signature");
+    RecoverableSignature {
+        sig: Signature { r, s },
+        recovery_id,
+    }
 }
 
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
 pub fn verify_ecdsa(public_key: &PublicKey, message: &[u8], sig: &Signature) -> bool {
-    // Hash the message
-    let hash = RU256::from_bytes(&hash256(message.to_vec()));
+    // r and s must be scalars in 1..n
+    let (Some(r), Some(s)) = (Fn::from_canonical(&sig.r), Fn::from_canonical(&sig.s)) else {
+        return false;
+    };
+    if r.is_zero() || s.is_zero() {
+        return false;
+    }
 
-    // Grab the group order
-    let n = &BITCOIN.gen.n;
+    // Hash the message
+    let hash = Fn::from_bytes(&hash256(message.to_vec()));
 
     // Calculate w = 1/s mod n
-    let w = RU256::from_bytes(&[1]).div_mod(&sig.s, n);
+    let w = s.inv().unwrap();
 
     // Calculate u1 = hash * w mod n
-    let u1 = hash.mul_mod(&w, n);
+    let u1 = hash * w.clone();
 
     // Calculate u2 = r * w mod n
-    let u2 = sig.r.mul_mod(&w, n);
+    let u2 = r.clone() * w;
 
     // Calculate u1 * G
-    let u1_point = BITCOIN.gen.G.clone().mul(u1);
+    let u1_point = SECP256K1::g().mul(u1.as_ru256().clone());
 
     // Calculate u2 * public_key
-    let u2_point = public_key.0.clone().mul(u2);
-
-    // Calculate the verification point
-    let verification_point = u1_point + u2_point;
-
-    // Check if the x-coordinate of the verification point equals r
-    verification_point.x == sig.r
+    let u2_point = public_key.0.clone().mul(u2.as_ru256().clone());
+
+    // Calculate the verification point. The public key and signature come
+    // from whoever wants them checked, and a key of (hash / r) * G makes the
+    // two points equal, so this needs the complete addition
+    let verification_point = point_add(&u1_point, &u2_point);
+
+    // Check if the x-coordinate of the verification point, as a scalar,
+    // equals r. The point at infinity has x = 0, which never equals r
+    let x = Fp::new(&verification_point.x).reduce_to_scalar();
+    let valid = bool::from(x.as_ru256().ct_eq(r.as_ru256()));
+    debug!(valid, "ecdsa verification");
+    valid
 }
 
+#[cfg(feature = "rand")]
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
 pub fn sign_schnorr(secret_key: &RU256, message: &[u8]) -> Signature {
-    let n = &BITCOIN.gen.n;
+    sign_schnorr_with_rng(secret_key, message, &mut rand::thread_rng())
+}
 
-    let k = gen_secret_key(n);
+/// Like `sign_schnorr` with the nonce drawn from `rng`
+#[cfg(feature = "rand")]
+pub fn sign_schnorr_with_rng<R: Rng + ?Sized>(
+    secret_key: &RU256,
+    message: &[u8],
+    rng: &mut R,
+) -> Signature {
+    let k = gen_secret_key_with_rng(&SECP256K1::n(), rng);
+    sensitive!(?k, "schnorr nonce");
     #[allow(non_snake_case)]
     let R = PublicKey::from_sk(&k);
 
-    let r = R.0.x.clone();
-    let mut bytes_vec = vec![0u8; 32];
-    r.to_bytes(&mut bytes_vec);
-    bytes_vec.extend_from_slice(message);
-    let hashed = hash256(bytes_vec);
-    let e = RU256::from_bytes(&hashed);
-    let s = (k + e * secret_key.clone()) % n.clone();
+    // r stays a field element, it's only ever compared to an x coordinate
+    let r = Fp::new(&R.0.x);
+    let e = schnorr_challenge(&r, message);
+    let s = Fn::new(&k) + e * Fn::new(secret_key);
 
-    Signature { r, s }
+    Signature {
+        r: r.as_ru256().clone(),
+        s: s.as_ru256().clone(),
+    }
 }
 
-pub fn verify_schnorr(public_key: &PublicKey, message: &[u8], sig: &Signature) -> bool {
-    let n = &BITCOIN.gen.n;
+/// e = hash(r || message) as a scalar
+pub(crate) fn schnorr_challenge(r: &Fp, message: &[u8]) -> Fn {
+    let mut bytes_vec = r.to_bytes().to_vec();
+    bytes_vec.extend_from_slice(message);
+    Fn::from_bytes(&hash256(bytes_vec))
+}
 
-    assert!(sig.r >= RU256::from_u64(1) && sig.r < *n);
-    assert!(sig.s >= RU256::from_u64(1) && sig.s < *n);
+pub fn verify_schnorr(public_key: &PublicKey, message: &[u8], sig: &Signature) -> bool {
+    // r is an x coordinate, below p, and s a nonzero scalar below n
+    let (Some(r), Some(s)) = (Fp::from_canonical(&sig.r), Fn::from_canonical(&sig.s)) else {
+        return false;
+    };
+    if r.is_zero() || s.is_zero() {
+        return false;
+    }
 
-    let mut bytes_vec = vec![0u8; 32];
-    sig.r.to_bytes(&mut bytes_vec);
-    bytes_vec.extend_from_slice(message);
-    let hashed = hash256(bytes_vec);
-    let e = RU256::from_bytes(&hashed);
+    let e = schnorr_challenge(&r, message);
     #[allow(non_snake_case)]
     let pubkey_point = &public_key.0;
     #[allow(non_snake_case)]
-    let R = BITCOIN.gen.G.clone().mul(sig.s.clone()) + (-pubkey_point.clone().mul(e));
-
-    R.x == sig.r
+    let R = point_add(
+        &SECP256K1::g().mul(s.as_ru256().clone()),
+        &-pubkey_point.clone().mul(e.as_ru256().clone()),
+    );
+
+    // both sides as field elements, so equal residues compare equal. R at
+    // infinity has x = 0 and r is nonzero
+    bool::from(Fp::new(&R.x).as_ru256().ct_eq(r.as_ru256()))
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
+    use crate::keys::gen_secret_key;
+    use crate::strategies;
 
     #[test]
     fn test_signature_encode_decode() {
@@ -163,7 +389,7 @@ mod tests {
         let s = RU256::from_u64(67890);
         let sig = Signature { r, s };
         let der = sig.encode();
-        let decoded_sig = Signature::decode(&der);
+        let decoded_sig = Signature::decode_all(&der);
         assert_eq!(sig, decoded_sig);
     }
 
@@ -187,7 +413,7 @@ mod tests {
 
     #[test]
     fn test_sign_ecdsa() {
-        let secret_key = gen_secret_key(&BITCOIN.gen.n);
+        let secret_key = gen_secret_key(&SECP256K1::n());
         let message = b"test message";
 
         println!("Secret Key: {:?}", secret_key);
@@ -211,16 +437,45 @@ mod tests {
 
     #[test]
     fn test_verify_ecdsa() {
-        let secret_key = gen_secret_key(&BITCOIN.gen.n);
+        let secret_key = gen_secret_key(&SECP256K1::n());
         let public_key = PublicKey::from_sk(&secret_key);
         let message = b"test message";
         let sig = sign_ecdsa(&secret_key, message);
         assert!(verify_ecdsa(&public_key, message, &sig));
     }
 
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let n = SECP256K1::n();
+        let secret_key = gen_secret_key_with_rng(&n, &mut StdRng::seed_from_u64(7));
+        assert_eq!(
+            secret_key,
+            gen_secret_key_with_rng(&n, &mut StdRng::seed_from_u64(7))
+        );
+        assert_ne!(
+            secret_key,
+            gen_secret_key_with_rng(&n, &mut StdRng::seed_from_u64(8))
+        );
+
+        let message = b"test message";
+        let sig = sign_ecdsa_with_rng(&secret_key, message, &mut StdRng::seed_from_u64(1));
+        assert_eq!(
+            sig,
+            sign_ecdsa_with_rng(&secret_key, message, &mut StdRng::seed_from_u64(1))
+        );
+        assert!(verify_ecdsa(
+            &PublicKey::from_sk(&secret_key),
+            message,
+            &sig
+        ));
+    }
+
     #[test]
     fn test_sign_schnorr() {
-        let secret_key = gen_secret_key(&BITCOIN.gen.n);
+        let secret_key = gen_secret_key(&SECP256K1::n());
         let message = b"test message";
         let sig = sign_schnorr(&secret_key, message);
         assert!(verify_schnorr(
@@ -232,10 +487,138 @@ mod tests {
 
     #[test]
     fn test_verify_schnorr() {
-        let secret_key = gen_secret_key(&BITCOIN.gen.n);
+        let secret_key = gen_secret_key(&SECP256K1::n());
         let public_key = PublicKey::from_sk(&secret_key);
         let message = b"test message";
         let sig = sign_schnorr(&secret_key, message);
         assert!(verify_schnorr(&public_key, message, &sig));
     }
+
+    #[test]
+    fn test_verify_with_colliding_points() {
+        let message = b"test message";
+        let sig = Signature {
+            r: RU256::from_u64(5),
+            s: RU256::from_u64(7),
+        };
+        let (r, s) = (Fn::new(&sig.r), Fn::new(&sig.s));
+
+        // u1 * G == u2 * P for P = (hash / r) * G
+        let hash = Fn::from_bytes(&hash256(message.to_vec()));
+        let k = hash * r.inv().unwrap();
+        let public_key = PublicKey::from_sk(k.as_ru256());
+        assert!(!verify_ecdsa(&public_key, message, &sig));
+
+        // s * G == -(e * P) for P = -(s / e) * G
+        let e = schnorr_challenge(&Fp::new(&sig.r), message);
+        let k = -(s * e.inv().unwrap());
+        let public_key = PublicKey::from_sk(k.as_ru256());
+        assert!(!verify_schnorr(&public_key, message, &sig));
+    }
+
+    #[test]
+    fn test_from_der_rejects_lax_encodings() {
+        let der = Signature {
+            r: RU256::from_u64(0x80),
+            s: RU256::from_u64(1),
+        }
+        .encode();
+        assert_eq!(hex::encode(&der), "3007020200800201 01".replace(' ', ""));
+        assert!(Signature::from_der(&der).is_ok());
+
+        let cases = [
+            // r without its sign padding
+            ("3006020180020101", DerError::Negative),
+            // s padded with a needless zero
+            ("30080202008002020001", DerError::NonMinimal),
+            ("30050201010200", DerError::Length(7)),
+            ("3006020002020101", DerError::ZeroLength),
+            ("300702020080030101", DerError::Tag),
+            ("3107020200800201 01", DerError::Tag),
+            ("3008020200800201 01", DerError::LengthMismatch),
+        ];
+        for (der, err) in cases {
+            let der = hex::decode(der.replace(' ', "")).unwrap();
+            assert_eq!(Signature::from_der(&der), Err(err), "{}", hex::encode(&der));
+        }
+    }
+
+    #[test]
+    fn test_recover_public_key() {
+        // a tiny secret key and nonce keep this to the two multiplications
+        // of recovery
+        let secret_key = RU256::from_u64(3);
+        let message = b"test message";
+        let sig = sign_ecdsa_with_nonce(&secret_key, message, &RU256::from_u64(2));
+
+        let decoded = RecoverableSignature::from_compact(&sig.to_compact()).unwrap();
+        assert_eq!(decoded, sig);
+        assert_eq!(
+            decoded.recover(message),
+            Some(PublicKey::from_sk(&secret_key))
+        );
+
+        let mut bytes = sig.to_compact();
+        bytes[64] = 4;
+        assert_eq!(RecoverableSignature::from_compact(&bytes), None);
+    }
+
+    #[test]
+    fn test_recover_with_colliding_points() {
+        // with R = kG and s = -+z/k, u2R is +-u1G, which doubles or cancels
+        let message = b"test message";
+        let k = RU256::from_u64(2);
+        #[allow(non_snake_case)]
+        let R = PublicKey::from_sk(&k).0;
+        let r = R.x.clone();
+        let z = Fn::from_bytes(&hash256(message.to_vec()));
+        let k_inv = Fn::new(&k).inv().unwrap();
+        let recoverable = |s: Fn| RecoverableSignature {
+            sig: Signature {
+                r: r.clone(),
+                s: s.as_ru256().clone(),
+            },
+            recovery_id: R.y.v.bit(0) as u8,
+        };
+
+        let u1 = -z.clone() * Fn::new(&r).inv().unwrap();
+        assert_eq!(
+            recoverable(-z.clone() * k_inv.clone()).recover(message),
+            Some(PublicKey::from_sk((u1.clone() + u1).as_ru256()))
+        );
+        assert_eq!(recoverable(z * k_inv).recover(message), None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_compact_roundtrip(sig in strategies::signature()) {
+            prop_assert_eq!(Signature::from_compact(&sig.to_compact()), sig);
+        }
+
+        #[test]
+        fn prop_der_roundtrip(sig in strategies::signature()) {
+            prop_assert_eq!(Signature::decode_all(&sig.encode()), sig);
+        }
+
+        #[test]
+        fn prop_from_der_never_panics(der in prop::collection::vec(any::<u8>(), 0..80)) {
+            let _ = Signature::from_der(&der);
+        }
+
+        /// Flipping a byte of a valid encoding must give an error or another
+        /// canonical encoding, never a second encoding of some signature
+        #[test]
+        fn prop_from_der_accepts_only_canonical(
+            sig in strategies::signature(),
+            index in any::<prop::sample::Index>(),
+            byte in any::<u8>(),
+        ) {
+            let mut der = sig.encode();
+            let i = index.index(der.len());
+            der[i] = byte;
+            if let Ok(sig) = Signature::from_der(&der) {
+                prop_assert_eq!(sig.encode(), der);
+            }
+        }
+    }
 }
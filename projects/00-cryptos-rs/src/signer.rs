@@ -0,0 +1,362 @@
+use std::fmt;
+
+use rand::Rng;
+
+use crate::encoding::{Decodable, Encodable};
+use crate::keys::{gen_key_pair_with_rng, PublicKey};
+use crate::ru256::RU256;
+use crate::signature::{self, Signature};
+use crate::transaction::{Script, Tx, SIGHASH_ALL};
+use crate::utils;
+
+// Signing behind a trait, so wallet flows work the same whether the keys are
+// in this process or on a separate device that only ever hands out public
+// keys and signatures. `SoftwareSigner` keeps secret keys in memory,
+// `HardwareSigner` talks to a device over a byte channel with a small framed
+// protocol, and `MockDevice` plays such a device, including the user who has
+// to confirm each transaction on its screen. Keys are picked by index.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignerError {
+    /// The signer has no key with this index
+    UnknownKey(u32),
+    /// The user declined on the device
+    Rejected,
+    /// The channel to the device failed
+    Transport(String),
+    /// The device sent something that isn't a valid response
+    InvalidResponse,
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerError::UnknownKey(key) => write!(f, "no key with index {}", key),
+            SignerError::Rejected => write!(f, "rejected on the device"),
+            SignerError::Transport(reason) => write!(f, "device unreachable: {}", reason),
+            SignerError::InvalidResponse => write!(f, "invalid response from the device"),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+pub trait Signer {
+    fn get_pubkey(&mut self, key: u32) -> Result<PublicKey, SignerError>;
+
+    fn sign_ecdsa(&mut self, key: u32, message: &[u8]) -> Result<Signature, SignerError>;
+
+    fn sign_schnorr(&mut self, key: u32, message: &[u8]) -> Result<Signature, SignerError>;
+
+    /// A SIGHASH_ALL signature of legacy input `input` of `tx` spending
+    /// `script_pubkey`, DER encoded with the sighash type appended as it goes
+    /// in the scriptSig
+    fn sign_tx_input(
+        &mut self,
+        key: u32,
+        tx: &Tx,
+        input: usize,
+        script_pubkey: &Script,
+    ) -> Result<Vec<u8>, SignerError>;
+}
+
+/// Keys held in memory
+#[derive(Debug, Clone)]
+pub struct SoftwareSigner {
+    keys: Vec<RU256>,
+}
+
+impl SoftwareSigner {
+    pub fn new(keys: Vec<RU256>) -> Self {
+        SoftwareSigner { keys }
+    }
+
+    /// A signer with `count` fresh random keys
+    pub fn generate(count: usize) -> Self {
+        SoftwareSigner::generate_with_rng(count, &mut rand::thread_rng())
+    }
+
+    /// Like `generate` with the keys drawn from `rng`
+    pub fn generate_with_rng<R: Rng + ?Sized>(count: usize, rng: &mut R) -> Self {
+        SoftwareSigner::new((0..count).map(|_| gen_key_pair_with_rng(rng).0).collect())
+    }
+
+    fn secret_key(&self, key: u32) -> Result<&RU256, SignerError> {
+        self.keys
+            .get(key as usize)
+            .ok_or(SignerError::UnknownKey(key))
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn get_pubkey(&mut self, key: u32) -> Result<PublicKey, SignerError> {
+        Ok(PublicKey::from_sk(self.secret_key(key)?))
+    }
+
+    fn sign_ecdsa(&mut self, key: u32, message: &[u8]) -> Result<Signature, SignerError> {
+        Ok(signature::sign_ecdsa(self.secret_key(key)?, message))
+    }
+
+    fn sign_schnorr(&mut self, key: u32, message: &[u8]) -> Result<Signature, SignerError> {
+        Ok(signature::sign_schnorr(self.secret_key(key)?, message))
+    }
+
+    fn sign_tx_input(
+        &mut self,
+        key: u32,
+        tx: &Tx,
+        input: usize,
+        script_pubkey: &Script,
+    ) -> Result<Vec<u8>, SignerError> {
+        let message = tx.sig_message(input, script_pubkey);
+        let mut sig = self.sign_ecdsa(key, &message)?.encode();
+        sig.push(SIGHASH_ALL);
+        Ok(sig)
+    }
+}
+
+/// A byte link to a device, like a serial port: one request frame out, one
+/// response frame back
+pub trait Channel {
+    fn exchange(&mut self, frame: &[u8]) -> Result<Vec<u8>, SignerError>;
+}
+
+// Frames are a 2 byte little endian length and then the body. A request body
+// is a command byte, the key index as 4 bytes little endian and the command's
+// payload, a response body a status byte and the result.
+const CMD_GET_PUBKEY: u8 = 0x01;
+const CMD_SIGN_ECDSA: u8 = 0x02;
+const CMD_SIGN_SCHNORR: u8 = 0x03;
+const CMD_SIGN_TX_INPUT: u8 = 0x04;
+
+const STATUS_OK: u8 = 0x00;
+const STATUS_UNKNOWN_KEY: u8 = 0x01;
+const STATUS_REJECTED: u8 = 0x02;
+const STATUS_BAD_REQUEST: u8 = 0x03;
+
+fn frame(body: &[u8]) -> Vec<u8> {
+    let length: u16 = body.len().try_into().expect("frame body too long");
+    let mut out = length.to_le_bytes().to_vec();
+    out.extend(body);
+    out
+}
+
+/// The body of a frame, None unless the length matches
+fn unframe(frame: &[u8]) -> Option<&[u8]> {
+    let (length, body) = frame.split_at_checked(2)?;
+    (u16::from_le_bytes(length.try_into().unwrap()) as usize == body.len()).then_some(body)
+}
+
+/// A signer on the other end of a channel
+#[derive(Debug)]
+pub struct HardwareSigner<C: Channel> {
+    pub channel: C,
+}
+
+impl<C: Channel> HardwareSigner<C> {
+    pub fn new(channel: C) -> Self {
+        HardwareSigner { channel }
+    }
+
+    /// Send a command, returning the result of a successful response
+    fn request(&mut self, command: u8, key: u32, payload: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let mut body = vec![command];
+        body.extend(key.to_le_bytes());
+        body.extend(payload);
+        let response = self.channel.exchange(&frame(&body))?;
+        let body = unframe(&response).ok_or(SignerError::InvalidResponse)?;
+        match body.split_first() {
+            Some((&STATUS_OK, result)) => Ok(result.to_vec()),
+            Some((&STATUS_UNKNOWN_KEY, _)) => Err(SignerError::UnknownKey(key)),
+            Some((&STATUS_REJECTED, _)) => Err(SignerError::Rejected),
+            _ => Err(SignerError::InvalidResponse),
+        }
+    }
+
+    fn request_signature(
+        &mut self,
+        command: u8,
+        key: u32,
+        message: &[u8],
+    ) -> Result<Signature, SignerError> {
+        let result = self.request(command, key, message)?;
+        let compact = result
+            .try_into()
+            .map_err(|_| SignerError::InvalidResponse)?;
+        Ok(Signature::from_compact(&compact))
+    }
+}
+
+impl<C: Channel> Signer for HardwareSigner<C> {
+    fn get_pubkey(&mut self, key: u32) -> Result<PublicKey, SignerError> {
+        let sec = self.request(CMD_GET_PUBKEY, key, &[])?;
+        PublicKey::try_from_bytes(&sec).ok_or(SignerError::InvalidResponse)
+    }
+
+    fn sign_ecdsa(&mut self, key: u32, message: &[u8]) -> Result<Signature, SignerError> {
+        self.request_signature(CMD_SIGN_ECDSA, key, message)
+    }
+
+    fn sign_schnorr(&mut self, key: u32, message: &[u8]) -> Result<Signature, SignerError> {
+        self.request_signature(CMD_SIGN_SCHNORR, key, message)
+    }
+
+    /// The device gets the whole transaction rather than a hash, so it can
+    /// show the outputs to the user before signing
+    fn sign_tx_input(
+        &mut self,
+        key: u32,
+        tx: &Tx,
+        input: usize,
+        script_pubkey: &Script,
+    ) -> Result<Vec<u8>, SignerError> {
+        let mut payload = (input as u32).to_le_bytes().to_vec();
+        payload.extend(script_pubkey.encode());
+        payload.extend(tx.encode());
+        let sig = self.request(CMD_SIGN_TX_INPUT, key, &payload)?;
+        match sig.split_last() {
+            Some((&SIGHASH_ALL, der)) if Signature::from_der(der).is_ok() => Ok(sig),
+            _ => Err(SignerError::InvalidResponse),
+        }
+    }
+}
+
+/// A pretend hardware wallet answering frames with an in-memory signer
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    signer: SoftwareSigner,
+    /// Whether the user confirms transactions shown on the device
+    pub approve: bool,
+    /// Outputs shown on the screen for the last transaction, as
+    /// `(address or script hex, sats)`
+    pub screen: Vec<(String, u64)>,
+}
+
+impl MockDevice {
+    pub fn new(signer: SoftwareSigner) -> Self {
+        MockDevice {
+            signer,
+            approve: true,
+            screen: vec![],
+        }
+    }
+
+    /// Run one request body, returning the response body
+    fn handle(&mut self, body: &[u8]) -> Vec<u8> {
+        let Some((&command, rest)) = body.split_first() else {
+            return vec![STATUS_BAD_REQUEST];
+        };
+        let Some((key, mut payload)) = rest.split_at_checked(4) else {
+            return vec![STATUS_BAD_REQUEST];
+        };
+        let key = u32::from_le_bytes(key.try_into().unwrap());
+        let result = match command {
+            CMD_GET_PUBKEY => self.signer.get_pubkey(key).map(|pk| pk.sec(true, false)),
+            CMD_SIGN_ECDSA => self
+                .signer
+                .sign_ecdsa(key, payload)
+                .map(|sig| sig.to_compact().to_vec()),
+            CMD_SIGN_SCHNORR => self
+                .signer
+                .sign_schnorr(key, payload)
+                .map(|sig| sig.to_compact().to_vec()),
+            CMD_SIGN_TX_INPUT => {
+                // a real device would parse defensively, here a malformed
+                // transaction is a bug in HardwareSigner
+                let input = utils::read_u32(&mut payload).unwrap() as usize;
+                let script_pubkey = Script::decode(&mut payload);
+                let tx = Tx::decode_all(payload);
+                if input >= tx.tx_ins.len() {
+                    return vec![STATUS_BAD_REQUEST];
+                }
+                self.screen = tx
+                    .tx_outs
+                    .iter()
+                    .map(|tx_out| {
+                        let shown = tx_out
+                            .script_pubkey
+                            .address("main")
+                            .unwrap_or_else(|| hex::encode(tx_out.script_pubkey.to_bytes()));
+                        (shown, tx_out.amount.to_sat())
+                    })
+                    .collect();
+                if !self.approve {
+                    return vec![STATUS_REJECTED];
+                }
+                self.signer.sign_tx_input(key, &tx, input, &script_pubkey)
+            }
+            _ => return vec![STATUS_BAD_REQUEST],
+        };
+        match result {
+            Ok(result) => [vec![STATUS_OK], result].concat(),
+            Err(SignerError::UnknownKey(_)) => vec![STATUS_UNKNOWN_KEY],
+            Err(_) => vec![STATUS_BAD_REQUEST],
+        }
+    }
+}
+
+impl Channel for MockDevice {
+    fn exchange(&mut self, request: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let body = unframe(request).ok_or(SignerError::Transport("bad frame".to_string()))?;
+        Ok(frame(&self.handle(body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::signature::verify_ecdsa;
+    use crate::transaction::{Cmd, TxBuilder};
+
+    fn check_signer<S: Signer>(signer: &mut S) {
+        let pubkey = signer.get_pubkey(1).unwrap();
+        let sig = signer.sign_ecdsa(1, b"hello").unwrap();
+        assert!(verify_ecdsa(&pubkey, b"hello", &sig));
+        assert_eq!(signer.get_pubkey(5), Err(SignerError::UnknownKey(5)));
+
+        // sign a P2PKH spend and check it the way a node would
+        let script_pubkey = Script::p2pkh(&pubkey.sec(true, true));
+        let mut tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_input(vec![0x22; 32], 3)
+            .add_output(Amount::from_sat(9_000), Script::p2pkh(&[0xbb; 20]))
+            .build();
+        let sig = signer.sign_tx_input(1, &tx, 1, &script_pubkey).unwrap();
+        let message = tx.sig_message(1, &script_pubkey);
+        tx.tx_ins[1].script_sig =
+            Script::new(vec![Cmd::Push(sig), Cmd::push(&pubkey.sec(true, false))]);
+        assert!((tx.tx_ins[1].script_sig.clone() + script_pubkey).evaluate(&message));
+    }
+
+    #[test]
+    fn test_software_signer() {
+        check_signer(&mut SoftwareSigner::generate(2));
+    }
+
+    #[test]
+    fn test_hardware_signer() {
+        let software = SoftwareSigner::generate(2);
+        let mut hardware = HardwareSigner::new(MockDevice::new(software.clone()));
+        check_signer(&mut hardware);
+        assert_eq!(
+            hardware.get_pubkey(0),
+            software.clone().get_pubkey(0),
+            "same keys on both ends"
+        );
+        assert_eq!(hardware.channel.screen.len(), 1);
+        assert_eq!(hardware.channel.screen[0].1, 9_000);
+
+        hardware.channel.approve = false;
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xcc; 20]))
+            .build();
+        assert_eq!(
+            hardware.sign_tx_input(0, &tx, 0, &Script::p2pkh(&[0xaa; 20])),
+            Err(SignerError::Rejected)
+        );
+        // messages are still signed, only transactions need confirming
+        assert!(hardware.sign_ecdsa(0, b"hello").is_ok());
+    }
+}
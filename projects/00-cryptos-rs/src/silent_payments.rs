@@ -0,0 +1,685 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::amount::Amount;
+use crate::bech32::{convert_bits, decode_unlimited, encode, Variant};
+use crate::block::Block;
+use crate::field::Fn;
+use crate::hashes::{hash160, tagged};
+use crate::keys::{PublicKey, XOnlyPublicKey};
+use crate::ru256::RU256;
+use crate::secp256k1::{point_add, point_mul, Point, SECP256K1};
+use crate::transaction::{Cmd, Prevouts, Script, Tx, TxIn};
+
+// Silent payments (BIP352), reusable addresses that never show up on chain.
+// The receiver publishes a scan and a spend public key. A sender does ECDH
+// between the scan key and the sum of the secret keys of the inputs it spends,
+// and pays to the spend key tweaked by a hash of the shared secret, a taproot
+// output nobody else can link to the address. The receiver finds it by doing
+// the same ECDH from the other side, its scan secret key times the sum of the
+// input public keys, for every transaction with taproot outputs. The input
+// hash mixes in the smallest outpoint so two transactions from the same keys
+// don't pay to the same output. Labels tweak the spend key once more, giving
+// one receiver several addresses that are still scanned in a single pass.
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_DUP: u8 = 0x76;
+const OP_HASH160: u8 = 0xa9;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_CHECKSIG: u8 = 0xac;
+
+/// The x coordinate of H from BIP341, a taproot internal key with no known
+/// secret key. Script path spends from it have no key to share a secret with.
+const NUMS_H: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// The annex of a taproot witness starts with this byte
+const ANNEX_TAG: u8 = 0x50;
+
+fn hrp(net: &str) -> &'static str {
+    match net {
+        "main" => "sp",
+        "test" => "tsp",
+        _ => panic!("{} is not a valid net type, should be main|test", net),
+    }
+}
+
+fn p2tr(output_key: &XOnlyPublicKey) -> Script {
+    Script::new(vec![Cmd::Op(OP_1), Cmd::push(&output_key.serialize())])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SilentPaymentAddress {
+    pub scan_key: PublicKey,
+    pub spend_key: PublicKey,
+    pub net: String,
+}
+
+impl SilentPaymentAddress {
+    /// Parse an `sp1` or `tsp1` address. Versions above 0 may carry more data
+    /// after the two keys, which is ignored; version 31 is reserved for an
+    /// incompatible change.
+    pub fn decode(address: &str) -> Option<Self> {
+        let (hrp, data, variant) = decode_unlimited(address)?;
+        let net = match hrp.as_str() {
+            "sp" => "main",
+            "tsp" => "test",
+            _ => return None,
+        };
+        let (&version, data) = data.split_first()?;
+        if variant != Variant::Bech32m || version == 31 {
+            return None;
+        }
+        let payload = convert_bits(data, 5, 8, false)?;
+        if payload.len() < 66 || (version == 0 && payload.len() != 66) {
+            return None;
+        }
+        Some(SilentPaymentAddress {
+            scan_key: PublicKey::try_from_bytes(&payload[..33])?,
+            spend_key: PublicKey::try_from_bytes(&payload[33..66])?,
+            net: net.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut payload = self.scan_key.sec(true, false);
+        payload.extend(self.spend_key.sec(true, false));
+        let mut data = vec![0];
+        data.extend(convert_bits(&payload, 8, 5, true).unwrap());
+        write!(f, "{}", encode(hrp(&self.net), &data, Variant::Bech32m))
+    }
+}
+
+/// The public key an input contributes, None for inputs that don't take part:
+/// anything but P2PKH, P2SH-P2WPKH, P2WPKH and taproot key path spends, and
+/// uncompressed keys
+pub fn input_public_key(tx_in: &TxIn, prevout: &Script) -> Option<PublicKey> {
+    let compressed = |key: &[u8]| {
+        (key.len() == 33)
+            .then(|| PublicKey::try_from_bytes(key))
+            .flatten()
+    };
+    match prevout.template()?.as_slice() {
+        [Cmd::Op(OP_DUP), Cmd::Op(OP_HASH160), Cmd::Push(pkb_hash), Cmd::Op(OP_EQUALVERIFY), Cmd::Op(OP_CHECKSIG)] =>
+        {
+            // the key is the last push of the script sig that hashes right
+            let pushes: Vec<_> = tx_in
+                .script_sig
+                .instructions()
+                .map_while(Result::ok)
+                .collect();
+            pushes.iter().rev().find_map(|cmd| match cmd {
+                Cmd::Push(key) if hash160(key)[..] == pkb_hash[..] => compressed(key),
+                _ => None,
+            })
+        }
+        [Cmd::Op(OP_HASH160), Cmd::Push(_), Cmd::Op(OP_EQUAL)] => {
+            // only P2SH wrapping a P2WPKH program
+            match tx_in.script_sig.cmds().ok()?.as_slice() {
+                [Cmd::Push(redeem)] if redeem.len() == 22 && redeem[..2] == [OP_0, 20] => {
+                    compressed(tx_in.witness.last()?)
+                }
+                _ => None,
+            }
+        }
+        [Cmd::Op(OP_0), Cmd::Push(program)] if program.len() == 20 => {
+            compressed(tx_in.witness.last()?)
+        }
+        [Cmd::Op(OP_1), Cmd::Push(program)] if program.len() == 32 => {
+            let mut witness = tx_in.witness.as_slice();
+            if witness.len() > 1 && witness.last()?.first() == Some(&ANNEX_TAG) {
+                witness = &witness[..witness.len() - 1];
+            }
+            // a script path spend ends with the control block, which starts
+            // with the internal key after a version byte
+            if witness.len() > 1
+                && witness.last()?.get(1..33) == Some(&hex::decode(NUMS_H).unwrap()[..])
+            {
+                return None;
+            }
+            XOnlyPublicKey::from_bytes(program[..].try_into().unwrap())?.lift_x()
+        }
+        _ => None,
+    }
+}
+
+/// Whether the transaction spends a segwit output of a version above 1, which
+/// future versions of silent payments may give a meaning to
+fn spends_future_segwit(tx: &Tx, prevouts: &Prevouts) -> bool {
+    tx.tx_ins.iter().any(|tx_in| {
+        let Some(prevout) = prevouts.get(&(tx_in.prev_tx.clone(), tx_in.prev_index)) else {
+            return false;
+        };
+        matches!(
+            prevout.script_pubkey.template().unwrap_or_default().as_slice(),
+            [Cmd::Op(version), Cmd::Push(_)] if (OP_1 + 1..=OP_16).contains(version)
+        )
+    })
+}
+
+/// hash(smallest outpoint || A) as a scalar, with A the sum of the input keys
+fn input_hash(outpoints: &[(Vec<u8>, u32)], sum: &PublicKey) -> Fn {
+    let smallest = outpoints
+        .iter()
+        .map(|(prev_tx, prev_index)| {
+            let mut outpoint = prev_tx.clone();
+            outpoint.extend(prev_index.to_le_bytes());
+            outpoint
+        })
+        .min()
+        .expect("at least one input");
+    let mut data = smallest;
+    data.extend(sum.sec(true, false));
+    Fn::from_bytes(&tagged("BIP0352/Inputs", &data))
+}
+
+/// t_k, the tweak of the k-th output paying to one scan key
+fn shared_secret_tweak(shared_secret: &Point, k: u32) -> Fn {
+    let mut data = PublicKey(shared_secret.clone()).sec(true, false);
+    data.extend(k.to_be_bytes());
+    Fn::from_bytes(&tagged("BIP0352/SharedSecret", &data))
+}
+
+/// The tweak of label `m` for the receiver with `scan_key`, m = 0 is kept
+/// for change
+pub fn label_tweak(scan_key: &RU256, m: u32) -> RU256 {
+    let mut data = Fn::new(scan_key).to_bytes().to_vec();
+    data.extend(m.to_be_bytes());
+    Fn::from_bytes(&tagged("BIP0352/Label", &data))
+        .as_ru256()
+        .clone()
+}
+
+/// An input the sender spends, with the secret key that signs for it
+#[derive(Debug, Clone)]
+pub struct SenderInput {
+    pub prev_tx: Vec<u8>,
+    pub prev_index: u32,
+    pub secret_key: RU256,
+    /// Taproot keys are x-only, so a key with odd y counts negated
+    pub taproot: bool,
+}
+
+/// The taproot outputs paying each of `recipients`, in order. `outpoints` are
+/// those of every input of the transaction, including ones not in `inputs`
+/// because they have no eligible key. None if there are no inputs or their
+/// keys cancel out.
+pub fn create_outputs(
+    inputs: &[SenderInput],
+    outpoints: &[(Vec<u8>, u32)],
+    recipients: &[SilentPaymentAddress],
+) -> Option<Vec<Script>> {
+    let mut sum = Fn::zero();
+    for input in inputs {
+        let key = Fn::new(&input.secret_key);
+        let odd = || XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&input.secret_key)).1;
+        sum = sum + if input.taproot && odd() { -key } else { key };
+    }
+    if sum.is_zero() || outpoints.is_empty() {
+        return None;
+    }
+    let input_hash = input_hash(outpoints, &PublicKey::from_sk(sum.as_ru256()));
+    let tweaked = input_hash * sum;
+
+    // one shared secret per scan key, and k counting the outputs to it
+    let mut secrets: HashMap<Vec<u8>, (Point, u32)> = HashMap::new();
+    let mut outputs = vec![];
+    for recipient in recipients {
+        let (shared_secret, k) = secrets
+            .entry(recipient.scan_key.sec(true, false))
+            .or_insert_with(|| (point_mul(&tweaked, &recipient.scan_key.0), 0));
+        let t_k = shared_secret_tweak(shared_secret, *k);
+        *k += 1;
+        let output = point_add(
+            &recipient.spend_key.0,
+            &SECP256K1::public_key(t_k.as_ru256()),
+        );
+        if output.is_zero_point() {
+            return None;
+        }
+        outputs.push(p2tr(&XOnlyPublicKey::from_pubkey(&PublicKey(output)).0));
+    }
+    Some(outputs)
+}
+
+/// A silent payment found while scanning
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedOutput {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: Amount,
+    pub output_key: XOnlyPublicKey,
+    /// Added to the spend secret key to get the key of the output
+    pub tweak: RU256,
+    pub label: Option<u32>,
+}
+
+impl ReceivedOutput {
+    /// The secret key of the output from the receiver's spend secret key
+    pub fn secret_key(&self, spend_key: &RU256) -> RU256 {
+        (Fn::new(spend_key) + Fn::new(&self.tweak))
+            .as_ru256()
+            .clone()
+    }
+}
+
+/// The receiving side. Scanning only needs the scan secret key and the spend
+/// public key, so it can run on a machine that can't spend.
+#[derive(Debug, Clone)]
+pub struct Receiver {
+    scan_key: RU256,
+    spend_key: PublicKey,
+    net: String,
+    /// Label points m * G by compressed encoding, with m and its tweak
+    labels: HashMap<Vec<u8>, (u32, RU256)>,
+}
+
+impl Receiver {
+    pub fn new(scan_key: &RU256, spend_key: &PublicKey, net: &str) -> Self {
+        hrp(net);
+        Receiver {
+            scan_key: scan_key.clone(),
+            spend_key: spend_key.clone(),
+            net: net.to_string(),
+            labels: HashMap::new(),
+        }
+    }
+
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress {
+            scan_key: PublicKey::from_sk(&self.scan_key),
+            spend_key: self.spend_key.clone(),
+            net: self.net.clone(),
+        }
+    }
+
+    /// The address for label `m`, which scanning looks for from now on
+    pub fn labeled_address(&mut self, m: u32) -> SilentPaymentAddress {
+        let tweak = label_tweak(&self.scan_key, m);
+        let label = SECP256K1::public_key(&tweak);
+        let spend_key = point_add(&self.spend_key.0, &label);
+        self.labels
+            .insert(PublicKey(label).sec(true, false), (m, tweak));
+        SilentPaymentAddress {
+            spend_key: PublicKey(spend_key),
+            ..self.address()
+        }
+    }
+
+    /// The outputs of `tx` paying to this receiver. `prevouts` must hold the
+    /// outputs its inputs spend.
+    pub fn scan_tx(&self, tx: &Tx, prevouts: &Prevouts) -> Vec<ReceivedOutput> {
+        let mut taproot: Vec<(u32, XOnlyPublicKey)> = tx
+            .tx_outs
+            .iter()
+            .enumerate()
+            .filter_map(
+                |(vout, tx_out)| match tx_out.script_pubkey.template()?.as_slice() {
+                    [Cmd::Op(OP_1), Cmd::Push(program)] if program.len() == 32 => {
+                        let key = XOnlyPublicKey::from_bytes(program[..].try_into().unwrap())?;
+                        Some((vout as u32, key))
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+        if tx.is_coinbase() || taproot.is_empty() || spends_future_segwit(tx, prevouts) {
+            return vec![];
+        }
+
+        // the smallest outpoint is taken over every input, eligible or not
+        let outpoints: Vec<(Vec<u8>, u32)> = tx
+            .tx_ins
+            .iter()
+            .map(|tx_in| (tx_in.prev_tx.clone(), tx_in.prev_index))
+            .collect();
+        let mut sum: Option<Point> = None;
+        for (tx_in, outpoint) in tx.tx_ins.iter().zip(&outpoints) {
+            let Some(prevout) = prevouts.get(outpoint) else {
+                continue;
+            };
+            if let Some(key) = input_public_key(tx_in, &prevout.script_pubkey) {
+                sum = Some(match sum {
+                    None => key.0,
+                    Some(sum) => point_add(&sum, &key.0),
+                });
+            }
+        }
+        // keys summing to infinity at some point are fine as long as the
+        // final sum isn't
+        let Some(sum) = sum.filter(|sum| !sum.is_zero_point()) else {
+            return vec![];
+        };
+        let input_hash = input_hash(&outpoints, &PublicKey(sum.clone()));
+        let shared_secret = point_mul(&(input_hash * Fn::new(&self.scan_key)), &sum);
+
+        let txid = tx.id();
+        let mut found = vec![];
+        let mut k = 0;
+        loop {
+            let t_k = shared_secret_tweak(&shared_secret, k);
+            let p_k = point_add(&self.spend_key.0, &SECP256K1::public_key(t_k.as_ru256()));
+            if p_k.is_zero_point() {
+                break;
+            }
+            let x_only = XOnlyPublicKey::from_pubkey(&PublicKey(p_k.clone())).0;
+            let mut matched = None;
+            for (i, (_, output_key)) in taproot.iter().enumerate() {
+                if *output_key == x_only {
+                    matched = Some((i, t_k.as_ru256().clone(), None));
+                    break;
+                }
+                // output - P_k for either y of the output is a label point
+                let output = output_key.lift_x().unwrap().0;
+                let label = [output.clone(), -output].iter().find_map(|output| {
+                    let point = point_add(output, &-p_k.clone());
+                    if point.is_zero_point() {
+                        return None;
+                    }
+                    self.labels.get(&PublicKey(point).sec(true, false))
+                });
+                if let Some((m, label_tweak)) = label {
+                    let tweak = (t_k.clone() + Fn::new(label_tweak)).as_ru256().clone();
+                    matched = Some((i, tweak, Some(*m)));
+                    break;
+                }
+            }
+            let Some((i, tweak, label)) = matched else {
+                break;
+            };
+            let (vout, output_key) = taproot.remove(i);
+            found.push(ReceivedOutput {
+                txid: txid.clone(),
+                vout,
+                amount: tx.tx_outs[vout as usize].amount,
+                output_key,
+                tweak,
+                label,
+            });
+            k += 1;
+        }
+        found
+    }
+
+    /// The outputs of every transaction in `block` paying to this receiver
+    pub fn scan_block(&self, block: &Block, prevouts: &Prevouts) -> Vec<ReceivedOutput> {
+        block
+            .txs
+            .iter()
+            .flat_map(|tx| self.scan_tx(tx, prevouts))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{TxBuilder, TxOut};
+
+    const OP_CHECKMULTISIG: u8 = 0xae;
+
+    fn key(byte: u8) -> RU256 {
+        RU256::from_bytes(&[byte; 32])
+    }
+
+    fn key_hex(hex: &str) -> RU256 {
+        RU256::from_bytes(&hex::decode(hex).unwrap())
+    }
+
+    fn x_only(hex: &str) -> XOnlyPublicKey {
+        XOnlyPublicKey::from_bytes(&hex::decode(hex).unwrap().try_into().unwrap()).unwrap()
+    }
+
+    /// An outpoint from a txid in display order
+    fn outpoint(txid: &str, vout: u32) -> (Vec<u8>, u32) {
+        let mut prev_tx = hex::decode(txid).unwrap();
+        prev_tx.reverse();
+        (prev_tx, vout)
+    }
+
+    // the receiver and the outpoints shared by the BIP352 vectors below
+    const SCAN_KEY: &str = "0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c";
+    const SPEND_KEY: &str = "9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3";
+    const ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+    const TXIDS: [&str; 2] = [
+        "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+    ];
+
+    #[test]
+    fn bip352_send_vectors() {
+        let address = SilentPaymentAddress::decode(ADDRESS).unwrap();
+        let receiver = Receiver::new(
+            &key_hex(SCAN_KEY),
+            &PublicKey::from_sk(&key_hex(SPEND_KEY)),
+            "main",
+        );
+        assert_eq!(receiver.address(), address);
+
+        let first = "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1";
+        // (second input key, whether each input is taproot, output key)
+        let vectors = [
+            // taproot only inputs with even y-values
+            (
+                "fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7",
+                [true, true],
+                "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb",
+            ),
+            // taproot only with mixed even/odd y-values
+            (
+                "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+                [true, true],
+                "77cab7dd12b10259ee82c6ea4b509774e33e7078e7138f568092241bf26b99f1",
+            ),
+            // taproot and non-taproot inputs
+            (
+                "8d4751f6e8a3586880fb66c19ae277969bd5aa06f61c4ee2f1e2486efdf666d3",
+                [true, false],
+                "30523cca96b2a9ae3c98beb5e60f7d190ec5bc79b2d11a0b2d4d09a608c448f0",
+            ),
+        ];
+        let outpoints = TXIDS.map(|txid| outpoint(txid, 0));
+        for (second, taproot, output_key) in vectors {
+            let inputs: Vec<SenderInput> = [first, second]
+                .iter()
+                .zip(&outpoints)
+                .zip(taproot)
+                .map(
+                    |((secret_key, (prev_tx, prev_index)), taproot)| SenderInput {
+                        prev_tx: prev_tx.clone(),
+                        prev_index: *prev_index,
+                        secret_key: key_hex(secret_key),
+                        taproot,
+                    },
+                )
+                .collect();
+            let outputs =
+                create_outputs(&inputs, &outpoints, std::slice::from_ref(&address)).unwrap();
+            assert_eq!(outputs, vec![p2tr(&x_only(output_key))]);
+        }
+    }
+
+    #[test]
+    fn bip352_receive_vector() {
+        // simple send: two P2PKH inputs. The vector's signatures are left
+        // out, scanning only reads the keys.
+        let spend_key = key_hex(SPEND_KEY);
+        let receiver = Receiver::new(&key_hex(SCAN_KEY), &PublicKey::from_sk(&spend_key), "main");
+        let input_keys = [
+            "025a1e61f898173040e20616d43e9f496fba90338a39faa1ed98fcbaeee4dd9be5",
+            "03bd85685d03d111699b15d046319febe77f8de5286e9e512703cdee1bf3be3792",
+        ];
+        let output_key = x_only("3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1");
+
+        let mut builder = TxBuilder::new("main");
+        for txid in TXIDS {
+            let (prev_tx, prev_index) = outpoint(txid, 0);
+            builder = builder.add_input(prev_tx, prev_index);
+        }
+        let mut tx = builder
+            .add_output(Amount::from_sat(1_000), p2tr(&output_key))
+            .build();
+        let mut prevouts = Prevouts::new();
+        for ((tx_in, key), txid) in tx.tx_ins.iter_mut().zip(input_keys).zip(TXIDS) {
+            let key = hex::decode(key).unwrap();
+            tx_in.script_sig = Script::new(vec![Cmd::push(&[0x30; 71]), Cmd::push(&key)]);
+            prevouts.insert(
+                outpoint(txid, 0),
+                TxOut {
+                    amount: Amount::from_sat(1_000),
+                    script_pubkey: Script::p2pkh(&hash160(&key)),
+                },
+            );
+        }
+
+        let found = receiver.scan_tx(&tx, &prevouts);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].output_key, output_key);
+        assert_eq!(
+            found[0].tweak,
+            key_hex("f438b40179a3c4262de12986c0e6cce0634007cdc79c1dcd3e20b9ebc2e7eef6")
+        );
+        assert_eq!(
+            XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&found[0].secret_key(&spend_key))).0,
+            output_key
+        );
+    }
+
+    #[test]
+    fn test_address() {
+        let receiver = Receiver::new(&key(0x11), &PublicKey::from_sk(&key(0x22)), "main");
+        let address = receiver.address().to_string();
+        assert!(address.starts_with("sp1q"));
+        assert_eq!(address.len(), 116);
+        assert_eq!(
+            SilentPaymentAddress::decode(&address),
+            Some(receiver.address())
+        );
+        let testnet = Receiver::new(&key(0x11), &PublicKey::from_sk(&key(0x22)), "test");
+        assert!(testnet.address().to_string().starts_with("tsp1q"));
+        // a segwit address isn't one
+        assert_eq!(
+            SilentPaymentAddress::decode(
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_send_and_scan() {
+        let (scan_key, spend_key) = (key(0x11), key(0x22));
+        let mut receiver = Receiver::new(&scan_key, &PublicKey::from_sk(&spend_key), "main");
+        let plain = receiver.address();
+        let labeled = receiver.labeled_address(1);
+        assert_ne!(plain.spend_key, labeled.spend_key);
+
+        // a P2WPKH and a taproot input
+        let (wpkh_key, tr_key) = (key(0x33), key(0x44));
+        let wpkh_pubkey = PublicKey::from_sk(&wpkh_key);
+        let tr_pubkey = XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&tr_key)).0;
+        let inputs = [
+            SenderInput {
+                prev_tx: vec![0xee; 32],
+                prev_index: 1,
+                secret_key: wpkh_key,
+                taproot: false,
+            },
+            SenderInput {
+                prev_tx: vec![0xdd; 32],
+                prev_index: 0,
+                secret_key: tr_key,
+                taproot: true,
+            },
+        ];
+        // plus a P2SH multisig input, which has no key to add but the
+        // smallest outpoint
+        let outpoints = [
+            (vec![0xee; 32], 1),
+            (vec![0xdd; 32], 0),
+            (vec![0x01; 32], 0),
+        ];
+        let outputs = create_outputs(&inputs, &outpoints, &[plain, labeled]).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_ne!(outputs[0], outputs[1]);
+
+        let mut tx = TxBuilder::new("main")
+            .add_input(vec![0xee; 32], 1)
+            .add_input(vec![0xdd; 32], 0)
+            .add_input(vec![0x01; 32], 0)
+            .add_output(Amount::from_sat(10_000), outputs[1].clone())
+            .add_output(Amount::from_sat(5_000), Script::p2pkh(&[0xaa; 20]))
+            .add_output(Amount::from_sat(20_000), outputs[0].clone())
+            .build();
+        tx.segwit = true;
+        tx.tx_ins[0].witness = vec![vec![0x30; 71], wpkh_pubkey.sec(true, false)];
+        tx.tx_ins[1].witness = vec![vec![0x01; 64]];
+        let redeem = Script::new(vec![
+            Cmd::Op(OP_1),
+            Cmd::push(&wpkh_pubkey.sec(true, false)),
+            Cmd::Op(OP_1),
+            Cmd::Op(OP_CHECKMULTISIG),
+        ]);
+        tx.tx_ins[2].script_sig = Script::new(vec![
+            Cmd::Op(OP_0),
+            Cmd::push(&[0x30; 71]),
+            Cmd::push(&redeem.to_bytes()),
+        ]);
+        let mut prevouts = Prevouts::new();
+        prevouts.insert(
+            (vec![0x01; 32], 0),
+            TxOut {
+                amount: Amount::from_sat(1_000),
+                script_pubkey: Script::new(vec![
+                    Cmd::Op(OP_HASH160),
+                    Cmd::push(&hash160(&redeem.to_bytes())),
+                    Cmd::Op(OP_EQUAL),
+                ]),
+            },
+        );
+        prevouts.insert(
+            (vec![0xee; 32], 1),
+            TxOut {
+                amount: Amount::from_sat(30_000),
+                script_pubkey: Script::new(vec![
+                    Cmd::Op(OP_0),
+                    Cmd::push(&wpkh_pubkey.sec(true, true)),
+                ]),
+            },
+        );
+        prevouts.insert(
+            (vec![0xdd; 32], 0),
+            TxOut {
+                amount: Amount::from_sat(6_000),
+                script_pubkey: p2tr(&tr_pubkey),
+            },
+        );
+
+        let found = receiver.scan_tx(&tx, &prevouts);
+        assert_eq!(found.len(), 2);
+        let by_vout = |vout| found.iter().find(|output| output.vout == vout).unwrap();
+        assert_eq!(by_vout(2).label, None);
+        assert_eq!(by_vout(0).label, Some(1));
+        assert_eq!(by_vout(0).amount, Amount::from_sat(10_000));
+        for output in &found {
+            let secret_key = output.secret_key(&spend_key);
+            assert_eq!(
+                XOnlyPublicKey::from_pubkey(&PublicKey::from_sk(&secret_key)).0,
+                output.output_key
+            );
+        }
+
+        // nothing for someone else, nor once the taproot input is a script
+        // path spend from the unspendable key
+        let other = Receiver::new(&key(0x55), &PublicKey::from_sk(&spend_key), "main");
+        assert_eq!(other.scan_tx(&tx, &prevouts), vec![]);
+        let mut control_block = vec![0xc0];
+        control_block.extend(hex::decode(NUMS_H).unwrap());
+        tx.tx_ins[1].witness = vec![vec![0x51], control_block];
+        assert_eq!(receiver.scan_tx(&tx, &prevouts), vec![]);
+    }
+}
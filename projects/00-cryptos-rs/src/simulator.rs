@@ -0,0 +1,159 @@
+use primitive_types::U256;
+
+use crate::block::{build_genesis, work_from_bits, Block, Chain, GENESIS_PUBKEY, TARGET_SPACING};
+use crate::hashes::sha256d;
+
+// Toy chains for playing with consensus. Headers are mined at a difficulty low
+// enough to find a nonce in a few hashes, branches can be forked off any
+// header, and feeding them to `Chain` shows which tip a node would follow:
+// the one with the most work, not the most blocks, and on a tie the one it saw
+// first. The chains never reach a retarget, which would clamp the bits to
+// mainnet's limit, so keep them under 2016 blocks.
+
+/// The easiest target there is, about every other nonce meets it
+pub const EASY_BITS: [u8; 4] = [0xff, 0xff, 0x7f, 0x20];
+
+/// Timestamp of toy genesis blocks
+const TOY_GENESIS_TIME: u32 = 1700000000;
+
+/// What a node did with a branch it was offered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The branch built on the tip and was appended
+    Extended,
+    /// The branch had more work, `depth` blocks of the old tip were disconnected
+    Reorg { depth: u32 },
+    /// A valid branch without more work than the tip, the tip stays
+    Kept,
+    /// The branch doesn't connect to the chain or has an invalid header
+    Rejected,
+}
+
+/// A chain holding just a genesis block mined at `bits`, in the same little
+/// endian form as `Block::bits`
+pub fn toy_chain(bits: &[u8]) -> Chain {
+    let pubkey = hex::decode(GENESIS_PUBKEY).unwrap();
+    let genesis = build_genesis("toy chain", &pubkey, TOY_GENESIS_TIME, bits);
+    Chain::with_genesis("main", genesis)
+}
+
+/// Mine `count` headers building on the header at `height`, without offering
+/// them to the chain. `seed` is committed to in the merkle roots so branches
+/// mined off the same header with different seeds don't collide.
+pub fn mine_branch(chain: &Chain, height: u32, count: u32, seed: u32) -> Vec<Block> {
+    let mut branch = chain.fork_at(height);
+    let mut headers = vec![];
+    for _ in 0..count {
+        let tip = branch.tip();
+        let timestamp = tip.timestamp + TARGET_SPACING;
+        let mut commitment = seed.to_le_bytes().to_vec();
+        commitment.extend((branch.height() + 1).to_le_bytes());
+        let mut block = Block {
+            version: tip.version,
+            prev_block: hex::decode(tip.id()).unwrap(),
+            merkle_root: sha256d(&commitment).to_vec(),
+            timestamp,
+            bits: branch.next_bits(timestamp),
+            nonce: vec![0; 4],
+            txs: vec![],
+        };
+        assert!(block.mine(), "no nonce meets the target, use easier bits");
+        assert!(branch.push(block.clone()));
+        headers.push(block);
+    }
+    headers
+}
+
+/// Total work of the headers of a branch
+pub fn branch_work(headers: &[Block]) -> U256 {
+    headers.iter().fold(U256::zero(), |work, header| {
+        work + work_from_bits(&header.bits)
+    })
+}
+
+/// Offer a branch to the chain, switching to it if it has more work
+pub fn submit(chain: &mut Chain, headers: Vec<Block>) -> Outcome {
+    let old_height = chain.height();
+    let Some(fork_height) = headers
+        .first()
+        .and_then(|first| chain.height_of(&hex::encode(&first.prev_block)))
+    else {
+        return Outcome::Rejected;
+    };
+    if chain.submit_branch(headers.clone()) {
+        return match old_height - fork_height {
+            0 => Outcome::Extended,
+            depth => Outcome::Reorg { depth },
+        };
+    }
+    // not switched, either it lost on work or it was invalid
+    let mut branch = chain.fork_at(fork_height);
+    if headers.into_iter().all(|header| branch.push(header)) {
+        Outcome::Kept
+    } else {
+        Outcome::Rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_branch_wins() {
+        let mut chain = toy_chain(&EASY_BITS);
+        let main = mine_branch(&chain, 0, 3, 0);
+        assert_eq!(submit(&mut chain, main), Outcome::Extended);
+        let old_tip = chain.tip().id();
+
+        // a competing branch off block 1 as long as the chain loses the tie
+        let fork = mine_branch(&chain, 1, 2, 1);
+        assert_eq!(submit(&mut chain, fork), Outcome::Kept);
+        assert_eq!(chain.tip().id(), old_tip);
+
+        // one more block and it has more work
+        let longer = mine_branch(&chain, 1, 3, 2);
+        assert_eq!(submit(&mut chain, longer), Outcome::Reorg { depth: 2 });
+        assert_eq!(chain.height(), 4);
+        assert_eq!(chain.chainwork(), work_from_bits(&EASY_BITS) * 5);
+    }
+
+    #[test]
+    fn test_invalid_branch_rejected() {
+        let mut chain = toy_chain(&EASY_BITS);
+        let mut branch = mine_branch(&chain, 0, 3, 0);
+        // find a nonce that misses the target
+        let header = &mut branch[1];
+        let nonce = (0u32..)
+            .find(|nonce| {
+                header.nonce = nonce.to_le_bytes().to_vec();
+                !header.validate()
+            })
+            .unwrap();
+        header.nonce = nonce.to_le_bytes().to_vec();
+        assert_eq!(submit(&mut chain, branch), Outcome::Rejected);
+        assert_eq!(chain.height(), 0);
+
+        let orphan = mine_branch(&toy_chain(&[0xff, 0xff, 0x7e, 0x20]), 0, 1, 0);
+        assert_eq!(submit(&mut chain, orphan), Outcome::Rejected);
+    }
+
+    #[test]
+    fn test_work_beats_length() {
+        // half the easy target, twice the work per block
+        let hard_bits = [0xff, 0xff, 0x3f, 0x20];
+        let mut hard = toy_chain(&hard_bits);
+        let branch = mine_branch(&hard, 0, 2, 0);
+        submit(&mut hard, branch);
+        let mut easy = toy_chain(&EASY_BITS);
+        let branch = mine_branch(&easy, 0, 4, 0);
+        submit(&mut easy, branch);
+
+        assert!(hard.height() < easy.height());
+        assert!(hard.chainwork() > easy.chainwork());
+        assert_eq!(
+            branch_work(&mine_branch(&hard, 0, 2, 1)),
+            work_from_bits(&hard_bits) * 2
+        );
+    }
+}
@@ -0,0 +1,772 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "rand")]
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::shamir::interpolate;
+
+// SLIP-39, Shamir shares written down as words. A master secret is encrypted
+// with a passphrase, split into groups with a threshold of groups needed, and
+// each group secret is split again among its members. Every share becomes a
+// mnemonic of 10 bit words carrying an identifier tying the shares of one
+// secret together, the group and member parameters, the share value and an
+// RS1024 checksum, so a mistyped word is caught before anything is combined.
+// The byte level splitting is the one from `shamir`, with the secret at
+// x = 255 and a digest of it at x = 254 so that combining shares that don't
+// belong together fails instead of giving a wrong secret. Any passphrase
+// decrypts to some secret, the wrong one just gives a different wallet.
+
+const RADIX_BITS: usize = 10;
+/// Identifier, extendable flag and iteration exponent, 20 bits
+const ID_EXP_WORDS: usize = 2;
+const CHECKSUM_WORDS: usize = 3;
+/// Header, group and member parameters, then the checksum
+const METADATA_WORDS: usize = ID_EXP_WORDS + 2 + CHECKSUM_WORDS;
+const MIN_STRENGTH_BYTES: usize = 16;
+#[cfg(feature = "rand")]
+const MAX_SHARE_COUNT: u8 = 16;
+
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const DIGEST_LENGTH: usize = 4;
+
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Slip39Error {
+    /// A word that isn't in the wordlist
+    UnknownWord(String),
+    /// Too few words, or a share value that isn't a whole number of bytes
+    Length,
+    Checksum,
+    /// Nonzero padding bits in the share value
+    Padding,
+    /// Shares from different secrets or with conflicting parameters
+    Mismatch,
+    /// Fewer groups or members than their threshold
+    NotEnoughShares,
+    /// The shares combined to something that doesn't match its digest
+    Digest,
+}
+
+impl fmt::Display for Slip39Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Slip39Error::UnknownWord(word) => write!(f, "{} is not a SLIP-39 word", word),
+            Slip39Error::Length => write!(f, "invalid mnemonic length"),
+            Slip39Error::Checksum => write!(f, "invalid mnemonic checksum"),
+            Slip39Error::Padding => write!(f, "invalid mnemonic padding"),
+            Slip39Error::Mismatch => write!(f, "shares don't belong together"),
+            Slip39Error::NotEnoughShares => write!(f, "not enough shares"),
+            Slip39Error::Digest => write!(f, "shares don't match their digest"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Slip39Error {}
+
+/// One mnemonic share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slip39Share {
+    /// Random 15 bit value shared by every share of one secret
+    pub identifier: u16,
+    /// Whether the identifier is left out of the encryption, so the secret
+    /// can later be split again under the same passphrase
+    pub extendable: bool,
+    /// The passphrase is stretched with 10000 * 2^e PBKDF2 iterations
+    pub iteration_exponent: u8,
+    pub group_index: u8,
+    pub group_threshold: u8,
+    pub group_count: u8,
+    pub member_index: u8,
+    pub member_threshold: u8,
+    pub value: Vec<u8>,
+}
+
+fn rs1024_polymod(values: &[u16]) -> u32 {
+    const GEN: [u32; 10] = [
+        0xe0e040, 0x1c1c080, 0x3838100, 0x7070200, 0xe0e0009, 0x1c0c2412, 0x38086c24, 0x3090fc48,
+        0x21b1f890, 0x3f3f120,
+    ];
+    let mut chk = 1u32;
+    for &v in values {
+        let b = chk >> 20;
+        chk = ((chk & 0xfffff) << 10) ^ v as u32;
+        for (i, gen) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= gen;
+            }
+        }
+    }
+    chk
+}
+
+fn customization(extendable: bool) -> &'static [u8] {
+    if extendable {
+        b"shamir_extendable"
+    } else {
+        b"shamir"
+    }
+}
+
+/// The checksum words over `words`, which don't include them yet
+fn rs1024_checksum(words: &[u16], extendable: bool) -> [u16; CHECKSUM_WORDS] {
+    let mut values: Vec<u16> = customization(extendable)
+        .iter()
+        .map(|&b| b as u16)
+        .collect();
+    values.extend_from_slice(words);
+    values.extend_from_slice(&[0; CHECKSUM_WORDS]);
+    let polymod = rs1024_polymod(&values) ^ 1;
+    [2, 1, 0].map(|i| ((polymod >> (RADIX_BITS * i)) & 1023) as u16)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let ipad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    let opad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha256::new()
+        .chain_update(ipad)
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(opad)
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut block_index = 1u32;
+    while out.len() < len {
+        let mut salted = salt.to_vec();
+        salted.extend_from_slice(&block_index.to_be_bytes());
+        let mut u = hmac_sha256(password, &salted);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &u);
+            for (t, u) in t.iter_mut().zip(u.iter()) {
+                *t ^= u;
+            }
+        }
+        let take = (len - out.len()).min(32);
+        out.extend_from_slice(&t[..take]);
+        block_index += 1;
+    }
+    out
+}
+
+/// The Feistel round function, keyed by the round and the passphrase
+fn round_function(round: u8, passphrase: &[u8], exponent: u8, salt: &[u8], r: &[u8]) -> Vec<u8> {
+    let mut password = vec![round];
+    password.extend_from_slice(passphrase);
+    let mut salted = salt.to_vec();
+    salted.extend_from_slice(r);
+    let iterations = (BASE_ITERATION_COUNT << exponent) / ROUND_COUNT as u32;
+    pbkdf2_sha256(&password, &salted, iterations, r.len())
+}
+
+fn salt(identifier: u16, extendable: bool) -> Vec<u8> {
+    if extendable {
+        vec![]
+    } else {
+        let mut salt = b"shamir".to_vec();
+        salt.extend_from_slice(&identifier.to_be_bytes());
+        salt
+    }
+}
+
+/// Run the four round Feistel network over `data`, backwards to decrypt
+fn feistel(
+    data: &[u8],
+    passphrase: &[u8],
+    exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    decrypt: bool,
+) -> Vec<u8> {
+    let salt = salt(identifier, extendable);
+    let (mut l, mut r) = (
+        data[..data.len() / 2].to_vec(),
+        data[data.len() / 2..].to_vec(),
+    );
+    for i in 0..ROUND_COUNT {
+        let round = if decrypt { ROUND_COUNT - 1 - i } else { i };
+        let f = round_function(round, passphrase, exponent, &salt, &r);
+        let next: Vec<u8> = l.iter().zip(f.iter()).map(|(a, b)| a ^ b).collect();
+        l = core::mem::replace(&mut r, next);
+    }
+    r.extend_from_slice(&l);
+    r
+}
+
+/// Shares of `secret` at x = 0 to count - 1, the digest share pinning the
+/// polynomial unless the threshold is 1
+#[cfg(feature = "rand")]
+fn split_secret<R: Rng + ?Sized>(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+    rng: &mut R,
+) -> Vec<(u8, Vec<u8>)> {
+    if threshold == 1 {
+        return (0..count).map(|index| (index, secret.to_vec())).collect();
+    }
+    let random_len = secret.len() - DIGEST_LENGTH;
+    let mut shares: Vec<(u8, Vec<u8>)> = (0..threshold - 2)
+        .map(|index| (index, (0..secret.len()).map(|_| rng.gen()).collect()))
+        .collect();
+    let random_part: Vec<u8> = (0..random_len).map(|_| rng.gen()).collect();
+    let mut digest = hmac_sha256(&random_part, secret)[..DIGEST_LENGTH].to_vec();
+    digest.extend_from_slice(&random_part);
+
+    let mut points: Vec<(u8, &[u8])> = shares.iter().map(|(i, v)| (*i, v.as_slice())).collect();
+    points.push((DIGEST_INDEX, &digest));
+    points.push((SECRET_INDEX, secret));
+    let rest: Vec<(u8, Vec<u8>)> = (threshold - 2..count)
+        .map(|index| (index, interpolate(&points, index)))
+        .collect();
+    shares.extend(rest);
+    shares
+}
+
+/// The secret behind `threshold` shares, checked against the digest share
+fn recover_secret(threshold: u8, shares: &[(u8, &[u8])]) -> Result<Vec<u8>, Slip39Error> {
+    if threshold == 1 {
+        return Ok(shares[0].1.to_vec());
+    }
+    let secret = interpolate(shares, SECRET_INDEX);
+    let digest = interpolate(shares, DIGEST_INDEX);
+    let (expected, random_part) = digest.split_at(DIGEST_LENGTH);
+    if hmac_sha256(random_part, &secret)[..DIGEST_LENGTH] != *expected {
+        return Err(Slip39Error::Digest);
+    }
+    Ok(secret)
+}
+
+impl Slip39Share {
+    /// The mnemonic, words separated by spaces
+    pub fn to_mnemonic(&self) -> String {
+        let id_exp = (self.identifier as u32) << 5
+            | (self.extendable as u32) << 4
+            | self.iteration_exponent as u32;
+        let params = (self.group_index as u32) << 16
+            | ((self.group_threshold - 1) as u32) << 12
+            | ((self.group_count - 1) as u32) << 8
+            | (self.member_index as u32) << 4
+            | (self.member_threshold - 1) as u32;
+        let mut words = vec![
+            (id_exp >> 10) as u16,
+            (id_exp & 1023) as u16,
+            (params >> 10) as u16,
+            (params & 1023) as u16,
+        ];
+
+        // the value as a big endian integer, left padded to whole words
+        let value_words = (self.value.len() * 8).div_ceil(RADIX_BITS);
+        let mut bits = vec![false; value_words * RADIX_BITS - self.value.len() * 8];
+        bits.extend(
+            self.value
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1)),
+        );
+        words.extend(
+            bits.chunks(RADIX_BITS)
+                .map(|chunk| chunk.iter().fold(0u16, |acc, &bit| acc << 1 | bit as u16)),
+        );
+        let checksum = rs1024_checksum(&words, self.extendable);
+        words.extend_from_slice(&checksum);
+
+        let words: Vec<&str> = words.iter().map(|&w| WORDLIST[w as usize]).collect();
+        words.join(" ")
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Self, Slip39Error> {
+        let words = mnemonic
+            .split_whitespace()
+            .map(|word| {
+                let lower = word.to_lowercase();
+                WORDLIST
+                    .binary_search(&lower.as_str())
+                    .map(|index| index as u16)
+                    .map_err(|_| Slip39Error::UnknownWord(word.into()))
+            })
+            .collect::<Result<Vec<u16>, _>>()?;
+        let min_words = METADATA_WORDS + (MIN_STRENGTH_BYTES * 8).div_ceil(RADIX_BITS);
+        if words.len() < min_words {
+            return Err(Slip39Error::Length);
+        }
+        let extendable = (words[1] >> 4) & 1 == 1;
+        if rs1024_polymod(
+            &customization(extendable)
+                .iter()
+                .map(|&b| b as u16)
+                .chain(words.iter().copied())
+                .collect::<Vec<_>>(),
+        ) != 1
+        {
+            return Err(Slip39Error::Checksum);
+        }
+
+        let value_words = &words[ID_EXP_WORDS + 2..words.len() - CHECKSUM_WORDS];
+        let padding = value_words.len() * RADIX_BITS % 16;
+        if padding > 8 {
+            return Err(Slip39Error::Length);
+        }
+        let bits: Vec<bool> = value_words
+            .iter()
+            .flat_map(|word| (0..RADIX_BITS).rev().map(move |i| (word >> i) & 1 == 1))
+            .collect();
+        if bits[..padding].iter().any(|&bit| bit) {
+            return Err(Slip39Error::Padding);
+        }
+        let value = bits[padding..]
+            .chunks(8)
+            .map(|chunk| chunk.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8))
+            .collect();
+
+        let params = (words[2] as u32) << 10 | words[3] as u32;
+        let group_threshold = ((params >> 12) & 15) as u8 + 1;
+        let group_count = ((params >> 8) & 15) as u8 + 1;
+        if group_threshold > group_count {
+            return Err(Slip39Error::Mismatch);
+        }
+        Ok(Slip39Share {
+            identifier: (words[0] << 5) | (words[1] >> 5),
+            extendable,
+            iteration_exponent: (words[1] & 15) as u8,
+            group_index: (params >> 16) as u8,
+            group_threshold,
+            group_count,
+            member_index: ((params >> 4) & 15) as u8,
+            member_threshold: (params & 15) as u8 + 1,
+            value,
+        })
+    }
+}
+
+/// Split `master_secret` into groups of mnemonic shares, `groups` holding the
+/// member threshold and count of each group and `group_threshold` of the
+/// groups needed to recover it
+#[cfg(feature = "rand")]
+pub fn split(
+    master_secret: &[u8],
+    passphrase: &[u8],
+    group_threshold: u8,
+    groups: &[(u8, u8)],
+) -> Vec<Vec<Slip39Share>> {
+    split_with_rng(
+        master_secret,
+        passphrase,
+        group_threshold,
+        groups,
+        true,
+        0,
+        &mut rand::thread_rng(),
+    )
+}
+
+/// Like `split` with the identifier and share polynomials drawn from `rng`,
+/// and the extendable flag and iteration exponent chosen
+#[cfg(feature = "rand")]
+pub fn split_with_rng<R: Rng + ?Sized>(
+    master_secret: &[u8],
+    passphrase: &[u8],
+    group_threshold: u8,
+    groups: &[(u8, u8)],
+    extendable: bool,
+    iteration_exponent: u8,
+    rng: &mut R,
+) -> Vec<Vec<Slip39Share>> {
+    assert!(
+        master_secret.len() >= MIN_STRENGTH_BYTES && master_secret.len().is_multiple_of(2),
+        "master secret must be an even number of bytes, at least 16"
+    );
+    assert!(
+        passphrase.iter().all(|&b| (32..=126).contains(&b)),
+        "passphrase must be printable ASCII"
+    );
+    assert!(
+        iteration_exponent < 16,
+        "iteration exponent must fit in 4 bits"
+    );
+    assert!(
+        group_threshold >= 1 && group_threshold as usize <= groups.len(),
+        "group threshold must be between 1 and the group count"
+    );
+    assert!(
+        groups.len() <= MAX_SHARE_COUNT as usize,
+        "at most 16 groups"
+    );
+    for &(threshold, count) in groups {
+        assert!(
+            threshold >= 1 && threshold <= count && count <= MAX_SHARE_COUNT,
+            "member threshold must be between 1 and the member count, at most 16"
+        );
+        assert!(
+            threshold > 1 || count == 1,
+            "a member threshold of 1 needs a single member share"
+        );
+    }
+
+    let identifier = rng.gen_range(0..1 << 15);
+    let encrypted = feistel(
+        master_secret,
+        passphrase,
+        iteration_exponent,
+        identifier,
+        extendable,
+        false,
+    );
+    let group_count = groups.len() as u8;
+    split_secret(group_threshold, group_count, &encrypted, rng)
+        .into_iter()
+        .zip(groups)
+        .map(
+            |((group_index, group_secret), &(member_threshold, count))| {
+                split_secret(member_threshold, count, &group_secret, rng)
+                    .into_iter()
+                    .map(|(member_index, value)| Slip39Share {
+                        identifier,
+                        extendable,
+                        iteration_exponent,
+                        group_index,
+                        group_threshold,
+                        group_count,
+                        member_index,
+                        member_threshold,
+                        value,
+                    })
+                    .collect()
+            },
+        )
+        .collect()
+}
+
+/// The master secret behind `shares`, decrypted with `passphrase`
+pub fn combine(shares: &[Slip39Share], passphrase: &[u8]) -> Result<Vec<u8>, Slip39Error> {
+    let first = shares.first().ok_or(Slip39Error::NotEnoughShares)?;
+    let same_secret = |share: &Slip39Share| {
+        share.identifier == first.identifier
+            && share.extendable == first.extendable
+            && share.iteration_exponent == first.iteration_exponent
+            && share.group_threshold == first.group_threshold
+            && share.group_count == first.group_count
+            && share.value.len() == first.value.len()
+    };
+    if !shares.iter().all(same_secret) {
+        return Err(Slip39Error::Mismatch);
+    }
+
+    // the shares of each group, which must agree on the member threshold
+    let mut groups: Vec<(u8, Vec<&Slip39Share>)> = vec![];
+    for share in shares {
+        match groups
+            .iter_mut()
+            .find(|(index, _)| *index == share.group_index)
+        {
+            Some((_, members)) => {
+                if members[0].member_threshold != share.member_threshold
+                    || members.iter().any(|m| m.member_index == share.member_index)
+                {
+                    return Err(Slip39Error::Mismatch);
+                }
+                members.push(share);
+            }
+            None => groups.push((share.group_index, vec![share])),
+        }
+    }
+    if groups.len() < first.group_threshold as usize {
+        return Err(Slip39Error::NotEnoughShares);
+    }
+
+    let mut group_secrets = vec![];
+    for (group_index, members) in &groups {
+        let threshold = members[0].member_threshold;
+        if members.len() < threshold as usize {
+            return Err(Slip39Error::NotEnoughShares);
+        }
+        let points: Vec<(u8, &[u8])> = members
+            .iter()
+            .take(threshold as usize)
+            .map(|m| (m.member_index, m.value.as_slice()))
+            .collect();
+        group_secrets.push((*group_index, recover_secret(threshold, &points)?));
+    }
+    let points: Vec<(u8, &[u8])> = group_secrets
+        .iter()
+        .take(first.group_threshold as usize)
+        .map(|(index, secret)| (*index, secret.as_slice()))
+        .collect();
+    let encrypted = recover_secret(first.group_threshold, &points)?;
+    Ok(feistel(
+        &encrypted,
+        passphrase,
+        first.iteration_exponent,
+        first.identifier,
+        first.extendable,
+        true,
+    ))
+}
+
+/// `combine` straight from the mnemonics
+pub fn combine_mnemonics(mnemonics: &[&str], passphrase: &[u8]) -> Result<Vec<u8>, Slip39Error> {
+    let shares = mnemonics
+        .iter()
+        .map(|mnemonic| Slip39Share::from_mnemonic(mnemonic))
+        .collect::<Result<Vec<_>, _>>()?;
+    combine(&shares, passphrase)
+}
+
+/// The 1024 words, sorted and unique in their first four letters
+#[rustfmt::skip]
+pub const WORDLIST: [&str; 1024] = [
+    "academic", "acid", "acne", "acquire", "acrobat", "activity", "actress", "adapt", "adequate",
+    "adjust", "admit", "adorn", "adult", "advance", "advocate", "afraid", "again", "agency",
+    "agree", "aide", "aircraft", "airline", "airport", "ajar", "alarm", "album", "alcohol", "alien",
+    "alive", "alpha", "already", "alto", "aluminum", "always", "amazing", "ambition", "amount",
+    "amuse", "analysis", "anatomy", "ancestor", "ancient", "angel", "angry", "animal", "answer",
+    "antenna", "anxiety", "apart", "aquatic", "arcade", "arena", "argue", "armed", "artist",
+    "artwork", "aspect", "auction", "august", "aunt", "average", "aviation", "avoid", "award",
+    "away", "axis", "axle", "beam", "beard", "beaver", "become", "bedroom", "behavior", "being",
+    "believe", "belong", "benefit", "best", "beyond", "bike", "biology", "birthday", "bishop",
+    "black", "blanket", "blessing", "blimp", "blind", "blue", "body", "bolt", "boring", "born",
+    "both", "boundary", "bracelet", "branch", "brave", "breathe", "briefing", "broken", "brother",
+    "browser", "bucket", "budget", "building", "bulb", "bulge", "bumpy", "bundle", "burden",
+    "burning", "busy", "buyer", "cage", "calcium", "camera", "campus", "canyon", "capacity",
+    "capital", "capture", "carbon", "cards", "careful", "cargo", "carpet", "carve", "category",
+    "cause", "ceiling", "center", "ceramic", "champion", "change", "charity", "check", "chemical",
+    "chest", "chew", "chubby", "cinema", "civil", "class", "clay", "cleanup", "client", "climate",
+    "clinic", "clock", "clogs", "closet", "clothes", "club", "cluster", "coal", "coastal", "coding",
+    "column", "company", "corner", "costume", "counter", "course", "cover", "cowboy", "cradle",
+    "craft", "crazy", "credit", "cricket", "criminal", "crisis", "critical", "crowd", "crucial",
+    "crunch", "crush", "crystal", "cubic", "cultural", "curious", "curly", "custody", "cylinder",
+    "daisy", "damage", "dance", "darkness", "database", "daughter", "deadline", "deal", "debris",
+    "debut", "decent", "decision", "declare", "decorate", "decrease", "deliver", "demand",
+    "density", "deny", "depart", "depend", "depict", "deploy", "describe", "desert", "desire",
+    "desktop", "destroy", "detailed", "detect", "device", "devote", "diagnose", "dictate", "diet",
+    "dilemma", "diminish", "dining", "diploma", "disaster", "discuss", "disease", "dish", "dismiss",
+    "display", "distance", "dive", "divorce", "document", "domain", "domestic", "dominant", "dough",
+    "downtown", "dragon", "dramatic", "dream", "dress", "drift", "drink", "drove", "drug", "dryer",
+    "duckling", "duke", "duration", "dwarf", "dynamic", "early", "earth", "easel", "easy", "echo",
+    "eclipse", "ecology", "edge", "editor", "educate", "either", "elbow", "elder", "election",
+    "elegant", "element", "elephant", "elevator", "elite", "else", "email", "emerald", "emission",
+    "emperor", "emphasis", "employer", "empty", "ending", "endless", "endorse", "enemy", "energy",
+    "enforce", "engage", "enjoy", "enlarge", "entrance", "envelope", "envy", "epidemic", "episode",
+    "equation", "equip", "eraser", "erode", "escape", "estate", "estimate", "evaluate", "evening",
+    "evidence", "evil", "evoke", "exact", "example", "exceed", "exchange", "exclude", "excuse",
+    "execute", "exercise", "exhaust", "exotic", "expand", "expect", "explain", "express", "extend",
+    "extra", "eyebrow", "facility", "fact", "failure", "faint", "fake", "false", "family", "famous",
+    "fancy", "fangs", "fantasy", "fatal", "fatigue", "favorite", "fawn", "fiber", "fiction",
+    "filter", "finance", "findings", "finger", "firefly", "firm", "fiscal", "fishing", "fitness",
+    "flame", "flash", "flavor", "flea", "flexible", "flip", "float", "floral", "fluff", "focus",
+    "forbid", "force", "forecast", "forget", "formal", "fortune", "forward", "founder", "fraction",
+    "fragment", "frequent", "freshman", "friar", "fridge", "friendly", "frost", "froth", "frozen",
+    "fumes", "funding", "furl", "fused", "galaxy", "game", "garbage", "garden", "garlic",
+    "gasoline", "gather", "general", "genius", "genre", "genuine", "geology", "gesture", "glad",
+    "glance", "glasses", "glen", "glimpse", "goat", "golden", "graduate", "grant", "grasp",
+    "gravity", "gray", "greatest", "grief", "grill", "grin", "grocery", "gross", "group", "grownup",
+    "grumpy", "guard", "guest", "guilt", "guitar", "gums", "hairy", "hamster", "hand", "hanger",
+    "harvest", "have", "havoc", "hawk", "hazard", "headset", "health", "hearing", "heat", "helpful",
+    "herald", "herd", "hesitate", "hobo", "holiday", "holy", "home", "hormone", "hospital", "hour",
+    "huge", "human", "humidity", "hunting", "husband", "hush", "husky", "hybrid", "idea",
+    "identify", "idle", "image", "impact", "imply", "improve", "impulse", "include", "income",
+    "increase", "index", "indicate", "industry", "infant", "inform", "inherit", "injury", "inmate",
+    "insect", "inside", "install", "intend", "intimate", "invasion", "involve", "iris", "island",
+    "isolate", "item", "ivory", "jacket", "jerky", "jewelry", "join", "judicial", "juice", "jump",
+    "junction", "junior", "junk", "jury", "justice", "kernel", "keyboard", "kidney", "kind",
+    "kitchen", "knife", "knit", "laden", "ladle", "ladybug", "lair", "lamp", "language", "large",
+    "laser", "laundry", "lawsuit", "leader", "leaf", "learn", "leaves", "lecture", "legal",
+    "legend", "legs", "lend", "length", "level", "liberty", "library", "license", "lift", "likely",
+    "lilac", "lily", "lips", "liquid", "listen", "literary", "living", "lizard", "loan", "lobe",
+    "location", "losing", "loud", "loyalty", "luck", "lunar", "lunch", "lungs", "luxury", "lying",
+    "lyrics", "machine", "magazine", "maiden", "mailman", "main", "makeup", "making", "mama",
+    "manager", "mandate", "mansion", "manual", "marathon", "march", "market", "marvel", "mason",
+    "material", "math", "maximum", "mayor", "meaning", "medal", "medical", "member", "memory",
+    "mental", "merchant", "merit", "method", "metric", "midst", "mild", "military", "mineral",
+    "minister", "miracle", "mixed", "mixture", "mobile", "modern", "modify", "moisture", "moment",
+    "morning", "mortgage", "mother", "mountain", "mouse", "move", "much", "mule", "multiple",
+    "muscle", "museum", "music", "mustang", "nail", "national", "necklace", "negative", "nervous",
+    "network", "news", "nuclear", "numb", "numerous", "nylon", "oasis", "obesity", "object",
+    "observe", "obtain", "ocean", "often", "olympic", "omit", "oral", "orange", "orbit", "order",
+    "ordinary", "organize", "ounce", "oven", "overall", "owner", "paces", "pacific", "package",
+    "paid", "painting", "pajamas", "pancake", "pants", "papa", "paper", "parcel", "parking",
+    "party", "patent", "patrol", "payment", "payroll", "peaceful", "peanut", "peasant", "pecan",
+    "penalty", "pencil", "percent", "perfect", "permit", "petition", "phantom", "pharmacy", "photo",
+    "phrase", "physics", "pickup", "picture", "piece", "pile", "pink", "pipeline", "pistol",
+    "pitch", "plains", "plan", "plastic", "platform", "playoff", "pleasure", "plot", "plunge",
+    "practice", "prayer", "preach", "predator", "pregnant", "premium", "prepare", "presence",
+    "prevent", "priest", "primary", "priority", "prisoner", "privacy", "prize", "problem",
+    "process", "profile", "program", "promise", "prospect", "provide", "prune", "public", "pulse",
+    "pumps", "punish", "puny", "pupal", "purchase", "purple", "python", "quantity", "quarter",
+    "quick", "quiet", "race", "racism", "radar", "railroad", "rainbow", "raisin", "random",
+    "ranked", "rapids", "raspy", "reaction", "realize", "rebound", "rebuild", "recall", "receiver",
+    "recover", "regret", "regular", "reject", "relate", "remember", "remind", "remove", "render",
+    "repair", "repeat", "replace", "require", "rescue", "research", "resident", "response",
+    "result", "retailer", "retreat", "reunion", "revenue", "review", "reward", "rhyme", "rhythm",
+    "rich", "rival", "river", "robin", "rocky", "romantic", "romp", "roster", "round", "royal",
+    "ruin", "ruler", "rumor", "sack", "safari", "salary", "salon", "salt", "satisfy", "satoshi",
+    "saver", "says", "scandal", "scared", "scatter", "scene", "scholar", "science", "scout",
+    "scramble", "screw", "script", "scroll", "seafood", "season", "secret", "security", "segment",
+    "senior", "shadow", "shaft", "shame", "shaped", "sharp", "shelter", "sheriff", "short",
+    "should", "shrimp", "sidewalk", "silent", "silver", "similar", "simple", "single", "sister",
+    "skin", "skunk", "slap", "slavery", "sled", "slice", "slim", "slow", "slush", "smart", "smear",
+    "smell", "smirk", "smith", "smoking", "smug", "snake", "snapshot", "sniff", "society",
+    "software", "soldier", "solution", "soul", "source", "space", "spark", "speak", "species",
+    "spelling", "spend", "spew", "spider", "spill", "spine", "spirit", "spit", "spray", "sprinkle",
+    "square", "squeeze", "stadium", "staff", "standard", "starting", "station", "stay", "steady",
+    "step", "stick", "stilt", "story", "strategy", "strike", "style", "subject", "submit", "sugar",
+    "suitable", "sunlight", "superior", "surface", "surprise", "survive", "sweater", "swimming",
+    "swing", "switch", "symbolic", "sympathy", "syndrome", "system", "tackle", "tactics", "tadpole",
+    "talent", "task", "taste", "taught", "taxi", "teacher", "teammate", "teaspoon", "temple",
+    "tenant", "tendency", "tension", "terminal", "testify", "texture", "thank", "that", "theater",
+    "theory", "therapy", "thorn", "threaten", "thumb", "thunder", "ticket", "tidy", "timber",
+    "timely", "ting", "tofu", "together", "tolerate", "total", "toxic", "tracks", "traffic",
+    "training", "transfer", "trash", "traveler", "treat", "trend", "trial", "tricycle", "trip",
+    "triumph", "trouble", "true", "trust", "twice", "twin", "type", "typical", "ugly", "ultimate",
+    "umbrella", "uncover", "undergo", "unfair", "unfold", "unhappy", "union", "universe", "unkind",
+    "unknown", "unusual", "unwrap", "upgrade", "upstairs", "username", "usher", "usual", "valid",
+    "valuable", "vampire", "vanish", "various", "vegan", "velvet", "venture", "verdict", "verify",
+    "very", "veteran", "vexed", "victim", "video", "view", "vintage", "violence", "viral",
+    "visitor", "visual", "vitamins", "vocal", "voice", "volume", "voter", "voting", "walnut",
+    "warmth", "warn", "watch", "wavy", "wealthy", "weapon", "webcam", "welcome", "welfare",
+    "western", "width", "wildlife", "window", "wine", "wireless", "wisdom", "withdraw", "wits",
+    "wolf", "woman", "work", "worthy", "wrap", "wrist", "writing", "wrote", "year", "yelp", "yield",
+    "yoga", "zero",
+];
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    // from the SLIP-39 test vectors, all with the passphrase TREZOR
+    const PASSPHRASE: &[u8] = b"TREZOR";
+
+    #[test]
+    fn test_vectors() {
+        let single = "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard";
+        assert_eq!(
+            hex::encode(combine_mnemonics(&[single], PASSPHRASE).unwrap()),
+            "bb54aac4b89dc868ba37d9cc21b2cece"
+        );
+        let share = Slip39Share::from_mnemonic(single).unwrap();
+        assert_eq!(share.to_mnemonic(), single);
+
+        let mistyped = single.replace("keyboard", "kidney");
+        assert_eq!(
+            Slip39Share::from_mnemonic(&mistyped),
+            Err(Slip39Error::Checksum)
+        );
+        assert_eq!(
+            Slip39Share::from_mnemonic(&single.replace("coal", "coat")),
+            Err(Slip39Error::UnknownWord("coat".into()))
+        );
+
+        // two of three member shares
+        let shares = [
+            "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+            "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+        ];
+        assert_eq!(
+            hex::encode(combine_mnemonics(&shares, PASSPHRASE).unwrap()),
+            "b43ceb7e57a0ea8766221624d01b0864"
+        );
+        assert_eq!(
+            combine_mnemonics(&shares[..1], PASSPHRASE),
+            Err(Slip39Error::NotEnoughShares)
+        );
+
+        let long = "theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck";
+        assert_eq!(
+            hex::encode(combine_mnemonics(&[long], PASSPHRASE).unwrap()),
+            "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92"
+        );
+    }
+
+    #[test]
+    fn test_split_combine() {
+        let secret = b"sixteen byte key".to_vec();
+        let mut rng = StdRng::seed_from_u64(3);
+        // two of: the owner's single share, 2 of 3 family members, 3 of 5 friends
+        let groups = split_with_rng(
+            &secret,
+            PASSPHRASE,
+            2,
+            &[(1, 1), (2, 3), (3, 5)],
+            true,
+            0,
+            &mut rng,
+        );
+        assert_eq!(
+            groups.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![1, 3, 5]
+        );
+        let mnemonics: Vec<Vec<String>> = groups
+            .iter()
+            .map(|group| group.iter().map(Slip39Share::to_mnemonic).collect())
+            .collect();
+        assert_eq!(mnemonics[0][0].split(' ').count(), 20);
+
+        let owner_and_family = [
+            mnemonics[0][0].as_str(),
+            mnemonics[1][2].as_str(),
+            mnemonics[1][0].as_str(),
+        ];
+        assert_eq!(
+            combine_mnemonics(&owner_and_family, PASSPHRASE),
+            Ok(secret.clone())
+        );
+        let family_and_friends: Vec<&str> = [&mnemonics[1][1], &mnemonics[1][2]]
+            .into_iter()
+            .chain(&mnemonics[2][1..4])
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            combine_mnemonics(&family_and_friends, PASSPHRASE),
+            Ok(secret.clone())
+        );
+        assert_ne!(
+            combine_mnemonics(&family_and_friends, b""),
+            Ok(secret.clone())
+        );
+
+        // one family member short
+        assert_eq!(
+            combine_mnemonics(&owner_and_family[..2], PASSPHRASE),
+            Err(Slip39Error::NotEnoughShares)
+        );
+        // a member share with a flipped bit slips past its checksum here, but
+        // not past the digest
+        let mut tampered = groups[1][0].clone();
+        tampered.value[0] ^= 1;
+        assert_eq!(
+            combine(
+                &[groups[0][0].clone(), groups[1][2].clone(), tampered],
+                PASSPHRASE
+            ),
+            Err(Slip39Error::Digest)
+        );
+        // shares of another secret
+        let other = split_with_rng(&secret, PASSPHRASE, 1, &[(1, 1)], true, 0, &mut rng);
+        assert_eq!(
+            combine(&[groups[0][0].clone(), other[0][0].clone()], PASSPHRASE),
+            Err(Slip39Error::Mismatch)
+        );
+    }
+}
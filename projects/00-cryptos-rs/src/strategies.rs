@@ -0,0 +1,124 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::amount::Amount;
+use crate::block::Block;
+use crate::ru256::RU256;
+use crate::secp256k1::{Point, SECP256K1};
+use crate::signature::Signature;
+use crate::transaction::{Cmd, Script, Tx, TxIn, TxOut};
+
+// proptest strategies for the crate's types, shared by the property tests
+// living next to the code they exercise.
+
+/// Any 256 bit value
+pub fn ru256() -> impl Strategy<Value = RU256> {
+    any::<[u8; 32]>().prop_map(|bytes| RU256::from_bytes(&bytes))
+}
+
+/// A non-zero modulus
+pub fn modulus() -> impl Strategy<Value = RU256> {
+    ru256().prop_filter("modulus must be non-zero", |p| !p.is_zero())
+}
+
+/// A curve point `k * G` for a small `k`, scalar multiplication is slow enough
+/// that large scalars would make every case take seconds
+pub fn point() -> impl Strategy<Value = Point> {
+    (1u64..1024).prop_map(|k| SECP256K1::public_key(&RU256::from_u64(k)))
+}
+
+pub fn signature() -> impl Strategy<Value = Signature> {
+    (ru256(), ru256()).prop_map(|(r, s)| Signature { r, s })
+}
+
+/// Opcodes and non-empty pushes, some long enough for OP_PUSHDATA2. Push
+/// opcodes can't appear as a bare `Cmd::Op`, they would be read back as pushes.
+fn cmd() -> impl Strategy<Value = Cmd> {
+    prop_oneof![
+        any::<u8>()
+            .prop_filter("push opcode", |op| !(0x01..=0x4e).contains(op))
+            .prop_map(Cmd::Op),
+        vec(any::<u8>(), 1..80).prop_map(Cmd::Push),
+        vec(any::<u8>(), 250..300).prop_map(Cmd::Push),
+    ]
+}
+
+pub fn script() -> impl Strategy<Value = Script> {
+    vec(cmd(), 0..8).prop_map(Script::new)
+}
+
+fn tx_in(segwit: bool) -> impl Strategy<Value = TxIn> {
+    let witness_items = if segwit { 0..4usize } else { 0..1usize };
+    (
+        vec(any::<u8>(), 32),
+        any::<u32>(),
+        script(),
+        any::<u32>(),
+        vec(vec(any::<u8>(), 0..80), witness_items),
+    )
+        .prop_map(
+            |(prev_tx, prev_index, script_sig, sequence, witness)| TxIn {
+                prev_tx,
+                prev_index,
+                script_sig,
+                sequence,
+                witness,
+                ..Default::default()
+            },
+        )
+}
+
+fn tx_out() -> impl Strategy<Value = TxOut> {
+    (any::<u64>(), script()).prop_map(|(sat, script_pubkey)| TxOut {
+        amount: Amount::from_sat(sat),
+        script_pubkey,
+    })
+}
+
+/// Transactions with at least one input, a zero input count would be read back
+/// as the segwit marker
+pub fn tx() -> impl Strategy<Value = Tx> {
+    any::<bool>().prop_flat_map(|segwit| {
+        (
+            any::<u32>(),
+            vec(tx_in(segwit), 1..4),
+            vec(tx_out(), 0..4),
+            any::<u32>(),
+        )
+            .prop_map(move |(version, tx_ins, tx_outs, locktime)| Tx {
+                version,
+                tx_ins,
+                tx_outs,
+                locktime,
+                segwit,
+            })
+    })
+}
+
+/// A block header without transactions
+pub fn block_header() -> impl Strategy<Value = Block> {
+    (
+        any::<u32>(),
+        vec(any::<u8>(), 32),
+        vec(any::<u8>(), 32),
+        any::<u32>(),
+        vec(any::<u8>(), 4),
+        vec(any::<u8>(), 4),
+    )
+        .prop_map(
+            |(version, prev_block, merkle_root, timestamp, bits, nonce)| Block {
+                version,
+                prev_block,
+                merkle_root,
+                timestamp,
+                bits,
+                nonce,
+                txs: vec![],
+            },
+        )
+}
+
+/// A block with transactions
+pub fn block() -> impl Strategy<Value = Block> {
+    (block_header(), vec(tx(), 0..4)).prop_map(|(header, txs)| Block { txs, ..header })
+}
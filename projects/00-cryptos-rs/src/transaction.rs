@@ -1,10 +1,18 @@
 use std::collections::HashMap;
-use std::io::{Cursor, Read};
+use std::fmt;
 
+use serde_json::{json, Value};
+
+use crate::amount::Amount;
+use crate::bech32::{decode_segwit_address, encode_segwit_address};
 use crate::bitcoin::BITCOIN;
-use crate::keys::PublicKey;
-use crate::ripemd160::ripemd160;
-use crate::sha256::{hash256, sha256};
+use crate::encoding::{
+    try_read_varint, try_take, try_take_array, Decodable, DecodingError, Encodable, TryDecodable,
+};
+use crate::hashes::hash160;
+use crate::keys::{b58check_decode, b58check_encode, pkb_hash_to_address, PublicKey};
+use crate::policy::ScriptType;
+use crate::sha256::hash256;
 use crate::signature::{verify_ecdsa, Signature};
 use crate::utils;
 
@@ -39,14 +47,44 @@ impl TxFetcher {
             raw
         };
 
-        let mut cursor = Cursor::new(&raw);
-        let tx = Tx::decode(&mut cursor);
+        let tx = Tx::decode_all(&raw);
         assert_eq!(tx.id(), tx_id);
         tx
     }
 }
 
-#[derive(Debug, Default)]
+/// The outputs spent by a transaction's inputs, keyed by each input's
+/// `(prev_tx, prev_index)`
+pub type Prevouts = HashMap<(Vec<u8>, u32), TxOut>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeeError {
+    /// An input's spent output isn't in the prevouts
+    MissingPrevout { prev_tx: Vec<u8>, prev_index: u32 },
+    /// The inputs or outputs add up to more than fits in a u64
+    Overflow,
+    /// The outputs are worth more than the inputs
+    Negative { input: Amount, output: Amount },
+}
+
+impl fmt::Display for FeeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeError::MissingPrevout {
+                prev_tx,
+                prev_index,
+            } => write!(f, "missing prevout {}:{}", hex::encode(prev_tx), prev_index),
+            FeeError::Overflow => write!(f, "amounts overflow"),
+            FeeError::Negative { input, output } => {
+                write!(f, "outputs ({}) exceed inputs ({})", output, input)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FeeError {}
+
+#[derive(Debug, Default, Clone)]
 pub struct Tx {
     pub version: u32,
     pub tx_ins: Vec<TxIn>,
@@ -56,49 +94,108 @@ pub struct Tx {
 }
 
 impl Tx {
-    pub fn decode(s: &mut Cursor<&Vec<u8>>) -> Self {
-        let version = utils::read_u32(s).unwrap();
-        let segwit = utils::read_u8(s).unwrap() == 0;
-        let tx_in_count = utils::read_varint(s).unwrap();
-        let tx_ins = (0..tx_in_count).map(|_| TxIn::decode(s)).collect();
-        let tx_out_count = utils::read_varint(s).unwrap();
-        let tx_outs = (0..tx_out_count).map(|_| TxOut::decode(s)).collect();
-        let locktime = utils::read_u32(s).unwrap();
-        Tx {
-            version,
-            tx_ins,
-            tx_outs,
-            locktime,
-            segwit,
-        }
+    /// Serialization without the segwit marker and witnesses, which is what
+    /// the txid commits to
+    pub fn encode_legacy(&self) -> Vec<u8> {
+        self.encode_with_witness(false)
     }
 
-    pub fn encode(&self, force_legacy: bool, _sig_index: Option<usize>) -> Vec<u8> {
+    fn encode_with_witness(&self, with_witness: bool) -> Vec<u8> {
+        let with_witness = with_witness && self.segwit;
         let mut result = vec![];
         result.extend(&self.version.to_le_bytes());
-        if self.segwit && !force_legacy {
-            result.push(0);
+        if with_witness {
+            result.extend([0x00, 0x01]);
         }
         result.extend(utils::encode_varint(self.tx_ins.len() as u64));
         for tx_in in &self.tx_ins {
-            result.extend(tx_in.encode(None));
+            result.extend(tx_in.encode());
         }
         result.extend(utils::encode_varint(self.tx_outs.len() as u64));
         for tx_out in &self.tx_outs {
             result.extend(tx_out.encode());
         }
+        if with_witness {
+            for tx_in in &self.tx_ins {
+                result.extend(utils::encode_varint(tx_in.witness.len() as u64));
+                for item in &tx_in.witness {
+                    result.extend(utils::encode_varint(item.len() as u64));
+                    result.extend(item);
+                }
+            }
+        }
         result.extend(&self.locktime.to_le_bytes());
         result
     }
 
     pub fn id(&self) -> String {
-        hex::encode(hash256(self.encode(true, None)))
+        // txids are displayed in reverse byte order, same as block ids
+        let mut result = hash256(self.encode_legacy());
+        result.reverse();
+        hex::encode(result)
     }
 
-    pub fn fee(&self) -> u64 {
-        let input_total: u64 = self.tx_ins.iter().map(|tx_in| tx_in.value()).sum();
-        let output_total: u64 = self.tx_outs.iter().map(|tx_out| tx_out.amount).sum();
-        input_total - output_total
+    /// Hash of the full serialization, witnesses included. Same as the txid for
+    /// a legacy transaction.
+    pub fn wtxid(&self) -> String {
+        let mut result = hash256(self.encode());
+        result.reverse();
+        hex::encode(result)
+    }
+
+    /// Non-witness bytes count 4 weight units, witness bytes 1
+    pub fn weight(&self) -> usize {
+        self.encode_legacy().len() * 3 + self.encode().len()
+    }
+
+    /// Virtual size in vbytes, which fee rates are quoted in
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+
+    /// Inputs minus outputs, with the spent outputs looked up in `prevouts`.
+    /// A coinbase spends no outputs and pays no fee.
+    pub fn fee(&self, prevouts: &Prevouts) -> Result<Amount, FeeError> {
+        if self.is_coinbase() {
+            return Ok(Amount::ZERO);
+        }
+        let mut inputs = vec![];
+        for tx_in in &self.tx_ins {
+            let prevout = prevouts
+                .get(&(tx_in.prev_tx.clone(), tx_in.prev_index))
+                .ok_or_else(|| FeeError::MissingPrevout {
+                    prev_tx: tx_in.prev_tx.clone(),
+                    prev_index: tx_in.prev_index,
+                })?;
+            inputs.push(prevout.amount);
+        }
+        let input = Amount::checked_sum(inputs).ok_or(FeeError::Overflow)?;
+        let output = Amount::checked_sum(self.tx_outs.iter().map(|tx_out| tx_out.amount))
+            .ok_or(FeeError::Overflow)?;
+        input
+            .checked_sub(output)
+            .ok_or(FeeError::Negative { input, output })
+    }
+
+    /// Fetch the outputs spent by the inputs, each previous tx only once. A
+    /// previous tx without the output spent is a missing prevout.
+    pub fn fetch_prevouts(&self) -> Result<Prevouts, FeeError> {
+        let mut txs: HashMap<Vec<u8>, Tx> = HashMap::new();
+        let mut prevouts = Prevouts::new();
+        for tx_in in &self.tx_ins {
+            let prev_tx = txs
+                .entry(tx_in.prev_tx.clone())
+                .or_insert_with(|| TxFetcher::fetch(&tx_in.prev_txid(), &tx_in.net));
+            let tx_out = prev_tx
+                .tx_outs
+                .get(tx_in.prev_index as usize)
+                .ok_or_else(|| FeeError::MissingPrevout {
+                    prev_tx: tx_in.prev_tx.clone(),
+                    prev_index: tx_in.prev_index,
+                })?;
+            prevouts.insert((tx_in.prev_tx.clone(), tx_in.prev_index), tx_out.clone());
+        }
+        Ok(prevouts)
     }
 
     pub fn validate(&self) -> bool {
@@ -107,8 +204,9 @@ impl Tx {
         }
 
         for (i, tx_in) in self.tx_ins.iter().enumerate() {
-            let mod_tx_enc = self.encode(false, Some(i));
-            let combined = tx_in.script_sig.clone() + tx_in.script_pubkey();
+            let script_pubkey = tx_in.script_pubkey();
+            let mod_tx_enc = self.sig_message(i, &script_pubkey);
+            let combined = tx_in.script_sig.clone() + script_pubkey;
             if !combined.evaluate(&mod_tx_enc) {
                 return false;
             }
@@ -117,23 +215,209 @@ impl Tx {
         true
     }
 
+    /// What a SIGHASH_ALL signature of legacy input `input` commits to: the
+    /// transaction with every scriptSig emptied except that input's, which is
+    /// replaced by the `script_pubkey` it spends, then the sighash type.
+    /// `sign_ecdsa` hashes it with hash256.
+    pub fn sig_message(&self, input: usize, script_pubkey: &Script) -> Vec<u8> {
+        assert!(input < self.tx_ins.len(), "input {} out of range", input);
+        let mut tx = self.clone();
+        for (i, tx_in) in tx.tx_ins.iter_mut().enumerate() {
+            tx_in.script_sig = match i == input {
+                true => script_pubkey.clone(),
+                false => Script::default(),
+            };
+        }
+        let mut message = tx.encode_legacy();
+        message.extend((SIGHASH_ALL as u32).to_le_bytes());
+        message
+    }
+
+    /// What a SIGHASH_ALL signature of segwit v0 input `input` commits to
+    /// (BIP143): the version, the hashes of every outpoint, sequence and
+    /// output, then the input's outpoint, its `script_code`, the `amount` it
+    /// spends and its sequence, the locktime and the sighash type. The amount
+    /// is what lets offline signers trust the fee, and hashing the rest once
+    /// keeps it from growing with the number of inputs.
+    pub fn segwit_sig_message(
+        &self,
+        input: usize,
+        script_code: &Script,
+        amount: Amount,
+    ) -> Vec<u8> {
+        assert!(input < self.tx_ins.len(), "input {} out of range", input);
+        let mut prevouts = vec![];
+        let mut sequences = vec![];
+        for tx_in in &self.tx_ins {
+            prevouts.extend(&tx_in.prev_tx);
+            prevouts.extend(tx_in.prev_index.to_le_bytes());
+            sequences.extend(tx_in.sequence.to_le_bytes());
+        }
+        let outputs = self.tx_outs.iter().flat_map(TxOut::encode).collect();
+
+        let tx_in = &self.tx_ins[input];
+        let mut message = self.version.to_le_bytes().to_vec();
+        message.extend(hash256(prevouts));
+        message.extend(hash256(sequences));
+        message.extend(&tx_in.prev_tx);
+        message.extend(tx_in.prev_index.to_le_bytes());
+        message.extend(script_code.encode());
+        message.extend(amount.to_sat().to_le_bytes());
+        message.extend(tx_in.sequence.to_le_bytes());
+        message.extend(hash256(outputs));
+        message.extend(self.locktime.to_le_bytes());
+        message.extend((SIGHASH_ALL as u32).to_le_bytes());
+        message
+    }
+
+    /// Whether the transaction may go in the block at `height`. A locktime
+    /// below 500,000,000 is a height, above it a time, which is compared to
+    /// the median time past of the previous block (BIP113) rather than the
+    /// block's own timestamp that its miner picks. Inputs with a final
+    /// sequence turn the lock off.
+    pub fn is_final(&self, height: u32, median_time_past: u32) -> bool {
+        if self.locktime == 0 {
+            return true;
+        }
+        let now = match self.locktime < LOCKTIME_THRESHOLD {
+            true => height,
+            false => median_time_past,
+        };
+        self.locktime < now || self.tx_ins.iter().all(|tx_in| tx_in.sequence == 0xffffffff)
+    }
+
     pub fn is_coinbase(&self) -> bool {
         self.tx_ins.len() == 1
             && self.tx_ins[0].prev_tx == vec![0; 32]
             && self.tx_ins[0].prev_index == 0xffffffff
     }
 
+    /// The transaction as Bitcoin Core's `decoderawtransaction` shows it, with
+    /// addresses for `net`
+    pub fn to_json(&self, net: &str) -> Value {
+        let vin: Vec<Value> = self
+            .tx_ins
+            .iter()
+            .map(|tx_in| {
+                let mut vin = if self.is_coinbase() {
+                    json!({"coinbase": hex::encode(tx_in.script_sig.to_bytes())})
+                } else {
+                    let mut txid = tx_in.prev_tx.clone();
+                    txid.reverse();
+                    json!({
+                        "txid": hex::encode(txid),
+                        "vout": tx_in.prev_index,
+                        "scriptSig": {
+                            "asm": tx_in.script_sig.asm(),
+                            "hex": hex::encode(tx_in.script_sig.to_bytes()),
+                        },
+                    })
+                };
+                if !tx_in.witness.is_empty() {
+                    vin["txinwitness"] = tx_in.witness.iter().map(hex::encode).collect();
+                }
+                vin["sequence"] = json!(tx_in.sequence);
+                vin
+            })
+            .collect();
+        let vout: Vec<Value> = self
+            .tx_outs
+            .iter()
+            .enumerate()
+            .map(|(n, tx_out)| {
+                let script_pubkey = &tx_out.script_pubkey;
+                let mut script_json = json!({
+                    "asm": script_pubkey.asm(),
+                    "hex": hex::encode(script_pubkey.to_bytes()),
+                });
+                if let Some(address) = script_pubkey.address(net) {
+                    script_json["address"] = json!(address);
+                }
+                script_json["type"] = json!(ScriptType::of(script_pubkey).name());
+                json!({
+                    "value": tx_out.amount.to_btc(),
+                    "n": n,
+                    "scriptPubKey": script_json,
+                })
+            })
+            .collect();
+        json!({
+            "txid": self.id(),
+            "hash": self.wtxid(),
+            "version": self.version,
+            "size": self.encode().len(),
+            "vsize": self.vsize(),
+            "weight": self.weight(),
+            "locktime": self.locktime,
+            "vin": vin,
+            "vout": vout,
+        })
+    }
+
+    /// BIP34 height, the first push of the coinbase scriptSig as a little
+    /// endian number, or OP_1..OP_16 for the smallest heights
     pub fn coinbase_height(&self) -> Option<u32> {
-        if self.is_coinbase() {
-            Some(u32::from_le_bytes(
-                self.tx_ins[0].script_sig.cmds[0]
-                    .clone()
-                    .try_into()
-                    .unwrap(),
-            ))
-        } else {
-            None
+        if !self.is_coinbase() {
+            return None;
         }
+        match self.tx_ins[0].script_sig.instructions().next()?.ok()? {
+            Cmd::Op(op) if (OP_1..=OP_16).contains(&op) => Some((op - OP_1 + 1) as u32),
+            Cmd::Push(height) if height.len() <= 4 => {
+                let mut bytes = [0u8; 4];
+                bytes[..height.len()].copy_from_slice(&height);
+                Some(u32::from_le_bytes(bytes))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Full serialization, with the segwit marker and witnesses for segwit
+/// transactions
+impl Encodable for Tx {
+    fn encode(&self) -> Vec<u8> {
+        self.encode_with_witness(true)
+    }
+}
+
+impl TryDecodable for Tx {
+    fn try_decode(bytes: &mut &[u8]) -> Result<Self, DecodingError> {
+        let version = u32::from_le_bytes(try_take_array(bytes)?);
+        // segwit transactions have a 0x00 marker and 0x01 flag before the inputs,
+        // otherwise the marker byte is the input count
+        let segwit = bytes.first() == Some(&0);
+        if segwit && try_take(bytes, 2)?[1] != 0x01 {
+            return Err(DecodingError::Invalid("segwit flag"));
+        }
+        // counts come from the input, so vectors grow as items decode instead
+        // of being allocated up front
+        let tx_in_count = try_read_varint(bytes)?;
+        let mut tx_ins = (0..tx_in_count)
+            .map(|_| TxIn::try_decode(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tx_out_count = try_read_varint(bytes)?;
+        let tx_outs = (0..tx_out_count)
+            .map(|_| TxOut::try_decode(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        if segwit {
+            for tx_in in tx_ins.iter_mut() {
+                let num_items = try_read_varint(bytes)?;
+                for _ in 0..num_items {
+                    let item_len = try_read_varint(bytes)?;
+                    let item_len =
+                        usize::try_from(item_len).map_err(|_| DecodingError::UnexpectedEnd)?;
+                    tx_in.witness.push(try_take(bytes, item_len)?.to_vec());
+                }
+            }
+        }
+        let locktime = u32::from_le_bytes(try_take_array(bytes)?);
+        Ok(Tx {
+            version,
+            tx_ins,
+            tx_outs,
+            locktime,
+            segwit,
+        })
     }
 }
 
@@ -148,23 +432,29 @@ pub struct TxIn {
 }
 
 impl TxIn {
-    pub fn decode(s: &mut Cursor<&Vec<u8>>) -> Self {
-        let mut prev_tx = vec![0; 32];
-        s.read_exact(&mut prev_tx).unwrap();
-        let prev_index = utils::read_u32(s).unwrap();
-        let script_sig = Script::decode(s);
-        let sequence = utils::read_u32(s).unwrap();
-        TxIn {
-            prev_tx,
-            prev_index,
-            script_sig,
-            sequence,
-            witness: vec![],
-            net: String::new(),
-        }
+    pub fn value(&self) -> Amount {
+        // Look up the amount in the previous transaction
+        let tx = TxFetcher::fetch(&self.prev_txid(), &self.net);
+        tx.tx_outs[self.prev_index as usize].amount
     }
 
-    pub fn encode(&self, script_override: Option<bool>) -> Vec<u8> {
+    pub fn script_pubkey(&self) -> Script {
+        // Look up the script_pubkey in the previous transaction
+        let tx = TxFetcher::fetch(&self.prev_txid(), &self.net);
+        tx.tx_outs[self.prev_index as usize].script_pubkey.clone()
+    }
+
+    /// The txid of the previous transaction, which the fetcher takes.
+    /// prev_tx is in internal byte order.
+    fn prev_txid(&self) -> String {
+        let mut txid = self.prev_tx.clone();
+        txid.reverse();
+        hex::encode(txid)
+    }
+}
+
+impl Encodable for TxIn {
+    fn encode(&self) -> Vec<u8> {
         let mut result = vec![];
         result.extend(&self.prev_tx);
         result.extend(&self.prev_index.to_le_bytes());
@@ -172,124 +462,904 @@ impl TxIn {
         result.extend(&self.sequence.to_le_bytes());
         result
     }
+}
 
-    pub fn value(&self) -> u64 {
-        // Look up the amount in the previous transaction
-        let tx = TxFetcher::fetch(&hex::encode(&self.prev_tx), &self.net);
-        tx.tx_outs[self.prev_index as usize].amount
-    }
-
-    pub fn script_pubkey(&self) -> Script {
-        // Look up the script_pubkey in the previous transaction
-        let tx = TxFetcher::fetch(&hex::encode(&self.prev_tx), &self.net);
-        tx.tx_outs[self.prev_index as usize].script_pubkey.clone()
+impl TryDecodable for TxIn {
+    fn try_decode(bytes: &mut &[u8]) -> Result<Self, DecodingError> {
+        let prev_tx = try_take(bytes, 32)?.to_vec();
+        let prev_index = u32::from_le_bytes(try_take_array(bytes)?);
+        let script_sig = Script::try_decode(bytes)?;
+        let sequence = u32::from_le_bytes(try_take_array(bytes)?);
+        Ok(TxIn {
+            prev_tx,
+            prev_index,
+            script_sig,
+            sequence,
+            witness: vec![],
+            net: String::new(),
+        })
     }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct TxOut {
-    amount: u64,
-    script_pubkey: Script,
+    pub amount: Amount,
+    pub script_pubkey: Script,
+}
+
+impl Encodable for TxOut {
+    fn encode(&self) -> Vec<u8> {
+        let mut result = vec![];
+        result.extend(&self.amount.to_sat().to_le_bytes());
+        result.extend(self.script_pubkey.encode());
+        result
+    }
 }
 
-impl TxOut {
-    pub fn decode(s: &mut Cursor<&Vec<u8>>) -> Self {
-        let amount = utils::read_u64(s).unwrap();
-        let script_pubkey = Script::decode(s);
-        TxOut {
+impl TryDecodable for TxOut {
+    fn try_decode(bytes: &mut &[u8]) -> Result<Self, DecodingError> {
+        let amount = Amount::from_sat(u64::from_le_bytes(try_take_array(bytes)?));
+        let script_pubkey = Script::try_decode(bytes)?;
+        Ok(TxOut {
             amount,
             script_pubkey,
+        })
+    }
+}
+
+/// Builds unsigned transactions output by output
+#[derive(Debug, Default)]
+pub struct TxBuilder {
+    tx: Tx,
+    net: String,
+}
+
+impl TxBuilder {
+    pub fn new(net: &str) -> Self {
+        assert!(
+            net == "main" || net == "test",
+            "{} is not a valid net type, should be main|test",
+            net
+        );
+        TxBuilder {
+            tx: Tx {
+                version: 1,
+                ..Default::default()
+            },
+            net: net.to_string(),
         }
     }
 
-    pub fn encode(&self) -> Vec<u8> {
-        let mut result = vec![];
-        result.extend(&self.amount.to_le_bytes());
-        result.extend(self.script_pubkey.encode());
-        result
+    pub fn add_input(mut self, prev_tx: Vec<u8>, prev_index: u32) -> Self {
+        self.tx.tx_ins.push(TxIn {
+            prev_tx,
+            prev_index,
+            sequence: 0xffffffff,
+            net: self.net.clone(),
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn add_output(mut self, amount: Amount, script_pubkey: Script) -> Self {
+        self.tx.tx_outs.push(TxOut {
+            amount,
+            script_pubkey,
+        });
+        self
+    }
+
+    /// Zero value OP_RETURN output carrying `data`, relay policy allows only
+    /// one per transaction
+    pub fn add_data_output(self, data: &[u8]) -> Self {
+        assert!(
+            self.tx
+                .tx_outs
+                .iter()
+                .all(|tx_out| tx_out.script_pubkey.op_return_data().is_none()),
+            "transaction already has a data output"
+        );
+        self.add_output(Amount::ZERO, Script::op_return(data))
+    }
+
+    pub fn locktime(mut self, locktime: u32) -> Self {
+        self.tx.locktime = locktime;
+        self
+    }
+
+    pub fn build(self) -> Tx {
+        self.tx
     }
 }
 
+const OP_0: u8 = 0x00;
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1NEGATE: u8 = 0x4f;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
 const OP_DUP: u8 = 0x76;
 const OP_HASH160: u8 = 0xa9;
+const OP_EQUAL: u8 = 0x87;
 const OP_EQUALVERIFY: u8 = 0x88;
 const OP_CHECKSIG: u8 = 0xac;
+const OP_RETURN: u8 = 0x6a;
 
-#[derive(Debug, Default, Clone)]
+/// Sign every input and output, the sighash type of nearly all signatures
+pub const SIGHASH_ALL: u8 = 0x01;
+
+/// Locktimes below are block heights, above unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Largest OP_RETURN payload nodes relay by default
+pub const MAX_OP_RETURN_DATA: usize = 80;
+
+/// Name of a non-push opcode
+pub(crate) fn opcode_name(op: u8) -> &'static str {
+    #[rustfmt::skip]
+    const NAMES: [&str; 0xbb - 0x50] = [
+        "OP_RESERVED", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14",
+        "15", "16", "OP_NOP", "OP_VER", "OP_IF", "OP_NOTIF", "OP_VERIF", "OP_VERNOTIF", "OP_ELSE",
+        "OP_ENDIF", "OP_VERIFY", "OP_RETURN", "OP_TOALTSTACK", "OP_FROMALTSTACK", "OP_2DROP",
+        "OP_2DUP", "OP_3DUP", "OP_2OVER", "OP_2ROT", "OP_2SWAP", "OP_IFDUP", "OP_DEPTH", "OP_DROP",
+        "OP_DUP", "OP_NIP", "OP_OVER", "OP_PICK", "OP_ROLL", "OP_ROT", "OP_SWAP", "OP_TUCK",
+        "OP_CAT", "OP_SUBSTR", "OP_LEFT", "OP_RIGHT", "OP_SIZE", "OP_INVERT", "OP_AND", "OP_OR",
+        "OP_XOR", "OP_EQUAL", "OP_EQUALVERIFY", "OP_RESERVED1", "OP_RESERVED2", "OP_1ADD",
+        "OP_1SUB", "OP_2MUL", "OP_2DIV", "OP_NEGATE", "OP_ABS", "OP_NOT", "OP_0NOTEQUAL", "OP_ADD",
+        "OP_SUB", "OP_MUL", "OP_DIV", "OP_MOD", "OP_LSHIFT", "OP_RSHIFT", "OP_BOOLAND",
+        "OP_BOOLOR", "OP_NUMEQUAL", "OP_NUMEQUALVERIFY", "OP_NUMNOTEQUAL", "OP_LESSTHAN",
+        "OP_GREATERTHAN", "OP_LESSTHANOREQUAL", "OP_GREATERTHANOREQUAL", "OP_MIN", "OP_MAX",
+        "OP_WITHIN", "OP_RIPEMD160", "OP_SHA1", "OP_SHA256", "OP_HASH160", "OP_HASH256",
+        "OP_CODESEPARATOR", "OP_CHECKSIG", "OP_CHECKSIGVERIFY", "OP_CHECKMULTISIG",
+        "OP_CHECKMULTISIGVERIFY", "OP_NOP1", "OP_CHECKLOCKTIMEVERIFY", "OP_CHECKSEQUENCEVERIFY",
+        "OP_NOP4", "OP_NOP5", "OP_NOP6", "OP_NOP7", "OP_NOP8", "OP_NOP9", "OP_NOP10",
+        "OP_CHECKSIGADD",
+    ];
+    match op {
+        0x50..=0xba => NAMES[(op - 0x50) as usize],
+        _ => "OP_UNKNOWN",
+    }
+}
+
+/// A minimally encoded script number: little endian with the sign in the top
+/// bit of the last byte
+pub(crate) fn script_num(data: &[u8]) -> i64 {
+    let Some((&last, _)) = data.split_last() else {
+        return 0;
+    };
+    let mut value = 0i64;
+    for (i, byte) in data.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        -(value & !(0x80 << (8 * (data.len() - 1))))
+    } else {
+        value
+    }
+}
+
+/// One element of a script: an opcode, or data pushed on the stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cmd {
+    Op(u8),
+    Push(Vec<u8>),
+}
+
+impl Cmd {
+    pub fn push(data: &[u8]) -> Self {
+        Cmd::Push(data.to_vec())
+    }
+}
+
+/// A script as the bytes it was written with. Parsing it into `Cmd`s and
+/// writing those back isn't lossless: a push can use a larger opcode than it
+/// needs, and a script can end in the middle of a push, which is only an
+/// error once the script runs. So the bytes are what's serialized and
+/// hashed, and `cmds` parses them for whatever needs opcodes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Script {
-    pub cmds: Vec<Vec<u8>>,
+    raw: Vec<u8>,
+}
+
+/// A push that runs past the end of its script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TruncatedPush;
+
+/// The opcodes and pushes of a script in order, ending after a push that runs
+/// past the end
+pub struct Instructions<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Instructions<'a> {
+    /// The bytes not parsed yet
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Cmd, TruncatedPush>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        fn take_checked<'a>(raw: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+            let (head, tail) = raw.split_at_checked(n)?;
+            *raw = tail;
+            Some(head)
+        }
+
+        let (&op, mut rest) = self.raw.split_first()?;
+        let length = match op {
+            0x01..=0x4b => Some(op as usize),
+            OP_PUSHDATA1 => take_checked(&mut rest, 1).map(|length| length[0] as usize),
+            OP_PUSHDATA2 => take_checked(&mut rest, 2)
+                .map(|length| u16::from_le_bytes(length.try_into().unwrap()) as usize),
+            OP_PUSHDATA4 => take_checked(&mut rest, 4)
+                .map(|length| u32::from_le_bytes(length.try_into().unwrap()) as usize),
+            _ => {
+                self.raw = rest;
+                return Some(Ok(Cmd::Op(op)));
+            }
+        };
+        let data = length.and_then(|length| take_checked(&mut rest, length));
+        // nothing comes after a truncated push
+        self.raw = match data {
+            Some(_) => rest,
+            None => &[],
+        };
+        Some(data.map(Cmd::push).ok_or(TruncatedPush))
+    }
 }
 
 impl Script {
-    pub fn decode(s: &mut Cursor<&Vec<u8>>) -> Self {
-        let length = utils::read_varint(s).unwrap() as usize;
-        let mut cmds = vec![];
-        for _ in 0..length {
-            let cmd_length = utils::read_u8(s).unwrap() as usize;
-            let mut cmd = vec![0; cmd_length];
-            s.read_exact(&mut cmd).unwrap();
-            cmds.push(cmd);
+    /// Standard pay-to-public-key-hash locking script
+    pub fn p2pkh(pkb_hash: &[u8]) -> Self {
+        Script::new(vec![
+            Cmd::Op(OP_DUP),
+            Cmd::Op(OP_HASH160),
+            Cmd::push(pkb_hash),
+            Cmd::Op(OP_EQUALVERIFY),
+            Cmd::Op(OP_CHECKSIG),
+        ])
+    }
+
+    /// Provably unspendable script embedding `data`, within the default relay
+    /// limit
+    pub fn op_return(data: &[u8]) -> Self {
+        assert!(
+            data.len() <= MAX_OP_RETURN_DATA,
+            "OP_RETURN data is {} bytes, at most {} are standard",
+            data.len(),
+            MAX_OP_RETURN_DATA
+        );
+        Script::new(vec![Cmd::Op(OP_RETURN), Cmd::push(data)])
+    }
+
+    /// Human readable form, opcodes by name and pushes in hex, except short
+    /// pushes which are shown as the number they encode, like Bitcoin Core. A
+    /// truncated push shows as `[error]`.
+    pub fn asm(&self) -> String {
+        let parts: Vec<String> = self
+            .instructions()
+            .map(|cmd| match cmd {
+                Ok(Cmd::Op(OP_0)) => "0".to_string(),
+                Ok(Cmd::Op(OP_1NEGATE)) => "-1".to_string(),
+                Ok(Cmd::Op(op @ OP_1..=OP_16)) => (op - OP_1 + 1).to_string(),
+                Ok(Cmd::Op(op)) => opcode_name(op).to_string(),
+                Ok(Cmd::Push(data)) if data.len() <= 4 => script_num(&data).to_string(),
+                Ok(Cmd::Push(data)) => hex::encode(data),
+                Err(TruncatedPush) => "[error]".to_string(),
+            })
+            .collect();
+        parts.join(" ")
+    }
+
+    /// Payload of an OP_RETURN script, None for any other script
+    pub fn op_return_data(&self) -> Option<Vec<u8>> {
+        match self.cmds().ok()?.as_slice() {
+            [Cmd::Op(OP_RETURN), Cmd::Push(data)] => Some(data.clone()),
+            _ => None,
         }
-        Script { cmds }
     }
 
-    pub fn encode(&self) -> Vec<u8> {
-        let mut result = vec![];
-        result.extend(utils::encode_varint(self.cmds.len() as u64));
-        for cmd in &self.cmds {
-            result.push(cmd.len() as u8);
-            result.extend(cmd);
+    /// Address of a P2PKH, P2SH or segwit locking script, None for any other
+    /// script
+    pub fn address(&self, net: &str) -> Option<String> {
+        let (p2sh_version, hrp) = match net {
+            "main" => (0x05, "bc"),
+            "test" => (0xc4, "tb"),
+            _ => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        match self.template()?.as_slice() {
+            [Cmd::Op(OP_DUP), Cmd::Op(OP_HASH160), Cmd::Push(pkb_hash), Cmd::Op(OP_EQUALVERIFY), Cmd::Op(OP_CHECKSIG)]
+                if pkb_hash.len() == 20 =>
+            {
+                Some(pkb_hash_to_address(pkb_hash, net))
+            }
+            [Cmd::Op(OP_HASH160), Cmd::Push(script_hash), Cmd::Op(OP_EQUAL)]
+                if script_hash.len() == 20 =>
+            {
+                let mut payload = vec![p2sh_version];
+                payload.extend(script_hash);
+                Some(b58check_encode(&payload))
+            }
+            // a version push and a 2 to 40 byte witness program
+            [Cmd::Op(version @ (OP_0 | OP_1..=OP_16)), Cmd::Push(program)]
+                if (2..=40).contains(&program.len())
+                    && (*version != OP_0 || program.len() == 20 || program.len() == 32) =>
+            {
+                let version = match *version {
+                    OP_0 => 0,
+                    op => op - OP_1 + 1,
+                };
+                Some(encode_segwit_address(hrp, version, program))
+            }
+            _ => None,
+        }
+    }
+
+    /// The locking script an address stands for, None if it isn't a valid
+    /// address on `net`
+    pub fn from_address(address: &str, net: &str) -> Option<Script> {
+        let (p2pkh_version, p2sh_version, hrp) = match net {
+            "main" => (0x00, 0x05, "bc"),
+            "test" => (0x6f, 0xc4, "tb"),
+            _ => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        if let Some((version, program)) = decode_segwit_address(hrp, address) {
+            let version = match version {
+                0 => OP_0,
+                version => OP_1 + version - 1,
+            };
+            return Some(Script::new(vec![Cmd::Op(version), Cmd::Push(program)]));
+        }
+        let payload = b58check_decode(address)?;
+        match payload.split_first() {
+            Some((&version, hash)) if hash.len() == 20 && version == p2pkh_version => {
+                Some(Script::p2pkh(hash))
+            }
+            Some((&version, hash)) if hash.len() == 20 && version == p2sh_version => {
+                Some(Script::new(vec![
+                    Cmd::Op(OP_HASH160),
+                    Cmd::push(hash),
+                    Cmd::Op(OP_EQUAL),
+                ]))
+            }
+            _ => None,
         }
-        result
     }
 
     pub fn evaluate(&self, mod_tx_enc: &[u8]) -> bool {
         // Ensure the script is a standard P2PKH transaction
-        if self.cmds.len() != 7 {
+        let Ok(cmds) = self.cmds() else {
             return false;
-        }
-
-        // Extract the commands
-        let signature = &self.cmds[0];
-        let pubkey = &self.cmds[1];
-        let op_dup = self.cmds[2][0];
-        let op_hash160 = self.cmds[3][0];
-        let pubkey_hash = &self.cmds[4];
-        let op_equalverify = self.cmds[5][0];
-        let op_checksig = self.cmds[6][0];
-
-        // Verify the opcodes
-        if op_dup != OP_DUP
-            || op_hash160 != OP_HASH160
-            || op_equalverify != OP_EQUALVERIFY
-            || op_checksig != OP_CHECKSIG
-        {
+        };
+        let [Cmd::Push(signature), Cmd::Push(pubkey), Cmd::Op(OP_DUP), Cmd::Op(OP_HASH160), Cmd::Push(pubkey_hash), Cmd::Op(OP_EQUALVERIFY), Cmd::Op(OP_CHECKSIG)] =
+            cmds.as_slice()
+        else {
+            return false;
+        };
+        if signature.is_empty() {
             return false;
         }
 
         // Verify the public key hash
-        if *pubkey_hash != ripemd160(&sha256(pubkey.to_vec())) {
+        if pubkey_hash[..] != hash160(pubkey) {
             return false;
         }
 
         // Verify the digital signature
         let sighash_type = signature[signature.len() - 1];
-        if sighash_type != 1 {
+        if sighash_type != SIGHASH_ALL {
             return false;
         }
         let der = &signature[..signature.len() - 1];
-        let sig = Signature::decode(der);
+        let Ok(sig) = Signature::from_der(der) else {
+            return false;
+        };
         let pk = PublicKey::from_bytes(pubkey);
         verify_ecdsa(&pk, mod_tx_enc, &sig)
     }
 }
 
+impl Script {
+    /// A script of `cmds`, each push written with the smallest push opcode
+    /// that fits. An empty push is written as OP_0.
+    pub fn new(cmds: Vec<Cmd>) -> Self {
+        let mut raw = vec![];
+        for cmd in cmds {
+            match cmd {
+                Cmd::Op(op) => raw.push(op),
+                Cmd::Push(data) => {
+                    let length = data.len();
+                    match length {
+                        0 => raw.push(OP_0),
+                        1..=0x4b => raw.push(length as u8),
+                        0x4c..=0xff => raw.extend([OP_PUSHDATA1, length as u8]),
+                        0x100..=0xffff => {
+                            raw.push(OP_PUSHDATA2);
+                            raw.extend((length as u16).to_le_bytes());
+                        }
+                        _ => {
+                            raw.push(OP_PUSHDATA4);
+                            raw.extend((length as u32).to_le_bytes());
+                        }
+                    }
+                    raw.extend(data);
+                }
+            }
+        }
+        Script { raw }
+    }
+
+    /// The script of exactly these bytes, whether or not they parse
+    pub fn from_bytes(raw: &[u8]) -> Self {
+        Script { raw: raw.to_vec() }
+    }
+
+    /// The raw script, without the length prefix
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.raw.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions { raw: &self.raw }
+    }
+
+    /// The opcodes and pushes, an error if a push runs past the end
+    pub fn cmds(&self) -> Result<Vec<Cmd>, TruncatedPush> {
+        self.instructions().collect()
+    }
+
+    /// The cmds of a script written the way `Script::new` writes them, None
+    /// for any other. Output templates are fixed bytes, so a script with a
+    /// push written with a larger opcode than needed matches none of them.
+    pub fn template(&self) -> Option<Vec<Cmd>> {
+        let cmds = self.cmds().ok()?;
+        (Script::new(cmds.clone()) == *self).then_some(cmds)
+    }
+}
+
+/// Length prefixed, as scripts appear in transactions
+impl Encodable for Script {
+    fn encode(&self) -> Vec<u8> {
+        let mut result = utils::encode_varint(self.raw.len() as u64);
+        result.extend(&self.raw);
+        result
+    }
+}
+
+impl TryDecodable for Script {
+    fn try_decode(bytes: &mut &[u8]) -> Result<Self, DecodingError> {
+        let length = try_read_varint(bytes)?;
+        let length = usize::try_from(length).map_err(|_| DecodingError::UnexpectedEnd)?;
+        Ok(Script::from_bytes(try_take(bytes, length)?))
+    }
+}
+
+/// The two scripts one after the other
 impl std::ops::Add for Script {
     type Output = Script;
 
-    fn add(self, other: Script) -> Script {
-        let mut cmds = self.cmds.clone();
-        cmds.extend(other.cmds);
-        Script { cmds }
+    fn add(mut self, other: Script) -> Script {
+        self.raw.extend(other.raw);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::sha256::sha256;
+    use crate::strategies;
+
+    proptest! {
+        #[test]
+        fn prop_tx_roundtrip(tx in strategies::tx()) {
+            let raw = tx.encode();
+            let decoded = Tx::decode_all(&raw);
+            prop_assert_eq!(decoded.segwit, tx.segwit);
+            prop_assert_eq!(decoded.encode(), raw);
+            prop_assert_eq!(decoded.id(), tx.id());
+
+            // the legacy serialization drops the witnesses
+            let legacy = tx.encode_legacy();
+            let decoded = Tx::decode_all(&legacy);
+            prop_assert!(!decoded.segwit);
+            prop_assert_eq!(decoded.encode(), legacy);
+        }
+
+        #[test]
+        fn prop_script_roundtrip(script in strategies::script()) {
+            let raw = script.encode();
+            let decoded = Script::decode_all(&raw);
+            prop_assert_eq!(decoded.cmds(), script.cmds());
+            prop_assert_eq!(decoded, script);
+        }
+
+        #[test]
+        fn prop_tx_truncated(tx in strategies::tx(), cut in any::<prop::sample::Index>()) {
+            let raw = tx.encode();
+            let cut = cut.index(raw.len());
+            prop_assert_eq!(Tx::try_decode_all(&raw[..cut]).err(), Some(DecodingError::UnexpectedEnd));
+        }
+    }
+
+    #[test]
+    fn try_decode_rejects_malformed() {
+        // version, a segwit marker with a bad flag
+        let raw = hex::decode("0100000000020000000000").unwrap();
+        assert_eq!(
+            Tx::try_decode_all(&raw).err(),
+            Some(DecodingError::Invalid("segwit flag"))
+        );
+        // version, then claiming 2^64 - 1 inputs with none present
+        let raw = hex::decode("01000000ffffffffffffffffff").unwrap();
+        assert_eq!(
+            Tx::try_decode_all(&raw).err(),
+            Some(DecodingError::UnexpectedEnd)
+        );
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0x22; 20]))
+            .build();
+        let mut raw = tx.encode();
+        raw.push(0);
+        assert_eq!(
+            Tx::try_decode_all(&raw).err(),
+            Some(DecodingError::TrailingBytes(1))
+        );
+    }
+
+    #[test]
+    fn push_encoding() {
+        // data length, push opcode and length prefix
+        let cases: [(usize, &[u8]); 7] = [
+            (1, &[0x01]),
+            (75, &[0x4b]),
+            (76, &[OP_PUSHDATA1, 76]),
+            (255, &[OP_PUSHDATA1, 0xff]),
+            (256, &[OP_PUSHDATA2, 0x00, 0x01]),
+            (65535, &[OP_PUSHDATA2, 0xff, 0xff]),
+            (65536, &[OP_PUSHDATA4, 0x00, 0x00, 0x01, 0x00]),
+        ];
+        for (length, prefix) in cases {
+            let script = Script::new(vec![Cmd::Push(vec![0xab; length]), Cmd::Op(OP_CHECKSIG)]);
+            let raw = script.to_bytes();
+            assert_eq!(&raw[..prefix.len()], prefix);
+            assert_eq!(raw.len(), prefix.len() + length + 1);
+            assert_eq!(Script::from_bytes(&raw), script);
+            assert_eq!(script.template().unwrap()[0], Cmd::Push(vec![0xab; length]));
+        }
+
+        // a push longer than what's left
+        assert_eq!(Script::from_bytes(&[0x02, 0xab]).cmds(), Err(TruncatedPush));
+        assert_eq!(
+            Script::from_bytes(&[OP_PUSHDATA2, 0x01]).cmds(),
+            Err(TruncatedPush)
+        );
+    }
+
+    #[test]
+    fn script_keeps_its_bytes() {
+        // 20 bytes pushed with OP_PUSHDATA1, which a minimal push would write
+        // as 0x14
+        let mut non_minimal = vec![OP_DUP, OP_HASH160, OP_PUSHDATA1, 20];
+        non_minimal.extend([0x22; 20]);
+        non_minimal.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+        // valid on the wire, only an error once run
+        let truncated = [OP_RETURN, OP_PUSHDATA1, 0x05, 0xab];
+
+        let mut tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::from_bytes(&non_minimal))
+            .add_output(Amount::ZERO, Script::from_bytes(&truncated))
+            .build();
+        tx.tx_ins[0].script_sig = Script::from_bytes(&[0x03, 0xfc, 0x79]);
+        let raw = tx.encode();
+        let decoded = Tx::try_decode_all(&raw).unwrap();
+        assert_eq!(decoded.encode(), raw);
+        assert_eq!(decoded.id(), tx.id());
+        assert_eq!(
+            decoded.sig_message(0, &decoded.tx_outs[0].script_pubkey),
+            tx.sig_message(0, &tx.tx_outs[0].script_pubkey)
+        );
+
+        let script_pubkey = &decoded.tx_outs[0].script_pubkey;
+        assert_eq!(script_pubkey.as_bytes(), non_minimal);
+        assert_eq!(
+            script_pubkey.cmds().unwrap(),
+            Script::p2pkh(&[0x22; 20]).cmds().unwrap()
+        );
+        // it isn't the P2PKH template though
+        assert_eq!(script_pubkey.template(), None);
+        assert_eq!(script_pubkey.address("main"), None);
+
+        assert_eq!(decoded.tx_outs[1].script_pubkey.as_bytes(), truncated);
+        assert_eq!(decoded.tx_outs[1].script_pubkey.cmds(), Err(TruncatedPush));
+        assert_eq!(decoded.tx_outs[1].script_pubkey.asm(), "OP_RETURN [error]");
+        assert_eq!(decoded.tx_ins[0].script_sig.asm(), "[error]");
+    }
+
+    #[test]
+    fn p2pkh_wire_format() {
+        let raw = hex::decode("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").unwrap();
+        let script = Script::from_bytes(&raw);
+        assert_eq!(script, Script::p2pkh(&raw[3..23]));
+        assert_eq!(script.to_bytes(), raw);
+        assert_eq!(
+            script.address("main").unwrap(),
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"
+        );
+    }
+
+    #[test]
+    fn script_addresses() {
+        let cases = [
+            (
+                "a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87",
+                "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            ),
+            (
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            ),
+            (
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+            ),
+        ];
+        for (script, address) in cases {
+            let script = Script::from_bytes(&hex::decode(script).unwrap());
+            assert_eq!(script.address("main").as_deref(), Some(address));
+            assert_eq!(Script::from_address(address, "main"), Some(script));
+        }
+        assert_eq!(
+            Script::from_address("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", "test"),
+            None
+        );
+        assert_eq!(
+            Script::from_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", "test"),
+            None
+        );
+        // v0 programs must be 20 or 32 bytes
+        let script = Script::new(vec![Cmd::Op(OP_0), Cmd::push(&[0x11; 25])]);
+        assert_eq!(script.address("main"), None);
+    }
+
+    #[test]
+    fn coinbase_height() {
+        let coinbase = |cmd| Tx {
+            tx_ins: vec![TxIn {
+                prev_tx: vec![0; 32],
+                prev_index: 0xffffffff,
+                script_sig: Script::new(vec![cmd, Cmd::push(b"extranonce")]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        // block 227836, the first with a BIP34 height
+        assert_eq!(
+            coinbase(Cmd::push(&[0xfc, 0x79, 0x03])).coinbase_height(),
+            Some(227836)
+        );
+        assert_eq!(coinbase(Cmd::Op(OP_16)).coinbase_height(), Some(16));
+        assert_eq!(Tx::default().coinbase_height(), None);
+    }
+
+    #[test]
+    fn tx_to_json() {
+        let pkb_hash = hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let mut prev_tx = vec![0x11; 31];
+        prev_tx.push(0x22);
+        let tx = TxBuilder::new("main")
+            .add_input(prev_tx, 3)
+            .add_output(Amount::from_sat(10_000), Script::p2pkh(&pkb_hash))
+            .add_data_output(b"hello")
+            .build();
+        let json = tx.to_json("main");
+
+        assert_eq!(json["txid"], tx.id());
+        assert_eq!(json["hash"], tx.id());
+        assert_eq!(json["vsize"], tx.vsize());
+        assert_eq!(json["vin"][0]["txid"], format!("22{}", "11".repeat(31)));
+        assert_eq!(json["vin"][0]["vout"], 3);
+        let p2pkh = &json["vout"][0];
+        assert_eq!(p2pkh["value"], 0.0001);
+        assert_eq!(
+            p2pkh["scriptPubKey"]["asm"],
+            "OP_DUP OP_HASH160 751e76e8199196d454941c45d1b3a323f1433bd6 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        assert_eq!(
+            p2pkh["scriptPubKey"]["address"],
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"
+        );
+        assert_eq!(p2pkh["scriptPubKey"]["type"], "pubkeyhash");
+        let data = &json["vout"][1]["scriptPubKey"];
+        assert_eq!(data["asm"], "OP_RETURN 68656c6c6f");
+        assert_eq!(data["type"], "nulldata");
+        assert!(data.get("address").is_none());
+    }
+
+    #[test]
+    fn script_asm_numbers() {
+        let script = Script::new(vec![
+            Cmd::push(&[0xfc, 0x79, 0x03]),
+            Cmd::push(&[0x81]),
+            Cmd::Op(OP_0),
+            Cmd::Op(OP_16),
+            Cmd::Op(0xba),
+            Cmd::Op(0xff),
+        ]);
+        assert_eq!(script.asm(), "227836 -1 0 16 OP_CHECKSIGADD OP_UNKNOWN");
+    }
+
+    #[test]
+    fn fee_from_prevouts() {
+        let tx = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_input(vec![0x11; 32], 1)
+            .add_output(Amount::from_sat(2_500), Script::p2pkh(&[0x22; 20]))
+            .build();
+        let prevout = |sat| TxOut {
+            amount: Amount::from_sat(sat),
+            script_pubkey: Script::p2pkh(&[0x33; 20]),
+        };
+
+        let mut prevouts = Prevouts::new();
+        prevouts.insert((vec![0x11; 32], 0), prevout(1_000));
+        assert_eq!(
+            tx.fee(&prevouts),
+            Err(FeeError::MissingPrevout {
+                prev_tx: vec![0x11; 32],
+                prev_index: 1
+            })
+        );
+
+        prevouts.insert((vec![0x11; 32], 1), prevout(2_000));
+        assert_eq!(tx.fee(&prevouts), Ok(Amount::from_sat(500)));
+
+        prevouts.insert((vec![0x11; 32], 1), prevout(1_000));
+        assert_eq!(
+            tx.fee(&prevouts),
+            Err(FeeError::Negative {
+                input: Amount::from_sat(2_000),
+                output: Amount::from_sat(2_500)
+            })
+        );
+
+        prevouts.insert((vec![0x11; 32], 1), prevout(u64::MAX));
+        assert_eq!(tx.fee(&prevouts), Err(FeeError::Overflow));
+    }
+
+    #[test]
+    fn fetch_prevouts_from_cache() {
+        let prev_tx = TxBuilder::new("test")
+            .add_input(vec![0x44; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0x55; 20]))
+            .build();
+        // the cache is keyed by txid, so nothing is fetched
+        std::fs::create_dir_all("txdb").unwrap();
+        let cache_file = format!("txdb/{}", prev_tx.id());
+        std::fs::write(&cache_file, prev_tx.encode()).unwrap();
+
+        let mut hash = hex::decode(prev_tx.id()).unwrap();
+        hash.reverse();
+        let tx = TxBuilder::new("test").add_input(hash.clone(), 0).build();
+        let prevouts = tx.fetch_prevouts();
+        let value = tx.tx_ins[0].value();
+        let script_pubkey = tx.tx_ins[0].script_pubkey();
+        let out_of_range = TxBuilder::new("test")
+            .add_input(hash.clone(), 1)
+            .build()
+            .fetch_prevouts();
+        std::fs::remove_file(&cache_file).unwrap();
+        // only if nothing else is cached
+        let _ = std::fs::remove_dir("txdb");
+
+        let prevouts = prevouts.unwrap();
+        assert_eq!(prevouts.len(), 1);
+        assert_eq!(prevouts[&(hash.clone(), 0)].amount, Amount::from_sat(1_000));
+        assert_eq!(value, Amount::from_sat(1_000));
+        assert_eq!(script_pubkey, Script::p2pkh(&[0x55; 20]));
+        assert_eq!(
+            out_of_range.unwrap_err(),
+            FeeError::MissingPrevout {
+                prev_tx: hash,
+                prev_index: 1
+            }
+        );
+    }
+
+    #[test]
+    fn validate_signed_p2pkh() {
+        use crate::ru256::RU256;
+        use crate::signer::{Signer, SoftwareSigner};
+
+        let mut signer = SoftwareSigner::new(vec![RU256::from_u64(0xa11ce)]);
+        let pubkey = signer.get_pubkey(0).unwrap();
+        let spent = Script::p2pkh(&pubkey.sec(true, true));
+        let prev_tx = TxBuilder::new("test")
+            .add_input(vec![0x66; 32], 0)
+            .add_output(Amount::from_sat(3_000), Script::p2pkh(&[0x77; 20]))
+            .add_output(Amount::from_sat(4_000), spent.clone())
+            .build();
+        std::fs::create_dir_all("txdb").unwrap();
+        let cache_file = format!("txdb/{}", prev_tx.id());
+        std::fs::write(&cache_file, prev_tx.encode()).unwrap();
+
+        let mut hash = hex::decode(prev_tx.id()).unwrap();
+        hash.reverse();
+        let mut tx = TxBuilder::new("test")
+            .add_input(hash, 1)
+            .add_output(Amount::from_sat(3_500), Script::p2pkh(&[0x88; 20]))
+            .build();
+        let sig = signer.sign_tx_input(0, &tx, 0, &spent).unwrap();
+        tx.tx_ins[0].script_sig =
+            Script::new(vec![Cmd::Push(sig), Cmd::Push(pubkey.sec(true, false))]);
+        let valid = tx.validate();
+        // the signature commits to the outputs
+        let mut tampered = tx.clone();
+        tampered.tx_outs[0].amount = Amount::from_sat(3_900);
+        let tampered_valid = tampered.validate();
+        std::fs::remove_file(&cache_file).unwrap();
+        let _ = std::fs::remove_dir("txdb");
+
+        assert!(valid);
+        assert!(!tampered_valid);
+    }
+
+    #[test]
+    fn coinbase_pays_no_fee() {
+        let coinbase = TxBuilder::new("main")
+            .add_input(vec![0; 32], 0xffffffff)
+            .add_output(Amount::from_sat(50_0000_0000), Script::p2pkh(&[0x22; 20]))
+            .build();
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.fee(&Prevouts::new()), Ok(Amount::ZERO));
+    }
+
+    #[test]
+    fn op_return_data_output() {
+        let commitment = sha256(b"mint keyset commitment".to_vec());
+        let tx = TxBuilder::new("test")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0x22; 20]))
+            .add_data_output(&commitment)
+            .build();
+
+        assert_eq!(tx.tx_outs[1].amount, Amount::ZERO);
+        assert_eq!(tx.tx_outs[1].script_pubkey.address("test"), None);
+        let decoded = Tx::decode_all(&tx.encode());
+        assert_eq!(
+            decoded.tx_outs[1].script_pubkey.op_return_data(),
+            Some(commitment.to_vec())
+        );
+        assert_eq!(decoded.tx_outs[0].script_pubkey.op_return_data(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 80 are standard")]
+    fn op_return_data_too_large() {
+        Script::op_return(&[0u8; MAX_OP_RETURN_DATA + 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already has a data output")]
+    fn one_data_output_per_tx() {
+        TxBuilder::new("main")
+            .add_data_output(b"first")
+            .add_data_output(b"second");
     }
 }
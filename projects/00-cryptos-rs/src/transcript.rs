@@ -0,0 +1,100 @@
+use alloc::vec::Vec;
+
+use crate::field::Fn;
+use crate::secp256k1::Point;
+use crate::sha256::sha256;
+
+// A Fiat-Shamir transcript in the style of Merlin, on the crate's sha256
+// instead of STROBE. A protocol starts one with its domain, appends every
+// public value under a label as it goes, and draws challenges from it, each
+// challenge depending on the domain and everything appended before it in
+// order. The state is a running hash: each operation hashes the previous
+// state with the operation, the label and the data, all length prefixed, so
+// two different sequences of operations can't hash alike. Drawing a
+// challenge ratchets the state past it. Proofs take the transcript from the
+// caller, which lets a protocol bind its own context (keys, session ids) to
+// every proof in it, and the verifier has to replay the same appends to get
+// the same challenges.
+
+const DOMAIN: u8 = 0;
+const APPEND: u8 = 1;
+const CHALLENGE: u8 = 2;
+const RATCHET: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    state: [u8; 32],
+}
+
+impl Transcript {
+    pub fn new(domain: &'static [u8]) -> Self {
+        let mut transcript = Transcript { state: [0; 32] };
+        transcript.absorb(DOMAIN, b"ecash-course transcript v1", domain);
+        transcript
+    }
+
+    fn absorb(&mut self, operation: u8, label: &[u8], data: &[u8]) {
+        let mut input = Vec::with_capacity(32 + 9 + label.len() + data.len());
+        input.extend(self.state);
+        input.push(operation);
+        input.extend((label.len() as u32).to_le_bytes());
+        input.extend(label);
+        input.extend((data.len() as u32).to_le_bytes());
+        input.extend(data);
+        self.state = sha256(input).try_into().unwrap();
+    }
+
+    pub fn append_message(&mut self, label: &'static [u8], message: &[u8]) {
+        self.absorb(APPEND, label, message);
+    }
+
+    pub fn append_u64(&mut self, label: &'static [u8], value: u64) {
+        self.append_message(label, &value.to_le_bytes());
+    }
+
+    /// A point in its compressed encoding
+    pub fn append_point(&mut self, label: &'static [u8], point: &Point) {
+        self.append_message(label, &point.to_compressed_bytes());
+    }
+
+    pub fn append_scalar(&mut self, label: &'static [u8], scalar: &Fn) {
+        self.append_message(label, &scalar.to_bytes());
+    }
+
+    pub fn challenge_bytes(&mut self, label: &'static [u8]) -> [u8; 32] {
+        self.absorb(CHALLENGE, label, &[]);
+        let challenge = self.state;
+        self.absorb(RATCHET, &[], &[]);
+        challenge
+    }
+
+    pub fn challenge_scalar(&mut self, label: &'static [u8]) -> Fn {
+        Fn::from_bytes(&self.challenge_bytes(label))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript() {
+        let mut prover = Transcript::new(b"test protocol");
+        let mut verifier = prover.clone();
+        prover.append_message(b"message", b"hello");
+        verifier.append_message(b"message", b"hello");
+        let challenge = prover.challenge_bytes(b"challenge");
+        assert_eq!(verifier.challenge_bytes(b"challenge"), challenge);
+        // the state moved past the challenge
+        assert_ne!(prover.challenge_bytes(b"challenge"), challenge);
+
+        // the same bytes split differently between label and message
+        let mut split = Transcript::new(b"test protocol");
+        split.append_message(b"mess", b"agehello");
+        assert_ne!(split.challenge_bytes(b"challenge"), challenge);
+
+        let mut other = Transcript::new(b"other protocol");
+        other.append_message(b"message", b"hello");
+        assert_ne!(other.challenge_bytes(b"challenge"), challenge);
+    }
+}
@@ -42,6 +42,37 @@ pub fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
 }
 
 pub fn encode_varint(value: u64) -> Vec<u8> {
-    let buf = value.to_le_bytes().to_vec();
-    buf
+    match value {
+        0..=0xFC => vec![value as u8],
+        0xFD..=0xFFFF => {
+            let mut buf = vec![0xFD];
+            buf.extend_from_slice(&(value as u16).to_le_bytes());
+            buf
+        }
+        0x10000..=0xFFFFFFFF => {
+            let mut buf = vec![0xFE];
+            buf.extend_from_slice(&(value as u32).to_le_bytes());
+            buf
+        }
+        _ => {
+            let mut buf = vec![0xFF];
+            buf.extend_from_slice(&value.to_le_bytes());
+            buf
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn prop_varint_roundtrip(value: u64) {
+            let encoded = encode_varint(value);
+            prop_assert_eq!(read_varint(&mut encoded.as_slice()).unwrap(), value);
+        }
+    }
 }
@@ -0,0 +1,361 @@
+use crate::block::Chain;
+
+// Soft fork signalling with version bits (BIP9). Miners set bit `bit` of the
+// header version to say they are ready for a deployment. Its state only
+// changes at retarget period boundaries, decided by the period before:
+// DEFINED until the median time past reaches the start time, STARTED while
+// blocks are counted, LOCKED_IN after a period where at least `threshold`
+// blocks signalled, then ACTIVE from the next period on (or once the minimum
+// activation height is reached, as taproot's speedy trial did). A deployment
+// that times out before locking in is FAILED for good.
+
+/// The top three bits of a version that signals, anything else isn't read as
+/// version bits
+const TOP_BITS: u32 = 0x20000000;
+const TOP_MASK: u32 = 0xe0000000;
+
+/// A soft fork miners signal for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deployment {
+    pub name: &'static str,
+    /// Which version bit signals for it, 0 to 28
+    pub bit: u8,
+    /// Median time past from which signalling is counted
+    pub start_time: u32,
+    /// Median time past after which it fails if it hasn't locked in
+    pub timeout: u32,
+    /// The earliest height it can activate at after locking in
+    pub min_activation_height: u32,
+}
+
+/// Segregated witness on mainnet (BIP141), activated at 481824
+pub const SEGWIT: Deployment = Deployment {
+    name: "segwit",
+    bit: 1,
+    start_time: 1479168000,
+    timeout: 1510704000,
+    min_activation_height: 0,
+};
+
+/// Taproot on mainnet (BIP341), locked in at 687744 and activated at 709632
+pub const TAPROOT: Deployment = Deployment {
+    name: "taproot",
+    bit: 2,
+    start_time: 1619222400,
+    timeout: 1628640000,
+    min_activation_height: 709632,
+};
+
+impl Deployment {
+    /// Whether a header with this version signals for the deployment
+    pub fn signals(&self, version: u32) -> bool {
+        version & TOP_MASK == TOP_BITS && (version >> self.bit) & 1 == 1
+    }
+}
+
+/// Where a deployment is, the same for every block of a retarget period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdState {
+    Defined,
+    Started,
+    LockedIn,
+    Active,
+    Failed,
+}
+
+impl ThresholdState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThresholdState::Defined => "defined",
+            ThresholdState::Started => "started",
+            ThresholdState::LockedIn => "locked_in",
+            ThresholdState::Active => "active",
+            ThresholdState::Failed => "failed",
+        }
+    }
+}
+
+/// Signalling so far in the period of a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statistics {
+    pub period: u32,
+    pub threshold: u32,
+    /// Blocks of the period up to and including the block
+    pub elapsed: u32,
+    /// How many of them signalled
+    pub count: u32,
+    /// Whether the threshold can still be reached this period
+    pub possible: bool,
+}
+
+/// The period and threshold signalling is counted over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionBits {
+    pub period: u32,
+    pub threshold: u32,
+}
+
+impl VersionBits {
+    /// The parameters of a network, 90% of a retarget period on mainnet and
+    /// 75% on testnet
+    pub fn new(net: &str) -> Self {
+        let threshold = match net {
+            "main" => 1815,
+            "test" => 1512,
+            _ => panic!("{} is not a valid net type, should be main|test", net),
+        };
+        VersionBits {
+            period: 2016,
+            threshold,
+        }
+    }
+
+    /// The state of `deployment` for the block at `height`, which can be at
+    /// most one past the tip of `chain`
+    pub fn state(&self, chain: &Chain, deployment: &Deployment, height: u32) -> ThresholdState {
+        assert!(height <= chain.height() + 1, "no block before {}", height);
+        self.period_states(chain, deployment, height / self.period)
+            .pop()
+            .unwrap()
+    }
+
+    /// The state of `deployment` for the next block
+    pub fn next_state(&self, chain: &Chain, deployment: &Deployment) -> ThresholdState {
+        self.state(chain, deployment, chain.height() + 1)
+    }
+
+    /// The height `deployment` activated at, if it has by the next block
+    pub fn activation_height(&self, chain: &Chain, deployment: &Deployment) -> Option<u32> {
+        let last = (chain.height() + 1) / self.period;
+        self.period_states(chain, deployment, last)
+            .iter()
+            .position(|&state| state == ThresholdState::Active)
+            .map(|period| period as u32 * self.period)
+    }
+
+    /// Signalling for `deployment` in the period of the block at `height`, up
+    /// to and including it
+    pub fn statistics(&self, chain: &Chain, deployment: &Deployment, height: u32) -> Statistics {
+        let start = height - height % self.period;
+        let elapsed = height - start + 1;
+        let count = self.count(chain, deployment, start, height + 1);
+        Statistics {
+            period: self.period,
+            threshold: self.threshold,
+            elapsed,
+            count,
+            possible: self.period - self.threshold >= elapsed - count,
+        }
+    }
+
+    /// The version a miner building on the tip should set, signalling for
+    /// every deployment that is started or locked in
+    pub fn block_version(&self, chain: &Chain, deployments: &[Deployment]) -> u32 {
+        deployments
+            .iter()
+            .filter(|deployment| {
+                matches!(
+                    self.next_state(chain, deployment),
+                    ThresholdState::Started | ThresholdState::LockedIn
+                )
+            })
+            .fold(TOP_BITS, |version, deployment| {
+                version | 1 << deployment.bit
+            })
+    }
+
+    /// The states of periods 0 to `last`, each decided by the period before
+    fn period_states(
+        &self,
+        chain: &Chain,
+        deployment: &Deployment,
+        last: u32,
+    ) -> Vec<ThresholdState> {
+        let mut states = vec![ThresholdState::Defined];
+        for period in 1..=last {
+            let start = period * self.period;
+            let median_time_past = chain.median_time_past_at(start - 1);
+            let next = match *states.last().unwrap() {
+                ThresholdState::Defined if median_time_past >= deployment.timeout => {
+                    ThresholdState::Failed
+                }
+                ThresholdState::Defined if median_time_past >= deployment.start_time => {
+                    ThresholdState::Started
+                }
+                ThresholdState::Started
+                    if self.count(chain, deployment, start - self.period, start)
+                        >= self.threshold =>
+                {
+                    ThresholdState::LockedIn
+                }
+                ThresholdState::Started if median_time_past >= deployment.timeout => {
+                    ThresholdState::Failed
+                }
+                ThresholdState::LockedIn if start >= deployment.min_activation_height => {
+                    ThresholdState::Active
+                }
+                state => state,
+            };
+            states.push(next);
+        }
+        states
+    }
+
+    /// How many blocks in `start..end` signal for `deployment`
+    fn count(&self, chain: &Chain, deployment: &Deployment, start: u32, end: u32) -> u32 {
+        (start..end)
+            .filter(|&height| deployment.signals(chain.header(height).unwrap().version))
+            .count() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, TARGET_SPACING};
+    use crate::simulator::{toy_chain, EASY_BITS};
+
+    const PARAMS: VersionBits = VersionBits {
+        period: 10,
+        threshold: 8,
+    };
+
+    fn mine(chain: &mut Chain, versions: &[u32]) {
+        for &version in versions {
+            let tip = chain.tip();
+            let timestamp = tip.timestamp + TARGET_SPACING;
+            let mut block = Block {
+                version,
+                prev_block: hex::decode(tip.id()).unwrap(),
+                merkle_root: vec![0; 32],
+                timestamp,
+                bits: chain.next_bits(timestamp),
+                nonce: vec![0; 4],
+                txs: vec![],
+            };
+            assert!(block.mine());
+            assert!(chain.push(block));
+        }
+    }
+
+    fn deployment(chain: &Chain) -> Deployment {
+        // the median time past of block 9 is that of block 5, so signalling
+        // is counted from the second period on
+        let genesis_time = chain.tip().timestamp;
+        Deployment {
+            name: "test",
+            bit: 3,
+            start_time: genesis_time + 5 * TARGET_SPACING,
+            timeout: genesis_time + 100 * TARGET_SPACING,
+            min_activation_height: 0,
+        }
+    }
+
+    const SIGNAL: u32 = TOP_BITS | 1 << 3;
+
+    #[test]
+    fn test_signals() {
+        assert!(SEGWIT.signals(0x20000002));
+        assert!(!SEGWIT.signals(0x20000004));
+        assert!(TAPROOT.signals(0x3fffe004));
+        // version 4 headers don't signal anything
+        assert!(!TAPROOT.signals(0x00000004));
+        assert!(!TAPROOT.signals(0x60000004));
+    }
+
+    #[test]
+    fn test_activation() {
+        let mut chain = toy_chain(&EASY_BITS);
+        let deployment = deployment(&chain);
+        mine(&mut chain, &[TOP_BITS; 8]);
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::Defined
+        );
+        assert_eq!(
+            PARAMS.block_version(&chain, std::slice::from_ref(&deployment)),
+            TOP_BITS
+        );
+
+        mine(&mut chain, &[TOP_BITS]);
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::Started
+        );
+        assert_eq!(
+            PARAMS.block_version(&chain, std::slice::from_ref(&deployment)),
+            SIGNAL
+        );
+
+        // eight of ten is enough, with two misses left to spare
+        mine(&mut chain, &[SIGNAL, TOP_BITS, SIGNAL, SIGNAL, TOP_BITS]);
+        let statistics = PARAMS.statistics(&chain, &deployment, chain.height());
+        assert_eq!((statistics.elapsed, statistics.count), (5, 3));
+        assert!(statistics.possible);
+        mine(&mut chain, &[SIGNAL; 5]);
+        assert_eq!(
+            PARAMS.state(&chain, &deployment, chain.height()),
+            ThresholdState::Started
+        );
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::LockedIn
+        );
+        assert_eq!(PARAMS.activation_height(&chain, &deployment), None);
+
+        // active a period later, whether or not blocks keep signalling
+        mine(&mut chain, &[TOP_BITS; 10]);
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::Active
+        );
+        assert_eq!(PARAMS.activation_height(&chain, &deployment), Some(30));
+        assert_eq!(
+            PARAMS.block_version(&chain, std::slice::from_ref(&deployment)),
+            TOP_BITS
+        );
+    }
+
+    #[test]
+    fn test_min_activation_height() {
+        let mut chain = toy_chain(&EASY_BITS);
+        let deployment = Deployment {
+            min_activation_height: 40,
+            ..deployment(&chain)
+        };
+        mine(&mut chain, &[TOP_BITS; 9]);
+        mine(&mut chain, &[SIGNAL; 20]);
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::LockedIn
+        );
+        mine(&mut chain, &[TOP_BITS; 10]);
+        assert_eq!(PARAMS.activation_height(&chain, &deployment), Some(40));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut chain = toy_chain(&EASY_BITS);
+        let deployment = Deployment {
+            timeout: chain.tip().timestamp + 14 * TARGET_SPACING,
+            ..deployment(&chain)
+        };
+        mine(&mut chain, &[TOP_BITS; 9]);
+        mine(&mut chain, &[SIGNAL, SIGNAL, SIGNAL]);
+        mine(&mut chain, &[TOP_BITS; 3]);
+        let statistics = PARAMS.statistics(&chain, &deployment, chain.height());
+        assert_eq!((statistics.elapsed, statistics.count), (6, 3));
+        assert!(!statistics.possible);
+        mine(&mut chain, &[SIGNAL; 4]);
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::Failed
+        );
+        mine(&mut chain, &[SIGNAL; 10]);
+        assert_eq!(
+            PARAMS.next_state(&chain, &deployment),
+            ThresholdState::Failed
+        );
+        assert_eq!(PARAMS.activation_height(&chain, &deployment), None);
+    }
+}
@@ -0,0 +1,483 @@
+use std::cmp::Reverse;
+
+use crate::amount::{Amount, FeeRate};
+use crate::bip21::PaymentUri;
+use crate::descriptor::Descriptor;
+use crate::encoding::Encodable;
+use crate::history::{Event, EventKind};
+use crate::index::{Index, Utxo};
+use crate::policy::Policy;
+use crate::transaction::{Prevouts, Script, Tx, TxOut};
+
+// Restoring a wallet from its descriptor (e.g. one derived from a mnemonic):
+// walk the addresses forward, asking a chain backend which ones were ever used,
+// and stop once `gap_limit` consecutive addresses turn out to be unused. Wallets
+// hand out addresses in order, so nothing past the gap is expected to hold
+// funds.
+//
+// Spending a coin costs its input's share of the fee, so at high fee rates
+// small coins are worth less than they cost and are better left alone. Coin
+// selection skips those and won't create change that would be dust itself,
+// leaving it to the fee instead.
+
+/// Gap limit from BIP44, the usual default for restoring
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Anything that can answer per-script usage queries about the chain
+pub trait ChainBackend {
+    type Error;
+
+    /// Ids of all transactions paying to or spending from the script
+    fn history(&self, script_pubkey: &Script) -> Result<Vec<String>, Self::Error>;
+
+    /// Outputs locked to the script that are still unspent
+    fn unspent(&self, script_pubkey: &Script) -> Result<Vec<Utxo>, Self::Error>;
+}
+
+impl ChainBackend for Index {
+    type Error = sled::Error;
+
+    fn history(&self, script_pubkey: &Script) -> sled::Result<Vec<String>> {
+        self.history_for_script(script_pubkey)
+    }
+
+    fn unspent(&self, script_pubkey: &Script) -> sled::Result<Vec<Utxo>> {
+        self.unspent_for_script(script_pubkey)
+    }
+}
+
+/// Coins picked to fund a transaction
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub utxos: Vec<Utxo>,
+    pub fee: Amount,
+    /// None when what's left over would be dust, it's added to the fee then
+    pub change: Option<TxOut>,
+}
+
+#[derive(Debug)]
+pub struct Wallet {
+    pub descriptor: Descriptor,
+    pub gap_limit: u32,
+    /// Index of the first address after the last used one
+    pub next_index: u32,
+    pub utxos: Vec<Utxo>,
+    /// Ids of the transactions touching the wallet, grouped by address
+    pub history: Vec<String>,
+}
+
+impl Wallet {
+    /// Rebuild the wallet state by scanning the chain until `gap_limit`
+    /// consecutive unused addresses are found
+    pub fn scan<B: ChainBackend>(
+        backend: &B,
+        descriptor: Descriptor,
+        gap_limit: u32,
+    ) -> Result<Self, B::Error> {
+        assert!(gap_limit > 0, "gap limit must be at least 1");
+        let mut wallet = Wallet {
+            descriptor,
+            gap_limit,
+            next_index: 0,
+            utxos: vec![],
+            history: vec![],
+        };
+
+        if !wallet.descriptor.is_ranged() {
+            let script_pubkey = wallet.descriptor.script_pubkey(0);
+            if wallet.add_script(backend, &script_pubkey)? {
+                wallet.next_index = 1;
+            }
+            return Ok(wallet);
+        }
+
+        // derive in batches up to the end of the current gap, every used
+        // address found pushes the end further out
+        let mut index = 0;
+        while index < wallet.next_index + gap_limit {
+            let count = wallet.next_index + gap_limit - index;
+            for script_pubkey in wallet.descriptor.script_pubkeys(index, count) {
+                if wallet.add_script(backend, &script_pubkey)? {
+                    wallet.next_index = index + 1;
+                }
+                index += 1;
+            }
+        }
+        Ok(wallet)
+    }
+
+    /// Record the history and UTXOs of one script, returns whether it was used
+    fn add_script<B: ChainBackend>(
+        &mut self,
+        backend: &B,
+        script_pubkey: &Script,
+    ) -> Result<bool, B::Error> {
+        let history = backend.history(script_pubkey)?;
+        if history.is_empty() {
+            return Ok(false);
+        }
+        for txid in history {
+            if !self.history.contains(&txid) {
+                self.history.push(txid);
+            }
+        }
+        self.utxos.extend(backend.unspent(script_pubkey)?);
+        Ok(true)
+    }
+
+    pub fn balance(&self) -> Amount {
+        self.utxos.iter().map(|utxo| utxo.amount).sum()
+    }
+
+    /// The next unused receive script
+    pub fn next_script_pubkey(&self) -> Script {
+        self.descriptor.script_pubkey(self.next_index)
+    }
+
+    /// Virtual size of an input spending one of the wallet's coins
+    fn input_vsize(&self) -> usize {
+        // outpoint, scriptSig length and sequence take 41 bytes, a signature
+        // and key in the witness 108 weight units, a schnorr signature 66
+        match self.descriptor {
+            Descriptor::Wpkh(_) => 41 + 27,
+            // plus the redeem script push in the scriptSig
+            Descriptor::ShWpkh(_) => 41 + 23 + 27,
+            Descriptor::Tr(_) => 41 + 17,
+        }
+    }
+
+    /// Coins worth no more than the fee of spending them at `fee_rate`
+    pub fn uneconomical_utxos(&self, fee_rate: FeeRate) -> Vec<&Utxo> {
+        let cost = fee_rate.fee(self.input_vsize());
+        self.utxos
+            .iter()
+            .filter(|utxo| utxo.amount <= cost)
+            .collect()
+    }
+
+    /// Pick coins, largest first, to pay `outputs` at `fee_rate` with change
+    /// to the next receive script. None if the economical coins don't cover
+    /// it
+    pub fn select_coins(&self, outputs: &[TxOut], fee_rate: FeeRate) -> Option<Selection> {
+        let target: Amount = outputs.iter().map(|tx_out| tx_out.amount).sum();
+        // version, locktime, input and output counts, and the segwit marker
+        // rounded up
+        let base_vsize = 4
+            + 4
+            + 1
+            + 1
+            + 1
+            + outputs
+                .iter()
+                .map(|tx_out| tx_out.encode().len())
+                .sum::<usize>();
+        let mut change = TxOut {
+            amount: Amount::ZERO,
+            script_pubkey: self.next_script_pubkey(),
+        };
+        let change_vsize = change.encode().len();
+        // change nodes won't relay or that isn't worth spending is dropped
+        let dust_fee_rate = fee_rate.max(Policy::default().dust_relay_fee_rate);
+
+        let uneconomical = self.uneconomical_utxos(fee_rate);
+        let mut coins: Vec<&Utxo> = self
+            .utxos
+            .iter()
+            .filter(|utxo| !uneconomical.contains(utxo))
+            .collect();
+        coins.sort_by_key(|utxo| Reverse(utxo.amount));
+
+        let mut selected = vec![];
+        let mut total = Amount::ZERO;
+        for utxo in coins {
+            selected.push(utxo.clone());
+            total += utxo.amount;
+            let vsize = base_vsize + selected.len() * self.input_vsize();
+            if total < target + fee_rate.fee(vsize) {
+                continue;
+            }
+            let fee = fee_rate.fee(vsize + change_vsize);
+            if let Some(left) = total.checked_sub(target + fee) {
+                change.amount = left;
+                if !change.is_dust(dust_fee_rate) {
+                    return Some(Selection {
+                        utxos: selected,
+                        fee,
+                        change: Some(change),
+                    });
+                }
+            }
+            return Some(Selection {
+                utxos: selected,
+                fee: total - target,
+                change: None,
+            });
+        }
+        None
+    }
+
+    /// A BIP21 URI asking to be paid to the next unused address, add an
+    /// invoice or ecash request to it for a unified QR code
+    pub fn receive_uri(
+        &self,
+        net: &str,
+        amount: Option<Amount>,
+        label: Option<&str>,
+    ) -> PaymentUri {
+        let address = self
+            .next_script_pubkey()
+            .address(net)
+            .expect("wallet scripts all have addresses");
+        PaymentUri {
+            label: label.map(str::to_string),
+            ..PaymentUri::new(&address, amount)
+        }
+    }
+
+    /// The wallet's side of `tx` as a history event at `time`: what it paid
+    /// others and the fee if it spent coins, else what it received. None if
+    /// `tx` doesn't touch the wallet. `prevouts` has to hold the outputs the
+    /// inputs spend, or the wallet's own coins can't be recognized.
+    pub fn tx_event(&self, tx: &Tx, prevouts: &Prevouts, net: &str, time: u64) -> Option<Event> {
+        let scripts = match self.descriptor.is_ranged() {
+            true => self
+                .descriptor
+                .script_pubkeys(0, self.next_index + self.gap_limit),
+            false => vec![self.descriptor.script_pubkey(0)],
+        };
+        let received: Amount = tx
+            .tx_outs
+            .iter()
+            .filter(|tx_out| scripts.contains(&tx_out.script_pubkey))
+            .map(|tx_out| tx_out.amount)
+            .sum();
+        let spent: Amount = tx
+            .tx_ins
+            .iter()
+            .filter_map(|tx_in| prevouts.get(&(tx_in.prev_tx.clone(), tx_in.prev_index)))
+            .filter(|tx_out| scripts.contains(&tx_out.script_pubkey))
+            .map(|tx_out| tx_out.amount)
+            .sum();
+
+        let (kind, amount, fee, counterparty) = if spent > Amount::ZERO {
+            let fee = tx.fee(prevouts).unwrap_or(Amount::ZERO);
+            let paid = spent.checked_sub(received + fee).unwrap_or(Amount::ZERO);
+            let counterparty = tx
+                .tx_outs
+                .iter()
+                .find(|tx_out| !scripts.contains(&tx_out.script_pubkey))
+                .and_then(|tx_out| tx_out.script_pubkey.address(net));
+            (EventKind::Send, paid, fee, counterparty)
+        } else if received > Amount::ZERO {
+            (EventKind::Receive, received, Amount::ZERO, None)
+        } else {
+            return None;
+        };
+        Some(Event {
+            time,
+            kind,
+            amount: amount.to_sat(),
+            unit: "sat".to_string(),
+            fee: fee.to_sat(),
+            counterparty,
+            reference: tx.id(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::encoding::Encodable;
+    use crate::transaction::TxBuilder;
+
+    /// Chain state keyed by encoded script, counting the queries made
+    #[derive(Default)]
+    struct MockBackend {
+        scripts: HashMap<Vec<u8>, (Vec<String>, Vec<Utxo>)>,
+        queries: Cell<u32>,
+    }
+
+    impl MockBackend {
+        fn fund(&mut self, script_pubkey: &Script, txid: &str, sat: u64) {
+            let entry = self.scripts.entry(script_pubkey.encode()).or_default();
+            entry.0.push(txid.to_string());
+            entry.1.push(Utxo {
+                txid: txid.to_string(),
+                vout: 0,
+                amount: Amount::from_sat(sat),
+            });
+        }
+    }
+
+    impl ChainBackend for MockBackend {
+        type Error = ();
+
+        fn history(&self, script_pubkey: &Script) -> Result<Vec<String>, ()> {
+            self.queries.set(self.queries.get() + 1);
+            Ok(self
+                .scripts
+                .get(&script_pubkey.encode())
+                .map(|(history, _)| history.clone())
+                .unwrap_or_default())
+        }
+
+        fn unspent(&self, script_pubkey: &Script) -> Result<Vec<Utxo>, ()> {
+            Ok(self
+                .scripts
+                .get(&script_pubkey.encode())
+                .map(|(_, utxos)| utxos.clone())
+                .unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_scan_stops_at_gap() {
+        let descriptor = Descriptor::from_str("wpkh(xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/*)").unwrap();
+        let scripts = descriptor.script_pubkeys(0, 2);
+
+        let mut backend = MockBackend::default();
+        backend.fund(&scripts[0], "aa", 1000);
+        backend.fund(&scripts[1], "bb", 2000);
+
+        let wallet = Wallet::scan(&backend, descriptor, 1).unwrap();
+        // addresses 0 and 1 are used, 2 is the gap
+        assert_eq!(backend.queries.get(), 3);
+        assert_eq!(wallet.next_index, 2);
+        assert_eq!(wallet.balance(), Amount::from_sat(3000));
+        assert_eq!(wallet.history, vec!["aa", "bb"]);
+
+        let uri = wallet.receive_uri("main", Some(Amount::from_sat(5000)), Some("alice"));
+        assert_eq!(
+            uri.address,
+            wallet.descriptor.script_pubkey(2).address("main").unwrap()
+        );
+        assert!(uri.address.starts_with("bc1q"));
+        assert!(uri.to_string().ends_with("?amount=0.00005&label=alice"));
+    }
+
+    #[test]
+    fn test_scan_single_key() {
+        let descriptor = Descriptor::from_str(
+            "wpkh(0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c)",
+        )
+        .unwrap();
+        let mut backend = MockBackend::default();
+        backend.fund(&descriptor.script_pubkey(0), "aa", 1000);
+
+        let wallet = Wallet::scan(&backend, descriptor, DEFAULT_GAP_LIMIT).unwrap();
+        assert_eq!(backend.queries.get(), 1);
+        assert_eq!(wallet.next_index, 1);
+        assert_eq!(wallet.utxos.len(), 1);
+    }
+
+    #[test]
+    fn test_select_coins() {
+        let descriptor = Descriptor::from_str(
+            "wpkh(0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c)",
+        )
+        .unwrap();
+        let utxo = |txid: &str, sat| Utxo {
+            txid: txid.to_string(),
+            vout: 0,
+            amount: Amount::from_sat(sat),
+        };
+        let wallet = Wallet {
+            descriptor,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            next_index: 1,
+            utxos: vec![
+                utxo("aa", 100),
+                utxo("bb", 5_000),
+                utxo("cc", 20_000),
+                utxo("dd", 50_000),
+            ],
+            history: vec![],
+        };
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        // spending a P2WPKH coin takes 68 vbytes, 680 sat
+        let uneconomical = wallet.uneconomical_utxos(fee_rate);
+        assert_eq!(uneconomical.len(), 1);
+        assert_eq!(uneconomical[0].txid, "aa");
+
+        let pay = |sat| {
+            let payment = TxOut {
+                amount: Amount::from_sat(sat),
+                script_pubkey: wallet.descriptor.script_pubkey(0),
+            };
+            wallet.select_coins(&[payment], fee_rate)
+        };
+        let selection = pay(30_000).unwrap();
+        assert_eq!(selection.utxos.len(), 1);
+        assert_eq!(selection.utxos[0].txid, "dd");
+        assert_eq!(selection.fee, Amount::from_sat(1_410));
+        assert_eq!(selection.change.unwrap().amount, Amount::from_sat(18_590));
+
+        // 190 sat of change would be dust, it goes to the fee
+        let selection = pay(48_400).unwrap();
+        assert!(selection.change.is_none());
+        assert_eq!(selection.fee, Amount::from_sat(1_600));
+
+        let selection = pay(60_000).unwrap();
+        assert_eq!(selection.utxos.len(), 2);
+        assert_eq!(selection.fee, Amount::from_sat(2_090));
+        assert_eq!(selection.change.unwrap().amount, Amount::from_sat(7_910));
+
+        // the 100 sat coin isn't worth adding
+        assert!(pay(75_000).is_none());
+    }
+
+    #[test]
+    fn test_tx_event() {
+        let descriptor = Descriptor::from_str(
+            "wpkh(0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c)",
+        )
+        .unwrap();
+        let wallet = Wallet {
+            descriptor,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            next_index: 1,
+            utxos: vec![],
+            history: vec![],
+        };
+        let mine = wallet.descriptor.script_pubkey(0);
+        let other = Script::p2pkh(&[0x11; 20]);
+
+        let funding = TxBuilder::new("main")
+            .add_input(vec![0xaa; 32], 0)
+            .add_output(Amount::from_sat(50_000), mine.clone())
+            .build();
+        let event = wallet
+            .tx_event(&funding, &Prevouts::new(), "main", 100)
+            .unwrap();
+        assert_eq!(event.kind, EventKind::Receive);
+        assert_eq!((event.amount, event.fee), (50_000, 0));
+        assert_eq!(event.reference, funding.id());
+
+        let mut funding_txid = hex::decode(funding.id()).unwrap();
+        funding_txid.reverse();
+        let spend = TxBuilder::new("main")
+            .add_input(funding_txid.clone(), 0)
+            .add_output(Amount::from_sat(30_000), other.clone())
+            .add_output(Amount::from_sat(18_000), mine)
+            .build();
+        let mut prevouts = Prevouts::new();
+        prevouts.insert((funding_txid, 0), funding.tx_outs[0].clone());
+        let event = wallet.tx_event(&spend, &prevouts, "main", 200).unwrap();
+        assert_eq!(event.kind, EventKind::Send);
+        assert_eq!((event.amount, event.fee), (30_000, 2_000));
+        assert_eq!(event.counterparty, other.address("main"));
+
+        let unrelated = TxBuilder::new("main")
+            .add_input(vec![0xbb; 32], 0)
+            .add_output(Amount::from_sat(1_000), other)
+            .build();
+        assert!(wallet
+            .tx_event(&unrelated, &Prevouts::new(), "main", 300)
+            .is_none());
+    }
+}
@@ -0,0 +1,67 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::encoding::Encodable;
+use crate::keys::{gen_secret_key, PublicKey};
+use crate::ru256::RU256;
+use crate::secp256k1::SECP256K1;
+use crate::signature::{sign_ecdsa, verify_ecdsa, Signature};
+
+// JavaScript bindings for the interactive demos on the course website. Secret
+// keys cross the boundary as hex strings, everything else as byte arrays.
+
+fn parse_secret_key(secret_key: &str) -> Result<RU256, JsError> {
+    let key = RU256::from_str(secret_key).map_err(|_| JsError::new("secret key is not hex"))?;
+    if key.is_zero() || key >= SECP256K1::n() {
+        return Err(JsError::new("secret key is out of range"));
+    }
+    Ok(key)
+}
+
+/// A random secret key as a hex string
+#[wasm_bindgen(js_name = generateSecretKey)]
+pub fn generate_secret_key() -> String {
+    gen_secret_key(&SECP256K1::n()).to_string()
+}
+
+/// SEC encoded public key of a secret key
+#[wasm_bindgen(js_name = publicKey)]
+pub fn public_key(secret_key: &str, compressed: bool) -> Result<Vec<u8>, JsError> {
+    let secret_key = parse_secret_key(secret_key)?;
+    Ok(PublicKey::from_sk(&secret_key).sec(compressed, false))
+}
+
+/// P2PKH address of a secret key's compressed public key, net is main|test
+#[wasm_bindgen]
+pub fn address(secret_key: &str, net: &str) -> Result<String, JsError> {
+    if net != "main" && net != "test" {
+        return Err(JsError::new("net should be main|test"));
+    }
+    let secret_key = parse_secret_key(secret_key)?;
+    Ok(PublicKey::from_sk(&secret_key).address(net, true))
+}
+
+/// DER encoded ECDSA signature over the message
+#[wasm_bindgen]
+pub fn sign(secret_key: &str, message: &[u8]) -> Result<Vec<u8>, JsError> {
+    let secret_key = parse_secret_key(secret_key)?;
+    Ok(sign_ecdsa(&secret_key, message).encode())
+}
+
+/// Verify a DER encoded ECDSA signature against a SEC encoded public key,
+/// false for a key or signature that doesn't decode
+#[wasm_bindgen]
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Some(public_key) = PublicKey::try_from_bytes(public_key) else {
+        return false;
+    };
+    Signature::from_der(signature).is_ok_and(|sig| verify_ecdsa(&public_key, message, &sig))
+}
+
+#[wasm_bindgen]
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    crate::sha256::sha256(data.to_vec())
+}
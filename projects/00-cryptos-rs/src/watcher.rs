@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::amount::Amount;
+use crate::block::Block;
+use crate::transaction::{Script, Tx};
+
+// Watch-only tracking of addresses and scripts without their keys. A full
+// node flow feeds in whole blocks, an SPV flow the transactions a merkleblock
+// proved along with each new header height, and either can pass unconfirmed
+// transactions from the mempool or a backend notification. The watcher turns
+// them into events: an output received, a watched output spent, and a
+// transaction touching the watched scripts reaching the wanted depth.
+// Reorgs aren't followed, a transaction confirms once and stays confirmed.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+    /// An output paying to a watched script, `height` is None while it's
+    /// unconfirmed
+    Received {
+        txid: String,
+        vout: u32,
+        script_pubkey: Script,
+        amount: Amount,
+        height: Option<u32>,
+    },
+    /// A received output was spent by `spent_by`
+    Spent {
+        txid: String,
+        vout: u32,
+        spent_by: String,
+        height: Option<u32>,
+    },
+    /// A transaction that received or spent is `depth` blocks deep
+    Confirmed {
+        txid: String,
+        height: u32,
+        depth: u32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Watcher {
+    /// Encoded scripts being watched
+    scripts: HashSet<Vec<u8>>,
+    /// Depth at which a transaction is reported as confirmed
+    confirmations: u32,
+    /// Received outputs not spent yet
+    outputs: HashMap<(String, u32), Amount>,
+    /// Transactions already reported, so one seen unconfirmed first isn't
+    /// reported again when it's mined
+    seen: HashSet<String>,
+    /// Mined transactions not yet deep enough, txid -> height
+    pending: HashMap<String, u32>,
+}
+
+fn display_txid(txid: &[u8]) -> String {
+    let mut txid = txid.to_vec();
+    txid.reverse();
+    hex::encode(txid)
+}
+
+impl Watcher {
+    /// A watcher reporting transactions once they're `confirmations` deep
+    pub fn new(confirmations: u32) -> Self {
+        assert!(confirmations > 0, "confirmations must be at least 1");
+        Watcher {
+            scripts: HashSet::new(),
+            confirmations,
+            outputs: HashMap::new(),
+            seen: HashSet::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn watch_script(&mut self, script_pubkey: &Script) {
+        self.scripts.insert(script_pubkey.to_bytes());
+    }
+
+    /// Watch the script of an address, false if it isn't an address on `net`
+    pub fn watch_address(&mut self, address: &str, net: &str) -> bool {
+        match Script::from_address(address, net) {
+            Some(script_pubkey) => {
+                self.watch_script(&script_pubkey);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_watched(&self, script_pubkey: &Script) -> bool {
+        self.scripts.contains(&script_pubkey.to_bytes())
+    }
+
+    /// Received outputs that weren't spent yet
+    pub fn unspent(&self) -> impl Iterator<Item = (&str, u32, Amount)> {
+        self.outputs
+            .iter()
+            .map(|((txid, vout), amount)| (txid.as_str(), *vout, *amount))
+    }
+
+    /// Look at a transaction, mined at `height` or unconfirmed. Returns what
+    /// it received or spent the first time it's seen, a transaction mined
+    /// later only starts counting towards its confirmation.
+    pub fn process_tx(&mut self, tx: &Tx, height: Option<u32>) -> Vec<WatchEvent> {
+        let txid = tx.id();
+        let mut events = vec![];
+        if !self.seen.contains(&txid) {
+            if !tx.is_coinbase() {
+                for tx_in in &tx.tx_ins {
+                    let outpoint = (display_txid(&tx_in.prev_tx), tx_in.prev_index);
+                    if self.outputs.remove(&outpoint).is_some() {
+                        events.push(WatchEvent::Spent {
+                            txid: outpoint.0,
+                            vout: outpoint.1,
+                            spent_by: txid.clone(),
+                            height,
+                        });
+                    }
+                }
+            }
+            for (vout, tx_out) in tx.tx_outs.iter().enumerate() {
+                if !self.is_watched(&tx_out.script_pubkey) {
+                    continue;
+                }
+                self.outputs
+                    .insert((txid.clone(), vout as u32), tx_out.amount);
+                events.push(WatchEvent::Received {
+                    txid: txid.clone(),
+                    vout: vout as u32,
+                    script_pubkey: tx_out.script_pubkey.clone(),
+                    amount: tx_out.amount,
+                    height,
+                });
+            }
+            if events.is_empty() {
+                return events;
+            }
+            self.seen.insert(txid.clone());
+        }
+        if let Some(height) = height {
+            self.pending.insert(txid, height);
+        }
+        events
+    }
+
+    /// The chain tip moved to `height`, report the transactions that are now
+    /// deep enough, shallowest last
+    pub fn set_tip(&mut self, height: u32) -> Vec<WatchEvent> {
+        let mut confirmed: Vec<(String, u32)> = self
+            .pending
+            .iter()
+            .filter(|(_, &mined)| mined <= height && height - mined + 1 >= self.confirmations)
+            .map(|(txid, &mined)| (txid.clone(), mined))
+            .collect();
+        confirmed.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        confirmed
+            .into_iter()
+            .map(|(txid, mined)| {
+                self.pending.remove(&txid);
+                WatchEvent::Confirmed {
+                    txid,
+                    height: mined,
+                    depth: height - mined + 1,
+                }
+            })
+            .collect()
+    }
+
+    /// Process every transaction of a block mined at `height`, then move the
+    /// tip to it
+    pub fn process_block(&mut self, block: &Block, height: u32) -> Vec<WatchEvent> {
+        let mut events: Vec<WatchEvent> = block
+            .txs
+            .iter()
+            .flat_map(|tx| self.process_tx(tx, Some(height)))
+            .collect();
+        events.extend(self.set_tip(height));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashes::sha256d;
+    use crate::keys::pkb_hash_to_address;
+    use crate::transaction::TxBuilder;
+
+    fn block(txs: Vec<Tx>) -> Block {
+        Block {
+            version: 1,
+            prev_block: vec![0; 32],
+            merkle_root: vec![0; 32],
+            timestamp: 0,
+            bits: vec![0xff, 0xff, 0x00, 0x1d],
+            nonce: vec![0; 4],
+            txs,
+        }
+    }
+
+    #[test]
+    fn test_receive_spend_confirm() {
+        let mut watcher = Watcher::new(2);
+        assert!(watcher.watch_address(&pkb_hash_to_address(&[0xaa; 20], "main"), "main"));
+        assert!(!watcher.watch_address("not an address", "main"));
+
+        let payment = TxBuilder::new("main")
+            .add_input(vec![0x11; 32], 0)
+            .add_output(Amount::from_sat(5_000), Script::p2pkh(&[0xbb; 20]))
+            .add_output(Amount::from_sat(7_000), Script::p2pkh(&[0xaa; 20]))
+            .build();
+        let unrelated = TxBuilder::new("main")
+            .add_input(vec![0x22; 32], 0)
+            .add_output(Amount::from_sat(1_000), Script::p2pkh(&[0xcc; 20]))
+            .build();
+        let spend = TxBuilder::new("main")
+            .add_input(sha256d(&payment.encode_legacy()).to_vec(), 1)
+            .add_output(Amount::from_sat(6_000), Script::p2pkh(&[0xdd; 20]))
+            .build();
+
+        let events = watcher.process_block(&block(vec![payment.clone(), unrelated.clone()]), 1);
+        assert_eq!(
+            events,
+            vec![WatchEvent::Received {
+                txid: payment.id(),
+                vout: 1,
+                script_pubkey: Script::p2pkh(&[0xaa; 20]),
+                amount: Amount::from_sat(7_000),
+                height: Some(1),
+            }]
+        );
+        assert_eq!(watcher.unspent().count(), 1);
+
+        // the spend shows up in the mempool first, then gets mined
+        let spent = WatchEvent::Spent {
+            txid: payment.id(),
+            vout: 1,
+            spent_by: spend.id(),
+            height: None,
+        };
+        assert_eq!(watcher.process_tx(&spend, None), vec![spent]);
+        assert_eq!(watcher.unspent().count(), 0);
+        assert_eq!(
+            watcher.process_block(&block(vec![spend.clone()]), 2),
+            vec![WatchEvent::Confirmed {
+                txid: payment.id(),
+                height: 1,
+                depth: 2,
+            }]
+        );
+        assert_eq!(
+            watcher.set_tip(3),
+            vec![WatchEvent::Confirmed {
+                txid: spend.id(),
+                height: 2,
+                depth: 2,
+            }]
+        );
+        assert_eq!(watcher.set_tip(4), vec![]);
+    }
+}
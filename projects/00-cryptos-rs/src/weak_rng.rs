@@ -0,0 +1,175 @@
+use std::fmt;
+use std::ops::Range;
+
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use rayon::prelude::*;
+
+use crate::keys::{gen_secret_key_with_rng, PublicKey};
+use crate::ru256::RU256;
+use crate::secp256k1::SECP256K1;
+
+// A secret key is only as unpredictable as the randomness it came from. A
+// wallet that seeds its generator with a 32 bit number, or with the time it
+// was started, has at most 2^32 keys it can ever produce, however good the
+// generator is, and an attacker who knows (or guesses) how it seeds can try
+// them all against a public key or address. The brute force here is a
+// parallel loop over seeds, each try is one scalar multiplication, so even
+// with this crate's slow arithmetic a timestamp known to within a day falls
+// in under an hour on a laptop, and the full 32 bit space only takes a
+// bigger machine. The way out is an entropy source that says how much
+// entropy it has, and key generation that refuses too little.
+
+/// Entropy below this makes key generation refuse the source
+pub const MIN_ENTROPY_BITS: u32 = 128;
+
+/// Randomness for secret keys
+pub trait EntropySource {
+    fn fill_bytes(&mut self, dest: &mut [u8]);
+
+    /// How many bits an attacker has to guess to predict the output
+    fn entropy_bits(&self) -> u32;
+}
+
+/// The operating system's generator, the right source for real keys
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsEntropy;
+
+impl EntropySource for OsEntropy {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest)
+    }
+
+    fn entropy_bits(&self) -> u32 {
+        256
+    }
+}
+
+/// A good generator behind a guessable seed, the mistake this module is about
+#[derive(Debug, Clone)]
+pub struct SeededEntropy {
+    rng: StdRng,
+    bits: u32,
+}
+
+impl SeededEntropy {
+    /// Seeded from a 32 bit number, like a process id or C's `srand`
+    pub fn from_seed_u32(seed: u32) -> Self {
+        SeededEntropy {
+            rng: StdRng::seed_from_u64(seed as u64),
+            bits: 32,
+        }
+    }
+
+    /// Seeded from the unix time in seconds. Anyone who knows the day the key
+    /// was made has only about 2^17 seconds to try.
+    pub fn from_timestamp(seconds: u64) -> Self {
+        SeededEntropy {
+            rng: StdRng::seed_from_u64(seconds),
+            bits: 17,
+        }
+    }
+}
+
+impl EntropySource for SeededEntropy {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn entropy_bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+/// A source with too little entropy for a secret key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeakEntropy {
+    pub bits: u32,
+}
+
+impl fmt::Display for WeakEntropy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "entropy source has {} bits, at least {} are needed for a secret key",
+            self.bits, MIN_ENTROPY_BITS
+        )
+    }
+}
+
+impl std::error::Error for WeakEntropy {}
+
+/// A secret key from `source`, as long as it has enough entropy
+pub fn secret_key_from<E: EntropySource>(source: &mut E) -> Result<RU256, WeakEntropy> {
+    let bits = source.entropy_bits();
+    if bits < MIN_ENTROPY_BITS {
+        return Err(WeakEntropy { bits });
+    }
+    let n = SECP256K1::n();
+    loop {
+        let mut key_bytes = [0u8; 32];
+        source.fill_bytes(&mut key_bytes);
+        let key = RU256::from_bytes(&key_bytes);
+        if key >= RU256::from_u64(1) && key < n {
+            return Ok(key);
+        }
+    }
+}
+
+/// The key `gen_secret_key` makes when its generator is seeded with `seed`
+pub fn weak_secret_key(seed: u64) -> RU256 {
+    gen_secret_key_with_rng(&SECP256K1::n(), &mut StdRng::seed_from_u64(seed))
+}
+
+/// The seeds of a key made around unix time `seconds`, `window` seconds
+/// either side
+pub fn timestamp_seeds(seconds: u64, window: u64) -> Range<u64> {
+    seconds.saturating_sub(window)..seconds.saturating_add(window + 1)
+}
+
+/// Try every seed in `seeds` until one makes the secret key of
+/// `public_key`, returns the seed and the key
+pub fn recover_seed(public_key: &PublicKey, seeds: Range<u64>) -> Option<(u64, RU256)> {
+    let g = SECP256K1::g();
+    seeds.into_par_iter().find_map_any(|seed| {
+        let key = weak_secret_key(seed);
+        // the ladder needs a single inversion, which makes it the fastest
+        // multiplication here
+        (SECP256K1::scalar_multiplication_ct(&key, &g) == public_key.0).then_some((seed, key))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy_sources() {
+        let key = secret_key_from(&mut OsEntropy).unwrap();
+        assert!(key >= RU256::from_u64(1) && key < SECP256K1::n());
+
+        assert_eq!(
+            secret_key_from(&mut SeededEntropy::from_seed_u32(7)),
+            Err(WeakEntropy { bits: 32 })
+        );
+        assert_eq!(
+            secret_key_from(&mut SeededEntropy::from_timestamp(1_700_000_000)),
+            Err(WeakEntropy { bits: 17 })
+        );
+    }
+
+    #[test]
+    fn test_recover_seed() {
+        // a wallet that seeded with the time it made the key
+        let created = 1_700_000_123;
+        let key = weak_secret_key(created);
+        let public_key =
+            PublicKey::from_point(SECP256K1::scalar_multiplication_ct(&key, &SECP256K1::g()));
+
+        // the attacker knows roughly when
+        let seeds = timestamp_seeds(1_700_000_120, 4);
+        assert_eq!(seeds.clone().count(), 9);
+        assert_eq!(recover_seed(&public_key, seeds), Some((created, key)));
+        assert_eq!(recover_seed(&public_key, 0..4), None);
+    }
+}
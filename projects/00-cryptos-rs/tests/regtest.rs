@@ -0,0 +1,251 @@
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use cryptos_rs::amount::Amount;
+use cryptos_rs::block::Block;
+use cryptos_rs::bloom::MerkleBlock;
+use cryptos_rs::broadcast::{broadcast, BroadcastError, Broadcaster, CoreRpc};
+use cryptos_rs::encoding::Decodable;
+use cryptos_rs::signer::{Signer, SoftwareSigner};
+use cryptos_rs::transaction::{Cmd, Script, Tx, TxBuilder};
+use cryptos_rs::watcher::Watcher;
+
+// End to end tests against a real node: each test starts its own bitcoind in
+// regtest, mines coins to keys the crate holds, and spends, broadcasts and
+// proves them with the crate's own code, so anything the crate gets wrong
+// about consensus or relay policy shows up as a rejection. Regtest shares
+// testnet's address prefixes, so the crate's "test" net works as is. Run with
+// `cargo test --features integration-tests --test regtest`, the tests pass
+// without doing anything when there's no bitcoind on the PATH.
+
+const RPC_USER: &str = "cryptos";
+const RPC_PASSWORD: &str = "cryptos";
+
+/// Coinbase outputs can be spent this many blocks later
+const COINBASE_MATURITY: u64 = 100;
+
+/// A bitcoind in regtest with its own data directory, stopped and removed on
+/// drop
+struct Bitcoind {
+    process: Child,
+    datadir: PathBuf,
+    rpc: CoreRpc,
+}
+
+/// A port nothing listens on right now
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+impl Bitcoind {
+    /// Start a node and wait for its RPC, None if bitcoind isn't installed
+    fn start(name: &str) -> Option<Self> {
+        if Command::new("bitcoind").arg("-version").output().is_err() {
+            eprintln!("bitcoind not found, skipping {}", name);
+            return None;
+        }
+        let datadir = std::env::temp_dir().join(format!("regtest-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&datadir);
+        std::fs::create_dir_all(&datadir).unwrap();
+        let rpc_port = free_port();
+        let process = Command::new("bitcoind")
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.display()))
+            .arg(format!("-rpcport={}", rpc_port))
+            .arg(format!("-rpcuser={}", RPC_USER))
+            .arg(format!("-rpcpassword={}", RPC_PASSWORD))
+            .args(["-listen=0", "-server", "-txindex", "-disablewallet"])
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let node = Bitcoind {
+            process,
+            datadir,
+            rpc: CoreRpc {
+                url: format!("http://127.0.0.1:{}", rpc_port),
+                user: RPC_USER.to_string(),
+                password: RPC_PASSWORD.to_string(),
+            },
+        };
+
+        // the RPC answers with an error while the node is still warming up
+        let start = Instant::now();
+        while node.try_call("getblockchaininfo", json!([])).is_none() {
+            assert!(
+                start.elapsed() < Duration::from_secs(30),
+                "bitcoind didn't start"
+            );
+            thread::sleep(Duration::from_millis(100));
+        }
+        Some(node)
+    }
+
+    fn try_call(&self, method: &str, params: Value) -> Option<Value> {
+        let request = json!({"jsonrpc": "1.0", "id": "test", "method": method, "params": params});
+        let response: Value = reqwest::blocking::Client::new()
+            .post(&self.rpc.url)
+            .basic_auth(&self.rpc.user, Some(&self.rpc.password))
+            .body(request.to_string())
+            .send()
+            .and_then(|response| response.text())
+            .ok()
+            .and_then(|body| serde_json::from_str(&body).ok())?;
+        response.get("error").filter(|error| error.is_null())?;
+        response.get("result").cloned()
+    }
+
+    fn call(&self, method: &str, params: Value) -> Value {
+        self.try_call(method, params)
+            .unwrap_or_else(|| panic!("{} failed", method))
+    }
+
+    /// Mine `count` blocks paying to `address`
+    fn mine(&self, count: u64, address: &str) -> Vec<String> {
+        let hashes = self.call("generatetoaddress", json!([count, address]));
+        serde_json::from_value(hashes).unwrap()
+    }
+
+    fn block(&self, hash: &str) -> Block {
+        let raw = self.call("getblock", json!([hash, 0]));
+        Block::decode(&mut hex::decode(raw.as_str().unwrap()).unwrap().as_slice())
+    }
+
+    fn tip_height(&self) -> u32 {
+        self.call("getblockcount", json!([])).as_u64().unwrap() as u32
+    }
+}
+
+impl Drop for Bitcoind {
+    fn drop(&mut self) {
+        let _ = self.try_call("stop", json!([]));
+        if self.process.wait().is_err() {
+            let _ = self.process.kill();
+        }
+        let _ = std::fs::remove_dir_all(&self.datadir);
+    }
+}
+
+/// A signer with two keys and their testnet (so regtest) P2PKH scripts
+fn keys() -> (SoftwareSigner, [Script; 2], [String; 2]) {
+    let mut signer = SoftwareSigner::generate(2);
+    let mut scripts = vec![];
+    let mut addresses = vec![];
+    for key in 0..2 {
+        let public_key = signer.get_pubkey(key).unwrap();
+        scripts.push(Script::p2pkh(&public_key.sec(true, true)));
+        addresses.push(public_key.address("test", true));
+    }
+    (
+        signer,
+        scripts.try_into().unwrap(),
+        addresses.try_into().unwrap(),
+    )
+}
+
+/// Spend output `vout` of `prev` paying to key 0, sending `amount` to `to`
+fn spend(signer: &mut SoftwareSigner, prev: &Tx, vout: u32, amount: Amount, to: &Script) -> Tx {
+    let mut prev_tx = hex::decode(prev.id()).unwrap();
+    prev_tx.reverse();
+    let mut tx = TxBuilder::new("test")
+        .add_input(prev_tx, vout)
+        .add_output(amount, to.clone())
+        .build();
+    let spent = &prev.tx_outs[vout as usize].script_pubkey;
+    let sig = signer.sign_tx_input(0, &tx, 0, spent).unwrap();
+    let sec = signer.get_pubkey(0).unwrap().sec(true, false);
+    tx.tx_ins[0].script_sig = Script::new(vec![Cmd::Push(sig), Cmd::Push(sec)]);
+    tx
+}
+
+#[test]
+fn test_spend_coinbase() {
+    let Some(node) = Bitcoind::start("spend") else {
+        return;
+    };
+    let (mut signer, scripts, addresses) = keys();
+    let hashes = node.mine(1, &addresses[0]);
+    node.mine(COINBASE_MATURITY, &addresses[1]);
+
+    // the crate's watcher finds the coinbase by scanning the blocks
+    let mut watcher = Watcher::new(1);
+    watcher.watch_script(&scripts[0]);
+    for height in 1..=node.tip_height() {
+        let hash = node.call("getblockhash", json!([height]));
+        watcher.process_block(&node.block(hash.as_str().unwrap()), height);
+    }
+    let coinbase = node.block(&hashes[0]).txs[0].clone();
+    assert_eq!(
+        watcher.unspent().collect::<Vec<_>>(),
+        [(coinbase.id().as_str(), 0, coinbase.tx_outs[0].amount)]
+    );
+
+    let fee = Amount::from_sat(10_000);
+    let tx = spend(
+        &mut signer,
+        &coinbase,
+        0,
+        coinbase.tx_outs[0].amount - fee,
+        &scripts[1],
+    );
+    assert_eq!(broadcast(&tx, &[&node.rpc]), Ok(tx.id()));
+    let mempool = node.call("getrawmempool", json!([]));
+    assert!(mempool.as_array().unwrap().contains(&json!(tx.id())));
+
+    // once mined, spending the coinbase again has no inputs left to spend
+    let block_hash = node.mine(1, &addresses[1]).remove(0);
+    let mined = node.block(&block_hash);
+    assert!(mined.txs.iter().any(|mined| mined.id() == tx.id()));
+    let double_spend = spend(
+        &mut signer,
+        &coinbase,
+        0,
+        Amount::from_sat(1_000),
+        &scripts[0],
+    );
+    let hex = hex::encode(cryptos_rs::encoding::Encodable::encode(&double_spend));
+    assert_eq!(
+        node.rpc.broadcast_hex(&hex),
+        Err(BroadcastError::MissingInputs)
+    );
+}
+
+#[test]
+fn test_spv_proof() {
+    let Some(node) = Bitcoind::start("spv") else {
+        return;
+    };
+    let (mut signer, scripts, addresses) = keys();
+    let hashes = node.mine(1, &addresses[0]);
+    node.mine(COINBASE_MATURITY, &addresses[1]);
+    let coinbase = node.block(&hashes[0]).txs[0].clone();
+    let tx = spend(
+        &mut signer,
+        &coinbase,
+        0,
+        coinbase.tx_outs[0].amount - Amount::from_sat(10_000),
+        &scripts[1],
+    );
+    broadcast(&tx, &[&node.rpc]).unwrap();
+    let block_hash = node.mine(1, &addresses[1]).remove(0);
+
+    // Core's merkleblock proof checks out with the crate's SPV code
+    let proof = node.call("gettxoutproof", json!([[tx.id()], block_hash]));
+    let merkle_block =
+        MerkleBlock::decode(&mut hex::decode(proof.as_str().unwrap()).unwrap().as_slice());
+    assert_eq!(merkle_block.header.id(), block_hash);
+    let mut txid = hex::decode(tx.id()).unwrap();
+    txid.reverse();
+    assert_eq!(
+        merkle_block.matched_txids(),
+        Some(vec![<[u8; 32]>::try_from(txid).unwrap()])
+    );
+}